@@ -228,6 +228,14 @@ impl AggFunc {
         false // consider aggregate functions nondeterministic since they depend on the number of rows, not only the input arguments
     }
 
+    /// Returns true if the effect of a step can be undone with `AggInverse`.
+    pub fn supports_inverse(&self) -> bool {
+        matches!(
+            self,
+            Self::Avg | Self::Count | Self::Count0 | Self::Sum | Self::Total
+        )
+    }
+
     pub fn num_args(&self) -> usize {
         match self {
             Self::Avg => 1,
@@ -271,6 +279,55 @@ impl AggFunc {
     }
 }
 
+/// Built-in functions that can only be evaluated over a window.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowFunc {
+    RowNumber,
+    Rank,
+    DenseRank,
+    PercentRank,
+    CumeDist,
+    Ntile,
+    Lag,
+    Lead,
+    FirstValue,
+    LastValue,
+    NthValue,
+}
+
+impl WindowFunc {
+    pub fn is_deterministic(&self) -> bool {
+        false // the result depends on the position of the row within its partition
+    }
+
+    /// Returns true if the function reads values from rows other than the current row.
+    pub fn needs_row_lookup(&self) -> bool {
+        matches!(
+            self,
+            Self::Lag | Self::Lead | Self::FirstValue | Self::LastValue | Self::NthValue
+        )
+    }
+}
+
+impl Display for WindowFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            Self::RowNumber => "row_number",
+            Self::Rank => "rank",
+            Self::DenseRank => "dense_rank",
+            Self::PercentRank => "percent_rank",
+            Self::CumeDist => "cume_dist",
+            Self::Ntile => "ntile",
+            Self::Lag => "lag",
+            Self::Lead => "lead",
+            Self::FirstValue => "first_value",
+            Self::LastValue => "last_value",
+            Self::NthValue => "nth_value",
+        };
+        write!(f, "{str}")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ScalarFunc {
     Cast,
@@ -599,6 +656,7 @@ impl Display for AlterTableFunc {
 #[derive(Debug)]
 pub enum Func {
     Agg(AggFunc),
+    Window(WindowFunc),
     Scalar(ScalarFunc),
     Math(MathFunc),
     Vector(VectorFunc),
@@ -612,6 +670,7 @@ impl Display for Func {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Agg(agg_func) => write!(f, "{}", agg_func.to_string()),
+            Self::Window(window_func) => write!(f, "{window_func}"),
             Self::Scalar(scalar_func) => write!(f, "{scalar_func}"),
             Self::Math(math_func) => write!(f, "{math_func}"),
             Self::Vector(vector_func) => write!(f, "{vector_func}"),
@@ -633,6 +692,7 @@ impl Func {
     pub fn is_deterministic(&self) -> bool {
         match self {
             Self::Agg(agg_func) => agg_func.is_deterministic(),
+            Self::Window(window_func) => window_func.is_deterministic(),
             Self::Scalar(scalar_func) => scalar_func.is_deterministic(),
             Self::Math(math_func) => math_func.is_deterministic(),
             Self::Vector(vector_func) => vector_func.is_deterministic(),
@@ -701,6 +761,50 @@ impl Func {
                 }
                 Ok(Self::Agg(AggFunc::Total))
             }
+            "row_number" | "rank" | "dense_rank" | "percent_rank" | "cume_dist" => {
+                if arg_count != 0 {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+                }
+                Ok(Self::Window(match normalized_name.as_str() {
+                    "row_number" => WindowFunc::RowNumber,
+                    "rank" => WindowFunc::Rank,
+                    "dense_rank" => WindowFunc::DenseRank,
+                    "percent_rank" => WindowFunc::PercentRank,
+                    _ => WindowFunc::CumeDist,
+                }))
+            }
+            "ntile" => {
+                if arg_count != 1 {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+                }
+                Ok(Self::Window(WindowFunc::Ntile))
+            }
+            "lag" | "lead" => {
+                if !(1..=3).contains(&arg_count) {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+                }
+                Ok(Self::Window(if normalized_name == "lag" {
+                    WindowFunc::Lag
+                } else {
+                    WindowFunc::Lead
+                }))
+            }
+            "first_value" | "last_value" => {
+                if arg_count != 1 {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+                }
+                Ok(Self::Window(if normalized_name == "first_value" {
+                    WindowFunc::FirstValue
+                } else {
+                    WindowFunc::LastValue
+                }))
+            }
+            "nth_value" => {
+                if arg_count != 2 {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+                }
+                Ok(Self::Window(WindowFunc::NthValue))
+            }
             "timediff" => {
                 if arg_count != 2 {
                     crate::bail_parse_error!("wrong number of arguments to function {}()", name)
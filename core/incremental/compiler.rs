@@ -1949,6 +1949,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("name".to_string()),
@@ -1961,6 +1962,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("age".to_string()),
@@ -1973,11 +1975,14 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                 ],
                 has_rowid: true,
                 is_strict: false,
                 has_autoincrement: false,
+                foreign_keys: vec![],
+                checks: vec![],
                 unique_sets: vec![],
             };
             schema.add_btree_table(Arc::new(users_table));
@@ -2002,6 +2007,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("product_name".to_string()),
@@ -2014,6 +2020,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("price".to_string()),
@@ -2026,11 +2033,14 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                 ],
                 has_rowid: true,
                 is_strict: false,
                 has_autoincrement: false,
+                foreign_keys: vec![],
+                checks: vec![],
                 unique_sets: vec![],
             };
             schema.add_btree_table(Arc::new(products_table));
@@ -2055,6 +2065,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("user_id".to_string()),
@@ -2067,6 +2078,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("product_id".to_string()),
@@ -2079,6 +2091,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("quantity".to_string()),
@@ -2091,10 +2104,13 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                 ],
                 has_rowid: true,
                 has_autoincrement: false,
+                foreign_keys: vec![],
+                checks: vec![],
                 is_strict: false,
                 unique_sets: vec![],
             };
@@ -2117,6 +2133,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("name".to_string()),
@@ -2129,11 +2146,14 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                 ],
                 has_rowid: true,
                 is_strict: false,
                 has_autoincrement: false,
+                foreign_keys: vec![],
+                checks: vec![],
                 unique_sets: vec![],
             };
             schema.add_btree_table(Arc::new(customers_table));
@@ -2155,6 +2175,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("customer_id".to_string()),
@@ -2167,6 +2188,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("vendor_id".to_string()),
@@ -2179,6 +2201,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("quantity".to_string()),
@@ -2191,11 +2214,14 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                 ],
                 has_rowid: true,
                 is_strict: false,
                 has_autoincrement: false,
+                foreign_keys: vec![],
+                checks: vec![],
                 unique_sets: vec![],
             };
             schema.add_btree_table(Arc::new(purchases_table));
@@ -2217,6 +2243,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("name".to_string()),
@@ -2229,6 +2256,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("price".to_string()),
@@ -2241,11 +2269,14 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                 ],
                 has_rowid: true,
                 is_strict: false,
                 has_autoincrement: false,
+                foreign_keys: vec![],
+                checks: vec![],
                 unique_sets: vec![],
             };
             schema.add_btree_table(Arc::new(vendors_table));
@@ -2266,6 +2297,7 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                     SchemaColumn {
                         name: Some("amount".to_string()),
@@ -2278,11 +2310,14 @@ mod tests {
                         unique: false,
                         collation: None,
                         hidden: false,
+                        generated: None,
                     },
                 ],
                 has_rowid: true,
                 is_strict: false,
                 has_autoincrement: false,
+                foreign_keys: vec![],
+                checks: vec![],
                 unique_sets: vec![],
             };
             schema.add_btree_table(Arc::new(sales_table));
@@ -50,6 +50,7 @@ pub fn create_dbsp_state_index(root_page: usize) -> Index {
                 collation: None,
                 pos_in_table: 0,
                 default: None,
+                expr: None,
             },
             IndexColumn {
                 name: "zset_id".to_string(),
@@ -57,6 +58,7 @@ pub fn create_dbsp_state_index(root_page: usize) -> Index {
                 collation: None,
                 pos_in_table: 1,
                 default: None,
+                expr: None,
             },
             IndexColumn {
                 name: "element_id".to_string(),
@@ -64,11 +66,13 @@ pub fn create_dbsp_state_index(root_page: usize) -> Index {
                 collation: None,
                 pos_in_table: 2,
                 default: None,
+                expr: None,
             },
         ],
         unique: true,
         ephemeral: false,
         has_rowid: true,
+        primary_key_suffix: vec![],
         where_clause: None,
     }
 }
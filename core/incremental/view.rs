@@ -1396,6 +1396,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("name".to_string()),
@@ -1408,12 +1409,15 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
             ],
             has_rowid: true,
             is_strict: false,
             unique_sets: vec![],
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
         };
 
         // Create orders table
@@ -1433,6 +1437,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("customer_id".to_string()),
@@ -1445,6 +1450,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("total".to_string()),
@@ -1457,11 +1463,14 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
             ],
             has_rowid: true,
             is_strict: false,
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
             unique_sets: vec![],
         };
 
@@ -1482,6 +1491,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("name".to_string()),
@@ -1494,6 +1504,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("price".to_string()),
@@ -1506,11 +1517,14 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
             ],
             has_rowid: true,
             is_strict: false,
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
             unique_sets: vec![],
         };
 
@@ -1531,6 +1545,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("level".to_string()),
@@ -1543,6 +1558,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("timestamp".to_string()),
@@ -1555,11 +1571,14 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
             ],
             has_rowid: true, // Has implicit rowid but no alias
             is_strict: false,
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
             unique_sets: vec![],
         };
 
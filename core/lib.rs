@@ -20,6 +20,7 @@ mod schema;
 #[cfg(feature = "series")]
 mod series;
 pub mod state_machine;
+mod stats;
 pub mod storage;
 #[allow(dead_code)]
 #[cfg(feature = "time")]
@@ -29,6 +30,7 @@ pub mod types;
 mod util;
 #[cfg(feature = "uuid")]
 mod uuid;
+mod vacuum;
 mod vdbe;
 mod vector;
 mod vtab;
@@ -39,6 +41,7 @@ pub mod numeric;
 #[cfg(not(feature = "fuzz"))]
 mod numeric;
 
+use crate::stats::{Stat1Row, Stat4Row, STAT1_TABLE_NAME, STAT4_TABLE_NAME};
 use crate::storage::checksum::CHECKSUM_REQUIRED_RESERVED_BYTES;
 use crate::translate::pragma::TURSO_CDC_DEFAULT_TABLE_NAME;
 #[cfg(all(feature = "fs", feature = "conn_raw_api"))]
@@ -60,7 +63,7 @@ pub use io::{
     WriteCompletion, IO,
 };
 use parking_lot::RwLock;
-use schema::Schema;
+use schema::{is_temp_schema_table_name, Schema};
 use std::{
     borrow::Cow,
     cell::RefCell,
@@ -82,6 +85,7 @@ pub use storage::encryption::{CipherMode, EncryptionContext, EncryptionKey};
 use storage::page_cache::PageCache;
 use storage::pager::{AtomicDbState, DbState};
 use storage::sqlite3_ondisk::PageSize;
+use storage::super_journal;
 pub use storage::{
     buffer_pool::BufferPool,
     database::DatabaseStorage,
@@ -175,12 +179,52 @@ enum TransactionState {
     None,
 }
 
+/// A savepoint opened with `SAVEPOINT name`, or the statement journal of a write statement
+/// running inside an explicit transaction.
+#[derive(Debug, Clone)]
+pub(crate) struct Savepoint {
+    /// Normalized savepoint name, `None` for a statement journal.
+    pub(crate) name: Option<String>,
+    /// Whether the savepoint started the transaction, in which case releasing it commits.
+    pub(crate) starts_transaction: bool,
+    /// Schema at the time the savepoint was opened, restored by `ROLLBACK TO`.
+    pub(crate) schema: Arc<Schema>,
+    /// Deferred foreign key violations at the time the savepoint was opened.
+    pub(crate) deferred_fk_violations: i64,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum SyncMode {
     Off = 0,
     Full = 2,
 }
 
+/// Where temporary tables and indices are stored, as set by `PRAGMA temp_store`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TempStore {
+    Default = 0,
+    File = 1,
+    Memory = 2,
+}
+
+/// A transaction open on the temp database or an attached database. It ends together with the
+/// transaction of the main database.
+#[derive(Debug, Clone)]
+pub(crate) struct AuxTransaction {
+    pub(crate) database_id: usize,
+    pub(crate) write: bool,
+    /// Schema of the database when the write transaction began, restored on rollback.
+    pub(crate) schema: Option<Arc<Schema>>,
+    /// Number of savepoints of the connection when the write transaction began. Savepoints opened
+    /// after it are mirrored by savepoints of the database's pager.
+    pub(crate) savepoint_base: usize,
+    /// Schema of the database when each mirrored savepoint was opened.
+    pub(crate) savepoint_schemas: Vec<Option<Arc<Schema>>>,
+    /// Schema of the database when the running statement began, if it has a statement journal.
+    /// The statement journal is the innermost savepoint of the pager.
+    pub(crate) statement_schema: Option<Option<Arc<Schema>>>,
+}
+
 pub(crate) type MvStore = mvcc::MvStore<mvcc::LocalClock>;
 
 pub(crate) type MvCursor = mvcc::cursor::MvccLazyCursor<mvcc::LocalClock>;
@@ -398,6 +442,9 @@ impl Database {
         opts: DatabaseOpts,
         encryption_opts: Option<EncryptionOpts>,
     ) -> Result<Arc<Database>> {
+        if !path.starts_with(crate::util::MEMORY_PATH) {
+            super_journal::recover(&io, path, wal_path)?;
+        }
         let shared_wal = WalFileShared::open_shared_if_exists(&io, wal_path)?;
 
         let mv_store = if opts.enable_mvcc {
@@ -502,6 +549,7 @@ impl Database {
             database_schemas: RwLock::new(std::collections::HashMap::new()),
             auto_commit: AtomicBool::new(true),
             transaction_state: RwLock::new(TransactionState::None),
+            savepoints: RwLock::new(Vec::new()),
             last_insert_rowid: AtomicI64::new(0),
             last_change: AtomicI64::new(0),
             total_changes: AtomicI64::new(0),
@@ -514,6 +562,11 @@ impl Database {
             closed: AtomicBool::new(false),
             attached_databases: RwLock::new(DatabaseCatalog::new()),
             query_only: AtomicBool::new(false),
+            recursive_triggers: AtomicBool::new(false),
+            foreign_keys: AtomicBool::new(false),
+            ignore_check_constraints: AtomicBool::new(false),
+            analysis_limit: AtomicI64::new(0),
+            deferred_fk_violations: AtomicI64::new(0),
             mv_tx: RwLock::new(None),
             view_transaction_states: AllViewsTxState::new(),
             metrics: RwLock::new(ConnectionMetrics::new()),
@@ -523,6 +576,8 @@ impl Database {
             sync_mode: RwLock::new(SyncMode::Full),
             data_sync_retry: AtomicBool::new(false),
             busy_timeout: RwLock::new(Duration::new(0, 0)),
+            aux_transactions: RwLock::new(Vec::new()),
+            temp_store: RwLock::new(TempStore::Default),
         });
         self.n_connections
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
@@ -925,6 +980,14 @@ impl DatabaseCatalog {
         idx
     }
 
+    fn set_temp(&mut self, data: (Arc<Database>, Arc<Pager>)) {
+        self.index_to_data.insert(1, data);
+    }
+
+    fn remove_temp(&mut self) {
+        self.index_to_data.remove(&1);
+    }
+
     fn remove(&mut self, s: &str) -> Option<usize> {
         if let Some(index) = self.name_to_index.remove(s) {
             // Should be impossible to remove main or temp.
@@ -987,6 +1050,8 @@ pub struct Connection {
     /// Whether to automatically commit transaction
     auto_commit: AtomicBool,
     transaction_state: RwLock<TransactionState>,
+    /// Open savepoints, innermost last.
+    savepoints: RwLock<Vec<Savepoint>>,
     last_insert_rowid: AtomicI64,
     last_change: AtomicI64,
     total_changes: AtomicI64,
@@ -1004,6 +1069,16 @@ pub struct Connection {
     /// Attached databases
     attached_databases: RwLock<DatabaseCatalog>,
     query_only: AtomicBool,
+    /// Whether a trigger may fire itself, directly or through other triggers.
+    recursive_triggers: AtomicBool,
+    /// Whether foreign key constraints are enforced.
+    foreign_keys: AtomicBool,
+    /// Whether CHECK constraints are not enforced.
+    ignore_check_constraints: AtomicBool,
+    /// The maximum number of entries of each index that ANALYZE visits, or 0 for no limit.
+    analysis_limit: AtomicI64,
+    /// Number of outstanding deferred foreign key violations of the current transaction.
+    deferred_fk_violations: AtomicI64,
     pub(crate) mv_tx: RwLock<Option<(crate::mvcc::database::TxID, TransactionMode)>>,
 
     /// Per-connection view transaction states for uncommitted changes. This represents
@@ -1021,6 +1096,9 @@ pub struct Connection {
     /// User defined max accumulated Busy timeout duration
     /// Default is 0 (no timeout)
     busy_timeout: RwLock<std::time::Duration>,
+    /// Transactions open on the temp and attached databases.
+    aux_transactions: RwLock<Vec<AuxTransaction>>,
+    temp_store: RwLock<TempStore>,
 }
 
 impl Drop for Connection {
@@ -1192,7 +1270,61 @@ impl Connection {
         self.with_schema_mut(|schema| {
             *schema = fresh;
         });
-        Result::Ok(())
+        self.load_analysis(0)
+    }
+
+    /// Reads the `sqlite_stat1` and `sqlite_stat4` tables of a database into its schema, for
+    /// the query planner.
+    pub(crate) fn load_analysis(self: &Arc<Connection>, database_id: usize) -> Result<()> {
+        let prefix = match self.get_database_name_by_index(database_id) {
+            Some(name) if database_id != 0 => format!("{name}."),
+            _ => String::new(),
+        };
+        let (has_stat1, has_stat4) = self.with_schema(database_id, |schema| {
+            (
+                schema.get_btree_table(STAT1_TABLE_NAME).is_some(),
+                schema.get_btree_table(STAT4_TABLE_NAME).is_some(),
+            )
+        });
+        let stat1 = if has_stat1 {
+            self.query_stat_rows(&format!(
+                "SELECT tbl, idx, stat FROM {prefix}{STAT1_TABLE_NAME}"
+            ))?
+            .iter()
+            .filter_map(|row| Stat1Row::from_values(row))
+            .collect()
+        } else {
+            Vec::new()
+        };
+        let stat4 = if has_stat4 {
+            self.query_stat_rows(&format!(
+                "SELECT tbl, idx, neq, nlt, ndlt, sample FROM {prefix}{STAT4_TABLE_NAME}"
+            ))?
+            .iter()
+            .filter_map(|row| Stat4Row::from_values(row))
+            .collect()
+        } else {
+            Vec::new()
+        };
+        self.with_database_schema_mut(database_id, |schema| schema.set_stats(stat1, stat4));
+        Ok(())
+    }
+
+    fn query_stat_rows(self: &Arc<Connection>, sql: &str) -> Result<Vec<Vec<Value>>> {
+        let mut stmt = self.prepare(sql)?;
+        stmt.set_mv_tx(self.get_mv_tx());
+        let mut rows = Vec::new();
+        loop {
+            match stmt.step()? {
+                StepResult::Row => {
+                    let row = stmt.row().unwrap();
+                    rows.push(row.get_values().cloned().collect());
+                }
+                StepResult::IO => stmt.run_once()?,
+                StepResult::Done => return Ok(rows),
+                StepResult::Interrupt | StepResult::Busy => return Err(LimboError::Busy),
+            }
+        }
     }
 
     #[instrument(skip_all, level = Level::INFO)]
@@ -1425,10 +1557,18 @@ impl Connection {
         db_opts: DatabaseOpts,
         io: Arc<dyn IO>,
     ) -> Result<Arc<Database>> {
-        let mut opts = OpenOptions::parse(uri)?;
-        // FIXME: for now, only support read only attach
-        opts.mode = OpenMode::ReadOnly;
+        let opts = OpenOptions::parse(uri)?;
         let flags = opts.get_flags()?;
+        if opts.path == crate::util::MEMORY_PATH || matches!(opts.mode, OpenMode::Memory) {
+            let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+            return Database::open_file_with_flags(
+                io,
+                crate::util::MEMORY_PATH,
+                flags,
+                db_opts,
+                None,
+            );
+        }
         let io = opts.vfs.map(Database::io_for_vfs).unwrap_or(Ok(io))?;
         let db = Database::open_file_with_flags(io.clone(), &opts.path, flags, db_opts, None)?;
         if let Some(modeof) = opts.modeof {
@@ -1670,6 +1810,8 @@ impl Connection {
                 self.set_tx_state(TransactionState::None);
             }
         }
+        self.end_aux_transactions(true)?;
+        self.drop_temp_database();
 
         if self
             .db
@@ -1929,13 +2071,436 @@ impl Connection {
     }
 
     fn get_pager_from_database_index(&self, index: &usize) -> Arc<Pager> {
-        if *index < 2 {
+        if *index == 0 {
             self.pager.read().clone()
         } else {
+            if *index == 1 {
+                // A statement may outlive the temp database it was prepared against, e.g. after
+                // `PRAGMA temp_store` dropped it.
+                self.open_temp_database()
+                    .expect("temp database should open if it was opened before");
+            }
             self.attached_databases.read().get_pager_by_index(index)
         }
     }
 
+    /// Open the temp database of the connection unless it is open already. The temp database
+    /// holds the TEMP tables and indexes, lives in memory and is dropped on close.
+    #[cfg(feature = "fs")]
+    fn open_temp_database(&self) -> Result<()> {
+        if self.is_temp_database_open() {
+            return Ok(());
+        }
+        let db_opts = DatabaseOpts::new()
+            .with_indexes(self.db.opts.enable_indexes)
+            .with_views(self.db.experimental_views_enabled())
+            .with_strict(self.db.experimental_strict_enabled());
+        let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+        let db = Database::open_file_with_flags(
+            io,
+            crate::util::MEMORY_PATH,
+            OpenFlags::default(),
+            db_opts,
+            None,
+        )?;
+        let pager = Arc::new(db.init_pager(None)?);
+        // Allocate the schema table right away so that the temp schema can be read before the
+        // first TEMP table is created.
+        pager.io.block(|| pager.maybe_allocate_page1())?;
+        self.attached_databases.write().set_temp((db, pager));
+        self.database_schemas
+            .write()
+            .insert(1, Arc::new(Schema::new(db_opts.enable_indexes)));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "fs"))]
+    fn open_temp_database(&self) -> Result<()> {
+        Err(LimboError::InvalidArgument(
+            "temp databases are not available in this build (no-fs)".to_string(),
+        ))
+    }
+
+    pub(crate) fn is_temp_database_open(&self) -> bool {
+        self.attached_databases
+            .read()
+            .index_to_data
+            .contains_key(&1)
+    }
+
+    /// Drop the temp database together with all TEMP tables and indexes.
+    fn drop_temp_database(&self) {
+        self.attached_databases.write().remove_temp();
+        self.database_schemas.write().remove(&1);
+    }
+
+    pub fn get_temp_store(&self) -> TempStore {
+        *self.temp_store.read()
+    }
+
+    /// Set where temporary tables are stored. Like in SQLite, changing the setting drops all
+    /// TEMP tables and indexes.
+    pub fn set_temp_store(&self, temp_store: TempStore) -> Result<()> {
+        if temp_store == self.get_temp_store() {
+            return Ok(());
+        }
+        if self.is_temp_database_open() && !self.auto_commit.load(Ordering::SeqCst) {
+            return Err(LimboError::TxError(
+                "temporary storage cannot be changed from within a transaction".to_string(),
+            ));
+        }
+        self.drop_temp_database();
+        *self.temp_store.write() = temp_store;
+        Ok(())
+    }
+
+    /// Whether the temp database has a table named `table_name`, which then hides a table of the
+    /// same name in the main database.
+    fn temp_schema_has_table(&self, table_name: &str) -> bool {
+        // Every database has a schema table, an unqualified name refers to the main one.
+        if schema::is_system_table(table_name) {
+            return false;
+        }
+        self.database_schemas.read().get(&1).is_some_and(|schema| {
+            schema.get_table(table_name).is_some() || schema.get_view(table_name).is_some()
+        })
+    }
+
+    /// The indexes of the attached databases, in the order in which names are looked up in them.
+    pub(crate) fn attached_database_indexes(&self) -> Vec<usize> {
+        let mut indexes = self
+            .attached_databases
+            .read()
+            .name_to_index
+            .values()
+            .copied()
+            .collect::<Vec<_>>();
+        indexes.sort_unstable();
+        indexes
+    }
+
+    /// The first attached database with a table named `table_name`, if the main database has no
+    /// table of that name.
+    fn attached_database_with_table(&self, table_name: &str) -> Option<usize> {
+        let has_table = |schema: &Schema| {
+            schema.get_table(table_name).is_some() || schema.get_view(table_name).is_some()
+        };
+        if schema::is_system_table(table_name) || has_table(&self.schema.read()) {
+            return None;
+        }
+        self.attached_database_indexes()
+            .into_iter()
+            .find(|index| self.with_schema(*index, has_table))
+    }
+
+    /// Resolve the database of the index `index_name`: the temp database if it has an index of
+    /// that name, otherwise the main database, unless only an attached database has one.
+    pub(crate) fn resolve_index_database_id(
+        &self,
+        index_name: &ast::QualifiedName,
+    ) -> Result<usize> {
+        if index_name.db_name.is_some() {
+            return self.resolve_database_id(index_name);
+        }
+        let normalized_name = crate::util::normalize_ident(index_name.name.as_str());
+        let in_temp = self
+            .database_schemas
+            .read()
+            .get(&1)
+            .is_some_and(|schema| !schema.is_unique_idx_name(&normalized_name));
+        if in_temp {
+            return Ok(1);
+        }
+        if !self.schema.read().is_unique_idx_name(&normalized_name) {
+            return Ok(0);
+        }
+        Ok(self
+            .attached_database_indexes()
+            .into_iter()
+            .find(|index| {
+                self.with_schema(*index, |schema| {
+                    !schema.is_unique_idx_name(&normalized_name)
+                })
+            })
+            .unwrap_or(0))
+    }
+
+    /// Returns the schema of the database at `database_id`.
+    pub(crate) fn get_database_schema(&self, database_id: usize) -> Result<Arc<Schema>> {
+        if database_id == 0 {
+            return Ok(self.schema.read().clone());
+        }
+        if database_id == 1 {
+            self.open_temp_database()?;
+        } else {
+            self.with_schema(database_id, |_| ());
+        }
+        Ok(self
+            .database_schemas
+            .read()
+            .get(&database_id)
+            .cloned()
+            .expect("schema of the database should be cached by with_schema"))
+    }
+
+    /// Drops the cached schema of an attached database, so that it is loaded again from the
+    /// schema last committed to the database. The schema of the temp database only lives in the
+    /// connection, and so does the schema being changed by an open write transaction.
+    pub(crate) fn reload_database_schema(&self, database_id: usize) {
+        if database_id <= 1
+            || self
+                .aux_transactions
+                .read()
+                .iter()
+                .any(|tx| tx.database_id == database_id && tx.write)
+        {
+            return;
+        }
+        self.database_schemas.write().remove(&database_id);
+    }
+
+    /// Modify the schema of the database at `database_id`.
+    pub(crate) fn with_database_schema_mut<T>(
+        &self,
+        database_id: usize,
+        f: impl FnOnce(&mut Schema) -> T,
+    ) -> T {
+        if database_id == 0 {
+            return self.with_schema_mut(f);
+        }
+        self.with_schema(database_id, |_| ());
+        let mut schemas = self.database_schemas.write();
+        let schema = schemas
+            .get_mut(&database_id)
+            .expect("schema of the database should be cached by with_schema");
+        f(Arc::make_mut(schema))
+    }
+
+    /// Begin a transaction on the temp or an attached database, or upgrade the transaction
+    /// open on it to a write transaction. Inside an explicit transaction, a write also opens a
+    /// statement journal so that a failing statement can be undone on its own.
+    pub(crate) fn begin_aux_tx(&self, database_id: usize, write: bool) -> Result<IOResult<()>> {
+        if write && self.is_readonly(database_id) {
+            return Err(LimboError::ReadOnly);
+        }
+        let pager = self.get_pager_from_database_index(&database_id);
+        let mut txs = self.aux_transactions.write();
+        let pos = match txs.iter().position(|tx| tx.database_id == database_id) {
+            Some(pos) => pos,
+            None => {
+                pager.begin_read_tx()?;
+                txs.push(AuxTransaction {
+                    database_id,
+                    write: false,
+                    schema: None,
+                    savepoint_base: 0,
+                    savepoint_schemas: Vec::new(),
+                    statement_schema: None,
+                });
+                txs.len() - 1
+            }
+        };
+        if !write {
+            return Ok(IOResult::Done(()));
+        }
+        if !txs[pos].write {
+            match pager.begin_write_tx() {
+                Ok(IOResult::Done(_)) => {}
+                Ok(IOResult::IO(io)) => return Ok(IOResult::IO(io)),
+                Err(err) => {
+                    let tx = txs.remove(pos);
+                    drop(txs);
+                    self.end_aux_tx(&tx, true)?;
+                    return Err(err);
+                }
+            }
+            txs[pos].write = true;
+            txs[pos].schema = self.database_schemas.read().get(&database_id).cloned();
+            txs[pos].savepoint_base = self
+                .savepoints
+                .read()
+                .iter()
+                .filter(|sp| sp.name.is_some())
+                .count();
+        }
+        if !self.auto_commit.load(Ordering::SeqCst) {
+            if txs[pos].statement_schema.is_some() {
+                pager.release_savepoint(txs[pos].savepoint_schemas.len());
+            }
+            pager.open_savepoint();
+            txs[pos].statement_schema =
+                Some(self.database_schemas.read().get(&database_id).cloned());
+        }
+        Ok(IOResult::Done(()))
+    }
+
+    fn end_aux_tx(&self, tx: &AuxTransaction, rollback: bool) -> Result<()> {
+        let pager = self.get_pager_from_database_index(&tx.database_id);
+        let result = pager.io.block(|| {
+            pager.end_aux_tx(
+                rollback,
+                tx.write,
+                self.is_wal_auto_checkpoint_disabled(),
+                self.get_sync_mode(),
+                self.get_data_sync_retry(),
+            )
+        });
+        if result.is_err() && tx.write && !rollback {
+            let _ = pager.io.block(|| {
+                pager.end_aux_tx(
+                    true,
+                    true,
+                    self.is_wal_auto_checkpoint_disabled(),
+                    self.get_sync_mode(),
+                    self.get_data_sync_retry(),
+                )
+            });
+        }
+        if tx.write && (rollback || result.is_err()) {
+            let mut schemas = self.database_schemas.write();
+            match &tx.schema {
+                Some(schema) => schemas.insert(tx.database_id, schema.clone()),
+                None => schemas.remove(&tx.database_id),
+            };
+        } else if tx.write && tx.database_id > 1 {
+            // Publish the committed schema of an attached database, for the other connections
+            // that use the same database file.
+            let db = self
+                .attached_databases
+                .read()
+                .get_database_by_index(tx.database_id);
+            let schema = self.database_schemas.read().get(&tx.database_id).cloned();
+            if let (Some(db), Some(schema)) = (db, schema) {
+                db.update_schema_if_newer(schema)?;
+            }
+        }
+        result
+    }
+
+    /// Commit or roll back the transactions open on the temp and attached databases, before the
+    /// transaction of the main database ends.
+    pub(crate) fn end_aux_transactions(&self, rollback: bool) -> Result<()> {
+        let txs = std::mem::take(&mut *self.aux_transactions.write());
+        let mut result = if rollback {
+            Ok(())
+        } else {
+            self.commit_multi_database_frames(&txs)
+        };
+        for tx in txs.iter() {
+            let tx_result = if result.is_ok() {
+                self.end_aux_tx(tx, rollback)
+            } else {
+                self.end_aux_tx(tx, true)
+            };
+            if result.is_ok() {
+                result = tx_result;
+            }
+        }
+        result
+    }
+
+    /// Write the frames of a transaction that changed more than one database, and only then make
+    /// them visible, so that an error leaves none of the databases committed. If more than one
+    /// of them is a file, a super-journal also makes the commit atomic across a crash. The
+    /// transactions are ended afterwards as usual.
+    fn commit_multi_database_frames(&self, txs: &[AuxTransaction]) -> Result<()> {
+        let main_write = matches!(self.get_tx_state(), TransactionState::Write { .. })
+            && self.db.mv_store.is_none()
+            && !self.is_nested_stmt.load(Ordering::SeqCst);
+        // The pager of every database written to, with the path and IO of its file if it has one.
+        let mut participants: Vec<(Arc<Pager>, Option<(String, Arc<dyn IO>)>)> = Vec::new();
+        if main_write {
+            let file = (!self.db.path.starts_with(crate::util::MEMORY_PATH))
+                .then(|| (self.db.path.clone(), self.db.io.clone()));
+            participants.push((self.pager.read().clone(), file));
+        }
+        for tx in txs.iter().filter(|tx| tx.write) {
+            let file = if tx.database_id > 1 {
+                self.attached_databases
+                    .read()
+                    .get_database_by_index(tx.database_id)
+                    .filter(|db| !db.path.starts_with(crate::util::MEMORY_PATH))
+                    .map(|db| (db.path.clone(), db.io.clone()))
+            } else {
+                None
+            };
+            participants.push((self.get_pager_from_database_index(&tx.database_id), file));
+        }
+        if participants.len() < 2 {
+            return Ok(());
+        }
+        let files: Vec<_> = participants
+            .iter()
+            .filter_map(|(pager, file)| file.as_ref().map(|file| (pager, file)))
+            .collect();
+        let child_journals: Vec<(String, Arc<dyn IO>)> = files
+            .iter()
+            .map(|(_, (path, io))| (super_journal::child_journal_path(path), io.clone()))
+            .collect();
+        let super_journal = match files.first() {
+            Some((_, (path, io))) if files.len() > 1 => {
+                Some((super_journal::super_journal_path(io, path), io.clone()))
+            }
+            _ => None,
+        };
+        let remove_journals = || {
+            if let Some((path, io)) = &super_journal {
+                let _ = io.remove_file(path);
+            }
+            for (path, io) in &child_journals {
+                let _ = io.remove_file(path);
+            }
+        };
+        if let Some((super_path, super_io)) = &super_journal {
+            let child_paths: Vec<String> = child_journals
+                .iter()
+                .map(|(path, _)| path.clone())
+                .collect();
+            let written = super_journal::write_super_journal(super_io, super_path, &child_paths)
+                .and_then(|_| {
+                    files.iter().zip(&child_journals).try_for_each(
+                        |((pager, _), (child_path, io))| {
+                            super_journal::write_child_journal(
+                                io,
+                                child_path,
+                                super_path,
+                                pager.committed_wal_size(),
+                            )
+                        },
+                    )
+                });
+            if let Err(err) = written {
+                remove_journals();
+                return Err(err);
+            }
+        }
+        let mut result = participants.iter().try_for_each(|(pager, _)| {
+            pager
+                .io
+                .block(|| pager.prepare_commit(self.get_sync_mode(), self.get_data_sync_retry()))
+        });
+        // Deleting the super-journal is the commit point.
+        if let (true, Some((path, io))) = (result.is_ok(), &super_journal) {
+            result = io.remove_file(path);
+        }
+        if let Err(err) = result {
+            // The frames are rolled back with the transactions. Drop them from the WAL files too,
+            // then the journals that would otherwise drop them after a crash.
+            let truncated = participants.iter().fold(true, |truncated, (pager, _)| {
+                pager.truncate_uncommitted_frames().is_ok() && truncated
+            });
+            if truncated {
+                remove_journals();
+            }
+            return Err(err);
+        }
+        for (pager, _) in participants.iter() {
+            pager.publish_commit()?;
+        }
+        remove_journals();
+        Ok(())
+    }
+
     #[cfg(feature = "fs")]
     fn is_attached(&self, alias: &str) -> bool {
         self.attached_databases
@@ -1987,7 +2552,13 @@ impl Connection {
             .with_indexes(use_indexes)
             .with_views(use_views)
             .with_strict(use_strict);
-        let db = Self::from_uri_attached(path, db_opts, self.db.io.clone())?;
+        // An in-memory main database has no file system IO to open the attached file with.
+        let io = if self.db.path.starts_with(crate::util::MEMORY_PATH) {
+            Database::io_for_path(path)?
+        } else {
+            self.db.io.clone()
+        };
+        let db = Self::from_uri_attached(path, db_opts, io)?;
         let pager = Arc::new(db.init_pager(None)?);
 
         self.attached_databases.write().insert(alias, (db, pager));
@@ -2007,14 +2578,25 @@ impl Connection {
             )));
         }
 
-        // Remove from attached databases
-        let mut attached_dbs = self.attached_databases.write();
-        if attached_dbs.remove(alias).is_none() {
+        let Some((index, _)) = self.get_attached_database(alias) else {
             return Err(LimboError::InvalidArgument(format!(
                 "no such database: {alias}"
             )));
+        };
+        if self
+            .aux_transactions
+            .read()
+            .iter()
+            .any(|tx| tx.database_id == index)
+        {
+            return Err(LimboError::TxError(format!("database {alias} is locked")));
         }
 
+        // Remove from attached databases. The index may be reused by the next attached database,
+        // so its cached schema goes too.
+        self.attached_databases.write().remove(alias);
+        self.database_schemas.write().remove(&index);
+
         Ok(())
     }
 
@@ -2023,6 +2605,21 @@ impl Connection {
         self.attached_databases.read().get_database_by_name(alias)
     }
 
+    /// Get the name of a database by its index
+    pub(crate) fn get_database_name_by_index(&self, index: usize) -> Option<String> {
+        match index {
+            0 => Some("main".to_string()),
+            1 => Some("temp".to_string()),
+            _ => self
+                .attached_databases
+                .read()
+                .name_to_index
+                .iter()
+                .find(|(_, &idx)| idx == index)
+                .map(|(name, _)| name.clone()),
+        }
+    }
+
     /// List all attached database aliases
     pub fn list_attached_databases(&self) -> Vec<String> {
         self.attached_databases
@@ -2043,7 +2640,10 @@ impl Connection {
             let name_bytes = db_name_normalized.as_bytes();
             match_ignore_ascii_case!(match name_bytes {
                 b"main" => Ok(0),
-                b"temp" => Ok(1),
+                b"temp" => {
+                    self.open_temp_database()?;
+                    Ok(1)
+                }
                 _ => {
                     // Look up attached database
                     if let Some((idx, _attached_db)) =
@@ -2057,9 +2657,18 @@ impl Connection {
                     }
                 }
             })
+        } else if is_temp_schema_table_name(qualified_name.name.as_str()) {
+            self.open_temp_database()?;
+            Ok(1)
+        } else if self.temp_schema_has_table(qualified_name.name.as_str()) {
+            // Unqualified table name - TEMP tables hide the tables of the main database
+            Ok(1)
         } else {
-            // Unqualified table name - use main database
-            Ok(0)
+            // Unqualified table name - use main database, unless only an attached database has
+            // a table of that name
+            Ok(self
+                .attached_database_with_table(qualified_name.name.as_str())
+                .unwrap_or(0))
         }
     }
 
@@ -2070,9 +2679,15 @@ impl Connection {
             let schema = self.schema.read();
             f(&schema)
         } else if database_id == 1 {
-            // Temp database - uses same schema as main for now, but this will change later.
-            let schema = self.schema.read();
-            f(&schema)
+            // Temp database - the schema lives only in the connection, and is empty until the
+            // temp database is opened.
+            let schemas = self.database_schemas.read();
+            if let Some(schema) = schemas.get(&1) {
+                return f(schema);
+            }
+            drop(schemas);
+            let indexes_enabled = self.schema.read().indexes_enabled();
+            f(&Schema::new(indexes_enabled))
         } else {
             // Attached database - check cache first, then load from database
             let mut schemas = self.database_schemas.write();
@@ -2124,8 +2739,13 @@ impl Connection {
         let main_path = Self::get_canonical_path_for_database(&self.db);
         databases.push((0, "main".to_string(), main_path));
 
-        // Add attached databases
+        // Add the temp database (always seq=1, name="temp") once it is open
         let attached_dbs = self.attached_databases.read();
+        if attached_dbs.index_to_data.contains_key(&1) {
+            databases.push((1, "temp".to_string(), String::new()));
+        }
+
+        // Add attached databases
         for (alias, &seq_number) in attached_dbs.name_to_index.iter() {
             let file_path = if let Some((db, _pager)) = attached_dbs.index_to_data.get(&seq_number)
             {
@@ -2153,6 +2773,208 @@ impl Connection {
         self.query_only.store(value, Ordering::SeqCst);
     }
 
+    pub fn get_recursive_triggers(&self) -> bool {
+        self.recursive_triggers.load(Ordering::SeqCst)
+    }
+
+    pub fn set_recursive_triggers(&self, value: bool) {
+        self.recursive_triggers.store(value, Ordering::SeqCst);
+    }
+
+    pub fn get_foreign_keys(&self) -> bool {
+        self.foreign_keys.load(Ordering::SeqCst)
+    }
+
+    pub fn set_foreign_keys(&self, value: bool) {
+        self.foreign_keys.store(value, Ordering::SeqCst);
+    }
+
+    pub fn get_ignore_check_constraints(&self) -> bool {
+        self.ignore_check_constraints.load(Ordering::SeqCst)
+    }
+
+    pub fn set_ignore_check_constraints(&self, value: bool) {
+        self.ignore_check_constraints.store(value, Ordering::SeqCst);
+    }
+
+    pub fn get_analysis_limit(&self) -> i64 {
+        self.analysis_limit.load(Ordering::SeqCst)
+    }
+
+    pub fn set_analysis_limit(&self, value: i64) {
+        self.analysis_limit.store(value, Ordering::SeqCst);
+    }
+
+    pub(crate) fn get_deferred_fk_violations(&self) -> i64 {
+        self.deferred_fk_violations.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_deferred_fk_violations(&self, value: i64) {
+        self.deferred_fk_violations.store(value, Ordering::SeqCst);
+    }
+
+    pub(crate) fn add_deferred_fk_violations(&self, amount: i64) {
+        self.deferred_fk_violations
+            .fetch_add(amount, Ordering::SeqCst);
+    }
+
+    /// Index of the innermost open savepoint with the given normalized name.
+    pub(crate) fn find_savepoint(&self, name: &str) -> Option<usize> {
+        self.savepoints
+            .read()
+            .iter()
+            .rposition(|savepoint| savepoint.name.as_deref() == Some(name))
+    }
+
+    pub(crate) fn get_savepoint(&self, idx: usize) -> Savepoint {
+        self.savepoints.read()[idx].clone()
+    }
+
+    pub(crate) fn push_savepoint(&self, savepoint: Savepoint) {
+        self.savepoints.write().push(savepoint);
+    }
+
+    /// Close the savepoint at `idx` and all savepoints opened after it.
+    pub(crate) fn truncate_savepoints(&self, idx: usize) {
+        self.savepoints.write().truncate(idx);
+    }
+
+    pub(crate) fn clear_savepoints(&self) {
+        self.savepoints.write().clear();
+    }
+
+    /// Mirror a newly opened savepoint in the databases written by the transaction.
+    pub(crate) fn open_aux_savepoints(&self) {
+        for tx in self.aux_transactions.write().iter_mut() {
+            if tx.write {
+                self.get_pager_from_database_index(&tx.database_id)
+                    .open_savepoint();
+                tx.savepoint_schemas
+                    .push(self.database_schemas.read().get(&tx.database_id).cloned());
+            }
+        }
+    }
+
+    /// Release the savepoint at `idx` in the databases written by the transaction.
+    pub(crate) fn release_aux_savepoints(&self, idx: usize) {
+        for tx in self.aux_transactions.write().iter_mut() {
+            if !tx.write {
+                continue;
+            }
+            let aux_idx = idx.saturating_sub(tx.savepoint_base);
+            if aux_idx < tx.savepoint_schemas.len() {
+                self.get_pager_from_database_index(&tx.database_id)
+                    .release_savepoint(aux_idx);
+                tx.savepoint_schemas.truncate(aux_idx);
+            }
+            tx.savepoint_base = tx.savepoint_base.min(idx);
+        }
+    }
+
+    /// Roll back the databases written by the transaction to the savepoint at `idx`. A database
+    /// whose write transaction began after the savepoint was opened is rolled back entirely.
+    pub(crate) fn rollback_aux_savepoints(&self, idx: usize) -> Result<()> {
+        let mut txs = self.aux_transactions.write();
+        let mut result = Ok(());
+        let mut i = 0;
+        while i < txs.len() {
+            let tx = &mut txs[i];
+            if !tx.write {
+                i += 1;
+                continue;
+            }
+            if idx < tx.savepoint_base {
+                let tx = txs.remove(i);
+                if let Err(err) = self.end_aux_tx(&tx, true) {
+                    result = Err(err);
+                }
+                continue;
+            }
+            let aux_idx = idx - tx.savepoint_base;
+            self.get_pager_from_database_index(&tx.database_id)
+                .rollback_to_savepoint(aux_idx)?;
+            let schema = tx.savepoint_schemas[aux_idx].clone();
+            tx.savepoint_schemas.truncate(aux_idx + 1);
+            let mut schemas = self.database_schemas.write();
+            match schema {
+                Some(schema) => schemas.insert(tx.database_id, schema),
+                None => schemas.remove(&tx.database_id),
+            };
+            i += 1;
+        }
+        result
+    }
+
+    /// Open the statement journal of a write statement running inside an explicit transaction,
+    /// so that a failing statement can be undone without rolling back the whole transaction.
+    pub(crate) fn begin_statement(&self, pager: &Pager) {
+        self.end_statement(pager);
+        self.savepoints.write().push(Savepoint {
+            name: None,
+            starts_transaction: false,
+            schema: self.schema.read().clone(),
+            deferred_fk_violations: self.get_deferred_fk_violations(),
+        });
+        pager.open_savepoint();
+    }
+
+    /// Release the statement journal, if any, keeping the changes of the statement.
+    pub(crate) fn end_statement(&self, pager: &Pager) {
+        let mut savepoints = self.savepoints.write();
+        if savepoints.last().is_some_and(|sp| sp.name.is_none()) {
+            savepoints.pop();
+            pager.release_savepoint(savepoints.len());
+        }
+        drop(savepoints);
+        for tx in self.aux_transactions.write().iter_mut() {
+            if tx.statement_schema.take().is_some() {
+                self.get_pager_from_database_index(&tx.database_id)
+                    .release_savepoint(tx.savepoint_schemas.len());
+            }
+        }
+    }
+
+    pub(crate) fn has_statement_journal(&self) -> bool {
+        self.savepoints
+            .read()
+            .last()
+            .is_some_and(|sp| sp.name.is_none())
+            || self
+                .aux_transactions
+                .read()
+                .iter()
+                .any(|tx| tx.statement_schema.is_some())
+    }
+
+    /// Undo the changes of the running statement using its statement journals.
+    pub(crate) fn rollback_statement(&self, pager: &Pager) -> Result<()> {
+        let mut savepoints = self.savepoints.write();
+        if savepoints.last().is_some_and(|sp| sp.name.is_none()) {
+            let savepoint = savepoints.pop().unwrap();
+            let idx = savepoints.len();
+            pager.rollback_to_savepoint(idx)?;
+            pager.release_savepoint(idx);
+            *self.schema.write() = savepoint.schema;
+            self.set_deferred_fk_violations(savepoint.deferred_fk_violations);
+        }
+        drop(savepoints);
+        for tx in self.aux_transactions.write().iter_mut() {
+            let Some(schema) = tx.statement_schema.take() else {
+                continue;
+            };
+            let pager = self.get_pager_from_database_index(&tx.database_id);
+            let idx = tx.savepoint_schemas.len();
+            pager.rollback_to_savepoint(idx)?;
+            pager.release_savepoint(idx);
+            let mut schemas = self.database_schemas.write();
+            match schema {
+                Some(schema) => schemas.insert(tx.database_id, schema),
+                None => schemas.remove(&tx.database_id),
+            };
+        }
+        Ok(())
+    }
+
     pub fn get_sync_mode(&self) -> SyncMode {
         *self.sync_mode.read()
     }
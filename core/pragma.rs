@@ -35,6 +35,10 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
     use PragmaName::*;
 
     match pragma {
+        AnalysisLimit => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["analysis_limit"],
+        ),
         ApplicationId => Pragma::new(
             PragmaFlags::NoColumns1 | PragmaFlags::Result0,
             &["application_id"],
@@ -66,6 +70,7 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
             PragmaFlags::NeedSchema | PragmaFlags::Result0 | PragmaFlags::SchemaReq,
             &["module_list"],
         ),
+        Optimize => Pragma::new(PragmaFlags::NeedSchema, &[]),
         PageCount => Pragma::new(
             PragmaFlags::NeedSchema | PragmaFlags::Result0 | PragmaFlags::SchemaReq,
             &["page_count"],
@@ -93,6 +98,22 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
             PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
             &["cid", "name", "type", "notnull", "dflt_value", "pk"],
         ),
+        TableXinfo => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
+            &[
+                "cid",
+                "name",
+                "type",
+                "notnull",
+                "dflt_value",
+                "pk",
+                "hidden",
+            ],
+        ),
+        TempStore => Pragma::new(
+            PragmaFlags::NoColumns1 | PragmaFlags::Result0,
+            &["temp_store"],
+        ),
         UserVersion => Pragma::new(
             PragmaFlags::NoColumns1 | PragmaFlags::Result0,
             &["user_version"],
@@ -118,7 +139,36 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
             PragmaFlags::Result0 | PragmaFlags::NoColumns1,
             &["query_only"],
         ),
+        RecursiveTriggers => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["recursive_triggers"],
+        ),
+        ForeignKeyCheck => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result0 | PragmaFlags::Result1,
+            &["table", "rowid", "parent", "fkid"],
+        ),
+        ForeignKeyList => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
+            &[
+                "id",
+                "seq",
+                "table",
+                "from",
+                "to",
+                "on_update",
+                "on_delete",
+                "match",
+            ],
+        ),
+        ForeignKeys => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["foreign_keys"],
+        ),
         FreelistCount => Pragma::new(PragmaFlags::Result0, &["freelist_count"]),
+        IgnoreCheckConstraints => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["ignore_check_constraints"],
+        ),
         EncryptionKey => Pragma::new(
             PragmaFlags::Result0 | PragmaFlags::SchemaReq | PragmaFlags::NoColumns1,
             &["hexkey"],
@@ -1,9 +1,10 @@
 use crate::function::Func;
 use crate::incremental::view::IncrementalView;
 use crate::translate::expr::{
-    bind_and_rewrite_expr, walk_expr, BindingBehavior, ParamState, WalkControl,
+    bind_and_rewrite_expr, walk_expr, walk_expr_mut, BindingBehavior, ParamState, WalkControl,
 };
 use crate::translate::planner::ROWID_STRS;
+use crate::translate::schema::dequote_ident;
 use parking_lot::RwLock;
 
 /// Simple view structure for non-materialized views
@@ -18,15 +19,77 @@ pub struct View {
 /// Type alias for regular views collection
 pub type ViewsMap = HashMap<String, View>;
 
+/// A trigger as stored in sqlite_schema. The trigger body is kept as AST and compiled
+/// into a subprogram every time a statement that fires it is translated.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub table_name: String,
+    pub sql: String,
+    pub time: ast::TriggerTime,
+    pub event: ast::TriggerEvent,
+    pub for_each_row: bool,
+    pub when_clause: Option<Box<Expr>>,
+    pub commands: Vec<ast::TriggerCmd>,
+}
+
+impl Trigger {
+    pub fn from_sql(sql: &str) -> Result<Trigger> {
+        let mut parser = Parser::new(sql.as_bytes());
+        let cmd = parser.next_cmd()?;
+        match cmd {
+            Some(Cmd::Stmt(Stmt::CreateTrigger {
+                trigger_name,
+                time,
+                event,
+                tbl_name,
+                for_each_row,
+                when_clause,
+                commands,
+                ..
+            })) => Ok(Trigger {
+                name: normalize_ident(trigger_name.name.as_str()),
+                table_name: normalize_ident(tbl_name.name.as_str()),
+                sql: sql.to_string(),
+                // BEFORE is the default when no trigger time is given
+                time: time.unwrap_or(ast::TriggerTime::Before),
+                event,
+                for_each_row,
+                when_clause,
+                commands,
+            }),
+            _ => Err(LimboError::ParseError(format!(
+                "invalid trigger definition: {sql}"
+            ))),
+        }
+    }
+
+    /// Returns true if this trigger fires for an UPDATE that assigns `updated_columns`.
+    pub fn fires_on_update_of(&self, updated_columns: &[String]) -> bool {
+        match &self.event {
+            ast::TriggerEvent::Update => true,
+            ast::TriggerEvent::UpdateOf(columns) => columns.iter().any(|column| {
+                let column = normalize_ident(column.as_str());
+                updated_columns.iter().any(|updated| updated == &column)
+            }),
+            _ => false,
+        }
+    }
+}
+
+use crate::stats::{
+    build_table_stats, Stat1Row, Stat4Row, TableStats, STAT1_TABLE_NAME, STAT4_TABLE_NAME,
+};
 use crate::storage::btree::BTreeCursor;
 use crate::translate::collate::CollationSeq;
-use crate::translate::plan::{SelectPlan, TableReferences};
+use crate::translate::plan::{Plan, RecursiveCte, SelectPlan, TableReferences};
 use crate::util::{
     module_args_from_sql, module_name_from_sql, type_from_name, IOExt, UnparsedFromSqlIndex,
+    PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX,
 };
 use crate::{
     contains_ignore_ascii_case, eq_ignore_ascii_case, match_ignore_ascii_case, Connection,
-    LimboError, MvCursor, MvStore, Pager, RefValue, SymbolTable, VirtualTable,
+    LimboError, MvCursor, MvStore, Pager, RefValue, SymbolTable, Value, VirtualTable,
 };
 use crate::{util::normalize_ident, Result};
 use core::fmt;
@@ -43,6 +106,8 @@ use turso_parser::{
 
 const SCHEMA_TABLE_NAME: &str = "sqlite_schema";
 const SCHEMA_TABLE_NAME_ALT: &str = "sqlite_master";
+const TEMP_SCHEMA_TABLE_NAME: &str = "sqlite_temp_schema";
+const TEMP_SCHEMA_TABLE_NAME_ALT: &str = "sqlite_temp_master";
 pub const DBSP_TABLE_PREFIX: &str = "__turso_internal_dbsp_state_";
 
 /// Check if a table name refers to a system table that should be protected from direct writes
@@ -50,9 +115,17 @@ pub fn is_system_table(table_name: &str) -> bool {
     let normalized = table_name.to_lowercase();
     normalized == SCHEMA_TABLE_NAME
         || normalized == SCHEMA_TABLE_NAME_ALT
+        || normalized == TEMP_SCHEMA_TABLE_NAME
+        || normalized == TEMP_SCHEMA_TABLE_NAME_ALT
         || table_name.starts_with(DBSP_TABLE_PREFIX)
 }
 
+/// Check if a table name refers to the schema table of the temp database
+pub fn is_temp_schema_table_name(table_name: &str) -> bool {
+    let normalized = normalize_ident(table_name);
+    normalized == TEMP_SCHEMA_TABLE_NAME || normalized == TEMP_SCHEMA_TABLE_NAME_ALT
+}
+
 #[derive(Debug)]
 pub struct Schema {
     pub tables: HashMap<String, Arc<Table>>,
@@ -74,6 +147,12 @@ pub struct Schema {
 
     /// Mapping from table names to the materialized views that depend on them
     pub table_to_materialized_views: HashMap<String, Vec<String>>,
+
+    /// table_name to list of triggers defined on the table (or view)
+    pub triggers: HashMap<String, VecDeque<Arc<Trigger>>>,
+
+    /// table_name to the statistics of the table and its indexes gathered by ANALYZE
+    pub stats: HashMap<String, Arc<TableStats>>,
 }
 
 impl Schema {
@@ -97,6 +176,7 @@ impl Schema {
         let incremental_views = HashMap::new();
         let views: ViewsMap = HashMap::new();
         let table_to_materialized_views: HashMap<String, Vec<String>> = HashMap::new();
+        let triggers: HashMap<String, VecDeque<Arc<Trigger>>> = HashMap::new();
         Self {
             tables,
             materialized_view_names,
@@ -108,6 +188,8 @@ impl Schema {
             indexes_enabled,
             schema_version: 0,
             table_to_materialized_views,
+            triggers,
+            stats: HashMap::new(),
         }
     }
 
@@ -147,6 +229,7 @@ impl Schema {
 
         if self.views.contains_key(&name) {
             self.views.remove(&name);
+            self.triggers.remove(&name);
             Ok(())
         } else if self.materialized_view_names.contains(&name) {
             // Remove from tables
@@ -217,7 +300,9 @@ impl Schema {
 
     pub fn get_table(&self, name: &str) -> Option<Arc<Table>> {
         let name = normalize_ident(name);
-        let name = if name.eq_ignore_ascii_case(SCHEMA_TABLE_NAME_ALT) {
+        let name = if name.eq_ignore_ascii_case(SCHEMA_TABLE_NAME_ALT)
+            || is_temp_schema_table_name(&name)
+        {
             SCHEMA_TABLE_NAME
         } else {
             &name
@@ -228,6 +313,8 @@ impl Schema {
     pub fn remove_table(&mut self, table_name: &str) {
         let name = normalize_ident(table_name);
         self.tables.remove(&name);
+        self.triggers.remove(&name);
+        self.stats.remove(&name);
 
         // If this was a materialized view, also clean up the metadata
         if self.materialized_view_names.remove(&name) {
@@ -285,6 +372,91 @@ impl Schema {
             .get_mut(&name)
             .expect("Must have the index")
             .retain_mut(|other_idx| other_idx.name != idx.name);
+        if let Some(stats) = self.stats.get_mut(&name) {
+            Arc::make_mut(stats).indexes.remove(&idx.name);
+        }
+    }
+
+    /// Returns the statistics gathered by ANALYZE for a table and its indexes, if any.
+    pub fn table_stats(&self, table_name: &str) -> Option<&Arc<TableStats>> {
+        self.stats.get(&normalize_ident(table_name))
+    }
+
+    /// Replaces the statistics of the tables with the contents of `sqlite_stat1` and
+    /// `sqlite_stat4`.
+    pub fn set_stats(&mut self, stat1: Vec<Stat1Row>, stat4: Vec<Stat4Row>) {
+        let stats = build_table_stats(stat1, stat4, |table_name, index_name| {
+            let index_name = normalize_ident(index_name);
+            // The row of the PRIMARY KEY of a WITHOUT ROWID table is named after the table.
+            if index_name == table_name {
+                let btree = self.get_btree_table(table_name)?;
+                return (!btree.has_rowid)
+                    .then(|| (Index::without_rowid_primary_key(&btree).name, false));
+            }
+            let index = self.get_index(table_name, &index_name)?;
+            Some((index.name.clone(), index.where_clause.is_some()))
+        });
+        self.stats = stats
+            .into_iter()
+            .map(|(name, stats)| (name, Arc::new(stats)))
+            .collect();
+    }
+
+    pub fn add_trigger(&mut self, trigger: Arc<Trigger>) {
+        let table_name = normalize_ident(&trigger.table_name);
+        // Like indexes, SQLite keeps the most recently created trigger at the head of the list,
+        // which determines the order in which triggers on the same table fire.
+        self.triggers
+            .entry(table_name)
+            .or_default()
+            .push_front(trigger);
+    }
+
+    pub fn get_trigger(&self, name: &str) -> Option<&Arc<Trigger>> {
+        let name = normalize_ident(name);
+        self.triggers
+            .values()
+            .flat_map(|triggers| triggers.iter())
+            .find(|trigger| trigger.name == name)
+    }
+
+    pub fn get_triggers_for_table(&self, table_name: &str) -> impl Iterator<Item = &Arc<Trigger>> {
+        let name = normalize_ident(table_name);
+        self.triggers
+            .get(&name)
+            .map(|v| v.iter())
+            .unwrap_or_default()
+    }
+
+    /// Returns the foreign keys that refer to `table_name` as their parent table, as pairs of
+    /// the child table and the position of the constraint in its `foreign_keys`, ordered by
+    /// child table name.
+    pub fn get_referencing_foreign_keys(&self, table_name: &str) -> Vec<(Arc<BTreeTable>, usize)> {
+        let name = normalize_ident(table_name);
+        let mut referencing = self
+            .tables
+            .values()
+            .filter_map(|table| table.btree())
+            .flat_map(|table| {
+                (0..table.foreign_keys.len())
+                    .filter(|&i| table.foreign_keys[i].parent_table == name)
+                    .map(|i| (table.clone(), i))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        referencing.sort_by(|(a, i), (b, j)| a.name.cmp(&b.name).then(i.cmp(j)));
+        referencing
+    }
+
+    pub fn remove_trigger(&mut self, name: &str) -> Result<()> {
+        let name = normalize_ident(name);
+        for triggers in self.triggers.values_mut() {
+            if let Some(pos) = triggers.iter().position(|trigger| trigger.name == name) {
+                triggers.remove(pos);
+                return Ok(());
+            }
+        }
+        Err(LimboError::ParseError(format!("no such trigger: {name}")))
     }
 
     pub fn table_has_indexes(&self, table_name: &str) -> bool {
@@ -380,10 +552,24 @@ impl Schema {
             pager.io.block(|| cursor.next())?;
         }
 
+        let stat1 = self.read_stat_rows(&pager, STAT1_TABLE_NAME)?;
+        let stat4 = self.read_stat_rows(&pager, STAT4_TABLE_NAME)?;
+
         pager.end_read_tx()?;
 
         self.populate_indices(from_sql_indexes, automatic_indices)?;
 
+        self.set_stats(
+            stat1
+                .iter()
+                .filter_map(|row| Stat1Row::from_values(row))
+                .collect(),
+            stat4
+                .iter()
+                .filter_map(|row| Stat4Row::from_values(row))
+                .collect(),
+        );
+
         self.populate_materialized_views(
             materialized_view_info,
             dbsp_state_roots,
@@ -393,6 +579,31 @@ impl Schema {
         Ok(())
     }
 
+    /// Reads the rows of a statistics table, if the database has one, while the schema is loaded
+    /// from the b-tree.
+    fn read_stat_rows(&self, pager: &Arc<Pager>, table_name: &str) -> Result<Vec<Vec<Value>>> {
+        let Some(table) = self.get_btree_table(table_name) else {
+            return Ok(Vec::new());
+        };
+        let mut cursor = BTreeCursor::new_table(
+            None,
+            Arc::clone(pager),
+            table.root_page,
+            table.columns.len(),
+        );
+        let mut rows = Vec::new();
+        pager.io.block(|| cursor.rewind())?;
+        loop {
+            let Some(row) = pager.io.block(|| cursor.record())? else {
+                break;
+            };
+            rows.push(row.get_values().iter().map(|v| v.to_owned()).collect());
+            drop(row);
+            pager.io.block(|| cursor.next())?;
+        }
+        Ok(rows)
+    }
+
     /// Populate indices parsed from the schema.
     /// from_sql_indexes: indices explicitly created with CREATE INDEX
     /// automatic_indices: indices created automatically for primary key and unique constraints
@@ -431,6 +642,10 @@ impl Schema {
             automatic_indexes.reverse(); // reverse so we can pop() without shifting array elements, while still processing in left-to-right order
             let mut pk_index_added = false;
             for unique_set in table.unique_sets.iter().filter(|us| us.columns.len() == 1) {
+                if unique_set.is_primary_key && !table.has_rowid {
+                    // The PRIMARY KEY of a WITHOUT ROWID table is the table b-tree itself.
+                    continue;
+                }
                 let col_name = &unique_set.columns.first().unwrap().0;
                 let Some((pos_in_table, column)) = table.get_column(col_name) else {
                     return Err(LimboError::ParseError(format!(
@@ -468,6 +683,9 @@ impl Schema {
                 }
             }
             for unique_set in table.unique_sets.iter().filter(|us| us.columns.len() > 1) {
+                if unique_set.is_primary_key && !table.has_rowid {
+                    continue;
+                }
                 if unique_set.is_primary_key {
                     assert!(table.primary_key_columns.len() == unique_set.columns.len(), "trying to add a {}-column primary key index for table {}, but the table has {} primary key columns", unique_set.columns.len(), table.name, table.primary_key_columns.len());
                     // Add composite primary key index
@@ -545,6 +763,8 @@ impl Schema {
                 has_rowid: true,
                 is_strict: false,
                 has_autoincrement: false,
+                foreign_keys: vec![],
+                checks: vec![],
 
                 unique_sets: vec![],
             })));
@@ -713,6 +933,12 @@ impl Schema {
                     }
                 }
             }
+            "trigger" => {
+                let sql = maybe_sql.expect("sql should be present for trigger");
+                assert!(mv_store.is_none(), "triggers not yet supported for mvcc");
+                let trigger = Trigger::from_sql(sql)?;
+                self.add_trigger(Arc::new(trigger));
+            }
             _ => {}
         };
 
@@ -769,6 +995,17 @@ impl Clone for Schema {
             .map(|(name, view)| (name.clone(), view.clone()))
             .collect();
         let views = self.views.clone();
+        let triggers = self
+            .triggers
+            .iter()
+            .map(|(name, triggers)| {
+                let triggers = triggers
+                    .iter()
+                    .map(|trigger| Arc::new((**trigger).clone()))
+                    .collect();
+                (name.clone(), triggers)
+            })
+            .collect();
         Self {
             tables,
             materialized_view_names,
@@ -780,6 +1017,8 @@ impl Clone for Schema {
             indexes_enabled: self.indexes_enabled,
             schema_version: self.schema_version,
             table_to_materialized_views: self.table_to_materialized_views.clone(),
+            triggers,
+            stats: self.stats.clone(),
         }
     }
 }
@@ -876,6 +1115,151 @@ pub struct UniqueSet {
     pub is_primary_key: bool,
 }
 
+/// A FOREIGN KEY constraint, declared either as a `REFERENCES` column constraint or as a
+/// `FOREIGN KEY` table constraint.
+/// https://www.sqlite.org/foreignkeys.html
+#[derive(Clone, Debug)]
+pub struct ForeignKey {
+    /// Columns of the child table, i.e. the table declaring the constraint.
+    pub child_columns: Vec<String>,
+    /// Normalized name of the parent table.
+    pub parent_table: String,
+    /// Name of the parent table as written in the constraint, reported by
+    /// `PRAGMA foreign_key_list`.
+    pub parent_table_str: String,
+    /// Columns of the parent key. Empty if the constraint refers to the primary key of the
+    /// parent table implicitly.
+    pub parent_columns: Vec<String>,
+    pub on_delete: ast::RefAct,
+    pub on_update: ast::RefAct,
+    /// Whether the constraint is `DEFERRABLE INITIALLY DEFERRED`, in which case it is only
+    /// checked when the transaction commits.
+    pub deferred: bool,
+}
+
+impl ForeignKey {
+    fn new(
+        child_columns: Vec<String>,
+        clause: &ast::ForeignKeyClause,
+        defer_clause: Option<&ast::DeferSubclause>,
+    ) -> Result<Self> {
+        let parent_columns: Vec<String> = clause
+            .columns
+            .iter()
+            .map(|column| normalize_ident(column.col_name.as_str()))
+            .collect();
+        if !parent_columns.is_empty() && parent_columns.len() != child_columns.len() {
+            if child_columns.len() == 1 {
+                crate::bail_parse_error!(
+                    "foreign key on {} should reference only one column of table {}",
+                    child_columns[0],
+                    clause.tbl_name.as_str()
+                );
+            }
+            crate::bail_parse_error!(
+                "number of columns in foreign key does not match the number of columns in the referenced table"
+            );
+        }
+        let mut on_delete = ast::RefAct::NoAction;
+        let mut on_update = ast::RefAct::NoAction;
+        for arg in clause.args.iter() {
+            match arg {
+                ast::RefArg::OnDelete(action) => on_delete = *action,
+                ast::RefArg::OnUpdate(action) => on_update = *action,
+                // ON INSERT and MATCH are parsed but, as in SQLite, have no effect.
+                ast::RefArg::OnInsert(_) | ast::RefArg::Match(_) => {}
+            }
+        }
+        let deferred = defer_clause.is_some_and(|defer| {
+            defer.deferrable
+                && defer.init_deferred == Some(ast::InitDeferredPred::InitiallyDeferred)
+        });
+        Ok(Self {
+            child_columns,
+            parent_table: normalize_ident(clause.tbl_name.as_str()),
+            parent_table_str: clause.tbl_name.as_str().to_string(),
+            parent_columns,
+            on_delete,
+            on_update,
+            deferred,
+        })
+    }
+}
+
+/// A CHECK constraint, declared either as a column constraint or as a table constraint.
+/// https://www.sqlite.org/lang_createtable.html#ckconst
+#[derive(Clone, Debug)]
+pub struct CheckConstraint {
+    /// The name given with `CONSTRAINT <name>`, if any.
+    pub name: Option<ast::Name>,
+    /// The column whose definition declares the constraint, or None for a table constraint.
+    pub column: Option<String>,
+    /// A row violates the constraint if the expression is false. NULL does not violate it.
+    pub expr: Box<Expr>,
+}
+
+impl CheckConstraint {
+    /// The text of the error raised when a row violates the constraint: its name, or its
+    /// expression if it has no name.
+    pub fn violation_description(&self) -> String {
+        match &self.name {
+            Some(name) => dequote_ident(name),
+            None => self.expr.to_string(),
+        }
+    }
+
+    /// Appends the definition of the constraint to the SQL of its table.
+    fn push_sql(&self, sql: &mut String) {
+        if let Some(name) = &self.name {
+            sql.push_str("CONSTRAINT ");
+            sql.push_str(name.as_str());
+            sql.push(' ');
+        }
+        sql.push_str("CHECK (");
+        sql.push_str(&self.expr.to_string());
+        sql.push(')');
+    }
+
+    /// Returns true if the expression uses one of the table columns `names`, or the rowid if
+    /// `rowid` is set.
+    pub fn uses_any_column(&self, names: &[String], rowid: bool) -> bool {
+        expr_column_names(&self.expr)
+            .iter()
+            .any(|name| names.contains(name) || (rowid && ROWID_STRS.contains(&name.as_str())))
+    }
+}
+
+/// The expression of a `GENERATED ALWAYS AS (expr)` column.
+/// https://www.sqlite.org/gencol.html
+#[derive(Clone, Debug)]
+pub struct GeneratedColumn {
+    pub expr: Box<Expr>,
+    /// A STORED column is computed when the row is written and stored in the record. A VIRTUAL
+    /// column is computed when it is read and takes no space in the record.
+    pub stored: bool,
+}
+
+impl GeneratedColumn {
+    /// Returns true if the expression uses one of the table columns `names`.
+    pub fn uses_any_column(&self, names: &[String]) -> bool {
+        expr_column_names(&self.expr)
+            .iter()
+            .any(|name| names.contains(name))
+    }
+}
+
+/// Returns the normalized names of the columns an expression of a table refers to.
+fn expr_column_names(expr: &Expr) -> Vec<String> {
+    let mut names = vec![];
+    let _ = walk_expr(expr, &mut |e: &Expr| -> Result<WalkControl> {
+        if let Expr::Id(name) | Expr::Qualified(_, name) | Expr::DoublyQualified(_, _, name) = e {
+            names.push(normalize_ident(name.as_str()));
+        }
+        Ok(WalkControl::Continue)
+    });
+    names
+}
+
 #[derive(Clone, Debug)]
 pub struct BTreeTable {
     pub root_page: usize,
@@ -886,6 +1270,10 @@ pub struct BTreeTable {
     pub is_strict: bool,
     pub has_autoincrement: bool,
     pub unique_sets: Vec<UniqueSet>,
+    /// FOREIGN KEY constraints of the table, in declaration order.
+    pub foreign_keys: Vec<ForeignKey>,
+    /// CHECK constraints of the table, in declaration order.
+    pub checks: Vec<CheckConstraint>,
 }
 
 impl BTreeTable {
@@ -903,6 +1291,122 @@ impl BTreeTable {
         col.is_rowid_alias
     }
 
+    /// Returns the position of a column in the records of the table's b-tree.
+    /// The rows of a WITHOUT ROWID table are stored in the b-tree of its PRIMARY KEY, with the
+    /// PRIMARY KEY columns first and then the other columns in table order. VIRTUAL generated
+    /// columns are not stored.
+    pub fn column_storage_position(&self, column: usize) -> usize {
+        if self.has_rowid {
+            return self.columns[..column]
+                .iter()
+                .filter(|col| !col.is_virtual_generated())
+                .count();
+        }
+        if let Some(pos) = self
+            .primary_key_columns
+            .iter()
+            .position(|(name, _)| self.get_column(name).is_some_and(|(pos, _)| pos == column))
+        {
+            return pos;
+        }
+        self.primary_key_columns.len()
+            + self.columns[..column]
+                .iter()
+                .filter(|col| !col.primary_key && !col.is_virtual_generated())
+                .count()
+    }
+
+    /// Returns true if the records of the table hold a value for each of its columns, in table
+    /// order.
+    pub fn stores_columns_in_order(&self) -> bool {
+        self.has_rowid && !self.columns.iter().any(|col| col.is_virtual_generated())
+    }
+
+    /// Checks the definitions of the generated columns of the table.
+    pub fn check_generated_columns(&self) -> Result<()> {
+        for column in &self.columns {
+            let Some(generated) = &column.generated else {
+                continue;
+            };
+            if column.default.is_some() {
+                crate::bail_parse_error!("cannot use DEFAULT on a generated column");
+            }
+            if column.primary_key {
+                crate::bail_parse_error!("generated columns cannot be part of the PRIMARY KEY");
+            }
+            for name in expr_column_names(&generated.expr) {
+                if self.get_column(&name).is_none() {
+                    crate::bail_parse_error!("no such column: {}", name);
+                }
+            }
+        }
+        self.generated_columns_order()?;
+        Ok(())
+    }
+
+    /// Returns the positions of the generated columns whose value changes when the columns at
+    /// `changed` do, directly or through other generated columns.
+    pub fn generated_columns_depending_on(&self, changed: &[usize]) -> Vec<usize> {
+        let mut changed_names = changed
+            .iter()
+            .filter_map(|pos| self.columns[*pos].name.clone())
+            .collect::<Vec<_>>();
+        let mut dependents = vec![];
+        for pos in self.generated_columns_order().unwrap_or_default() {
+            let column = &self.columns[pos];
+            if column
+                .generated
+                .as_ref()
+                .unwrap()
+                .uses_any_column(&changed_names)
+            {
+                changed_names.extend(column.name.clone());
+                dependents.push(pos);
+            }
+        }
+        dependents
+    }
+
+    /// Returns the positions of the generated columns, ordered so that each one comes after the
+    /// generated columns its expression uses.
+    pub fn generated_columns_order(&self) -> Result<Vec<usize>> {
+        fn visit(
+            table: &BTreeTable,
+            column: usize,
+            visiting: &mut Vec<usize>,
+            order: &mut Vec<usize>,
+        ) -> Result<()> {
+            if order.contains(&column) {
+                return Ok(());
+            }
+            let name = table.columns[column].name.as_deref().unwrap_or_default();
+            visiting.push(column);
+            let generated = table.columns[column].generated.as_ref().unwrap();
+            for used in expr_column_names(&generated.expr) {
+                let Some((pos, col)) = table.get_column(&used) else {
+                    continue;
+                };
+                if col.generated.is_none() {
+                    continue;
+                }
+                if visiting.contains(&pos) {
+                    crate::bail_parse_error!("generated column loop on \"{}\"", name);
+                }
+                visit(table, pos, visiting, order)?;
+            }
+            visiting.pop();
+            order.push(column);
+            Ok(())
+        }
+        let mut order = vec![];
+        for (pos, column) in self.columns.iter().enumerate() {
+            if column.generated.is_some() {
+                visit(self, pos, &mut vec![], &mut order)?;
+            }
+        }
+        Ok(order)
+    }
+
     /// Returns the column position and column for a given column name.
     /// Returns None if the column name is not found.
     /// E.g. if table is CREATE TABLE t (a, b, c)
@@ -958,7 +1462,7 @@ impl BTreeTable {
                 sql.push_str(" UNIQUE");
             }
 
-            if column.primary_key {
+            if column.primary_key && self.primary_key_columns.len() <= 1 {
                 sql.push_str(" PRIMARY KEY");
             }
 
@@ -966,8 +1470,53 @@ impl BTreeTable {
                 sql.push_str(" DEFAULT ");
                 sql.push_str(&default.to_string());
             }
+
+            if let Some(generated) = &column.generated {
+                sql.push_str(" AS (");
+                sql.push_str(&generated.expr.to_string());
+                sql.push_str(if generated.stored {
+                    ") STORED"
+                } else {
+                    ") VIRTUAL"
+                });
+            }
+
+            for check in self
+                .checks
+                .iter()
+                .filter(|check| check.column.as_deref() == column.name.as_deref())
+            {
+                sql.push(' ');
+                check.push_sql(&mut sql);
+            }
+        }
+        if self.primary_key_columns.len() > 1 {
+            sql.push_str(", PRIMARY KEY (");
+            for (i, (column_name, order)) in self.primary_key_columns.iter().enumerate() {
+                if i > 0 {
+                    sql.push_str(", ");
+                }
+                if identifier_contains_special_chars(column_name) {
+                    sql.push('[');
+                    sql.push_str(column_name);
+                    sql.push(']');
+                } else {
+                    sql.push_str(column_name);
+                }
+                if *order == SortOrder::Desc {
+                    sql.push_str(" DESC");
+                }
+            }
+            sql.push(')');
+        }
+        for check in self.checks.iter().filter(|check| check.column.is_none()) {
+            sql.push_str(", ");
+            check.push_sql(&mut sql);
         }
         sql.push(')');
+        if !self.has_rowid {
+            sql.push_str(" WITHOUT ROWID");
+        }
         sql
     }
 
@@ -1009,6 +1558,12 @@ pub struct FromClauseSubquery {
     /// The start register for the result columns of the derived table;
     /// must be set before data is read from it.
     pub result_columns_start_reg: Option<usize>,
+    /// The SELECTs of a recursive CTE. When set, [FromClauseSubquery::plan] only describes
+    /// the result columns and the rows are produced by evaluating the CTE.
+    pub recursive: Option<Box<RecursiveCte>>,
+    /// The compound SELECT of the derived table. When set, [FromClauseSubquery::plan] only
+    /// describes the result columns and the rows are produced by the compound SELECT.
+    pub compound: Option<Box<Plan>>,
 }
 
 pub fn create_table(
@@ -1024,6 +1579,12 @@ pub fn create_table(
     let mut cols = vec![];
     let is_strict: bool;
     let mut unique_sets: Vec<UniqueSet> = vec![];
+    let mut foreign_keys: Vec<ForeignKey> = vec![];
+    let mut checks: Vec<CheckConstraint> = vec![];
+    // Table constraints are declared after the columns, so their foreign keys and CHECK
+    // constraints come last.
+    let mut table_foreign_keys: Vec<ForeignKey> = vec![];
+    let mut table_checks: Vec<CheckConstraint> = vec![];
     match body {
         CreateTableBody::ColumnsAndConstraints {
             columns,
@@ -1080,6 +1641,27 @@ pub fn create_table(
                         is_primary_key: false,
                     };
                     unique_sets.push(unique_set);
+                } else if let ast::TableConstraint::ForeignKey {
+                    columns,
+                    clause,
+                    deref_clause,
+                } = &c.constraint
+                {
+                    let child_columns = columns
+                        .iter()
+                        .map(|column| normalize_ident(column.col_name.as_str()))
+                        .collect();
+                    table_foreign_keys.push(ForeignKey::new(
+                        child_columns,
+                        clause,
+                        deref_clause.as_ref(),
+                    )?);
+                } else if let ast::TableConstraint::Check(expr) = &c.constraint {
+                    table_checks.push(CheckConstraint {
+                        name: c.name.clone(),
+                        column: None,
+                        expr: expr.clone(),
+                    });
                 }
             }
             for ast::ColumnDefinition {
@@ -1119,6 +1701,7 @@ pub fn create_table(
                 let mut order = SortOrder::Asc;
                 let mut unique = false;
                 let mut collation = None;
+                let mut generated = None;
                 for c_def in constraints {
                     match c_def.constraint {
                         ast::ColumnConstraint::PrimaryKey {
@@ -1156,7 +1739,38 @@ pub fn create_table(
                         ast::ColumnConstraint::Collate { ref collation_name } => {
                             collation = Some(CollationSeq::new(collation_name.as_str())?);
                         }
-                        _ => {}
+                        ast::ColumnConstraint::ForeignKey {
+                            ref clause,
+                            ref deref_clause,
+                        } => {
+                            foreign_keys.push(ForeignKey::new(
+                                vec![normalize_ident(&name)],
+                                clause,
+                                deref_clause.as_ref(),
+                            )?);
+                        }
+                        ast::ColumnConstraint::Check(ref expr) => {
+                            checks.push(CheckConstraint {
+                                name: c_def.name.clone(),
+                                column: Some(normalize_ident(&name)),
+                                expr: expr.clone(),
+                            });
+                        }
+                        ast::ColumnConstraint::Generated { ref expr, ref typ } => {
+                            let stored = match typ {
+                                None => false,
+                                Some(typ) if typ.as_str().eq_ignore_ascii_case("virtual") => false,
+                                Some(typ) if typ.as_str().eq_ignore_ascii_case("stored") => true,
+                                Some(_) => crate::bail_parse_error!(
+                                    "error in generated column \"{}\"",
+                                    normalize_ident(&name)
+                                ),
+                            };
+                            generated = Some(GeneratedColumn {
+                                expr: expr.clone(),
+                                stored,
+                            });
+                        }
                     }
                 }
 
@@ -1180,15 +1794,29 @@ pub fn create_table(
                     unique,
                     collation,
                     hidden: false,
+                    generated,
                 });
             }
             if options.contains(TableOptions::WITHOUT_ROWID) {
                 has_rowid = false;
             }
         }
-        CreateTableBody::AsSelect(_) => todo!(),
+        CreateTableBody::AsSelect(_) => {
+            // CREATE TABLE ... AS SELECT is stored in sqlite_schema with explicit column
+            // definitions, so it never reaches this point from a schema entry.
+            crate::bail_parse_error!("CREATE TABLE ... AS SELECT has no column definitions");
+        }
     };
 
+    if !has_rowid {
+        if primary_key_columns.is_empty() {
+            crate::bail_parse_error!("PRIMARY KEY missing on table {}", table_name);
+        }
+        if has_autoincrement {
+            crate::bail_parse_error!("AUTOINCREMENT not allowed on WITHOUT ROWID tables");
+        }
+    }
+
     // flip is_rowid_alias back to false if the table has multiple primary key columns
     // or if the table has no rowid
     if !has_rowid || primary_key_columns.len() > 1 {
@@ -1197,6 +1825,22 @@ pub fn create_table(
         }
     }
 
+    foreign_keys.append(&mut table_foreign_keys);
+    checks.append(&mut table_checks);
+    for fk in foreign_keys.iter() {
+        for child_column in fk.child_columns.iter() {
+            if !cols
+                .iter()
+                .any(|col| col.name.as_deref() == Some(child_column.as_str()))
+            {
+                crate::bail_parse_error!(
+                    "unknown column \"{}\" in foreign key definition",
+                    child_column
+                );
+            }
+        }
+    }
+
     if has_autoincrement {
         // only allow integers
         if primary_key_columns.len() != 1 {
@@ -1227,7 +1871,7 @@ pub fn create_table(
         }
     }
 
-    Ok(BTreeTable {
+    let table = BTreeTable {
         root_page,
         name: table_name,
         has_rowid,
@@ -1235,6 +1879,8 @@ pub fn create_table(
         has_autoincrement,
         columns: cols,
         is_strict,
+        foreign_keys,
+        checks,
         unique_sets: {
             // If there are any unique sets that have identical column names in the same order (even if they are PRIMARY KEY and UNIQUE and have different sort orders), remove the duplicates.
             // Examples:
@@ -1264,7 +1910,9 @@ pub fn create_table(
             }
             unique_sets
         },
-    })
+    };
+    table.check_generated_columns()?;
+    Ok(table)
 }
 
 pub fn _build_pseudo_table(columns: &[ResultColumn]) -> PseudoCursorType {
@@ -1298,12 +1946,21 @@ pub struct Column {
     pub unique: bool,
     pub collation: Option<CollationSeq>,
     pub hidden: bool,
+    pub generated: Option<GeneratedColumn>,
 }
 
 impl Column {
     pub fn affinity(&self) -> Affinity {
         affinity(&self.ty_str)
     }
+
+    /// Returns true if the column is a VIRTUAL generated column, which has no value in the
+    /// records of its table.
+    pub fn is_virtual_generated(&self) -> bool {
+        self.generated
+            .as_ref()
+            .is_some_and(|generated| !generated.stored)
+    }
 }
 
 // TODO: This might replace some of util::columns_from_create_table_body
@@ -1316,6 +1973,7 @@ impl From<&ColumnDefinition> for Column {
         let mut primary_key = false;
         let mut unique = false;
         let mut collation = None;
+        let mut generated = None;
 
         for ast::NamedColumnConstraint { constraint, .. } in &value.constraints {
             match constraint {
@@ -1325,6 +1983,14 @@ impl From<&ColumnDefinition> for Column {
                 ast::ColumnConstraint::Default(expr) => {
                     default.replace(expr.clone());
                 }
+                ast::ColumnConstraint::Generated { expr, typ } => {
+                    generated.replace(GeneratedColumn {
+                        expr: expr.clone(),
+                        stored: typ
+                            .as_ref()
+                            .is_some_and(|typ| typ.as_str().eq_ignore_ascii_case("stored")),
+                    });
+                }
                 ast::ColumnConstraint::Collate { collation_name } => {
                     collation.replace(
                         CollationSeq::new(collation_name.as_str())
@@ -1359,6 +2025,7 @@ impl From<&ColumnDefinition> for Column {
             unique,
             collation,
             hidden,
+            generated,
         }
     }
 }
@@ -1378,24 +2045,32 @@ impl From<&ColumnDefinition> for Column {
 ///
 /// Note that the order of the rules for determining column affinity is important. A column whose declared type is "CHARINT" will match both rules 1 and 2 but the first rule takes precedence and so the column affinity will be INTEGER.
 pub fn affinity(datatype: &str) -> Affinity {
-    // Note: callers of this function must ensure that the datatype is uppercase.
+    // The rules match the name of the type case-insensitively, without allocating, as the
+    // affinity of a column is computed for every value checked against it.
+    let contains = |needle: &str| {
+        datatype
+            .as_bytes()
+            .windows(needle.len())
+            .any(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+    };
+
     // Rule 1: INT -> INTEGER affinity
-    if datatype.contains("INT") {
+    if contains("INT") {
         return Affinity::Integer;
     }
 
     // Rule 2: CHAR/CLOB/TEXT -> TEXT affinity
-    if datatype.contains("CHAR") || datatype.contains("CLOB") || datatype.contains("TEXT") {
+    if contains("CHAR") || contains("CLOB") || contains("TEXT") {
         return Affinity::Text;
     }
 
     // Rule 3: BLOB or empty -> BLOB affinity (historically called NONE)
-    if datatype.contains("BLOB") || datatype.is_empty() || datatype.contains("ANY") {
+    if contains("BLOB") || datatype.is_empty() || contains("ANY") {
         return Affinity::Blob;
     }
 
     // Rule 4: REAL/FLOA/DOUB -> REAL affinity
-    if datatype.contains("REAL") || datatype.contains("FLOA") || datatype.contains("DOUB") {
+    if contains("REAL") || contains("FLOA") || contains("DOUB") {
         return Affinity::Real;
     }
 
@@ -1561,6 +2236,8 @@ pub fn sqlite_schema_table() -> BTreeTable {
         has_rowid: true,
         is_strict: false,
         has_autoincrement: false,
+        foreign_keys: vec![],
+        checks: vec![],
         primary_key_columns: vec![],
         columns: vec![
             Column {
@@ -1574,6 +2251,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("name".to_string()),
@@ -1586,6 +2264,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("tbl_name".to_string()),
@@ -1598,6 +2277,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("rootpage".to_string()),
@@ -1610,6 +2290,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("sql".to_string()),
@@ -1622,6 +2303,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
         ],
         unique_sets: vec![],
@@ -1640,9 +2322,13 @@ pub struct Index {
     /// Does the index have a rowid as the last column?
     /// This is the case for btree indexes (persistent or ephemeral) that
     /// have been created based on a table with a rowid.
-    /// For example, WITHOUT ROWID tables (not supported in Limbo yet),
+    /// For example, indexes on WITHOUT ROWID tables,
     /// and  SELECT DISTINCT ephemeral indexes will not have a rowid.
     pub has_rowid: bool,
+    /// For an index on a WITHOUT ROWID table, the PRIMARY KEY columns that are not columns of the
+    /// index. They are stored after the columns of the index to identify the row, like the rowid
+    /// is for the indexes of other tables.
+    pub primary_key_suffix: Vec<IndexColumn>,
     pub where_clause: Option<Box<Expr>>,
 }
 
@@ -1656,9 +2342,83 @@ pub struct IndexColumn {
     /// CREATE TABLE t (a,b,c)
     /// CREATE INDEX idx ON t(b)
     /// b.pos_in_table == 1
+    /// For a column on an expression this is `usize::MAX`, so it never matches a column.
     pub pos_in_table: usize,
     pub collation: Option<CollationSeq>,
     pub default: Option<Box<Expr>>,
+    /// For an index on an expression, e.g. CREATE INDEX idx ON t(lower(b)), the expression
+    /// of the column, which refers to the columns of the table by name.
+    pub expr: Option<Box<Expr>>,
+}
+
+impl IndexColumn {
+    /// A column of an index on an expression. A COLLATE at the top of the expression is the
+    /// collation of the column.
+    pub fn from_expr(expr: Box<Expr>, order: SortOrder) -> Result<Self> {
+        let collation = match expr.as_ref() {
+            Expr::Collate(_, collation) => Some(CollationSeq::new(collation.as_str())?),
+            _ => None,
+        };
+        Ok(IndexColumn {
+            name: expr.to_string(),
+            order,
+            pos_in_table: usize::MAX,
+            collation,
+            default: None,
+            expr: Some(expr),
+        })
+    }
+
+    /// Returns the expression of a column on an expression with its column references bound to
+    /// the table reference `table_id`, like the expressions of a query are. The result can be
+    /// compared with those expressions, or translated with the cursors of the table reference.
+    pub fn bind_expr(&self, table: &Table, table_id: ast::TableInternalId) -> Option<Expr> {
+        let mut expr = self.expr.as_ref()?.as_ref().clone();
+        let _ = walk_expr_mut(&mut expr, &mut |e: &mut Expr| -> Result<WalkControl> {
+            let name = match e {
+                Expr::Id(name) | Expr::Qualified(_, name) | Expr::DoublyQualified(_, _, name) => {
+                    normalize_ident(name.as_str())
+                }
+                _ => return Ok(WalkControl::Continue),
+            };
+            if let Some((column, col)) = table.get_column_by_name(&name) {
+                *e = Expr::Column {
+                    database: None,
+                    table: table_id,
+                    column,
+                    is_rowid_alias: col.is_rowid_alias,
+                };
+            }
+            Ok(WalkControl::Continue)
+        });
+        Some(expr)
+    }
+
+    /// Returns true if this is the table column `name`, or an expression that uses it.
+    pub fn uses_column(&self, name: &str) -> bool {
+        let name = normalize_ident(name);
+        let Some(expr) = &self.expr else {
+            return normalize_ident(&self.name) == name;
+        };
+        let mut used = false;
+        let _ = walk_expr(expr, &mut |e: &Expr| -> Result<WalkControl> {
+            if let Expr::Id(id) = e {
+                used |= normalize_ident(id.as_str()) == name;
+            }
+            Ok(WalkControl::Continue)
+        });
+        used
+    }
+
+    /// The affinity applied to the values of this column before they are compared with the
+    /// entries of a UNIQUE index. Expressions have no affinity.
+    pub fn affinity(&self, table_columns: &[Column]) -> Affinity {
+        if self.expr.is_some() {
+            Affinity::Blob
+        } else {
+            table_columns[self.pos_in_table].affinity()
+        }
+    }
 }
 
 impl Index {
@@ -1677,22 +2437,28 @@ impl Index {
                 let index_name = normalize_ident(idx_name.name.as_str());
                 let mut index_columns = Vec::with_capacity(columns.len());
                 for col in columns.into_iter() {
-                    let name = normalize_ident(&col.expr.to_string());
-                    let Some((pos_in_table, _)) = table.get_column(&name) else {
+                    let order = col.order.unwrap_or(SortOrder::Asc);
+                    let (Expr::Id(name) | Expr::Name(name)) = col.expr.as_ref() else {
+                        index_columns.push(IndexColumn::from_expr(col.expr, order)?);
+                        continue;
+                    };
+                    let name = normalize_ident(name.as_str());
+                    let Some((pos_in_table, column)) = table.get_column(&name) else {
                         return Err(crate::LimboError::InternalError(format!(
                             "Column {} is in index {} but not found in table {}",
                             name, index_name, table.name
                         )));
                     };
-                    let (_, column) = table.get_column(&name).unwrap();
                     index_columns.push(IndexColumn {
                         name,
-                        order: col.order.unwrap_or(SortOrder::Asc),
+                        order,
                         pos_in_table,
                         collation: column.collation,
                         default: column.default.clone(),
+                        expr: None,
                     });
                 }
+                let primary_key_suffix = Self::primary_key_suffix_for(table, &index_columns);
                 Ok(Index {
                     name: index_name,
                     table_name: normalize_ident(tbl_name.as_str()),
@@ -1701,6 +2467,7 @@ impl Index {
                     unique,
                     ephemeral: false,
                     has_rowid: table.has_rowid,
+                    primary_key_suffix,
                     where_clause,
                 })
             }
@@ -1733,6 +2500,7 @@ impl Index {
                 pos_in_table,
                 collation: column.collation,
                 default: column.default.clone(),
+                expr: None,
             });
         }
 
@@ -1746,10 +2514,96 @@ impl Index {
             unique: true,
             ephemeral: false,
             has_rowid: table.has_rowid,
+            primary_key_suffix: vec![],
             where_clause: None,
         })
     }
 
+    /// The PRIMARY KEY of a WITHOUT ROWID table, whose b-tree stores the rows of the table.
+    /// It is not one of the indexes of the table in the schema, but searches of the table use it.
+    pub fn without_rowid_primary_key(table: &BTreeTable) -> Index {
+        assert!(!table.has_rowid);
+        let columns = table
+            .primary_key_columns
+            .iter()
+            .map(|(col_name, order)| {
+                let (pos_in_table, column) = table
+                    .get_column(col_name)
+                    .expect("PRIMARY KEY column must exist");
+                IndexColumn {
+                    name: normalize_ident(col_name),
+                    order: *order,
+                    pos_in_table,
+                    collation: column.collation,
+                    default: column.default.clone(),
+                    expr: None,
+                }
+            })
+            .collect();
+        Index {
+            name: format!(
+                "{PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX}{}_primary_key",
+                table.name
+            ),
+            table_name: table.name.clone(),
+            root_page: table.root_page,
+            columns,
+            unique: true,
+            ephemeral: false,
+            has_rowid: false,
+            primary_key_suffix: vec![],
+            where_clause: None,
+        }
+    }
+
+    /// Returns true if this is the PRIMARY KEY of the given WITHOUT ROWID table, i.e. the b-tree of
+    /// the table itself.
+    pub fn is_without_rowid_primary_key_of(&self, table: &BTreeTable) -> bool {
+        !table.has_rowid && !self.ephemeral && self.root_page == table.root_page
+    }
+
+    /// The PRIMARY KEY columns of a WITHOUT ROWID table that are stored after the given columns
+    /// of one of its indexes.
+    pub fn primary_key_suffix_for(table: &BTreeTable, columns: &[IndexColumn]) -> Vec<IndexColumn> {
+        if table.has_rowid {
+            return vec![];
+        }
+        table
+            .primary_key_columns
+            .iter()
+            .filter_map(|(col_name, order)| {
+                let (pos_in_table, column) = table.get_column(col_name)?;
+                if columns.iter().any(|col| col.pos_in_table == pos_in_table) {
+                    return None;
+                }
+                Some(IndexColumn {
+                    name: normalize_ident(col_name),
+                    order: *order,
+                    pos_in_table,
+                    collation: column.collation,
+                    default: column.default.clone(),
+                    expr: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the position in the index records of each PRIMARY KEY column of a WITHOUT ROWID
+    /// table, in PRIMARY KEY order.
+    pub fn primary_key_positions(&self, table: &BTreeTable) -> Vec<usize> {
+        table
+            .primary_key_columns
+            .iter()
+            .map(|(col_name, _)| {
+                let (pos_in_table, _) = table
+                    .get_column(col_name)
+                    .expect("PRIMARY KEY column must exist");
+                self.column_table_pos_to_index_pos(pos_in_table)
+                    .expect("index must contain the PRIMARY KEY columns")
+            })
+            .collect()
+    }
+
     pub fn automatic_from_unique(
         table: &BTreeTable,
         auto_index: (String, usize), // name, root_page
@@ -1771,10 +2625,12 @@ impl Index {
                     pos_in_table: *pos_in_table,
                     collation: col.collation,
                     default: col.default.clone(),
+                    expr: None,
                 })
             })
             .collect::<Vec<_>>();
 
+        let primary_key_suffix = Self::primary_key_suffix_for(table, &unique_cols);
         Ok(Index {
             name: normalize_ident(index_name.as_str()),
             table_name: table.name.clone(),
@@ -1783,6 +2639,7 @@ impl Index {
             unique: true,
             ephemeral: false,
             has_rowid: table.has_rowid,
+            primary_key_suffix,
             where_clause: None,
         })
     }
@@ -1796,6 +2653,7 @@ impl Index {
     pub fn column_table_pos_to_index_pos(&self, table_pos: usize) -> Option<usize> {
         self.columns
             .iter()
+            .chain(self.primary_key_suffix.iter())
             .position(|c| c.pos_in_table == table_pos)
     }
 
@@ -2186,6 +3044,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    pub fn test_without_rowid_to_sql() -> Result<()> {
+        let tests = [
+            (
+                "CREATE TABLE t (a INT PRIMARY KEY, b) WITHOUT ROWID",
+                "CREATE TABLE t (a INT PRIMARY KEY, b) WITHOUT ROWID",
+            ),
+            (
+                "CREATE TABLE t (a, b, c, PRIMARY KEY (b DESC, a)) WITHOUT ROWID",
+                "CREATE TABLE t (a, b, c, PRIMARY KEY (b DESC, a)) WITHOUT ROWID",
+            ),
+        ];
+
+        for (sql, expected_sql) in tests {
+            let actual = BTreeTable::from_sql(sql, 0)?.to_sql();
+            assert_eq!(expected_sql, actual);
+        }
+
+        Ok(())
+    }
+
     #[test]
     #[should_panic]
     fn test_automatic_index_single_column() {
@@ -2216,6 +3095,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_index_from_sql_expression_column() -> Result<()> {
+        let table = BTreeTable::from_sql("CREATE TABLE t1 (a INTEGER, email TEXT);", 0)?;
+        let index = Index::from_sql(
+            "CREATE INDEX t1_email ON t1 (a, lower(email) COLLATE NOCASE DESC);",
+            3,
+            &table,
+        )?;
+
+        assert_eq!(index.columns.len(), 2);
+        assert_eq!(index.columns[0].pos_in_table, 0);
+        assert!(index.columns[0].expr.is_none());
+        assert_eq!(index.columns[1].pos_in_table, usize::MAX);
+        assert!(index.columns[1].expr.is_some());
+        assert!(index.columns[1].uses_column("email"));
+        assert!(!index.columns[1].uses_column("a"));
+        assert_eq!(index.columns[1].collation, Some(CollationSeq::NoCase));
+        assert!(matches!(index.columns[1].order, SortOrder::Desc));
+        Ok(())
+    }
+
     #[test]
     #[should_panic]
     fn test_automatic_index_no_primary_key() {
@@ -2234,6 +3134,8 @@ mod tests {
             has_rowid: true,
             is_strict: false,
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
             primary_key_columns: vec![("nonexistent".to_string(), SortOrder::Asc)],
             columns: vec![Column {
                 name: Some("a".to_string()),
@@ -2246,6 +3148,7 @@ mod tests {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             }],
             unique_sets: vec![],
         };
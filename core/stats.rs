@@ -0,0 +1,331 @@
+//! Table and index statistics gathered by `ANALYZE`.
+//!
+//! `ANALYZE` stores one row per index (or per table without indexes) in `sqlite_stat1`, and a
+//! few sampled entries of each index in `sqlite_stat4`, in the same format as SQLite. The rows are
+//! loaded into the [crate::schema::Schema] when the database is opened and after each `ANALYZE`,
+//! and the query planner uses them to estimate the number of rows that a search will return.
+
+use std::collections::HashMap;
+
+use crate::types::{ImmutableRecord, Value};
+
+pub const STAT1_TABLE_NAME: &str = "sqlite_stat1";
+pub const STAT4_TABLE_NAME: &str = "sqlite_stat4";
+
+/// The maximum number of entries of an index that are sampled into `sqlite_stat4`.
+pub const STAT4_SAMPLES: u64 = 24;
+
+/// The statistics of a table and of its indexes.
+#[derive(Debug, Clone, Default)]
+pub struct TableStats {
+    /// The number of rows of the table, if it was analyzed.
+    pub row_count: Option<u64>,
+    /// The statistics of the indexes of the table, by index name.
+    pub indexes: HashMap<String, IndexStats>,
+}
+
+/// The statistics of an index.
+#[derive(Debug, Clone, Default)]
+pub struct IndexStats {
+    /// The number of entries of the index.
+    pub row_count: u64,
+    /// The average number of entries that have the same values in the first 1, 2, ... columns
+    /// of the index.
+    pub avg_eq: Vec<u64>,
+    /// Entries of the index sampled at regular intervals, in index order.
+    pub samples: Vec<IndexSample>,
+}
+
+/// An entry of an index sampled into `sqlite_stat4`.
+#[derive(Debug, Clone)]
+pub struct IndexSample {
+    /// The number of entries equal to the sample in the first 1, 2, ... columns of the index.
+    pub eq: Vec<u64>,
+    /// The number of entries less than the sample in the first 1, 2, ... columns of the index.
+    pub lt: Vec<u64>,
+    /// The number of distinct values less than the sample in the first 1, 2, ... columns.
+    pub dlt: Vec<u64>,
+    /// The values of the columns of the sampled entry.
+    pub key: Vec<Value>,
+}
+
+/// A row of `sqlite_stat1`.
+#[derive(Debug, Clone)]
+pub struct Stat1Row {
+    pub tbl: String,
+    /// The name of the index, or NULL for the row that holds the number of rows of a table.
+    pub idx: Option<String>,
+    pub stat: String,
+}
+
+impl Stat1Row {
+    /// Reads a row of `sqlite_stat1` from its values, or returns None if it is malformed.
+    pub fn from_values(values: &[Value]) -> Option<Self> {
+        Some(Self {
+            tbl: values.first()?.to_text()?.to_string(),
+            idx: values.get(1)?.to_text().map(str::to_string),
+            stat: values.get(2)?.to_text()?.to_string(),
+        })
+    }
+}
+
+/// A row of `sqlite_stat4`.
+#[derive(Debug, Clone)]
+pub struct Stat4Row {
+    pub tbl: String,
+    pub idx: String,
+    pub neq: String,
+    pub nlt: String,
+    pub ndlt: String,
+    /// The sampled entry, as an index record.
+    pub sample: Vec<u8>,
+}
+
+impl Stat4Row {
+    /// Reads a row of `sqlite_stat4` from its values, or returns None if it is malformed.
+    pub fn from_values(values: &[Value]) -> Option<Self> {
+        Some(Self {
+            tbl: values.first()?.to_text()?.to_string(),
+            idx: values.get(1)?.to_text()?.to_string(),
+            neq: values.get(2)?.to_text()?.to_string(),
+            nlt: values.get(3)?.to_text()?.to_string(),
+            ndlt: values.get(4)?.to_text()?.to_string(),
+            sample: values.get(5)?.to_blob()?.to_vec(),
+        })
+    }
+}
+
+/// Parses a list of integers in the format of `sqlite_stat1.stat` and of the `sqlite_stat4`
+/// counters. Parsing stops at the first word that is not an integer, such as the `unordered` and
+/// `sz=N` options that SQLite may append to a `sqlite_stat1` row.
+pub fn parse_stat_numbers(s: &str) -> Vec<u64> {
+    s.split_ascii_whitespace()
+        .map_while(|word| word.parse().ok())
+        .collect()
+}
+
+fn format_stat_numbers(numbers: &[u64]) -> String {
+    numbers
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl IndexSample {
+    pub fn from_row(row: &Stat4Row) -> Self {
+        let record = ImmutableRecord::from_bin_record(row.sample.clone());
+        Self {
+            eq: parse_stat_numbers(&row.neq),
+            lt: parse_stat_numbers(&row.nlt),
+            dlt: parse_stat_numbers(&row.ndlt),
+            key: record.get_values().iter().map(|v| v.to_owned()).collect(),
+        }
+    }
+}
+
+/// An entry sampled by a [StatAccumulator], not yet written to `sqlite_stat4`.
+#[derive(Debug)]
+struct PendingSample {
+    eq: Vec<u64>,
+    lt: Vec<u64>,
+    dlt: Vec<u64>,
+    record: Vec<u8>,
+}
+
+/// Computes the statistics of an index while `ANALYZE` visits its entries in order.
+///
+/// For each entry, the caller tells which is the leftmost key column that differs from the
+/// previous entry; the accumulator counts runs of equal prefixes from that, in the same way as
+/// SQLite's `stat_push()`.
+#[derive(Debug)]
+pub struct StatAccumulator {
+    /// The number of columns reported in `sqlite_stat1`.
+    key_columns: usize,
+    /// The number of columns of the index entries, including the rowid or the PRIMARY KEY
+    /// columns that follow the key columns.
+    columns: usize,
+    /// The number of entries of the index.
+    row_count: u64,
+    /// Whether entries are sampled for `sqlite_stat4`.
+    sample: bool,
+    /// The number of entries visited so far.
+    rows: u64,
+    /// The number of entries equal to the current one in the first 1, 2, ... columns.
+    eq: Vec<u64>,
+    /// The number of entries less than the current one in the first 1, 2, ... columns.
+    lt: Vec<u64>,
+    /// The number of distinct values less than the current one in the first 1, 2, ... columns.
+    dlt: Vec<u64>,
+    samples: Vec<PendingSample>,
+    /// The samples whose `eq` counters are still being counted, with the number of leading
+    /// columns that are not final yet.
+    open_samples: Vec<(usize, usize)>,
+    /// The next sample returned by [StatAccumulator::next_sample].
+    next_sample: usize,
+}
+
+impl StatAccumulator {
+    pub fn new(key_columns: usize, columns: usize, row_count: u64, sample: bool) -> Self {
+        Self {
+            key_columns,
+            columns,
+            row_count,
+            sample,
+            rows: 0,
+            eq: vec![0; columns],
+            lt: vec![0; columns],
+            dlt: vec![0; columns],
+            samples: Vec::new(),
+            open_samples: Vec::new(),
+            next_sample: 0,
+        }
+    }
+
+    pub fn wants_samples(&self) -> bool {
+        self.sample
+    }
+
+    /// Adds an entry that differs from the previous one starting at column `changed`. The
+    /// record of the entry is given if samples are taken.
+    pub fn push(&mut self, changed: usize, record: Option<Vec<u8>>) {
+        let changed = changed.min(self.columns);
+        if self.rows == 0 {
+            self.eq.fill(1);
+        } else {
+            // The runs of entries equal to the open samples end at the changed column.
+            for (sample, pending) in self.open_samples.iter_mut() {
+                for i in changed..*pending {
+                    self.samples[*sample].eq[i] = self.eq[i];
+                }
+                *pending = (*pending).min(changed);
+            }
+            self.open_samples.retain(|(_, pending)| *pending > 0);
+            for i in 0..changed {
+                self.eq[i] += 1;
+            }
+            for i in changed..self.columns {
+                self.dlt[i] += 1;
+                self.lt[i] += self.eq[i];
+                self.eq[i] = 1;
+            }
+        }
+        self.rows += 1;
+
+        let Some(record) = record.filter(|_| self.sample) else {
+            return;
+        };
+        // Every entry differs from the previous one in its last column, so the entries less
+        // than the current one in all columns are all the previous entries.
+        let n_lt = self.lt.last().copied().unwrap_or(self.rows - 1);
+        let period = (self.row_count / STAT4_SAMPLES).max(1);
+        if n_lt / period != (n_lt + 1) / period && (self.samples.len() as u64) < STAT4_SAMPLES {
+            self.open_samples.push((self.samples.len(), self.columns));
+            self.samples.push(PendingSample {
+                eq: vec![0; self.columns],
+                lt: self.lt.clone(),
+                dlt: self.dlt.clone(),
+                record,
+            });
+        }
+    }
+
+    /// Returns the `sqlite_stat1.stat` text of the index: the number of entries, followed by the
+    /// average number of entries per distinct value of the first 1, 2, ... key columns.
+    pub fn stat1(&mut self) -> String {
+        self.finish();
+        let mut stat = vec![self.row_count.max(self.rows)];
+        for i in 0..self.key_columns {
+            let distinct = self.dlt.get(i).copied().unwrap_or(0) + 1;
+            let mut avg = self.rows.div_ceil(distinct);
+            // Like SQLite, round down averages that are barely above one.
+            if avg == 2 && self.rows * 10 <= distinct * 11 {
+                avg = 1;
+            }
+            stat.push(avg);
+        }
+        format_stat_numbers(&stat)
+    }
+
+    /// Returns the next sample as the `neq`, `nlt`, `ndlt` and `sample` columns of
+    /// `sqlite_stat4`.
+    pub fn next_sample(&mut self) -> Option<(String, String, String, Vec<u8>)> {
+        self.finish();
+        let sample = self.samples.get_mut(self.next_sample)?;
+        self.next_sample += 1;
+        Some((
+            format_stat_numbers(&sample.eq),
+            format_stat_numbers(&sample.lt),
+            format_stat_numbers(&sample.dlt),
+            std::mem::take(&mut sample.record),
+        ))
+    }
+
+    /// Ends the runs of the samples that are still open at the last entry.
+    fn finish(&mut self) {
+        for (sample, pending) in self.open_samples.drain(..) {
+            self.samples[sample].eq[..pending].copy_from_slice(&self.eq[..pending]);
+        }
+    }
+}
+
+/// Builds the statistics of the tables of a database from the rows of its `sqlite_stat1` and
+/// `sqlite_stat4` tables. `index_name` looks up the index of a row by table and index name, and
+/// returns the name of the index in the schema and whether it is a partial index, whose number
+/// of entries is not the number of rows of the table. Rows of unknown indexes are skipped.
+pub fn build_table_stats(
+    stat1: Vec<Stat1Row>,
+    stat4: Vec<Stat4Row>,
+    index_name: impl Fn(&str, &str) -> Option<(String, bool)>,
+) -> HashMap<String, TableStats> {
+    let mut tables: HashMap<String, TableStats> = HashMap::new();
+    for row in stat1 {
+        let numbers = parse_stat_numbers(&row.stat);
+        let Some(&row_count) = numbers.first() else {
+            continue;
+        };
+        let tbl = crate::util::normalize_ident(&row.tbl);
+        match row.idx {
+            None => {
+                tables.entry(tbl).or_default().row_count = Some(row_count);
+            }
+            Some(idx) => {
+                let Some((idx, partial)) = index_name(&tbl, &idx) else {
+                    continue;
+                };
+                let table = tables.entry(tbl).or_default();
+                if !partial {
+                    table.row_count = Some(row_count);
+                }
+                table.indexes.insert(
+                    idx,
+                    IndexStats {
+                        row_count,
+                        avg_eq: numbers[1..].to_vec(),
+                        samples: Vec::new(),
+                    },
+                );
+            }
+        }
+    }
+    for row in stat4 {
+        let tbl = crate::util::normalize_ident(&row.tbl);
+        let Some((idx, _)) = index_name(&tbl, &row.idx) else {
+            continue;
+        };
+        if let Some(index) = tables
+            .get_mut(&tbl)
+            .and_then(|table| table.indexes.get_mut(&idx))
+        {
+            index.samples.push(IndexSample::from_row(&row));
+        }
+    }
+    for table in tables.values_mut() {
+        for index in table.indexes.values_mut() {
+            index
+                .samples
+                .sort_by_key(|sample| sample.lt.last().copied());
+        }
+    }
+    tables
+}
@@ -2,7 +2,7 @@ use tracing::{instrument, Level};
 
 use crate::{
     io_yield_many, io_yield_one,
-    schema::Index,
+    schema::{BTreeTable, Index},
     storage::{
         pager::{BtreePageAllocMode, Pager},
         sqlite3_ondisk::{
@@ -686,10 +686,31 @@ impl BTreeCursor {
         cursor
     }
 
+    /// Create a cursor on the b-tree of a table.
+    /// The rows of a WITHOUT ROWID table are stored in an index b-tree keyed on its PRIMARY KEY.
+    pub fn new_for_table(
+        mv_cursor: Option<Arc<RwLock<MvCursor>>>,
+        pager: Arc<Pager>,
+        root_page: usize,
+        table: &BTreeTable,
+    ) -> Self {
+        if table.has_rowid {
+            Self::new_table(mv_cursor, pager, root_page, table.columns.len())
+        } else {
+            Self::new_index(
+                mv_cursor,
+                pager,
+                root_page,
+                &Index::without_rowid_primary_key(table),
+                table.columns.len(),
+            )
+        }
+    }
+
     pub fn has_rowid(&self) -> bool {
         match &self.index_info {
             Some(index_key_info) => index_key_info.has_rowid,
-            None => true, // currently we don't support WITHOUT ROWID tables
+            None => true, // table b-trees are always keyed on the rowid
         }
     }
 
@@ -1595,12 +1616,14 @@ impl BTreeCursor {
     ) -> Result<IOResult<()>> {
         let iter_dir = cmp.iteration_direction();
 
-        let key_values = index_key.get_values();
+        let mut key_values = index_key.get_values();
         let record_comparer = {
             let index_info = self
                 .index_info
                 .as_ref()
                 .expect("indexbtree_move_to without index_info");
+            // The rows of a WITHOUT ROWID table are keyed only on their PRIMARY KEY columns.
+            key_values.truncate(index_info.key_info.len());
             find_compare(&key_values, index_info)
         };
         tracing::debug!("Using record comparison strategy: {:?}", record_comparer);
@@ -1983,12 +2006,14 @@ impl BTreeCursor {
         key: &ImmutableRecord,
         seek_op: SeekOp,
     ) -> Result<IOResult<SeekResult>> {
-        let key_values = key.get_values();
+        let mut key_values = key.get_values();
         let record_comparer = {
             let index_info = self
                 .index_info
                 .as_ref()
                 .expect("indexbtree_seek without index_info");
+            // The rows of a WITHOUT ROWID table are keyed only on their PRIMARY KEY columns.
+            key_values.truncate(index_info.key_info.len());
             find_compare(&key_values, index_info)
         };
 
@@ -2303,13 +2328,15 @@ impl BTreeCursor {
                             }
                             BTreeCell::IndexLeafCell(..) | BTreeCell::IndexInteriorCell(..) => {
                                 return_if_io!(self.record());
+                                let key_info = &self.index_info.as_ref().unwrap().key_info;
+                                let key_len = record_values.len().min(key_info.len());
                                 let cmp = compare_immutable(
-                                    record_values.as_slice(),
-                                    self.get_immutable_record()
+                                    &record_values[..key_len],
+                                    &self.get_immutable_record()
                                         .as_ref()
                                         .unwrap()
-                                        .get_values().as_slice(),
-                                        &self.index_info.as_ref().unwrap().key_info,
+                                        .get_values()[..key_len],
+                                        key_info,
                                 );
                                 if cmp == Ordering::Equal {
                                     tracing::debug!("IndexLeafCell: found exact match with cell_idx={cell_idx}, overwriting");
@@ -2600,6 +2627,7 @@ impl BTreeCursor {
             .stack
             .get_page_at_level(self.stack.current() - 1)
             .expect("parent page should be on the stack");
+        self.pager.add_dirty(parent);
         let parent_contents = parent.get_contents();
         let rightmost_pointer = parent_contents
             .rightmost_pointer()
@@ -2642,7 +2670,6 @@ impl BTreeCursor {
             usable_space,
         )?;
         parent_contents.write_rightmost_ptr(new_rightmost_leaf.get().id as u32);
-        self.pager.add_dirty(parent);
         self.pager.add_dirty(&new_rightmost_leaf);
 
         // Continue balance from the parent page (inserting the new divider cell may have overflowed the parent)
@@ -2907,6 +2934,9 @@ impl BTreeCursor {
                     {
                         let page = page.as_ref().unwrap();
                         turso_assert!(page.is_loaded(), "page should be loaded");
+                        // Siblings were marked dirty before their reads completed, mark them again so
+                        // that an open savepoint can record their original contents.
+                        self.pager.add_dirty(page);
 
                         #[cfg(debug_assertions)]
                         let page_type_of_siblings = balance_info.pages_to_balance[0]
@@ -8573,6 +8603,7 @@ mod tests {
                         collation: None,
                         pos_in_table: i,
                         default: None,
+                        expr: None,
                     })
                     .collect(),
                 table_name: "test".to_string(),
@@ -8580,6 +8611,7 @@ mod tests {
                 unique: false,
                 ephemeral: false,
                 has_rowid: false,
+                primary_key_suffix: vec![],
             };
             let num_columns = index_def.columns.len();
             let mut cursor = BTreeCursor::new_index(
@@ -8734,12 +8766,14 @@ mod tests {
                     collation: None,
                     pos_in_table: 0,
                     default: None,
+                    expr: None,
                 }],
                 table_name: "test".to_string(),
                 root_page: index_root_page,
                 unique: false,
                 ephemeral: false,
                 has_rowid: false,
+                primary_key_suffix: vec![],
             };
             let mut cursor =
                 BTreeCursor::new_index(None, pager.clone(), index_root_page, &index_def, 1);
@@ -22,6 +22,7 @@ pub(crate) mod pager;
 pub(super) mod slot_bitmap;
 pub(crate) mod sqlite3_ondisk;
 mod state_machines;
+pub(crate) mod super_journal;
 #[allow(clippy::arc_with_non_send_sync)]
 pub(crate) mod wal;
 
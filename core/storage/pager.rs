@@ -16,7 +16,7 @@ use crate::{
 };
 use parking_lot::RwLock;
 use std::cell::{Cell, RefCell, UnsafeCell};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::hash;
 use std::rc::Rc;
 use std::sync::atomic::{
@@ -370,6 +370,8 @@ struct CommitInfo {
     result: RefCell<Option<PagerCommitResult>>,
     state: Cell<CommitState>,
     time: Cell<crate::io::clock::Instant>,
+    /// Leave the appended frames invisible to other connections until [Pager::publish_commit].
+    prepare_only: Cell<bool>,
 }
 
 /// Track the state of the auto-vacuum mode.
@@ -529,6 +531,22 @@ pub struct Pager {
     #[cfg(not(feature = "omit_autovacuum"))]
     vacuum_state: RwLock<VacuumState>,
     pub(crate) io_ctx: RwLock<IOContext>,
+    /// Statement journals of the open savepoints, innermost last.
+    savepoints: RwLock<Vec<PagerSavepoint>>,
+    /// The capacity of the page cache before [Pager::overwrite_with] grew it to hold every page
+    /// of the database, restored when the transaction ends.
+    overwrite_cache_capacity: RwLock<Option<usize>>,
+}
+
+/// The original contents of the pages first modified while a savepoint was the innermost one.
+#[derive(Debug, Default)]
+struct PagerSavepoint {
+    pages: HashMap<usize, Vec<u8>>,
+    /// Checksums of the pages that were already dirty when the savepoint was opened. Rolling back
+    /// to the savepoint asserts that those without an image were not modified, i.e. that every
+    /// page write went through [Pager::add_dirty] first.
+    #[cfg(debug_assertions)]
+    dirty_page_checksums: HashMap<usize, u64>,
 }
 
 #[cfg(not(feature = "omit_autovacuum"))]
@@ -618,6 +636,7 @@ impl Pager {
                 completions: RefCell::new(Vec::new()),
                 state: CommitState::PrepareWal.into(),
                 time: now.into(),
+                prepare_only: false.into(),
             },
             syncing: Arc::new(AtomicBool::new(false)),
             checkpoint_state: RwLock::new(CheckpointState::Checkpoint),
@@ -639,6 +658,8 @@ impl Pager {
                 btree_create_vacuum_full_state: BtreeCreateVacuumFullState::Start,
             }),
             io_ctx: RwLock::new(IOContext::default()),
+            savepoints: RwLock::new(Vec::new()),
+            overwrite_cache_capacity: RwLock::new(None),
         })
     }
 
@@ -840,6 +861,12 @@ impl Pager {
                         }
                     };
 
+                    turso_assert!(
+                        ptrmap_page.get().id == ptrmap_pg_no,
+                        "ptrmap page has unexpected number"
+                    );
+                    self.add_dirty(&ptrmap_page);
+
                     let full_buffer_slice = page_content.buffer.as_mut_slice();
 
                     if offset_in_ptrmap_page + PTRMAP_ENTRY_SIZE > full_buffer_slice.len() {
@@ -861,11 +888,6 @@ impl Pager {
                             [offset_in_ptrmap_page..offset_in_ptrmap_page + PTRMAP_ENTRY_SIZE],
                     )?;
 
-                    turso_assert!(
-                        ptrmap_page.get().id == ptrmap_pg_no,
-                        "ptrmap page has unexpected number"
-                    );
-                    self.add_dirty(&ptrmap_page);
                     self.vacuum_state.write().ptrmap_put_state = PtrMapPutState::Start;
                     break Ok(IOResult::Done(()));
                 }
@@ -1151,6 +1173,8 @@ impl Pager {
         ));
         wal.borrow().end_write_tx();
         wal.borrow().end_read_tx();
+        self.clear_savepoints();
+        self.restore_page_cache_capacity();
 
         if schema_did_change {
             let schema = connection.schema.read().clone();
@@ -1159,6 +1183,96 @@ impl Pager {
         Ok(IOResult::Done(commit_status))
     }
 
+    /// Commit or roll back the transaction of the temp or an attached database. Unlike
+    /// [Pager::end_tx], this leaves the transaction state and the schema of the connection alone.
+    pub fn end_aux_tx(
+        &self,
+        rollback: bool,
+        is_write: bool,
+        wal_auto_checkpoint_disabled: bool,
+        sync_mode: crate::SyncMode,
+        data_sync_retry: bool,
+    ) -> Result<IOResult<()>> {
+        let Some(wal) = self.wal.as_ref() else {
+            return Ok(IOResult::Done(()));
+        };
+        if !is_write {
+            wal.borrow().end_read_tx();
+            return Ok(IOResult::Done(()));
+        }
+        if rollback {
+            wal.borrow().end_write_tx();
+            wal.borrow().end_read_tx();
+            self.rollback_pages(true)?;
+            return Ok(IOResult::Done(()));
+        }
+        return_if_io!(self.commit_dirty_pages(
+            wal_auto_checkpoint_disabled,
+            sync_mode,
+            data_sync_retry
+        ));
+        wal.borrow().end_write_tx();
+        wal.borrow().end_read_tx();
+        self.clear_savepoints();
+        Ok(IOResult::Done(()))
+    }
+
+    /// Write and sync the frames of the write transaction without making them visible to other
+    /// connections, as the first phase of a commit that spans several databases. The commit is
+    /// finished by [Pager::publish_commit], or discarded by rolling the transaction back.
+    pub fn prepare_commit(
+        &self,
+        sync_mode: crate::SyncMode,
+        data_sync_retry: bool,
+    ) -> Result<IOResult<()>> {
+        if self.wal.is_none() {
+            return Ok(IOResult::Done(()));
+        }
+        self.commit_info.prepare_only.set(true);
+        let result = self.commit_dirty_pages(true, sync_mode, data_sync_retry);
+        if !matches!(result, Ok(IOResult::IO(_))) {
+            self.commit_info.prepare_only.set(false);
+        }
+        return_if_io!(result);
+        Ok(IOResult::Done(()))
+    }
+
+    /// Make the frames written by [Pager::prepare_commit] visible to other connections.
+    pub fn publish_commit(&self) -> Result<()> {
+        let Some(wal) = self.wal.as_ref() else {
+            return Ok(());
+        };
+        wal.borrow_mut().finish_append_frames_commit()
+    }
+
+    /// The size of the WAL file up to its last committed frame.
+    pub fn committed_wal_size(&self) -> u64 {
+        let Some(wal) = self.wal.as_ref() else {
+            return 0;
+        };
+        let max_frame = wal.borrow().get_max_frame_in_wal();
+        if max_frame == 0 {
+            return 0;
+        }
+        let frame_size = self.get_page_size_unchecked().get() as u64
+            + sqlite3_ondisk::WAL_FRAME_HEADER_SIZE as u64;
+        sqlite3_ondisk::WAL_HEADER_SIZE as u64 + max_frame * frame_size
+    }
+
+    /// Drop the frames that [Pager::prepare_commit] wrote to the WAL file, so that they are not
+    /// recovered after a crash.
+    pub fn truncate_uncommitted_frames(&self) -> Result<()> {
+        let Some(wal) = self.wal.as_ref() else {
+            return Ok(());
+        };
+        let Some(c) = wal.borrow_mut().truncate_uncommitted_frames()? else {
+            return Ok(());
+        };
+        self.io.wait_for_completion(c)?;
+        let c = wal.borrow_mut().sync()?;
+        self.io.wait_for_completion(c)
+    }
+
     #[instrument(skip_all, level = Level::DEBUG)]
     pub fn end_read_tx(&self) -> Result<()> {
         let Some(wal) = self.wal.as_ref() else {
@@ -1306,12 +1420,188 @@ impl Pager {
     }
 
     pub fn add_dirty(&self, page: &Page) {
+        self.record_savepoint_image(page);
         // TODO: check duplicates?
         let mut dirty_pages = self.dirty_pages.write();
         dirty_pages.insert(page.get().id);
         page.set_dirty();
     }
 
+    /// Record the current contents of `page` in the innermost open savepoint, unless the page was
+    /// already recorded there. Must be called before the page is modified, even if it is already
+    /// dirty: pages stay dirty until the transaction ends, as dirty pages are neither evicted nor
+    /// checkpointed, so this is the only place their images are taken.
+    fn record_savepoint_image(&self, page: &Page) {
+        let mut savepoints = self.savepoints.write();
+        let Some(savepoint) = savepoints.last_mut() else {
+            return;
+        };
+        // Pages that are still being read will be marked dirty again once they are loaded.
+        if !page.is_loaded() {
+            return;
+        }
+        savepoint
+            .pages
+            .entry(page.get().id)
+            .or_insert_with(|| page.get_contents().as_ptr().to_vec());
+    }
+
+    /// Open a new savepoint. Pages dirtied from now on have their original contents recorded
+    /// so that they can be restored by [Pager::rollback_to_savepoint].
+    pub fn open_savepoint(&self) {
+        let savepoint = self.new_savepoint();
+        self.savepoints.write().push(savepoint);
+    }
+
+    fn new_savepoint(&self) -> PagerSavepoint {
+        PagerSavepoint {
+            #[cfg(debug_assertions)]
+            dirty_page_checksums: self.dirty_page_checksums(),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn dirty_page_checksums(&self) -> HashMap<usize, u64> {
+        if self.dirty_pages.read().is_empty() {
+            return HashMap::new();
+        }
+        let usable_space = self.usable_space();
+        let mut cache = self.page_cache.write();
+        self.dirty_pages
+            .read()
+            .iter()
+            .filter_map(|page_id| {
+                let page = cache.peek(&PageCacheKey::new(*page_id), false)?;
+                page.is_loaded()
+                    .then(|| (*page_id, page_checksum(&page, usable_space)))
+            })
+            .collect()
+    }
+
+    /// Release the savepoint at `idx` and all savepoints opened after it. The page images they
+    /// recorded are merged into the enclosing savepoint, if any.
+    pub fn release_savepoint(&self, idx: usize) {
+        let mut savepoints = self.savepoints.write();
+        turso_assert!(idx < savepoints.len(), "savepoint {idx} is not open");
+        let released = savepoints.split_off(idx);
+        let Some(parent) = savepoints.last_mut() else {
+            return;
+        };
+        for savepoint in released {
+            for (page_id, image) in savepoint.pages {
+                // Keep the oldest image of each page.
+                parent.pages.entry(page_id).or_insert(image);
+            }
+        }
+    }
+
+    /// Undo all page modifications made since the savepoint at `idx` was opened. Savepoints opened
+    /// after it are released, the savepoint itself stays open.
+    ///
+    /// The restored pages stay dirty, so the WAL frames appended for them on commit supersede any
+    /// frames written for their newer contents.
+    pub fn rollback_to_savepoint(&self, idx: usize) -> Result<()> {
+        // A failed statement may have left a page operation half done.
+        self.reset_internal_states();
+        let mut savepoints = self.savepoints.write();
+        turso_assert!(idx < savepoints.len(), "savepoint {idx} is not open");
+        #[cfg(debug_assertions)]
+        let dirty_page_checksums = std::mem::take(&mut savepoints[idx].dirty_page_checksums);
+        // Walk from the innermost savepoint outwards so that older images win.
+        let mut images: HashMap<usize, Vec<u8>> = HashMap::new();
+        for savepoint in savepoints.drain(idx..).rev() {
+            images.extend(savepoint.pages);
+        }
+        savepoints.push(PagerSavepoint::default());
+        drop(savepoints);
+
+        // Pages allocated after the savepoint lie past the restored end of the database.
+        let db_size = images
+            .get(&DatabaseHeader::PAGE_ID)
+            .map(|image| {
+                bytemuck::from_bytes::<DatabaseHeader>(&image[0..DatabaseHeader::SIZE])
+                    .database_size
+                    .get() as usize
+            })
+            .unwrap_or(usize::MAX);
+
+        #[cfg(debug_assertions)]
+        if !dirty_page_checksums.is_empty() {
+            let usable_space = self.usable_space();
+            let mut cache = self.page_cache.write();
+            for (page_id, checksum) in dirty_page_checksums {
+                if images.contains_key(&page_id) {
+                    continue;
+                }
+                if let Some(page) = cache
+                    .peek(&PageCacheKey::new(page_id), false)
+                    .filter(|page| page.is_loaded())
+                {
+                    turso_assert!(
+                        page_checksum(&page, usable_space) == checksum,
+                        "page {page_id} was modified without add_dirty while a savepoint was open"
+                    );
+                }
+            }
+        }
+
+        let mut cache = self.page_cache.write();
+        let mut dirty_pages = self.dirty_pages.write();
+        for (page_id, image) in images {
+            if page_id > db_size {
+                continue;
+            }
+            let page_key = PageCacheKey::new(page_id);
+            let page = match cache.get(&page_key)? {
+                Some(page) => page,
+                None => {
+                    let offset = if page_id == DatabaseHeader::PAGE_ID {
+                        DatabaseHeader::SIZE
+                    } else {
+                        0
+                    };
+                    let page = allocate_new_page(page_id, &self.buffer_pool, offset);
+                    cache.insert(page_key, page.clone())?;
+                    page
+                }
+            };
+            let contents = page.get_contents();
+            contents.as_ptr().copy_from_slice(&image);
+            contents.overflow_cells.clear();
+            dirty_pages.insert(page_id);
+            page.set_dirty();
+        }
+        let truncated: Vec<usize> = dirty_pages
+            .iter()
+            .copied()
+            .filter(|page_id| *page_id > db_size)
+            .collect();
+        for page_id in truncated {
+            dirty_pages.remove(&page_id);
+            let page_key = PageCacheKey::new(page_id);
+            if let Some(page) = cache.get(&page_key)? {
+                page.clear_dirty();
+            }
+            cache.delete(page_key)?;
+        }
+        #[cfg(debug_assertions)]
+        {
+            drop(dirty_pages);
+            drop(cache);
+            let dirty_page_checksums = self.dirty_page_checksums();
+            if let Some(savepoint) = self.savepoints.write().last_mut() {
+                savepoint.dirty_page_checksums = dirty_page_checksums;
+            }
+        }
+        Ok(())
+    }
+
+    /// Discard all open savepoints.
+    pub fn clear_savepoints(&self) {
+        self.savepoints.write().clear();
+    }
+
     pub fn wal_state(&self) -> Result<WalState> {
         let Some(wal) = self.wal.as_ref() else {
             return Err(LimboError::InternalError(
@@ -1609,7 +1899,9 @@ impl Pager {
                     if completions.iter().all(|c| c.is_completed()) {
                         completions.clear();
                         self.commit_info.state.set(CommitState::PrepareWal);
-                        wal.borrow_mut().finish_append_frames_commit()?;
+                        if !self.commit_info.prepare_only.get() {
+                            wal.borrow_mut().finish_append_frames_commit()?;
+                        }
                         let result = self.commit_info.result.borrow_mut().take();
                         return Ok(IOResult::Done(result.expect("commit result should be set")));
                     }
@@ -1854,6 +2146,76 @@ impl Pager {
         Ok(result)
     }
 
+    /// Replaces every page of the database with the pages of `source`, a compacted copy of it
+    /// built by VACUUM, and shrinks the database to the size of the copy. Must be called in a
+    /// write transaction: the new pages reach the database file through the WAL when it
+    /// commits. The schema cookie is bumped because every b-tree moved.
+    pub fn overwrite_with(&self, source: &Pager) -> Result<()> {
+        let old_header = self.io.block(|| self.with_header(|header| *header))?;
+        let source_header = source.io.block(|| source.with_header(|header| *header))?;
+        let new_size = source_header.database_size.get() as usize;
+
+        // Every page of the copy is dirtied at once, so the cache must be able to hold them all
+        // until the transaction ends.
+        {
+            let mut page_cache = self.page_cache.write();
+            if page_cache.capacity() < new_size + 1 {
+                let capacity = page_cache.capacity();
+                let mut overwrite_cache_capacity = self.overwrite_cache_capacity.write();
+                if overwrite_cache_capacity.is_none() {
+                    *overwrite_cache_capacity = Some(capacity);
+                }
+                page_cache.resize(capacity + new_size);
+            }
+        }
+
+        for page_idx in 1..=new_size {
+            let (source_page, c) = source.read_page(page_idx)?;
+            if let Some(c) = c {
+                source.io.wait_for_completion(c)?;
+            }
+            let offset = if page_idx == DatabaseHeader::PAGE_ID {
+                DatabaseHeader::SIZE
+            } else {
+                0
+            };
+            let page = allocate_new_page(page_idx, &self.buffer_pool, offset);
+            page.get_contents()
+                .as_ptr()
+                .copy_from_slice(source_page.get_contents().as_ptr());
+            self.page_cache
+                .write()
+                .upsert_page(PageCacheKey::new(page_idx), page.clone())?;
+            self.add_dirty(&page);
+        }
+        // The pages past the end of the copy are no longer part of the database.
+        self.page_cache.write().truncate(new_size)?;
+
+        let schema_cookie = old_header.schema_cookie.get() + 1;
+        self.io.block(|| {
+            self.with_header_mut(|header| {
+                // The layout of the b-trees comes from the copy, the rest is kept.
+                *header = old_header;
+                header.database_size = source_header.database_size;
+                header.freelist_trunk_page = source_header.freelist_trunk_page;
+                header.freelist_pages = source_header.freelist_pages;
+                header.vacuum_mode_largest_root_page = source_header.vacuum_mode_largest_root_page;
+                header.incremental_vacuum_enabled = source_header.incremental_vacuum_enabled;
+                header.schema_cookie = schema_cookie.into();
+                header.change_counter = (old_header.change_counter.get() + 1).into();
+            })
+        })?;
+        Ok(())
+    }
+
+    /// Shrinks the page cache back to its capacity before [Pager::overwrite_with], once its
+    /// pages are no longer dirty.
+    fn restore_page_cache_capacity(&self) {
+        if let Some(capacity) = self.overwrite_cache_capacity.write().take() {
+            self.page_cache.write().resize(capacity);
+        }
+    }
+
     pub fn freepage_list(&self) -> u32 {
         self.io
             .block(|| HeaderRefMut::from_pager(self))
@@ -2208,6 +2570,7 @@ impl Pager {
                         leaf_page.get().id
                     );
                     let page_contents = trunk_page.get_contents();
+                    self.add_dirty(trunk_page);
                     self.add_dirty(leaf_page);
                     // zero out the page
                     turso_assert!(
@@ -2246,7 +2609,6 @@ impl Pager {
                         FREELIST_TRUNK_OFFSET_LEAF_COUNT,
                         remaining_leaves_count as u32,
                     );
-                    self.add_dirty(trunk_page);
 
                     header.freelist_pages = (header.freelist_pages.get() - 1).into();
                     let leaf_page = leaf_page.clone();
@@ -2313,9 +2675,22 @@ impl Pager {
         is_write: bool,
     ) -> Result<(), LimboError> {
         tracing::debug!(schema_did_change);
+        connection.clear_savepoints();
+        connection.set_deferred_fk_violations(0);
+        self.rollback_pages(is_write)?;
+        if schema_did_change {
+            *connection.schema.write() = connection.db.clone_schema()?;
+        }
+        Ok(())
+    }
+
+    /// Discard the pages modified by the transaction.
+    fn rollback_pages(&self, is_write: bool) -> Result<(), LimboError> {
+        self.clear_savepoints();
         self.clear_page_cache();
         if is_write {
             self.dirty_pages.write().clear();
+            self.restore_page_cache_capacity();
         } else {
             turso_assert!(
                 self.dirty_pages.read().is_empty(),
@@ -2323,15 +2698,11 @@ impl Pager {
             );
         }
         self.reset_internal_states();
-        if schema_did_change {
-            *connection.schema.write() = connection.db.clone_schema()?;
-        }
         if is_write {
             if let Some(wal) = self.wal.as_ref() {
                 wal.borrow_mut().rollback()?;
             }
         }
-
         Ok(())
     }
 
@@ -2401,6 +2772,16 @@ impl Pager {
     }
 }
 
+/// Checksum of the usable space of `page`: the reserved space at its end may be filled in place
+/// when the page is written out.
+#[cfg(debug_assertions)]
+fn page_checksum(page: &Page, usable_space: usize) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = hash::DefaultHasher::new();
+    page.get_contents().as_ptr()[..usable_space].hash(&mut hasher);
+    hasher.finish()
+}
+
 pub fn allocate_new_page(page_id: usize, buffer_pool: &Arc<BufferPool>, offset: usize) -> PageRef {
     let page = Arc::new(Page::new(page_id));
     {
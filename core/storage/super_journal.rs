@@ -0,0 +1,160 @@
+//! Super-journals make a transaction that writes to several database files atomic.
+//!
+//! The commit of such a transaction runs in two phases. First, a super-journal listing the
+//! participating databases is written, and every database gets a child journal (`<path>-mj`)
+//! that names the super-journal and records the size of its WAL before the commit. Then the
+//! frames of every database are written and synced, without being made visible. Deleting the
+//! super-journal is the commit point, after which the frames are made visible and the child
+//! journals are deleted.
+//!
+//! When a database with a child journal is opened and the super-journal it names still exists,
+//! the commit never reached its commit point, so the WAL is truncated back to the recorded size.
+
+use crate::io::{OpenFlags, IO};
+use crate::{Buffer, Completion, Result};
+use std::sync::Arc;
+
+/// The path of the child journal of the database at `db_path`.
+pub fn child_journal_path(db_path: &str) -> String {
+    format!("{db_path}-mj")
+}
+
+/// A new super-journal path next to the database at `db_path`.
+pub fn super_journal_path(io: &Arc<dyn IO>, db_path: &str) -> String {
+    format!("{db_path}-mj{:08X}", io.generate_random_number() as u32)
+}
+
+/// Write and sync the super-journal, which lists the child journals of the transaction.
+pub fn write_super_journal(io: &Arc<dyn IO>, path: &str, child_paths: &[String]) -> Result<()> {
+    let mut contents = String::new();
+    for child_path in child_paths {
+        contents.push_str(child_path);
+        contents.push('\n');
+    }
+    write_file(io, path, contents.as_bytes())
+}
+
+/// Write and sync the child journal of a database whose WAL is `wal_size` bytes long.
+pub fn write_child_journal(
+    io: &Arc<dyn IO>,
+    path: &str,
+    super_journal_path: &str,
+    wal_size: u64,
+) -> Result<()> {
+    write_file(
+        io,
+        path,
+        format!("{super_journal_path}\n{wal_size}\n").as_bytes(),
+    )
+}
+
+/// Roll back the WAL at `wal_path` if the database at `db_path` took part in a multi-database
+/// commit that was interrupted before its commit point. Must run before the WAL is opened.
+pub fn recover(io: &Arc<dyn IO>, db_path: &str, wal_path: &str) -> Result<()> {
+    let child_path = child_journal_path(db_path);
+    let Some(contents) = read_file(io, &child_path)? else {
+        return Ok(());
+    };
+    let contents = String::from_utf8_lossy(&contents);
+    let mut lines = contents.lines();
+    let (Some(super_path), Some(wal_size)) = (
+        lines.next(),
+        lines.next().and_then(|size| size.parse::<u64>().ok()),
+    ) else {
+        // The child journal is written before any frame, so an incomplete one has nothing to
+        // roll back.
+        io.remove_file(&child_path)?;
+        return Ok(());
+    };
+    let Some(super_contents) = read_file(io, super_path)? else {
+        // The commit reached its commit point.
+        io.remove_file(&child_path)?;
+        return Ok(());
+    };
+    tracing::debug!("rolling back {wal_path} to {wal_size} bytes, super-journal {super_path}");
+    if let Ok(wal_file) = io.open_file(wal_path, OpenFlags::None, false) {
+        if wal_file.size()? > wal_size {
+            let c = wal_file.truncate(wal_size, Completion::new_trunc(|_| {}))?;
+            io.wait_for_completion(c)?;
+            let c = wal_file.sync(Completion::new_sync(|_| {}))?;
+            io.wait_for_completion(c)?;
+        }
+    }
+    io.remove_file(&child_path)?;
+    // The super-journal is deleted once no database it lists still needs it.
+    let super_contents = String::from_utf8_lossy(&super_contents);
+    for other_child_path in super_contents.lines() {
+        if let Some(other) = read_file(io, other_child_path)? {
+            if String::from_utf8_lossy(&other).lines().next() == Some(super_path) {
+                return Ok(());
+            }
+        }
+    }
+    io.remove_file(super_path)
+}
+
+fn write_file(io: &Arc<dyn IO>, path: &str, contents: &[u8]) -> Result<()> {
+    let file = io.open_file(path, OpenFlags::Create, false)?;
+    let c = file.truncate(0, Completion::new_trunc(|_| {}))?;
+    io.wait_for_completion(c)?;
+    let buffer = Arc::new(Buffer::new(contents.to_vec()));
+    let c = file.pwrite(0, buffer, Completion::new_write(|_| {}))?;
+    io.wait_for_completion(c)?;
+    let c = file.sync(Completion::new_sync(|_| {}))?;
+    io.wait_for_completion(c)
+}
+
+/// Read a whole file, or `None` if it does not exist.
+fn read_file(io: &Arc<dyn IO>, path: &str) -> Result<Option<Vec<u8>>> {
+    let Ok(file) = io.open_file(path, OpenFlags::None, false) else {
+        return Ok(None);
+    };
+    let size = file.size()? as usize;
+    if size == 0 {
+        return Ok(Some(Vec::new()));
+    }
+    let buffer = Arc::new(Buffer::new_temporary(size));
+    let c = file.pread(0, Completion::new_read(buffer.clone(), |_| {}))?;
+    io.wait_for_completion(c)?;
+    Ok(Some(buffer.as_slice().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryIO;
+
+    fn file_size(io: &Arc<dyn IO>, path: &str) -> Option<u64> {
+        read_file(io, path)
+            .unwrap()
+            .map(|contents| contents.len() as u64)
+    }
+
+    fn setup(io: &Arc<dyn IO>) -> String {
+        write_file(io, "test.db-wal", &[1; 100]).unwrap();
+        let super_path = super_journal_path(io, "test.db");
+        write_super_journal(io, &super_path, &[child_journal_path("test.db")]).unwrap();
+        write_child_journal(io, &child_journal_path("test.db"), &super_path, 40).unwrap();
+        super_path
+    }
+
+    #[test]
+    fn test_recover_interrupted_commit() {
+        let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+        let super_path = setup(&io);
+        recover(&io, "test.db", "test.db-wal").unwrap();
+        assert_eq!(file_size(&io, "test.db-wal"), Some(40));
+        assert_eq!(file_size(&io, &child_journal_path("test.db")), None);
+        assert_eq!(file_size(&io, &super_path), None);
+    }
+
+    #[test]
+    fn test_recover_committed() {
+        let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+        let super_path = setup(&io);
+        io.remove_file(&super_path).unwrap();
+        recover(&io, "test.db", "test.db-wal").unwrap();
+        assert_eq!(file_size(&io, "test.db-wal"), Some(100));
+        assert_eq!(file_size(&io, &child_journal_path("test.db")), None);
+    }
+}
@@ -303,6 +303,10 @@ pub trait Wal: Debug {
     fn get_min_frame(&self) -> u64;
     fn rollback(&mut self) -> Result<()>;
 
+    /// Truncate the WAL file after its last committed frame, dropping the frames that a
+    /// transaction wrote before it was rolled back. Returns `None` if there is nothing to drop.
+    fn truncate_uncommitted_frames(&mut self) -> Result<Option<Completion>>;
+
     /// Return unique set of pages changed **after** frame_watermark position and until current WAL session max_frame_no
     fn changed_pages_after(&self, frame_watermark: u64) -> Result<Vec<u32>>;
 
@@ -1362,6 +1366,21 @@ impl Wal for WalFile {
         Ok(())
     }
 
+    fn truncate_uncommitted_frames(&mut self) -> Result<Option<Completion>> {
+        let shared = self.get_shared();
+        let Some(file) = shared.file.as_ref() else {
+            return Ok(None);
+        };
+        let max_frame = shared.max_frame.load(Ordering::Acquire);
+        let frame_size = (shared.page_size() + WAL_FRAME_HEADER_SIZE as u32) as u64;
+        let size = WAL_HEADER_SIZE as u64 + max_frame * frame_size;
+        if file.size()? <= size {
+            return Ok(None);
+        }
+        let c = file.truncate(size, Completion::new_trunc(|_| {}))?;
+        Ok(Some(c))
+    }
+
     #[instrument(skip_all, level = Level::DEBUG)]
     fn finish_append_frames_commit(&mut self) -> Result<()> {
         let mut shared = self.get_shared_mut();
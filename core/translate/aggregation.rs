@@ -13,7 +13,7 @@ use crate::{
 use super::{
     emitter::{Resolver, TranslateCtx},
     expr::translate_expr,
-    plan::{Aggregate, Distinctness, SelectPlan, TableReferences},
+    plan::{AggOrderByCtx, Aggregate, Distinctness, SelectPlan, TableReferences},
     result_row::emit_select_result,
 };
 
@@ -28,6 +28,17 @@ pub fn emit_ungrouped_aggregation<'a>(
     let agg_start_reg = t_ctx.reg_agg_start.unwrap();
     for (i, agg) in plan.aggregates.iter().enumerate() {
         let agg_result_reg = agg_start_reg + i;
+        // With a constant false condition, the ephemeral indexes of ordered aggregates are
+        // never opened, and there is nothing to feed to the aggregates anyway.
+        if !plan.contains_constant_false_condition {
+            emit_ordered_aggregation_steps(
+                program,
+                &plan.table_references,
+                agg,
+                agg_result_reg,
+                &t_ctx.resolver,
+            )?;
+        }
         program.emit_insn(Insn::AggFinal {
             register: agg_result_reg,
             func: agg.func.clone(),
@@ -160,6 +171,9 @@ pub enum AggArgumentSource<'a> {
         func: &'a AggFunc,
         args: &'a Vec<ast::Expr>,
         distinctness: &'a Distinctness,
+        /// The aggregate the arguments belong to, if any. It provides the expressions of the
+        /// FILTER and ORDER BY clauses.
+        aggregate: Option<&'a Aggregate>,
     },
 }
 
@@ -171,7 +185,7 @@ impl<'a> AggArgumentSource<'a> {
         col_start: usize,
         aggregate: &'a Aggregate,
     ) -> Self {
-        let dest_reg_start = program.alloc_registers(aggregate.args.len());
+        let dest_reg_start = program.alloc_registers(aggregate.num_inputs());
         Self::PseudoCursor {
             cursor_id,
             col_start,
@@ -198,6 +212,26 @@ impl<'a> AggArgumentSource<'a> {
             func,
             args,
             distinctness,
+            aggregate: None,
+        }
+    }
+
+    /// Create a new [AggArgumentSource] that retrieves the values by evaluating the argument,
+    /// ORDER BY and FILTER expressions of `aggregate`.
+    pub fn new_from_aggregate(aggregate: &'a Aggregate) -> Self {
+        Self::Expression {
+            func: &aggregate.func,
+            args: &aggregate.args,
+            distinctness: &aggregate.distinctness,
+            aggregate: Some(aggregate),
+        }
+    }
+
+    pub fn aggregate(&self) -> Option<&'a Aggregate> {
+        match self {
+            AggArgumentSource::PseudoCursor { aggregate, .. } => Some(aggregate),
+            AggArgumentSource::Register { aggregate, .. } => Some(aggregate),
+            AggArgumentSource::Expression { aggregate, .. } => *aggregate,
         }
     }
 
@@ -230,7 +264,7 @@ impl<'a> AggArgumentSource<'a> {
             AggArgumentSource::Expression { args, .. } => args.len(),
         }
     }
-    /// Read the value of an aggregate function argument
+    /// Read the value of an aggregate function input, see [Aggregate::inputs].
     pub fn translate(
         &self,
         program: &mut ProgramBuilder,
@@ -256,15 +290,17 @@ impl<'a> AggArgumentSource<'a> {
                 src_reg_start: start_reg,
                 ..
             } => Ok(*start_reg + arg_idx),
-            AggArgumentSource::Expression { args, .. } => {
+            AggArgumentSource::Expression {
+                args, aggregate, ..
+            } => {
+                let expr = match args.get(arg_idx) {
+                    Some(arg) => arg,
+                    None => aggregate
+                        .and_then(|aggregate| aggregate.inputs().nth(arg_idx))
+                        .expect("aggregate input index out of bounds"),
+                };
                 let dest_reg = program.alloc_register();
-                translate_expr(
-                    program,
-                    Some(referenced_tables),
-                    &args[arg_idx],
-                    dest_reg,
-                    resolver,
-                )
+                translate_expr(program, Some(referenced_tables), expr, dest_reg, resolver)
             }
         }
     }
@@ -281,12 +317,170 @@ impl<'a> AggArgumentSource<'a> {
 /// * In `SELECT SUM(price) FROM t`, `price` is evaluated for each row and added to the accumulator.
 /// * In `SELECT product_category, SUM(price) FROM t GROUP BY product_category`, `price` is evaluated for
 ///   each row in the group and added to that group’s accumulator.
+///
+/// Rows rejected by the FILTER clause of the aggregate are skipped. The arguments of an aggregate with
+/// an ORDER BY clause are buffered instead, see [emit_ordered_aggregation_steps].
 pub fn translate_aggregation_step(
     program: &mut ProgramBuilder,
     referenced_tables: &TableReferences,
     agg_arg_source: AggArgumentSource,
     target_register: usize,
     resolver: &Resolver,
+) -> Result<usize> {
+    let Some(aggregate) = agg_arg_source.aggregate() else {
+        return emit_aggregation_step(
+            program,
+            referenced_tables,
+            &agg_arg_source,
+            target_register,
+            resolver,
+        );
+    };
+    let label_skip = program.allocate_label();
+    if aggregate.filter.is_some() {
+        let filter_reg = agg_arg_source.translate(
+            program,
+            referenced_tables,
+            resolver,
+            aggregate.num_inputs() - 1,
+        )?;
+        program.emit_insn(Insn::IfNot {
+            reg: filter_reg,
+            target_pc: label_skip,
+            jump_if_null: true,
+        });
+    }
+    match &aggregate.order_by_ctx {
+        Some(ctx) => emit_ordered_aggregation_insert(
+            program,
+            referenced_tables,
+            &agg_arg_source,
+            aggregate,
+            ctx,
+            resolver,
+        )?,
+        None => {
+            emit_aggregation_step(
+                program,
+                referenced_tables,
+                &agg_arg_source,
+                target_register,
+                resolver,
+            )?;
+        }
+    }
+    program.preassign_label_to_next_insn(label_skip);
+    Ok(target_register)
+}
+
+/// Emits the bytecode inserting the arguments of an aggregate with an ORDER BY clause into the
+/// ephemeral index buffering them.
+fn emit_ordered_aggregation_insert(
+    program: &mut ProgramBuilder,
+    referenced_tables: &TableReferences,
+    agg_arg_source: &AggArgumentSource,
+    aggregate: &Aggregate,
+    ctx: &AggOrderByCtx,
+    resolver: &Resolver,
+) -> Result<()> {
+    // The record is made of the ORDER BY terms, the sequence number and the arguments.
+    let num_args = aggregate.args.len();
+    let num_keys = aggregate.order_by.len();
+    let record_start = program.alloc_registers(num_keys + 1 + num_args);
+    for i in 0..num_keys {
+        let reg = agg_arg_source.translate(program, referenced_tables, resolver, num_args + i)?;
+        program.emit_insn(Insn::Copy {
+            src_reg: reg,
+            dst_reg: record_start + i,
+            extra_amount: 0,
+        });
+    }
+    let args_start = record_start + num_keys + 1;
+    for i in 0..num_args {
+        let reg = agg_arg_source.translate(program, referenced_tables, resolver, i)?;
+        program.emit_insn(Insn::Copy {
+            src_reg: reg,
+            dst_reg: args_start + i,
+            extra_amount: 0,
+        });
+    }
+    handle_distinct(program, agg_arg_source.distinctness(), args_start);
+    program.emit_insn(Insn::AddImm {
+        register: ctx.reg_sequence,
+        value: 1,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: ctx.reg_sequence,
+        dst_reg: record_start + num_keys,
+        extra_amount: 0,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: record_start,
+        count: num_keys + 1 + num_args,
+        dest_reg: record_reg,
+        index_name: Some(ctx.ephemeral_index_name.clone()),
+        affinity_str: None,
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id: ctx.cursor_id,
+        record_reg,
+        unpacked_start: None,
+        unpacked_count: None,
+        flags: IdxInsertFlags::new(),
+    });
+    Ok(())
+}
+
+/// Emits the bytecode feeding the arguments buffered for an aggregate with an ORDER BY clause to
+/// the aggregate function, in the order of the clause. This must run before the aggregate is
+/// finalized.
+pub fn emit_ordered_aggregation_steps(
+    program: &mut ProgramBuilder,
+    referenced_tables: &TableReferences,
+    aggregate: &Aggregate,
+    target_register: usize,
+    resolver: &Resolver,
+) -> Result<()> {
+    let Some(ctx) = &aggregate.order_by_ctx else {
+        return Ok(());
+    };
+    let num_keys = aggregate.order_by.len();
+    let label_loop = program.allocate_label();
+    let label_end = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: ctx.cursor_id,
+        pc_if_empty: label_end,
+    });
+    program.preassign_label_to_next_insn(label_loop);
+    let args = (0..aggregate.args.len())
+        .map(|i| {
+            let reg = program.alloc_register();
+            program.emit_column_or_rowid(ctx.cursor_id, num_keys + 1 + i, reg);
+            ast::Expr::Register(reg)
+        })
+        .collect::<Vec<_>>();
+    emit_aggregation_step(
+        program,
+        referenced_tables,
+        &AggArgumentSource::new_from_expression(&aggregate.func, &args, &Distinctness::NonDistinct),
+        target_register,
+        resolver,
+    )?;
+    program.emit_insn(Insn::Next {
+        cursor_id: ctx.cursor_id,
+        pc_if_next: label_loop,
+    });
+    program.preassign_label_to_next_insn(label_end);
+    Ok(())
+}
+
+fn emit_aggregation_step(
+    program: &mut ProgramBuilder,
+    referenced_tables: &TableReferences,
+    agg_arg_source: &AggArgumentSource,
+    target_register: usize,
+    resolver: &Resolver,
 ) -> Result<usize> {
     let num_args = agg_arg_source.num_args();
     let func = agg_arg_source.agg_func();
@@ -7,22 +7,25 @@ use crate::{
     util::normalize_ident,
     vdbe::{
         builder::{CursorType, ProgramBuilder},
-        insn::{Cookie, Insn, RegisterOrLiteral},
+        insn::{Cookie, IdxInsertFlags, Insn, RegisterOrLiteral},
     },
     LimboError, Result, SymbolTable,
 };
 
-use super::{schema::SQLITE_TABLEID, update::translate_update_for_schema_change};
+use super::{
+    insert::emit_table_record, schema::SQLITE_TABLEID, update::translate_update_for_schema_change,
+};
 
 pub fn translate_alter_table(
     alter: ast::AlterTable,
+    database_id: usize,
     syms: &SymbolTable,
     schema: &Schema,
     mut program: ProgramBuilder,
     connection: &Arc<crate::Connection>,
     input: &str,
 ) -> Result<ProgramBuilder> {
-    program.begin_write_operation();
+    program.begin_write_operation_on(database_id);
     let ast::AlterTable {
         name: table_name,
         body: alter_table,
@@ -94,6 +97,48 @@ pub fn translate_alter_table(
                 )));
             }
 
+            if let Some(index) = schema
+                .get_indices(table_name)
+                .find(|index| index.columns.iter().any(|c| c.uses_column(column_name)))
+            {
+                return Err(LimboError::ParseError(format!(
+                    "error in index {} after drop column: no such column: {column_name}",
+                    index.name
+                )));
+            }
+
+            if btree
+                .columns
+                .iter()
+                .enumerate()
+                .all(|(i, column)| i == dropped_index || column.generated.is_some())
+            {
+                return Err(LimboError::ParseError(format!(
+                    "error in table {table_name} after drop column: must have at least one non-generated column"
+                )));
+            }
+
+            // The CHECK constraints of the column are dropped with it.
+            let dropped_name = normalize_ident(column_name);
+            btree
+                .checks
+                .retain(|check| check.column.as_ref() != Some(&dropped_name));
+            if btree
+                .checks
+                .iter()
+                .any(|check| check.uses_any_column(&[dropped_name.clone()], false))
+                || btree.columns.iter().any(|column| {
+                    column
+                        .generated
+                        .as_ref()
+                        .is_some_and(|g| g.uses_any_column(&[dropped_name.clone()]))
+                })
+            {
+                return Err(LimboError::ParseError(format!(
+                    "error in table {table_name} after drop column: no such column: {column_name}"
+                )));
+            }
+
             btree.columns.remove(dropped_index);
 
             let sql = btree.to_sql().replace('\'', "''");
@@ -115,6 +160,7 @@ pub fn translate_alter_table(
             translate_update_for_schema_change(
                 schema,
                 &mut update,
+                database_id,
                 syms,
                 program,
                 connection,
@@ -129,7 +175,7 @@ pub fn translate_alter_table(
                     program.emit_insn(Insn::OpenWrite {
                         cursor_id,
                         root_page: RegisterOrLiteral::Literal(root_page),
-                        db: 0,
+                        db: database_id,
                     });
 
                     program.cursor_loop(cursor_id, |program, rowid| {
@@ -148,20 +194,19 @@ pub fn translate_alter_table(
                         }
 
                         let record = program.alloc_register();
-
-                        let affinity_str = btree
-                            .columns
-                            .iter()
-                            .map(|col| col.affinity().aff_mask())
-                            .collect::<String>();
-
-                        program.emit_insn(Insn::MakeRecord {
-                            start_reg: first_column,
-                            count: column_count,
-                            dest_reg: record,
-                            index_name: None,
-                            affinity_str: Some(affinity_str),
-                        });
+                        emit_table_record(program, &btree, first_column, record);
+
+                        if !btree.has_rowid {
+                            // The row keeps its PRIMARY KEY, so the new record overwrites it
+                            program.emit_insn(Insn::IdxInsert {
+                                cursor_id,
+                                record_reg: record,
+                                unpacked_start: None,
+                                unpacked_count: None,
+                                flags: IdxInsertFlags::new(),
+                            });
+                            return;
+                        }
 
                         program.emit_insn(Insn::Insert {
                             cursor: cursor_id,
@@ -173,13 +218,14 @@ pub fn translate_alter_table(
                     });
 
                     program.emit_insn(Insn::SetCookie {
-                        db: 0,
+                        db: database_id,
                         cookie: Cookie::SchemaVersion,
                         value: schema.schema_version as i32 + 1,
                         p5: 0,
                     });
 
                     program.emit_insn(Insn::DropColumn {
+                        db: database_id,
                         table: table_name,
                         column_index: dropped_index,
                     })
@@ -189,6 +235,12 @@ pub fn translate_alter_table(
         ast::AlterTableBody::AddColumn(col_def) => {
             let column = Column::from(&col_def);
 
+            if column.generated.as_ref().is_some_and(|g| g.stored) {
+                return Err(LimboError::ParseError(
+                    "cannot add a STORED column".to_string(),
+                ));
+            }
+
             if let Some(default) = &column.default {
                 if !matches!(
                     default.as_ref(),
@@ -216,6 +268,7 @@ pub fn translate_alter_table(
 
             // TODO: All quoted ids will be quoted with `[]`, we should store some info from the parsed AST
             btree.columns.push(column.clone());
+            btree.check_generated_columns()?;
 
             let sql = btree.to_sql();
             let mut escaped = String::with_capacity(sql.len());
@@ -244,18 +297,20 @@ pub fn translate_alter_table(
             translate_update_for_schema_change(
                 schema,
                 &mut update,
+                database_id,
                 syms,
                 program,
                 connection,
                 input,
                 |program| {
                     program.emit_insn(Insn::SetCookie {
-                        db: 0,
+                        db: database_id,
                         cookie: Cookie::SchemaVersion,
                         value: schema.schema_version as i32 + 1,
                         p5: 0,
                     });
                     program.emit_insn(Insn::AddColumn {
+                        db: database_id,
                         table: table_name.to_owned(),
                         column,
                     });
@@ -286,7 +341,7 @@ pub fn translate_alter_table(
             program.emit_insn(Insn::OpenWrite {
                 cursor_id,
                 root_page: RegisterOrLiteral::Literal(sqlite_schema.root_page),
-                db: 0,
+                db: database_id,
             });
 
             program.cursor_loop(cursor_id, |program, rowid| {
@@ -337,13 +392,14 @@ pub fn translate_alter_table(
             });
 
             program.emit_insn(Insn::SetCookie {
-                db: 0,
+                db: database_id,
                 cookie: Cookie::SchemaVersion,
                 value: schema.schema_version as i32 + 1,
                 p5: 0,
             });
 
             program.emit_insn(Insn::RenameTable {
+                db: database_id,
                 from: table_name.to_owned(),
                 to: new_name.to_owned(),
             });
@@ -421,7 +477,7 @@ pub fn translate_alter_table(
             program.emit_insn(Insn::OpenWrite {
                 cursor_id,
                 root_page: RegisterOrLiteral::Literal(sqlite_schema.root_page),
-                db: 0,
+                db: database_id,
             });
 
             program.cursor_loop(cursor_id, |program, rowid| {
@@ -479,12 +535,13 @@ pub fn translate_alter_table(
             });
 
             program.emit_insn(Insn::SetCookie {
-                db: 0,
+                db: database_id,
                 cookie: Cookie::SchemaVersion,
                 value: schema.schema_version as i32 + 1,
                 p5: 0,
             });
             program.emit_insn(Insn::AlterColumn {
+                db: database_id,
                 table: table_name.to_owned(),
                 column_index,
                 definition,
@@ -4,7 +4,8 @@ use turso_parser::ast;
 
 use crate::{
     bail_parse_error,
-    schema::{BTreeTable, Schema},
+    schema::{BTreeTable, Index, Schema},
+    stats::{STAT1_TABLE_NAME, STAT4_TABLE_NAME},
     storage::pager::CreateBTreeFlags,
     translate::{
         emitter::Resolver,
@@ -13,223 +14,627 @@ use crate::{
     util::normalize_ident,
     vdbe::{
         builder::{CursorType, ProgramBuilder},
-        insn::{Insn, RegisterOrLiteral},
+        insn::{CmpInsFlags, Cookie, Insn, RegisterOrLiteral},
     },
-    Result, SymbolTable,
+    Connection, Result, SymbolTable,
 };
 
+const STAT1_SQL: &str = "CREATE TABLE sqlite_stat1(tbl,idx,stat)";
+const STAT4_SQL: &str = "CREATE TABLE sqlite_stat4(tbl,idx,neq,nlt,ndlt,sample)";
+
+/// A table to analyze, or a single index of the table.
+pub struct AnalyzeTarget {
+    pub table: Arc<BTreeTable>,
+    pub index: Option<Arc<Index>>,
+}
+
 pub fn translate_analyze(
     target_opt: Option<ast::QualifiedName>,
     schema: &Schema,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
 ) -> Result<ProgramBuilder> {
+    let analysis_limit = connection.get_analysis_limit();
     let Some(target) = target_opt else {
-        bail_parse_error!("ANALYZE with no target is not supported");
+        // ANALYZE with no target analyzes every database but the temp database.
+        let mut databases = vec![0];
+        databases.extend(
+            connection
+                .attached_database_indexes()
+                .into_iter()
+                .filter(|db| *db > 1),
+        );
+        for db in databases {
+            let db_schema = connection.get_database_schema(db)?;
+            let db_schema = if db == 0 { schema } else { &db_schema };
+            let targets = database_targets(db_schema);
+            emit_analyze(
+                &mut program,
+                db_schema,
+                syms,
+                db,
+                &targets,
+                true,
+                analysis_limit,
+            )?;
+        }
+        return Ok(program);
     };
-    let normalized = normalize_ident(target.name.as_str());
-    let Some(target_schema) = schema.get_table(&normalized) else {
-        bail_parse_error!("ANALYZE <schema_name> is not supported");
+
+    // A single name may be the name of a database.
+    if target.db_name.is_none() {
+        let as_database =
+            ast::QualifiedName::fullname(target.name.clone(), ast::Name::new("sqlite_schema"));
+        if let Ok(db) = connection.resolve_database_id(&as_database) {
+            let db_schema = connection.get_database_schema(db)?;
+            let db_schema = if db == 0 { schema } else { &db_schema };
+            let targets = database_targets(db_schema);
+            emit_analyze(
+                &mut program,
+                db_schema,
+                syms,
+                db,
+                &targets,
+                true,
+                analysis_limit,
+            )?;
+            return Ok(program);
+        }
+    }
+
+    let name = normalize_ident(target.name.as_str());
+    let index_db = connection.resolve_index_database_id(&target)?;
+    let index_schema = connection.get_database_schema(index_db)?;
+    let index_schema = if index_db == 0 { schema } else { &index_schema };
+    if let Some(index) = index_schema
+        .indexes
+        .values()
+        .flatten()
+        .find(|index| index.name == name)
+    {
+        let table = index_schema
+            .get_btree_table(&index.table_name)
+            .expect("table of index must exist");
+        let targets = [AnalyzeTarget {
+            table,
+            index: Some(index.clone()),
+        }];
+        emit_analyze(
+            &mut program,
+            index_schema,
+            syms,
+            index_db,
+            &targets,
+            false,
+            analysis_limit,
+        )?;
+        return Ok(program);
+    }
+
+    let db = connection.resolve_database_id(&target)?;
+    let db_schema = connection.get_database_schema(db)?;
+    let db_schema = if db == 0 { schema } else { &db_schema };
+    let table = match db_schema.get_table(&name) {
+        Some(table) => table.btree(),
+        None if db_schema.get_view(&name).is_some() => None,
+        None => match &target.db_name {
+            Some(db_name) => bail_parse_error!("no such table: {}.{}", db_name.as_str(), name),
+            None => bail_parse_error!("no such table: {}", name),
+        },
     };
-    let Some(target_btree) = target_schema.btree() else {
-        bail_parse_error!("ANALYZE on index is not supported");
+    // Like SQLite, the statistics tables are created even for views and virtual tables, which
+    // have no statistics.
+    let targets: Vec<AnalyzeTarget> = table
+        .map(|table| AnalyzeTarget { table, index: None })
+        .into_iter()
+        .collect();
+    emit_analyze(
+        &mut program,
+        db_schema,
+        syms,
+        db,
+        &targets,
+        false,
+        analysis_limit,
+    )?;
+    Ok(program)
+}
+
+/// Returns the tables of a database that ANALYZE gathers statistics for.
+pub fn database_targets(schema: &Schema) -> Vec<AnalyzeTarget> {
+    let mut targets: Vec<AnalyzeTarget> = schema
+        .tables
+        .values()
+        .filter_map(|table| table.btree())
+        .filter(|table| !schema.materialized_view_names.contains(&table.name))
+        .map(|table| AnalyzeTarget { table, index: None })
+        .collect();
+    targets.sort_by(|a, b| a.table.name.cmp(&b.table.name));
+    targets
+}
+
+/// Emits the gathering of the statistics of `targets` into the `sqlite_stat1` and `sqlite_stat4`
+/// tables of a database, creating them if needed. The previous statistics of the targets are
+/// deleted first; all of them if `clear_all` is set. Only the first `analysis_limit` entries of
+/// each index are visited if it is not 0.
+pub fn emit_analyze(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    syms: &SymbolTable,
+    db: usize,
+    targets: &[AnalyzeTarget],
+    clear_all: bool,
+    analysis_limit: i64,
+) -> Result<()> {
+    program.begin_write_operation_on(db);
+
+    // The statistics of the internal tables, such as sqlite_stat1 itself, are not gathered.
+    let targets: Vec<&AnalyzeTarget> = targets
+        .iter()
+        .filter(|target| {
+            !target
+                .table
+                .name
+                .to_ascii_lowercase()
+                .starts_with("sqlite_")
+        })
+        .collect();
+
+    let stat1 = schema.get_btree_table(STAT1_TABLE_NAME);
+    let stat4 = schema.get_btree_table(STAT4_TABLE_NAME);
+    let resolver = Resolver::new(schema, syms);
+    let mut created = Vec::new();
+    let mut open_stat_table = |program: &mut ProgramBuilder,
+                               existing: Option<Arc<BTreeTable>>,
+                               name: &str,
+                               sql: &str|
+     -> Result<(Arc<BTreeTable>, RegisterOrLiteral<usize>)> {
+        if let Some(table) = existing {
+            let root_page = RegisterOrLiteral::Literal(table.root_page);
+            return Ok((table, root_page));
+        }
+        let root_reg = program.alloc_register();
+        program.emit_insn(Insn::CreateBtree {
+            db,
+            root: root_reg,
+            flags: CreateBTreeFlags::new_table(),
+        });
+        let schema_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+        let schema_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(schema_table));
+        program.emit_insn(Insn::OpenWrite {
+            cursor_id: schema_cursor_id,
+            root_page: 1usize.into(),
+            db,
+        });
+        emit_schema_entry(
+            program,
+            &resolver,
+            schema_cursor_id,
+            None,
+            SchemaEntryType::Table,
+            name,
+            name,
+            root_reg,
+            Some(sql.to_string()),
+        )?;
+        created.push(name.to_string());
+        // The root page of the table is only known when the program runs.
+        Ok((
+            Arc::new(BTreeTable::from_sql(sql, 0)?),
+            RegisterOrLiteral::Register(root_reg),
+        ))
     };
+    let stat1_existed = stat1.is_some();
+    let stat4_existed = stat4.is_some();
+    let (stat1, stat1_root) = open_stat_table(program, stat1, STAT1_TABLE_NAME, STAT1_SQL)?;
+    let (stat4, stat4_root) = open_stat_table(program, stat4, STAT4_TABLE_NAME, STAT4_SQL)?;
+    if !created.is_empty() {
+        program.emit_insn(Insn::SetCookie {
+            db,
+            cookie: Cookie::SchemaVersion,
+            value: schema.schema_version as i32 + 1,
+            p5: 0,
+        });
+        let where_clause = created
+            .iter()
+            .map(|name| format!("tbl_name = '{name}'"))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        program.emit_insn(Insn::ParseSchema {
+            db,
+            where_clause: Some(format!("({where_clause}) AND type != 'trigger'")),
+        });
+    }
 
-    // This is emitted early because SQLite does, and thus generated VDBE matches a bit closer.
-    let null_reg = program.alloc_register();
-    program.emit_insn(Insn::Null {
-        dest: null_reg,
-        dest_end: None,
+    let stat1_cursor = program.alloc_cursor_id(CursorType::BTreeTable(stat1));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: stat1_cursor,
+        root_page: stat1_root,
+        db,
+    });
+    let stat4_cursor = program.alloc_cursor_id(CursorType::BTreeTable(stat4));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: stat4_cursor,
+        root_page: stat4_root,
+        db,
     });
 
-    // After preparing/creating sqlite_stat1, we need to OpenWrite it, and how we acquire
-    // the necessary BTreeTable for cursor creation and root page for the instruction changes
-    // depending on which path we take.
-    let sqlite_stat1_btreetable: Arc<BTreeTable>;
-    let sqlite_stat1_source: RegisterOrLiteral<_>;
-
-    if let Some(sqlite_stat1) = schema.get_btree_table("sqlite_stat1") {
-        sqlite_stat1_btreetable = sqlite_stat1.clone();
-        sqlite_stat1_source = RegisterOrLiteral::Literal(sqlite_stat1.root_page);
-        // sqlite_stat1 already exists, so we need to remove the row
-        // corresponding to the stats for the table which we're about to
-        // ANALYZE. SQLite implements this as a full table scan over
-        // sqlite_stat1 deleting any rows where the first column (table_name)
-        // is the targeted table.
-        let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(sqlite_stat1.clone()));
+    // Delete the previous statistics of the targets.
+    for (cursor_id, existed, table_name) in [
+        (stat1_cursor, stat1_existed, STAT1_TABLE_NAME),
+        (stat4_cursor, stat4_existed, STAT4_TABLE_NAME),
+    ] {
+        if !existed {
+            continue;
+        }
+        if clear_all {
+            emit_delete_stat_rows(program, cursor_id, table_name, None);
+            continue;
+        }
+        for target in targets.iter() {
+            let filter = match &target.index {
+                Some(index) => (1, index.name.as_str()),
+                None => (0, target.table.name.as_str()),
+            };
+            emit_delete_stat_rows(program, cursor_id, table_name, Some(filter));
+        }
+    }
+
+    for target in targets {
+        emit_analyze_table(
+            program,
+            schema,
+            db,
+            target,
+            stat1_cursor,
+            stat4_cursor,
+            analysis_limit,
+        );
+    }
+
+    program.emit_insn(Insn::LoadAnalysis { db });
+    Ok(())
+}
+
+/// Emits the deletion of the statistics whose column `column` (0 for `tbl`, 1 for `idx`) is
+/// `name`, for DROP TABLE and DROP INDEX.
+pub fn emit_clear_stat_rows(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    db: usize,
+    column: usize,
+    name: &str,
+) {
+    if name.eq_ignore_ascii_case(STAT1_TABLE_NAME) || name.eq_ignore_ascii_case(STAT4_TABLE_NAME) {
+        return;
+    }
+    for table_name in [STAT1_TABLE_NAME, STAT4_TABLE_NAME] {
+        let Some(table) = schema.get_btree_table(table_name) else {
+            continue;
+        };
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
         program.emit_insn(Insn::OpenWrite {
             cursor_id,
-            root_page: RegisterOrLiteral::Literal(sqlite_stat1.root_page),
-            db: 0,
+            root_page: RegisterOrLiteral::Literal(table.root_page),
+            db,
         });
-        let after_loop = program.allocate_label();
-        program.emit_insn(Insn::Rewind {
-            cursor_id,
-            pc_if_empty: after_loop,
-        });
-        let loophead = program.allocate_label();
-        program.preassign_label_to_next_insn(loophead);
+        emit_delete_stat_rows(program, cursor_id, table_name, Some((column, name)));
+    }
+}
+
+/// Emits a loop over the statistics table open on `cursor_id` that deletes the rows whose
+/// column `filter.0` (0 for `tbl`, 1 for `idx`) is `filter.1`, or all rows if there is no
+/// filter.
+pub fn emit_delete_stat_rows(
+    program: &mut ProgramBuilder,
+    cursor_id: usize,
+    table_name: &str,
+    filter: Option<(usize, &str)>,
+) {
+    let after_loop = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: after_loop,
+    });
+    let loop_start = program.allocate_label();
+    let next = program.allocate_label();
+    program.preassign_label_to_next_insn(loop_start);
+    if let Some((column, name)) = filter {
         let column_reg = program.alloc_register();
         program.emit_insn(Insn::Column {
             cursor_id,
-            column: 0,
+            column,
             dest: column_reg,
             default: None,
         });
-        let tablename_reg = program.alloc_register();
-        program.emit_insn(Insn::String8 {
-            value: target_schema.get_name().to_string(),
-            dest: tablename_reg,
-        });
+        let name_reg = program.emit_string8_new_reg(name.to_string());
         program.mark_last_insn_constant();
-        // FIXME: The SQLite instruction says p4=BINARY-8 and p5=81.  Neither are currently supported in Turso.
         program.emit_insn(Insn::Ne {
             lhs: column_reg,
-            rhs: tablename_reg,
-            target_pc: after_loop,
-            flags: Default::default(),
+            rhs: name_reg,
+            target_pc: next,
+            flags: CmpInsFlags::default().jump_if_null(),
             collation: None,
         });
-        let rowid_reg = program.alloc_register();
-        program.emit_insn(Insn::RowId {
-            cursor_id,
-            dest: rowid_reg,
-        });
-        program.emit_insn(Insn::Delete {
-            cursor_id,
-            table_name: "sqlite_stat1".to_string(),
-        });
-        program.emit_insn(Insn::Next {
-            cursor_id,
-            pc_if_next: loophead,
-        });
-        program.preassign_label_to_next_insn(after_loop);
-    } else {
-        // FIXME: Emit ReadCookie 0 3 2
-        // FIXME: Emit If 3 +2 0
-        // FIXME: Emit SetCookie 0 2 4
-        // FIXME: Emit SetCookie 0 5 1
-
-        // See the large comment in schema.rs:translate_create_table about
-        // deviating from SQLite codegen, as the same deviation is being done
-        // here.
-
-        // TODO: this code half-copies translate_create_table, because there's
-        // no way to get the table_root_reg back out, and it's needed for later
-        // codegen to open the table we just created.  It's worth a future
-        // refactoring to remove the duplication one the rest of ANALYZE is
-        // implemented.
-        let table_root_reg = program.alloc_register();
-        program.emit_insn(Insn::CreateBtree {
-            db: 0,
-            root: table_root_reg,
-            flags: CreateBTreeFlags::new_table(),
-        });
-        let sql = "CREATE TABLE sqlite_stat1(tbl,idx,stat)";
-        // The root_page==0 is false, but we don't rely on it, and there's no
-        // way to initialize it with a correct value.
-        sqlite_stat1_btreetable = Arc::new(BTreeTable::from_sql(sql, 0)?);
-        sqlite_stat1_source = RegisterOrLiteral::Register(table_root_reg);
-
-        let table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
-        let sqlite_schema_cursor_id =
-            program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
-        program.emit_insn(Insn::OpenWrite {
-            cursor_id: sqlite_schema_cursor_id,
-            root_page: 1usize.into(),
-            db: 0,
-        });
+    }
+    program.emit_insn(Insn::Delete {
+        cursor_id,
+        table_name: table_name.to_string(),
+    });
+    program.preassign_label_to_next_insn(next);
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.preassign_label_to_next_insn(after_loop);
+}
 
-        let resolver = Resolver::new(schema, syms);
-        // Add the table entry to sqlite_schema
-        emit_schema_entry(
-            &mut program,
-            &resolver,
-            sqlite_schema_cursor_id,
-            None,
-            SchemaEntryType::Table,
-            "sqlite_stat1",
-            "sqlite_stat1",
-            table_root_reg,
-            Some(sql.to_string()),
-        )?;
-        //FIXME: Emit SetCookie?
-        let parse_schema_where_clause =
-            "tbl_name = 'sqlite_stat1' AND type != 'trigger'".to_string();
-        program.emit_insn(Insn::ParseSchema {
-            db: sqlite_schema_cursor_id,
-            where_clause: Some(parse_schema_where_clause),
-        });
+/// Emits the gathering of the statistics of a table, or of one of its indexes.
+fn emit_analyze_table(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    db: usize,
+    target: &AnalyzeTarget,
+    stat1_cursor: usize,
+    stat4_cursor: usize,
+    analysis_limit: i64,
+) {
+    let table = &target.table;
+    let indexes: Vec<Arc<Index>> = match &target.index {
+        Some(index) => vec![index.clone()],
+        None => {
+            let mut indexes: Vec<Arc<Index>> = schema.get_indices(&table.name).cloned().collect();
+            if !table.has_rowid {
+                indexes.push(Arc::new(Index::without_rowid_primary_key(table)));
+            }
+            indexes
+        }
     };
 
-    if target_schema.columns().iter().any(|c| c.primary_key) {
-        bail_parse_error!("ANALYZE on tables with primary key is not supported");
-    }
-    if !target_btree.has_rowid {
-        bail_parse_error!("ANALYZE on tables without rowid is not supported");
+    // The row of sqlite_stat1 is made of these three registers.
+    let tbl_reg = program.alloc_registers(3);
+    let idx_reg = tbl_reg + 1;
+    let stat_reg = tbl_reg + 2;
+    program.emit_insn(Insn::String8 {
+        value: table.name.clone(),
+        dest: tbl_reg,
+    });
+
+    for index in indexes.iter() {
+        emit_analyze_index(
+            program,
+            db,
+            table,
+            index,
+            tbl_reg,
+            stat1_cursor,
+            stat4_cursor,
+            analysis_limit,
+        );
     }
 
-    // Count the number of rows in the target table, and insert it into sqlite_stat1.
-    let sqlite_stat1 = sqlite_stat1_btreetable;
-    let stat_cursor = program.alloc_cursor_id(CursorType::BTreeTable(sqlite_stat1.clone()));
-    program.emit_insn(Insn::OpenWrite {
-        cursor_id: stat_cursor,
-        root_page: sqlite_stat1_source,
-        db: 0,
-    });
-    let target_cursor = program.alloc_cursor_id(CursorType::BTreeTable(target_btree.clone()));
+    // The number of rows of the table is recorded separately if no index has an entry for each
+    // row.
+    if target.index.is_some() || indexes.iter().any(|index| index.where_clause.is_none()) {
+        return;
+    }
+    let table_cursor = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
     program.emit_insn(Insn::OpenRead {
-        cursor_id: target_cursor,
-        root_page: target_btree.root_page,
-        db: 0,
-    });
-    let rowid_reg = program.alloc_register();
-    let record_reg = program.alloc_register();
-    let tablename_reg = program.alloc_register();
-    let indexname_reg = program.alloc_register();
-    let count_reg = program.alloc_register();
-    program.emit_insn(Insn::String8 {
-        value: target_schema.get_name().to_string(),
-        dest: tablename_reg,
+        cursor_id: table_cursor,
+        root_page: table.root_page,
+        db,
     });
     program.emit_insn(Insn::Count {
-        cursor_id: target_cursor,
-        target_reg: count_reg,
+        cursor_id: table_cursor,
+        target_reg: stat_reg,
         exact: true,
     });
     let after_insert = program.allocate_label();
     program.emit_insn(Insn::IfNot {
-        reg: count_reg,
+        reg: stat_reg,
         target_pc: after_insert,
         jump_if_null: false,
     });
     program.emit_insn(Insn::Null {
-        dest: indexname_reg,
+        dest: idx_reg,
         dest_end: None,
     });
+    emit_insert_stat_row(program, stat1_cursor, STAT1_TABLE_NAME, tbl_reg, 3);
+    program.preassign_label_to_next_insn(after_insert);
+}
+
+/// Emits a scan of an index that feeds each entry to [Insn::StatPush], with the position of
+/// the leftmost key column that differs from the previous entry, and writes the resulting
+/// statistics to sqlite_stat1 and sqlite_stat4.
+#[allow(clippy::too_many_arguments)]
+fn emit_analyze_index(
+    program: &mut ProgramBuilder,
+    db: usize,
+    table: &Arc<BTreeTable>,
+    index: &Arc<Index>,
+    tbl_reg: usize,
+    stat1_cursor: usize,
+    stat4_cursor: usize,
+    analysis_limit: i64,
+) {
+    let is_primary_key = index.is_without_rowid_primary_key_of(table);
+    let num_key_columns = index.columns.len();
+    let num_columns = if is_primary_key {
+        num_key_columns
+    } else if index.has_rowid {
+        num_key_columns + 1
+    } else {
+        num_key_columns + index.primary_key_suffix.len()
+    };
+    let sample = analysis_limit == 0;
+
+    let index_cursor = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+    program.emit_insn(Insn::OpenRead {
+        cursor_id: index_cursor,
+        root_page: index.root_page,
+        db,
+    });
+    let row_count_reg = program.alloc_register();
+    program.emit_insn(Insn::Count {
+        cursor_id: index_cursor,
+        target_reg: row_count_reg,
+        exact: true,
+    });
+    program.emit_insn(Insn::StatInit {
+        num_key_columns,
+        num_columns,
+        row_count_reg,
+        sample,
+    });
+    // Like SQLite, the scan stops after the entry that follows the first `analysis_limit` ones.
+    let limit_reg = (analysis_limit > 0).then(|| {
+        let reg = program.alloc_register();
+        program.emit_int(analysis_limit + 1, reg);
+        reg
+    });
+
+    // Like SQLite, a partial index without entries still gets a sqlite_stat1 row, but an empty
+    // index of an empty table does not.
+    let end = program.allocate_label();
+    let after_scan = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: index_cursor,
+        pc_if_empty: if index.where_clause.is_some() {
+            after_scan
+        } else {
+            end
+        },
+    });
+
+    let changed_reg = program.alloc_register();
+    let column_reg = program.alloc_register();
+    let prev_reg = program.alloc_registers(num_key_columns.max(1));
+    let load_prev_labels: Vec<_> = (0..num_key_columns)
+        .map(|_| program.allocate_label())
+        .collect();
+    let push = program.allocate_label();
+
+    // The first entry differs from the (nonexistent) previous entry in all its columns.
+    program.emit_int(0, changed_reg);
+    program.emit_insn(Insn::Goto {
+        target_pc: load_prev_labels.first().copied().unwrap_or(push),
+    });
+
+    let next_entry = program.allocate_label();
+    program.preassign_label_to_next_insn(next_entry);
+    for (i, column) in index.columns.iter().enumerate() {
+        program.emit_int(i as i64, changed_reg);
+        program.emit_insn(Insn::Column {
+            cursor_id: index_cursor,
+            column: i,
+            dest: column_reg,
+            default: None,
+        });
+        let collation = column.collation.or_else(|| {
+            table
+                .columns
+                .get(column.pos_in_table)
+                .and_then(|c| c.collation)
+        });
+        program.emit_insn(Insn::Ne {
+            lhs: column_reg,
+            rhs: prev_reg + i,
+            target_pc: load_prev_labels[i],
+            flags: CmpInsFlags::default().null_eq(),
+            collation,
+        });
+    }
+    program.emit_int(num_key_columns as i64, changed_reg);
+    program.emit_insn(Insn::Goto { target_pc: push });
+    // Remember the columns of the entry from the first one that changed.
+    for (i, label) in load_prev_labels.into_iter().enumerate() {
+        program.preassign_label_to_next_insn(label);
+        program.emit_insn(Insn::Column {
+            cursor_id: index_cursor,
+            column: i,
+            dest: prev_reg + i,
+            default: None,
+        });
+    }
+    program.preassign_label_to_next_insn(push);
+    program.emit_insn(Insn::StatPush {
+        cursor_id: index_cursor,
+        changed_column_reg: changed_reg,
+    });
+    if let Some(limit_reg) = limit_reg {
+        program.emit_insn(Insn::DecrJumpZero {
+            reg: limit_reg,
+            target_pc: after_scan,
+        });
+    }
+    program.emit_insn(Insn::Next {
+        cursor_id: index_cursor,
+        pc_if_next: next_entry,
+    });
+
+    program.preassign_label_to_next_insn(after_scan);
+    // The PRIMARY KEY of a WITHOUT ROWID table is named after the table, like in SQLite.
+    let index_name = if is_primary_key {
+        table.name.clone()
+    } else {
+        index.name.clone()
+    };
+    program.emit_insn(Insn::String8 {
+        value: index_name,
+        dest: tbl_reg + 1,
+    });
+    program.emit_insn(Insn::StatGet { dest: tbl_reg + 2 });
+    emit_insert_stat_row(program, stat1_cursor, STAT1_TABLE_NAME, tbl_reg, 3);
+
+    if sample {
+        // The row of sqlite_stat4 is made of tbl, idx, neq, nlt, ndlt and sample.
+        let stat4_reg = program.alloc_registers(6);
+        program.emit_insn(Insn::Copy {
+            src_reg: tbl_reg,
+            dst_reg: stat4_reg,
+            extra_amount: 1,
+        });
+        let next_sample = program.allocate_label();
+        program.preassign_label_to_next_insn(next_sample);
+        program.emit_insn(Insn::StatSample {
+            dest: stat4_reg + 2,
+            pc_if_done: end,
+        });
+        emit_insert_stat_row(program, stat4_cursor, STAT4_TABLE_NAME, stat4_reg, 6);
+        program.emit_insn(Insn::Goto {
+            target_pc: next_sample,
+        });
+    }
+    program.preassign_label_to_next_insn(end);
+}
+
+fn emit_insert_stat_row(
+    program: &mut ProgramBuilder,
+    cursor: usize,
+    table_name: &str,
+    start_reg: usize,
+    count: usize,
+) {
+    let record_reg = program.alloc_register();
+    let rowid_reg = program.alloc_register();
     program.emit_insn(Insn::MakeRecord {
-        start_reg: tablename_reg,
-        count: 3,
+        start_reg,
+        count,
         dest_reg: record_reg,
         index_name: None,
         affinity_str: None,
     });
     program.emit_insn(Insn::NewRowid {
-        cursor: stat_cursor,
+        cursor,
         rowid_reg,
         prev_largest_reg: 0,
     });
-    // FIXME: SQLite sets OPFLAG_APPEND on the insert, but that's not supported in turso right now.
-    // SQLite doesn't emit the table name, but like... why not?
     program.emit_insn(Insn::Insert {
-        cursor: stat_cursor,
+        cursor,
         key_reg: rowid_reg,
         record_reg,
         flag: Default::default(),
-        table_name: "sqlite_stat1".to_string(),
+        table_name: table_name.to_string(),
     });
-    program.preassign_label_to_next_insn(after_insert);
-    // FIXME: Emit LoadAnalysis
-    // FIXME: Emit Expire
-    Ok(program)
 }
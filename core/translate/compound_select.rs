@@ -1,6 +1,8 @@
-use crate::schema::{Index, IndexColumn, Schema};
+use crate::schema::{Index, IndexColumn, PseudoCursorType, Schema};
+use crate::translate::collate::CollationSeq;
 use crate::translate::emitter::{emit_query, LimitCtx, TranslateCtx};
 use crate::translate::expr::translate_expr;
+use crate::translate::order_by::sorter_insert;
 use crate::translate::plan::{Plan, QueryDestination, SelectPlan};
 use crate::translate::result_row::try_fold_expr_to_i64;
 use crate::vdbe::builder::{CursorType, ProgramBuilder};
@@ -19,25 +21,56 @@ pub fn emit_program_for_compound_select(
     plan: Plan,
     schema: &Schema,
     syms: &SymbolTable,
+) -> crate::Result<()> {
+    let Plan::CompoundSelect { right_most, .. } = &plan else {
+        crate::bail_parse_error!("expected compound select plan");
+    };
+
+    let right_plan = right_most.clone();
+    // When a compound SELECT is part of a query that yields results to a coroutine (e.g. within an INSERT clause),
+    // we must allocate registers for the result columns to be yielded. Each subselect will then yield to
+    // the coroutine using the same set of registers, which must directly follow the yield register.
+    let reg_result_cols_start = match right_most.query_destination {
+        QueryDestination::CoroutineYield { .. } => {
+            Some(program.alloc_registers(right_most.result_columns.len()))
+        }
+        _ => None,
+    };
+    emit_compound_select_rows(program, plan, schema, syms, reg_result_cols_start)?;
+
+    program.result_columns = right_plan.result_columns;
+    program.table_references.extend(right_plan.table_references);
+
+    Ok(())
+}
+
+/// Emits the rows of a compound SELECT: as result rows, or yielded to the coroutine of its
+/// destination in the registers starting at `reg_result_cols_start`.
+pub fn emit_compound_select_rows(
+    program: &mut ProgramBuilder,
+    mut plan: Plan,
+    schema: &Schema,
+    syms: &SymbolTable,
+    reg_result_cols_start: Option<usize>,
 ) -> crate::Result<()> {
     let Plan::CompoundSelect {
         left: _left,
         right_most,
         limit,
         offset,
-        ..
-    } = &plan
+        order_by,
+    } = &mut plan
     else {
         crate::bail_parse_error!("expected compound select plan");
     };
-
-    let right_plan = right_most.clone();
     // Trivial exit on LIMIT 0
     if matches!(limit.as_ref().and_then(try_fold_expr_to_i64), Some(v) if v == 0) {
-        program.result_columns = right_plan.result_columns;
-        program.table_references.extend(right_plan.table_references);
         return Ok(());
     }
+    let yield_reg = match right_most.query_destination {
+        QueryDestination::CoroutineYield { yield_reg, .. } => Some(yield_reg),
+        _ => None,
+    };
 
     let right_most_ctx = TranslateCtx::new(
         program,
@@ -87,32 +120,211 @@ pub fn emit_program_for_compound_select(
         reg
     });
 
-    // When a compound SELECT is part of a query that yields results to a coroutine (e.g. within an INSERT clause),
-    // we must allocate registers for the result columns to be yielded. Each subselect will then yield to
-    // the coroutine using the same set of registers.
-    let (yield_reg, reg_result_cols_start) = match right_most.query_destination {
-        QueryDestination::CoroutineYield { yield_reg, .. } => {
-            let start_reg = program.alloc_registers(right_most.result_columns.len());
-            (Some(yield_reg), Some(start_reg))
-        }
-        _ => (None, None),
+    emit_explain!(program, true, "COMPOUND QUERY".to_owned());
+    match order_by.take() {
+        Some(order_by) => emit_sorted_compound_select(
+            program,
+            plan,
+            schema,
+            syms,
+            &order_by,
+            limit_ctx,
+            offset_reg,
+            yield_reg,
+            reg_result_cols_start,
+        )?,
+        None => emit_compound_select(
+            program,
+            plan,
+            schema,
+            syms,
+            limit_ctx,
+            offset_reg,
+            yield_reg,
+            reg_result_cols_start,
+        )?,
+    }
+    program.pop_current_parent_explain();
+
+    Ok(())
+}
+
+/// Emits bytecode for a compound SELECT statement with an ORDER BY clause.
+/// The compound SELECT runs as a coroutine, whose rows are inserted into a sorter keyed by
+/// the ORDER BY columns. The LIMIT and OFFSET apply to the rows read back from the sorter.
+#[allow(clippy::too_many_arguments)]
+fn emit_sorted_compound_select(
+    program: &mut ProgramBuilder,
+    mut plan: Plan,
+    schema: &Schema,
+    syms: &SymbolTable,
+    order_by: &[(usize, SortOrder, Option<CollationSeq>)],
+    limit_ctx: Option<LimitCtx>,
+    offset_reg: Option<usize>,
+    yield_reg: Option<usize>,
+    reg_result_cols_start: Option<usize>,
+) -> crate::Result<()> {
+    let Plan::CompoundSelect {
+        left,
+        right_most,
+        limit,
+        offset,
+        ..
+    } = &mut plan
+    else {
+        unreachable!()
     };
+    // The LIMIT and OFFSET are applied when reading from the sorter.
+    *limit = None;
+    *offset = None;
 
-    emit_explain!(program, true, "COMPOUND QUERY".to_owned());
+    let num_columns = right_most.result_columns.len();
+    let inner_yield_reg = program.alloc_register();
+    let inner_result_cols_start = program.alloc_registers(num_columns);
+    let coroutine_start_label = program.allocate_label();
+    for select in left
+        .iter_mut()
+        .map(|(plan, _)| plan)
+        .chain(std::iter::once(right_most))
+    {
+        select.query_destination = QueryDestination::CoroutineYield {
+            yield_reg: inner_yield_reg,
+            coroutine_implementation_start: coroutine_start_label,
+        };
+    }
+
+    let coroutine_end_label = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg: inner_yield_reg,
+        jump_on_definition: coroutine_end_label,
+        start_offset: coroutine_start_label,
+    });
+    program.preassign_label_to_next_insn(coroutine_start_label);
     emit_compound_select(
         program,
         plan,
         schema,
         syms,
-        limit_ctx,
-        offset_reg,
-        yield_reg,
-        reg_result_cols_start,
+        None,
+        None,
+        Some(inner_yield_reg),
+        Some(inner_result_cols_start),
     )?;
-    program.pop_current_parent_explain();
+    program.emit_insn(Insn::EndCoroutine {
+        yield_reg: inner_yield_reg,
+    });
+    program.preassign_label_to_next_insn(coroutine_end_label);
 
-    program.result_columns = right_plan.result_columns;
-    program.table_references.extend(right_plan.table_references);
+    // The sorter has the sort keys first, then the result columns.
+    let num_keys = order_by.len();
+    let sorter_column_count = num_keys + num_columns;
+    let sort_cursor = program.alloc_cursor_id(CursorType::Sorter);
+    program.emit_insn(Insn::SorterOpen {
+        cursor_id: sort_cursor,
+        columns: num_keys,
+        order: order_by.iter().map(|(_, order, _)| *order).collect(),
+        collations: order_by
+            .iter()
+            .map(|(_, _, collation)| *collation)
+            .collect(),
+    });
+
+    let label_sort = program.allocate_label();
+    let label_insert_loop = program.allocate_label();
+    let sorter_start_reg = program.alloc_registers(sorter_column_count);
+    let record_reg = program.alloc_register();
+    program.preassign_label_to_next_insn(label_insert_loop);
+    program.emit_insn(Insn::Yield {
+        yield_reg: inner_yield_reg,
+        end_offset: label_sort,
+    });
+    for (i, (column, _, _)) in order_by.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: inner_result_cols_start + column,
+            dst_reg: sorter_start_reg + i,
+            extra_amount: 0,
+        });
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: inner_result_cols_start,
+        dst_reg: sorter_start_reg + num_keys,
+        extra_amount: num_columns - 1,
+    });
+    sorter_insert(
+        program,
+        sorter_start_reg,
+        sorter_column_count,
+        sort_cursor,
+        record_reg,
+    );
+    program.emit_insn(Insn::Goto {
+        target_pc: label_insert_loop,
+    });
+    program.preassign_label_to_next_insn(label_sort);
+
+    emit_explain!(program, false, "USE TEMP B-TREE FOR ORDER BY".to_owned());
+    let label_end = program.allocate_label();
+    let label_sort_loop = program.allocate_label();
+    let label_sort_next = program.allocate_label();
+    let reg_sorter_data = program.alloc_register();
+    let pseudo_cursor = program.alloc_cursor_id(CursorType::Pseudo(PseudoCursorType {
+        column_count: sorter_column_count,
+    }));
+    program.emit_insn(Insn::OpenPseudo {
+        cursor_id: pseudo_cursor,
+        content_reg: reg_sorter_data,
+        num_fields: sorter_column_count,
+    });
+    program.emit_insn(Insn::SorterSort {
+        cursor_id: sort_cursor,
+        pc_if_empty: label_end,
+    });
+    program.preassign_label_to_next_insn(label_sort_loop);
+    if let Some(reg) = offset_reg {
+        program.emit_insn(Insn::IfPos {
+            reg,
+            target_pc: label_sort_next,
+            decrement_by: 1,
+        });
+    }
+    program.emit_insn(Insn::SorterData {
+        cursor_id: sort_cursor,
+        dest_reg: reg_sorter_data,
+        pseudo_cursor,
+    });
+    let cols_start_reg =
+        reg_result_cols_start.unwrap_or_else(|| program.alloc_registers(num_columns));
+    for i in 0..num_columns {
+        program.emit_insn(Insn::Column {
+            cursor_id: pseudo_cursor,
+            column: num_keys + i,
+            dest: cols_start_reg + i,
+            default: None,
+        });
+    }
+    if let Some(yield_reg) = yield_reg {
+        program.emit_insn(Insn::Yield {
+            yield_reg,
+            end_offset: BranchOffset::Offset(0),
+        });
+    } else {
+        program.emit_insn(Insn::ResultRow {
+            start_reg: cols_start_reg,
+            count: num_columns,
+        });
+    }
+    if let Some(limit_ctx) = limit_ctx {
+        program.emit_insn(Insn::DecrJumpZero {
+            reg: limit_ctx.reg_limit,
+            target_pc: label_end,
+        });
+    }
+    program.preassign_label_to_next_insn(label_sort_next);
+    program.emit_insn(Insn::SorterNext {
+        cursor_id: sort_cursor,
+        pc_if_next: label_sort_loop,
+    });
+    program.preassign_label_to_next_insn(label_end);
 
     Ok(())
 }
@@ -203,7 +415,7 @@ fn emit_compound_select(
                     } => (cursor_id, index.clone()),
                     _ => {
                         new_dedupe_index = true;
-                        create_dedupe_index(program, &right_most, schema)?
+                        create_dedupe_index(program, &right_most)?
                     }
                 };
                 plan.query_destination = QueryDestination::EphemeralIndex {
@@ -258,8 +470,7 @@ fn emit_compound_select(
                     target_cursor_id = Some(cursor_id);
                 }
 
-                let (left_cursor_id, left_index) =
-                    create_dedupe_index(program, &right_most, schema)?;
+                let (left_cursor_id, left_index) = create_dedupe_index(program, &right_most)?;
                 plan.query_destination = QueryDestination::EphemeralIndex {
                     cursor_id: left_cursor_id,
                     index: left_index.clone(),
@@ -283,8 +494,7 @@ fn emit_compound_select(
                     reg_result_cols_start,
                 )?;
 
-                let (right_cursor_id, right_index) =
-                    create_dedupe_index(program, &right_most, schema)?;
+                let (right_cursor_id, right_index) = create_dedupe_index(program, &right_most)?;
                 right_most.query_destination = QueryDestination::EphemeralIndex {
                     cursor_id: right_cursor_id,
                     index: right_index,
@@ -312,7 +522,7 @@ fn emit_compound_select(
                     } => (cursor_id, index),
                     _ => {
                         new_index = true;
-                        create_dedupe_index(program, &right_most, schema)?
+                        create_dedupe_index(program, &right_most)?
                     }
                 };
                 plan.query_destination = QueryDestination::EphemeralIndex {
@@ -374,12 +584,7 @@ fn emit_compound_select(
 fn create_dedupe_index(
     program: &mut ProgramBuilder,
     select: &SelectPlan,
-    schema: &Schema,
 ) -> crate::Result<(usize, Arc<Index>)> {
-    if !schema.indexes_enabled {
-        crate::bail_parse_error!("UNION OR INTERSECT or EXCEPT is not supported without indexes");
-    }
-
     let dedupe_index = Arc::new(Index {
         columns: select
             .result_columns
@@ -392,6 +597,7 @@ fn create_dedupe_index(
                 order: SortOrder::Asc,
                 pos_in_table: 0,
                 default: None,
+                expr: None,
                 collation: None, // FIXME: this should be inferred
             })
             .collect(),
@@ -401,6 +607,7 @@ fn create_dedupe_index(
         table_name: String::new(),
         unique: false,
         has_rowid: false,
+        primary_key_suffix: vec![],
         where_clause: None,
     });
     let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(dedupe_index.clone()));
@@ -1,22 +1,30 @@
 use crate::schema::Table;
-use crate::translate::emitter::emit_program;
+use crate::translate::emitter::{emit_program, OperationMode, Resolver};
 use crate::translate::expr::ParamState;
+use crate::translate::fkey::has_fks;
 use crate::translate::optimizer::optimize_plan;
 use crate::translate::plan::{DeletePlan, Operation, Plan};
-use crate::translate::planner::{parse_limit, parse_where};
+use crate::translate::planner::{check_indexed_by, parse_limit, parse_where, parse_with};
+use crate::translate::subquery::expr_has_subquery;
+use crate::translate::trigger::{has_triggers, translate_delete_from_view};
+use crate::translate::update::prepare_ephemeral_rowid_plan;
 use crate::util::normalize_ident;
-use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, TableRefIdCounter};
+use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts};
 use crate::{schema::Schema, Result, SymbolTable};
 use std::sync::Arc;
-use turso_parser::ast::{Expr, Limit, QualifiedName, ResultColumn};
+use turso_parser::ast::{Expr, Indexed, Limit, QualifiedName, ResultColumn, SortedColumn, With};
 
 use super::plan::{ColumnUsedMask, JoinedTable, TableReferences};
 
 #[allow(clippy::too_many_arguments)]
 pub fn translate_delete(
     schema: &Schema,
+    with: Option<With>,
     tbl_name: &QualifiedName,
+    indexed: Option<Indexed>,
+    database_id: usize,
     where_clause: Option<Box<Expr>>,
+    order_by: Vec<SortedColumn>,
     limit: Option<Limit>,
     returning: Vec<ResultColumn>,
     syms: &SymbolTable,
@@ -24,6 +32,24 @@ pub fn translate_delete(
     connection: &Arc<crate::Connection>,
 ) -> Result<ProgramBuilder> {
     let tbl_name = normalize_ident(tbl_name.name.as_str());
+    check_indexed_by(schema, &tbl_name, indexed.as_ref())?;
+
+    if let Some(view) = schema.get_view(&tbl_name) {
+        if !returning.is_empty() {
+            crate::bail_parse_error!("RETURNING is not supported for views");
+        }
+        let resolver = Resolver::new(schema, syms);
+        return translate_delete_from_view(
+            program,
+            &resolver,
+            connection,
+            view,
+            with,
+            where_clause,
+            order_by,
+            limit,
+        );
+    }
 
     // Check if this is a system table that should be protected from direct writes
     if crate::schema::is_system_table(&tbl_name) {
@@ -48,12 +74,17 @@ pub fn translate_delete(
     let result_columns = vec![];
 
     let mut delete_plan = prepare_delete_plan(
+        &mut program,
         schema,
+        syms,
+        with,
         tbl_name,
+        indexed,
+        database_id,
         where_clause,
+        order_by,
         limit,
         result_columns,
-        &mut program.table_reference_counter,
         connection,
     )?;
     optimize_plan(&mut delete_plan, schema)?;
@@ -70,13 +101,19 @@ pub fn translate_delete(
     Ok(program)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn prepare_delete_plan(
+    program: &mut ProgramBuilder,
     schema: &Schema,
+    syms: &SymbolTable,
+    with: Option<With>,
     tbl_name: String,
+    indexed: Option<Indexed>,
+    database_id: usize,
     where_clause: Option<Box<Expr>>,
-    limit: Option<Limit>,
+    order_by: Vec<SortedColumn>,
+    mut limit: Option<Limit>,
     result_columns: Vec<super::plan::ResultSetColumn>,
-    table_ref_counter: &mut TableRefIdCounter,
     connection: &Arc<crate::Connection>,
 ) -> Result<Plan> {
     let table = match schema.get_table(&tbl_name) {
@@ -97,29 +134,95 @@ pub fn prepare_delete_plan(
         crate::bail_parse_error!("Table is neither a virtual table nor a btree table");
     };
     let indexes = schema.get_indices(table.get_name()).cloned().collect();
+    let mut param_ctx = ParamState::default();
+    // The table must get the first internal id, since the DELETE loop is emitted with the
+    // default join order.
+    let internal_id = program.table_reference_counter.next();
+
+    // The CTEs of the WITH clause can be referred to by the subqueries of the WHERE clause,
+    // which are evaluated by the ephemeral plan.
+    let ctes = match with {
+        Some(with) => parse_with(
+            schema,
+            with,
+            syms,
+            &[],
+            &mut program.table_reference_counter,
+            connection,
+            &mut param_ctx,
+        )?,
+        None => vec![],
+    };
+
+    // With an ORDER BY clause, the rows to delete are chosen by the ephemeral plan, which sorts
+    // them and applies the LIMIT and OFFSET.
+    let ephemeral_limit = if order_by.is_empty() {
+        None
+    } else {
+        limit.take()
+    };
+
+    // Triggers and foreign key actions fired by the DELETE may modify the table, so the rows to
+    // delete are collected upfront instead of being deleted while the table is scanned.
+    let ephemeral_plan = if has_triggers(schema, &tbl_name, OperationMode::DELETE, None, None)
+        || has_fks(schema, connection, &tbl_name, None)
+        || where_clause.as_deref().is_some_and(expr_has_subquery)
+        || !order_by.is_empty()
+    {
+        prepare_ephemeral_rowid_plan(
+            program,
+            schema,
+            syms,
+            ctes,
+            table.clone(),
+            &tbl_name,
+            database_id,
+            indexed.clone(),
+            Operation::default_scan_for(&table),
+            None,
+            where_clause.as_deref(),
+            vec![],
+            order_by,
+            ephemeral_limit,
+            None,
+            connection,
+            &mut param_ctx,
+        )?
+    } else {
+        None
+    };
+
     let joined_tables = vec![JoinedTable {
         op: Operation::default_scan_for(&table),
         table,
         identifier: tbl_name,
-        internal_id: table_ref_counter.next(),
+        internal_id,
         join_info: None,
         col_used_mask: ColumnUsedMask::default(),
-        database_id: 0,
+        database_id,
+        // The hint applies to the search for the rows to delete, which the ephemeral plan does
+        // when there is one.
+        indexed: if ephemeral_plan.is_none() {
+            indexed
+        } else {
+            None
+        },
     }];
     let mut table_references = TableReferences::new(joined_tables, vec![]);
 
     let mut where_predicates = vec![];
-    let mut param_ctx = ParamState::default();
 
-    // Parse the WHERE clause
-    parse_where(
-        where_clause.as_deref(),
-        &mut table_references,
-        None,
-        &mut where_predicates,
-        connection,
-        &mut param_ctx,
-    )?;
+    if ephemeral_plan.is_none() {
+        // Parse the WHERE clause
+        parse_where(
+            where_clause.as_deref(),
+            &mut table_references,
+            None,
+            &mut where_predicates,
+            connection,
+            &mut param_ctx,
+        )?;
+    }
 
     // Parse the LIMIT/OFFSET clause
     let (resolved_limit, resolved_offset) = limit.map_or(Ok((None, None)), |mut l| {
@@ -135,6 +238,7 @@ pub fn prepare_delete_plan(
         offset: resolved_offset,
         contains_constant_false_condition: false,
         indexes,
+        ephemeral_plan,
     };
 
     Ok(Plan::Delete(plan))
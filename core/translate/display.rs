@@ -53,11 +53,11 @@ impl Display for Plan {
                 }
                 if let Some(order_by) = order_by {
                     writeln!(f, "ORDER BY:")?;
-                    for (expr, dir) in order_by {
+                    for (column, dir, _) in order_by {
                         writeln!(
                             f,
                             "  - {} {}",
-                            expr,
+                            column + 1,
                             if *dir == SortOrder::Asc {
                                 "ASC"
                             } else {
@@ -311,8 +311,11 @@ impl ToTokens for Plan {
                     s.append(TokenType::TK_BY, None)?;
 
                     s.comma(
-                        order_by.iter().map(|(expr, order)| ast::SortedColumn {
-                            expr: expr.clone().into(),
+                        order_by.iter().map(|(column, order, _)| ast::SortedColumn {
+                            expr: ast::Expr::Literal(ast::Literal::Numeric(
+                                (column + 1).to_string(),
+                            ))
+                            .into(),
                             order: Some(*order),
                             nulls: None,
                         }),
@@ -348,7 +351,7 @@ impl ToTokens for JoinedTable {
     fn to_tokens<S: TokenStream + ?Sized, C: ToSqlContext>(
         &self,
         s: &mut S,
-        _context: &C,
+        context: &C,
     ) -> Result<(), S::Error> {
         match &self.table {
             Table::BTree(..) | Table::Virtual(..) => {
@@ -361,11 +364,15 @@ impl ToTokens for JoinedTable {
             }
             Table::FromClauseSubquery(from_clause_subquery) => {
                 s.append(TokenType::TK_LP, None)?;
-                // Could possibly merge the contexts together here
-                from_clause_subquery.plan.to_tokens(
-                    s,
-                    &PlanContext(&[&from_clause_subquery.plan.table_references]),
-                )?;
+                if let Some(compound) = &from_clause_subquery.compound {
+                    compound.to_tokens(s, context)?;
+                } else {
+                    // Could possibly merge the contexts together here
+                    from_clause_subquery.plan.to_tokens(
+                        s,
+                        &PlanContext(&[&from_clause_subquery.plan.table_references]),
+                    )?;
+                }
                 s.append(TokenType::TK_RP, None)?;
 
                 s.append(TokenType::TK_AS, None)?;
@@ -5,7 +5,7 @@ use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use tracing::{instrument, Level};
-use turso_parser::ast::{self, Expr};
+use turso_parser::ast::{self, Expr, TriggerTime};
 
 use super::aggregation::emit_ungrouped_aggregation;
 use super::expr::translate_expr;
@@ -13,25 +13,33 @@ use super::group_by::{
     group_by_agg_phase, group_by_emit_row_phase, init_group_by, GroupByMetadata, GroupByRowSource,
 };
 use super::main_loop::{
-    close_loop, emit_loop, init_distinct, init_loop, open_loop, LeftJoinMetadata, LoopLabels,
+    close_loop, emit_loop, emit_unmatched_right_join_rows, init_distinct, init_loop, open_loop,
+    LeftJoinMetadata, LoopLabels, RightJoinMetadata,
 };
 use super::order_by::{emit_order_by, init_order_by, SortMetadata};
 use super::plan::{
-    Distinctness, JoinOrderMember, Operation, Scan, SelectPlan, TableReferences, UpdatePlan,
+    Distinctness, EvalAt, JoinOrderMember, Operation, Scan, SelectPlan, TableReferences, UpdatePlan,
 };
 use super::select::emit_simple_count;
-use super::subquery::emit_subqueries;
-use crate::error::SQLITE_CONSTRAINT_PRIMARYKEY;
+use super::subquery::{emit_non_from_clause_subquery, emit_subqueries};
+use crate::error::{SQLITE_CONSTRAINT_CHECK, SQLITE_CONSTRAINT_PRIMARYKEY};
 use crate::function::Func;
-use crate::schema::{BTreeTable, Column, Schema, Table};
+use crate::schema::{BTreeTable, Column, Index, Schema, Table};
 use crate::translate::compound_select::emit_program_for_compound_select;
 use crate::translate::expr::{
-    emit_returning_results, translate_expr_no_constant_opt, walk_expr_mut, NoConstantOptReason,
-    ReturningValueRegisters, WalkControl,
+    emit_returning_results, emit_table_column, translate_expr_no_constant_opt, walk_expr_mut,
+    NoConstantOptReason, ReturningValueRegisters, WalkControl,
 };
+use crate::translate::fkey::{emit_fk_actions, emit_fk_checks, has_fks};
+use crate::translate::insert::{emit_table_record, format_unique_violation_desc};
 use crate::translate::plan::{DeletePlan, JoinedTable, Plan, QueryDestination, Search};
 use crate::translate::planner::ROWID_STRS;
 use crate::translate::result_row::try_fold_expr_to_i64;
+use crate::translate::trigger::{emit_triggers, has_triggers, TriggerRowRegisters};
+use crate::translate::upsert::{
+    emit_replace_delete_row, emit_replace_delete_without_rowid_row,
+    emit_unique_probe_for_row_image, emit_unique_probe_for_without_rowid_row_image,
+};
 use crate::translate::values::emit_values;
 use crate::translate::window::{emit_window_results, init_window, WindowMetadata};
 use crate::util::{exprs_are_equivalent, normalize_ident};
@@ -140,6 +148,9 @@ pub struct TranslateCtx<'a> {
     /// mapping between table loop index and associated metadata (for left joins only)
     /// this metadata exists for the right table in a given left join
     pub meta_left_joins: Vec<Option<LeftJoinMetadata>>,
+    /// mapping between table loop index and associated metadata (for right joins only)
+    /// this metadata exists for the right table in a given right join
+    pub meta_right_joins: Vec<Option<RightJoinMetadata>>,
     pub resolver: Resolver<'a>,
     /// A list of expressions that are not aggregates, along with a flag indicating
     /// whether the expression should be included in the output for each group.
@@ -175,6 +186,7 @@ impl<'a> TranslateCtx<'a> {
             reg_result_cols_start: None,
             meta_group_by: None,
             meta_left_joins: (0..table_count).map(|_| None).collect(),
+            meta_right_joins: (0..table_count).map(|_| None).collect(),
             meta_sort: None,
             resolver: Resolver::new(schema, syms),
             non_aggregate_expressions: Vec::new(),
@@ -248,6 +260,16 @@ fn emit_program_for_select(
             return Ok(());
         }
     }
+    // When the query yields its results to a coroutine (e.g. within an INSERT clause), the
+    // consumer reads them from the registers right after the yield register, so allocate them
+    // before any FROM clause subquery allocates registers of its own.
+    if matches!(
+        plan.query_destination,
+        QueryDestination::CoroutineYield { .. }
+    ) && plan.values.is_empty()
+    {
+        t_ctx.reg_result_cols_start = Some(program.alloc_registers(plan.result_columns.len()));
+    }
     // Emit main parts of query
     emit_query(program, &mut plan, &mut t_ctx)?;
 
@@ -270,6 +292,21 @@ pub fn emit_query<'a>(
     // Emit subqueries first so the results can be read in the main query loop.
     emit_subqueries(program, t_ctx, &mut plan.table_references)?;
 
+    // Subqueries outside the FROM clause that do not depend on the rows of the main loop are
+    // evaluated before it.
+    for subquery in plan
+        .non_from_clause_subqueries
+        .iter_mut()
+        .filter(|subquery| subquery.eval_at(&plan.join_order) == EvalAt::BeforeLoop)
+    {
+        emit_non_from_clause_subquery(
+            program,
+            t_ctx.resolver.schema,
+            t_ctx.resolver.symbol_table,
+            subquery,
+        )?;
+    }
+
     init_limit(program, t_ctx, &plan.limit, &plan.offset);
 
     // No rows will be read from source table loops if there is a constant false condition eg. WHERE 0
@@ -353,6 +390,7 @@ pub fn emit_query<'a>(
         plan.group_by.as_ref(),
         OperationMode::SELECT,
         &plan.where_clause,
+        &plan.non_from_clause_subqueries,
     )?;
 
     if plan.is_simple_count() {
@@ -367,6 +405,7 @@ pub fn emit_query<'a>(
         &plan.table_references,
         &plan.join_order,
         &plan.where_clause,
+        &mut plan.non_from_clause_subqueries,
         None,
     )?;
 
@@ -382,6 +421,9 @@ pub fn emit_query<'a>(
         None,
     )?;
 
+    // Emit the unmatched rows of the right tables of RIGHT and FULL OUTER JOINs
+    emit_unmatched_right_join_rows(program, t_ctx, plan)?;
+
     program.preassign_label_to_next_insn(after_main_loop_label);
 
     let mut order_by_necessary =
@@ -440,7 +482,7 @@ fn emit_program_for_delete(
         }
     }
 
-    init_limit(program, &mut t_ctx, &plan.limit, &None);
+    init_limit(program, &mut t_ctx, &plan.limit, &plan.offset);
 
     // No rows will be read from source table loops if there is a constant false condition eg. WHERE 0
     let after_main_loop_label = program.allocate_label();
@@ -451,6 +493,27 @@ fn emit_program_for_delete(
         });
     }
 
+    let ephemeral_plan = plan.ephemeral_plan.take();
+    let temp_cursor_id = ephemeral_plan
+        .as_ref()
+        .map(|plan| match &plan.query_destination {
+            QueryDestination::EphemeralTable { cursor_id, .. }
+            | QueryDestination::EphemeralIndex { cursor_id, .. } => *cursor_id,
+            _ => unreachable!(),
+        });
+    if let Some(ephemeral_plan) = ephemeral_plan {
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id: temp_cursor_id.unwrap(),
+            is_table: matches!(
+                ephemeral_plan.query_destination,
+                QueryDestination::EphemeralTable { .. }
+            ),
+        });
+        program.incr_nesting();
+        emit_program_for_select(program, ephemeral_plan, schema, syms)?;
+        program.decr_nesting();
+    }
+
     // Initialize cursors and other resources needed for query execution
     init_loop(
         program,
@@ -460,6 +523,7 @@ fn emit_program_for_delete(
         None,
         OperationMode::DELETE,
         &plan.where_clause,
+        &[],
     )?;
 
     // Set up main query execution loop
@@ -469,7 +533,8 @@ fn emit_program_for_delete(
         &plan.table_references,
         &[JoinOrderMember::default()],
         &plan.where_clause,
-        None,
+        &mut [],
+        temp_cursor_id,
     )?;
 
     emit_delete_insns(
@@ -478,6 +543,7 @@ fn emit_program_for_delete(
         &mut t_ctx,
         &mut plan.table_references,
         &plan.result_columns,
+        temp_cursor_id,
     )?;
 
     // Clean up and close the main execution loop
@@ -486,7 +552,7 @@ fn emit_program_for_delete(
         &mut t_ctx,
         &plan.table_references,
         &[JoinOrderMember::default()],
-        None,
+        temp_cursor_id,
     )?;
     program.preassign_label_to_next_insn(after_main_loop_label);
 
@@ -496,12 +562,84 @@ fn emit_program_for_delete(
     Ok(())
 }
 
+/// The registers identifying the row being changed: its rowid, or the PRIMARY KEY columns of a
+/// WITHOUT ROWID table.
+#[derive(Debug, Clone, Copy)]
+struct RowKey {
+    start_reg: usize,
+    num_regs: usize,
+    is_rowid: bool,
+}
+
+impl RowKey {
+    /// Reads the PRIMARY KEY of a WITHOUT ROWID table from the ephemeral index the keys were
+    /// collected into, or from the table cursor. For rowid tables `rowid_reg` already holds the key.
+    fn emit(
+        program: &mut ProgramBuilder,
+        rowid_reg: usize,
+        primary_key: Option<&Index>,
+        temp_cursor_id: Option<CursorID>,
+        table_cursor_id: CursorID,
+    ) -> Self {
+        let Some(primary_key) = primary_key else {
+            return Self {
+                start_reg: rowid_reg,
+                num_regs: 1,
+                is_rowid: true,
+            };
+        };
+        let num_regs = primary_key.columns.len();
+        let start_reg = program.alloc_registers(num_regs);
+        for (i, column) in primary_key.columns.iter().enumerate() {
+            match temp_cursor_id {
+                Some(temp_cursor_id) => {
+                    program.emit_column_or_rowid(temp_cursor_id, i, start_reg + i)
+                }
+                None => program.emit_column_or_rowid(
+                    table_cursor_id,
+                    column.pos_in_table,
+                    start_reg + i,
+                ),
+            }
+        }
+        Self {
+            start_reg,
+            num_regs,
+            is_rowid: false,
+        }
+    }
+
+    /// Positions `cursor_id` on the row, jumping to `target_pc` if it does not exist.
+    fn emit_seek(
+        &self,
+        program: &mut ProgramBuilder,
+        cursor_id: CursorID,
+        target_pc: BranchOffset,
+    ) {
+        if self.is_rowid {
+            program.emit_insn(Insn::NotExists {
+                cursor: cursor_id,
+                rowid_reg: self.start_reg,
+                target_pc,
+            });
+        } else {
+            program.emit_insn(Insn::NotFound {
+                cursor_id,
+                target_pc,
+                record_reg: self.start_reg,
+                num_regs: self.num_regs,
+            });
+        }
+    }
+}
+
 fn emit_delete_insns(
     connection: &Arc<Connection>,
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
     table_references: &mut TableReferences,
     result_columns: &[super::plan::ResultSetColumn],
+    temp_cursor_id: Option<CursorID>,
 ) -> Result<()> {
     // we can either use this obviously safe raw pointer or we can clone it
     let table_reference: *const JoinedTable = table_references.joined_tables().first().unwrap();
@@ -530,9 +668,108 @@ fn emit_delete_insns(
     // Emit the instructions to delete the row
     let key_reg = program.alloc_register();
     program.emit_insn(Insn::RowId {
-        cursor_id: main_table_cursor_id,
+        cursor_id: temp_cursor_id.unwrap_or(main_table_cursor_id),
         dest: key_reg,
     });
+    // Rows of a WITHOUT ROWID table are identified by their PRIMARY KEY instead
+    let primary_key = unsafe { &*table_reference }
+        .btree()
+        .filter(|btree| !btree.has_rowid)
+        .map(|btree| Index::without_rowid_primary_key(&btree));
+    if primary_key.is_some() && t_ctx.cdc_cursor_id.is_some() {
+        bail_parse_error!("capturing changes of WITHOUT ROWID tables is not supported");
+    }
+    let row_key = RowKey::emit(
+        program,
+        key_reg,
+        primary_key.as_ref(),
+        temp_cursor_id,
+        main_table_cursor_id,
+    );
+
+    let has_delete_triggers = unsafe { &*table_reference }.virtual_table().is_none()
+        && has_triggers(
+            t_ctx.resolver.schema,
+            table_name,
+            OperationMode::DELETE,
+            None,
+            None,
+        );
+    // Jumped to when the row is no longer there or a trigger executed RAISE(IGNORE)
+    let skip_row_label = if temp_cursor_id.is_some() || has_delete_triggers {
+        Some(program.allocate_label())
+    } else {
+        None
+    };
+    // When iterating over the rowids collected in the ephemeral table, the table cursor
+    // must be positioned at the row to delete.
+    if temp_cursor_id.is_some() {
+        row_key.emit_seek(program, main_table_cursor_id, skip_row_label.unwrap());
+    }
+
+    if let Some(offset) = t_ctx.reg_offset {
+        program.emit_insn(Insn::IfPos {
+            reg: offset,
+            target_pc: t_ctx.labels_main_loop.first().unwrap().next,
+            decrement_by: 1,
+        });
+    }
+
+    let has_delete_fks = unsafe { &*table_reference }.virtual_table().is_none()
+        && has_fks(t_ctx.resolver.schema, connection, table_name, None);
+
+    // The OLD image of the row is needed by the triggers and the foreign key checks.
+    let trigger_regs = if has_delete_triggers || has_delete_fks {
+        let table_columns = unsafe { &*table_reference }.columns();
+        let regs = TriggerRowRegisters::alloc(program, table_columns.len());
+        regs.emit_old_from_cursor(program, main_table_cursor_id);
+        if let Some(btree) = unsafe { &*table_reference }.btree() {
+            emit_generated_columns(
+                program,
+                &btree,
+                regs.old_columns(),
+                regs.old_rowid(),
+                &t_ctx.resolver,
+            )?;
+        }
+        regs.emit_null_new(program);
+        if has_triggers(
+            t_ctx.resolver.schema,
+            table_name,
+            OperationMode::DELETE,
+            Some(TriggerTime::Before),
+            None,
+        ) {
+            emit_triggers(
+                program,
+                &t_ctx.resolver,
+                connection,
+                table_name,
+                table_columns,
+                OperationMode::DELETE,
+                TriggerTime::Before,
+                None,
+                regs,
+                skip_row_label.unwrap(),
+            )?;
+            // The triggers may have modified the table, so reposition the cursor. If they
+            // deleted the row, there is nothing left to delete.
+            row_key.emit_seek(program, main_table_cursor_id, skip_row_label.unwrap());
+        }
+        if has_delete_fks {
+            emit_fk_checks(
+                program,
+                t_ctx.resolver.schema,
+                table_name,
+                Some(regs.old_image()),
+                None,
+                None,
+            )?;
+        }
+        Some(regs)
+    } else {
+        None
+    };
 
     if unsafe { &*table_reference }.virtual_table().is_some() {
         let conflict_action = 0u16;
@@ -600,24 +837,49 @@ fn emit_delete_insns(
             } else {
                 None
             };
-            let num_regs = index.columns.len() + 1;
+            let num_regs = if index.has_rowid {
+                index.columns.len() + 1
+            } else {
+                index.columns.len() + index.primary_key_suffix.len()
+            };
             let start_reg = program.alloc_registers(num_regs);
-            // Emit columns that are part of the index
-            index
+            // Emit columns that are part of the index, followed by the PRIMARY KEY columns that
+            // identify the row in an index of a WITHOUT ROWID table
+            for (reg_offset, column_index) in index
                 .columns
                 .iter()
+                .chain(index.primary_key_suffix.iter())
                 .enumerate()
-                .for_each(|(reg_offset, column_index)| {
-                    program.emit_column_or_rowid(
+            {
+                match column_index.bind_expr(&unsafe { &*table_reference }.table, internal_id) {
+                    Some(expr) => {
+                        translate_expr_no_constant_opt(
+                            program,
+                            Some(table_references),
+                            &expr,
+                            start_reg + reg_offset,
+                            &t_ctx.resolver,
+                            NoConstantOptReason::RegisterReuse,
+                        )?;
+                    }
+                    None => emit_table_column(
+                        program,
+                        &unsafe { &*table_reference }
+                            .btree()
+                            .expect("index of a btree table"),
                         main_table_cursor_id,
                         column_index.pos_in_table,
                         start_reg + reg_offset,
-                    );
+                        &t_ctx.resolver,
+                    )?,
+                }
+            }
+            if index.has_rowid {
+                program.emit_insn(Insn::RowId {
+                    cursor_id: main_table_cursor_id,
+                    dest: start_reg + num_regs - 1,
                 });
-            program.emit_insn(Insn::RowId {
-                cursor_id: main_table_cursor_id,
-                dest: start_reg + num_regs - 1,
-            });
+            }
             program.emit_insn(Insn::IdxDelete {
                 start_reg,
                 num_regs,
@@ -674,8 +936,21 @@ fn emit_delete_insns(
             let columns_start_reg = program.alloc_registers(cols_len);
 
             // Read all column values from the row to be deleted
-            for (i, _column) in unsafe { &*table_reference }.columns().iter().enumerate() {
-                program.emit_column_or_rowid(main_table_cursor_id, i, columns_start_reg + i);
+            let btree = unsafe { &*table_reference }.btree();
+            for i in 0..cols_len {
+                match &btree {
+                    Some(btree) => emit_table_column(
+                        program,
+                        btree,
+                        main_table_cursor_id,
+                        i,
+                        columns_start_reg + i,
+                        &t_ctx.resolver,
+                    )?,
+                    None => {
+                        program.emit_column_or_rowid(main_table_cursor_id, i, columns_start_reg + i)
+                    }
+                }
             }
 
             // Emit RETURNING results using the values we just read
@@ -693,7 +968,12 @@ fn emit_delete_insns(
             table_name: table_name.to_string(),
         });
 
-        if let Some(index) = iteration_index {
+        // The PRIMARY KEY of a WITHOUT ROWID table is the table itself, already deleted above
+        if let Some(index) = iteration_index.filter(|index| {
+            unsafe { &*table_reference }
+                .btree()
+                .is_none_or(|btree| !index.is_without_rowid_primary_key_of(&btree))
+        }) {
             let iteration_index_cursor =
                 program.resolve_cursor_id(&CursorKey::index(internal_id, index.clone()));
             program.emit_insn(Insn::Delete {
@@ -701,6 +981,34 @@ fn emit_delete_insns(
                 table_name: index.name.clone(),
             });
         }
+
+        if let Some(regs) = trigger_regs {
+            if has_delete_fks {
+                emit_fk_actions(
+                    program,
+                    &t_ctx.resolver,
+                    connection,
+                    table_name,
+                    OperationMode::DELETE,
+                    None,
+                    regs,
+                )?;
+            }
+            let after_triggers_label = program.allocate_label();
+            emit_triggers(
+                program,
+                &t_ctx.resolver,
+                connection,
+                table_name,
+                unsafe { &*table_reference }.columns(),
+                OperationMode::DELETE,
+                TriggerTime::After,
+                None,
+                regs,
+                after_triggers_label,
+            )?;
+            program.preassign_label_to_next_insn(after_triggers_label);
+        }
     }
     if let Some(limit_ctx) = t_ctx.limit_ctx {
         program.emit_insn(Insn::DecrJumpZero {
@@ -709,6 +1017,10 @@ fn emit_delete_insns(
         })
     }
 
+    if let Some(label) = skip_row_label {
+        program.preassign_label_to_next_insn(label);
+    }
+
     Ok(())
 }
 
@@ -747,16 +1059,20 @@ fn emit_program_for_update(
     }
 
     let ephemeral_plan = plan.ephemeral_plan.take();
-    let temp_cursor_id = ephemeral_plan.as_ref().map(|plan| {
-        let QueryDestination::EphemeralTable { cursor_id, .. } = &plan.query_destination else {
-            unreachable!()
-        };
-        *cursor_id
-    });
+    let temp_cursor_id = ephemeral_plan
+        .as_ref()
+        .map(|plan| match &plan.query_destination {
+            QueryDestination::EphemeralTable { cursor_id, .. }
+            | QueryDestination::EphemeralIndex { cursor_id, .. } => *cursor_id,
+            _ => unreachable!(),
+        });
     if let Some(ephemeral_plan) = ephemeral_plan {
         program.emit_insn(Insn::OpenEphemeral {
             cursor_id: temp_cursor_id.unwrap(),
-            is_table: true,
+            is_table: matches!(
+                ephemeral_plan.query_destination,
+                QueryDestination::EphemeralTable { .. }
+            ),
         });
         program.incr_nesting();
         emit_program_for_select(program, ephemeral_plan, schema, syms)?;
@@ -772,26 +1088,23 @@ fn emit_program_for_update(
         None,
         OperationMode::UPDATE,
         &plan.where_clause,
+        &[],
     )?;
 
     // Prepare index cursors
     let mut index_cursors = Vec::with_capacity(plan.indexes_to_update.len());
+    let target_table = plan.table_references.joined_tables().first().unwrap();
     for index in &plan.indexes_to_update {
-        let index_cursor = if let Some(cursor) = program.resolve_cursor_id_safe(&CursorKey::index(
-            plan.table_references
-                .joined_tables()
-                .first()
-                .unwrap()
-                .internal_id,
-            index.clone(),
-        )) {
+        let index_cursor = if let Some(cursor) = program
+            .resolve_cursor_id_safe(&CursorKey::index(target_table.internal_id, index.clone()))
+        {
             cursor
         } else {
             let cursor = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
             program.emit_insn(Insn::OpenWrite {
                 cursor_id: cursor,
                 root_page: RegisterOrLiteral::Literal(index.root_page),
-                db: 0,
+                db: target_table.database_id,
             });
             cursor
         };
@@ -806,6 +1119,7 @@ fn emit_program_for_update(
         &plan.table_references,
         &[JoinOrderMember::default()],
         &plan.where_clause,
+        &mut [],
         temp_cursor_id,
     )?;
 
@@ -888,6 +1202,27 @@ fn emit_update_insns(
         cursor_id: temp_cursor_id.unwrap_or(cursor_id),
         dest: beg,
     });
+    // Rows of a WITHOUT ROWID table are identified by their PRIMARY KEY instead
+    let primary_key = unsafe { &*table_ref }
+        .btree()
+        .filter(|btree| !btree.has_rowid)
+        .map(|btree| Index::without_rowid_primary_key(&btree));
+    if primary_key.is_some() && t_ctx.cdc_cursor_id.is_some() {
+        bail_parse_error!("capturing changes of WITHOUT ROWID tables is not supported");
+    }
+    let row_key = RowKey::emit(
+        program,
+        beg,
+        primary_key.as_ref(),
+        temp_cursor_id,
+        cursor_id,
+    );
+    // The clustered PRIMARY KEY index is the table cursor itself
+    let index = index.filter(|(index, _)| {
+        unsafe { &*table_ref }
+            .btree()
+            .is_none_or(|btree| !index.is_without_rowid_primary_key_of(&btree))
+    });
 
     // Check if rowid was provided (through INTEGER PRIMARY KEY as a rowid alias)
     let rowid_alias_index = unsafe { &*table_ref }
@@ -908,19 +1243,56 @@ fn emit_update_insns(
         None
     };
 
-    let check_rowid_not_exists_label = if has_user_provided_rowid {
+    // Names of the columns assigned by the SET clause, which decide the UPDATE OF triggers to fire
+    let updated_columns = plan
+        .set_clauses
+        .iter()
+        .filter_map(|(idx, _)| {
+            unsafe { &*table_ref }.columns()[*idx]
+                .name
+                .as_deref()
+                .map(normalize_ident)
+        })
+        .collect::<Vec<_>>();
+    let has_update_triggers = !is_virtual
+        && has_triggers(
+            t_ctx.resolver.schema,
+            unsafe { &*table_ref }.table.get_name(),
+            OperationMode::UPDATE,
+            None,
+            Some(&updated_columns),
+        );
+
+    let has_update_fks = !is_virtual
+        && has_fks(
+            t_ctx.resolver.schema,
+            connection,
+            unsafe { &*table_ref }.table.get_name(),
+            Some(&updated_columns),
+        );
+
+    // UPDATE OR IGNORE skips the rows whose new values violate a constraint, and UPDATE OR
+    // REPLACE skips the rows it deleted because an earlier row conflicted with them.
+    let or_conflict = plan.or_conflict.unwrap_or(ast::ResolveType::Abort);
+    let resolves_conflicts = matches!(
+        or_conflict,
+        ast::ResolveType::Ignore | ast::ResolveType::Replace
+    );
+    let check_rowid_not_exists_label = if has_user_provided_rowid
+        || temp_cursor_id.is_some()
+        || has_update_triggers
+        || resolves_conflicts
+    {
         Some(program.allocate_label())
     } else {
         None
     };
 
-    if has_user_provided_rowid {
-        program.emit_insn(Insn::NotExists {
-            cursor: cursor_id,
-            rowid_reg: beg,
-            target_pc: check_rowid_not_exists_label.unwrap(),
-        });
-    } else {
+    // When iterating over the rowids collected in the ephemeral table, the table cursor
+    // must be positioned at the row to update.
+    if has_user_provided_rowid || temp_cursor_id.is_some() {
+        row_key.emit_seek(program, cursor_id, check_rowid_not_exists_label.unwrap());
+    } else if primary_key.is_none() {
         // if no rowid, we're done
         program.emit_insn(Insn::IsNull {
             reg: beg,
@@ -928,6 +1300,47 @@ fn emit_update_insns(
         });
     }
 
+    // The new values of an UPDATE ... FROM were evaluated by the join of the ephemeral plan,
+    // and are stored along with the rowid, or after the PRIMARY KEY of a WITHOUT ROWID table.
+    if let Some(start_reg) = plan.from_values_start_reg {
+        let temp_cursor_id = temp_cursor_id.expect("UPDATE ... FROM must use an ephemeral table");
+        let values_offset = if row_key.is_rowid {
+            0
+        } else {
+            // A row matched by several rows of the join has an entry for each set of new values,
+            // next to each other. Only the first one is used.
+            let prev_key_reg = program.alloc_registers(row_key.num_regs);
+            let new_key_label = program.allocate_label();
+            for i in 0..row_key.num_regs {
+                program.emit_insn(Insn::Ne {
+                    lhs: row_key.start_reg + i,
+                    rhs: prev_key_reg + i,
+                    target_pc: new_key_label,
+                    flags: CmpInsFlags::default().null_eq(),
+                    collation: program.curr_collation(),
+                });
+            }
+            program.emit_insn(Insn::Goto {
+                target_pc: loop_labels.next,
+            });
+            program.preassign_label_to_next_insn(new_key_label);
+            program.emit_insn(Insn::Copy {
+                src_reg: row_key.start_reg,
+                dst_reg: prev_key_reg,
+                extra_amount: row_key.num_regs - 1,
+            });
+            row_key.num_regs
+        };
+        for i in 0..plan.set_clauses.len() {
+            program.emit_insn(Insn::Column {
+                cursor_id: temp_cursor_id,
+                column: values_offset + i,
+                dest: start_reg + i,
+                default: None,
+            });
+        }
+    }
+
     if is_virtual {
         program.emit_insn(Insn::Copy {
             src_reg: beg,
@@ -987,8 +1400,34 @@ fn emit_update_insns(
                     target_reg,
                     &t_ctx.resolver,
                 )?;
-                if table_column.notnull {
+                if table_column.notnull || (table_column.primary_key && primary_key.is_some()) {
                     use crate::error::SQLITE_CONSTRAINT_NOTNULL;
+                    match or_conflict {
+                        ast::ResolveType::Ignore => program.emit_insn(Insn::IsNull {
+                            reg: target_reg,
+                            target_pc: check_rowid_not_exists_label.unwrap(),
+                        }),
+                        // REPLACE stores the default value instead of NULL, if there is one
+                        ast::ResolveType::Replace => {
+                            if let Some(default) = &table_column.default {
+                                let not_null_label = program.allocate_label();
+                                program.emit_insn(Insn::NotNull {
+                                    reg: target_reg,
+                                    target_pc: not_null_label,
+                                });
+                                translate_expr_no_constant_opt(
+                                    program,
+                                    None,
+                                    default,
+                                    target_reg,
+                                    &t_ctx.resolver,
+                                    NoConstantOptReason::RegisterReuse,
+                                )?;
+                                program.preassign_label_to_next_insn(not_null_label);
+                            }
+                        }
+                        _ => {}
+                    }
                     program.emit_insn(Insn::HaltIfNull {
                         target_reg,
                         err_code: SQLITE_CONSTRAINT_NOTNULL,
@@ -1070,6 +1509,141 @@ fn emit_update_insns(
         }
     }
 
+    if let Some(btree) = unsafe { &*table_ref }.btree() {
+        emit_generated_columns(
+            program,
+            &btree,
+            start,
+            rowid_set_clause_reg.unwrap_or(beg),
+            &t_ctx.resolver,
+        )?;
+    }
+
+    // The OLD and NEW images of the row are needed by the triggers and the foreign key checks.
+    let trigger_regs = if has_update_triggers || has_update_fks {
+        let table_columns = unsafe { &*table_ref }.columns();
+        let regs = TriggerRowRegisters::alloc(program, col_len);
+        regs.emit_old_from_cursor(program, cursor_id);
+        if let Some(btree) = unsafe { &*table_ref }.btree() {
+            emit_generated_columns(
+                program,
+                &btree,
+                regs.old_columns(),
+                regs.old_rowid(),
+                &t_ctx.resolver,
+            )?;
+        }
+        regs.emit_new_from_registers(
+            program,
+            rowid_set_clause_reg.unwrap_or(beg),
+            start,
+            table_columns,
+        );
+        if has_triggers(
+            t_ctx.resolver.schema,
+            table_name,
+            OperationMode::UPDATE,
+            Some(TriggerTime::Before),
+            Some(&updated_columns),
+        ) {
+            let skip_row_label = check_rowid_not_exists_label.unwrap();
+            emit_triggers(
+                program,
+                &t_ctx.resolver,
+                connection,
+                table_name,
+                table_columns,
+                OperationMode::UPDATE,
+                TriggerTime::Before,
+                Some(&updated_columns),
+                regs,
+                skip_row_label,
+            )?;
+            // The triggers may have modified the table, so reposition the cursor. If they
+            // deleted the row, there is nothing left to update.
+            row_key.emit_seek(program, cursor_id, skip_row_label);
+        }
+        if has_update_fks {
+            emit_fk_checks(
+                program,
+                t_ctx.resolver.schema,
+                table_name,
+                Some(regs.old_image()),
+                Some(regs.new_image()),
+                Some(&updated_columns),
+            )?;
+        }
+        Some(regs)
+    } else {
+        None
+    };
+
+    if let Some(btree) = unsafe { &*table_ref }.btree() {
+        // UPDATE OR IGNORE skips the rows that violate a CHECK constraint. For REPLACE, like in
+        // SQLite, a violation aborts the statement.
+        let skip_label = matches!(or_conflict, ast::ResolveType::Ignore)
+            .then(|| check_rowid_not_exists_label.unwrap());
+        // The generated columns computed from the updated columns change as well.
+        let changed_columns = btree
+            .generated_columns_depending_on(
+                &plan
+                    .set_clauses
+                    .iter()
+                    .map(|(idx, _)| *idx)
+                    .collect::<Vec<_>>(),
+            )
+            .into_iter()
+            .filter_map(|pos| btree.columns[pos].name.clone())
+            .chain(updated_columns.iter().cloned())
+            .collect::<Vec<_>>();
+        emit_check_constraints(
+            program,
+            connection,
+            &btree,
+            Some(&changed_columns),
+            start,
+            rowid_set_clause_reg.unwrap_or(beg),
+            skip_label,
+            &t_ctx.resolver,
+        )?;
+    }
+
+    if resolves_conflicts && !is_virtual {
+        let indexes = plan
+            .indexes_to_update
+            .iter()
+            .zip(&index_cursors)
+            .map(|(index, (idx_cursor_id, _))| (index.as_ref(), *idx_cursor_id))
+            .collect::<Vec<_>>();
+        match &primary_key {
+            Some(primary_key) => emit_update_without_rowid_conflict_resolution(
+                program,
+                &unsafe { &*table_ref }.table,
+                primary_key,
+                or_conflict,
+                cursor_id,
+                row_key,
+                start,
+                &plan.set_clauses,
+                &indexes,
+                check_rowid_not_exists_label.unwrap(),
+                &t_ctx.resolver,
+            )?,
+            None => emit_update_conflict_resolution(
+                program,
+                &unsafe { &*table_ref }.table,
+                or_conflict,
+                cursor_id,
+                row_key,
+                rowid_set_clause_reg,
+                start,
+                &indexes,
+                check_rowid_not_exists_label.unwrap(),
+                &t_ctx.resolver,
+            )?,
+        }
+    }
+
     for (index, (idx_cursor_id, record_reg)) in plan.indexes_to_update.iter().zip(&index_cursors) {
         // We need to know whether or not the OLD values satisfied the predicate on the
         // partial index, so we can know whether or not to delete the old index entry,
@@ -1139,19 +1713,47 @@ fn emit_update_insns(
         }
 
         // Delete old index entry
-        let num_regs = index.columns.len() + 1;
+        let num_regs = if index.has_rowid {
+            index.columns.len() + 1
+        } else {
+            index.columns.len() + index.primary_key_suffix.len()
+        };
         let delete_start_reg = program.alloc_registers(num_regs);
-        for (reg_offset, column_index) in index.columns.iter().enumerate() {
-            program.emit_column_or_rowid(
+        for (reg_offset, column_index) in index
+            .columns
+            .iter()
+            .chain(index.primary_key_suffix.iter())
+            .enumerate()
+        {
+            match column_index.bind_expr(&unsafe { &*table_ref }.table, internal_id) {
+                Some(expr) => {
+                    translate_expr_no_constant_opt(
+                        program,
+                        Some(&plan.table_references),
+                        &expr,
+                        delete_start_reg + reg_offset,
+                        &t_ctx.resolver,
+                        NoConstantOptReason::RegisterReuse,
+                    )?;
+                }
+                None => emit_table_column(
+                    program,
+                    &unsafe { &*table_ref }
+                        .btree()
+                        .expect("index of a btree table"),
+                    cursor_id,
+                    column_index.pos_in_table,
+                    delete_start_reg + reg_offset,
+                    &t_ctx.resolver,
+                )?,
+            }
+        }
+        if index.has_rowid {
+            program.emit_insn(Insn::RowId {
                 cursor_id,
-                column_index.pos_in_table,
-                delete_start_reg + reg_offset,
-            );
+                dest: delete_start_reg + num_regs - 1,
+            });
         }
-        program.emit_insn(Insn::RowId {
-            cursor_id,
-            dest: delete_start_reg + num_regs - 1,
-        });
         program.emit_insn(Insn::IdxDelete {
             start_reg: delete_start_reg,
             num_regs,
@@ -1177,10 +1779,34 @@ fn emit_update_insns(
 
         // Build new index entry
         let num_cols = index.columns.len();
-        let idx_start_reg = program.alloc_registers(num_cols + 1);
+        let idx_start_reg = program.alloc_registers(num_regs);
         let rowid_reg = rowid_set_clause_reg.unwrap_or(beg);
 
-        for (i, col) in index.columns.iter().enumerate() {
+        for (i, col) in index
+            .columns
+            .iter()
+            .chain(index.primary_key_suffix.iter())
+            .enumerate()
+        {
+            if let Some(expr) = &col.expr {
+                // Evaluate the expression of the index column on the new values
+                let mut new_expr = expr.as_ref().clone();
+                rewrite_where_for_update_registers(
+                    &mut new_expr,
+                    unsafe { &*table_ref }.columns(),
+                    start,
+                    rowid_reg,
+                )?;
+                translate_expr_no_constant_opt(
+                    program,
+                    None,
+                    &new_expr,
+                    idx_start_reg + i,
+                    &t_ctx.resolver,
+                    NoConstantOptReason::RegisterReuse,
+                )?;
+                continue;
+            }
             let col_in_table = unsafe { &*table_ref }
                 .columns()
                 .get(col.pos_in_table)
@@ -1196,15 +1822,17 @@ fn emit_update_insns(
             });
         }
         // last register is the rowid
-        program.emit_insn(Insn::Copy {
-            src_reg: rowid_reg,
-            dst_reg: idx_start_reg + num_cols,
-            extra_amount: 0,
-        });
+        if index.has_rowid {
+            program.emit_insn(Insn::Copy {
+                src_reg: rowid_reg,
+                dst_reg: idx_start_reg + num_cols,
+                extra_amount: 0,
+            });
+        }
 
         program.emit_insn(Insn::MakeRecord {
             start_reg: idx_start_reg,
-            count: num_cols + 1,
+            count: num_regs,
             dest_reg: *record_reg,
             index_name: Some(index.name.clone()),
             affinity_str: None,
@@ -1215,11 +1843,7 @@ fn emit_update_insns(
             let aff = index
                 .columns
                 .iter()
-                .map(|ic| {
-                    unsafe { &*table_ref }.columns()[ic.pos_in_table]
-                        .affinity()
-                        .aff_mask()
-                })
+                .map(|ic| ic.affinity(unsafe { &*table_ref }.columns()).aff_mask())
                 .collect::<String>();
             program.emit_insn(Insn::Affinity {
                 start_reg: idx_start_reg,
@@ -1235,33 +1859,60 @@ fn emit_update_insns(
                 num_regs: num_cols,
             });
 
-            let idx_rowid_reg = program.alloc_register();
-            program.emit_insn(Insn::IdxRowId {
-                cursor_id: *idx_cursor_id,
-                dest: idx_rowid_reg,
-            });
-
             // Skip over the UNIQUE constraint failure if the existing row is the one that we are currently changing
-            program.emit_insn(Insn::Eq {
-                lhs: beg,
-                rhs: idx_rowid_reg,
-                target_pc: constraint_check,
-                flags: CmpInsFlags::default(),
-                collation: program.curr_collation(),
-            });
+            if index.has_rowid {
+                let idx_rowid_reg = program.alloc_register();
+                program.emit_insn(Insn::IdxRowId {
+                    cursor_id: *idx_cursor_id,
+                    dest: idx_rowid_reg,
+                });
+                program.emit_insn(Insn::Eq {
+                    lhs: beg,
+                    rhs: idx_rowid_reg,
+                    target_pc: constraint_check,
+                    flags: CmpInsFlags::default(),
+                    collation: program.curr_collation(),
+                });
+            } else {
+                // In a WITHOUT ROWID table the row is identified by the PRIMARY KEY stored in the
+                // index entry
+                let conflict_label = program.allocate_label();
+                let btree = unsafe { &*table_ref }
+                    .btree()
+                    .expect("index of a btree table");
+                let pk_reg = program.alloc_register();
+                for (i, pos) in index.primary_key_positions(&btree).into_iter().enumerate() {
+                    program.emit_column_or_rowid(*idx_cursor_id, pos, pk_reg);
+                    program.emit_insn(Insn::Ne {
+                        lhs: row_key.start_reg + i,
+                        rhs: pk_reg,
+                        target_pc: conflict_label,
+                        flags: CmpInsFlags::default(),
+                        collation: program.curr_collation(),
+                    });
+                }
+                program.emit_insn(Insn::Goto {
+                    target_pc: constraint_check,
+                });
+                program.preassign_label_to_next_insn(conflict_label);
+            }
 
-            let column_names = index.columns.iter().enumerate().fold(
-                String::with_capacity(50),
-                |mut accum, (idx, col)| {
-                    if idx > 0 {
-                        accum.push_str(", ");
-                    }
-                    accum.push_str(table_name);
-                    accum.push('.');
-                    accum.push_str(&col.name);
-                    accum
-                },
-            );
+            let column_names = if index.columns.iter().any(|col| col.expr.is_some()) {
+                format!("index '{}'", index.name)
+            } else {
+                index.columns.iter().enumerate().fold(
+                    String::with_capacity(50),
+                    |mut accum, (idx, col)| {
+                        if idx > 0 {
+                            accum.push_str(", ");
+                        }
+                        accum.push_str(table_name);
+                        accum.push('.');
+                        accum.push_str(&col.name);
+                        accum
+                    },
+                )
+            };
 
             program.emit_insn(Insn::Halt {
                 err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
@@ -1276,7 +1927,7 @@ fn emit_update_insns(
             cursor_id: *idx_cursor_id,
             record_reg: *record_reg,
             unpacked_start: Some(idx_start_reg),
-            unpacked_count: Some((num_cols + 1) as u16),
+            unpacked_count: Some(num_regs as u16),
             flags: IdxInsertFlags::new().nchange(true),
         });
 
@@ -1332,21 +1983,24 @@ fn emit_update_insns(
             program.preassign_label_to_next_insn(record_label);
         }
 
-        let record_reg = program.alloc_register();
+        let old_row_deleted = if let Some(primary_key) = &primary_key {
+            emit_update_without_rowid_row(
+                program,
+                &btree_table,
+                primary_key,
+                cursor_id,
+                start,
+                row_key,
+                &plan.set_clauses,
+                check_rowid_not_exists_label,
+            )
+        } else {
+            false
+        };
 
-        let affinity_str = unsafe { &*table_ref }
-            .columns()
-            .iter()
-            .map(|col| col.affinity().aff_mask())
-            .collect::<String>();
+        let record_reg = program.alloc_register();
 
-        program.emit_insn(Insn::MakeRecord {
-            start_reg: start,
-            count: col_len,
-            dest_reg: record_reg,
-            index_name: None,
-            affinity_str: Some(affinity_str),
-        });
+        emit_table_record(program, &btree_table, start, record_reg);
 
         if has_user_provided_rowid {
             program.emit_insn(Insn::NotExists {
@@ -1397,19 +2051,30 @@ fn emit_update_insns(
             });
         }
 
-        program.emit_insn(Insn::Insert {
-            cursor: cursor_id,
-            key_reg: rowid_set_clause_reg.unwrap_or(beg),
-            record_reg,
-            flag: if has_user_provided_rowid {
-                // The previous Insn::NotExists and Insn::Delete seek to the old rowid,
-                // so to insert a new user-provided rowid, we need to seek to the correct place.
-                InsertFlags::new().require_seek().update_rowid_change()
-            } else {
-                InsertFlags::new()
-            },
-            table_name: unsafe { &*table_ref }.identifier.clone(),
-        });
+        if primary_key.is_some() {
+            program.emit_insn(Insn::IdxInsert {
+                cursor_id,
+                record_reg,
+                unpacked_start: None,
+                unpacked_count: None,
+                // Deleting the old row already counted the change
+                flags: IdxInsertFlags::new().nchange(!old_row_deleted),
+            });
+        } else {
+            program.emit_insn(Insn::Insert {
+                cursor: cursor_id,
+                key_reg: rowid_set_clause_reg.unwrap_or(beg),
+                record_reg,
+                flag: if has_user_provided_rowid {
+                    // The previous Insn::NotExists and Insn::Delete seek to the old rowid,
+                    // so to insert a new user-provided rowid, we need to seek to the correct place.
+                    InsertFlags::new().require_seek().update_rowid_change()
+                } else {
+                    InsertFlags::new()
+                },
+                table_name: unsafe { &*table_ref }.identifier.clone(),
+            });
+        }
 
         // Emit RETURNING results if specified
         if let Some(returning_columns) = &plan.returning {
@@ -1424,6 +2089,34 @@ fn emit_update_insns(
             }
         }
 
+        if let Some(regs) = trigger_regs {
+            if has_update_fks {
+                emit_fk_actions(
+                    program,
+                    &t_ctx.resolver,
+                    connection,
+                    table_name,
+                    OperationMode::UPDATE,
+                    Some(&updated_columns),
+                    regs,
+                )?;
+            }
+            let after_triggers_label = program.allocate_label();
+            emit_triggers(
+                program,
+                &t_ctx.resolver,
+                connection,
+                table_name,
+                unsafe { &*table_ref }.columns(),
+                OperationMode::UPDATE,
+                TriggerTime::After,
+                Some(&updated_columns),
+                regs,
+                after_triggers_label,
+            )?;
+            program.preassign_label_to_next_insn(after_triggers_label);
+        }
+
         // create full CDC record after update if necessary
         let cdc_after_reg = if program.capture_data_changes_mode().has_after() {
             Some(emit_cdc_patch_record(
@@ -1498,7 +2191,7 @@ fn emit_update_insns(
             cursor_id,
             arg_count,
             start_reg: beg,
-            conflict_action: 0u16,
+            conflict_action: plan.or_conflict.map_or(0, |c| c.bit_value()) as u16,
         });
     }
 
@@ -1517,6 +2210,261 @@ fn emit_update_insns(
     Ok(())
 }
 
+/// Resolves the conflicts of the new values of a row with the rowid and the UNIQUE indexes of the
+/// table for UPDATE OR IGNORE and UPDATE OR REPLACE, before anything is changed. IGNORE skips the
+/// row, while REPLACE deletes the rows that the new values conflict with. The table cursor is
+/// positioned at the row being updated again afterwards.
+#[allow(clippy::too_many_arguments)]
+fn emit_update_conflict_resolution(
+    program: &mut ProgramBuilder,
+    table: &Table,
+    or_conflict: ast::ResolveType,
+    cursor_id: CursorID,
+    row_key: RowKey,
+    new_rowid_reg: Option<usize>,
+    columns_start_reg: usize,
+    indexes: &[(&Index, usize)],
+    skip_row_label: BranchOffset,
+    resolver: &Resolver,
+) -> Result<()> {
+    let old_rowid_reg = row_key.start_reg;
+    let emit_on_conflict = |program: &mut ProgramBuilder, conflict_rowid_reg: usize| {
+        if or_conflict == ast::ResolveType::Replace {
+            emit_replace_delete_row(
+                program,
+                table,
+                cursor_id,
+                conflict_rowid_reg,
+                indexes,
+                resolver,
+            )
+        } else {
+            program.emit_insn(Insn::Goto {
+                target_pc: skip_row_label,
+            });
+            Ok(())
+        }
+    };
+
+    // The rowid is checked first, then the UNIQUE indexes
+    if let Some(new_rowid_reg) = new_rowid_reg {
+        let ok_label = program.allocate_label();
+        program.emit_insn(Insn::Eq {
+            lhs: new_rowid_reg,
+            rhs: old_rowid_reg,
+            target_pc: ok_label,
+            flags: CmpInsFlags::default(),
+            collation: program.curr_collation(),
+        });
+        program.emit_insn(Insn::NotExists {
+            cursor: cursor_id,
+            rowid_reg: new_rowid_reg,
+            target_pc: ok_label,
+        });
+        emit_on_conflict(program, new_rowid_reg)?;
+        program.preassign_label_to_next_insn(ok_label);
+    }
+    for (index, idx_cursor_id) in indexes.iter().filter(|(index, _)| index.unique) {
+        let ok_label = program.allocate_label();
+        let conflict_rowid_reg = emit_unique_probe_for_row_image(
+            program,
+            table,
+            index,
+            *idx_cursor_id,
+            columns_start_reg,
+            new_rowid_reg.unwrap_or(old_rowid_reg),
+            old_rowid_reg,
+            ok_label,
+            resolver,
+        )?;
+        emit_on_conflict(program, conflict_rowid_reg)?;
+        program.preassign_label_to_next_insn(ok_label);
+    }
+    row_key.emit_seek(program, cursor_id, skip_row_label);
+    Ok(())
+}
+
+/// Checks that the new PRIMARY KEY of an updated row of a WITHOUT ROWID table is not taken by
+/// another row, and deletes the old row so that the new record is inserted under its new key.
+/// Nothing is emitted if the UPDATE does not change the PRIMARY KEY, since the new record then
+/// overwrites the old one. Returns whether the old row is deleted.
+#[allow(clippy::too_many_arguments)]
+fn emit_update_without_rowid_row(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    primary_key: &Index,
+    cursor_id: CursorID,
+    columns_start_reg: usize,
+    row_key: RowKey,
+    set_clauses: &[(usize, Box<Expr>)],
+    skip_row_label: Option<BranchOffset>,
+) -> bool {
+    if !primary_key_changed(primary_key, set_clauses) {
+        return false;
+    }
+    let num_cols = primary_key.columns.len();
+    let pk_start_reg = emit_new_primary_key(program, table, primary_key, columns_start_reg);
+    let ok = program.allocate_label();
+    let conflict = program.allocate_label();
+    program.emit_insn(Insn::NoConflict {
+        cursor_id,
+        target_pc: ok,
+        record_reg: pk_start_reg,
+        num_regs: num_cols,
+    });
+    // The key is taken by the row being updated if the PRIMARY KEY did not actually change
+    for i in 0..num_cols {
+        program.emit_insn(Insn::Ne {
+            lhs: pk_start_reg + i,
+            rhs: row_key.start_reg + i,
+            target_pc: conflict,
+            flags: CmpInsFlags::default(),
+            collation: program.curr_collation(),
+        });
+    }
+    program.emit_insn(Insn::Goto { target_pc: ok });
+    program.preassign_label_to_next_insn(conflict);
+    program.emit_insn(Insn::Halt {
+        err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+        description: format_unique_violation_desc(&table.name, primary_key),
+    });
+    program.preassign_label_to_next_insn(ok);
+    // NoConflict moved the cursor away from the row being updated
+    row_key.emit_seek(
+        program,
+        cursor_id,
+        skip_row_label.expect("rows are collected upfront when the PRIMARY KEY changes"),
+    );
+    program.emit_insn(Insn::Delete {
+        cursor_id,
+        table_name: table.name.clone(),
+    });
+    true
+}
+
+/// Resolves the conflicts of the new values of a row of a WITHOUT ROWID table with its PRIMARY KEY
+/// and its UNIQUE indexes for UPDATE OR IGNORE and UPDATE OR REPLACE, like
+/// [emit_update_conflict_resolution] does for the tables with a rowid.
+#[allow(clippy::too_many_arguments)]
+fn emit_update_without_rowid_conflict_resolution(
+    program: &mut ProgramBuilder,
+    table: &Table,
+    primary_key: &Index,
+    or_conflict: ast::ResolveType,
+    cursor_id: CursorID,
+    row_key: RowKey,
+    columns_start_reg: usize,
+    set_clauses: &[(usize, Box<Expr>)],
+    indexes: &[(&Index, usize)],
+    skip_row_label: BranchOffset,
+    resolver: &Resolver,
+) -> Result<()> {
+    let btree = table
+        .btree()
+        .expect("WITHOUT ROWID table must be a btree table");
+    let emit_on_conflict = |program: &mut ProgramBuilder, conflict_key_reg: usize| {
+        if or_conflict == ast::ResolveType::Replace {
+            emit_replace_delete_without_rowid_row(
+                program,
+                table,
+                cursor_id,
+                conflict_key_reg,
+                row_key.num_regs,
+                indexes,
+                resolver,
+            )
+        } else {
+            program.emit_insn(Insn::Goto {
+                target_pc: skip_row_label,
+            });
+            Ok(())
+        }
+    };
+
+    // The PRIMARY KEY is checked first, if it changes, then the UNIQUE indexes
+    if primary_key_changed(primary_key, set_clauses) {
+        let pk_start_reg = emit_new_primary_key(program, &btree, primary_key, columns_start_reg);
+        let ok_label = program.allocate_label();
+        let conflict_label = program.allocate_label();
+        program.emit_insn(Insn::NoConflict {
+            cursor_id,
+            target_pc: ok_label,
+            record_reg: pk_start_reg,
+            num_regs: row_key.num_regs,
+        });
+        for i in 0..row_key.num_regs {
+            program.emit_insn(Insn::Ne {
+                lhs: pk_start_reg + i,
+                rhs: row_key.start_reg + i,
+                target_pc: conflict_label,
+                flags: CmpInsFlags::default(),
+                collation: program.curr_collation(),
+            });
+        }
+        program.emit_insn(Insn::Goto {
+            target_pc: ok_label,
+        });
+        program.preassign_label_to_next_insn(conflict_label);
+        emit_on_conflict(program, pk_start_reg)?;
+        program.preassign_label_to_next_insn(ok_label);
+    }
+    for (index, idx_cursor_id) in indexes.iter().filter(|(index, _)| index.unique) {
+        let ok_label = program.allocate_label();
+        let conflict_key_reg = emit_unique_probe_for_without_rowid_row_image(
+            program,
+            table,
+            index,
+            *idx_cursor_id,
+            columns_start_reg,
+            row_key.start_reg,
+            ok_label,
+            resolver,
+        )?;
+        emit_on_conflict(program, conflict_key_reg)?;
+        program.preassign_label_to_next_insn(ok_label);
+    }
+    row_key.emit_seek(program, cursor_id, skip_row_label);
+    Ok(())
+}
+
+/// Returns whether the SET clauses of an UPDATE assign a column of the PRIMARY KEY.
+fn primary_key_changed(primary_key: &Index, set_clauses: &[(usize, Box<Expr>)]) -> bool {
+    primary_key.columns.iter().any(|column| {
+        set_clauses
+            .iter()
+            .any(|(idx, _)| *idx == column.pos_in_table)
+    })
+}
+
+/// Loads the new PRIMARY KEY of an updated row of a WITHOUT ROWID table, with the affinities of
+/// its columns applied, into consecutive registers and returns the first one.
+pub fn emit_new_primary_key(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    primary_key: &Index,
+    columns_start_reg: usize,
+) -> usize {
+    let num_cols = primary_key.columns.len();
+    let pk_start_reg = program.alloc_registers(num_cols);
+    for (i, column) in primary_key.columns.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: columns_start_reg + column.pos_in_table,
+            dst_reg: pk_start_reg + i,
+            extra_amount: 0,
+        });
+    }
+    program.emit_insn(Insn::Affinity {
+        start_reg: pk_start_reg,
+        count: NonZeroUsize::new(num_cols).expect("nonzero col count"),
+        affinities: primary_key
+            .columns
+            .iter()
+            .map(|column| table.columns[column.pos_in_table].affinity().aff_mask())
+            .collect(),
+    });
+    pk_start_reg
+}
+
 pub fn prepare_cdc_if_necessary(
     program: &mut ProgramBuilder,
     schema: &Schema,
@@ -1790,6 +2738,136 @@ fn init_limit(
     }
 }
 
+/// Computes the generated columns of a row from its other columns, which are in the registers
+/// starting at `columns_start_reg`. Used for rows being written and for the OLD image of a row,
+/// whose VIRTUAL columns are not stored.
+pub(crate) fn emit_generated_columns(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    columns_start_reg: usize,
+    rowid_reg: usize,
+    resolver: &Resolver,
+) -> Result<()> {
+    let order = table.generated_columns_order()?;
+    if order.is_empty() {
+        return Ok(());
+    }
+    // The expressions see the values the other columns are stored with.
+    program.emit_insn(Insn::Affinity {
+        start_reg: columns_start_reg,
+        count: NonZeroUsize::new(table.columns.len()).expect("tables have columns"),
+        affinities: table
+            .columns
+            .iter()
+            .map(|column| column.affinity().aff_mask())
+            .collect(),
+    });
+    for pos in order {
+        let column = &table.columns[pos];
+        let mut expr = column.generated.as_ref().unwrap().expr.as_ref().clone();
+        rewrite_where_for_update_registers(
+            &mut expr,
+            &table.columns,
+            columns_start_reg,
+            rowid_reg,
+        )?;
+        translate_expr_no_constant_opt(
+            program,
+            None,
+            &expr,
+            columns_start_reg + pos,
+            resolver,
+            NoConstantOptReason::RegisterReuse,
+        )?;
+        program.emit_insn(Insn::Affinity {
+            start_reg: columns_start_reg + pos,
+            count: NonZeroUsize::MIN,
+            affinities: column.affinity().aff_mask().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Emits the evaluation of the CHECK constraints of `table` for a new row whose columns are in
+/// the registers starting at `columns_start_reg` and whose rowid is in `rowid_reg`. An UPDATE
+/// passes the names of the `updated_columns`, and only the constraints that use them are checked.
+/// A violation jumps to `skip_label` if one is given, i.e. for OR IGNORE, and halts otherwise.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn emit_check_constraints(
+    program: &mut ProgramBuilder,
+    connection: &Connection,
+    table: &BTreeTable,
+    updated_columns: Option<&[String]>,
+    columns_start_reg: usize,
+    rowid_reg: usize,
+    skip_label: Option<BranchOffset>,
+    resolver: &Resolver,
+) -> Result<()> {
+    if connection.get_ignore_check_constraints() {
+        return Ok(());
+    }
+    let checks = table
+        .checks
+        .iter()
+        .filter(|check| {
+            updated_columns.is_none_or(|names| {
+                let rowid_updated = table
+                    .get_rowid_alias_column()
+                    .is_some_and(|(_, column)| names.contains(column.name.as_ref().unwrap()));
+                check.uses_any_column(names, rowid_updated)
+            })
+        })
+        .collect::<Vec<_>>();
+    if checks.is_empty() {
+        return Ok(());
+    }
+    // The constraints see the values the row is stored with.
+    program.emit_insn(Insn::Affinity {
+        start_reg: columns_start_reg,
+        count: NonZeroUsize::new(table.columns.len()).expect("tables have columns"),
+        affinities: table
+            .columns
+            .iter()
+            .map(|column| column.affinity().aff_mask())
+            .collect(),
+    });
+    for check in checks {
+        let mut expr = check.expr.as_ref().clone();
+        rewrite_where_for_update_registers(
+            &mut expr,
+            &table.columns,
+            columns_start_reg,
+            rowid_reg,
+        )?;
+        let reg = program.alloc_register();
+        translate_expr_no_constant_opt(
+            program,
+            None,
+            &expr,
+            reg,
+            resolver,
+            NoConstantOptReason::RegisterReuse,
+        )?;
+        let ok_label = program.allocate_label();
+        program.emit_insn(Insn::If {
+            reg,
+            target_pc: ok_label,
+            jump_if_null: true,
+        });
+        match skip_label {
+            Some(skip_label) => program.emit_insn(Insn::Goto {
+                target_pc: skip_label,
+            }),
+            None => program.emit_insn(Insn::Halt {
+                err_code: SQLITE_CONSTRAINT_CHECK,
+                description: check.violation_description(),
+            }),
+        }
+        program.preassign_label_to_next_insn(ok_label);
+    }
+    Ok(())
+}
+
 /// We have `Expr`s which have *not* had column references bound to them,
 /// so they are in the state of Expr::Id/Expr::Qualified, etc, and instead of binding Expr::Column
 /// we need to bind Expr::Register, as we have already loaded the *new* column values from the
@@ -1,3 +1,4 @@
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use tracing::{instrument, Level};
@@ -6,21 +7,22 @@ use turso_parser::ast::{self, As, Expr, UnaryOperator};
 use super::emitter::Resolver;
 use super::optimizer::Optimizable;
 use super::plan::TableReferences;
+use crate::error::{SQLITE_CONSTRAINT_TRIGGER, SQLITE_IGNORE};
 #[cfg(feature = "json")]
 use crate::function::JsonFunc;
 use crate::function::{Func, FuncCtx, MathFuncArity, ScalarFunc, VectorFunc};
 use crate::functions::datetime;
 use crate::parameters::PARAM_PREFIX;
-use crate::schema::{affinity, Affinity, Table, Type};
+use crate::schema::{affinity, Affinity, BTreeTable, Index, Table, Type};
 use crate::translate::optimizer::TakeOwnership;
-use crate::translate::plan::ResultSetColumn;
+use crate::translate::plan::{using_column_coalesced, ResultSetColumn};
 use crate::translate::planner::parse_row_id;
 use crate::util::{exprs_are_equivalent, normalize_ident, parse_numeric_literal};
 use crate::vdbe::builder::CursorKey;
 use crate::vdbe::{
     builder::ProgramBuilder,
     insn::{CmpInsFlags, Insn},
-    BranchOffset,
+    BranchOffset, CursorID,
 };
 use crate::{Result, Value};
 
@@ -289,9 +291,6 @@ pub fn translate_condition_expr(
     resolver: &Resolver,
 ) -> Result<()> {
     match expr {
-        ast::Expr::Register(_) => {
-            crate::bail_parse_error!("Register in WHERE clause is currently unused. Consider removing Resolver::expr_to_reg_cache and using Expr::Register instead");
-        }
         ast::Expr::Collate(_, _) => {
             crate::bail_parse_error!("Collate in WHERE clause is not supported");
         }
@@ -315,9 +314,6 @@ pub fn translate_condition_expr(
         ast::Expr::FunctionCallStar { .. } => {
             crate::bail_parse_error!("FunctionCallStar in WHERE clause is not supported");
         }
-        ast::Expr::Raise(_, _) => {
-            crate::bail_parse_error!("RAISE in WHERE clause is not supported");
-        }
         ast::Expr::Between { .. } => {
             crate::bail_parse_error!("BETWEEN expression should have been rewritten in optmizer")
         }
@@ -398,7 +394,10 @@ pub fn translate_condition_expr(
         | ast::Expr::FunctionCall { .. }
         | ast::Expr::Column { .. }
         | ast::Expr::RowId { .. }
-        | ast::Expr::Case { .. } => {
+        | ast::Expr::Case { .. }
+        | ast::Expr::Register(_)
+        | ast::Expr::Raise(_, _)
+        | ast::Expr::SubqueryResult { .. } => {
             let reg = program.alloc_register();
             translate_expr(program, Some(referenced_tables), expr, reg, resolver)?;
             emit_cond_jump(program, condition_metadata, reg);
@@ -668,7 +667,7 @@ pub fn translate_expr(
         ast::Expr::Cast { expr, type_name } => {
             let type_name = type_name.as_ref().unwrap(); // TODO: why is this optional?
             translate_expr(program, referenced_tables, expr, target_register, resolver)?;
-            let type_affinity = affinity(&type_name.name.to_uppercase());
+            let type_affinity = affinity(&type_name.name);
             program.emit_insn(Insn::Cast {
                 reg: target_register,
                 affinity: type_affinity,
@@ -718,6 +717,9 @@ pub fn translate_expr(
                         name.as_str()
                     )
                 }
+                Func::Window(_) => {
+                    crate::bail_parse_error!("misuse of window function {}()", name.as_str())
+                }
                 Func::External(_) => {
                     let regs = program.alloc_registers(args_count);
                     for (i, arg_expr) in args.iter().enumerate() {
@@ -1837,19 +1839,8 @@ pub fn translate_expr(
             column,
             is_rowid_alias,
         } => {
-            let (index, use_covering_index) = {
-                if let Some(table_reference) = referenced_tables
-                    .unwrap()
-                    .find_joined_table_by_internal_id(*table_ref_id)
-                {
-                    (
-                        table_reference.op.index(),
-                        table_reference.utilizes_covering_index(),
-                    )
-                } else {
-                    (None, false)
-                }
-            };
+            let (index, use_covering_index) =
+                column_read_index(program, referenced_tables.unwrap(), *table_ref_id);
 
             let table = referenced_tables
                 .unwrap()
@@ -1872,7 +1863,7 @@ pub fn translate_expr(
                     } else {
                         Some(program.resolve_cursor_id(&CursorKey::table(*table_ref_id)))
                     };
-                    let index_cursor_id = index.map(|index| {
+                    let index_cursor_id = index.as_ref().map(|index| {
                         program.resolve_cursor_id(&CursorKey::index(*table_ref_id, index.clone()))
                     });
                     if *is_rowid_alias {
@@ -1900,7 +1891,7 @@ pub fn translate_expr(
                             )
                         };
                         let column = if use_covering_index {
-                            let index = index.expect(
+                            let index = index.as_ref().expect(
                                 "index cursor should be opened when use_covering_index=true",
                             );
                             index.column_table_pos_to_index_pos(*column).unwrap_or_else(|| {
@@ -1910,7 +1901,17 @@ pub fn translate_expr(
                             *column
                         };
 
-                        program.emit_column_or_rowid(read_cursor, column, target_register);
+                        match table.btree() {
+                            Some(btree) if !use_covering_index => emit_table_column(
+                                program,
+                                &btree,
+                                read_cursor,
+                                column,
+                                target_register,
+                                resolver,
+                            )?,
+                            _ => program.emit_column_or_rowid(read_cursor, column, target_register),
+                        }
                     }
                     let Some(column) = table.get_column_at(*column) else {
                         crate::bail_parse_error!("column index out of bounds");
@@ -1946,19 +1947,8 @@ pub fn translate_expr(
             database: _,
             table: table_ref_id,
         } => {
-            let (index, use_covering_index) = {
-                if let Some(table_reference) = referenced_tables
-                    .unwrap()
-                    .find_joined_table_by_internal_id(*table_ref_id)
-                {
-                    (
-                        table_reference.op.index(),
-                        table_reference.utilizes_covering_index(),
-                    )
-                } else {
-                    (None, false)
-                }
-            };
+            let (index, use_covering_index) =
+                column_read_index(program, referenced_tables.unwrap(), *table_ref_id);
 
             if use_covering_index {
                 let index =
@@ -2120,10 +2110,117 @@ pub fn translate_expr(
         ast::Expr::Qualified(_, _) => {
             unreachable!("Qualified should be resolved to a Column before translation")
         }
-        ast::Expr::Raise(_, _) => crate::bail_parse_error!("RAISE is not supported"),
+        ast::Expr::Raise(resolve_type, message) => {
+            if !program.is_trigger_program() {
+                crate::bail_parse_error!("RAISE() may only be used within a trigger-program");
+            }
+            match resolve_type {
+                ast::ResolveType::Ignore => {
+                    program.emit_halt_err(SQLITE_IGNORE, String::new());
+                }
+                ast::ResolveType::Abort | ast::ResolveType::Fail | ast::ResolveType::Rollback => {
+                    let message = match message.as_deref() {
+                        Some(ast::Expr::Literal(ast::Literal::String(s))) => sanitize_string(s),
+                        _ => crate::bail_parse_error!(
+                            "RAISE() error message must be a string literal"
+                        ),
+                    };
+                    program.emit_halt_err(SQLITE_CONSTRAINT_TRIGGER, message);
+                }
+                ast::ResolveType::Replace => {
+                    crate::bail_parse_error!("RAISE(REPLACE) is not valid")
+                }
+            }
+            program.emit_insn(Insn::Null {
+                dest: target_register,
+                dest_end: None,
+            });
+            Ok(target_register)
+        }
         ast::Expr::Subquery(_) => {
             crate::bail_parse_error!("Subquery in WHERE clause is not supported")
         }
+        ast::Expr::SubqueryResult {
+            lhs,
+            not_in,
+            query_type,
+            ..
+        } => match query_type {
+            ast::SubqueryType::Exists { result_reg } | ast::SubqueryType::Scalar { result_reg } => {
+                program.emit_insn(Insn::Copy {
+                    src_reg: *result_reg,
+                    dst_reg: target_register,
+                    extra_amount: 0,
+                });
+                Ok(target_register)
+            }
+            ast::SubqueryType::In { cursor_id } => {
+                let lhs = lhs
+                    .as_ref()
+                    .expect("IN subquery result must have a left-hand side");
+                let label_true = program.allocate_label();
+                let label_false = program.allocate_label();
+                let label_null = program.allocate_label();
+                let label_end = program.allocate_label();
+                // An empty subquery makes the IN false, even if the left-hand side is NULL.
+                program.emit_insn(Insn::Rewind {
+                    cursor_id: *cursor_id,
+                    pc_if_empty: label_false,
+                });
+                let lhs_reg = program.alloc_register();
+                translate_expr(program, referenced_tables, lhs, lhs_reg, resolver)?;
+                program.emit_insn(Insn::IsNull {
+                    reg: lhs_reg,
+                    target_pc: label_null,
+                });
+                program.emit_insn(Insn::Found {
+                    cursor_id: *cursor_id,
+                    target_pc: label_true,
+                    record_reg: lhs_reg,
+                    num_regs: 1,
+                });
+                // The index is sorted, so NULLs come first: if the subquery returned a NULL,
+                // the IN is NULL instead of false when no value matched.
+                let first_reg = program.alloc_register();
+                program.emit_insn(Insn::Rewind {
+                    cursor_id: *cursor_id,
+                    pc_if_empty: label_false,
+                });
+                program.emit_insn(Insn::Column {
+                    cursor_id: *cursor_id,
+                    column: 0,
+                    dest: first_reg,
+                    default: None,
+                });
+                program.emit_insn(Insn::NotNull {
+                    reg: first_reg,
+                    target_pc: label_false,
+                });
+                program.preassign_label_to_next_insn(label_null);
+                program.emit_insn(Insn::Null {
+                    dest: target_register,
+                    dest_end: None,
+                });
+                program.emit_insn(Insn::Goto {
+                    target_pc: label_end,
+                });
+                program.preassign_label_to_next_insn(label_true);
+                program.emit_insn(Insn::Integer {
+                    value: if *not_in { 0 } else { 1 },
+                    dest: target_register,
+                });
+                program.emit_insn(Insn::Goto {
+                    target_pc: label_end,
+                });
+                program.preassign_label_to_next_insn(label_false);
+                program.emit_insn(Insn::Integer {
+                    value: if *not_in { 1 } else { 0 },
+                    dest: target_register,
+                });
+                program.preassign_label_to_next_insn(label_end);
+                Ok(target_register)
+            }
+        },
         ast::Expr::Unary(op, expr) => match (op, expr.as_ref()) {
             (UnaryOperator::Positive, expr) => {
                 translate_expr(program, referenced_tables, expr, target_register, resolver)
@@ -2235,6 +2332,84 @@ pub fn translate_expr(
     Ok(target_register)
 }
 
+/// Returns the index of the table reference that columns are read through, if any,
+/// and whether the table cursor is skipped entirely because that index is covering.
+fn column_read_index(
+    program: &ProgramBuilder,
+    referenced_tables: &TableReferences,
+    table_ref_id: ast::TableInternalId,
+) -> (Option<Arc<Index>>, bool) {
+    if let Some(table_reference) = referenced_tables.find_joined_table_by_internal_id(table_ref_id)
+    {
+        return (
+            table_reference.op.index().cloned(),
+            table_reference.utilizes_covering_index(),
+        );
+    }
+    // A table of an outer query, e.g. in a correlated subquery. Its plan is not known here,
+    // so read through its index cursor only if it has no table cursor.
+    if program
+        .resolve_cursor_id_safe(&CursorKey::table(table_ref_id))
+        .is_some()
+    {
+        return (None, false);
+    }
+    match program.resolve_cursor_index(table_ref_id) {
+        Some(index) => (Some(index), true),
+        None => (None, false),
+    }
+}
+
+/// Reads a column of the row a table cursor points to into `target_register`. A VIRTUAL
+/// generated column has no value in the row, so its expression is evaluated over the other
+/// columns of the row.
+pub fn emit_table_column(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursor_id: CursorID,
+    column: usize,
+    target_register: usize,
+    resolver: &Resolver,
+) -> Result<()> {
+    let table_column = &table.columns[column];
+    let Some(generated) = table_column
+        .generated
+        .as_ref()
+        .filter(|generated| !generated.stored)
+    else {
+        program.emit_column_or_rowid(cursor_id, column, target_register);
+        return Ok(());
+    };
+    let mut expr = generated.expr.as_ref().clone();
+    walk_expr_mut(&mut expr, &mut |e: &mut ast::Expr| -> Result<WalkControl> {
+        if let ast::Expr::Id(name)
+        | ast::Expr::Qualified(_, name)
+        | ast::Expr::DoublyQualified(_, _, name) = e
+        {
+            if let Some((pos, _)) = table.get_column(name.as_str()) {
+                let reg = program.alloc_register();
+                emit_table_column(program, table, cursor_id, pos, reg, resolver)?;
+                *e = ast::Expr::Register(reg);
+            }
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    translate_expr_no_constant_opt(
+        program,
+        None,
+        &expr,
+        target_register,
+        resolver,
+        NoConstantOptReason::RegisterReuse,
+    )?;
+    program.emit_insn(Insn::Affinity {
+        start_reg: target_register,
+        count: NonZeroUsize::MIN,
+        affinities: table_column.affinity().aff_mask().to_string(),
+    });
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 fn binary_expr_shared(
     program: &mut ProgramBuilder,
@@ -3185,6 +3360,11 @@ where
                     walk_expr(lhs, func)?;
                     // TODO: Walk through select statements if needed
                 }
+                ast::Expr::SubqueryResult { lhs, .. } => {
+                    if let Some(lhs) = lhs {
+                        walk_expr(lhs, func)?;
+                    }
+                }
                 ast::Expr::InTable { lhs, args, .. } => {
                     walk_expr(lhs, func)?;
                     for expr in args {
@@ -3376,15 +3556,24 @@ pub fn bind_and_rewrite_expr<'a>(
                                 referenced_tables.joined_tables()[0].internal_id,
                                 || referenced_tables.joined_tables().len() != 1,
                             )? {
+                                if referenced_tables.joined_tables()[0]
+                                    .btree()
+                                    .is_some_and(|btree| !btree.has_rowid)
+                                {
+                                    crate::bail_parse_error!("no such column: {}", id.as_str());
+                                }
                                 *expr = row_id_expr;
 
                                 return Ok(WalkControl::Continue);
                             }
                         }
                         let mut match_result = None;
+                        let mut first_match = None;
 
                         // First check joined tables
-                        for joined_table in referenced_tables.joined_tables().iter() {
+                        for (table_idx, joined_table) in
+                            referenced_tables.joined_tables().iter().enumerate()
+                        {
                             let col_idx = joined_table.table.columns().iter().position(|c| {
                                 c.name
                                     .as_ref()
@@ -3416,7 +3605,29 @@ pub fn bind_and_rewrite_expr<'a>(
                                     col_idx.unwrap(),
                                     col.is_rowid_alias,
                                 ));
+                                first_match = first_match.or(Some((table_idx, col_idx.unwrap())));
+                            }
+                        }
+
+                        if let Some(coalesced) = first_match.and_then(|(table_idx, col_idx)| {
+                            using_column_coalesced(
+                                referenced_tables.joined_tables(),
+                                table_idx,
+                                col_idx,
+                            )
+                        }) {
+                            let mut used_columns = vec![];
+                            walk_expr(&coalesced, &mut |expr: &Expr| -> Result<WalkControl> {
+                                if let Expr::Column { table, column, .. } = expr {
+                                    used_columns.push((*table, *column));
+                                }
+                                Ok(WalkControl::Continue)
+                            })?;
+                            for (table_id, col_idx) in used_columns {
+                                referenced_tables.mark_column_used(table_id, col_idx);
                             }
+                            *expr = coalesced;
+                            return Ok(WalkControl::Continue);
                         }
 
                         // Then check outer query references, if we still didn't find something.
@@ -3498,6 +3709,9 @@ pub fn bind_and_rewrite_expr<'a>(
                         let normalized_id = normalize_ident(id.as_str());
 
                         if let Some(row_id_expr) = parse_row_id(&normalized_id, tbl_id, || false)? {
+                            if tbl.btree().is_some_and(|btree| !btree.has_rowid) {
+                                crate::bail_parse_error!("no such column: {}", id.as_str());
+                            }
                             *expr = row_id_expr;
 
                             return Ok(WalkControl::Continue);
@@ -3708,6 +3922,11 @@ where
                     walk_expr_mut(lhs, func)?;
                     // TODO: Walk through select statements if needed
                 }
+                ast::Expr::SubqueryResult { lhs, .. } => {
+                    if let Some(lhs) = lhs {
+                        walk_expr_mut(lhs, func)?;
+                    }
+                }
                 ast::Expr::InTable { lhs, args, .. } => {
                     walk_expr_mut(lhs, func)?;
                     for expr in args {
@@ -3788,6 +4007,7 @@ pub fn get_expr_affinity(
             }
             Affinity::Blob
         }
+        ast::Expr::RowId { .. } => Affinity::Integer,
         ast::Expr::Cast { type_name, .. } => {
             if let Some(type_name) = type_name {
                 crate::schema::affinity(&type_name.name)
@@ -4083,6 +4303,7 @@ pub fn process_returning_clause(
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            indexed: None,
         }],
         vec![],
     );
@@ -4115,7 +4336,7 @@ pub fn process_returning_clause(
                         database: None,
                         table: internal_id,
                         column: column_index,
-                        is_rowid_alias: false,
+                        is_rowid_alias: column.is_rowid_alias,
                     };
 
                     result_columns.push(ResultSetColumn {
@@ -4133,7 +4354,7 @@ pub fn process_returning_clause(
                         database: None,
                         table: internal_id,
                         column: column_index,
-                        is_rowid_alias: false,
+                        is_rowid_alias: column.is_rowid_alias,
                     };
 
                     result_columns.push(ResultSetColumn {
@@ -0,0 +1,925 @@
+//! Foreign key constraints.
+//! https://www.sqlite.org/foreignkeys.html
+//!
+//! Like in SQLite, violations are counted rather than reported right away: a statement that
+//! inserts a child row without a parent increments a counter with [Insn::FkCounter], and a
+//! statement that later inserts the missing parent decrements it again. Immediate constraints
+//! are counted per statement and checked when the statement halts, deferred constraints are
+//! counted per transaction and checked when it commits.
+//!
+//! For every row a statement inserts, updates or deletes, [emit_fk_checks] looks up the parent
+//! row of each foreign key of the table (the table is the child), and scans the child rows of
+//! each foreign key referring to the table (the table is the parent). ON DELETE and ON UPDATE
+//! actions are compiled into trigger programs by [emit_fk_actions] and run after the row has
+//! been modified.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use turso_parser::ast::{self, Expr, Operator, RefAct, TriggerEvent, TriggerTime};
+
+use crate::error::SQLITE_CONSTRAINT_FOREIGNKEY;
+use crate::schema::{BTreeTable, ForeignKey, Index, Schema, Trigger};
+use crate::translate::emitter::{OperationMode, Resolver};
+use crate::translate::trigger::{emit_fk_action_trigger, TriggerRowRegisters};
+use crate::vdbe::builder::{CursorType, ProgramBuilder};
+use crate::vdbe::insn::{CmpInsFlags, Insn};
+use crate::vdbe::BranchOffset;
+use crate::{bail_parse_error, Connection, Result};
+
+/// Registers holding an image of a row: its rowid, and its column values in table column order.
+#[derive(Debug, Clone, Copy)]
+pub struct RowImage {
+    pub rowid_reg: usize,
+    pub columns_start_reg: usize,
+}
+
+impl RowImage {
+    /// Register holding the value of column `idx` of `table`. A rowid alias column is stored
+    /// as NULL in the record, so its value is the rowid.
+    fn column_reg(&self, table: &BTreeTable, idx: usize) -> usize {
+        if table.columns[idx].is_rowid_alias {
+            self.rowid_reg
+        } else {
+            self.columns_start_reg + idx
+        }
+    }
+}
+
+/// A foreign key resolved against the schema.
+struct ResolvedForeignKey {
+    child: Arc<BTreeTable>,
+    /// The id of the constraint as reported by `PRAGMA foreign_key_list`.
+    id: usize,
+    fk: ForeignKey,
+    parent: Arc<BTreeTable>,
+    /// Positions of the child key columns in the child table.
+    child_columns: Vec<usize>,
+    /// Positions of the parent key columns in the parent table, in the same order.
+    parent_columns: Vec<usize>,
+    /// The unique index the parent key is looked up in, or None if the parent key is the rowid.
+    parent_index: Option<Arc<Index>>,
+    /// An index of the child table whose leading columns are the child key, used to find the
+    /// child rows of a parent key without scanning the whole child table.
+    child_index: Option<Arc<Index>>,
+}
+
+impl ResolvedForeignKey {
+    fn new(schema: &Schema, child: Arc<BTreeTable>, idx: usize) -> Result<Self> {
+        let fk = child.foreign_keys[idx].clone();
+        let Some(parent) = schema.get_btree_table(&fk.parent_table) else {
+            bail_parse_error!("no such table: main.{}", fk.parent_table_str);
+        };
+        Self::with_parent(schema, child, idx, parent)
+    }
+
+    fn with_parent(
+        schema: &Schema,
+        child: Arc<BTreeTable>,
+        idx: usize,
+        parent: Arc<BTreeTable>,
+    ) -> Result<Self> {
+        let fk = child.foreign_keys[idx].clone();
+        let mismatch = || {
+            crate::LimboError::ParseError(format!(
+                "foreign key mismatch - \"{}\" referencing \"{}\"",
+                child.name, fk.parent_table_str
+            ))
+        };
+        let parent_key = parent_key_columns(&fk, &parent);
+        if parent_key.len() != fk.child_columns.len() {
+            return Err(mismatch());
+        }
+        let parent_columns = parent_key
+            .iter()
+            .map(|name| parent.get_column(name).map(|(idx, _)| idx))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(mismatch)?;
+        let child_columns = fk
+            .child_columns
+            .iter()
+            .map(|name| child.get_column(name).map(|(idx, _)| idx))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(mismatch)?;
+        let parent_index =
+            if parent_columns.len() == 1 && parent.columns[parent_columns[0]].is_rowid_alias {
+                None
+            } else {
+                // The parent key must be covered by a UNIQUE constraint (or index) on exactly the
+                // parent key columns. The PRIMARY KEY of a WITHOUT ROWID table is not one of the
+                // indexes of the table in the schema.
+                let primary_key = (!parent.has_rowid)
+                    .then(|| Arc::new(Index::without_rowid_primary_key(&parent)));
+                let index = primary_key
+                    .iter()
+                    .chain(schema.get_indices(&parent.name))
+                    .find(|index| {
+                        index.unique
+                            && index.where_clause.is_none()
+                            && index.columns.len() == parent_columns.len()
+                            && index
+                                .columns
+                                .iter()
+                                .all(|column| parent_columns.contains(&column.pos_in_table))
+                    })
+                    .cloned()
+                    .ok_or_else(mismatch)?;
+                Some(index)
+            };
+        let child_primary_key =
+            (!child.has_rowid).then(|| Arc::new(Index::without_rowid_primary_key(&child)));
+        let child_index = schema
+            .get_indices(&child.name)
+            .chain(child_primary_key.iter())
+            .find(|index| {
+                index.where_clause.is_none()
+                    && index.columns.len() >= child_columns.len()
+                    && index.columns[..child_columns.len()]
+                        .iter()
+                        .all(|column| child_columns.contains(&column.pos_in_table))
+            })
+            .cloned();
+        Ok(Self {
+            id: child.foreign_keys.len() - 1 - idx,
+            child,
+            fk,
+            parent,
+            child_columns,
+            parent_columns,
+            parent_index,
+            child_index,
+        })
+    }
+
+    /// Registers holding the child key of `row`, a row of the child table.
+    fn child_key(&self, row: RowImage) -> Vec<usize> {
+        self.child_columns
+            .iter()
+            .map(|&idx| row.column_reg(&self.child, idx))
+            .collect()
+    }
+
+    /// Registers holding the parent key of `row`, a row of the parent table.
+    fn parent_key(&self, row: RowImage) -> Vec<usize> {
+        self.parent_columns
+            .iter()
+            .map(|&idx| row.column_reg(&self.parent, idx))
+            .collect()
+    }
+
+    fn is_self_referencing(&self) -> bool {
+        self.child.name == self.parent.name
+    }
+}
+
+/// Names of the parent key columns of `fk`: the columns listed in the constraint, or the
+/// primary key of the parent table if none are.
+fn parent_key_columns(fk: &ForeignKey, parent: &BTreeTable) -> Vec<String> {
+    if !fk.parent_columns.is_empty() {
+        return fk.parent_columns.clone();
+    }
+    parent
+        .primary_key_columns
+        .iter()
+        .map(|(name, _)| crate::util::normalize_ident(name))
+        .collect()
+}
+
+fn any_column_updated(columns: &[String], updated_columns: Option<&[String]>) -> bool {
+    match updated_columns {
+        Some(updated) => columns.iter().any(|column| updated.contains(column)),
+        None => true,
+    }
+}
+
+/// Whether the parent key of `fk`, which refers to `parent`, may be modified by a statement
+/// setting `updated_columns` (None for INSERT and DELETE).
+fn parent_key_modified(
+    fk: &ForeignKey,
+    parent: &BTreeTable,
+    updated_columns: Option<&[String]>,
+) -> bool {
+    let columns = parent_key_columns(fk, parent);
+    // Without a parent key the constraint is a mismatch, which is reported while resolving it.
+    columns.is_empty() || any_column_updated(&columns, updated_columns)
+}
+
+/// Returns true if foreign keys are enabled and `table_name` is the child or the parent of a
+/// foreign key that a statement inserting, deleting or (setting `updated_columns`) updating
+/// rows of the table has to check.
+pub fn has_fks(
+    schema: &Schema,
+    connection: &Connection,
+    table_name: &str,
+    updated_columns: Option<&[String]>,
+) -> bool {
+    if !connection.get_foreign_keys() {
+        return false;
+    }
+    let Some(table) = schema.get_btree_table(table_name) else {
+        return false;
+    };
+    // Foreign keys are only enforced for tables of the main database.
+    let is_main_table = connection.with_schema(0, |main| {
+        main.get_btree_table(table_name)
+            .is_some_and(|main_table| Arc::ptr_eq(&main_table, &table))
+    });
+    if !is_main_table {
+        return false;
+    }
+    table
+        .foreign_keys
+        .iter()
+        .any(|fk| any_column_updated(&fk.child_columns, updated_columns))
+        || schema
+            .get_referencing_foreign_keys(table_name)
+            .iter()
+            .any(|(child, idx)| {
+                parent_key_modified(&child.foreign_keys[*idx], &table, updated_columns)
+            })
+}
+
+/// Emits the foreign key checks for a row of `table_name` that is about to be deleted (`old`
+/// only), inserted (`new` only), or updated (both), where `updated_columns` are the columns
+/// assigned by the UPDATE.
+pub fn emit_fk_checks(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    table_name: &str,
+    old: Option<RowImage>,
+    new: Option<RowImage>,
+    updated_columns: Option<&[String]>,
+) -> Result<()> {
+    let Some(table) = schema.get_btree_table(table_name) else {
+        return Ok(());
+    };
+
+    // The table is the child: the parent of the new key must exist, and the old key no longer
+    // needs one.
+    for idx in 0..table.foreign_keys.len() {
+        if !any_column_updated(&table.foreign_keys[idx].child_columns, updated_columns) {
+            continue;
+        }
+        let fk = ResolvedForeignKey::new(schema, table.clone(), idx)?;
+        let unchanged_label = program.allocate_label();
+        if let (Some(old), Some(new)) = (old, new) {
+            emit_jump_if_key_unchanged(
+                program,
+                &fk.child_key(old),
+                &fk.child_key(new),
+                unchanged_label,
+            );
+        }
+        if let Some(old) = old {
+            emit_child_check(program, &fk, old, -1);
+        }
+        if let Some(new) = new {
+            emit_child_check(program, &fk, new, 1);
+        }
+        program.preassign_label_to_next_insn(unchanged_label);
+    }
+
+    // The table is the parent: child rows referring to the old key lose their parent, and
+    // child rows referring to the new key found one.
+    for (child, idx) in schema.get_referencing_foreign_keys(table_name) {
+        if !parent_key_modified(&child.foreign_keys[idx], &table, updated_columns) {
+            continue;
+        }
+        let fk = ResolvedForeignKey::with_parent(schema, child, idx, table.clone())?;
+        let unchanged_label = program.allocate_label();
+        if let (Some(old), Some(new)) = (old, new) {
+            emit_jump_if_key_unchanged(
+                program,
+                &fk.parent_key(old),
+                &fk.parent_key(new),
+                unchanged_label,
+            );
+        }
+        if let Some(new) = new {
+            emit_children_scan(program, &fk, new, None, ChildAction::Count(-1));
+        }
+        if let Some(old) = old {
+            let action = if new.is_some() {
+                fk.fk.on_update
+            } else {
+                fk.fk.on_delete
+            };
+            // A row referring to itself does not keep itself from being deleted.
+            let excluded_row = fk.is_self_referencing().then_some(old);
+            let child_action = if action == RefAct::Restrict {
+                ChildAction::Restrict
+            } else {
+                ChildAction::Count(1)
+            };
+            emit_children_scan(program, &fk, old, excluded_row, child_action);
+        }
+        program.preassign_label_to_next_insn(unchanged_label);
+    }
+    Ok(())
+}
+
+/// Emits the ON DELETE or ON UPDATE actions of the foreign keys referring to `table_name`, for
+/// a row that has just been deleted or updated. `regs` holds the OLD and NEW images of the row.
+#[allow(clippy::too_many_arguments)]
+pub fn emit_fk_actions(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    connection: &Arc<Connection>,
+    table_name: &str,
+    mode: OperationMode,
+    updated_columns: Option<&[String]>,
+    regs: TriggerRowRegisters,
+) -> Result<()> {
+    let schema = resolver.schema;
+    let Some(table) = schema.get_btree_table(table_name) else {
+        return Ok(());
+    };
+    for (child, idx) in schema.get_referencing_foreign_keys(table_name) {
+        let fk = &child.foreign_keys[idx];
+        let action = match mode {
+            OperationMode::DELETE => fk.on_delete,
+            OperationMode::UPDATE => fk.on_update,
+            _ => continue,
+        };
+        if !matches!(
+            action,
+            RefAct::Cascade | RefAct::SetNull | RefAct::SetDefault
+        ) || !parent_key_modified(fk, &table, updated_columns)
+        {
+            continue;
+        }
+        let fk = ResolvedForeignKey::with_parent(schema, child, idx, table.clone())?;
+        let trigger = action_trigger(&fk, mode, action);
+        emit_fk_action_trigger(
+            program,
+            resolver,
+            connection,
+            &trigger,
+            &table.columns,
+            mode,
+            regs,
+        )?;
+    }
+    Ok(())
+}
+
+/// Builds the trigger that performs `action` on the child rows of `fk` when their parent row
+/// is deleted or updated, e.g. for ON DELETE CASCADE:
+/// `DELETE FROM child WHERE child.c1 = OLD.p1 AND child.c2 = OLD.p2`.
+fn action_trigger(fk: &ResolvedForeignKey, mode: OperationMode, action: RefAct) -> Trigger {
+    let child_column = |j: usize| {
+        ast::Name::Ident(
+            fk.child.columns[fk.child_columns[j]]
+                .name
+                .clone()
+                .expect("child key column has a name"),
+        )
+    };
+    let parent_column = |qualifier: &str, j: usize| {
+        Box::new(Expr::Qualified(
+            ast::Name::Ident(qualifier.to_string()),
+            ast::Name::Ident(
+                fk.parent.columns[fk.parent_columns[j]]
+                    .name
+                    .clone()
+                    .expect("parent key column has a name"),
+            ),
+        ))
+    };
+    let key_len = fk.child_columns.len();
+    let where_clause = (0..key_len)
+        .map(|j| {
+            Box::new(Expr::Binary(
+                Box::new(Expr::Id(child_column(j))),
+                Operator::Equals,
+                parent_column("old", j),
+            ))
+        })
+        .reduce(|lhs, rhs| Box::new(Expr::Binary(lhs, Operator::And, rhs)));
+
+    let command = if mode == OperationMode::DELETE && action == RefAct::Cascade {
+        ast::TriggerCmd::Delete {
+            tbl_name: ast::Name::Ident(fk.child.name.clone()),
+            where_clause,
+        }
+    } else {
+        let sets = (0..key_len)
+            .map(|j| ast::Set {
+                col_names: vec![child_column(j)],
+                expr: match action {
+                    RefAct::Cascade => parent_column("new", j),
+                    RefAct::SetDefault => fk.child.columns[fk.child_columns[j]]
+                        .default
+                        .clone()
+                        .unwrap_or_else(|| Box::new(Expr::Literal(ast::Literal::Null))),
+                    _ => Box::new(Expr::Literal(ast::Literal::Null)),
+                },
+            })
+            .collect();
+        ast::TriggerCmd::Update {
+            or_conflict: None,
+            tbl_name: ast::Name::Ident(fk.child.name.clone()),
+            sets,
+            from: None,
+            where_clause,
+        }
+    };
+
+    // An ON UPDATE action only fires if the parent key actually changed.
+    let when_clause = (mode == OperationMode::UPDATE)
+        .then(|| {
+            (0..key_len)
+                .map(|j| {
+                    Box::new(Expr::Binary(
+                        parent_column("old", j),
+                        Operator::IsNot,
+                        parent_column("new", j),
+                    ))
+                })
+                .reduce(|lhs, rhs| Box::new(Expr::Binary(lhs, Operator::Or, rhs)))
+        })
+        .flatten();
+
+    let event = if mode == OperationMode::DELETE {
+        TriggerEvent::Delete
+    } else {
+        TriggerEvent::Update
+    };
+    Trigger {
+        // The sqlite_ prefix is reserved, so the name cannot clash with a user trigger.
+        name: format!(
+            "sqlite_fk_{}_{}_{}",
+            fk.child.name,
+            fk.id,
+            if mode == OperationMode::DELETE {
+                "delete"
+            } else {
+                "update"
+            }
+        ),
+        table_name: fk.parent.name.clone(),
+        sql: command.to_string(),
+        time: TriggerTime::After,
+        event,
+        for_each_row: true,
+        when_clause,
+        commands: vec![command],
+    }
+}
+
+/// Jumps to `target_pc` if every register of `old_key` IS the corresponding register of
+/// `new_key`.
+fn emit_jump_if_key_unchanged(
+    program: &mut ProgramBuilder,
+    old_key: &[usize],
+    new_key: &[usize],
+    target_pc: BranchOffset,
+) {
+    let changed_label = program.allocate_label();
+    for (&old, &new) in old_key.iter().zip(new_key) {
+        program.emit_insn(Insn::Ne {
+            lhs: old,
+            rhs: new,
+            target_pc: changed_label,
+            flags: CmpInsFlags::default().null_eq(),
+            collation: None,
+        });
+    }
+    program.emit_insn(Insn::Goto { target_pc });
+    program.preassign_label_to_next_insn(changed_label);
+}
+
+/// Emits a check that the parent row of the child key of `row` exists. If it does not, the
+/// violation counter of the constraint is incremented by `amount`: +1 for a child key being
+/// added, -1 for a child key being removed.
+fn emit_child_check(
+    program: &mut ProgramBuilder,
+    fk: &ResolvedForeignKey,
+    row: RowImage,
+    amount: i64,
+) {
+    let ok_label = program.allocate_label();
+    let child_key = fk.child_key(row);
+    // A child key with a NULL column never needs a parent.
+    for &reg in child_key.iter() {
+        program.emit_insn(Insn::IsNull {
+            reg,
+            target_pc: ok_label,
+        });
+    }
+    if amount < 0 {
+        // Removing a child key can only fix violations, so there is nothing to do if none
+        // are outstanding.
+        program.emit_insn(Insn::FkIfZero {
+            deferred: fk.fk.deferred,
+            target_pc: ok_label,
+        });
+    }
+    if amount > 0 && fk.is_self_referencing() {
+        // A row inserted into a self-referencing table may be its own parent.
+        let not_own_parent_label = program.allocate_label();
+        for (&child_reg, parent_reg) in child_key.iter().zip(fk.parent_key(row)) {
+            program.emit_insn(Insn::Ne {
+                lhs: child_reg,
+                rhs: parent_reg,
+                target_pc: not_own_parent_label,
+                flags: CmpInsFlags::default().jump_if_null(),
+                collation: None,
+            });
+        }
+        program.emit_insn(Insn::Goto {
+            target_pc: ok_label,
+        });
+        program.preassign_label_to_next_insn(not_own_parent_label);
+    }
+    let missing_label = program.allocate_label();
+    emit_parent_lookup(program, fk, &child_key, missing_label);
+    program.emit_insn(Insn::Goto {
+        target_pc: ok_label,
+    });
+    program.preassign_label_to_next_insn(missing_label);
+    program.emit_insn(Insn::FkCounter {
+        deferred: fk.fk.deferred,
+        amount,
+    });
+    program.preassign_label_to_next_insn(ok_label);
+}
+
+/// Looks up the parent row with the key in `key_regs` and jumps to `missing_label` if there is
+/// none; falls through otherwise.
+fn emit_parent_lookup(
+    program: &mut ProgramBuilder,
+    fk: &ResolvedForeignKey,
+    key_regs: &[usize],
+    missing_label: BranchOffset,
+) {
+    let found_label = program.allocate_label();
+    let not_found_label = program.allocate_label();
+    let cursor_id = match &fk.parent_index {
+        None => {
+            let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(fk.parent.clone()));
+            program.emit_insn(Insn::OpenRead {
+                cursor_id,
+                root_page: fk.parent.root_page,
+                db: 0,
+            });
+            program.emit_insn(Insn::SeekRowid {
+                cursor_id,
+                src_reg: key_regs[0],
+                target_pc: not_found_label,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: found_label,
+            });
+            cursor_id
+        }
+        Some(index) => {
+            let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+            program.emit_insn(Insn::OpenRead {
+                cursor_id,
+                root_page: index.root_page,
+                db: 0,
+            });
+            // The key must be in index column order, with the affinities of the parent key.
+            let start_reg = program.alloc_registers(index.columns.len());
+            let mut affinities = String::with_capacity(index.columns.len());
+            for (i, column) in index.columns.iter().enumerate() {
+                let j = fk
+                    .parent_columns
+                    .iter()
+                    .position(|&idx| idx == column.pos_in_table)
+                    .expect("index covers the parent key");
+                program.emit_insn(Insn::Copy {
+                    src_reg: key_regs[j],
+                    dst_reg: start_reg + i,
+                    extra_amount: 0,
+                });
+                affinities.push(fk.parent.columns[column.pos_in_table].affinity().aff_mask());
+            }
+            program.emit_insn(Insn::Affinity {
+                start_reg,
+                count: NonZeroUsize::new(index.columns.len()).expect("nonempty parent key"),
+                affinities,
+            });
+            program.emit_insn(Insn::Found {
+                cursor_id,
+                target_pc: found_label,
+                record_reg: start_reg,
+                num_regs: index.columns.len(),
+            });
+            cursor_id
+        }
+    };
+    program.preassign_label_to_next_insn(not_found_label);
+    program.emit_insn(Insn::Close { cursor_id });
+    program.emit_insn(Insn::Goto {
+        target_pc: missing_label,
+    });
+    program.preassign_label_to_next_insn(found_label);
+    program.emit_insn(Insn::Close { cursor_id });
+}
+
+/// What to do for each child row found by [emit_children_scan].
+#[derive(Debug, Clone, Copy)]
+enum ChildAction {
+    /// Add the amount to the violation counter of the constraint.
+    Count(i64),
+    /// Fail the statement right away, for ON DELETE/UPDATE RESTRICT.
+    Restrict,
+}
+
+/// Scans the child rows referring to the parent key of `row` and runs `action` for each of
+/// them, except for `excluded_row`.
+fn emit_children_scan(
+    program: &mut ProgramBuilder,
+    fk: &ResolvedForeignKey,
+    row: RowImage,
+    excluded_row: Option<RowImage>,
+    action: ChildAction,
+) {
+    let done_label = program.allocate_label();
+    let parent_key = fk.parent_key(row);
+    // No child row can refer to a parent key with a NULL column.
+    for &reg in parent_key.iter() {
+        program.emit_insn(Insn::IsNull {
+            reg,
+            target_pc: done_label,
+        });
+    }
+    if let ChildAction::Count(amount) = action {
+        if amount < 0 {
+            program.emit_insn(Insn::FkIfZero {
+                deferred: fk.fk.deferred,
+                target_pc: done_label,
+            });
+        }
+    }
+
+    let loop_label = program.allocate_label();
+    let next_label = program.allocate_label();
+    let close_label = program.allocate_label();
+    let key_len = parent_key.len();
+    // Use an index on the child key if there is one, otherwise scan the whole child table.
+    let cursor_id = if let Some(index) = &fk.child_index {
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+        program.emit_insn(Insn::OpenRead {
+            cursor_id,
+            root_page: index.root_page,
+            db: 0,
+        });
+        let start_reg = program.alloc_registers(key_len);
+        let mut affinities = String::with_capacity(key_len);
+        for (i, column) in index.columns.iter().take(key_len).enumerate() {
+            let j = fk
+                .child_columns
+                .iter()
+                .position(|&idx| idx == column.pos_in_table)
+                .expect("index starts with the child key");
+            program.emit_insn(Insn::Copy {
+                src_reg: parent_key[j],
+                dst_reg: start_reg + i,
+                extra_amount: 0,
+            });
+            affinities.push(fk.child.columns[column.pos_in_table].affinity().aff_mask());
+        }
+        program.emit_insn(Insn::Affinity {
+            start_reg,
+            count: NonZeroUsize::new(key_len).expect("nonempty child key"),
+            affinities,
+        });
+        program.emit_insn(Insn::SeekGE {
+            is_index: true,
+            cursor_id,
+            start_reg,
+            num_regs: key_len,
+            target_pc: close_label,
+            eq_only: true,
+        });
+        program.preassign_label_to_next_insn(loop_label);
+        program.emit_insn(Insn::IdxGT {
+            cursor_id,
+            start_reg,
+            num_regs: key_len,
+            target_pc: close_label,
+        });
+        if let Some(excluded_row) = excluded_row {
+            emit_jump_if_same_child_row(
+                program,
+                fk,
+                cursor_id,
+                Some(index),
+                excluded_row,
+                next_label,
+            );
+        }
+        cursor_id
+    } else {
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(fk.child.clone()));
+        program.emit_insn(Insn::OpenRead {
+            cursor_id,
+            root_page: fk.child.root_page,
+            db: 0,
+        });
+        program.emit_insn(Insn::Rewind {
+            cursor_id,
+            pc_if_empty: close_label,
+        });
+        program.preassign_label_to_next_insn(loop_label);
+        let column_reg = program.alloc_register();
+        for (j, &idx) in fk.child_columns.iter().enumerate() {
+            let child_column = &fk.child.columns[idx];
+            program.emit_column_or_rowid(cursor_id, idx, column_reg);
+            program.emit_insn(Insn::Ne {
+                lhs: column_reg,
+                rhs: parent_key[j],
+                target_pc: next_label,
+                flags: CmpInsFlags::default()
+                    .jump_if_null()
+                    .with_affinity(child_column.affinity()),
+                collation: fk.parent.columns[fk.parent_columns[j]].collation,
+            });
+        }
+        if let Some(excluded_row) = excluded_row {
+            emit_jump_if_same_child_row(program, fk, cursor_id, None, excluded_row, next_label);
+        }
+        cursor_id
+    };
+
+    match action {
+        ChildAction::Count(amount) => program.emit_insn(Insn::FkCounter {
+            deferred: fk.fk.deferred,
+            amount,
+        }),
+        ChildAction::Restrict => program.emit_insn(Insn::Halt {
+            err_code: SQLITE_CONSTRAINT_FOREIGNKEY,
+            description: String::new(),
+        }),
+    }
+    program.preassign_label_to_next_insn(next_label);
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_label,
+    });
+    program.preassign_label_to_next_insn(close_label);
+    program.emit_insn(Insn::Close { cursor_id });
+    program.preassign_label_to_next_insn(done_label);
+}
+
+/// Jumps to `target_pc` if the child row `cursor_id` is positioned at is `row`, i.e. has the
+/// same rowid, or the same PRIMARY KEY for a WITHOUT ROWID table. `index` is the index the cursor
+/// is open on, if it is not a cursor on the child table.
+fn emit_jump_if_same_child_row(
+    program: &mut ProgramBuilder,
+    fk: &ResolvedForeignKey,
+    cursor_id: usize,
+    index: Option<&Index>,
+    row: RowImage,
+    target_pc: BranchOffset,
+) {
+    let child = &fk.child;
+    let (cursor_key, row_key) = if child.has_rowid {
+        let rowid_reg = program.alloc_register();
+        program.emit_insn(match index {
+            Some(_) => Insn::IdxRowId {
+                cursor_id,
+                dest: rowid_reg,
+            },
+            None => Insn::RowId {
+                cursor_id,
+                dest: rowid_reg,
+            },
+        });
+        (vec![rowid_reg], vec![row.rowid_reg])
+    } else {
+        let columns = Index::without_rowid_primary_key(child)
+            .columns
+            .iter()
+            .map(|column| column.pos_in_table)
+            .collect::<Vec<_>>();
+        let positions = match index {
+            Some(index) => index.primary_key_positions(child),
+            None => columns.clone(),
+        };
+        let start_reg = program.alloc_registers(positions.len());
+        for (i, &pos) in positions.iter().enumerate() {
+            program.emit_column_or_rowid(cursor_id, pos, start_reg + i);
+        }
+        (
+            (start_reg..start_reg + positions.len()).collect(),
+            columns
+                .iter()
+                .map(|&idx| row.column_reg(child, idx))
+                .collect(),
+        )
+    };
+    emit_jump_if_key_unchanged(program, &cursor_key, &row_key, target_pc);
+}
+
+/// Generates the bytecode for `PRAGMA foreign_key_check`, which reports a
+/// `(table, rowid, parent, fkid)` row for every row of `table` (or of every table) that
+/// violates one of its foreign keys.
+pub fn translate_foreign_key_check(
+    schema: &Schema,
+    table: Option<&str>,
+    program: &mut ProgramBuilder,
+) -> Result<()> {
+    let children = match table {
+        Some(name) => match schema.get_btree_table(name) {
+            Some(table) => vec![table],
+            None => bail_parse_error!("no such table: {}", name),
+        },
+        None => {
+            let mut tables = schema
+                .tables
+                .values()
+                .filter_map(|table| table.btree())
+                .collect::<Vec<_>>();
+            tables.sort_by(|a, b| a.name.cmp(&b.name));
+            tables
+        }
+    };
+
+    let result_reg = program.alloc_registers(4);
+    for child in children {
+        if child.foreign_keys.is_empty() {
+            continue;
+        }
+        // A foreign key whose parent table does not exist is violated by every child key.
+        let fks = (0..child.foreign_keys.len())
+            .rev()
+            .map(|idx| {
+                match schema.get_btree_table(&child.foreign_keys[idx].parent_table) {
+                    Some(parent) => {
+                        ResolvedForeignKey::with_parent(schema, child.clone(), idx, parent)
+                            .map(Some)
+                    }
+                    None => Ok(None),
+                }
+                .map(|fk| (idx, fk))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(child.clone()));
+        program.emit_insn(Insn::OpenRead {
+            cursor_id,
+            root_page: child.root_page,
+            db: 0,
+        });
+        let loop_label = program.allocate_label();
+        let done_label = program.allocate_label();
+        program.emit_insn(Insn::Rewind {
+            cursor_id,
+            pc_if_empty: done_label,
+        });
+        program.preassign_label_to_next_insn(loop_label);
+        for (idx, fk) in fks {
+            let foreign_key = &child.foreign_keys[idx];
+            let ok_label = program.allocate_label();
+            let key_regs = foreign_key
+                .child_columns
+                .iter()
+                .map(|name| {
+                    let (column_idx, _) = child
+                        .get_column(name)
+                        .expect("foreign key columns are validated");
+                    let reg = program.alloc_register();
+                    program.emit_column_or_rowid(cursor_id, column_idx, reg);
+                    program.emit_insn(Insn::IsNull {
+                        reg,
+                        target_pc: ok_label,
+                    });
+                    reg
+                })
+                .collect::<Vec<_>>();
+            if let Some(fk) = &fk {
+                let missing_label = program.allocate_label();
+                emit_parent_lookup(program, fk, &key_regs, missing_label);
+                program.emit_insn(Insn::Goto {
+                    target_pc: ok_label,
+                });
+                program.preassign_label_to_next_insn(missing_label);
+            }
+            program.emit_string8(child.name.clone(), result_reg);
+            // Like in SQLite, the rows of a WITHOUT ROWID table are reported with a NULL rowid.
+            if child.has_rowid {
+                program.emit_insn(Insn::RowId {
+                    cursor_id,
+                    dest: result_reg + 1,
+                });
+            } else {
+                program.emit_null(result_reg + 1, None);
+            }
+            program.emit_string8(foreign_key.parent_table_str.clone(), result_reg + 2);
+            program.emit_int((child.foreign_keys.len() - 1 - idx) as i64, result_reg + 3);
+            program.emit_result_row(result_reg, 4);
+            program.preassign_label_to_next_insn(ok_label);
+        }
+        program.emit_insn(Insn::Next {
+            cursor_id,
+            pc_if_next: loop_label,
+        });
+        program.preassign_label_to_next_insn(done_label);
+        program.emit_insn(Insn::Close { cursor_id });
+    }
+    Ok(())
+}
@@ -9,7 +9,7 @@ use super::{
 };
 use crate::translate::plan::ResultSetColumn;
 use crate::translate::{
-    aggregation::{translate_aggregation_step, AggArgumentSource},
+    aggregation::{emit_ordered_aggregation_steps, translate_aggregation_step, AggArgumentSource},
     plan::Aggregate,
 };
 use crate::translate::{
@@ -354,6 +354,16 @@ fn collect_result_columns<'a>(
                     result_columns.push(expr);
                 }
             }
+            // A correlated subquery is evaluated in the loop over the tables it refers to, so
+            // its result is stored with the other columns of the row.
+            ast::Expr::SubqueryResult { subquery_id, .. }
+                if plan.non_from_clause_subqueries.iter().any(|subquery| {
+                    subquery.internal_id == *subquery_id && !subquery.correlated_tables.is_empty()
+                }) =>
+            {
+                result_columns.push(expr);
+                return Ok(WalkControl::SkipChildren);
+            }
             _ => {
                 if plan.aggregates.iter().any(|a| a.original_expr == *expr) {
                     return Ok(WalkControl::SkipChildren);
@@ -592,7 +602,7 @@ pub fn group_by_process_single_group(
                 .expect("distinct aggregate context not populated");
             program.preassign_label_to_next_insn(ctx.label_on_conflict);
         }
-        offset += agg.args.len();
+        offset += agg.num_inputs();
     }
 
     // We only need to store non-aggregate columns once per group
@@ -763,6 +773,13 @@ pub fn group_by_emit_row_phase<'a>(
             .reg_agg_start
             .expect("aggregate registers must be initialized");
         let agg_result_reg = agg_start_reg + i;
+        emit_ordered_aggregation_steps(
+            program,
+            &plan.table_references,
+            agg,
+            agg_result_reg,
+            &t_ctx.resolver,
+        )?;
         program.emit_insn(Insn::AggFinal {
             register: agg_result_reg,
             func: agg.func.clone(),
@@ -850,6 +867,18 @@ pub fn group_by_emit_row_phase<'a>(
             });
         });
 
+    // Likewise for the ephemeral indexes buffering the arguments of ordered aggregates.
+    for ctx in plan
+        .aggregates
+        .iter()
+        .filter_map(|agg| agg.order_by_ctx.as_ref())
+    {
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id: ctx.cursor_id,
+            is_table: false,
+        });
+    }
+
     program.emit_insn(Insn::Integer {
         value: 0,
         dest: registers.reg_data_in_acc_flag,
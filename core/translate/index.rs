@@ -1,10 +1,15 @@
 use std::sync::Arc;
 
+use crate::function::Func;
 use crate::schema::Table;
+use crate::translate::collate::CollationSeq;
 use crate::translate::emitter::{
     emit_cdc_full_record, emit_cdc_insns, prepare_cdc_if_necessary, OperationMode, Resolver,
 };
-use crate::translate::expr::{translate_condition_expr, ConditionMetadata};
+use crate::translate::expr::{
+    emit_table_column, translate_condition_expr, translate_expr_no_constant_opt, walk_expr,
+    ConditionMetadata, NoConstantOptReason, WalkControl,
+};
 use crate::translate::plan::{
     ColumnUsedMask, IterationDirection, JoinedTable, Operation, Scan, TableReferences,
 };
@@ -13,7 +18,7 @@ use crate::vdbe::insn::{CmpInsFlags, Cookie};
 use crate::vdbe::BranchOffset;
 use crate::SymbolTable;
 use crate::{
-    schema::{BTreeTable, Column, Index, IndexColumn, PseudoCursorType, Schema},
+    schema::{BTreeTable, Index, IndexColumn, PseudoCursorType, Schema},
     storage::pager::CreateBTreeFlags,
     util::normalize_ident,
     vdbe::{
@@ -23,6 +28,7 @@ use crate::{
 };
 use turso_parser::ast::{self, Expr, SortOrder, SortedColumn};
 
+use super::analyze::emit_clear_stat_rows;
 use super::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
 
 #[allow(clippy::too_many_arguments)]
@@ -31,6 +37,7 @@ pub fn translate_create_index(
     idx_name: &str,
     tbl_name: &str,
     columns: &[SortedColumn],
+    database_id: usize,
     schema: &Schema,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
@@ -69,25 +76,17 @@ pub fn translate_create_index(
     let Some(tbl) = table.btree() else {
         crate::bail_parse_error!("Error: table '{tbl_name}' is not a b-tree table.");
     };
-    let columns = resolve_sorted_columns(&tbl, columns)?;
-
+    let index_columns = resolve_sorted_columns(&tbl, columns)?;
+    let primary_key_suffix = Index::primary_key_suffix_for(&tbl, &index_columns);
     let idx = Arc::new(Index {
         name: idx_name.clone(),
         table_name: tbl.name.clone(),
         root_page: 0, //  we dont have access till its created, after we parse the schema table
-        columns: columns
-            .iter()
-            .map(|((pos_in_table, col), order)| IndexColumn {
-                name: col.name.as_ref().unwrap().clone(),
-                order: *order,
-                pos_in_table: *pos_in_table,
-                collation: col.collation,
-                default: col.default.clone(),
-            })
-            .collect(),
+        columns: index_columns,
         unique: unique_if_not_exists.0,
         ephemeral: false,
         has_rowid: tbl.has_rowid,
+        primary_key_suffix,
         // store the *original* where clause, because we need to rewrite it
         // before translating, and it cannot reference a table alias
         where_clause: where_clause.clone(),
@@ -102,48 +101,14 @@ pub fn translate_create_index(
         );
     }
 
-    // Allocate the necessary cursors:
-    //
-    // 1. sqlite_schema_cursor_id - sqlite_schema table
-    // 2. btree_cursor_id         - new index btree
-    // 3. table_cursor_id         - table we are creating the index on
-    // 4. sorter_cursor_id        - sorter
-    // 5. pseudo_cursor_id        - pseudo table to store the sorted index values
     let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
     let sqlite_schema_cursor_id =
         program.alloc_cursor_id(CursorType::BTreeTable(sqlite_table.clone()));
-    let table_ref = program.table_reference_counter.next();
-    let btree_cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(idx.clone()));
-    let table_cursor_id = program.alloc_cursor_id_keyed(
-        CursorKey::table(table_ref),
-        CursorType::BTreeTable(tbl.clone()),
-    );
-    let sorter_cursor_id = program.alloc_cursor_id(CursorType::Sorter);
-    let pseudo_cursor_id = program.alloc_cursor_id(CursorType::Pseudo(PseudoCursorType {
-        column_count: tbl.columns.len(),
-    }));
-
-    let mut table_references = TableReferences::new(
-        vec![JoinedTable {
-            op: Operation::Scan(Scan::BTreeTable {
-                iter_dir: IterationDirection::Forwards,
-                index: None,
-            }),
-            table: Table::BTree(tbl.clone()),
-            identifier: tbl_name.clone(),
-            internal_id: table_ref,
-            join_info: None,
-            col_used_mask: ColumnUsedMask::default(),
-            database_id: 0,
-        }],
-        vec![],
-    );
-    let where_clause = idx.bind_where_expr(Some(&mut table_references), connection);
 
     // Create a new B-Tree and store the root page index in a register
     let root_page_reg = program.alloc_register();
     program.emit_insn(Insn::CreateBtree {
-        db: 0,
+        db: database_id,
         root: root_page_reg,
         flags: CreateBTreeFlags::new_index(),
     });
@@ -152,13 +117,13 @@ pub fn translate_create_index(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
-        db: 0,
+        db: database_id,
     });
     let sql = create_idx_stmt_to_sql(
         &tbl_name,
         &idx_name,
         unique_if_not_exists,
-        &columns,
+        &idx.columns,
         &idx.where_clause.clone(),
     );
     let resolver = Resolver::new(schema, syms);
@@ -175,27 +140,111 @@ pub fn translate_create_index(
         Some(sql),
     )?;
 
+    emit_index_build(
+        &mut program,
+        &resolver,
+        &tbl,
+        &idx,
+        RegisterOrLiteral::Register(root_page_reg),
+        database_id,
+        connection,
+    )?;
+
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    // Parse the schema table to get the index root page and add new index to Schema
+    let parse_schema_where_clause = format!("name = '{idx_name}' AND type = 'index'");
+    program.emit_insn(Insn::ParseSchema {
+        db: database_id,
+        where_clause: Some(parse_schema_where_clause),
+    });
+    // Close the final sqlite_schema cursor
+    program.emit_insn(Insn::Close {
+        cursor_id: sqlite_schema_cursor_id,
+    });
+
+    Ok(program)
+}
+
+/// Emits the filling of the empty b-tree of `idx`, whose root page is `root_page`, with the
+/// entries of the rows of `tbl`. The entries are gathered in a sorter first, so that they are
+/// appended to the b-tree in order rather than inserted one row at a time.
+fn emit_index_build(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    tbl: &Arc<BTreeTable>,
+    idx: &Arc<Index>,
+    root_page: RegisterOrLiteral<usize>,
+    database_id: usize,
+    connection: &Arc<crate::Connection>,
+) -> crate::Result<()> {
+    // Allocate the necessary cursors:
+    //
+    // 1. btree_cursor_id         - index btree
+    // 2. table_cursor_id         - table of the index
+    // 3. sorter_cursor_id        - sorter
+    // 4. pseudo_cursor_id        - pseudo table to store the sorted index values
+    let table_ref = program.table_reference_counter.next();
+    let btree_cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(idx.clone()));
+    let table_cursor_id = program.alloc_cursor_id_keyed(
+        CursorKey::table(table_ref),
+        CursorType::BTreeTable(tbl.clone()),
+    );
+    let sorter_cursor_id = program.alloc_cursor_id(CursorType::Sorter);
+    let pseudo_cursor_id = program.alloc_cursor_id(CursorType::Pseudo(PseudoCursorType {
+        column_count: tbl.columns.len(),
+    }));
+
+    let mut table_references = TableReferences::new(
+        vec![JoinedTable {
+            op: Operation::Scan(Scan::BTreeTable {
+                iter_dir: IterationDirection::Forwards,
+                index: None,
+            }),
+            table: Table::BTree(tbl.clone()),
+            identifier: tbl.name.clone(),
+            internal_id: table_ref,
+            join_info: None,
+            col_used_mask: ColumnUsedMask::default(),
+            database_id,
+            indexed: None,
+        }],
+        vec![],
+    );
+    let where_clause = idx.bind_where_expr(Some(&mut table_references), connection);
+
     // determine the order of the columns in the index for the sorter
     let order = idx.columns.iter().map(|c| c.order).collect();
     // open the sorter and the pseudo table
     program.emit_insn(Insn::SorterOpen {
         cursor_id: sorter_cursor_id,
-        columns: columns.len(),
+        columns: idx.columns.len(),
         order,
         collations: idx.columns.iter().map(|c| c.collation).collect(),
     });
+    // The index records end with the rowid, or with the remaining PRIMARY KEY columns
+    // for a WITHOUT ROWID table.
+    let record_len = if tbl.has_rowid {
+        idx.columns.len() + 1
+    } else {
+        idx.columns.len() + idx.primary_key_suffix.len()
+    };
     let content_reg = program.alloc_register();
     program.emit_insn(Insn::OpenPseudo {
         cursor_id: pseudo_cursor_id,
         content_reg,
-        num_fields: columns.len() + 1,
+        num_fields: record_len,
     });
 
-    // open the table we are creating the index on for reading
+    // open the table of the index for reading
     program.emit_insn(Insn::OpenRead {
         cursor_id: table_cursor_id,
         root_page: tbl.root_page,
-        db: 0,
+        db: database_id,
     });
 
     let loop_start_label = program.allocate_label();
@@ -215,7 +264,7 @@ pub fn translate_create_index(
     if let Some(where_clause) = where_clause {
         let label = program.allocate_label();
         translate_condition_expr(
-            &mut program,
+            program,
             &table_references,
             &where_clause,
             ConditionMetadata {
@@ -223,26 +272,55 @@ pub fn translate_create_index(
                 jump_target_when_false: label,
                 jump_target_when_true: BranchOffset::Placeholder,
             },
-            &resolver,
+            resolver,
         )?;
         skip_row_label = Some(label);
     }
 
-    let start_reg = program.alloc_registers(columns.len() + 1);
-    for (i, (col, _)) in columns.iter().enumerate() {
-        program.emit_column_or_rowid(table_cursor_id, col.0, start_reg + i);
+    let start_reg = program.alloc_registers(record_len);
+    for (i, col) in idx.columns.iter().enumerate() {
+        match col.bind_expr(&table_references.joined_tables()[0].table, table_ref) {
+            Some(expr) => {
+                translate_expr_no_constant_opt(
+                    program,
+                    Some(&table_references),
+                    &expr,
+                    start_reg + i,
+                    resolver,
+                    NoConstantOptReason::RegisterReuse,
+                )?;
+            }
+            None => emit_table_column(
+                program,
+                tbl,
+                table_cursor_id,
+                col.pos_in_table,
+                start_reg + i,
+                resolver,
+            )?,
+        }
+    }
+    if tbl.has_rowid {
+        let rowid_reg = start_reg + idx.columns.len();
+        program.emit_insn(Insn::RowId {
+            cursor_id: table_cursor_id,
+            dest: rowid_reg,
+        });
+    } else {
+        for (i, col) in idx.primary_key_suffix.iter().enumerate() {
+            program.emit_column_or_rowid(
+                table_cursor_id,
+                col.pos_in_table,
+                start_reg + idx.columns.len() + i,
+            );
+        }
     }
-    let rowid_reg = start_reg + columns.len();
-    program.emit_insn(Insn::RowId {
-        cursor_id: table_cursor_id,
-        dest: rowid_reg,
-    });
     let record_reg = program.alloc_register();
     program.emit_insn(Insn::MakeRecord {
         start_reg,
-        count: columns.len() + 1,
+        count: record_len,
         dest_reg: record_reg,
-        index_name: Some(idx_name.clone()),
+        index_name: Some(idx.name.clone()),
         affinity_str: None,
     });
     program.emit_insn(Insn::SorterInsert {
@@ -259,12 +337,12 @@ pub fn translate_create_index(
     });
     program.preassign_label_to_next_insn(loop_end_label);
 
-    // Open the index btree we created for writing to insert the
+    // Open the empty index btree for writing to insert the
     // newly sorted index records.
     program.emit_insn(Insn::OpenWrite {
         cursor_id: btree_cursor_id,
-        root_page: RegisterOrLiteral::Register(root_page_reg),
-        db: 0,
+        root_page,
+        db: database_id,
     });
 
     let sorted_loop_start = program.allocate_label();
@@ -301,62 +379,94 @@ pub fn translate_create_index(
     });
     program.preassign_label_to_next_insn(sorted_loop_end);
 
-    // End of the outer loop
-    //
-    // Keep schema table open to emit ParseSchema, close the other cursors.
     program.close_cursors(&[sorter_cursor_id, table_cursor_id, btree_cursor_id]);
 
-    program.emit_insn(Insn::SetCookie {
-        db: 0,
-        cookie: Cookie::SchemaVersion,
-        value: schema.schema_version as i32 + 1,
-        p5: 0,
-    });
-    // Parse the schema table to get the index root page and add new index to Schema
-    let parse_schema_where_clause = format!("name = '{idx_name}' AND type = 'index'");
-    program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
-        where_clause: Some(parse_schema_where_clause),
-    });
-    // Close the final sqlite_schema cursor
-    program.emit_insn(Insn::Close {
-        cursor_id: sqlite_schema_cursor_id,
-    });
-
-    Ok(program)
+    Ok(())
 }
 
-fn resolve_sorted_columns<'a>(
-    table: &'a BTreeTable,
+fn resolve_sorted_columns(
+    table: &BTreeTable,
     cols: &[SortedColumn],
-) -> crate::Result<Vec<((usize, &'a Column), SortOrder)>> {
+) -> crate::Result<Vec<IndexColumn>> {
     let mut resolved = Vec::with_capacity(cols.len());
     for sc in cols {
-        let ident = normalize_ident(match sc.expr.as_ref() {
-            // SQLite supports indexes on arbitrary expressions, but we don't (yet).
-            // See "How to use indexes on expressions" in https://www.sqlite.org/expridx.html
-            Expr::Id(ast::Name::Ident(col_name))
-            | Expr::Id(ast::Name::Quoted(col_name))
-            | Expr::Name(ast::Name::Ident(col_name))
-            | Expr::Name(ast::Name::Quoted(col_name)) => col_name,
-            _ => crate::bail_parse_error!("Error: cannot use expressions in CREATE INDEX"),
-        });
-        let Some(col) = table.get_column(&ident) else {
+        let order = sc.order.unwrap_or(SortOrder::Asc);
+        let (Expr::Id(col_name) | Expr::Name(col_name)) = sc.expr.as_ref() else {
+            // See "Indexes On Expressions" in https://www.sqlite.org/expridx.html
+            validate_index_expr(table, &sc.expr)?;
+            resolved.push(IndexColumn::from_expr(sc.expr.clone(), order)?);
+            continue;
+        };
+        let ident = normalize_ident(col_name.as_str());
+        let Some((pos_in_table, col)) = table.get_column(&ident) else {
             crate::bail_parse_error!(
                 "Error: column '{ident}' does not exist in table '{}'",
                 table.name
             );
         };
-        resolved.push((col, sc.order.unwrap_or(SortOrder::Asc)));
+        resolved.push(IndexColumn {
+            name: col.name.as_ref().unwrap().clone(),
+            order,
+            pos_in_table,
+            collation: col.collation,
+            default: col.default.clone(),
+            expr: None,
+        });
     }
     Ok(resolved)
 }
 
+/// Checks that an expression of an index only refers to the columns of the table, and that its
+/// value only depends on them.
+fn validate_index_expr(table: &BTreeTable, expr: &Expr) -> crate::Result<()> {
+    walk_expr(expr, &mut |e: &Expr| -> crate::Result<WalkControl> {
+        match e {
+            Expr::Id(name) | Expr::Name(name) => {
+                if table.get_column(&normalize_ident(name.as_str())).is_none() {
+                    crate::bail_parse_error!("no such column: {}", name.as_str());
+                }
+            }
+            Expr::Qualified(..) | Expr::DoublyQualified(..) => {
+                crate::bail_parse_error!("the \".\" operator prohibited in index expressions");
+            }
+            Expr::Exists(_) | Expr::InSelect { .. } | Expr::Subquery(_) => {
+                crate::bail_parse_error!("subqueries prohibited in index expressions");
+            }
+            Expr::Variable(_) => {
+                crate::bail_parse_error!("parameters prohibited in index expressions");
+            }
+            Expr::FunctionCall {
+                name, filter_over, ..
+            }
+            | Expr::FunctionCallStar {
+                name, filter_over, ..
+            } => {
+                let arg_count = match e {
+                    Expr::FunctionCall { args, .. } => args.len(),
+                    _ => 0,
+                };
+                let func = Func::resolve_function(name.as_str(), arg_count)?;
+                if matches!(func, Func::Agg(_)) || filter_over.over_clause.is_some() {
+                    crate::bail_parse_error!("misuse of aggregate function {}()", name.as_str());
+                }
+                if !func.is_deterministic() {
+                    crate::bail_parse_error!(
+                        "non-deterministic functions prohibited in index expressions"
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(())
+}
+
 fn create_idx_stmt_to_sql(
     tbl_name: &str,
     idx_name: &str,
     unique_if_not_exists: (bool, bool),
-    cols: &[((usize, &Column), SortOrder)],
+    cols: &[IndexColumn],
     where_clause: &Option<Box<Expr>>,
 ) -> String {
     let mut sql = String::with_capacity(128);
@@ -372,12 +482,12 @@ fn create_idx_stmt_to_sql(
     sql.push_str(" ON ");
     sql.push_str(tbl_name);
     sql.push_str(" (");
-    for (i, (col, order)) in cols.iter().enumerate() {
+    for (i, col) in cols.iter().enumerate() {
         if i > 0 {
             sql.push_str(", ");
         }
-        sql.push_str(col.1.name.as_ref().unwrap());
-        if *order == SortOrder::Desc {
+        sql.push_str(&col.name);
+        if col.order == SortOrder::Desc {
             sql.push_str(" DESC");
         }
     }
@@ -392,6 +502,7 @@ fn create_idx_stmt_to_sql(
 pub fn translate_drop_index(
     idx_name: &str,
     if_exists: bool,
+    database_id: usize,
     schema: &Schema,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
@@ -469,7 +580,7 @@ pub fn translate_drop_index(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
-        db: 0,
+        db: database_id,
     });
 
     let loop_start_label = program.allocate_label();
@@ -557,7 +668,7 @@ pub fn translate_drop_index(
     program.resolve_label(loop_end_label, program.offset());
 
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
         value: schema.schema_version as i32 + 1,
         p5: 0,
@@ -567,16 +678,144 @@ pub fn translate_drop_index(
     program.emit_insn(Insn::Destroy {
         root: maybe_index.unwrap().root_page,
         former_root_reg: 0,
-        is_temp: 0,
+        db: database_id,
     });
 
+    // The statistics of the index go with it.
+    if let Some(idx) = maybe_index {
+        emit_clear_stat_rows(&mut program, schema, database_id, 1, &idx.name);
+    }
+
     // Remove from the Schema any mention of the index
     if let Some(idx) = maybe_index {
         program.emit_insn(Insn::DropIndex {
             index: idx.clone(),
-            db: 0,
+            db: database_id,
         });
     }
 
     Ok(program)
 }
+
+/// Translates REINDEX. Without a name, every index of every database is rebuilt. Otherwise the
+/// name is that of a collation, whose indexes are rebuilt in every database, or that of a table,
+/// whose indexes are rebuilt, or that of a single index.
+pub fn translate_reindex(
+    name: Option<ast::QualifiedName>,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+    connection: &Arc<crate::Connection>,
+) -> crate::Result<ProgramBuilder> {
+    // Unlike ANALYZE, REINDEX covers the temp database too, which is not attached by name.
+    let mut databases = vec![0];
+    if connection.is_temp_database_open() {
+        databases.push(1);
+    }
+    databases.extend(connection.attached_database_indexes());
+
+    let Some(name) = name else {
+        for db in databases {
+            let db_schema = connection.get_database_schema(db)?;
+            let db_schema = if db == 0 { schema } else { &db_schema };
+            emit_reindex(&mut program, db_schema, syms, db, connection, |_| true)?;
+        }
+        return Ok(program);
+    };
+    let object_name = normalize_ident(name.name.as_str());
+
+    // Like SQLite, an unqualified name is looked up as a collation first.
+    if name.db_name.is_none() {
+        if let Ok(collation) = CollationSeq::new(&object_name) {
+            for db in databases {
+                let db_schema = connection.get_database_schema(db)?;
+                let db_schema = if db == 0 { schema } else { &db_schema };
+                emit_reindex(&mut program, db_schema, syms, db, connection, |index| {
+                    index
+                        .columns
+                        .iter()
+                        .any(|column| column.collation.unwrap_or_default() == collation)
+                })?;
+            }
+            return Ok(program);
+        }
+    }
+
+    let db = connection.resolve_database_id(&name)?;
+    let db_schema = connection.get_database_schema(db)?;
+    let db_schema = if db == 0 { schema } else { &db_schema };
+    if db_schema.get_table(&object_name).is_some() {
+        emit_reindex(&mut program, db_schema, syms, db, connection, |index| {
+            index.table_name == object_name
+        })?;
+        return Ok(program);
+    }
+
+    let index_db = connection.resolve_index_database_id(&name)?;
+    let index_schema = connection.get_database_schema(index_db)?;
+    let index_schema = if index_db == 0 { schema } else { &index_schema };
+    if index_schema.is_unique_idx_name(&object_name) {
+        crate::bail_parse_error!("unable to identify the object to be reindexed");
+    }
+    emit_reindex(
+        &mut program,
+        index_schema,
+        syms,
+        index_db,
+        connection,
+        |index| index.name == object_name,
+    )?;
+    Ok(program)
+}
+
+/// Emits the rebuilding of the indexes of a database that match `filter`. Each index b-tree is
+/// emptied, keeping its root page, and filled again from the rows of its table.
+fn emit_reindex(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    syms: &SymbolTable,
+    db: usize,
+    connection: &Arc<crate::Connection>,
+    filter: impl Fn(&Index) -> bool,
+) -> crate::Result<()> {
+    let mut indexes: Vec<&Arc<Index>> = schema
+        .indexes
+        .values()
+        .flatten()
+        .filter(|index| !index.ephemeral && filter(index))
+        .collect();
+    if indexes.is_empty() {
+        return Ok(());
+    }
+    indexes.sort_by(|a, b| a.name.cmp(&b.name));
+    program.begin_write_operation_on(db);
+
+    let resolver = Resolver::new(schema, syms);
+    for index in indexes {
+        let Some(table) = schema.get_btree_table(&index.table_name) else {
+            continue;
+        };
+        // The PRIMARY KEY of a WITHOUT ROWID table is the table itself.
+        if index.is_without_rowid_primary_key_of(&table) {
+            continue;
+        }
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+        program.emit_insn(Insn::OpenWrite {
+            cursor_id,
+            root_page: RegisterOrLiteral::Literal(index.root_page),
+            db,
+        });
+        program.emit_insn(Insn::ResetSorter { cursor_id });
+        program.emit_insn(Insn::Close { cursor_id });
+        emit_index_build(
+            program,
+            &resolver,
+            &table,
+            index,
+            RegisterOrLiteral::Literal(index.root_page),
+            db,
+            connection,
+        )?;
+    }
+    Ok(())
+}
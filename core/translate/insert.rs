@@ -1,23 +1,28 @@
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 use turso_parser::ast::{
-    self, Expr, InsertBody, OneSelect, QualifiedName, ResolveType, ResultColumn, Upsert, UpsertDo,
-    With,
+    self, Expr, InsertBody, OneSelect, QualifiedName, ResolveType, ResultColumn, TriggerTime,
+    Upsert, UpsertDo, With,
 };
 
 use crate::error::{
     SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY, SQLITE_CONSTRAINT_UNIQUE,
 };
-use crate::schema::{self, Index, Table};
+use crate::schema::{self, Index, IndexColumn, Table};
 use crate::translate::emitter::{
-    emit_cdc_insns, emit_cdc_patch_record, prepare_cdc_if_necessary, OperationMode,
+    emit_cdc_insns, emit_cdc_patch_record, emit_check_constraints, emit_generated_columns,
+    prepare_cdc_if_necessary, OperationMode,
 };
 use crate::translate::expr::{
     bind_and_rewrite_expr, emit_returning_results, process_returning_clause, walk_expr_mut,
     BindingBehavior, ParamState, ReturningValueRegisters, WalkControl,
 };
+use crate::translate::fkey::{emit_fk_checks, has_fks, RowImage};
 use crate::translate::plan::TableReferences;
 use crate::translate::planner::ROWID_STRS;
+use crate::translate::trigger::{
+    emit_triggers, has_triggers, translate_insert_into_view, TriggerRowRegisters,
+};
 use crate::translate::upsert::{
     collect_set_clauses_for_upsert, emit_upsert, resolve_upsert_target, ResolvedUpsertTarget,
 };
@@ -51,6 +56,7 @@ pub fn translate_insert(
     with: Option<With>,
     on_conflict: Option<ResolveType>,
     tbl_name: QualifiedName,
+    database_id: usize,
     columns: Vec<ast::Name>,
     mut body: InsertBody,
     mut returning: Vec<ResultColumn>,
@@ -64,8 +70,16 @@ pub fn translate_insert(
         approx_num_labels: 5,
     };
     program.extend(&opts);
-    if with.is_some() {
-        crate::bail_parse_error!("WITH clause is not supported");
+    // The CTEs of the WITH clause are in scope in the SELECT that produces the rows to insert,
+    // along with the CTEs of its own WITH clause.
+    if let (Some(with), InsertBody::Select(select, _)) = (with, &mut body) {
+        match &mut select.with {
+            Some(select_with) => {
+                select_with.recursive |= with.recursive;
+                select_with.ctes.splice(0..0, with.ctes);
+            }
+            None => select.with = Some(with),
+        }
     }
 
     if on_conflict.is_some() {
@@ -86,6 +100,15 @@ pub fn translate_insert(
         crate::bail_parse_error!("table {} may not be modified", table_name);
     }
 
+    let resolver = Resolver::new(schema, syms);
+
+    if let Some(view) = schema.get_view(table_name.as_str()) {
+        if !returning.is_empty() {
+            crate::bail_parse_error!("RETURNING is not supported for views");
+        }
+        return translate_insert_into_view(program, &resolver, connection, view, &columns, body);
+    }
+
     let table = match schema.get_table(table_name.as_str()) {
         Some(table) => table,
         None => crate::bail_parse_error!("no such table: {}", table_name),
@@ -96,8 +119,6 @@ pub fn translate_insert(
         crate::bail_parse_error!("cannot modify materialized view {}", table_name);
     }
 
-    let resolver = Resolver::new(schema, syms);
-
     if let Some(virtual_table) = &table.virtual_table() {
         program = translate_virtual_table_insert(
             program,
@@ -113,9 +134,6 @@ pub fn translate_insert(
     let Some(btree_table) = table.btree() else {
         crate::bail_parse_error!("no such table: {}", table_name);
     };
-    if !btree_table.has_rowid {
-        crate::bail_parse_error!("INSERT into WITHOUT ROWID table is not supported");
-    }
 
     let root_page = btree_table.root_page;
 
@@ -199,14 +217,25 @@ pub fn translate_insert(
     };
 
     if inserting_multiple_rows && btree_table.has_autoincrement {
-        ensure_sequence_initialized(&mut program, schema, &btree_table)?;
+        ensure_sequence_initialized(&mut program, schema, &btree_table, database_id)?;
     }
 
+    let has_insert_triggers = has_triggers(
+        schema,
+        table_name.as_str(),
+        OperationMode::INSERT,
+        None,
+        None,
+    );
+
     let halt_label = program.allocate_label();
     let loop_start_label = program.allocate_label();
     let row_done_label = program.allocate_label();
 
     let cdc_table = prepare_cdc_if_necessary(&mut program, schema, table.get_name())?;
+    if cdc_table.is_some() && !btree_table.has_rowid {
+        crate::bail_parse_error!("capturing changes of WITHOUT ROWID tables is not supported");
+    }
 
     // Process RETURNING clause using shared module
     let (mut result_columns, _) = process_returning_clause(
@@ -267,7 +296,7 @@ pub fn translate_insert(
                  ** of the tables being read by the SELECT statement.  Also use a
                  ** temp table in the case of row triggers.
                  */
-                if program.is_table_open(&table) {
+                if program.is_table_open(&table) || has_insert_triggers {
                     let temp_cursor_id =
                         program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone()));
                     temp_table_ctx = Some(TempTableCtx {
@@ -298,7 +327,7 @@ pub fn translate_insert(
                         btree_table
                             .columns
                             .iter()
-                            .filter(|col| !col.hidden)
+                            .filter(|col| !col.hidden && col.generated.is_none())
                             .map(|col| col.affinity().aff_mask())
                             .collect::<String>()
                     } else {
@@ -350,13 +379,13 @@ pub fn translate_insert(
                     program.emit_insn(Insn::OpenWrite {
                         cursor_id,
                         root_page: RegisterOrLiteral::Literal(root_page),
-                        db: 0,
+                        db: database_id,
                     });
                 } else {
                     program.emit_insn(Insn::OpenWrite {
                         cursor_id,
                         root_page: RegisterOrLiteral::Literal(root_page),
-                        db: 0,
+                        db: database_id,
                     });
 
                     // Main loop
@@ -374,20 +403,20 @@ pub fn translate_insert(
             }
         }
         InsertBody::DefaultValues => {
-            let num_values = table.columns().len();
             values = Some(
                 table
                     .columns()
                     .iter()
+                    .filter(|c| c.generated.is_none())
                     .map(|c| {
                         c.default
                             .clone()
                             .unwrap_or(Box::new(ast::Expr::Literal(ast::Literal::Null)))
                     })
-                    .collect(),
+                    .collect::<Vec<_>>(),
             );
             (
-                num_values,
+                values.as_ref().unwrap().len(),
                 program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone())),
             )
         }
@@ -415,7 +444,12 @@ pub fn translate_insert(
     let insertion = build_insertion(&mut program, &table, &columns, num_values)?;
 
     let upsert_entry = program.allocate_label();
-    let conflict_rowid_reg = program.alloc_register();
+    // The rowid of the row an UPSERT updates, or its PRIMARY KEY for a WITHOUT ROWID table.
+    let conflict_rowid_reg = if btree_table.has_rowid {
+        program.alloc_register()
+    } else {
+        program.alloc_registers(btree_table.primary_key_columns.len())
+    };
 
     if inserting_multiple_rows {
         translate_rows_multiple(
@@ -430,7 +464,7 @@ pub fn translate_insert(
         program.emit_insn(Insn::OpenWrite {
             cursor_id,
             root_page: RegisterOrLiteral::Literal(root_page),
-            db: 0,
+            db: database_id,
         });
 
         translate_rows_single(&mut program, &values.unwrap(), &insertion, &resolver)?;
@@ -441,10 +475,50 @@ pub fn translate_insert(
         program.emit_insn(Insn::OpenWrite {
             cursor_id: idx_cursor.2,
             root_page: idx_cursor.1.into(),
-            db: 0,
+            db: database_id,
         });
     }
 
+    // Create and insert the record
+    let affinity_str = insertion
+        .col_mappings
+        .iter()
+        .map(|col_mapping| col_mapping.column.affinity().aff_mask())
+        .collect::<String>();
+
+    let trigger_regs = has_insert_triggers
+        .then(|| TriggerRowRegisters::alloc(&mut program, table.columns().len()));
+    if let Some(regs) = trigger_regs {
+        if has_triggers(
+            schema,
+            table_name.as_str(),
+            OperationMode::INSERT,
+            Some(TriggerTime::Before),
+            None,
+        ) {
+            emit_generated_columns(
+                &mut program,
+                &btree_table,
+                insertion.first_col_register(),
+                insertion.key_register(),
+                &resolver,
+            )?;
+            emit_new_row_for_triggers(&mut program, regs, &insertion, &affinity_str, true);
+            emit_triggers(
+                &mut program,
+                &resolver,
+                connection,
+                table_name.as_str(),
+                table.columns(),
+                OperationMode::INSERT,
+                TriggerTime::Before,
+                None,
+                regs,
+                row_done_label,
+            )?;
+        }
+    }
+
     let has_user_provided_rowid = insertion.key.is_provided_by_user();
     let key_ready_for_uniqueness_check_label = program.allocate_label();
     let key_generation_label = program.allocate_label();
@@ -459,7 +533,7 @@ pub fn translate_insert(
         program.emit_insn(Insn::OpenWrite {
             cursor_id: seq_cursor_id,
             root_page: seq_table.root_page.into(),
-            db: 0,
+            db: database_id,
         });
 
         let table_name_reg = program.emit_string8_new_reg(btree_table.name.clone());
@@ -593,7 +667,7 @@ pub fn translate_insert(
                 insertion.key_register(),
             )?;
         }
-    } else {
+    } else if btree_table.has_rowid {
         program.emit_insn(Insn::NewRowid {
             cursor: cursor_id,
             rowid_reg: insertion.key_register(),
@@ -603,6 +677,26 @@ pub fn translate_insert(
 
     program.preassign_label_to_next_insn(key_ready_for_uniqueness_check_label);
 
+    emit_generated_columns(
+        &mut program,
+        &btree_table,
+        insertion.first_col_register(),
+        insertion.key_register(),
+        &resolver,
+    )?;
+
+    // Like in SQLite, the CHECK constraints are checked before the uniqueness constraints.
+    emit_check_constraints(
+        &mut program,
+        connection,
+        &btree_table,
+        None,
+        insertion.first_col_register(),
+        insertion.key_register(),
+        None,
+        &resolver,
+    )?;
+
     // Check uniqueness constraint for rowid if it was provided by user.
     // When the DB allocates it there are no need for separate uniqueness checks.
 
@@ -648,6 +742,76 @@ pub fn translate_insert(
         program.preassign_label_to_next_insn(make_record_label);
     }
 
+    // The PRIMARY KEY of a WITHOUT ROWID table is the key of the table b-tree.
+    let primary_key =
+        (!btree_table.has_rowid).then(|| Arc::new(Index::without_rowid_primary_key(&btree_table)));
+    if let Some(primary_key) = &primary_key {
+        let num_cols = primary_key.columns.len();
+        let pk_start_reg = program.alloc_registers(num_cols);
+        for (i, column) in primary_key.columns.iter().enumerate() {
+            let Some(col_mapping) = insertion.get_col_mapping_by_name(&column.name) else {
+                return Err(crate::LimboError::PlanningError(
+                    "Column not found in INSERT".to_string(),
+                ));
+            };
+            program.emit_insn(Insn::Copy {
+                src_reg: col_mapping.register,
+                dst_reg: pk_start_reg + i,
+                extra_amount: 0,
+            });
+        }
+        program.emit_insn(Insn::Affinity {
+            start_reg: pk_start_reg,
+            count: NonZeroUsize::new(num_cols).expect("nonzero col count"),
+            affinities: primary_key
+                .columns
+                .iter()
+                .map(|ic| table.columns()[ic.pos_in_table].affinity().aff_mask())
+                .collect(),
+        });
+        let ok = program.allocate_label();
+        program.emit_insn(Insn::NoConflict {
+            cursor_id,
+            target_pc: ok,
+            record_reg: pk_start_reg,
+            num_regs: num_cols,
+        });
+        // Conflict on the PRIMARY KEY: route through UPSERT if it targets the PK, like for the
+        // rowid above
+        let upsert_do = match (upsert_opt.as_ref(), resolved_upsert.as_ref()) {
+            (
+                Some(upsert),
+                Some(ResolvedUpsertTarget::CatchAll | ResolvedUpsertTarget::PrimaryKey),
+            ) => Some(&upsert.do_clause),
+            _ => None,
+        };
+        match upsert_do {
+            Some(UpsertDo::Nothing) => {
+                program.emit_insn(Insn::Goto {
+                    target_pc: row_done_label,
+                });
+            }
+            Some(UpsertDo::Set { .. }) => {
+                // The conflicting row has exactly the PRIMARY KEY being inserted
+                program.emit_insn(Insn::Copy {
+                    src_reg: pk_start_reg,
+                    dst_reg: conflict_rowid_reg,
+                    extra_amount: num_cols - 1,
+                });
+                program.emit_insn(Insn::Goto {
+                    target_pc: upsert_entry,
+                });
+            }
+            None => {
+                program.emit_insn(Insn::Halt {
+                    err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+                    description: format_unique_violation_desc(table_name.as_str(), primary_key),
+                });
+            }
+        }
+        program.preassign_label_to_next_insn(ok);
+    }
+
     match table.btree() {
         Some(t) if t.is_strict => {
             program.emit_insn(Insn::TypeCheck {
@@ -673,10 +837,6 @@ pub fn translate_insert(
     //
     // otherwise, raise SQLITE_CONSTRAINT_UNIQUE
     for index in schema.get_indices(table_name.as_str()) {
-        let column_mappings = index
-            .columns
-            .iter()
-            .map(|idx_col| insertion.get_col_mapping_by_name(&idx_col.name));
         // find which cursor we opened earlier for this index
         let idx_cursor_id = idx_cursors
             .iter()
@@ -708,36 +868,54 @@ pub fn translate_insert(
         };
 
         let num_cols = index.columns.len();
+        // the index records end with the rowid, or with the rest of the PRIMARY KEY for a
+        // WITHOUT ROWID table
+        let record_len = if btree_table.has_rowid {
+            num_cols + 1
+        } else {
+            num_cols + index.primary_key_suffix.len()
+        };
         // allocate scratch registers for the index columns plus rowid
-        let idx_start_reg = program.alloc_registers(num_cols + 1);
+        let idx_start_reg = program.alloc_registers(record_len);
 
         // build unpacked key [idx_start_reg .. idx_start_reg+num_cols-1], and rowid in last reg,
         // copy each index column from the table's column registers into these scratch regs
-        for (i, column_mapping) in column_mappings.clone().enumerate() {
-            // copy from the table's column register over to the index's scratch register
-            let Some(col_mapping) = column_mapping else {
-                return Err(crate::LimboError::PlanningError(
-                    "Column not found in INSERT".to_string(),
-                ));
-            };
+        for (i, idx_col) in index.columns.iter().enumerate() {
+            emit_index_column_for_insertion(
+                &mut program,
+                &insertion,
+                idx_col,
+                idx_start_reg + i,
+                &resolver,
+            )?;
+        }
+        if btree_table.has_rowid {
+            // last register is the rowid
             program.emit_insn(Insn::Copy {
-                src_reg: col_mapping.register,
-                dst_reg: idx_start_reg + i,
+                src_reg: insertion.key_register(),
+                dst_reg: idx_start_reg + num_cols,
                 extra_amount: 0,
             });
+        } else {
+            for (i, idx_col) in index.primary_key_suffix.iter().enumerate() {
+                let Some(col_mapping) = insertion.get_col_mapping_by_name(&idx_col.name) else {
+                    return Err(crate::LimboError::PlanningError(
+                        "Column not found in INSERT".to_string(),
+                    ));
+                };
+                program.emit_insn(Insn::Copy {
+                    src_reg: col_mapping.register,
+                    dst_reg: idx_start_reg + num_cols + i,
+                    extra_amount: 0,
+                });
+            }
         }
-        // last register is the rowid
-        program.emit_insn(Insn::Copy {
-            src_reg: insertion.key_register(),
-            dst_reg: idx_start_reg + num_cols,
-            extra_amount: 0,
-        });
 
         if index.unique {
             let aff = index
                 .columns
                 .iter()
-                .map(|ic| table.columns()[ic.pos_in_table].affinity().aff_mask())
+                .map(|ic| ic.affinity(table.columns()).aff_mask())
                 .collect::<String>();
             program.emit_insn(Insn::Affinity {
                 start_reg: idx_start_reg,
@@ -779,10 +957,24 @@ pub fn translate_insert(
                         }
                         UpsertDo::Set { .. } => {
                             // Route to DO UPDATE: capture conflicting rowid then jump
-                            program.emit_insn(Insn::IdxRowId {
-                                cursor_id: idx_cursor_id,
-                                dest: conflict_rowid_reg,
-                            });
+                            if btree_table.has_rowid {
+                                program.emit_insn(Insn::IdxRowId {
+                                    cursor_id: idx_cursor_id,
+                                    dest: conflict_rowid_reg,
+                                });
+                            } else {
+                                for (i, pos) in index
+                                    .primary_key_positions(&btree_table)
+                                    .into_iter()
+                                    .enumerate()
+                                {
+                                    program.emit_column_or_rowid(
+                                        idx_cursor_id,
+                                        pos,
+                                        conflict_rowid_reg + i,
+                                    );
+                                }
+                            }
                             program.emit_insn(Insn::Goto {
                                 target_pc: upsert_entry,
                             });
@@ -818,7 +1010,7 @@ pub fn translate_insert(
                 let record_reg = program.alloc_register();
                 program.emit_insn(Insn::MakeRecord {
                     start_reg: idx_start_reg,
-                    count: num_cols + 1,
+                    count: record_len,
                     dest_reg: record_reg,
                     index_name: Some(index.name.clone()),
                     affinity_str: None,
@@ -827,7 +1019,7 @@ pub fn translate_insert(
                     cursor_id: idx_cursor_id,
                     record_reg,
                     unpacked_start: Some(idx_start_reg),
-                    unpacked_count: Some((num_cols + 1) as u16),
+                    unpacked_count: Some(record_len as u16),
                     flags: IdxInsertFlags::new().nchange(true),
                 });
             }
@@ -838,7 +1030,7 @@ pub fn translate_insert(
                 let record_reg = program.alloc_register();
                 program.emit_insn(Insn::MakeRecord {
                     start_reg: idx_start_reg,
-                    count: num_cols + 1,
+                    count: record_len,
                     dest_reg: record_reg,
                     index_name: Some(index.name.clone()),
                     affinity_str: None,
@@ -847,7 +1039,7 @@ pub fn translate_insert(
                     cursor_id: idx_cursor_id,
                     record_reg,
                     unpacked_start: Some(idx_start_reg),
-                    unpacked_count: Some((num_cols + 1) as u16),
+                    unpacked_count: Some(record_len as u16),
                     flags: IdxInsertFlags::new().nchange(true),
                 });
             }
@@ -858,11 +1050,11 @@ pub fn translate_insert(
             program.resolve_label(lbl, program.offset());
         }
     }
-    for column_mapping in insertion
-        .col_mappings
-        .iter()
-        .filter(|column_mapping| column_mapping.column.notnull)
-    {
+    // The PRIMARY KEY columns of a WITHOUT ROWID table cannot be NULL.
+    for column_mapping in insertion.col_mappings.iter().filter(|column_mapping| {
+        column_mapping.column.notnull
+            || (column_mapping.column.primary_key && !btree_table.has_rowid)
+    }) {
         // if this is rowid alias - turso-db will emit NULL as a column value and always use rowid for the row as a column value
         if column_mapping.column.is_rowid_alias {
             continue;
@@ -895,20 +1087,26 @@ pub fn translate_insert(
         });
     }
 
-    // Create and insert the record
-    let affinity_str = insertion
-        .col_mappings
-        .iter()
-        .map(|col_mapping| col_mapping.column.affinity().aff_mask())
-        .collect::<String>();
+    if has_fks(schema, connection, table_name.as_str(), None) {
+        emit_fk_checks(
+            &mut program,
+            schema,
+            table_name.as_str(),
+            None,
+            Some(RowImage {
+                rowid_reg: insertion.key_register(),
+                columns_start_reg: insertion.first_col_register(),
+            }),
+            None,
+        )?;
+    }
 
-    program.emit_insn(Insn::MakeRecord {
-        start_reg: insertion.first_col_register(),
-        count: insertion.col_mappings.len(),
-        dest_reg: insertion.record_register(),
-        index_name: None,
-        affinity_str: Some(affinity_str),
-    });
+    emit_table_record(
+        &mut program,
+        &btree_table,
+        insertion.first_col_register(),
+        insertion.record_register(),
+    );
 
     if has_upsert {
         // COMMIT PHASE: no preflight jumps happened; emit the actual index writes now
@@ -947,31 +1145,41 @@ pub fn translate_insert(
             };
 
             let num_cols = index.columns.len();
-            let idx_start_reg = program.alloc_registers(num_cols + 1);
+            let record_len = if btree_table.has_rowid {
+                num_cols + 1
+            } else {
+                num_cols + index.primary_key_suffix.len()
+            };
+            let idx_start_reg = program.alloc_registers(record_len);
 
-            // Build [key cols..., rowid] from insertion registers
-            for (i, idx_col) in index.columns.iter().enumerate() {
-                let Some(cm) = insertion.get_col_mapping_by_name(&idx_col.name) else {
-                    return Err(crate::LimboError::PlanningError(
-                        "Column not found in INSERT (commit phase)".to_string(),
-                    ));
-                };
+            // Build [key cols..., rowid] (or the rest of the PRIMARY KEY instead of the rowid)
+            // from insertion registers
+            for (i, idx_col) in index
+                .columns
+                .iter()
+                .chain(index.primary_key_suffix.iter())
+                .enumerate()
+            {
+                emit_index_column_for_insertion(
+                    &mut program,
+                    &insertion,
+                    idx_col,
+                    idx_start_reg + i,
+                    &resolver,
+                )?;
+            }
+            if btree_table.has_rowid {
                 program.emit_insn(Insn::Copy {
-                    src_reg: cm.register,
-                    dst_reg: idx_start_reg + i,
+                    src_reg: insertion.key_register(),
+                    dst_reg: idx_start_reg + num_cols,
                     extra_amount: 0,
                 });
             }
-            program.emit_insn(Insn::Copy {
-                src_reg: insertion.key_register(),
-                dst_reg: idx_start_reg + num_cols,
-                extra_amount: 0,
-            });
 
             let record_reg = program.alloc_register();
             program.emit_insn(Insn::MakeRecord {
                 start_reg: idx_start_reg,
-                count: num_cols + 1,
+                count: record_len,
                 dest_reg: record_reg,
                 index_name: Some(index.name.clone()),
                 affinity_str: None,
@@ -980,7 +1188,7 @@ pub fn translate_insert(
                 cursor_id: idx_cursor_id,
                 record_reg,
                 unpacked_start: Some(idx_start_reg),
-                unpacked_count: Some((num_cols + 1) as u16),
+                unpacked_count: Some(record_len as u16),
                 flags: IdxInsertFlags::new().nchange(true),
             });
 
@@ -990,13 +1198,23 @@ pub fn translate_insert(
         }
     }
 
-    program.emit_insn(Insn::Insert {
-        cursor: cursor_id,
-        key_reg: insertion.key_register(),
-        record_reg: insertion.record_register(),
-        flag: InsertFlags::new(),
-        table_name: table_name.to_string(),
-    });
+    if btree_table.has_rowid {
+        program.emit_insn(Insn::Insert {
+            cursor: cursor_id,
+            key_reg: insertion.key_register(),
+            record_reg: insertion.record_register(),
+            flag: InsertFlags::new(),
+            table_name: table_name.to_string(),
+        });
+    } else {
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id,
+            record_reg: insertion.record_register(),
+            unpacked_start: None,
+            unpacked_count: None,
+            flags: IdxInsertFlags::new().nchange(true),
+        });
+    }
 
     if let Some((seq_cursor_id, r_seq, r_seq_rowid, table_name_reg)) = autoincrement_meta {
         let no_update_needed_label = program.allocate_label();
@@ -1023,6 +1241,30 @@ pub fn translate_insert(
         });
     }
 
+    if let Some(regs) = trigger_regs {
+        if has_triggers(
+            schema,
+            table_name.as_str(),
+            OperationMode::INSERT,
+            Some(TriggerTime::After),
+            None,
+        ) {
+            emit_new_row_for_triggers(&mut program, regs, &insertion, &affinity_str, false);
+            emit_triggers(
+                &mut program,
+                &resolver,
+                connection,
+                table_name.as_str(),
+                table.columns(),
+                OperationMode::INSERT,
+                TriggerTime::After,
+                None,
+                regs,
+                row_done_label,
+            )?;
+        }
+    }
+
     // Emit update in the CDC table if necessary (after the INSERT updated the table)
     if let Some((cdc_cursor_id, _)) = &cdc_table {
         let cdc_has_after = program.capture_data_changes_mode().has_after();
@@ -1091,6 +1333,7 @@ pub fn translate_insert(
                 &mut result_columns,
                 cdc_table.as_ref().map(|c| c.0),
                 row_done_label,
+                connection,
             )?;
         } else {
             // UpsertDo::Nothing case
@@ -1129,6 +1372,105 @@ pub fn translate_insert(
     Ok(program)
 }
 
+/// Builds the record of a row from its column values in table order. VIRTUAL generated columns
+/// are not stored, and the record of a WITHOUT ROWID table holds the PRIMARY KEY columns first,
+/// then the other columns.
+pub fn emit_table_record(
+    program: &mut ProgramBuilder,
+    table: &schema::BTreeTable,
+    columns_start_reg: usize,
+    record_reg: usize,
+) {
+    if table.stores_columns_in_order() {
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: columns_start_reg,
+            count: table.columns.len(),
+            dest_reg: record_reg,
+            index_name: None,
+            affinity_str: Some(
+                table
+                    .columns
+                    .iter()
+                    .map(|column| column.affinity().aff_mask())
+                    .collect(),
+            ),
+        });
+        return;
+    }
+    let num_cols = table
+        .columns
+        .iter()
+        .filter(|column| !column.is_virtual_generated())
+        .count();
+    let start_reg = program.alloc_registers(num_cols);
+    let mut affinities = vec![' '; num_cols];
+    for (i, column) in table.columns.iter().enumerate() {
+        if column.is_virtual_generated() {
+            continue;
+        }
+        let pos = table.column_storage_position(i);
+        program.emit_insn(Insn::Copy {
+            src_reg: columns_start_reg + i,
+            dst_reg: start_reg + pos,
+            extra_amount: 0,
+        });
+        affinities[pos] = column.affinity().aff_mask();
+    }
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count: num_cols,
+        dest_reg: record_reg,
+        index_name: None,
+        affinity_str: Some(affinities.into_iter().collect()),
+    });
+}
+
+/// Loads the row being inserted into `regs` for the INSERT triggers. In BEFORE triggers the
+/// rowid is not known yet unless the user provided it, and NEW.rowid is -1 like in SQLite.
+fn emit_new_row_for_triggers(
+    program: &mut ProgramBuilder,
+    regs: TriggerRowRegisters,
+    insertion: &Insertion,
+    affinity_str: &str,
+    before: bool,
+) {
+    regs.emit_null_old(program);
+    if before && !insertion.key.is_provided_by_user() {
+        program.emit_insn(Insn::Integer {
+            dest: regs.new_rowid(),
+            value: -1,
+        });
+    } else {
+        program.emit_insn(Insn::Copy {
+            src_reg: insertion.key_register(),
+            dst_reg: regs.new_rowid(),
+            extra_amount: 0,
+        });
+        if before {
+            let rowid_not_null_label = program.allocate_label();
+            program.emit_insn(Insn::NotNull {
+                reg: regs.new_rowid(),
+                target_pc: rowid_not_null_label,
+            });
+            program.emit_insn(Insn::Integer {
+                dest: regs.new_rowid(),
+                value: -1,
+            });
+            program.preassign_label_to_next_insn(rowid_not_null_label);
+        }
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: insertion.first_col_register(),
+        dst_reg: regs.new_columns(),
+        extra_amount: insertion.col_mappings.len() - 1,
+    });
+    program.emit_insn(Insn::Affinity {
+        start_reg: regs.new_columns(),
+        count: NonZeroUsize::new(insertion.col_mappings.len()).expect("nonzero col count"),
+        affinities: affinity_str.to_string(),
+    });
+}
+
 pub const ROWID_COLUMN: &Column = &Column {
     name: None,
     ty: schema::Type::Integer,
@@ -1140,6 +1482,7 @@ pub const ROWID_COLUMN: &Column = &Column {
     unique: false,
     collation: None,
     hidden: false,
+    generated: None,
 };
 
 /// Represents how a table should be populated during an INSERT.
@@ -1281,18 +1624,22 @@ fn build_insertion<'a>(
 
     if columns.is_empty() {
         // Case 1: No columns specified - map values to columns in order
-        if num_values != table_columns.iter().filter(|c| !c.hidden).count() {
+        let num_columns = table_columns
+            .iter()
+            .filter(|c| !c.hidden && c.generated.is_none())
+            .count();
+        if num_values != num_columns {
             crate::bail_parse_error!(
                 "table {} has {} columns but {} values were supplied",
                 &table.get_name(),
-                table_columns.len(),
+                num_columns,
                 num_values
             );
         }
         let mut value_idx = 0;
         for (i, col) in table_columns.iter().enumerate() {
-            if col.hidden {
-                // Hidden columns are not taken into account.
+            if col.hidden || col.generated.is_some() {
+                // Hidden and generated columns are not taken into account.
                 continue;
             }
             if col.is_rowid_alias {
@@ -1313,6 +1660,12 @@ fn build_insertion<'a>(
             let column_name = normalize_ident(column_name.as_str());
             if let Some((idx_in_table, col_in_table)) = table.get_column_by_name(&column_name) {
                 // Named column
+                if col_in_table.generated.is_some() {
+                    crate::bail_parse_error!(
+                        "cannot INSERT into generated column \"{}\"",
+                        column_name
+                    );
+                }
                 if col_in_table.is_rowid_alias {
                     insertion_key = InsertionKey::RowidAlias(ColMapping {
                         column: col_in_table,
@@ -1325,6 +1678,7 @@ fn build_insertion<'a>(
             } else if ROWID_STRS
                 .iter()
                 .any(|s| s.eq_ignore_ascii_case(&column_name))
+                && table.btree().is_some_and(|btree| btree.has_rowid)
             {
                 // Explicit use of the 'rowid' keyword
                 if let Some(col_in_table) = table.columns().iter().find(|c| c.is_rowid_alias) {
@@ -1377,7 +1731,13 @@ fn translate_rows_multiple<'short, 'long: 'short>(
     let translate_value_fn =
         |prg: &mut ProgramBuilder, value_index: usize, column_register: usize| {
             if let Some(temp_table_ctx) = temp_table_ctx {
-                prg.emit_column_or_rowid(temp_table_ctx.cursor_id, value_index, column_register);
+                // The temp table holds the values in the order of the statement, not of the table.
+                prg.emit_insn(Insn::Column {
+                    cursor_id: temp_table_ctx.cursor_id,
+                    column: value_index,
+                    dest: column_register,
+                    default: None,
+                });
             } else {
                 prg.emit_insn(Insn::Copy {
                     src_reg: yield_reg + value_index,
@@ -1487,8 +1847,9 @@ fn translate_column(
         program.emit_insn(Insn::SoftNull {
             reg: column_register,
         });
-    } else if column.hidden {
+    } else if column.hidden || column.generated.is_some() {
         // Emit NULL for not-explicitly-mentioned hidden columns, even ignoring DEFAULT.
+        // Generated columns are computed once the other columns are known.
         program.emit_insn(Insn::Null {
             dest: column_register,
             dest_end: None,
@@ -1574,6 +1935,7 @@ fn ensure_sequence_initialized(
     program: &mut ProgramBuilder,
     schema: &Schema,
     table: &schema::BTreeTable,
+    database_id: usize,
 ) -> Result<()> {
     let seq_table = schema.get_btree_table("sqlite_sequence").ok_or_else(|| {
         crate::error::LimboError::InternalError("sqlite_sequence table not found".to_string())
@@ -1584,7 +1946,7 @@ fn ensure_sequence_initialized(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: seq_cursor_id,
         root_page: seq_table.root_page.into(),
-        db: 0,
+        db: database_id,
     });
 
     let table_name_reg = program.emit_string8_new_reg(table.name.clone());
@@ -1680,7 +2042,9 @@ fn ensure_sequence_initialized(
 /// single column: `t.c1`
 /// multi-column:  `t.(k, c1)`
 pub fn format_unique_violation_desc(table_name: &str, index: &Index) -> String {
-    if index.columns.len() == 1 {
+    if index.columns.iter().any(|c| c.expr.is_some()) {
+        format!("index '{}'", index.name)
+    } else if index.columns.len() == 1 {
         let mut s = String::with_capacity(table_name.len() + 1 + index.columns[0].name.len());
         s.push_str(table_name);
         s.push('.');
@@ -1703,6 +2067,41 @@ pub fn format_unique_violation_desc(table_name: &str, index: &Index) -> String {
     }
 }
 
+/// Emit the value of a column of an index for the row being inserted: the value of the table
+/// column, or for an index on an expression, the value of the expression.
+fn emit_index_column_for_insertion(
+    program: &mut ProgramBuilder,
+    insertion: &Insertion,
+    idx_col: &IndexColumn,
+    dest_reg: usize,
+    resolver: &Resolver,
+) -> Result<()> {
+    if let Some(expr) = &idx_col.expr {
+        let mut expr = expr.as_ref().clone();
+        rewrite_partial_index_where(&mut expr, insertion)?;
+        translate_expr_no_constant_opt(
+            program,
+            Some(&TableReferences::new_empty()),
+            &expr,
+            dest_reg,
+            resolver,
+            NoConstantOptReason::RegisterReuse,
+        )?;
+        return Ok(());
+    }
+    let Some(col_mapping) = insertion.get_col_mapping_by_name(&idx_col.name) else {
+        return Err(crate::LimboError::PlanningError(
+            "Column not found in INSERT".to_string(),
+        ));
+    };
+    program.emit_insn(Insn::Copy {
+        src_reg: col_mapping.register,
+        dst_reg: dest_reg,
+        extra_amount: 0,
+    });
+    Ok(())
+}
+
 /// Rewrite WHERE clause for partial index to reference insertion registers
 pub fn rewrite_partial_index_where(
     expr: &mut ast::Expr,
@@ -2293,6 +2293,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("name".to_string()),
@@ -2305,6 +2306,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("age".to_string()),
@@ -2317,6 +2319,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("email".to_string()),
@@ -2329,11 +2332,14 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
             ],
             has_rowid: true,
             is_strict: false,
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
             unique_sets: vec![],
         };
         schema.add_btree_table(Arc::new(users_table));
@@ -2355,6 +2361,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("user_id".to_string()),
@@ -2367,6 +2374,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("product".to_string()),
@@ -2379,6 +2387,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("amount".to_string()),
@@ -2391,11 +2400,14 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
             ],
             has_rowid: true,
             is_strict: false,
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
             unique_sets: vec![],
         };
         schema.add_btree_table(Arc::new(orders_table));
@@ -2417,6 +2429,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("name".to_string()),
@@ -2429,6 +2442,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("price".to_string()),
@@ -2441,6 +2455,7 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
                 SchemaColumn {
                     name: Some("product_id".to_string()),
@@ -2453,11 +2468,14 @@ mod tests {
                     unique: false,
                     collation: None,
                     hidden: false,
+                    generated: None,
                 },
             ],
             has_rowid: true,
             is_strict: false,
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
             unique_sets: vec![],
         };
         schema.add_btree_table(Arc::new(products_table));
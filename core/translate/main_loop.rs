@@ -1,22 +1,26 @@
-use turso_parser::ast::{fmt::ToTokens, SortOrder};
+use turso_parser::ast::{self, fmt::ToTokens, SortOrder};
 
 use std::sync::Arc;
 
 use super::{
     aggregation::{translate_aggregation_step, AggArgumentSource},
     display::PlanContext,
-    emitter::{OperationMode, TranslateCtx},
+    emitter::{OperationMode, Resolver, TranslateCtx},
     expr::{
-        translate_condition_expr, translate_expr, translate_expr_no_constant_opt,
-        ConditionMetadata, NoConstantOptReason,
+        emit_table_column, translate_condition_expr, translate_expr,
+        translate_expr_no_constant_opt, walk_expr, ConditionMetadata, NoConstantOptReason,
+        WalkControl,
     },
     group_by::{group_by_agg_phase, GroupByMetadata, GroupByRowSource},
     optimizer::Optimizable,
     order_by::{order_by_sorter_insert, sorter_insert},
     plan::{
-        Aggregate, GroupBy, IterationDirection, JoinOrderMember, Operation, QueryDestination,
-        Search, SeekDef, SelectPlan, TableReferences, WhereTerm,
+        AggOrderByCtx, Aggregate, EvalAt, GroupBy, IterationDirection, JoinOrderMember,
+        JoinedTable, NonFromClauseSubquery, Operation, QueryDestination, Search, SeekDef,
+        SelectPlan, TableReferences, WhereTerm,
     },
+    planner::determine_where_to_eval_term,
+    subquery::emit_non_from_clause_subquery,
 };
 use crate::translate::window::emit_window_loop_source;
 use crate::{
@@ -46,6 +50,16 @@ pub struct LeftJoinMetadata {
     pub label_match_flag_check_value: BranchOffset,
 }
 
+// Metadata for handling RIGHT JOIN operations
+#[derive(Debug)]
+pub struct RightJoinMetadata {
+    // cursor of the ephemeral index holding the rowids of the rows of the right table that had a match,
+    // or their PRIMARY KEYs for a WITHOUT ROWID table
+    pub matched_rowids_cursor_id: CursorID,
+    // name of the ephemeral index
+    pub matched_rowids_index_name: String,
+}
+
 /// Jump labels for each loop in the query's main execution loop
 #[derive(Debug, Clone, Copy)]
 pub struct LoopLabels {
@@ -88,11 +102,13 @@ pub fn init_distinct(program: &mut ProgramBuilder, plan: &SelectPlan) -> Distinc
                     pos_in_table: i,
                     collation: None, // FIXME: this should be determined based on the result column expression!
                     default: None, // FIXME: this should be determined based on the result column expression!
+                    expr: None,
                 }
             })
             .collect(),
         unique: false,
         has_rowid: false,
+        primary_key_suffix: vec![],
         where_clause: None,
     });
     let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
@@ -111,6 +127,7 @@ pub fn init_distinct(program: &mut ProgramBuilder, plan: &SelectPlan) -> Distinc
 }
 
 /// Initialize resources needed for the source operators (tables, joins, etc)
+#[allow(clippy::too_many_arguments)]
 pub fn init_loop(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
@@ -119,6 +136,7 @@ pub fn init_loop(
     group_by: Option<&GroupBy>,
     mode: OperationMode,
     where_clause: &[WhereTerm],
+    subqueries: &[NonFromClauseSubquery],
 ) -> Result<()> {
     assert!(
         t_ctx.meta_left_joins.len() == tables.joined_tables().len(),
@@ -164,8 +182,10 @@ pub fn init_loop(
                 pos_in_table: 0,
                 collation: None, // FIXME: this should be inferred from the expression
                 default: None,   // FIXME: this should be inferred from the expression
+                expr: None,
             }],
             has_rowid: false,
+            primary_key_suffix: vec![],
             unique: false,
             where_clause: None,
         });
@@ -186,6 +206,77 @@ pub fn init_loop(
             }),
         };
     }
+    // Initialize ephemeral indexes buffering the arguments of aggregates with an ORDER BY clause
+    for (i, agg) in aggregates
+        .iter_mut()
+        .enumerate()
+        .filter(|(_, agg)| !agg.order_by.is_empty())
+    {
+        let index_name = format!("ordered_agg_{i}");
+        let key_columns = agg.order_by.iter().map(|(expr, order)| IndexColumn {
+            name: expr.displayer(&PlanContext(&[tables])).to_string(),
+            order: *order,
+            pos_in_table: 0,
+            collation: None,
+            default: None,
+            expr: None,
+        });
+        let sequence_column = IndexColumn {
+            name: "sequence".to_string(),
+            order: SortOrder::Asc,
+            pos_in_table: 0,
+            collation: None,
+            default: None,
+            expr: None,
+        };
+        let arg_columns = agg.args.iter().map(|arg| IndexColumn {
+            name: arg.displayer(&PlanContext(&[tables])).to_string(),
+            order: SortOrder::Asc,
+            pos_in_table: 0,
+            collation: None,
+            default: None,
+            expr: None,
+        });
+        let columns = key_columns
+            .chain(std::iter::once(sequence_column))
+            .chain(arg_columns)
+            .enumerate()
+            .map(|(pos, column)| IndexColumn {
+                pos_in_table: pos,
+                ..column
+            })
+            .collect();
+        let index = Arc::new(Index {
+            name: index_name.clone(),
+            table_name: String::new(),
+            ephemeral: true,
+            root_page: 0,
+            columns,
+            has_rowid: false,
+            primary_key_suffix: vec![],
+            unique: false,
+            where_clause: None,
+        });
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index));
+        if group_by.is_none() {
+            // As for distinct aggregates, with GROUP BY the ephemeral index is reinitialized
+            // for every group in the clear accumulator subroutine.
+            program.emit_insn(Insn::OpenEphemeral {
+                cursor_id,
+                is_table: false,
+            });
+        }
+        let reg_sequence = program.alloc_register();
+        program.emit_insn(Insn::Integer {
+            value: 0,
+            dest: reg_sequence,
+        });
+        agg.order_by_ctx = Some(AggOrderByCtx {
+            cursor_id,
+            ephemeral_index_name: index_name,
+            reg_sequence,
+        });
+    }
     for (table_index, table) in tables.joined_tables().iter().enumerate() {
         // Initialize bookkeeping for OUTER JOIN
         if let Some(join_info) = table.join_info.as_ref() {
@@ -197,6 +288,42 @@ pub fn init_loop(
                 };
                 t_ctx.meta_left_joins[table_index] = Some(lj_metadata);
             }
+            // Initialize bookkeeping for RIGHT JOIN: the rowids of the rows that had a match
+            if join_info.right {
+                let index_name = format!("right_join_{}", table.identifier);
+                let index = Arc::new(Index {
+                    name: index_name.clone(),
+                    table_name: String::new(),
+                    ephemeral: true,
+                    root_page: 0,
+                    columns: (0..row_key_exprs(table).len())
+                        .map(|i| IndexColumn {
+                            name: "rowid".to_string(),
+                            order: SortOrder::Asc,
+                            pos_in_table: i,
+                            collation: None,
+                            default: None,
+                            expr: None,
+                        })
+                        .collect(),
+                    has_rowid: false,
+                    primary_key_suffix: vec![],
+                    unique: false,
+                    where_clause: None,
+                });
+                let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index));
+                program.emit_insn(Insn::OpenEphemeral {
+                    cursor_id,
+                    is_table: false,
+                });
+                t_ctx.meta_right_joins[table_index] = Some(RightJoinMetadata {
+                    matched_rowids_cursor_id: cursor_id,
+                    matched_rowids_index_name: index_name,
+                });
+            }
+        }
+        if table.database_id != 0 {
+            program.begin_read_operation_on(table.database_id);
         }
         let (table_cursor_id, index_cursor_id) =
             table.open_cursors(program, mode, t_ctx.resolver.schema)?;
@@ -348,7 +475,11 @@ pub fn init_loop(
                 } = search
                 {
                     // Ephemeral index cursor are opened ad-hoc when needed.
-                    if !index.ephemeral {
+                    // The PRIMARY KEY of a WITHOUT ROWID table uses the table cursor.
+                    let is_table_btree = table
+                        .btree()
+                        .is_some_and(|btree| index.is_without_rowid_primary_key_of(&btree));
+                    if !index.ephemeral && !is_table_btree {
                         match mode {
                             OperationMode::SELECT => {
                                 program.emit_insn(Insn::OpenRead {
@@ -376,9 +507,21 @@ pub fn init_loop(
         }
     }
 
+    // Whether a condition can be evaluated before the loop does not depend on the order of
+    // the tables, only on which of them it references.
+    let join_order = tables
+        .joined_tables()
+        .iter()
+        .enumerate()
+        .map(|(i, t)| JoinOrderMember {
+            table_id: t.internal_id,
+            original_idx: i,
+            is_outer: t.join_info.as_ref().is_some_and(|j| j.outer),
+        })
+        .collect::<Vec<_>>();
     for cond in where_clause
         .iter()
-        .filter(|c| c.should_eval_before_loop(&[JoinOrderMember::default()]))
+        .filter(|c| c.should_eval_before_loop(&join_order, subqueries))
     {
         let jump_target = program.allocate_label();
         let meta = ConditionMetadata {
@@ -393,6 +536,46 @@ pub fn init_loop(
     Ok(())
 }
 
+/// Position the table cursor on the row that the index cursor points to.
+/// The rows of a WITHOUT ROWID table are looked up by the PRIMARY KEY stored in the index,
+/// and the PRIMARY KEY of such a table needs no lookup at all since it is the table b-tree.
+pub fn emit_table_seek_from_index(
+    program: &mut ProgramBuilder,
+    table: &JoinedTable,
+    index_cursor_id: CursorID,
+    table_cursor_id: CursorID,
+    label_if_not_found: BranchOffset,
+) {
+    if index_cursor_id == table_cursor_id {
+        return;
+    }
+    let without_rowid = table.btree().filter(|btree| !btree.has_rowid);
+    let (Some(btree), Some(index)) = (without_rowid, table.op.index()) else {
+        // Don't do a btree table seek until it's actually necessary to read from the table.
+        program.emit_insn(Insn::DeferredSeek {
+            index_cursor_id,
+            table_cursor_id,
+        });
+        return;
+    };
+    let positions = index.primary_key_positions(&btree);
+    let start_reg = program.alloc_registers(positions.len());
+    for (i, pos) in positions.iter().enumerate() {
+        program.emit_insn(Insn::Column {
+            cursor_id: index_cursor_id,
+            column: *pos,
+            dest: start_reg + i,
+            default: None,
+        });
+    }
+    program.emit_insn(Insn::NotFound {
+        cursor_id: table_cursor_id,
+        target_pc: label_if_not_found,
+        record_reg: start_reg,
+        num_regs: positions.len(),
+    });
+}
+
 /// Set up the main query execution loop
 /// For example in the case of a nested table scan, this means emitting the Rewind instruction
 /// for all tables involved, outermost first.
@@ -402,9 +585,39 @@ pub fn open_loop(
     table_references: &TableReferences,
     join_order: &[JoinOrderMember],
     predicates: &[WhereTerm],
+    subqueries: &mut [NonFromClauseSubquery],
+    temp_cursor_id: Option<CursorID>,
+) -> Result<()> {
+    for join_index in 0..join_order.len() {
+        open_loop_at(
+            program,
+            t_ctx,
+            table_references,
+            join_order,
+            predicates,
+            subqueries,
+            temp_cursor_id,
+            join_index,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Open the loop of the table at the given position of the join order.
+#[allow(clippy::too_many_arguments)]
+fn open_loop_at(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    table_references: &TableReferences,
+    join_order: &[JoinOrderMember],
+    predicates: &[WhereTerm],
+    subqueries: &mut [NonFromClauseSubquery],
     temp_cursor_id: Option<CursorID>,
+    join_index: usize,
 ) -> Result<()> {
-    for (join_index, join) in join_order.iter().enumerate() {
+    {
+        let join = &join_order[join_index];
         let joined_table_index = join.original_idx;
         let table = &table_references.joined_tables()[joined_table_index];
         let LoopLabels {
@@ -537,10 +750,13 @@ pub fn open_loop(
 
                 if let Some(table_cursor_id) = table_cursor_id {
                     if let Some(index_cursor_id) = index_cursor_id {
-                        program.emit_insn(Insn::DeferredSeek {
+                        emit_table_seek_from_index(
+                            program,
+                            table,
                             index_cursor_id,
                             table_cursor_id,
-                        });
+                            next,
+                        );
                     }
                 }
             }
@@ -580,12 +796,14 @@ pub fn open_loop(
                             };
                             Some(emit_autoindex(
                                 program,
+                                &table.table,
                                 index,
                                 table_cursor_id
                                     .expect("an ephemeral index must have a source table cursor"),
                                 index_cursor_id
                                     .expect("an ephemeral index must have an index cursor"),
                                 table_has_rowid,
+                                &t_ctx.resolver,
                             )?)
                         } else {
                             index_cursor_id
@@ -630,17 +848,46 @@ pub fn open_loop(
 
                     if let Some(index_cursor_id) = index_cursor_id {
                         if let Some(table_cursor_id) = table_cursor_id {
-                            // Don't do a btree table seek until it's actually necessary to read from the table.
-                            program.emit_insn(Insn::DeferredSeek {
+                            emit_table_seek_from_index(
+                                program,
+                                table,
                                 index_cursor_id,
                                 table_cursor_id,
-                            });
+                                next,
+                            );
                         }
                     }
                 }
             }
         }
 
+        // The correlated subqueries of this loop are only evaluated after the match flag is set,
+        // so the ON clause of an OUTER JOIN cannot use them.
+        if table
+            .join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.outer)
+        {
+            for cond in predicates
+                .iter()
+                .filter(|cond| cond.from_outer_join == Some(table.internal_id))
+            {
+                walk_expr(&cond.expr, &mut |expr: &ast::Expr| -> Result<WalkControl> {
+                    if let ast::Expr::SubqueryResult { subquery_id, .. } = expr {
+                        if subqueries.iter().any(|subquery| {
+                            subquery.internal_id == *subquery_id
+                                && subquery.eval_at(join_order) == EvalAt::Loop(join_index)
+                        }) {
+                            crate::bail_parse_error!(
+                                "correlated subqueries in the ON clause of an OUTER JOIN are not supported"
+                            );
+                        }
+                    }
+                    Ok(WalkControl::Continue)
+                })?;
+            }
+        }
+
         // First emit outer join conditions, if any.
         emit_conditions(
             program,
@@ -648,11 +895,53 @@ pub fn open_loop(
             table_references,
             join_order,
             predicates,
+            subqueries,
             join_index,
             next,
             true,
         )?;
 
+        // Remember that this row of the right table of a RIGHT JOIN had a match, so that it is not
+        // emitted again with NULLs for the left tables after the main loop.
+        // This comes before the LEFT JOIN match flag is set, where the NULL row of a FULL OUTER JOIN
+        // jumps to.
+        if let Some(rj_meta) = t_ctx.meta_right_joins[joined_table_index].as_ref() {
+            let key_exprs = row_key_exprs(table);
+            let rowid_reg = program.alloc_registers(key_exprs.len());
+            for (i, expr) in key_exprs.iter().enumerate() {
+                translate_expr(
+                    program,
+                    Some(table_references),
+                    expr,
+                    rowid_reg + i,
+                    &t_ctx.resolver,
+                )?;
+            }
+            let label_already_matched = program.allocate_label();
+            program.emit_insn(Insn::Found {
+                cursor_id: rj_meta.matched_rowids_cursor_id,
+                target_pc: label_already_matched,
+                record_reg: rowid_reg,
+                num_regs: key_exprs.len(),
+            });
+            let record_reg = program.alloc_register();
+            program.emit_insn(Insn::MakeRecord {
+                start_reg: rowid_reg,
+                count: key_exprs.len(),
+                dest_reg: record_reg,
+                index_name: Some(rj_meta.matched_rowids_index_name.clone()),
+                affinity_str: None,
+            });
+            program.emit_insn(Insn::IdxInsert {
+                cursor_id: rj_meta.matched_rowids_cursor_id,
+                record_reg,
+                unpacked_start: None,
+                unpacked_count: None,
+                flags: IdxInsertFlags::new(),
+            });
+            program.preassign_label_to_next_insn(label_already_matched);
+        }
+
         // Set the match flag to true if this is a LEFT JOIN.
         // At this point of execution we are going to emit columns for the left table,
         // and either emit columns or NULLs for the right table, depending on whether the null_flag is set
@@ -668,6 +957,21 @@ pub fn open_loop(
             }
         }
 
+        // Evaluate the correlated subqueries whose last referenced table is this one.
+        // Like the WHERE clause conditions, they come after the match flag is set, so that they
+        // are evaluated again for the NULL row of an OUTER JOIN.
+        for subquery in subqueries
+            .iter_mut()
+            .filter(|subquery| subquery.eval_at(join_order) == EvalAt::Loop(join_index))
+        {
+            emit_non_from_clause_subquery(
+                program,
+                t_ctx.resolver.schema,
+                t_ctx.resolver.symbol_table,
+                subquery,
+            )?;
+        }
+
         // Now we can emit conditions from the WHERE clause.
         // If the right table produces a NULL row, control jumps to the point where the match flag is set.
         // The WHERE clause conditions may reference columns from that row, so they cannot be emitted
@@ -678,6 +982,7 @@ pub fn open_loop(
             table_references,
             join_order,
             predicates,
+            subqueries,
             join_index,
             next,
             false,
@@ -694,14 +999,39 @@ fn emit_conditions(
     table_references: &TableReferences,
     join_order: &[JoinOrderMember],
     predicates: &[WhereTerm],
+    subqueries: &[NonFromClauseSubquery],
     join_index: usize,
     next: BranchOffset,
     from_outer_join: bool,
 ) -> Result<()> {
+    // The WHERE clause filters the joined rows, so its conditions cannot be evaluated before the
+    // rows of the right table of the last RIGHT JOIN have been recorded as matched. Those that
+    // would be evaluated in an earlier loop are evaluated in the loop of that table instead.
+    let last_right_join = join_order.iter().rposition(|join| {
+        table_references.joined_tables()[join.original_idx]
+            .join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.right)
+    });
+    let is_deferred_to_this_loop = |cond: &WhereTerm| {
+        !from_outer_join
+            && !cond.consumed
+            && last_right_join == Some(join_index)
+            && matches!(
+                determine_where_to_eval_term(cond, join_order, subqueries),
+                Ok(EvalAt::Loop(i)) if i < join_index
+            )
+    };
     for cond in predicates
         .iter()
         .filter(|cond| cond.from_outer_join.is_some() == from_outer_join)
-        .filter(|cond| cond.should_eval_at_loop(join_index, join_order))
+        .filter(|cond| {
+            let eval_at_loop = cond.should_eval_at_loop(join_index, join_order, subqueries);
+            if eval_at_loop && !from_outer_join {
+                return last_right_join.is_none_or(|r| join_index >= r);
+            }
+            eval_at_loop || is_deferred_to_this_loop(cond)
+        })
     {
         let jump_target_when_true = program.allocate_label();
         let condition_metadata = ConditionMetadata {
@@ -821,12 +1151,13 @@ fn emit_loop_source(
             }
 
             // Step 2: Process arguments for all aggregate functions
-            // For each aggregate, translate all its argument expressions
+            // For each aggregate, translate all its argument expressions, followed by the
+            // expressions of its ORDER BY and FILTER clauses
             for agg in aggregates.iter() {
                 // For a query like: SELECT group_col, SUM(val1), AVG(val2) FROM table GROUP BY group_col
                 // we'll process val1 and val2 here, storing them in the sorter so they're available
                 // when computing the aggregates after sorting by group_col
-                for expr in agg.args.iter() {
+                for expr in agg.inputs() {
                     let agg_reg = cur_reg;
                     cur_reg += 1;
                     translate_expr(
@@ -883,7 +1214,7 @@ fn emit_loop_source(
                 translate_aggregation_step(
                     program,
                     &plan.table_references,
-                    AggArgumentSource::new_from_expression(&agg.func, &agg.args, &agg.distinctness),
+                    AggArgumentSource::new_from_aggregate(agg),
                     reg,
                     &t_ctx.resolver,
                 )?;
@@ -940,10 +1271,22 @@ fn emit_loop_source(
                 plan.aggregates.is_empty(),
                 "We should not get here with aggregates"
             );
-            let offset_jump_to = t_ctx
-                .labels_main_loop
-                .first()
-                .map(|l| l.next)
+            // Rows skipped by OFFSET continue with the next iteration of the innermost loop.
+            let offset_jump_to = plan
+                .join_order
+                .last()
+                .map(|join| {
+                    let labels = &t_ctx.labels_main_loop[join.original_idx];
+                    let is_semi = plan.table_references.joined_tables()[join.original_idx]
+                        .join_info
+                        .as_ref()
+                        .is_some_and(|join_info| join_info.semi);
+                    if is_semi {
+                        labels.loop_end
+                    } else {
+                        labels.next
+                    }
+                })
                 .or(t_ctx.label_main_loop_end);
             emit_select_result(
                 program,
@@ -1000,6 +1343,14 @@ pub fn close_loop(
 
         let (table_cursor_id, index_cursor_id) = table.resolve_cursors(program)?;
 
+        // A semi-join only needs the first match, so once a row has been emitted for it
+        // we leave its loop and continue with the next row of the table to its left.
+        if table.join_info.as_ref().is_some_and(|j| j.semi) {
+            program.emit_insn(Insn::Goto {
+                target_pc: loop_labels.loop_end,
+            });
+        }
+
         match &table.op {
             Operation::Scan(scan) => {
                 program.resolve_label(loop_labels.next, program.offset());
@@ -1099,14 +1450,7 @@ pub fn close_loop(
                 // First we set the right table cursor's "pseudo null bit" on, which means any Insn::Column will return NULL.
                 // This needs to be set for both the table and the index cursor, if present,
                 // since even if the iteration cursor is the index cursor, it might fetch values from the table cursor.
-                [table_cursor_id, index_cursor_id]
-                    .iter()
-                    .filter_map(|maybe_cursor_id| maybe_cursor_id.as_ref())
-                    .for_each(|cursor_id| {
-                        program.emit_insn(Insn::NullRow {
-                            cursor_id: *cursor_id,
-                        });
-                    });
+                emit_null_row(program, table)?;
                 // Then we jump to setting the left join match flag to 1 again,
                 // but this time the right table cursor will set everything to null.
                 // This leads to emitting a row with cols from the left + nulls from the right,
@@ -1123,6 +1467,212 @@ pub fn close_loop(
     Ok(())
 }
 
+/// Make the columns of a table read as NULL, e.g. for the NULL row of an OUTER JOIN.
+fn emit_null_row(program: &mut ProgramBuilder, table: &JoinedTable) -> Result<()> {
+    if let Table::FromClauseSubquery(from_clause_subquery) = &table.table {
+        // The columns of a subquery are read from the registers it yields them in.
+        if let Some(start_reg) = from_clause_subquery.result_columns_start_reg {
+            program.emit_insn(Insn::Null {
+                dest: start_reg,
+                dest_end: (from_clause_subquery.columns.len() > 1)
+                    .then(|| start_reg + from_clause_subquery.columns.len() - 1),
+            });
+        }
+        return Ok(());
+    }
+    let (table_cursor_id, index_cursor_id) = table.resolve_cursors(program)?;
+    [table_cursor_id, index_cursor_id]
+        .iter()
+        .filter_map(|maybe_cursor_id| maybe_cursor_id.as_ref())
+        .for_each(|cursor_id| {
+            program.emit_insn(Insn::NullRow {
+                cursor_id: *cursor_id,
+            });
+        });
+    Ok(())
+}
+
+/// Emit the rows of the right table of each RIGHT JOIN (or FULL OUTER JOIN) that had no match
+/// in the main loop, with NULLs for the tables to its left.
+///
+/// This loops over the right table again, skipping the rows whose rowids were recorded when they
+/// had a match, and for each remaining row runs the loops of the tables after it in the join order
+/// and emits the result like the main loop does. The tables up to the last RIGHT JOIN keep their
+/// order from the FROM clause, so the tables to the left of the right table are the ones before it
+/// in the join order.
+/// The expressions identifying a row of the table: its rowid, or the PRIMARY KEY columns of a
+/// WITHOUT ROWID table.
+fn row_key_exprs(table: &JoinedTable) -> Vec<ast::Expr> {
+    match table.btree().filter(|btree| !btree.has_rowid) {
+        Some(btree) => Index::without_rowid_primary_key(&btree)
+            .columns
+            .iter()
+            .map(|column| ast::Expr::Column {
+                database: None,
+                table: table.internal_id,
+                column: column.pos_in_table,
+                is_rowid_alias: false,
+            })
+            .collect(),
+        None => vec![ast::Expr::RowId {
+            database: None,
+            table: table.internal_id,
+        }],
+    }
+}
+
+pub fn emit_unmatched_right_join_rows(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    plan: &SelectPlan,
+) -> Result<()> {
+    if t_ctx.meta_right_joins.iter().all(|meta| meta.is_none()) {
+        return Ok(());
+    }
+    if plan
+        .non_from_clause_subqueries
+        .iter()
+        .any(|subquery| subquery.is_correlated())
+    {
+        crate::bail_parse_error!(
+            "correlated subqueries are not supported with RIGHT and FULL OUTER JOIN"
+        );
+    }
+    // The loops are emitted again, so the labels stored in the plan (e.g. the DISTINCT ones) are
+    // replaced on a copy of it.
+    let mut plan = plan.clone();
+    for join_index in 0..plan.join_order.len() {
+        let table_index = plan.join_order[join_index].original_idx;
+        let table = &plan.table_references.joined_tables()[table_index];
+        let Some(rj_meta) = t_ctx.meta_right_joins[table_index].as_ref() else {
+            continue;
+        };
+        let matched_rowids_cursor_id = rj_meta.matched_rowids_cursor_id;
+
+        for join in plan.join_order[..join_index].iter() {
+            emit_null_row(
+                program,
+                &plan.table_references.joined_tables()[join.original_idx],
+            )?;
+        }
+
+        // The loops are emitted again, so they need new labels.
+        for join in plan.join_order[join_index..].iter() {
+            t_ctx.labels_main_loop[join.original_idx] = LoopLabels::new(program);
+            if let Some(lj_meta) = t_ctx.meta_left_joins[join.original_idx].as_mut() {
+                lj_meta.label_match_flag_set_true = program.allocate_label();
+                lj_meta.label_match_flag_check_value = program.allocate_label();
+            }
+        }
+        if let Distinctness::Distinct { ctx: Some(ctx) } = &mut plan.distinctness {
+            ctx.label_on_conflict = program.allocate_label();
+        }
+        for agg in plan.aggregates.iter_mut() {
+            if let Distinctness::Distinct { ctx: Some(ctx) } = &mut agg.distinctness {
+                ctx.label_on_conflict = program.allocate_label();
+            }
+        }
+
+        let LoopLabels {
+            loop_start,
+            next,
+            loop_end,
+        } = t_ctx.labels_main_loop[table_index];
+        let (table_cursor_id, index_cursor_id) = table.resolve_cursors(program)?;
+        let cursor_id = if table.utilizes_covering_index() {
+            index_cursor_id.expect("covering index must be opened")
+        } else {
+            table_cursor_id.expect("RIGHT JOIN table cursor must be opened")
+        };
+        program.emit_insn(Insn::Rewind {
+            cursor_id,
+            pc_if_empty: loop_end,
+        });
+        program.preassign_label_to_next_insn(loop_start);
+        let key_exprs = row_key_exprs(table);
+        let rowid_reg = program.alloc_registers(key_exprs.len());
+        for (i, expr) in key_exprs.iter().enumerate() {
+            translate_expr(
+                program,
+                Some(&plan.table_references),
+                expr,
+                rowid_reg + i,
+                &t_ctx.resolver,
+            )?;
+        }
+        program.emit_insn(Insn::Found {
+            cursor_id: matched_rowids_cursor_id,
+            target_pc: next,
+            record_reg: rowid_reg,
+            num_regs: key_exprs.len(),
+        });
+
+        // The WHERE clause conditions of the loops up to this one are evaluated again, now that the
+        // tables to the left are NULL. The conditions of the joins are not: they are what this row
+        // had no match for.
+        for cond in plan
+            .where_clause
+            .iter()
+            .filter(|cond| cond.from_outer_join.is_none())
+        {
+            // Conditions consumed by the access method of a table are evaluated too, since the
+            // tables to the left are not looped over and this one is scanned.
+            match determine_where_to_eval_term(
+                cond,
+                &plan.join_order,
+                &plan.non_from_clause_subqueries,
+            )? {
+                EvalAt::Loop(i) if i <= join_index => {}
+                _ => continue,
+            }
+            let jump_target_when_true = program.allocate_label();
+            let condition_metadata = ConditionMetadata {
+                jump_if_condition_is_true: false,
+                jump_target_when_true,
+                jump_target_when_false: next,
+            };
+            translate_condition_expr(
+                program,
+                &plan.table_references,
+                &cond.expr,
+                condition_metadata,
+                &t_ctx.resolver,
+            )?;
+            program.preassign_label_to_next_insn(jump_target_when_true);
+        }
+
+        for inner_join_index in join_index + 1..plan.join_order.len() {
+            open_loop_at(
+                program,
+                t_ctx,
+                &plan.table_references,
+                &plan.join_order,
+                &plan.where_clause,
+                &mut plan.non_from_clause_subqueries,
+                None,
+                inner_join_index,
+            )?;
+        }
+        emit_loop(program, t_ctx, &plan)?;
+        close_loop(
+            program,
+            t_ctx,
+            &plan.table_references,
+            &plan.join_order[join_index + 1..],
+            None,
+        )?;
+
+        program.resolve_label(next, program.offset());
+        program.emit_insn(Insn::Next {
+            cursor_id,
+            pc_if_next: loop_start,
+        });
+        program.preassign_label_to_next_insn(loop_end);
+    }
+
+    Ok(())
+}
+
 /// Emits instructions for an index seek. See e.g. [crate::translate::plan::SeekDef]
 /// for more details about the seek definition.
 ///
@@ -1397,10 +1947,12 @@ fn emit_seek_termination(
 /// Returns the cursor id of the ephemeral index cursor.
 fn emit_autoindex(
     program: &mut ProgramBuilder,
+    table: &Table,
     index: &Arc<Index>,
     table_cursor_id: CursorID,
     index_cursor_id: CursorID,
     table_has_rowid: bool,
+    resolver: &Resolver,
 ) -> Result<CursorID> {
     assert!(index.ephemeral, "Index {} is not ephemeral", index.name);
     let label_ephemeral_build_end = program.allocate_label();
@@ -1424,7 +1976,17 @@ fn emit_autoindex(
     let ephemeral_cols_start_reg = program.alloc_registers(num_regs_to_reserve);
     for (i, col) in index.columns.iter().enumerate() {
         let reg = ephemeral_cols_start_reg + i;
-        program.emit_column_or_rowid(table_cursor_id, col.pos_in_table, reg);
+        match table.btree() {
+            Some(btree) => emit_table_column(
+                program,
+                &btree,
+                table_cursor_id,
+                col.pos_in_table,
+                reg,
+                resolver,
+            )?,
+            None => program.emit_column_or_rowid(table_cursor_id, col.pos_in_table, reg),
+        }
     }
     if table_has_rowid {
         program.emit_insn(Insn::RowId {
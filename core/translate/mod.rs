@@ -17,6 +17,7 @@ pub(crate) mod delete;
 pub(crate) mod display;
 pub(crate) mod emitter;
 pub(crate) mod expr;
+pub(crate) mod fkey;
 pub(crate) mod group_by;
 pub(crate) mod index;
 pub(crate) mod insert;
@@ -34,8 +35,10 @@ pub(crate) mod schema;
 pub(crate) mod select;
 pub(crate) mod subquery;
 pub(crate) mod transaction;
+pub(crate) mod trigger;
 pub(crate) mod update;
 pub(crate) mod upsert;
+pub(crate) mod vacuum;
 mod values;
 pub(crate) mod view;
 mod window;
@@ -44,19 +47,20 @@ use crate::schema::Schema;
 use crate::storage::pager::Pager;
 use crate::translate::delete::translate_delete;
 use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
+use crate::vdbe::insn::SavepointOp;
 use crate::vdbe::Program;
 use crate::{bail_parse_error, Connection, Result, SymbolTable};
 use alter::translate_alter_table;
 use analyze::translate_analyze;
-use index::{translate_create_index, translate_drop_index};
+use index::{translate_create_index, translate_drop_index, translate_reindex};
 use insert::translate_insert;
 use rollback::translate_rollback;
 use schema::{translate_create_table, translate_create_virtual_table, translate_drop_table};
 use select::translate_select;
 use std::sync::Arc;
 use tracing::{instrument, Level};
-use transaction::{translate_tx_begin, translate_tx_commit};
-use turso_parser::ast::{self, Indexed};
+use transaction::{translate_savepoint, translate_tx_begin, translate_tx_commit};
+use turso_parser::ast;
 use update::translate_update;
 
 #[instrument(skip_all, level = Level::DEBUG)]
@@ -106,6 +110,7 @@ pub fn translate(
         stmt => translate_inner(schema, stmt, syms, program, &connection, input)?,
     };
 
+    program.set_aux_schema_cookies(|db| connection.with_schema(db, |schema| schema.schema_version));
     program.epilogue(schema);
 
     Ok(program.build(connection, change_cnt_on, input))
@@ -118,13 +123,14 @@ pub fn translate_inner(
     schema: &Schema,
     stmt: ast::Stmt,
     syms: &SymbolTable,
-    program: ProgramBuilder,
+    mut program: ProgramBuilder,
     connection: &Arc<Connection>,
     input: &str,
 ) -> Result<ProgramBuilder> {
     let is_write = matches!(
         stmt,
         ast::Stmt::AlterTable { .. }
+            | ast::Stmt::Analyze { .. }
             | ast::Stmt::CreateIndex { .. }
             | ast::Stmt::CreateTable { .. }
             | ast::Stmt::CreateTrigger { .. }
@@ -134,10 +140,12 @@ pub fn translate_inner(
             | ast::Stmt::Delete { .. }
             | ast::Stmt::DropIndex { .. }
             | ast::Stmt::DropTable { .. }
+            | ast::Stmt::DropTrigger { .. }
             | ast::Stmt::DropView { .. }
             | ast::Stmt::Reindex { .. }
             | ast::Stmt::Update { .. }
             | ast::Stmt::Insert { .. }
+            | ast::Stmt::Vacuum { into: None, .. }
     );
 
     if is_write && connection.get_query_only() {
@@ -146,11 +154,30 @@ pub fn translate_inner(
 
     let is_select = matches!(stmt, ast::Stmt::Select { .. });
 
+    // Statements inside trigger programs always modify the trigger's own database.
+    let database_id = if program.is_trigger_program() {
+        program.database_id()
+    } else if is_write {
+        target_database_id(&stmt, connection)?
+    } else {
+        0
+    };
+    program.set_database_id(database_id);
+    let database_schema;
+    let schema = if database_id != 0 {
+        // Changes to the temp and attached databases are not captured.
+        program.disable_capture_data_changes();
+        database_schema = connection.get_database_schema(database_id)?;
+        database_schema.as_ref()
+    } else {
+        schema
+    };
+
     let mut program = match stmt {
         ast::Stmt::AlterTable(alter) => {
-            translate_alter_table(alter, syms, schema, program, connection, input)?
+            translate_alter_table(alter, database_id, syms, schema, program, connection, input)?
         }
-        ast::Stmt::Analyze { name } => translate_analyze(name, schema, syms, program)?,
+        ast::Stmt::Analyze { name } => translate_analyze(name, schema, syms, program, connection)?,
         ast::Stmt::Attach { expr, db_name, key } => {
             attach::translate_attach(&expr, &db_name, &key, schema, syms, program)?
         }
@@ -168,6 +195,7 @@ pub fn translate_inner(
             idx_name.name.as_str(),
             tbl_name.as_str(),
             &columns,
+            database_id,
             schema,
             syms,
             program,
@@ -175,13 +203,13 @@ pub fn translate_inner(
             where_clause,
         )?,
         ast::Stmt::CreateTable {
-            temporary,
             if_not_exists,
             tbl_name,
             body,
+            ..
         } => translate_create_table(
             tbl_name,
-            temporary,
+            database_id,
             if_not_exists,
             body,
             schema,
@@ -189,7 +217,9 @@ pub fn translate_inner(
             program,
             connection,
         )?,
-        ast::Stmt::CreateTrigger { .. } => bail_parse_error!("CREATE TRIGGER not supported yet"),
+        stmt @ ast::Stmt::CreateTrigger { .. } => {
+            trigger::translate_create_trigger(stmt, database_id, schema, syms, program, connection)?
+        }
         ast::Stmt::CreateView {
             view_name,
             select,
@@ -197,6 +227,7 @@ pub fn translate_inner(
             ..
         } => view::translate_create_view(
             schema,
+            database_id,
             view_name.name.as_str(),
             &select,
             &columns,
@@ -225,51 +256,66 @@ pub fn translate_inner(
             indexed,
             order_by,
             with,
-        } => {
-            if with.is_some() {
-                bail_parse_error!("WITH clause is not supported in DELETE");
-            }
-            if indexed.is_some_and(|i| matches!(i, Indexed::IndexedBy(_))) {
-                bail_parse_error!("INDEXED BY clause is not supported in DELETE");
-            }
-            if !order_by.is_empty() {
-                bail_parse_error!("ORDER BY clause is not supported in DELETE");
-            }
-            translate_delete(
-                schema,
-                &tbl_name,
-                where_clause,
-                limit,
-                returning,
-                syms,
-                program,
-                connection,
-            )?
-        }
+        } => translate_delete(
+            schema,
+            with,
+            &tbl_name,
+            indexed,
+            database_id,
+            where_clause,
+            order_by,
+            limit,
+            returning,
+            syms,
+            program,
+            connection,
+        )?,
         ast::Stmt::Detach { name } => attach::translate_detach(&name, schema, syms, program)?,
         ast::Stmt::DropIndex {
             if_exists,
             idx_name,
-        } => translate_drop_index(idx_name.name.as_str(), if_exists, schema, syms, program)?,
+        } => translate_drop_index(
+            idx_name.name.as_str(),
+            if_exists,
+            database_id,
+            schema,
+            syms,
+            program,
+        )?,
         ast::Stmt::DropTable {
             if_exists,
             tbl_name,
-        } => translate_drop_table(tbl_name, if_exists, schema, syms, program)?,
-        ast::Stmt::DropTrigger { .. } => bail_parse_error!("DROP TRIGGER not supported yet"),
+        } => translate_drop_table(tbl_name, if_exists, database_id, schema, syms, program)?,
+        ast::Stmt::DropTrigger {
+            if_exists,
+            trigger_name,
+        } => trigger::translate_drop_trigger(
+            schema,
+            database_id,
+            trigger_name.name.as_str(),
+            if_exists,
+            program,
+        )?,
         ast::Stmt::DropView {
             if_exists,
             view_name,
-        } => view::translate_drop_view(schema, view_name.name.as_str(), if_exists, program)?,
+        } => view::translate_drop_view(
+            schema,
+            database_id,
+            view_name.name.as_str(),
+            if_exists,
+            program,
+        )?,
         ast::Stmt::Pragma { .. } => {
             bail_parse_error!("PRAGMA statement cannot be evaluated in a nested context")
         }
-        ast::Stmt::Reindex { .. } => bail_parse_error!("REINDEX not supported yet"),
-        ast::Stmt::Release { .. } => bail_parse_error!("RELEASE not supported yet"),
+        ast::Stmt::Reindex { name } => translate_reindex(name, schema, syms, program, connection)?,
+        ast::Stmt::Release { name } => translate_savepoint(SavepointOp::Release, name, program)?,
         ast::Stmt::Rollback {
             tx_name,
             savepoint_name,
         } => translate_rollback(schema, syms, program, tx_name, savepoint_name)?,
-        ast::Stmt::Savepoint { .. } => bail_parse_error!("SAVEPOINT not supported yet"),
+        ast::Stmt::Savepoint { name } => translate_savepoint(SavepointOp::Begin, name, program)?,
         ast::Stmt::Select(select) => {
             translate_select(
                 schema,
@@ -282,9 +328,11 @@ pub fn translate_inner(
             .program
         }
         ast::Stmt::Update(mut update) => {
-            translate_update(schema, &mut update, syms, program, connection)?
+            translate_update(schema, &mut update, database_id, syms, program, connection)?
+        }
+        ast::Stmt::Vacuum { name, into } => {
+            vacuum::translate_vacuum(name, into, schema, syms, program, connection)?
         }
-        ast::Stmt::Vacuum { .. } => bail_parse_error!("VACUUM not supported yet"),
         ast::Stmt::Insert {
             with,
             or_conflict,
@@ -297,6 +345,7 @@ pub fn translate_inner(
             with,
             or_conflict,
             tbl_name,
+            database_id,
             columns,
             body,
             returning,
@@ -308,7 +357,7 @@ pub fn translate_inner(
 
     // Indicate write operations so that in the epilogue we can emit the correct type of transaction
     if is_write {
-        program.begin_write_operation();
+        program.begin_write_operation_on(database_id);
     }
 
     // Indicate read operations so that in the epilogue we can emit the correct type of transaction
@@ -318,3 +367,60 @@ pub fn translate_inner(
 
     Ok(program)
 }
+
+/// Returns the database modified by a write statement: 0 for the main database, 1 for the temp
+/// database, and the index of the attached database otherwise.
+fn target_database_id(stmt: &ast::Stmt, connection: &Arc<Connection>) -> Result<usize> {
+    let database_id = match stmt {
+        ast::Stmt::CreateTable {
+            temporary: true, ..
+        } => 1,
+        ast::Stmt::CreateTable { tbl_name, .. } if tbl_name.db_name.is_some() => {
+            connection.resolve_database_id(tbl_name)?
+        }
+        ast::Stmt::CreateIndex {
+            idx_name, tbl_name, ..
+        } => {
+            if idx_name.db_name.is_some() {
+                connection.resolve_database_id(idx_name)?
+            } else {
+                connection.resolve_database_id(&ast::QualifiedName::single(tbl_name.clone()))?
+            }
+        }
+        ast::Stmt::Delete { tbl_name, .. }
+        | ast::Stmt::DropTable { tbl_name, .. }
+        | ast::Stmt::Insert { tbl_name, .. } => connection.resolve_database_id(tbl_name)?,
+        ast::Stmt::Update(update) => connection.resolve_database_id(&update.tbl_name)?,
+        ast::Stmt::DropIndex { idx_name, .. } => connection.resolve_index_database_id(idx_name)?,
+        ast::Stmt::AlterTable(alter) => match connection.resolve_database_id(&alter.name)? {
+            1 => bail_parse_error!("ALTER TABLE is not supported for TEMP tables"),
+            database_id => database_id,
+        },
+        ast::Stmt::CreateView {
+            temporary: true, ..
+        } => bail_parse_error!("TEMP views are not supported yet"),
+        ast::Stmt::CreateView { view_name, .. } if view_name.db_name.is_some() => {
+            match connection.resolve_database_id(view_name)? {
+                1 => bail_parse_error!("TEMP views are not supported yet"),
+                database_id => database_id,
+            }
+        }
+        ast::Stmt::CreateMaterializedView { view_name, .. }
+            if view_name.db_name.is_some() && connection.resolve_database_id(view_name)? != 0 =>
+        {
+            bail_parse_error!("materialized views are only supported in the main database")
+        }
+        ast::Stmt::CreateTrigger { trigger_name, .. } if trigger_name.db_name.is_some() => {
+            match connection.resolve_database_id(trigger_name)? {
+                1 => bail_parse_error!("TEMP triggers are not supported yet"),
+                database_id => database_id,
+            }
+        }
+        ast::Stmt::DropView { view_name, .. } => connection.resolve_database_id(view_name)?,
+        ast::Stmt::DropTrigger { trigger_name, .. } if trigger_name.db_name.is_some() => {
+            connection.resolve_database_id(trigger_name)?
+        }
+        _ => 0,
+    };
+    Ok(database_id)
+}
@@ -1,19 +1,20 @@
 use std::sync::Arc;
 
 use turso_ext::{ConstraintInfo, ConstraintUsage, ResultCode};
-use turso_parser::ast::SortOrder;
+use turso_parser::ast::{self, SortOrder};
 
 use crate::translate::optimizer::constraints::{convert_to_vtab_constraint, Constraint};
 use crate::{
     schema::{Index, Table},
     translate::plan::{IterationDirection, JoinOrderMember, JoinedTable},
+    util::normalize_ident,
     vtab::VirtualTable,
     LimboError, Result,
 };
 
 use super::{
     constraints::{usable_constraints_for_join_order, ConstraintRef, TableConstraints},
-    cost::{estimate_cost_for_scan_or_seek, Cost, IndexInfo},
+    cost::{estimate_cost_for_scan_or_seek, Cost, IndexInfo, ESTIMATED_HARDCODED_ROWS_PER_TABLE},
     order::OrderTarget,
 };
 
@@ -79,7 +80,14 @@ pub fn find_best_access_method_for_join_order<'a>(
             input_cardinality,
         ),
         Table::FromClauseSubquery(_) => Ok(Some(AccessMethod {
-            cost: estimate_cost_for_scan_or_seek(None, &[], &[], input_cardinality),
+            cost: estimate_cost_for_scan_or_seek(
+                None,
+                &[],
+                &[],
+                input_cardinality,
+                ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64,
+                None,
+            ),
             params: AccessMethodParams::Subquery,
         })),
     }
@@ -93,7 +101,9 @@ fn find_best_access_method_for_btree<'a>(
     input_cardinality: f64,
 ) -> Result<Option<AccessMethod<'a>>> {
     let table_no = join_order.last().unwrap().table_id;
-    let mut best_cost = estimate_cost_for_scan_or_seek(None, &[], &[], input_cardinality);
+    let row_count = rhs_constraints.row_count();
+    let mut best_cost =
+        estimate_cost_for_scan_or_seek(None, &[], &[], input_cardinality, row_count, None);
     let mut best_params = AccessMethodParams::BTreeTable {
         iter_dir: IterationDirection::Forwards,
         index: None,
@@ -101,12 +111,50 @@ fn find_best_access_method_for_btree<'a>(
     };
     let rowid_column_idx = rhs_table.columns().iter().position(|c| c.is_rowid_alias);
 
+    // INDEXED BY only allows the named index, and rules out scanning the table.
+    // NOT INDEXED only allows rowid lookups and the PRIMARY KEY of a WITHOUT ROWID table,
+    // which is the table itself.
+    let indexed_by = match &rhs_table.indexed {
+        Some(ast::Indexed::IndexedBy(index_name)) => {
+            best_cost = Cost(f64::MAX);
+            Some(normalize_ident(index_name.as_str()))
+        }
+        _ => None,
+    };
+    let is_allowed = |index: Option<&Arc<Index>>| match &rhs_table.indexed {
+        None => true,
+        Some(ast::Indexed::IndexedBy(_)) => {
+            index.is_some_and(|index| Some(&index.name) == indexed_by.as_ref())
+        }
+        Some(ast::Indexed::NotIndexed) => index.is_none_or(|index| {
+            rhs_table
+                .btree()
+                .is_some_and(|btree| index.is_without_rowid_primary_key_of(&btree))
+        }),
+    };
+    if indexed_by.is_some()
+        && !rhs_constraints
+            .candidates
+            .iter()
+            .any(|candidate| is_allowed(candidate.index.as_ref()))
+    {
+        return Err(LimboError::ParseError("no query solution".to_string()));
+    }
+
     // Estimate cost for each candidate index (including the rowid index) and replace best_access_method if the cost is lower.
-    for candidate in rhs_constraints.candidates.iter() {
+    for candidate in rhs_constraints
+        .candidates
+        .iter()
+        .filter(|candidate| is_allowed(candidate.index.as_ref()))
+    {
         let index_info = match candidate.index.as_ref() {
             Some(index) => IndexInfo {
                 unique: index.unique,
-                covering: rhs_table.index_is_covering(index),
+                // Searching the PRIMARY KEY of a WITHOUT ROWID table reads the rows directly.
+                covering: rhs_table.index_is_covering(index)
+                    || rhs_table
+                        .btree()
+                        .is_some_and(|btree| index.is_without_rowid_primary_key_of(&btree)),
                 column_count: index.columns.len(),
             },
             None => IndexInfo {
@@ -125,6 +173,11 @@ fn find_best_access_method_for_btree<'a>(
             &rhs_constraints.constraints,
             usable_constraint_refs,
             input_cardinality,
+            row_count,
+            candidate
+                .index
+                .as_ref()
+                .and_then(|index| rhs_constraints.index_stats(index)),
         );
 
         // All other things being equal, prefer an access method that satisfies the order target.
@@ -138,9 +191,10 @@ fn find_best_access_method_for_btree<'a>(
                 let correct_table = order_target.0[i].table_id == table_no;
                 let correct_column = {
                     match &candidate.index {
-                        Some(index) => index.columns[i].pos_in_table == order_target.0[i].column_no,
+                        Some(index) => order_target.0[i].is_index_column(index, i, rhs_table),
                         None => {
-                            rowid_column_idx.is_some_and(|idx| idx == order_target.0[i].column_no)
+                            rowid_column_idx.is_some()
+                                && rowid_column_idx == order_target.0[i].column_no
                         }
                     }
                 };
@@ -208,7 +262,14 @@ fn find_best_access_method_for_vtab<'a>(
         Ok(index_info) => {
             Ok(Some(AccessMethod {
                 // TODO: Base cost on `IndexInfo::estimated_cost` and output cardinality on `IndexInfo::estimated_rows`
-                cost: estimate_cost_for_scan_or_seek(None, &[], &[], input_cardinality),
+                cost: estimate_cost_for_scan_or_seek(
+                    None,
+                    &[],
+                    &[],
+                    input_cardinality,
+                    ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64,
+                    None,
+                ),
                 params: AccessMethodParams::VirtualTable {
                     idx_num: index_info.idx_num,
                     idx_str: index_info.idx_str,
@@ -6,13 +6,14 @@ use std::{
 
 use crate::{
     schema::{Column, Index},
+    stats::{IndexStats, TableStats},
     translate::{
-        expr::as_binary_components,
-        plan::{JoinOrderMember, TableReferences, WhereTerm},
+        expr::{as_binary_components, sanitize_string},
+        plan::{JoinOrderMember, NonFromClauseSubquery, TableReferences, WhereTerm},
         planner::{table_mask_from_expr, TableMask},
     },
-    util::exprs_are_equivalent,
-    Result,
+    util::{exprs_are_equivalent, normalize_ident, parse_signed_number},
+    Result, Value,
 };
 use turso_ext::{ConstraintInfo, ConstraintOp};
 use turso_parser::ast::{self, SortOrder, TableInternalId};
@@ -44,8 +45,10 @@ pub struct Constraint {
     pub where_clause_pos: (usize, BinaryExprSide),
     /// The comparison operator (e.g., `=`, `>`, `<`) used in the constraint.
     pub operator: ast::Operator,
-    /// The zero-based index of the constrained column within the table's schema.
-    pub table_col_pos: usize,
+    /// The zero-based index of the constrained column within the table's schema, or None if the
+    /// constrained side is an expression of the table's columns that an index is on, e.g.
+    /// `lower(t.x) = 'a'` with an index on `lower(x)`.
+    pub table_col_pos: Option<usize>,
     /// A bitmask representing the set of tables that appear on the *constraining* side
     /// of the comparison expression. For example, in SELECT * FROM t1,t2,t3 WHERE t1.x = t2.x + t3.x,
     /// the lhs_mask contains t2 and t3. Thus, this constraint can only be used if t2 and t3
@@ -76,6 +79,20 @@ impl Constraint {
             rhs.clone()
         }
     }
+
+    /// Get the constrained expression, e.g. 'lower(t.x)' from 'lower(t.x) = 2+3'
+    pub fn get_constrained_expr(&self, where_clause: &[WhereTerm]) -> ast::Expr {
+        let (idx, side) = self.where_clause_pos;
+        let where_term = &where_clause[idx];
+        let Ok(Some((lhs, _, rhs))) = as_binary_components(&where_term.expr) else {
+            panic!("Expected a valid binary expression");
+        };
+        if side == BinaryExprSide::Lhs {
+            rhs.clone()
+        } else {
+            lhs.clone()
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -145,6 +162,124 @@ pub struct TableConstraints {
     pub constraints: Vec<Constraint>,
     /// Candidates for indexes that may use the constraints to perform a lookup.
     pub candidates: Vec<ConstraintUseCandidate>,
+    /// The statistics gathered by ANALYZE for the table, if any.
+    pub stats: Option<Arc<TableStats>>,
+}
+
+impl TableConstraints {
+    /// The number of rows of the table, from its statistics if it was analyzed.
+    pub fn row_count(&self) -> f64 {
+        self.stats
+            .as_ref()
+            .and_then(|stats| stats.row_count)
+            .map_or(ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64, |rows| {
+                rows.max(1) as f64
+            })
+    }
+
+    /// The statistics of an index of the table, if it was analyzed.
+    pub fn index_stats(&self, index: &Index) -> Option<&IndexStats> {
+        self.stats.as_ref()?.indexes.get(&index.name)
+    }
+
+    /// Replace the estimated selectivities of the constraints with ones derived from the
+    /// statistics of the table: the average number of rows per value of the first column of an
+    /// index, or the sampled rows of the index when the constraint compares with a literal.
+    pub fn apply_stats(
+        &mut self,
+        stats: Arc<TableStats>,
+        columns: &[Column],
+        where_clause: &[WhereTerm],
+    ) {
+        if stats.row_count.is_none() {
+            return;
+        }
+        self.stats = Some(stats);
+        let rows = self.row_count();
+        for (i, constraint) in self.constraints.iter_mut().enumerate() {
+            let unique = constraint.operator == ast::Operator::Equals
+                && constraint
+                    .table_col_pos
+                    .is_some_and(|pos| columns[pos].is_rowid_alias || columns[pos].primary_key);
+            if unique {
+                constraint.selectivity = 1.0 / rows;
+                continue;
+            }
+            // The statistics of the first index that has the constrained column as its first column.
+            let Some(index_stats) = self.candidates.iter().find_map(|candidate| {
+                let index = candidate.index.as_ref()?;
+                candidate
+                    .refs
+                    .iter()
+                    .any(|cref| cref.constraint_vec_pos == i && cref.index_col_pos == 0)
+                    .then(|| self.stats.as_ref()?.indexes.get(&index.name))
+                    .flatten()
+            }) else {
+                continue;
+            };
+            let value = literal_value(&constraint.get_constraining_expr(where_clause));
+            let selectivity = match constraint.operator {
+                ast::Operator::Equals => {
+                    let sampled = value.as_ref().and_then(|value| {
+                        index_stats
+                            .samples
+                            .iter()
+                            .find(|sample| sample.key.first() == Some(value))
+                    });
+                    match (sampled, index_stats.avg_eq.first()) {
+                        (Some(sample), _) => sample.eq[0] as f64 / rows,
+                        (None, Some(avg_eq)) => *avg_eq as f64 / rows,
+                        (None, None) => continue,
+                    }
+                }
+                op @ (ast::Operator::Less
+                | ast::Operator::LessEquals
+                | ast::Operator::Greater
+                | ast::Operator::GreaterEquals) => {
+                    let Some(value) = value.filter(|_| !index_stats.samples.is_empty()) else {
+                        continue;
+                    };
+                    let rows_below = |inclusive: bool| {
+                        let mut below = 0;
+                        for sample in index_stats.samples.iter() {
+                            let Some(key) = sample.key.first() else {
+                                break;
+                            };
+                            match key.partial_cmp(&value) {
+                                Some(Ordering::Less) => below = sample.lt[0] + sample.eq[0],
+                                Some(Ordering::Equal) => {
+                                    below = sample.lt[0] + if inclusive { sample.eq[0] } else { 0 };
+                                    break;
+                                }
+                                _ => break,
+                            }
+                        }
+                        below as f64
+                    };
+                    let matching = match op {
+                        ast::Operator::Less => rows_below(false),
+                        ast::Operator::LessEquals => rows_below(true),
+                        ast::Operator::Greater => rows - rows_below(true),
+                        _ => rows - rows_below(false),
+                    };
+                    matching.max(1.0) / rows
+                }
+                _ => continue,
+            };
+            constraint.selectivity = selectivity.clamp(0.0, 1.0);
+        }
+    }
+}
+
+/// The value of a literal, possibly signed, for comparing with the sampled rows of an index.
+fn literal_value(expr: &ast::Expr) -> Option<Value> {
+    match expr {
+        ast::Expr::Literal(ast::Literal::String(s)) => Some(Value::build_text(sanitize_string(s))),
+        ast::Expr::Literal(ast::Literal::Numeric(_)) | ast::Expr::Unary(..) => {
+            parse_signed_number(expr).ok()
+        }
+        _ => None,
+    }
 }
 
 /// In lieu of statistics, we estimate that an equality filter will reduce the output set to 1% of its size.
@@ -157,10 +292,11 @@ const SELECTIVITY_OTHER: f64 = 0.9;
 const SELECTIVITY_UNIQUE_EQUALITY: f64 = 1.0 / ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64;
 
 /// Estimate the selectivity of a constraint based on the operator and the column type.
-fn estimate_selectivity(column: &Column, op: ast::Operator) -> f64 {
+/// The column is None for a constraint on an expression.
+fn estimate_selectivity(column: Option<&Column>, op: ast::Operator) -> f64 {
     match op {
         ast::Operator::Equals => {
-            if column.is_rowid_alias || column.primary_key {
+            if column.is_some_and(|column| column.is_rowid_alias || column.primary_key) {
                 SELECTIVITY_UNIQUE_EQUALITY
             } else {
                 SELECTIVITY_EQ
@@ -180,6 +316,7 @@ pub fn constraints_from_where_clause(
     where_clause: &[WhereTerm],
     table_references: &TableReferences,
     available_indexes: &HashMap<String, VecDeque<Arc<Index>>>,
+    subqueries: &[NonFromClauseSubquery],
 ) -> Result<Vec<TableConstraints>> {
     let mut constraints = Vec::new();
 
@@ -190,20 +327,49 @@ pub fn constraints_from_where_clause(
             .iter()
             .position(|c| c.is_rowid_alias);
 
+        let mut table_indexes: Vec<Arc<Index>> = available_indexes
+            .get(table_reference.table.get_name())
+            .map_or(Vec::new(), |indexes| indexes.iter().cloned().collect());
+        // The rows of a WITHOUT ROWID table can be searched by their PRIMARY KEY.
+        if let Some(btree) = table_reference
+            .table
+            .btree()
+            .filter(|btree| !btree.has_rowid)
+        {
+            table_indexes.push(Arc::new(Index::without_rowid_primary_key(&btree)));
+        }
+
+        // The expressions of the columns of the indexes on expressions, bound to the table
+        // reference so that they can be compared with the expressions of the WHERE clause.
+        let index_exprs = table_indexes
+            .iter()
+            .map(|index| {
+                index
+                    .columns
+                    .iter()
+                    .map(|c| c.bind_expr(&table_reference.table, table_reference.internal_id))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let is_indexed_expr = |expr: &ast::Expr| {
+            index_exprs
+                .iter()
+                .flatten()
+                .flatten()
+                .any(|index_expr| exprs_are_equivalent(index_expr, expr))
+        };
+
         let mut cs = TableConstraints {
             table_id: table_reference.internal_id,
             constraints: Vec::new(),
-            candidates: available_indexes
-                .get(table_reference.table.get_name())
-                .map_or(Vec::new(), |indexes| {
-                    indexes
-                        .iter()
-                        .map(|index| ConstraintUseCandidate {
-                            index: Some(index.clone()),
-                            refs: Vec::new(),
-                        })
-                        .collect()
-                }),
+            candidates: table_indexes
+                .iter()
+                .map(|index| ConstraintUseCandidate {
+                    index: Some(index.clone()),
+                    refs: Vec::new(),
+                })
+                .collect(),
+            stats: None,
         };
         // Add a candidate for the rowid index, which is always available when the table has a rowid alias.
         cs.candidates.push(ConstraintUseCandidate {
@@ -232,9 +398,9 @@ pub fn constraints_from_where_clause(
                         cs.constraints.push(Constraint {
                             where_clause_pos: (i, BinaryExprSide::Rhs),
                             operator,
-                            table_col_pos: *column,
-                            lhs_mask: table_mask_from_expr(rhs, table_references)?,
-                            selectivity: estimate_selectivity(table_column, operator),
+                            table_col_pos: Some(*column),
+                            lhs_mask: table_mask_from_expr(rhs, table_references, subqueries)?,
+                            selectivity: estimate_selectivity(Some(table_column), operator),
                         });
                     }
                 }
@@ -248,12 +414,21 @@ pub fn constraints_from_where_clause(
                         cs.constraints.push(Constraint {
                             where_clause_pos: (i, BinaryExprSide::Rhs),
                             operator,
-                            table_col_pos: rowid_alias_column.unwrap(),
-                            lhs_mask: table_mask_from_expr(rhs, table_references)?,
-                            selectivity: estimate_selectivity(table_column, operator),
+                            table_col_pos: rowid_alias_column,
+                            lhs_mask: table_mask_from_expr(rhs, table_references, subqueries)?,
+                            selectivity: estimate_selectivity(Some(table_column), operator),
                         });
                     }
                 }
+                _ if is_indexed_expr(lhs) => {
+                    cs.constraints.push(Constraint {
+                        where_clause_pos: (i, BinaryExprSide::Rhs),
+                        operator,
+                        table_col_pos: None,
+                        lhs_mask: table_mask_from_expr(rhs, table_references, subqueries)?,
+                        selectivity: estimate_selectivity(None, operator),
+                    });
+                }
                 _ => {}
             };
             match rhs {
@@ -263,9 +438,9 @@ pub fn constraints_from_where_clause(
                         cs.constraints.push(Constraint {
                             where_clause_pos: (i, BinaryExprSide::Lhs),
                             operator: opposite_cmp_op(operator),
-                            table_col_pos: *column,
-                            lhs_mask: table_mask_from_expr(lhs, table_references)?,
-                            selectivity: estimate_selectivity(table_column, operator),
+                            table_col_pos: Some(*column),
+                            lhs_mask: table_mask_from_expr(lhs, table_references, subqueries)?,
+                            selectivity: estimate_selectivity(Some(table_column), operator),
                         });
                     }
                 }
@@ -276,12 +451,21 @@ pub fn constraints_from_where_clause(
                         cs.constraints.push(Constraint {
                             where_clause_pos: (i, BinaryExprSide::Lhs),
                             operator: opposite_cmp_op(operator),
-                            table_col_pos: rowid_alias_column.unwrap(),
-                            lhs_mask: table_mask_from_expr(lhs, table_references)?,
-                            selectivity: estimate_selectivity(table_column, operator),
+                            table_col_pos: rowid_alias_column,
+                            lhs_mask: table_mask_from_expr(lhs, table_references, subqueries)?,
+                            selectivity: estimate_selectivity(Some(table_column), operator),
                         });
                     }
                 }
+                _ if is_indexed_expr(rhs) => {
+                    cs.constraints.push(Constraint {
+                        where_clause_pos: (i, BinaryExprSide::Lhs),
+                        operator: opposite_cmp_op(operator),
+                        table_col_pos: None,
+                        lhs_mask: table_mask_from_expr(lhs, table_references, subqueries)?,
+                        selectivity: estimate_selectivity(None, operator),
+                    });
+                }
                 _ => {}
             };
         }
@@ -299,7 +483,7 @@ pub fn constraints_from_where_clause(
 
         // For each constraint we found, add a reference to it for each index that may be able to use it.
         for (i, constraint) in cs.constraints.iter().enumerate() {
-            if rowid_alias_column == Some(constraint.table_col_pos) {
+            if rowid_alias_column.is_some() && rowid_alias_column == constraint.table_col_pos {
                 let rowid_candidate = cs
                     .candidates
                     .iter_mut()
@@ -317,12 +501,19 @@ pub fn constraints_from_where_clause(
                     sort_order: SortOrder::Asc,
                 });
             }
-            for index in available_indexes
-                .get(table_reference.table.get_name())
-                .unwrap_or(&VecDeque::new())
-            {
+            for (index, exprs) in table_indexes.iter().zip(index_exprs.iter()) {
+                let position_in_index = match constraint.table_col_pos {
+                    Some(table_col_pos) => index.column_table_pos_to_index_pos(table_col_pos),
+                    None => {
+                        let constrained_expr = constraint.get_constrained_expr(where_clause);
+                        exprs.iter().position(|expr| {
+                            expr.as_ref()
+                                .is_some_and(|expr| exprs_are_equivalent(expr, &constrained_expr))
+                        })
+                    }
+                };
                 if let Some(position_in_index) =
-                    index.column_table_pos_to_index_pos(constraint.table_col_pos)
+                    position_in_index.filter(|pos| *pos < index.columns.len())
                 {
                     if let Some(index_candidate) = cs.candidates.iter_mut().find_map(|candidate| {
                         if candidate.index.as_ref().is_some_and(|i| {
@@ -365,11 +556,17 @@ pub fn constraints_from_where_clause(
                 candidate.refs.truncate(first_inequality + 1);
             }
         }
+        let indexed_by = match &table_reference.indexed {
+            Some(ast::Indexed::IndexedBy(index_name)) => Some(normalize_ident(index_name.as_str())),
+            _ => None,
+        };
         cs.candidates.retain(|c| {
             if let Some(idx) = &c.index {
                 if idx.where_clause.is_some() && c.refs.is_empty() {
-                    // prevent a partial index from even being considered as a scan driver.
-                    return false;
+                    // prevent a partial index from even being considered as a scan driver,
+                    // unless INDEXED BY asks for it and the WHERE clause implies its predicate.
+                    return indexed_by.as_ref() == Some(&idx.name)
+                        && can_use_partial_index(idx, where_clause);
                 }
             }
             true
@@ -447,8 +644,9 @@ pub fn convert_to_vtab_constraint(
                 return None;
             }
             let all_required_tables_are_on_left_side = lhs_mask.contains_all(&constraint.lhs_mask);
+            let column_index = constraint.table_col_pos?;
             to_ext_constraint_op(&constraint.operator).map(|op| ConstraintInfo {
-                column_index: constraint.table_col_pos as u32,
+                column_index: column_index as u32,
                 op,
                 usable: all_required_tables_are_on_left_side,
                 index: i,
@@ -1,4 +1,7 @@
+use turso_parser::ast;
+
 use super::constraints::{Constraint, ConstraintRef};
+use crate::stats::IndexStats;
 
 /// A simple newtype wrapper over a f64 that represents the cost of an operation.
 ///
@@ -39,34 +42,43 @@ pub fn estimate_page_io_cost(rowcount: f64) -> Cost {
 /// Estimate the cost of a scan or seek operation.
 ///
 /// This is a very simple model that estimates the number of pages read
-/// based on the number of rows read, ignoring any CPU costs.
+/// based on the number of rows read, ignoring any CPU costs. The table is
+/// estimated to have `row_count` rows. If the index was analyzed, a lookup of
+/// the leading equality constraints returns the average number of rows per
+/// value of those columns.
 pub fn estimate_cost_for_scan_or_seek(
     index_info: Option<IndexInfo>,
     constraints: &[Constraint],
     usable_constraint_refs: &[ConstraintRef],
     input_cardinality: f64,
+    row_count: f64,
+    index_stats: Option<&IndexStats>,
 ) -> Cost {
     let Some(index_info) = index_info else {
-        return estimate_page_io_cost(
-            input_cardinality * ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64,
-        );
+        return estimate_page_io_cost(input_cardinality * row_count);
     };
 
-    let selectivity_multiplier: f64 = usable_constraint_refs
+    let selectivity = |refs: &[ConstraintRef]| -> f64 {
+        refs.iter()
+            .map(|cref| constraints[cref.constraint_vec_pos].selectivity)
+            .product()
+    };
+    let num_equalities = usable_constraint_refs
         .iter()
-        .map(|cref| {
-            let constraint = &constraints[cref.constraint_vec_pos];
-            constraint.selectivity
-        })
-        .product();
+        .take_while(|cref| constraints[cref.constraint_vec_pos].operator == ast::Operator::Equals)
+        .count();
+    let rows_per_lookup = match index_stats
+        .filter(|_| num_equalities > 0)
+        .and_then(|stats| stats.avg_eq.get(num_equalities - 1))
+    {
+        Some(avg_eq) => {
+            (*avg_eq).max(1) as f64 * selectivity(&usable_constraint_refs[num_equalities..])
+        }
+        None => selectivity(usable_constraint_refs) * row_count,
+    };
 
     // little cheeky bonus for covering indexes
     let covering_multiplier = if index_info.covering { 0.9 } else { 1.0 };
 
-    estimate_page_io_cost(
-        selectivity_multiplier
-            * ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64
-            * input_cardinality
-            * covering_multiplier,
-    )
+    estimate_page_io_cost(rows_per_lookup * input_cardinality * covering_multiplier)
 }
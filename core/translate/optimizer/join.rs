@@ -14,7 +14,6 @@ use crate::{
 use super::{
     access_method::{find_best_access_method_for_join_order, AccessMethod},
     constraints::TableConstraints,
-    cost::ESTIMATED_HARDCODED_ROWS_PER_TABLE,
     order::OrderTarget,
 };
 
@@ -98,10 +97,9 @@ pub fn join_lhs_and_rhs<'a>(
     // Produce a number of rows estimated to be returned when this table is filtered by the WHERE clause.
     // If this table is the rightmost table in the join order, we multiply by the input cardinality,
     // which is the output cardinality of the previous tables.
-    let output_cardinality = (input_cardinality as f64
-        * ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64
-        * output_cardinality_multiplier)
-        .ceil() as usize;
+    let output_cardinality =
+        (input_cardinality as f64 * rhs_constraints.row_count() * output_cardinality_multiplier)
+            .ceil() as usize;
 
     Ok(Some(JoinN {
         data: best_access_methods,
@@ -218,12 +216,18 @@ pub fn compute_best_join_order<'a>(
     // Example:
     // "a LEFT JOIN b" can NOT be reordered as "b LEFT JOIN a".
     // If there are outer joins in the plan, ensure correct ordering.
+    // The same goes for semi-joins, whose table must come after the tables to its left.
+    // The unmatched rows of a RIGHT JOIN are emitted after the main loop by looping over its table and
+    // the tables after it again, so the tables up to the last RIGHT JOIN are kept in their original order.
+    let last_right_join = joined_tables
+        .iter()
+        .rposition(|t| t.join_info.as_ref().is_some_and(|j| j.right));
     let left_join_illegal_map = {
         let left_join_count = joined_tables
             .iter()
-            .filter(|t| t.join_info.as_ref().is_some_and(|j| j.outer))
+            .filter(|t| t.join_info.as_ref().is_some_and(|j| j.outer || j.semi))
             .count();
-        if left_join_count == 0 {
+        if left_join_count == 0 && last_right_join.is_none() {
             None
         } else {
             // map from rhs table index to lhs table index
@@ -231,7 +235,12 @@ pub fn compute_best_join_order<'a>(
                 HashMap::with_capacity(left_join_count);
             for (i, _) in joined_tables.iter().enumerate() {
                 for (j, joined_table) in joined_tables.iter().enumerate().skip(i + 1) {
-                    if joined_table.join_info.as_ref().is_some_and(|j| j.outer) {
+                    if joined_table
+                        .join_info
+                        .as_ref()
+                        .is_some_and(|j| j.outer || j.semi)
+                        || last_right_join.is_some_and(|last_right_join| i <= last_right_join)
+                    {
                         // bitwise OR the masks
                         if let Some(illegal_lhs) = left_join_illegal_map.get_mut(&i) {
                             illegal_lhs.add_table(j);
@@ -508,6 +517,7 @@ mod tests {
     use super::*;
     use crate::{
         schema::{BTreeTable, Column, Index, IndexColumn, Table, Type},
+        stats::{IndexStats, TableStats},
         translate::{
             optimizer::access_method::AccessMethodParams,
             optimizer::constraints::{
@@ -540,9 +550,13 @@ mod tests {
         let where_clause = vec![];
 
         let access_methods_arena = RefCell::new(Vec::new());
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         let result = compute_best_join_order(
             table_references.joined_tables(),
@@ -569,9 +583,13 @@ mod tests {
         let where_clause = vec![];
 
         let access_methods_arena = RefCell::new(Vec::new());
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         // SELECT * from test_table
         // expecting best_best_plan() not to do any work due to empty where clause.
@@ -610,9 +628,13 @@ mod tests {
         let table_references = TableReferences::new(joined_tables, vec![]);
         let access_methods_arena = RefCell::new(Vec::new());
         let available_indexes = HashMap::new();
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         // SELECT * FROM test_table WHERE id = 42
         // expecting a RowidEq access method because id is a rowid alias.
@@ -671,17 +693,23 @@ mod tests {
                 pos_in_table: 0,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: true,
             ephemeral: false,
             root_page: 1,
             has_rowid: true,
+            primary_key_suffix: vec![],
         });
         available_indexes.insert("test_table".to_string(), VecDeque::from([index]));
 
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
         // SELECT * FROM test_table WHERE id = 42
         // expecting an IndexScan access method because id is a primary key with an index
         let result = compute_best_join_order(
@@ -720,7 +748,9 @@ mod tests {
                 t2.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: vec![],
+                    semi: false,
                 }),
                 table_id_counter.next(),
             ),
@@ -741,11 +771,13 @@ mod tests {
                 pos_in_table: 0,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: true,
             ephemeral: false,
             root_page: 1,
             has_rowid: true,
+            primary_key_suffix: vec![],
         });
         available_indexes.insert("table1".to_string(), VecDeque::from([index1]));
 
@@ -759,9 +791,13 @@ mod tests {
 
         let table_references = TableReferences::new(joined_tables, vec![]);
         let access_methods_arena = RefCell::new(Vec::new());
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         let result = compute_best_join_order(
             table_references.joined_tables(),
@@ -825,7 +861,9 @@ mod tests {
                 table_customers.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: vec![],
+                    semi: false,
                 }),
                 table_id_counter.next(),
             ),
@@ -833,7 +871,9 @@ mod tests {
                 table_order_items.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: vec![],
+                    semi: false,
                 }),
                 table_id_counter.next(),
             ),
@@ -859,11 +899,13 @@ mod tests {
                         pos_in_table: 0,
                         collation: None,
                         default: None,
+                        expr: None,
                     }],
                     unique: true,
                     ephemeral: false,
                     root_page: 1,
                     has_rowid: true,
+                    primary_key_suffix: vec![],
                 });
                 available_indexes.insert(table_name.to_string(), VecDeque::from([index]));
             });
@@ -877,11 +919,13 @@ mod tests {
                 pos_in_table: 1,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: false,
             ephemeral: false,
             root_page: 1,
             has_rowid: true,
+            primary_key_suffix: vec![],
         });
         let order_id_idx = Arc::new(Index {
             name: "order_items_order_id_idx".to_string(),
@@ -893,11 +937,13 @@ mod tests {
                 pos_in_table: 1,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: false,
             ephemeral: false,
             root_page: 1,
             has_rowid: true,
+            primary_key_suffix: vec![],
         });
 
         available_indexes
@@ -935,9 +981,13 @@ mod tests {
 
         let table_references = TableReferences::new(joined_tables, vec![]);
         let access_methods_arena = RefCell::new(Vec::new());
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         let result = compute_best_join_order(
             table_references.joined_tables(),
@@ -1012,7 +1062,9 @@ mod tests {
                 t2.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: vec![],
+                    semi: false,
                 }),
                 table_id_counter.next(),
             ),
@@ -1020,7 +1072,9 @@ mod tests {
                 t3.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: vec![],
+                    semi: false,
                 }),
                 table_id_counter.next(),
             ),
@@ -1044,9 +1098,13 @@ mod tests {
         let table_references = TableReferences::new(joined_tables, vec![]);
         let available_indexes = HashMap::new();
         let access_methods_arena = RefCell::new(Vec::new());
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
@@ -1118,7 +1176,9 @@ mod tests {
                     t.clone(),
                     Some(JoinInfo {
                         outer: false,
+                        right: false,
                         using: vec![],
+                        semi: false,
                     }),
                     table_id_counter.next(),
                 )
@@ -1127,7 +1187,9 @@ mod tests {
                 fact_table.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: vec![],
+                    semi: false,
                 }),
                 table_id_counter.next(),
             ));
@@ -1150,9 +1212,13 @@ mod tests {
         let table_references = TableReferences::new(joined_tables, vec![]);
         let access_methods_arena = RefCell::new(Vec::new());
         let available_indexes = HashMap::new();
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         let result = compute_best_join_order(
             table_references.joined_tables(),
@@ -1234,9 +1300,13 @@ mod tests {
 
         let table_references = TableReferences::new(joined_tables, vec![]);
         let access_methods_arena = RefCell::new(Vec::new());
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         // Run the optimizer
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
@@ -1308,6 +1378,7 @@ mod tests {
                     pos_in_table: 0,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "y".to_string(),
@@ -1315,12 +1386,14 @@ mod tests {
                     pos_in_table: 1,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
             ],
             unique: false,
             root_page: 2,
             ephemeral: false,
             has_rowid: true,
+            primary_key_suffix: vec![],
         });
 
         let mut available_indexes = HashMap::new();
@@ -1335,6 +1408,7 @@ mod tests {
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            indexed: None,
         });
 
         // Create where clause that only references second column
@@ -1355,9 +1429,13 @@ mod tests {
 
         let table_references = TableReferences::new(joined_tables, vec![]);
         let access_methods_arena = RefCell::new(Vec::new());
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
@@ -1395,6 +1473,7 @@ mod tests {
                     pos_in_table: 0,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c2".to_string(),
@@ -1402,6 +1481,7 @@ mod tests {
                     pos_in_table: 1,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c3".to_string(),
@@ -1409,12 +1489,14 @@ mod tests {
                     pos_in_table: 2,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
             ],
             unique: false,
             root_page: 2,
             ephemeral: false,
             has_rowid: true,
+            primary_key_suffix: vec![],
         });
         available_indexes.insert("t1".to_string(), VecDeque::from([index]));
 
@@ -1427,6 +1509,7 @@ mod tests {
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            indexed: None,
         });
 
         // Create where clause that references first and third columns
@@ -1463,9 +1546,13 @@ mod tests {
 
         let table_references = TableReferences::new(joined_tables, vec![]);
         let access_methods_arena = RefCell::new(Vec::new());
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
@@ -1483,7 +1570,7 @@ mod tests {
         assert!(constraint_refs.len() == 1);
         let constraint = &table_constraints[0].constraints[constraint_refs[0].constraint_vec_pos];
         assert!(constraint.operator == ast::Operator::Equals);
-        assert!(constraint.table_col_pos == 0); // c1
+        assert!(constraint.table_col_pos == Some(0)); // c1
     }
 
     #[test]
@@ -1507,6 +1594,7 @@ mod tests {
                     pos_in_table: 0,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c2".to_string(),
@@ -1514,6 +1602,7 @@ mod tests {
                     pos_in_table: 1,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c3".to_string(),
@@ -1521,11 +1610,13 @@ mod tests {
                     pos_in_table: 2,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
             ],
             root_page: 2,
             ephemeral: false,
             has_rowid: true,
+            primary_key_suffix: vec![],
             unique: false,
         });
         available_indexes.insert("t1".to_string(), VecDeque::from([index]));
@@ -1539,6 +1630,7 @@ mod tests {
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            indexed: None,
         });
 
         // Create where clause: c1 = 5 AND c2 > 10 AND c3 = 7
@@ -1589,9 +1681,13 @@ mod tests {
 
         let table_references = TableReferences::new(joined_tables, vec![]);
         let access_methods_arena = RefCell::new(Vec::new());
-        let table_constraints =
-            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
-                .unwrap();
+        let table_constraints = constraints_from_where_clause(
+            &where_clause,
+            &table_references,
+            &available_indexes,
+            &[],
+        )
+        .unwrap();
 
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
@@ -1610,10 +1706,113 @@ mod tests {
         assert!(constraint_refs.len() == 2);
         let constraint = &table_constraints[0].constraints[constraint_refs[0].constraint_vec_pos];
         assert!(constraint.operator == ast::Operator::Equals);
-        assert!(constraint.table_col_pos == 0); // c1
+        assert!(constraint.table_col_pos == Some(0)); // c1
         let constraint = &table_constraints[0].constraints[constraint_refs[1].constraint_vec_pos];
         assert!(constraint.operator == ast::Operator::Greater);
-        assert!(constraint.table_col_pos == 1); // c2
+        assert!(constraint.table_col_pos == Some(1)); // c2
+    }
+
+    #[test]
+    /// Test that the statistics gathered by ANALYZE make the planner prefer the index whose
+    /// values are the most selective, when the indexes look the same without statistics.
+    fn test_index_choice_uses_statistics() {
+        let table = _create_btree_table("t1", _create_column_list(&["x", "y"], Type::Integer));
+        let single_column_index = |name: &str, column: &str, pos_in_table: usize| {
+            Arc::new(Index {
+                name: name.to_string(),
+                table_name: "t1".to_string(),
+                where_clause: None,
+                columns: vec![IndexColumn {
+                    name: column.to_string(),
+                    order: SortOrder::Asc,
+                    pos_in_table,
+                    collation: None,
+                    default: None,
+                    expr: None,
+                }],
+                unique: false,
+                root_page: 2 + pos_in_table,
+                ephemeral: false,
+                has_rowid: true,
+                primary_key_suffix: vec![],
+            })
+        };
+        let mut available_indexes = HashMap::new();
+        available_indexes.insert(
+            "t1".to_string(),
+            VecDeque::from([
+                single_column_index("idx_x", "x", 0),
+                single_column_index("idx_y", "y", 1),
+            ]),
+        );
+
+        let mut table_id_counter = TableRefIdCounter::new();
+        let joined_tables = vec![_create_table_reference(
+            table,
+            None,
+            table_id_counter.next(),
+        )];
+        // SELECT * FROM t1 WHERE x = 1 AND y = 5
+        let where_clause = vec![
+            _create_binary_expr(
+                _create_column_expr(joined_tables[0].internal_id, 0, false),
+                ast::Operator::Equals,
+                _create_numeric_literal("1"),
+            ),
+            _create_binary_expr(
+                _create_column_expr(joined_tables[0].internal_id, 1, false),
+                ast::Operator::Equals,
+                _create_numeric_literal("5"),
+            ),
+        ];
+        let table_references = TableReferences::new(joined_tables, vec![]);
+
+        let best_index = |stats: Option<TableStats>| {
+            let access_methods_arena = RefCell::new(Vec::new());
+            let mut table_constraints = constraints_from_where_clause(
+                &where_clause,
+                &table_references,
+                &available_indexes,
+                &[],
+            )
+            .unwrap();
+            if let Some(stats) = stats {
+                table_constraints[0].apply_stats(
+                    Arc::new(stats),
+                    table_references.joined_tables()[0].columns(),
+                    &where_clause,
+                );
+            }
+            let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
+                table_references.joined_tables(),
+                None,
+                &table_constraints,
+                &access_methods_arena,
+            )
+            .unwrap()
+            .unwrap();
+            let access_method = &access_methods_arena.borrow()[best_plan.data[0].1];
+            let (_, index, _) = _as_btree(access_method);
+            index.unwrap().name.clone()
+        };
+
+        // Without statistics both indexes look the same and the first one is used.
+        assert_eq!(best_index(None), "idx_x");
+
+        // x has two distinct values and y is almost unique.
+        let index_stats = |avg_eq: u64| IndexStats {
+            row_count: 10000,
+            avg_eq: vec![avg_eq],
+            samples: vec![],
+        };
+        let stats = TableStats {
+            row_count: Some(10000),
+            indexes: HashMap::from([
+                ("idx_x".to_string(), index_stats(5000)),
+                ("idx_y".to_string(), index_stats(1)),
+            ]),
+        };
+        assert_eq!(best_index(Some(stats)), "idx_y");
     }
 
     fn _create_column(c: &TestColumn) -> Column {
@@ -1628,6 +1827,7 @@ mod tests {
             unique: false,
             collation: None,
             hidden: false,
+            generated: None,
         }
     }
     fn _create_column_of_type(name: &str, ty: Type) -> Column {
@@ -1659,6 +1859,8 @@ mod tests {
             root_page: 1, // Page number doesn't matter for tests
             name: name.to_string(),
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
             primary_key_columns: vec![],
             columns,
             has_rowid: true,
@@ -1683,6 +1885,7 @@ mod tests {
             join_info,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            indexed: None,
         }
     }
 
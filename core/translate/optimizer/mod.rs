@@ -13,13 +13,17 @@ use join::{compute_best_join_order, BestJoinOrderResult};
 use lift_common_subexpressions::lift_common_subexpressions_from_binary_or_terms;
 use order::{compute_order_target, plan_satisfies_order_target, EliminatesSortBy};
 use turso_ext::{ConstraintInfo, ConstraintUsage};
-use turso_parser::ast::{self, Expr, SortOrder};
+use turso_parser::ast::{self, Expr, SortOrder, TableInternalId};
 
 use crate::{
     schema::{Index, IndexColumn, Schema, Table},
     translate::{
-        optimizer::access_method::AccessMethodParams, optimizer::constraints::TableConstraints,
-        plan::Scan, plan::TerminationKey,
+        expr::{walk_expr, WalkControl},
+        optimizer::access_method::AccessMethodParams,
+        optimizer::constraints::TableConstraints,
+        plan::Scan,
+        plan::TerminationKey,
+        result_row::try_fold_expr_to_i64,
     },
     types::SeekOp,
     LimboError, Result,
@@ -28,8 +32,9 @@ use crate::{
 use super::{
     emitter::Resolver,
     plan::{
-        DeletePlan, GroupBy, IterationDirection, JoinOrderMember, JoinedTable, Operation, Plan,
-        Search, SeekDef, SeekKey, SelectPlan, TableReferences, UpdatePlan, WhereTerm,
+        DeletePlan, GroupBy, IterationDirection, JoinInfo, JoinOrderMember, JoinedTable,
+        NonFromClauseSubquery, Operation, Plan, Search, SeekDef, SeekKey, SelectPlan,
+        TableReferences, UpdatePlan, WhereTerm,
     },
 };
 
@@ -66,6 +71,7 @@ pub fn optimize_plan(plan: &mut Plan, schema: &Schema) -> Result<()> {
  * but having them separate makes them easier to understand
  */
 pub fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
+    decorrelate_exists_subquery(plan)?;
     optimize_subqueries(plan, schema)?;
     lift_common_subexpressions_from_binary_or_terms(&mut plan.where_clause)?;
     if let ConstantConditionEliminationResult::ImpossibleCondition =
@@ -82,6 +88,7 @@ pub fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()
         &mut plan.where_clause,
         &mut plan.order_by,
         &mut plan.group_by,
+        &plan.non_from_clause_subqueries,
     )?;
 
     if let Some(best_join_order) = best_join_order {
@@ -107,6 +114,7 @@ fn optimize_delete_plan(plan: &mut DeletePlan, schema: &Schema) -> Result<()> {
         &mut plan.where_clause,
         &mut plan.order_by,
         &mut None,
+        &[],
     )?;
 
     Ok(())
@@ -127,6 +135,7 @@ fn optimize_update_plan(plan: &mut UpdatePlan, schema: &Schema) -> Result<()> {
         &mut plan.where_clause,
         &mut plan.order_by,
         &mut None,
+        &[],
     )?;
 
     // It is not safe to use an index that is going to be updated as the iteration index for a table.
@@ -163,12 +172,157 @@ fn optimize_subqueries(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
     for table in plan.table_references.joined_tables_mut() {
         if let Table::FromClauseSubquery(from_clause_subquery) = &mut table.table {
             optimize_select_plan(&mut from_clause_subquery.plan, schema)?;
+            if let Some(recursive_cte) = &mut from_clause_subquery.recursive {
+                for plan in recursive_cte.initial.iter_mut() {
+                    optimize_select_plan(plan, schema)?;
+                }
+                for (plan, _) in recursive_cte.recursive.iter_mut() {
+                    optimize_select_plan(plan, schema)?;
+                }
+            }
+            if let Some(compound) = &mut from_clause_subquery.compound {
+                optimize_plan(compound, schema)?;
+            }
         }
     }
+    for subquery in plan.non_from_clause_subqueries.iter_mut() {
+        optimize_select_plan(&mut subquery.plan, schema)?;
+    }
+
+    Ok(())
+}
+
+/// Turn a correlated `EXISTS` subquery in the WHERE clause into a semi-join with the table of the subquery.
+/// For example, in
+/// ```sql
+/// SELECT * FROM t1 WHERE EXISTS (SELECT 1 FROM t2 WHERE t2.a = t1.a);
+/// ```
+/// t2 is joined to t1 as the last table in the join order, and the WHERE clause of the subquery
+/// becomes part of the WHERE clause of the query, so that e.g. an index on t2.a can be used.
+/// The loop over t2 is left as soon as it finds a match, so every row of t1 is emitted at most once.
+///
+/// Only the first subquery that qualifies is decorrelated: a plain SELECT from a single table that
+/// only references the tables of this query. Any other subquery keeps being evaluated per row.
+fn decorrelate_exists_subquery(plan: &mut SelectPlan) -> Result<()> {
+    if plan.table_references.joined_tables().iter().any(|t| {
+        t.join_info
+            .as_ref()
+            .is_some_and(|j| j.outer || j.right || j.semi)
+    }) {
+        return Ok(());
+    }
+
+    let mut candidate = None;
+    for (term_idx, term) in plan.where_clause.iter().enumerate() {
+        let Expr::SubqueryResult {
+            subquery_id,
+            query_type: ast::SubqueryType::Exists { .. },
+            ..
+        } = &term.expr
+        else {
+            continue;
+        };
+        if term.from_outer_join.is_some() {
+            continue;
+        }
+        let Some(subquery_idx) = plan
+            .non_from_clause_subqueries
+            .iter()
+            .position(|subquery| subquery.internal_id == *subquery_id)
+        else {
+            continue;
+        };
+        if !can_decorrelate(&plan.non_from_clause_subqueries[subquery_idx])
+            || count_subquery_references(plan, *subquery_id)? != 1
+        {
+            continue;
+        }
+        candidate = Some((term_idx, subquery_idx));
+        break;
+    }
+    let Some((term_idx, subquery_idx)) = candidate else {
+        return Ok(());
+    };
+
+    plan.where_clause.remove(term_idx);
+    let mut subquery_plan = plan.non_from_clause_subqueries.remove(subquery_idx).plan;
+    let mut table = subquery_plan
+        .table_references
+        .joined_tables_mut()
+        .pop()
+        .expect("decorrelated subquery must have a table");
+    table.join_info = Some(JoinInfo {
+        outer: false,
+        right: false,
+        using: vec![],
+        semi: true,
+    });
+    plan.table_references.add_joined_table(table);
+    plan.where_clause.append(&mut subquery_plan.where_clause);
 
     Ok(())
 }
 
+/// Whether an `EXISTS` subquery can be turned into a semi-join, see [decorrelate_exists_subquery].
+fn can_decorrelate(subquery: &NonFromClauseSubquery) -> bool {
+    let plan = &subquery.plan;
+    let [table] = plan.table_references.joined_tables() else {
+        return false;
+    };
+    let only_references_parent = plan
+        .table_references
+        .outer_query_refs()
+        .iter()
+        .filter(|outer_ref| outer_ref.is_used())
+        .all(|outer_ref| subquery.correlated_tables.contains(&outer_ref.internal_id));
+    // The limit of an EXISTS subquery has been folded to 1 unless it's not a constant.
+    let limit_is_constant = plan
+        .limit
+        .as_ref()
+        .is_none_or(|limit| try_fold_expr_to_i64(limit).is_some());
+
+    !subquery.correlated_tables.is_empty()
+        && only_references_parent
+        && matches!(table.table, Table::BTree(_))
+        && plan.group_by.is_none()
+        && plan.aggregates.is_empty()
+        && plan.window.is_none()
+        && plan.offset.is_none()
+        && limit_is_constant
+        && !plan.contains_constant_false_condition
+        && plan.values.is_empty()
+        && plan.non_from_clause_subqueries.is_empty()
+}
+
+/// The number of expressions of the plan that read the result of the given subquery.
+fn count_subquery_references(plan: &SelectPlan, subquery_id: TableInternalId) -> Result<usize> {
+    let mut count = 0;
+    let having = plan.group_by.iter().flat_map(|g| g.having.iter().flatten());
+    let group_by = plan.group_by.iter().flat_map(|g| g.exprs.iter());
+    let aggregates = plan
+        .aggregates
+        .iter()
+        .flat_map(|agg| agg.inputs().chain(std::iter::once(&agg.original_expr)));
+    for expr in plan
+        .where_clause
+        .iter()
+        .map(|term| &term.expr)
+        .chain(plan.result_columns.iter().map(|rc| &rc.expr))
+        .chain(plan.order_by.iter().map(|(expr, _)| expr.as_ref()))
+        .chain(having)
+        .chain(group_by)
+        .chain(aggregates)
+    {
+        walk_expr(expr, &mut |expr: &Expr| -> Result<WalkControl> {
+            if matches!(expr, Expr::SubqueryResult { subquery_id: id, .. } if *id == subquery_id) {
+                count += 1;
+            }
+            Ok(WalkControl::Continue)
+        })?;
+    }
+    Ok(count)
+}
+
 /// Optimize the join order and index selection for a query.
 ///
 /// This function does the following:
@@ -187,11 +341,64 @@ fn optimize_table_access(
     where_clause: &mut [WhereTerm],
     order_by: &mut Vec<(Box<ast::Expr>, SortOrder)>,
     group_by: &mut Option<GroupBy>,
+    subqueries: &[NonFromClauseSubquery],
 ) -> Result<Option<Vec<JoinOrderMember>>> {
     let access_methods_arena = RefCell::new(Vec::new());
     let maybe_order_target = compute_order_target(order_by, group_by.as_mut());
-    let constraints_per_table =
-        constraints_from_where_clause(where_clause, table_references, available_indexes)?;
+    // The unmatched rows of a RIGHT JOIN are emitted after all the other rows, so the order of
+    // the loops says nothing about the order of the rows.
+    let has_right_join = table_references.joined_tables().iter().any(|t| {
+        t.join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.right)
+    });
+    let maybe_order_target = maybe_order_target.filter(|_| !has_right_join);
+    // Indexes are looked up by table name, so the indexes of `schema` must not be used for a
+    // table of another database (e.g. a TEMP table) that happens to share the name.
+    let foreign_tables: Vec<String> = table_references
+        .joined_tables()
+        .iter()
+        .filter_map(|t| t.btree())
+        .filter(|btree| {
+            !schema
+                .get_btree_table(&btree.name)
+                .is_some_and(|own| Arc::ptr_eq(&own, btree))
+        })
+        .map(|btree| btree.name.clone())
+        .collect();
+    let own_indexes: HashMap<String, VecDeque<Arc<Index>>>;
+    let available_indexes = if foreign_tables.is_empty() {
+        available_indexes
+    } else {
+        own_indexes = available_indexes
+            .iter()
+            .filter(|(name, _)| !foreign_tables.contains(name))
+            .map(|(name, indexes)| (name.clone(), indexes.clone()))
+            .collect();
+        &own_indexes
+    };
+    let mut constraints_per_table = constraints_from_where_clause(
+        where_clause,
+        table_references,
+        available_indexes,
+        subqueries,
+    )?;
+    // Refine the estimates of the tables of `schema` that were analyzed.
+    for (table, constraints) in table_references
+        .joined_tables()
+        .iter()
+        .zip(constraints_per_table.iter_mut())
+    {
+        let Some(btree) = table
+            .btree()
+            .filter(|btree| !foreign_tables.contains(&btree.name))
+        else {
+            continue;
+        };
+        if let Some(stats) = schema.table_stats(&btree.name) {
+            constraints.apply_stats(stats.clone(), table.columns(), where_clause);
+        }
+    }
 
     // Currently the expressions we evaluate as constraints are binary expressions that will never be true for a NULL operand.
     // If there are any constraints on the right hand side table of an outer join that are not part of the outer join condition,
@@ -310,7 +517,24 @@ fn optimize_table_access(
                     let try_to_build_ephemeral_index = if schema.indexes_enabled() {
                         let is_leftmost_table = i == 0;
                         let uses_index = index.is_some();
-                        !is_leftmost_table && !uses_index
+                        // The unmatched rows of a RIGHT JOIN are read from the table itself, and
+                        // the ephemeral index would only be built once the main loop reaches it.
+                        let is_right_join = joined_tables[table_idx]
+                            .join_info
+                            .as_ref()
+                            .is_some_and(|join_info| join_info.right);
+                        // The ephemeral index refers to the rows by rowid.
+                        let has_rowid = joined_tables[table_idx]
+                            .table
+                            .btree()
+                            .is_some_and(|btree| btree.has_rowid);
+                        // INDEXED BY and NOT INDEXED rule out automatic indexes too.
+                        let has_index_hint = joined_tables[table_idx].indexed.is_some();
+                        !is_leftmost_table
+                            && !uses_index
+                            && !is_right_join
+                            && has_rowid
+                            && !has_index_hint
                     } else {
                         false
                     };
@@ -334,11 +558,14 @@ fn optimize_table_access(
                         });
                         continue;
                     };
+                    // The ephemeral index is on columns, so constraints on expressions cannot use it.
                     let temp_constraint_refs = (0..table_constraints.constraints.len())
-                        .map(|i| ConstraintRef {
-                            constraint_vec_pos: i,
-                            index_col_pos: table_constraints.constraints[i].table_col_pos,
-                            sort_order: SortOrder::Asc,
+                        .filter_map(|i| {
+                            Some(ConstraintRef {
+                                constraint_vec_pos: i,
+                                index_col_pos: table_constraints.constraints[i].table_col_pos?,
+                                sort_order: SortOrder::Asc,
+                            })
                         })
                         .collect::<Vec<_>>();
                     let usable_constraint_refs = usable_constraints_for_join_order(
@@ -632,7 +859,7 @@ impl Optimizable for ast::Expr {
                     return true;
                 }
 
-                let table_ref = tables.find_joined_table_by_internal_id(*table).unwrap();
+                let table_ref = tables.find_table_by_internal_id(*table).unwrap();
                 let columns = table_ref.columns();
                 let column = &columns[*column];
                 column.primary_key || column.notnull
@@ -663,6 +890,7 @@ impl Optimizable for ast::Expr {
             }
             Expr::Raise(..) => false,
             Expr::Subquery(..) => false,
+            Expr::SubqueryResult { .. } => false,
             Expr::Unary(_, expr) => expr.is_nonnull(tables),
             Expr::Variable(..) => false,
             Expr::Register(..) => false, // Register values can be null
@@ -739,8 +967,11 @@ impl Optimizable for ast::Expr {
             Expr::Qualified(_, _) => {
                 panic!("Qualified should have been rewritten as Column")
             }
-            Expr::Raise(_, expr) => expr.as_ref().is_none_or(|expr| expr.is_constant(resolver)),
+            // RAISE() halts the program, so it must only run where it appears.
+            Expr::Raise(..) => false,
             Expr::Subquery(_) => false,
+            // The result of a subquery is only known once it has been evaluated.
+            Expr::SubqueryResult { .. } => false,
             Expr::Unary(_, expr) => expr.is_constant(resolver),
             Expr::Variable(_) => false,
             Expr::Register(_) => false, // Register values are not constants
@@ -872,6 +1103,7 @@ fn ephemeral_index_build(
             pos_in_table: i,
             collation: c.collation,
             default: c.default.clone(),
+            expr: None,
         })
         // only include columns that are used in the query
         .filter(|c| table_reference.column_is_used(c.pos_in_table))
@@ -881,11 +1113,11 @@ fn ephemeral_index_build(
         let a_constraint = constraint_refs
             .iter()
             .enumerate()
-            .find(|(_, c)| constraints[c.constraint_vec_pos].table_col_pos == a.pos_in_table);
+            .find(|(_, c)| constraints[c.constraint_vec_pos].table_col_pos == Some(a.pos_in_table));
         let b_constraint = constraint_refs
             .iter()
             .enumerate()
-            .find(|(_, c)| constraints[c.constraint_vec_pos].table_col_pos == b.pos_in_table);
+            .find(|(_, c)| constraints[c.constraint_vec_pos].table_col_pos == Some(b.pos_in_table));
         match (a_constraint, b_constraint) {
             (Some(_), None) => Ordering::Less,
             (None, Some(_)) => Ordering::Greater,
@@ -909,6 +1141,7 @@ fn ephemeral_index_build(
             .table
             .btree()
             .is_some_and(|btree| btree.has_rowid),
+        primary_key_suffix: vec![],
     };
 
     ephemeral_index
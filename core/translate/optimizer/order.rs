@@ -3,6 +3,8 @@ use std::cell::RefCell;
 use turso_parser::ast::{self, SortOrder, TableInternalId};
 
 use crate::{
+    schema::Index,
+    translate::expr::{walk_expr, WalkControl},
     translate::optimizer::access_method::AccessMethodParams,
     translate::plan::{GroupBy, IterationDirection, JoinedTable},
     util::exprs_are_equivalent,
@@ -12,12 +14,51 @@ use super::{access_method::AccessMethod, join::JoinN};
 
 #[derive(Debug, PartialEq, Clone)]
 /// A convenience struct for representing a (table_no, column_no, [SortOrder]) tuple.
+/// An expression of the columns of a single table, e.g. `lower(t.x)`, has no column_no;
+/// only an index on that expression can provide its order.
 pub struct ColumnOrder {
     pub table_id: TableInternalId,
-    pub column_no: usize,
+    pub column_no: Option<usize>,
+    pub expr: Option<ast::Expr>,
     pub order: SortOrder,
 }
 
+impl ColumnOrder {
+    /// Returns true if the column at `index_col_pos` of `index`, an index on the table of
+    /// `table_ref`, is the column or the expression of this [ColumnOrder].
+    pub fn is_index_column(
+        &self,
+        index: &Index,
+        index_col_pos: usize,
+        table_ref: &JoinedTable,
+    ) -> bool {
+        let index_col = &index.columns[index_col_pos];
+        match &self.expr {
+            Some(expr) => index_col
+                .bind_expr(&table_ref.table, table_ref.internal_id)
+                .is_some_and(|index_expr| exprs_are_equivalent(&index_expr, expr)),
+            None => self.column_no == Some(index_col.pos_in_table),
+        }
+    }
+}
+
+/// Returns the table that an expression refers to, if it refers to the columns of exactly one
+/// table.
+fn single_table_of_expr(expr: &ast::Expr) -> Option<TableInternalId> {
+    let mut table_id = None;
+    let mut single = true;
+    let _ = walk_expr(expr, &mut |e: &ast::Expr| -> crate::Result<WalkControl> {
+        if let ast::Expr::Column { table, .. } | ast::Expr::RowId { table, .. } = e {
+            if table_id.is_some_and(|id| id != *table) {
+                single = false;
+            }
+            table_id = Some(*table);
+        }
+        Ok(WalkControl::Continue)
+    });
+    table_id.filter(|_| single)
+}
+
 #[derive(Debug, PartialEq, Clone)]
 /// If an [OrderTarget] is satisfied, then [EliminatesSort] describes which part of the query no longer requires sorting.
 pub enum EliminatesSortBy {
@@ -40,24 +81,22 @@ impl OrderTarget {
         if list.clone().count() == 0 {
             return None;
         }
-        if list
-            .clone()
-            .any(|(expr, _)| !matches!(expr, ast::Expr::Column { .. }))
-        {
-            return None;
-        }
         Some(OrderTarget(
-            list.map(|(expr, order)| {
-                let ast::Expr::Column { table, column, .. } = expr else {
-                    unreachable!();
-                };
-                ColumnOrder {
+            list.map(|(expr, order)| match expr {
+                ast::Expr::Column { table, column, .. } => Some(ColumnOrder {
                     table_id: *table,
-                    column_no: *column,
+                    column_no: Some(*column),
+                    expr: None,
                     order,
-                }
+                }),
+                _ => Some(ColumnOrder {
+                    table_id: single_table_of_expr(expr)?,
+                    column_no: None,
+                    expr: Some(expr.clone()),
+                    order,
+                }),
             })
-            .collect(),
+            .collect::<Option<Vec<_>>>()?,
             eliminates_sort,
         ))
     }
@@ -188,7 +227,7 @@ pub fn plan_satisfies_order_target(
                         let Some(rowid_alias_col) = rowid_alias_col else {
                             return false;
                         };
-                        let correct_column = target_col.column_no == rowid_alias_col;
+                        let correct_column = target_col.column_no == Some(rowid_alias_col);
                         if !correct_column {
                             return false;
                         }
@@ -210,9 +249,10 @@ pub fn plan_satisfies_order_target(
                     }
                     Some(index) => {
                         // All of the index columns must match the next required columns in the order target.
-                        for index_col in index.columns.iter() {
+                        for (index_col_pos, index_col) in index.columns.iter().enumerate() {
                             let target_col = &order_target.0[target_col_idx];
-                            let correct_column = target_col.column_no == index_col.pos_in_table;
+                            let correct_column =
+                                target_col.is_index_column(index, index_col_pos, table_ref);
                             if !correct_column {
                                 return false;
                             }
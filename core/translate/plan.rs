@@ -2,8 +2,8 @@ use std::{cmp::Ordering, sync::Arc};
 use turso_parser::ast::{self, FrameBound, FrameClause, FrameExclude, FrameMode, SortOrder};
 
 use crate::{
-    function::AggFunc,
-    schema::{BTreeTable, Column, FromClauseSubquery, Index, Schema, Table},
+    function::{AggFunc, WindowFunc},
+    schema::{Affinity, BTreeTable, Column, FromClauseSubquery, Index, Schema, Table},
     vdbe::{
         builder::{CursorKey, CursorType, ProgramBuilder},
         insn::{IdxInsertFlags, Insn},
@@ -15,7 +15,10 @@ use crate::{schema::Type, types::SeekOp};
 
 use turso_parser::ast::TableInternalId;
 
-use super::{emitter::OperationMode, planner::determine_where_to_eval_term};
+use super::{
+    collate::CollationSeq, emitter::OperationMode, expr::get_expr_affinity,
+    planner::determine_where_to_eval_term,
+};
 
 #[derive(Debug, Clone)]
 pub struct ResultSetColumn {
@@ -88,28 +91,41 @@ pub struct WhereTerm {
 }
 
 impl WhereTerm {
-    pub fn should_eval_before_loop(&self, join_order: &[JoinOrderMember]) -> bool {
+    pub fn should_eval_before_loop(
+        &self,
+        join_order: &[JoinOrderMember],
+        subqueries: &[NonFromClauseSubquery],
+    ) -> bool {
         if self.consumed {
             return false;
         }
-        let Ok(eval_at) = self.eval_at(join_order) else {
+        let Ok(eval_at) = self.eval_at(join_order, subqueries) else {
             return false;
         };
         eval_at == EvalAt::BeforeLoop
     }
 
-    pub fn should_eval_at_loop(&self, loop_idx: usize, join_order: &[JoinOrderMember]) -> bool {
+    pub fn should_eval_at_loop(
+        &self,
+        loop_idx: usize,
+        join_order: &[JoinOrderMember],
+        subqueries: &[NonFromClauseSubquery],
+    ) -> bool {
         if self.consumed {
             return false;
         }
-        let Ok(eval_at) = self.eval_at(join_order) else {
+        let Ok(eval_at) = self.eval_at(join_order, subqueries) else {
             return false;
         };
         eval_at == EvalAt::Loop(loop_idx)
     }
 
-    fn eval_at(&self, join_order: &[JoinOrderMember]) -> Result<EvalAt> {
-        determine_where_to_eval_term(self, join_order)
+    fn eval_at(
+        &self,
+        join_order: &[JoinOrderMember],
+        subqueries: &[NonFromClauseSubquery],
+    ) -> Result<EvalAt> {
+        determine_where_to_eval_term(self, join_order, subqueries)
     }
 }
 
@@ -167,12 +183,37 @@ pub enum Plan {
         right_most: SelectPlan,
         limit: Option<Box<Expr>>,
         offset: Option<Box<Expr>>,
-        order_by: Option<Vec<(ast::Expr, SortOrder)>>,
+        /// The ORDER BY clause as result column indexes, with the collation each is sorted by.
+        order_by: Option<Vec<(usize, SortOrder, Option<CollationSeq>)>>,
     },
     Delete(DeletePlan),
     Update(UpdatePlan),
 }
 
+impl Plan {
+    /// The SELECTs of a compound SELECT from left to right, or none for any other plan.
+    pub fn compound_selects(&self) -> impl Iterator<Item = &SelectPlan> {
+        let (left, right_most): (&[_], _) = match self {
+            Plan::CompoundSelect {
+                left, right_most, ..
+            } => (left, Some(right_most)),
+            _ => (&[], None),
+        };
+        left.iter().map(|(plan, _)| plan).chain(right_most)
+    }
+
+    /// The SELECTs of a compound SELECT from left to right, or none for any other plan.
+    pub fn compound_selects_mut(&mut self) -> impl Iterator<Item = &mut SelectPlan> {
+        let (left, right_most): (&mut [_], _) = match self {
+            Plan::CompoundSelect {
+                left, right_most, ..
+            } => (left, Some(right_most)),
+            _ => (&mut [], None),
+        };
+        left.iter_mut().map(|(plan, _)| plan).chain(right_most)
+    }
+}
+
 /// The destination of the results of a query.
 /// Typically, the results of a query are returned to the caller.
 /// However, there are some cases where the results are not returned to the caller,
@@ -207,6 +248,16 @@ pub enum QueryDestination {
         /// The table that will be used to store the results.
         table: Arc<BTreeTable>,
     },
+    /// The query is an `EXISTS` subquery, and whether it returns a row is stored in a register.
+    ExistsSubqueryResult {
+        /// The register that is set to 1 when the query returns a row.
+        result_reg: usize,
+    },
+    /// The query is a scalar subquery, and its first row is stored in a register.
+    ScalarSubqueryResult {
+        /// The register that holds the single column of the first row.
+        result_reg: usize,
+    },
 }
 
 impl QueryDestination {
@@ -327,6 +378,49 @@ pub struct SelectPlan {
     /// The window definition and all window functions associated with it. There is at most one
     /// window per SELECT. If the original query contains more, they are pushed down into subqueries.
     pub window: Option<Window>,
+    /// The subqueries outside the FROM clause, e.g. in `WHERE x IN (SELECT ...)`.
+    pub non_from_clause_subqueries: Vec<NonFromClauseSubquery>,
+}
+
+/// A subquery outside the FROM clause, e.g. `WHERE x IN (SELECT ...)` or `SELECT (SELECT max(y) FROM u)`.
+/// The expression it appears in reads its result through an [ast::Expr::SubqueryResult].
+#[derive(Debug, Clone)]
+pub struct NonFromClauseSubquery {
+    pub internal_id: TableInternalId,
+    pub query_type: ast::SubqueryType,
+    pub plan: Box<SelectPlan>,
+    /// The tables of the parent query that the subquery references.
+    /// A subquery that references none of them is evaluated before the main loop of the parent query,
+    /// otherwise it is evaluated for every row of the loop of the last of them in the join order.
+    pub correlated_tables: Vec<TableInternalId>,
+}
+
+impl NonFromClauseSubquery {
+    /// Returns the loop at which the subquery has to be evaluated, see [NonFromClauseSubquery::correlated_tables].
+    pub fn eval_at(&self, join_order: &[JoinOrderMember]) -> EvalAt {
+        self.correlated_tables
+            .iter()
+            .map(|table_id| {
+                EvalAt::Loop(
+                    join_order
+                        .iter()
+                        .position(|t| t.table_id == *table_id)
+                        .unwrap_or(usize::MAX),
+                )
+            })
+            .max()
+            .unwrap_or(EvalAt::BeforeLoop)
+    }
+
+    /// Whether the subquery references any table of an outer query, at any depth.
+    /// Subqueries that do not are only evaluated once per statement.
+    pub fn is_correlated(&self) -> bool {
+        self.plan
+            .table_references
+            .outer_query_refs()
+            .iter()
+            .any(|outer_ref| outer_ref.is_used())
+    }
 }
 
 impl SelectPlan {
@@ -335,7 +429,7 @@ impl SelectPlan {
     }
 
     pub fn agg_args_count(&self) -> usize {
-        self.aggregates.iter().map(|agg| agg.args.len()).sum()
+        self.aggregates.iter().map(|agg| agg.num_inputs()).sum()
     }
 
     /// Reference: https://github.com/sqlite/sqlite/blob/5db695197b74580c777b37ab1b787531f15f7f9f/src/select.c#L8613
@@ -344,10 +438,7 @@ impl SelectPlan {
     pub fn is_simple_count(&self) -> bool {
         if !self.where_clause.is_empty()
             || self.aggregates.len() != 1
-            || matches!(
-                self.query_destination,
-                QueryDestination::CoroutineYield { .. }
-            )
+            || !matches!(self.query_destination, QueryDestination::ResultRows)
             || self.table_references.joined_tables().len() != 1
             || !self.table_references.outer_query_refs().is_empty()
             || self.result_columns.len() != 1
@@ -391,6 +482,37 @@ impl SelectPlan {
     }
 }
 
+/// The SELECTs of a recursive common table expression.
+///
+/// The rows of the initial SELECTs are pushed onto a queue. Then, until the queue is empty,
+/// a row is popped off the queue, becomes a row of the CTE, and the recursive SELECTs are
+/// run with the CTE standing for that single row, pushing their rows onto the queue.
+#[derive(Debug, Clone)]
+pub struct RecursiveCte {
+    /// The SELECTs that do not refer to the CTE.
+    pub initial: Vec<SelectPlan>,
+    /// The SELECTs that refer to the CTE, each with the id of its reference to the CTE.
+    /// That reference is a FROM clause subquery that yields the row popped off the queue.
+    pub recursive: Vec<(SelectPlan, TableInternalId)>,
+    /// Whether the SELECTs are combined with UNION, in which case a row is never queued twice.
+    pub distinct: bool,
+    /// The ORDER BY clause as result column indexes; rows are popped off the queue in this order.
+    pub order_by: Vec<(usize, SortOrder)>,
+    /// limit clause
+    pub limit: Option<Box<Expr>>,
+    /// offset clause
+    pub offset: Option<Box<Expr>>,
+}
+
+impl RecursiveCte {
+    /// The initial and the recursive SELECTs.
+    pub fn plans(&self) -> impl Iterator<Item = &SelectPlan> {
+        self.initial
+            .iter()
+            .chain(self.recursive.iter().map(|(plan, _)| plan))
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct DeletePlan {
@@ -409,6 +531,9 @@ pub struct DeletePlan {
     pub contains_constant_false_condition: bool,
     /// Indexes that must be updated by the delete operation.
     pub indexes: Vec<Arc<Index>>,
+    /// If the table has DELETE triggers, the rowids of the rows to delete are first gathered
+    /// into an ephemeral table, which is then used as the source of the actual DELETE loop.
+    pub ephemeral_plan: Option<SelectPlan>,
 }
 
 #[derive(Debug, Clone)]
@@ -427,6 +552,11 @@ pub struct UpdatePlan {
     pub indexes_to_update: Vec<Arc<Index>>,
     // If the table's rowid alias is used, gather all the target rowids into an ephemeral table, and then use that table as the single JoinedTable for the actual UPDATE loop.
     pub ephemeral_plan: Option<SelectPlan>,
+    // For UPDATE ... FROM, the ephemeral table also holds the new values of the SET clause, which
+    // are read into the registers starting at this one. The SET expressions refer to them.
+    pub from_values_start_reg: Option<usize>,
+    // The conflict resolution of UPDATE OR <conflict>. ABORT is used if it is not given.
+    pub or_conflict: Option<ast::ResolveType>,
     // For ALTER TABLE turso-db emits appropriate DDL statement in the "updates" cell of CDC table
     // This field is present only for update plan created for ALTER TABLE when CDC mode has "updates" values
     pub cdc_update_alter_statement: Option<String>,
@@ -439,7 +569,7 @@ pub enum IterationDirection {
 }
 
 pub fn select_star(tables: &[JoinedTable], out_columns: &mut Vec<ResultSetColumn>) {
-    for table in tables.iter() {
+    for (table_idx, table) in tables.iter().enumerate() {
         out_columns.extend(
             table
                 .columns()
@@ -459,25 +589,108 @@ pub fn select_star(tables: &[JoinedTable], out_columns: &mut Vec<ResultSetColumn
                         true
                     }
                 })
-                .map(|(i, col)| ResultSetColumn {
-                    alias: None,
-                    expr: ast::Expr::Column {
-                        database: None,
-                        table: table.internal_id,
-                        column: i,
-                        is_rowid_alias: col.is_rowid_alias,
+                .map(
+                    |(i, col)| match using_column_coalesced(tables, table_idx, i) {
+                        Some(expr) => ResultSetColumn {
+                            alias: col.name.clone(),
+                            expr,
+                            contains_aggregates: false,
+                        },
+                        None => ResultSetColumn {
+                            alias: None,
+                            expr: ast::Expr::Column {
+                                database: None,
+                                table: table.internal_id,
+                                column: i,
+                                is_rowid_alias: col.is_rowid_alias,
+                            },
+                            contains_aggregates: false,
+                        },
                     },
-                    contains_aggregates: false,
-                }),
+                ),
         );
     }
 }
 
+/// A column in the USING clause of a RIGHT or FULL OUTER JOIN is NULL in the left table for the
+/// rows that only exist in the right table, so the column refers to the first of them that is not NULL.
+/// Returns `coalesce(left.column, right.column, ...)` if the given column of a table is merged
+/// that way with the same column of the tables joined to it later.
+pub fn using_column_coalesced(
+    tables: &[JoinedTable],
+    table_idx: usize,
+    column: usize,
+) -> Option<ast::Expr> {
+    let has_column = |table: &JoinedTable, name: &str| {
+        table.columns().iter().position(|col| {
+            col.name
+                .as_ref()
+                .is_some_and(|col_name| col_name.eq_ignore_ascii_case(name))
+        })
+    };
+    let table = &tables[table_idx];
+    let name = table.columns()[column].name.as_deref()?;
+    // The USING clauses of the later joins refer to the first table that has the column.
+    if tables[..table_idx]
+        .iter()
+        .any(|table| has_column(table, name).is_some())
+    {
+        return None;
+    }
+
+    let column_expr = |table: &JoinedTable, column: usize| {
+        Box::new(ast::Expr::Column {
+            database: None,
+            table: table.internal_id,
+            column,
+            is_rowid_alias: table.columns()[column].is_rowid_alias,
+        })
+    };
+    let mut args = vec![column_expr(table, column)];
+    let mut right = false;
+    for later_table in tables[table_idx + 1..].iter() {
+        let Some(join_info) = &later_table.join_info else {
+            continue;
+        };
+        if !join_info
+            .using
+            .iter()
+            .any(|using_col| using_col.as_str().eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+        if let Some(later_column) = has_column(later_table, name) {
+            right |= join_info.right;
+            args.push(column_expr(later_table, later_column));
+        }
+    }
+    if !right {
+        return None;
+    }
+    Some(ast::Expr::FunctionCall {
+        name: ast::Name::new("coalesce"),
+        distinctness: None,
+        args,
+        order_by: vec![],
+        filter_over: ast::FunctionTail {
+            filter_clause: None,
+            over_clause: None,
+        },
+    })
+}
+
 /// Join information for a table reference.
 #[derive(Debug, Clone)]
 pub struct JoinInfo {
-    /// Whether this is an OUTER JOIN.
+    /// Whether this is an OUTER JOIN, i.e. the rows of the tables to its left that have no match
+    /// in this table are emitted with NULLs for it (LEFT or FULL OUTER JOIN).
     pub outer: bool,
+    /// Whether the rows of this table that have no match in the tables to its left are emitted
+    /// with NULLs for them (RIGHT or FULL OUTER JOIN).
+    pub right: bool,
+    /// Whether this is a semi-join, i.e. a table moved here from a decorrelated `EXISTS` subquery.
+    /// A row of the tables to its left is emitted at most once, on the first matching row of this table.
+    pub semi: bool,
     /// The USING clause for the join, if any. NATURAL JOIN is transformed into USING (col1, col2, ...).
     pub using: Vec<ast::Name>,
 }
@@ -509,6 +722,9 @@ pub struct JoinedTable {
     pub col_used_mask: ColumnUsedMask,
     /// The index of the database. "main" is always zero.
     pub database_id: usize,
+    /// The INDEXED BY or NOT INDEXED clause of the table, if any, which restricts
+    /// the access methods the optimizer may choose for it.
+    pub indexed: Option<ast::Indexed>,
 }
 
 #[derive(Debug, Clone)]
@@ -593,6 +809,11 @@ impl TableReferences {
         self.joined_tables.push(joined_table);
     }
 
+    /// Add a new [OuterQueryReference] to the query plan.
+    pub fn add_outer_query_ref(&mut self, outer_query_ref: OuterQueryReference) {
+        self.outer_query_refs.push(outer_query_ref);
+    }
+
     /// Returns an immutable reference to the [JoinedTable]s in the query plan.
     pub fn joined_tables(&self) -> &[JoinedTable] {
         &self.joined_tables
@@ -809,6 +1030,17 @@ impl Operation {
     }
 }
 
+/// The type of a subquery column, which takes the affinity of the expression it is computed from.
+fn subquery_column_type(expr: &Expr, table_references: &TableReferences) -> (Type, &'static str) {
+    match get_expr_affinity(expr, Some(table_references)) {
+        Affinity::Integer => (Type::Integer, "INTEGER"),
+        Affinity::Text => (Type::Text, "TEXT"),
+        Affinity::Real => (Type::Real, "REAL"),
+        Affinity::Numeric => (Type::Numeric, "NUMERIC"),
+        Affinity::Blob => (Type::Blob, "BLOB"),
+    }
+}
+
 impl JoinedTable {
     /// Returns the btree table for this table reference, if it is a BTreeTable.
     pub fn btree(&self) -> Option<Arc<BTreeTable>> {
@@ -834,17 +1066,21 @@ impl JoinedTable {
         let columns = plan
             .result_columns
             .iter()
-            .map(|rc| Column {
-                name: rc.name(&plan.table_references).map(String::from),
-                ty: Type::Blob, // FIXME: infer proper type
-                ty_str: "BLOB".to_string(),
-                is_rowid_alias: false,
-                primary_key: false,
-                notnull: false,
-                default: None,
-                unique: false,
-                collation: None, // FIXME: infer collation from subquery
-                hidden: false,
+            .map(|rc| {
+                let (ty, ty_str) = subquery_column_type(&rc.expr, &plan.table_references);
+                Column {
+                    name: rc.name(&plan.table_references).map(String::from),
+                    ty,
+                    ty_str: ty_str.to_string(),
+                    is_rowid_alias: false,
+                    primary_key: false,
+                    notnull: false,
+                    default: None,
+                    unique: false,
+                    collation: None, // FIXME: infer collation from subquery
+                    hidden: false,
+                    generated: None,
+                }
             })
             .collect();
 
@@ -853,6 +1089,8 @@ impl JoinedTable {
             plan: Box::new(plan),
             columns,
             result_columns_start_reg: None,
+            recursive: None,
+            compound: None,
         });
         Self {
             op: Operation::default_scan_for(&table),
@@ -862,7 +1100,50 @@ impl JoinedTable {
             join_info,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            indexed: None,
+        }
+    }
+
+    /// Creates a new TableReference for a recursive CTE, whose result columns are described by `plan`.
+    pub fn new_recursive_cte(
+        identifier: String,
+        plan: SelectPlan,
+        recursive: RecursiveCte,
+        internal_id: TableInternalId,
+    ) -> Self {
+        let mut joined_table = Self::new_subquery(identifier, plan, None, internal_id);
+        if let Table::FromClauseSubquery(from_clause_subquery) = &mut joined_table.table {
+            from_clause_subquery.recursive = Some(Box::new(recursive));
         }
+        joined_table
+    }
+
+    /// Creates a new TableReference for a compound SELECT, whose result columns are described by
+    /// `plan`. Like in SQLite, the columns take the affinity of the leftmost SELECT.
+    pub fn new_compound_subquery(
+        identifier: String,
+        plan: SelectPlan,
+        compound: Plan,
+        internal_id: TableInternalId,
+    ) -> Self {
+        let mut joined_table = Self::new_subquery(identifier, plan, None, internal_id);
+        if let Table::FromClauseSubquery(from_clause_subquery) = &mut joined_table.table {
+            let leftmost = compound
+                .compound_selects()
+                .next()
+                .expect("a compound SELECT has at least two SELECTs");
+            for (column, rc) in from_clause_subquery
+                .columns
+                .iter_mut()
+                .zip(leftmost.result_columns.iter())
+            {
+                let (ty, ty_str) = subquery_column_type(&rc.expr, &leftmost.table_references);
+                column.ty = ty;
+                column.ty_str = ty_str.to_string();
+            }
+            from_clause_subquery.compound = Some(Box::new(compound));
+        }
+        joined_table
     }
 
     pub fn columns(&self) -> &[Column] {
@@ -906,12 +1187,15 @@ impl JoinedTable {
                             .alloc_cursor_id_keyed(CursorKey::table(self.internal_id), cursor_type),
                     )
                 };
-                let index_cursor_id = index.map(|index| {
-                    program.alloc_cursor_id_keyed(
-                        CursorKey::index(self.internal_id, index.clone()),
-                        CursorType::BTreeIndex(index.clone()),
-                    )
-                });
+                // The PRIMARY KEY of a WITHOUT ROWID table is read through the table cursor.
+                let index_cursor_id = index
+                    .filter(|index| !index.is_without_rowid_primary_key_of(btree))
+                    .map(|index| {
+                        program.alloc_cursor_id_keyed(
+                            CursorKey::index(self.internal_id, index.clone()),
+                            CursorType::BTreeIndex(index.clone()),
+                        )
+                    });
                 Ok((table_cursor_id, index_cursor_id))
             }
             Table::Virtual(virtual_table) => {
@@ -947,8 +1231,18 @@ impl JoinedTable {
         if self.col_used_mask.is_empty() {
             return false;
         }
+        if index.is_without_rowid_primary_key_of(btree) {
+            // The rows are read directly from the table's b-tree.
+            return false;
+        }
         let mut index_cols_mask = ColumnUsedMask::default();
-        for col in index.columns.iter() {
+        // The columns on expressions do not provide the values of any column.
+        for col in index
+            .columns
+            .iter()
+            .chain(index.primary_key_suffix.iter())
+            .filter(|col| col.expr.is_none())
+        {
             index_cols_mask.set(col.pos_in_table);
         }
 
@@ -1083,21 +1377,83 @@ pub struct Aggregate {
     pub args: Vec<ast::Expr>,
     pub original_expr: ast::Expr,
     pub distinctness: Distinctness,
+    /// Expression of the FILTER clause. Rows for which it is not true are not aggregated.
+    pub filter: Option<ast::Expr>,
+    /// Terms of the ORDER BY clause inside the call, e.g. `group_concat(x ORDER BY y)`.
+    /// The arguments are fed to the aggregate function in this order.
+    pub order_by: Vec<(ast::Expr, SortOrder)>,
+    /// Translation context for the ORDER BY clause, populated during emission.
+    pub order_by_ctx: Option<AggOrderByCtx>,
 }
 
 impl Aggregate {
     pub fn new(func: AggFunc, args: &[Box<Expr>], expr: &Expr, distinctness: Distinctness) -> Self {
+        let (filter, order_by) = match expr {
+            Expr::FunctionCall {
+                filter_over,
+                order_by,
+                ..
+            } => (
+                filter_over.filter_clause.as_deref().cloned(),
+                order_by
+                    .iter()
+                    .map(|col| (*col.expr.clone(), col.order.unwrap_or(SortOrder::Asc)))
+                    .collect(),
+            ),
+            Expr::FunctionCallStar { filter_over, .. } => {
+                (filter_over.filter_clause.as_deref().cloned(), vec![])
+            }
+            _ => (None, vec![]),
+        };
         Aggregate {
             func,
             args: args.iter().map(|arg| *arg.clone()).collect(),
             original_expr: expr.clone(),
             distinctness,
+            filter,
+            order_by,
+            order_by_ctx: None,
         }
     }
 
     pub fn is_distinct(&self) -> bool {
         self.distinctness.is_distinct()
     }
+
+    /// The expressions evaluated for every row fed to the aggregate: the arguments, followed
+    /// by the ORDER BY terms and the FILTER expression.
+    pub fn inputs(&self) -> impl Iterator<Item = &ast::Expr> {
+        self.args
+            .iter()
+            .chain(self.order_by.iter().map(|(expr, _)| expr))
+            .chain(self.filter.iter())
+    }
+
+    pub fn inputs_mut(&mut self) -> impl Iterator<Item = &mut ast::Expr> {
+        self.args
+            .iter_mut()
+            .chain(self.order_by.iter_mut().map(|(expr, _)| expr))
+            .chain(self.filter.iter_mut())
+    }
+
+    pub fn num_inputs(&self) -> usize {
+        self.args.len() + self.order_by.len() + usize::from(self.filter.is_some())
+    }
+}
+
+/// Translation context for an aggregate with an ORDER BY clause.
+///
+/// The arguments of such an aggregate are buffered in an ephemeral index keyed by the ORDER BY
+/// terms, and only fed to the aggregate function when the aggregate is finalized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggOrderByCtx {
+    /// The cursor ID for the ephemeral index buffering the arguments.
+    pub cursor_id: usize,
+    /// The index name for the ephemeral index, needed to lookup the cursor ID.
+    pub ephemeral_index_name: String,
+    /// A counter appended to the ORDER BY terms, so that rows with equal terms are kept and
+    /// aggregated in insertion order.
+    pub reg_sequence: usize,
 }
 
 /// Represents the window definition and all window functions associated with a single SELECT.
@@ -1114,6 +1470,8 @@ pub struct Window {
     pub deduplicated_partition_by_len: Option<usize>,
     /// Expressions from the ORDER BY clause.
     pub order_by: Vec<(Expr, SortOrder)>,
+    /// The frame of the window.
+    pub frame: WindowFrame,
     /// All window functions associated with this window.
     pub functions: Vec<WindowFunction>,
 }
@@ -1122,9 +1480,8 @@ impl Window {
     const DEFAULT_SORT_ORDER: SortOrder = SortOrder::Asc;
 
     pub fn new(name: Option<String>, ast: &ast::Window) -> Result<Self> {
-        if !Self::is_default_frame_spec(&ast.frame_clause) {
-            crate::bail_parse_error!("Custom frame specifications are not supported yet");
-        }
+        let frame = WindowFrame::new(ast.frame_clause.as_ref());
+        frame.validate(ast.order_by.len())?;
 
         Ok(Window {
             name,
@@ -1140,12 +1497,16 @@ impl Window {
                     )
                 })
                 .collect(),
+            frame,
             functions: vec![],
         })
     }
 
     pub fn is_equivalent(&self, ast: &ast::Window) -> bool {
-        if !Self::is_default_frame_spec(&ast.frame_clause) {
+        if !self
+            .frame
+            .is_equivalent(&WindowFrame::new(ast.frame_clause.as_ref()))
+        {
             return false;
         }
 
@@ -1172,39 +1533,157 @@ impl Window {
                     && *order_a == col_b.order.unwrap_or(Self::DEFAULT_SORT_ORDER)
             })
     }
+}
 
-    fn is_default_frame_spec(frame: &Option<FrameClause>) -> bool {
-        if let Some(frame_clause) = frame {
-            let FrameClause {
-                mode,
-                start,
-                end,
-                exclude,
-            } = frame_clause;
-            if *mode != FrameMode::Range {
-                return false;
+/// The frame of a window, i.e. the subset of the partition that aggregate functions and
+/// value functions such as `first_value()` are evaluated over for each row.
+#[derive(Debug, Clone)]
+pub struct WindowFrame {
+    pub mode: FrameMode,
+    pub start: FrameBound,
+    /// The end bound. When the frame clause only specifies a start bound, this is `CURRENT ROW`.
+    pub end: FrameBound,
+    pub exclude: FrameExclude,
+}
+
+impl WindowFrame {
+    /// Builds the frame from its AST representation. A missing frame clause is equivalent to
+    /// `RANGE BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW EXCLUDE NO OTHERS`.
+    ///
+    /// Like SQLite, offsets that are not constant expressions are replaced with NULL, which
+    /// later fails the runtime check of the offset value.
+    pub fn new(frame_clause: Option<&FrameClause>) -> Self {
+        let Some(frame_clause) = frame_clause else {
+            return WindowFrame {
+                mode: FrameMode::Range,
+                start: FrameBound::UnboundedPreceding,
+                end: FrameBound::CurrentRow,
+                exclude: FrameExclude::NoOthers,
+            };
+        };
+        let constant_offset = |bound: &FrameBound| match bound {
+            FrameBound::Preceding(expr) if !Self::is_constant_offset(expr) => {
+                FrameBound::Preceding(Box::new(Expr::Literal(ast::Literal::Null)))
             }
-            if *start != FrameBound::UnboundedPreceding {
-                return false;
+            FrameBound::Following(expr) if !Self::is_constant_offset(expr) => {
+                FrameBound::Following(Box::new(Expr::Literal(ast::Literal::Null)))
             }
-            if *end != Some(FrameBound::CurrentRow) {
-                return false;
+            bound => bound.clone(),
+        };
+        WindowFrame {
+            mode: frame_clause.mode,
+            start: constant_offset(&frame_clause.start),
+            end: frame_clause
+                .end
+                .as_ref()
+                .map_or(FrameBound::CurrentRow, constant_offset),
+            exclude: frame_clause
+                .exclude
+                .clone()
+                .unwrap_or(FrameExclude::NoOthers),
+        }
+    }
+
+    fn is_constant_offset(expr: &Expr) -> bool {
+        match expr {
+            Expr::Literal(_) | Expr::Variable(_) => true,
+            Expr::Binary(lhs, _, rhs) => {
+                Self::is_constant_offset(lhs) && Self::is_constant_offset(rhs)
             }
-            if let Some(exclude) = exclude {
-                if *exclude != FrameExclude::NoOthers {
-                    return false;
-                }
+            Expr::Unary(_, expr) | Expr::Cast { expr, .. } | Expr::Collate(expr, _) => {
+                Self::is_constant_offset(expr)
             }
+            Expr::Parenthesized(exprs) => exprs.iter().all(|e| Self::is_constant_offset(e)),
+            Expr::Case {
+                base,
+                when_then_pairs,
+                else_expr,
+            } => {
+                base.as_ref().is_none_or(|e| Self::is_constant_offset(e))
+                    && when_then_pairs
+                        .iter()
+                        .all(|(w, t)| Self::is_constant_offset(w) && Self::is_constant_offset(t))
+                    && else_expr
+                        .as_ref()
+                        .is_none_or(|e| Self::is_constant_offset(e))
+            }
+            _ => false,
+        }
+    }
+
+    fn validate(&self, order_by_len: usize) -> Result<()> {
+        // The frame cannot end before the current row if it starts at or after it.
+        if matches!(
+            (&self.start, &self.end),
+            (FrameBound::UnboundedFollowing, _)
+                | (_, FrameBound::UnboundedPreceding)
+                | (FrameBound::CurrentRow, FrameBound::Preceding(_))
+                | (
+                    FrameBound::Following(_),
+                    FrameBound::Preceding(_) | FrameBound::CurrentRow
+                )
+        ) {
+            crate::bail_parse_error!("unsupported frame specification");
+        }
+        let has_offset = |bound: &FrameBound| {
+            matches!(bound, FrameBound::Preceding(_) | FrameBound::Following(_))
+        };
+        if self.mode == FrameMode::Range
+            && order_by_len != 1
+            && (has_offset(&self.start) || has_offset(&self.end))
+        {
+            crate::bail_parse_error!(
+                "RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression"
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns true if this is the frame used when no frame clause is given, which spans from
+    /// the start of the partition to the last peer of the current row.
+    pub fn is_default(&self) -> bool {
+        self.mode == FrameMode::Range
+            && self.start == FrameBound::UnboundedPreceding
+            && self.end == FrameBound::CurrentRow
+            && self.exclude == FrameExclude::NoOthers
+    }
+
+    fn is_equivalent(&self, other: &WindowFrame) -> bool {
+        let bounds_are_equivalent = |a: &FrameBound, b: &FrameBound| match (a, b) {
+            (FrameBound::Preceding(a), FrameBound::Preceding(b))
+            | (FrameBound::Following(a), FrameBound::Following(b)) => exprs_are_equivalent(a, b),
+            (a, b) => a == b,
+        };
+        self.mode == other.mode
+            && self.exclude == other.exclude
+            && bounds_are_equivalent(&self.start, &other.start)
+            && bounds_are_equivalent(&self.end, &other.end)
+    }
+}
+
+/// The function evaluated over a window.
+#[derive(Debug, Clone)]
+pub enum WindowFunctionKind {
+    /// A regular aggregate function, evaluated over the rows of the window frame.
+    Agg(AggFunc),
+    /// A built-in window function, such as `rank()` or `lag()`, evaluated from the position
+    /// of the current row within its partition.
+    Window(WindowFunc),
+}
+
+impl std::fmt::Display for WindowFunctionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Agg(agg_func) => write!(f, "{}", agg_func.to_string()),
+            Self::Window(window_func) => write!(f, "{window_func}"),
         }
-        true
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct WindowFunction {
-    /// The resolved function. Currently, only regular aggregate functions are supported.
-    /// In the future, more specialized window functions such as `RANK()`, `ROW_NUMBER()`, etc. will be added.
-    pub func: AggFunc,
+    /// The resolved function.
+    pub func: WindowFunctionKind,
     /// The expression from which the function was resolved.
     pub original_expr: Expr,
 }
@@ -5,14 +5,15 @@ use super::{
     expr::walk_expr,
     plan::{
         Aggregate, ColumnUsedMask, Distinctness, EvalAt, IterationDirection, JoinInfo,
-        JoinOrderMember, JoinedTable, Operation, OuterQueryReference, Plan, QueryDestination,
-        ResultSetColumn, Scan, TableReferences, WhereTerm,
+        JoinOrderMember, JoinedTable, NonFromClauseSubquery, Operation, OuterQueryReference, Plan,
+        QueryDestination, RecursiveCte, ResultSetColumn, Scan, SelectPlan, TableReferences,
+        WhereTerm,
     },
     select::prepare_select_plan,
     SymbolTable,
 };
-use crate::translate::expr::{BindingBehavior, WalkControl};
-use crate::translate::plan::{Window, WindowFunction};
+use crate::translate::expr::{walk_expr_mut, BindingBehavior, WalkControl};
+use crate::translate::plan::{Window, WindowFunction, WindowFunctionKind};
 use crate::{
     ast::Limit,
     function::Func,
@@ -27,7 +28,8 @@ use crate::{
 };
 use turso_parser::ast::Literal::Null;
 use turso_parser::ast::{
-    self, As, Expr, FromClause, JoinType, Materialized, Over, QualifiedName, TableInternalId, With,
+    self, As, Expr, FromClause, JoinType, Materialized, Over, QualifiedName, SortOrder,
+    TableInternalId, With,
 };
 
 /// Valid ways to refer to the rowid of a btree table.
@@ -67,16 +69,6 @@ pub fn resolve_window_and_aggregate_functions(
                 filter_over,
                 order_by,
             } => {
-                if filter_over.filter_clause.is_some() {
-                    crate::bail_parse_error!(
-                        "FILTER clause is not supported yet in aggregate functions"
-                    );
-                }
-                if !order_by.is_empty() {
-                    crate::bail_parse_error!(
-                        "ORDER BY clause is not supported yet in aggregate functions"
-                    );
-                }
                 let args_count = args.len();
                 let distinctness = Distinctness::from_ast(distinctness.as_ref());
 
@@ -88,10 +80,16 @@ pub fn resolve_window_and_aggregate_functions(
                 match Func::resolve_function(name.as_str(), args_count) {
                     Ok(Func::Agg(f)) => {
                         if let Some(over_clause) = filter_over.over_clause.as_ref() {
+                            if !order_by.is_empty() {
+                                crate::bail_parse_error!(
+                                    "ORDER BY may not be used with non-aggregate {}()",
+                                    name.as_str()
+                                );
+                            }
                             link_with_window(
                                 windows.as_deref_mut(),
                                 expr,
-                                f,
+                                WindowFunctionKind::Agg(f),
                                 over_clause,
                                 distinctness,
                             )?;
@@ -101,15 +99,48 @@ pub fn resolve_window_and_aggregate_functions(
                         }
                         return Ok(WalkControl::SkipChildren);
                     }
+                    Ok(Func::Window(f)) => {
+                        let Some(over_clause) = filter_over.over_clause.as_ref() else {
+                            crate::bail_parse_error!(
+                                "misuse of window function {}()",
+                                name.as_str()
+                            );
+                        };
+                        if filter_over.filter_clause.is_some() {
+                            crate::bail_parse_error!(
+                                "FILTER clause may only be used with aggregate window functions"
+                            );
+                        }
+                        if !order_by.is_empty() {
+                            crate::bail_parse_error!(
+                                "ORDER BY may not be used with non-aggregate {}()",
+                                name.as_str()
+                            );
+                        }
+                        link_with_window(
+                            windows.as_deref_mut(),
+                            expr,
+                            WindowFunctionKind::Window(f),
+                            over_clause,
+                            distinctness,
+                        )?;
+                        return Ok(WalkControl::SkipChildren);
+                    }
                     Err(e) => {
                         if let Some(f) = syms.resolve_function(name.as_str(), args_count) {
                             let func = AggFunc::External(f.func.clone().into());
                             if let ExtFunc::Aggregate { .. } = f.as_ref().func {
                                 if let Some(over_clause) = filter_over.over_clause.as_ref() {
+                                    if !order_by.is_empty() {
+                                        crate::bail_parse_error!(
+                                            "ORDER BY may not be used with non-aggregate {}()",
+                                            name.as_str()
+                                        );
+                                    }
                                     link_with_window(
                                         windows.as_deref_mut(),
                                         expr,
-                                        func,
+                                        WindowFunctionKind::Agg(func),
                                         over_clause,
                                         distinctness,
                                     )?;
@@ -138,20 +169,27 @@ pub fn resolve_window_and_aggregate_functions(
                         }
                     }
                 }
-            }
-            Expr::FunctionCallStar { name, filter_over } => {
                 if filter_over.filter_clause.is_some() {
                     crate::bail_parse_error!(
-                        "FILTER clause is not supported yet in aggregate functions"
+                        "FILTER may not be used with non-aggregate {}()",
+                        name.as_str()
+                    );
+                }
+                if !order_by.is_empty() {
+                    crate::bail_parse_error!(
+                        "ORDER BY may not be used with non-aggregate {}()",
+                        name.as_str()
                     );
                 }
+            }
+            Expr::FunctionCallStar { name, filter_over } => {
                 match Func::resolve_function(name.as_str(), 0) {
                     Ok(Func::Agg(f)) => {
                         if let Some(over_clause) = filter_over.over_clause.as_ref() {
                             link_with_window(
                                 windows.as_deref_mut(),
                                 expr,
-                                f,
+                                WindowFunctionKind::Agg(f),
                                 over_clause,
                                 Distinctness::NonDistinct,
                             )?;
@@ -201,7 +239,7 @@ pub fn resolve_window_and_aggregate_functions(
 fn link_with_window(
     windows: Option<&mut Vec<Window>>,
     expr: &Expr,
-    func: AggFunc,
+    func: WindowFunctionKind,
     over_clause: &Over,
     distinctness: Distinctness,
 ) -> Result<()> {
@@ -275,7 +313,7 @@ fn parse_from_clause_table(
     connection: &Arc<crate::Connection>,
 ) -> Result<()> {
     match table {
-        ast::SelectTable::Table(qualified_name, maybe_alias, _) => parse_table(
+        ast::SelectTable::Table(qualified_name, maybe_alias, indexed) => parse_table(
             schema,
             syms,
             table_references,
@@ -285,10 +323,11 @@ fn parse_from_clause_table(
             &qualified_name,
             maybe_alias.as_ref(),
             &[],
+            indexed,
             connection,
         ),
         ast::SelectTable::Select(subselect, maybe_alias) => {
-            let Plan::Select(subplan) = prepare_select_plan(
+            let subplan = prepare_select_plan(
                 schema,
                 subselect,
                 syms,
@@ -296,10 +335,7 @@ fn parse_from_clause_table(
                 table_ref_counter,
                 QueryDestination::placeholder_for_subquery(),
                 connection,
-            )?
-            else {
-                crate::bail_parse_error!("Only non-compound SELECT queries are currently supported in FROM clause subqueries");
-            };
+            )?;
             let cur_table_index = table_references.joined_tables().len();
             let identifier = maybe_alias
                 .map(|a| match a {
@@ -308,12 +344,21 @@ fn parse_from_clause_table(
                 })
                 .map(|id| normalize_ident(id.as_str()))
                 .unwrap_or(format!("subquery_{cur_table_index}"));
-            table_references.add_joined_table(JoinedTable::new_subquery(
-                identifier,
-                subplan,
-                None,
-                table_ref_counter.next(),
-            ));
+            let subquery = match subplan {
+                Plan::Select(subplan) => {
+                    JoinedTable::new_subquery(identifier, subplan, None, table_ref_counter.next())
+                }
+                compound => compound_subquery(
+                    schema,
+                    syms,
+                    identifier,
+                    compound,
+                    &[],
+                    table_ref_counter,
+                    connection,
+                )?,
+            };
+            table_references.add_joined_table(subquery);
             Ok(())
         }
         ast::SelectTable::TableCall(qualified_name, args, maybe_alias) => parse_table(
@@ -326,12 +371,31 @@ fn parse_from_clause_table(
             &qualified_name,
             maybe_alias.as_ref(),
             &args,
+            None,
             connection,
         ),
         _ => todo!(),
     }
 }
 
+/// Returns an error if an INDEXED BY clause names an index that does not exist on `table_name`.
+/// Only tables have indexes, so the clause is an error on views and CTEs.
+pub fn check_indexed_by(
+    schema: &Schema,
+    table_name: &str,
+    indexed: Option<&ast::Indexed>,
+) -> Result<()> {
+    if let Some(ast::Indexed::IndexedBy(index_name)) = indexed {
+        if schema
+            .get_index(table_name, &normalize_ident(index_name.as_str()))
+            .is_none()
+        {
+            crate::bail_parse_error!("no such index: {}", index_name.as_str());
+        }
+    }
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 fn parse_table(
     schema: &Schema,
@@ -343,17 +407,25 @@ fn parse_table(
     qualified_name: &QualifiedName,
     maybe_alias: Option<&As>,
     args: &[Box<Expr>],
+    indexed: Option<ast::Indexed>,
     connection: &Arc<crate::Connection>,
 ) -> Result<()> {
     let normalized_qualified_name = normalize_ident(qualified_name.name.as_str());
     let database_id = connection.resolve_database_id(qualified_name)?;
     let table_name = &qualified_name.name;
+    // Whatever the name resolves to, an INDEXED BY clause must name one of its indexes.
+    let check_indexed = || {
+        connection.with_schema(database_id, |schema| {
+            check_indexed_by(schema, table_name.as_str(), indexed.as_ref())
+        })
+    };
 
     // Check if the FROM clause table is referring to a CTE in the current scope.
     if let Some(cte_idx) = ctes
         .iter()
         .position(|cte| cte.identifier == normalized_qualified_name)
     {
+        check_indexed()?;
         // TODO: what if the CTE is referenced multiple times?
         let mut cte_table = ctes.remove(cte_idx);
 
@@ -374,6 +446,7 @@ fn parse_table(
     let table = connection.with_schema(database_id, |schema| schema.get_table(table_name.as_str()));
 
     if let Some(table) = table {
+        check_indexed()?;
         let alias = maybe_alias
             .map(|a| match a {
                 ast::As::As(id) => id,
@@ -399,6 +472,7 @@ fn parse_table(
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id,
+            indexed,
         });
         return Ok(());
     };
@@ -407,8 +481,15 @@ fn parse_table(
         schema.get_view(table_name.as_str()).cloned()
     });
     if let Some(view) = regular_view {
+        check_indexed()?;
         // Views are essentially query aliases, so just Expand the view as a subquery
-        let view_select = view.select_stmt.clone();
+        let mut view_select = view.select_stmt.clone();
+        // The tables of a view in the temp or an attached database are looked up in that database.
+        if database_id != 0 {
+            if let Some(database_name) = connection.get_database_name_by_index(database_id) {
+                qualify_table_names(&mut view_select, &ast::Name::new(database_name))?;
+            }
+        }
         let subselect = Box::new(view_select);
 
         // Use the view name as alias if no explicit alias was provided
@@ -433,6 +514,7 @@ fn parse_table(
         schema.get_materialized_view(table_name.as_str())
     });
     if let Some(view) = view {
+        check_indexed()?;
         // Check if this materialized view has persistent storage
         let view_guard = view.lock().unwrap();
         let root_page = view_guard.get_root_page();
@@ -454,6 +536,8 @@ fn parse_table(
             has_rowid: true,
             is_strict: false,
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
 
             unique_sets: vec![],
         });
@@ -477,6 +561,7 @@ fn parse_table(
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id,
+            indexed: None,
         });
         return Ok(());
     }
@@ -492,6 +577,7 @@ fn parse_table(
         table_references.find_outer_query_ref_by_identifier(&normalized_qualified_name)
     {
         if matches!(outer_ref.table, Table::FromClauseSubquery(_)) {
+            check_indexed()?;
             table_references.add_joined_table(JoinedTable {
                 op: Operation::default_scan_for(&outer_ref.table),
                 table: outer_ref.table.clone(),
@@ -500,6 +586,7 @@ fn parse_table(
                 join_info: None,
                 col_used_mask: ColumnUsedMask::default(),
                 database_id,
+                indexed: None,
             });
             return Ok(());
         }
@@ -508,6 +595,97 @@ fn parse_table(
     crate::bail_parse_error!("no such table: {}", normalized_qualified_name);
 }
 
+/// Qualifies the unqualified table names of `select` with `database`.
+fn qualify_table_names(select: &mut ast::Select, database: &ast::Name) -> Result<()> {
+    if let Some(with) = &mut select.with {
+        for cte in with.ctes.iter_mut() {
+            qualify_table_names(&mut cte.select, database)?;
+        }
+    }
+    qualify_one_select_table_names(&mut select.body.select, database)?;
+    for compound in select.body.compounds.iter_mut() {
+        qualify_one_select_table_names(&mut compound.select, database)?;
+    }
+    for sorted_column in select.order_by.iter_mut() {
+        qualify_expr_table_names(&mut sorted_column.expr, database)?;
+    }
+    Ok(())
+}
+
+fn qualify_one_select_table_names(select: &mut ast::OneSelect, database: &ast::Name) -> Result<()> {
+    match select {
+        ast::OneSelect::Select {
+            columns,
+            from,
+            where_clause,
+            group_by,
+            ..
+        } => {
+            for column in columns.iter_mut() {
+                if let ast::ResultColumn::Expr(expr, _) = column {
+                    qualify_expr_table_names(expr, database)?;
+                }
+            }
+            if let Some(from) = from {
+                qualify_from_table_names(from, database)?;
+            }
+            if let Some(where_clause) = where_clause {
+                qualify_expr_table_names(where_clause, database)?;
+            }
+            if let Some(group_by) = group_by {
+                if let Some(having) = &mut group_by.having {
+                    qualify_expr_table_names(having, database)?;
+                }
+            }
+        }
+        ast::OneSelect::Values(values) => {
+            for expr in values.iter_mut().flat_map(|v| v.iter_mut()) {
+                qualify_expr_table_names(expr, database)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn qualify_from_table_names(from: &mut FromClause, database: &ast::Name) -> Result<()> {
+    let tables = std::iter::once(from.select.as_mut())
+        .chain(from.joins.iter_mut().map(|join| join.table.as_mut()));
+    for table in tables {
+        match table {
+            ast::SelectTable::Table(name, ..) => {
+                if name.db_name.is_none() {
+                    name.db_name = Some(database.clone());
+                }
+            }
+            ast::SelectTable::TableCall(_, args, _) => {
+                for arg in args.iter_mut() {
+                    qualify_expr_table_names(arg, database)?;
+                }
+            }
+            ast::SelectTable::Select(select, _) => qualify_table_names(select, database)?,
+            ast::SelectTable::Sub(from, _) => qualify_from_table_names(from, database)?,
+        }
+    }
+    for join in from.joins.iter_mut() {
+        if let Some(ast::JoinConstraint::On(expr)) = &mut join.constraint {
+            qualify_expr_table_names(expr, database)?;
+        }
+    }
+    Ok(())
+}
+
+fn qualify_expr_table_names(expr: &mut Expr, database: &ast::Name) -> Result<()> {
+    walk_expr_mut(expr, &mut |expr: &mut Expr| -> Result<WalkControl> {
+        match expr {
+            Expr::Exists(select) | Expr::Subquery(select) => qualify_table_names(select, database)?,
+            Expr::InSelect { rhs, .. } => qualify_table_names(rhs, database)?,
+            _ => {}
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(())
+}
+
 fn transform_args_into_where_terms(
     args: &[Box<Expr>],
     internal_id: TableInternalId,
@@ -570,104 +748,580 @@ pub fn parse_from(
         return Ok(());
     }
 
+    let mut ctes_as_subqueries = match with {
+        Some(with) => parse_with(
+            schema,
+            with,
+            syms,
+            table_references.outer_query_refs(),
+            table_ref_counter,
+            connection,
+            param_ctx,
+        )?,
+        None => vec![],
+    };
+
+    parse_from_clause(
+        schema,
+        std::mem::take(&mut from).unwrap(),
+        syms,
+        &mut ctes_as_subqueries,
+        out_where_clause,
+        vtab_predicates,
+        table_references,
+        table_ref_counter,
+        connection,
+        param_ctx,
+    )
+}
+
+/// Adds the tables of a FROM clause to `table_references`, after the tables already in it.
+/// Tables named after one of the `ctes` refer to it, and the CTE is removed from `ctes`.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_from_clause(
+    schema: &Schema,
+    from: FromClause,
+    syms: &SymbolTable,
+    ctes: &mut Vec<JoinedTable>,
+    out_where_clause: &mut Vec<WhereTerm>,
+    vtab_predicates: &mut Vec<Expr>,
+    table_references: &mut TableReferences,
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+    param_ctx: &mut ParamState,
+) -> Result<()> {
+    parse_from_clause_table(
+        schema,
+        *from.select,
+        table_references,
+        vtab_predicates,
+        ctes,
+        syms,
+        table_ref_counter,
+        connection,
+    )?;
+
+    for join in from.joins.into_iter() {
+        parse_join(
+            schema,
+            join,
+            syms,
+            ctes,
+            out_where_clause,
+            vtab_predicates,
+            table_references,
+            table_ref_counter,
+            connection,
+            param_ctx,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Plans the CTEs of a WITH clause as FROM clause subqueries, in the order they are defined.
+/// A CTE can refer to the CTEs defined before it and to the tables in `outer_query_refs`.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_with(
+    schema: &Schema,
+    with: With,
+    syms: &SymbolTable,
+    outer_query_refs: &[OuterQueryReference],
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+    param_ctx: &mut ParamState,
+) -> Result<Vec<JoinedTable>> {
     let mut ctes_as_subqueries = vec![];
+    let recursive = with.recursive;
+    for cte in with.ctes {
+        if cte.materialized == Materialized::Yes {
+            crate::bail_parse_error!("Materialized CTEs are not yet supported");
+        }
 
-    if let Some(with) = with {
-        if with.recursive {
-            crate::bail_parse_error!("Recursive CTEs are not yet supported");
+        // Check if normalized name conflicts with catalog tables or other CTEs
+        // TODO: sqlite actually allows overriding a catalog table with a CTE.
+        // We should carry over the 'Scope' struct to all of our identifier resolution.
+        let cte_name_normalized = normalize_ident(cte.tbl_name.as_str());
+        if schema.get_table(&cte_name_normalized).is_some() {
+            crate::bail_parse_error!(
+                "CTE name {} conflicts with catalog table name",
+                cte.tbl_name.as_str()
+            );
+        }
+        if outer_query_refs
+            .iter()
+            .any(|t| t.identifier == cte_name_normalized)
+        {
+            crate::bail_parse_error!(
+                "CTE name {} conflicts with WITH table name {}",
+                cte.tbl_name.as_str(),
+                cte_name_normalized
+            );
         }
-        for cte in with.ctes {
-            if cte.materialized == Materialized::Yes {
-                crate::bail_parse_error!("Materialized CTEs are not yet supported");
-            }
-            if !cte.columns.is_empty() {
-                crate::bail_parse_error!("CTE columns are not yet supported");
-            }
 
-            // Check if normalized name conflicts with catalog tables or other CTEs
-            // TODO: sqlite actually allows overriding a catalog table with a CTE.
-            // We should carry over the 'Scope' struct to all of our identifier resolution.
-            let cte_name_normalized = normalize_ident(cte.tbl_name.as_str());
-            if schema.get_table(&cte_name_normalized).is_some() {
-                crate::bail_parse_error!(
-                    "CTE name {} conflicts with catalog table name",
-                    cte.tbl_name.as_str()
-                );
-            }
-            if table_references
-                .outer_query_refs()
-                .iter()
-                .any(|t| t.identifier == cte_name_normalized)
-            {
-                crate::bail_parse_error!(
-                    "CTE name {} conflicts with WITH table name {}",
-                    cte.tbl_name.as_str(),
-                    cte_name_normalized
-                );
+        let mut outer_query_refs_for_cte = outer_query_refs.to_vec();
+        outer_query_refs_for_cte.extend(ctes_as_subqueries.iter().map(|t: &JoinedTable| {
+            OuterQueryReference {
+                identifier: t.identifier.clone(),
+                internal_id: t.internal_id,
+                table: t.table.clone(),
+                col_used_mask: ColumnUsedMask::default(),
             }
+        }));
 
-            let mut outer_query_refs_for_cte = table_references.outer_query_refs().to_vec();
-            outer_query_refs_for_cte.extend(ctes_as_subqueries.iter().map(|t: &JoinedTable| {
-                OuterQueryReference {
-                    identifier: t.identifier.clone(),
-                    internal_id: t.internal_id,
-                    table: t.table.clone(),
-                    col_used_mask: ColumnUsedMask::default(),
-                }
-            }));
+        let cte_columns: Vec<String> = cte
+            .columns
+            .iter()
+            .map(|column| normalize_ident(column.col_name.as_str()))
+            .collect();
 
-            // CTE can refer to other CTEs that came before it, plus any schema tables or tables in the outer scope.
-            let cte_plan = prepare_select_plan(
+        if recursive && select_references_table(&cte.select, &cte_name_normalized) {
+            let (cte_plan, recursive_cte) = prepare_recursive_cte_plan(
                 schema,
                 cte.select,
+                &cte_name_normalized,
+                &cte_columns,
                 syms,
                 &outer_query_refs_for_cte,
                 table_ref_counter,
-                QueryDestination::placeholder_for_subquery(),
                 connection,
+                param_ctx,
             )?;
-            let Plan::Select(cte_plan) = cte_plan else {
-                crate::bail_parse_error!("Only SELECT queries are currently supported in CTEs");
-            };
-            ctes_as_subqueries.push(JoinedTable::new_subquery(
+            ctes_as_subqueries.push(JoinedTable::new_recursive_cte(
                 cte_name_normalized,
                 cte_plan,
-                None,
+                recursive_cte,
                 table_ref_counter.next(),
             ));
+            continue;
         }
+
+        // CTE can refer to other CTEs that came before it, plus any schema tables or tables in the outer scope.
+        let cte_plan = prepare_select_plan(
+            schema,
+            cte.select,
+            syms,
+            &outer_query_refs_for_cte,
+            table_ref_counter,
+            QueryDestination::placeholder_for_subquery(),
+            connection,
+        )?;
+        let mut cte_plan = match cte_plan {
+            Plan::Select(cte_plan) => cte_plan,
+            compound => {
+                ctes_as_subqueries.push(compound_subquery(
+                    schema,
+                    syms,
+                    cte_name_normalized,
+                    compound,
+                    &cte_columns,
+                    table_ref_counter,
+                    connection,
+                )?);
+                continue;
+            }
+        };
+        if !cte_columns.is_empty() {
+            if cte_columns.len() != cte_plan.result_columns.len() {
+                crate::bail_parse_error!(
+                    "table {} has {} values for {} columns",
+                    cte_name_normalized,
+                    cte_plan.result_columns.len(),
+                    cte_columns.len()
+                );
+            }
+            for (result_column, name) in cte_plan.result_columns.iter_mut().zip(cte_columns) {
+                result_column.alias = Some(name);
+            }
+        }
+        ctes_as_subqueries.push(JoinedTable::new_subquery(
+            cte_name_normalized,
+            cte_plan,
+            None,
+            table_ref_counter.next(),
+        ));
     }
+    Ok(ctes_as_subqueries)
+}
 
-    let from_owned = std::mem::take(&mut from).unwrap();
-    let select_owned = from_owned.select;
-    let joins_owned = from_owned.joins;
-    parse_from_clause_table(
+/// Counts the references to the table `name` in the FROM clause of `select`, and returns
+/// whether `name` is also referenced from a subquery of that FROM clause.
+fn count_from_clause_references(select: &ast::OneSelect, name: &str) -> (usize, bool) {
+    let ast::OneSelect::Select {
+        from: Some(from), ..
+    } = select
+    else {
+        return (0, false);
+    };
+    let mut count = 0;
+    let mut in_subquery = false;
+    for table in std::iter::once(&from.select).chain(from.joins.iter().map(|join| &join.table)) {
+        match table.as_ref() {
+            ast::SelectTable::Table(qualified_name, _, _) => {
+                if qualified_name.db_name.is_none()
+                    && normalize_ident(qualified_name.name.as_str()) == name
+                {
+                    count += 1;
+                }
+            }
+            ast::SelectTable::Select(subselect, _) => {
+                in_subquery |= select_references_table(subselect, name);
+            }
+            ast::SelectTable::TableCall(..) | ast::SelectTable::Sub(..) => {}
+        }
+    }
+    (count, in_subquery)
+}
+
+/// Returns whether the FROM clause of any SELECT of `select` refers to the table `name`.
+fn select_references_table(select: &ast::Select, name: &str) -> bool {
+    std::iter::once(&select.body.select)
+        .chain(
+            select
+                .body
+                .compounds
+                .iter()
+                .map(|compound| &compound.select),
+        )
+        .any(|one_select| {
+            let (count, in_subquery) = count_from_clause_references(one_select, name);
+            count > 0 || in_subquery
+        })
+}
+
+/// Turns a compound SELECT into a FROM clause subquery. Its columns are named after `columns`
+/// if given, and after the result columns of the leftmost SELECT otherwise.
+#[allow(clippy::too_many_arguments)]
+fn compound_subquery(
+    schema: &Schema,
+    syms: &SymbolTable,
+    identifier: String,
+    compound: Plan,
+    columns: &[String],
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+) -> Result<JoinedTable> {
+    let leftmost = compound
+        .compound_selects()
+        .next()
+        .expect("a compound SELECT has at least two SELECTs");
+    let names: Vec<Option<String>> = if columns.is_empty() {
+        leftmost
+            .result_columns
+            .iter()
+            .map(|result_column| {
+                result_column
+                    .name(&leftmost.table_references)
+                    .map(String::from)
+            })
+            .collect()
+    } else {
+        if columns.len() != leftmost.result_columns.len() {
+            crate::bail_parse_error!(
+                "table {} has {} values for {} columns",
+                identifier,
+                leftmost.result_columns.len(),
+                columns.len()
+            );
+        }
+        columns.iter().cloned().map(Some).collect()
+    };
+    let Plan::Select(plan) = prepare_select_plan(
         schema,
-        *select_owned,
-        table_references,
-        vtab_predicates,
-        &mut ctes_as_subqueries,
+        placeholder_select(&names),
         syms,
+        &[],
         table_ref_counter,
+        QueryDestination::placeholder_for_subquery(),
         connection,
-    )?;
+    )?
+    else {
+        unreachable!("a single SELECT is not planned as a compound SELECT");
+    };
+    Ok(JoinedTable::new_compound_subquery(
+        identifier,
+        plan,
+        compound,
+        table_ref_counter.next(),
+    ))
+}
 
-    for join in joins_owned.into_iter() {
-        parse_join(
-            schema,
-            join,
-            syms,
-            &mut ctes_as_subqueries,
-            out_where_clause,
-            vtab_predicates,
-            table_references,
-            table_ref_counter,
-            connection,
-            param_ctx,
-        )?;
+/// A `SELECT NULL AS column, ...` query with one result column per entry of `names`.
+fn placeholder_select(names: &[Option<String>]) -> ast::Select {
+    ast::Select {
+        with: None,
+        body: ast::SelectBody {
+            select: ast::OneSelect::Select {
+                distinctness: None,
+                columns: names
+                    .iter()
+                    .map(|name| {
+                        ast::ResultColumn::Expr(
+                            Box::new(Expr::Literal(Null)),
+                            name.as_ref()
+                                .map(|name| As::As(ast::Name::Ident(name.clone()))),
+                        )
+                    })
+                    .collect(),
+                from: None,
+                where_clause: None,
+                group_by: None,
+                window_clause: vec![],
+            },
+            compounds: vec![],
+        },
+        order_by: vec![],
+        limit: None,
     }
+}
 
-    Ok(())
+pub fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{n}{suffix}")
+}
+
+/// Plans a CTE of a WITH RECURSIVE clause that refers to itself.
+///
+/// The CTE must consist of one or more initial SELECTs followed by one or more recursive
+/// SELECTs that refer to the CTE exactly once in their FROM clause, where the recursive
+/// SELECTs are combined with UNION or UNION ALL. In each recursive SELECT, the reference to
+/// the CTE is replaced with a subquery that yields the current row of the CTE.
+///
+/// Returns a plan that describes the result columns of the CTE, and the SELECTs that compute it.
+#[allow(clippy::too_many_arguments)]
+fn prepare_recursive_cte_plan(
+    schema: &Schema,
+    select: ast::Select,
+    name: &str,
+    columns: &[String],
+    syms: &SymbolTable,
+    outer_query_refs: &[OuterQueryReference],
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+    param_ctx: &mut ParamState,
+) -> Result<(SelectPlan, RecursiveCte)> {
+    if !schema.indexes_enabled {
+        crate::bail_parse_error!("Recursive CTEs are not supported without indexes");
+    }
+    let ast::Select {
+        with,
+        body,
+        order_by,
+        limit,
+    } = select;
+    if with.is_some() {
+        crate::bail_parse_error!("WITH is not supported in recursive CTEs yet");
+    }
+
+    let mut selects = vec![body.select];
+    let mut operators = vec![];
+    for compound in body.compounds {
+        operators.push(compound.operator);
+        selects.push(compound.select);
+    }
+    let references: Vec<(usize, bool)> = selects
+        .iter()
+        .map(|select| count_from_clause_references(select, name))
+        .collect();
+    if references.iter().any(|(count, _)| *count > 1) {
+        crate::bail_parse_error!("multiple references to recursive table: {}", name);
+    }
+    let num_initial = references
+        .iter()
+        .position(|(count, _)| *count > 0)
+        .unwrap_or(0);
+    let is_well_formed = num_initial > 0
+        && references.iter().all(|(_, in_subquery)| !in_subquery)
+        && references[num_initial..]
+            .iter()
+            .all(|(count, _)| *count == 1)
+        && matches!(
+            operators[num_initial - 1],
+            ast::CompoundOperator::Union | ast::CompoundOperator::UnionAll
+        )
+        && operators[num_initial..]
+            .iter()
+            .all(|operator| *operator == operators[num_initial - 1]);
+    if !is_well_formed {
+        crate::bail_parse_error!("circular reference: {}", name);
+    }
+    let distinct = operators[num_initial - 1] == ast::CompoundOperator::Union;
+    // The initial rows go through the queue, so UNION between the initial SELECTs is only
+    // honored when the queue itself discards duplicates.
+    if let Some(operator) = operators[..num_initial - 1].iter().find(|operator| {
+        **operator != ast::CompoundOperator::UnionAll
+            && (!distinct || **operator != ast::CompoundOperator::Union)
+    }) {
+        crate::bail_parse_error!(
+            "{} is not supported between the initial SELECTs of a recursive CTE",
+            operator
+        );
+    }
+
+    let plan_one_select =
+        |select: ast::OneSelect, table_ref_counter: &mut TableRefIdCounter| -> Result<SelectPlan> {
+            let plan = prepare_select_plan(
+                schema,
+                ast::Select {
+                    with: None,
+                    body: ast::SelectBody {
+                        select,
+                        compounds: vec![],
+                    },
+                    order_by: vec![],
+                    limit: None,
+                },
+                syms,
+                outer_query_refs,
+                table_ref_counter,
+                QueryDestination::placeholder_for_subquery(),
+                connection,
+            )?;
+            let Plan::Select(plan) = plan else {
+                unreachable!("a single SELECT is not planned as a compound SELECT");
+            };
+            Ok(plan)
+        };
+
+    let recursive_selects = selects.split_off(num_initial);
+    let mut initial = Vec::with_capacity(selects.len());
+    for select in selects {
+        initial.push(plan_one_select(select, table_ref_counter)?);
+    }
+    let num_columns = initial[0].result_columns.len();
+    let initial_names: Vec<Option<String>> = initial[0]
+        .result_columns
+        .iter()
+        .map(|result_column| {
+            result_column
+                .name(&initial[0].table_references)
+                .map(String::from)
+        })
+        .collect();
+    let names = if columns.is_empty() {
+        initial_names.clone()
+    } else {
+        if columns.len() != num_columns {
+            crate::bail_parse_error!(
+                "table {} has {} values for {} columns",
+                name,
+                num_columns,
+                columns.len()
+            );
+        }
+        columns.iter().cloned().map(Some).collect()
+    };
+
+    let mut recursive = Vec::with_capacity(recursive_selects.len());
+    for mut select in recursive_selects {
+        let ast::OneSelect::Select {
+            from: Some(from), ..
+        } = &mut select
+        else {
+            unreachable!("a recursive SELECT refers to the CTE in its FROM clause");
+        };
+        let mut identifier = None;
+        for table in std::iter::once(&mut from.select)
+            .chain(from.joins.iter_mut().map(|join| &mut join.table))
+        {
+            if let ast::SelectTable::Table(qualified_name, alias, _) = table.as_mut() {
+                if qualified_name.db_name.is_none()
+                    && normalize_ident(qualified_name.name.as_str()) == name
+                {
+                    let alias = alias
+                        .take()
+                        .unwrap_or_else(|| As::As(ast::Name::Ident(name.to_string())));
+                    identifier = Some(normalize_ident(match &alias {
+                        As::As(id) | As::Elided(id) => id.as_str(),
+                    }));
+                    **table = ast::SelectTable::Select(placeholder_select(&names), Some(alias));
+                }
+            }
+        }
+        let plan = plan_one_select(select, table_ref_counter)?;
+        let cte_reference = plan
+            .joined_tables()
+            .iter()
+            .find(|table| {
+                Some(&table.identifier) == identifier.as_ref()
+                    && matches!(table.table, Table::FromClauseSubquery(_))
+            })
+            .map(|table| table.internal_id)
+            .expect("recursive SELECT must refer to the CTE");
+        recursive.push((plan, cte_reference));
+    }
+
+    for (plan, operator) in initial.iter().skip(1).zip(operators.iter()).chain(
+        recursive
+            .iter()
+            .map(|(plan, _)| plan)
+            .zip(operators[num_initial - 1..].iter()),
+    ) {
+        if plan.result_columns.len() != num_columns {
+            crate::bail_parse_error!(
+                "SELECTs to the left and right of {} do not have the same number of result columns",
+                operator
+            );
+        }
+    }
+
+    // The ORDER BY terms of a recursive CTE must refer to its result columns.
+    let order_by = order_by
+        .into_iter()
+        .enumerate()
+        .map(|(i, term)| {
+            let column = match term.expr.as_ref() {
+                Expr::Literal(ast::Literal::Numeric(n)) => match n.parse::<usize>() {
+                    Ok(n) if (1..=num_columns).contains(&n) => n - 1,
+                    _ => crate::bail_parse_error!(
+                        "{} ORDER BY term out of range - should be between 1 and {}",
+                        ordinal(i + 1),
+                        num_columns
+                    ),
+                },
+                Expr::Id(id) => {
+                    let id = normalize_ident(id.as_str());
+                    match initial_names.iter().position(|name| {
+                        name.as_ref()
+                            .is_some_and(|name| normalize_ident(name) == id)
+                    }) {
+                        Some(column) => column,
+                        None => crate::bail_parse_error!(
+                            "{} ORDER BY term does not match any column in the result set",
+                            ordinal(i + 1)
+                        ),
+                    }
+                }
+                _ => crate::bail_parse_error!(
+                    "{} ORDER BY term does not match any column in the result set",
+                    ordinal(i + 1)
+                ),
+            };
+            Ok((column, term.order.unwrap_or(SortOrder::Asc)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let (limit, offset) = limit.map_or(Ok((None, None)), |mut limit| {
+        parse_limit(&mut limit, connection, param_ctx)
+    })?;
+
+    let cte_plan = plan_one_select(placeholder_select(&names).body.select, table_ref_counter)?;
+    Ok((
+        cte_plan,
+        RecursiveCte {
+            initial,
+            recursive,
+            distinct,
+            order_by,
+            limit,
+            offset,
+        },
+    ))
 }
 
 pub fn parse_where(
@@ -707,6 +1361,7 @@ pub fn parse_where(
 pub fn determine_where_to_eval_term(
     term: &WhereTerm,
     join_order: &[JoinOrderMember],
+    subqueries: &[NonFromClauseSubquery],
 ) -> Result<EvalAt> {
     if let Some(table_id) = term.from_outer_join {
         return Ok(EvalAt::Loop(
@@ -717,7 +1372,7 @@ pub fn determine_where_to_eval_term(
         ));
     }
 
-    determine_where_to_eval_expr(&term.expr, join_order)
+    determine_where_to_eval_expr(&term.expr, join_order, subqueries)
 }
 
 /// A bitmask representing a set of tables in a query plan.
@@ -820,6 +1475,7 @@ impl TableMask {
 pub fn table_mask_from_expr(
     top_level_expr: &Expr,
     table_references: &TableReferences,
+    subqueries: &[NonFromClauseSubquery],
 ) -> Result<TableMask> {
     let mut mask = TableMask::new();
     walk_expr(top_level_expr, &mut |expr: &Expr| -> Result<WalkControl> {
@@ -841,6 +1497,22 @@ pub fn table_mask_from_expr(
                     crate::bail_parse_error!("table not found in joined_tables");
                 }
             }
+            // A correlated subquery depends on the tables of this query that it references.
+            Expr::SubqueryResult { subquery_id, .. } => {
+                let subquery = subqueries
+                    .iter()
+                    .find(|s| s.internal_id == *subquery_id)
+                    .expect("subquery not found");
+                for table_id in subquery.correlated_tables.iter() {
+                    if let Some(table_idx) = table_references
+                        .joined_tables()
+                        .iter()
+                        .position(|t| t.internal_id == *table_id)
+                    {
+                        mask.add_table(table_idx);
+                    }
+                }
+            }
             _ => {}
         }
         Ok(WalkControl::Continue)
@@ -852,16 +1524,24 @@ pub fn table_mask_from_expr(
 pub fn determine_where_to_eval_expr(
     top_level_expr: &Expr,
     join_order: &[JoinOrderMember],
+    subqueries: &[NonFromClauseSubquery],
 ) -> Result<EvalAt> {
     let mut eval_at: EvalAt = EvalAt::BeforeLoop;
     walk_expr(top_level_expr, &mut |expr: &Expr| -> Result<WalkControl> {
         match expr {
             Expr::Column { table, .. } | Expr::RowId { table, .. } => {
-                let join_idx = join_order
+                // A table that is not part of the join order belongs to an outer query, so its
+                // columns are constant while this query runs.
+                if let Some(join_idx) = join_order.iter().position(|t| t.table_id == *table) {
+                    eval_at = eval_at.max(EvalAt::Loop(join_idx));
+                }
+            }
+            Expr::SubqueryResult { subquery_id, .. } => {
+                let subquery = subqueries
                     .iter()
-                    .position(|t| t.table_id == *table)
-                    .unwrap_or(usize::MAX);
-                eval_at = eval_at.max(EvalAt::Loop(join_idx));
+                    .find(|s| s.internal_id == *subquery_id)
+                    .expect("subquery not found");
+                eval_at = eval_at.max(subquery.eval_at(join_order));
             }
             _ => {}
         }
@@ -901,21 +1581,55 @@ fn parse_join(
         connection,
     )?;
 
-    let (outer, natural) = match join_operator {
+    let (outer, right, natural) = match join_operator {
         ast::JoinOperator::TypedJoin(Some(join_type)) => {
-            if join_type.contains(JoinType::RIGHT) {
-                crate::bail_parse_error!("RIGHT JOIN is not supported");
-            }
             if join_type.contains(JoinType::CROSS) {
                 crate::bail_parse_error!("CROSS JOIN is not supported");
             }
-            let is_outer = join_type.contains(JoinType::OUTER);
+            // FULL OUTER JOIN is both a LEFT and a RIGHT JOIN.
+            let is_right = join_type.contains(JoinType::RIGHT);
+            let is_outer = join_type.contains(JoinType::OUTER)
+                && (!is_right || join_type.contains(JoinType::LEFT));
             let is_natural = join_type.contains(JoinType::NATURAL);
-            (is_outer, is_natural)
+            (is_outer, is_right, is_natural)
         }
-        _ => (false, false),
+        _ => (false, false, false),
     };
 
+    if right {
+        let rightmost_table = table_references.joined_tables().last().unwrap();
+        if !matches!(rightmost_table.table, Table::BTree(_)) {
+            crate::bail_parse_error!(
+                "RIGHT and FULL OUTER JOIN are only supported with a table on the right side"
+            );
+        }
+        // The tables to the left of a RIGHT JOIN are on the NULL side of an outer join, so the
+        // conditions of the joins between them must not filter out the rows that only have
+        // NULLs for them. Like the ON clause of a LEFT JOIN, they are evaluated at the loop of
+        // the rightmost table they reference, which is the last one of their join since the
+        // order of these tables is kept, or else together with the ON clause of this join.
+        let right_table_id = rightmost_table.internal_id;
+        for term in out_where_clause
+            .iter_mut()
+            .filter(|term| term.from_outer_join.is_none())
+        {
+            let mut rightmost_referenced = None;
+            walk_expr(&term.expr, &mut |expr: &Expr| -> Result<WalkControl> {
+                if let Expr::Column { table, .. } | Expr::RowId { table, .. } = expr {
+                    let position = table_references
+                        .joined_tables()
+                        .iter()
+                        .position(|t| t.internal_id == *table);
+                    rightmost_referenced = rightmost_referenced.max(position);
+                }
+                Ok(WalkControl::Continue)
+            })?;
+            term.from_outer_join = Some(rightmost_referenced.map_or(right_table_id, |idx| {
+                table_references.joined_tables()[idx].internal_id
+            }));
+        }
+    }
+
     if natural && constraint.is_some() {
         crate::bail_parse_error!("NATURAL JOIN cannot be combined with ON or USING clause");
     }
@@ -965,7 +1679,7 @@ fn parse_join(
                 let start_idx = out_where_clause.len();
                 break_predicate_at_and_boundaries(expr, out_where_clause);
                 for predicate in out_where_clause[start_idx..].iter_mut() {
-                    predicate.from_outer_join = if outer {
+                    predicate.from_outer_join = if outer || right {
                         Some(table_references.joined_tables().last().unwrap().internal_id)
                     } else {
                         None
@@ -1052,7 +1766,7 @@ fn parse_join(
                     right_table.mark_column_used(right_col_idx);
                     out_where_clause.push(WhereTerm {
                         expr,
-                        from_outer_join: if outer {
+                        from_outer_join: if outer || right {
                             Some(right_table.internal_id)
                         } else {
                             None
@@ -1071,7 +1785,12 @@ fn parse_join(
         .joined_tables_mut()
         .get_mut(last_idx)
         .unwrap();
-    rightmost_table.join_info = Some(JoinInfo { outer, using });
+    rightmost_table.join_info = Some(JoinInfo {
+        outer,
+        right,
+        using,
+        semi: false,
+    });
 
     Ok(())
 }
@@ -7,6 +7,8 @@ use turso_macros::match_ignore_ascii_case;
 use turso_parser::ast::{self, ColumnDefinition, Expr, Literal, Name};
 use turso_parser::ast::{PragmaName, QualifiedName};
 
+use super::analyze::{database_targets, emit_analyze, AnalyzeTarget};
+use super::fkey::translate_foreign_key_check;
 use super::integrity_check::translate_integrity_check;
 use crate::pragma::pragma_for;
 use crate::schema::Schema;
@@ -60,9 +62,15 @@ pub fn translate_pragma(
     };
 
     let (mut program, mode) = match body {
+        None if pragma == PragmaName::Optimize => {
+            translate_optimize(schema, syms, None, &connection, program)?
+        }
         None => query_pragma(pragma, schema, None, pager, connection, program)?,
         Some(ast::PragmaBody::Equals(value) | ast::PragmaBody::Call(value)) => match pragma {
-            PragmaName::TableInfo => {
+            PragmaName::TableInfo
+            | PragmaName::TableXinfo
+            | PragmaName::ForeignKeyList
+            | PragmaName::ForeignKeyCheck => {
                 query_pragma(pragma, schema, Some(*value), pager, connection, program)?
             }
             _ => update_pragma(pragma, schema, syms, *value, pager, connection, program)?,
@@ -94,6 +102,14 @@ fn update_pragma(
     mut program: ProgramBuilder,
 ) -> crate::Result<(ProgramBuilder, TransactionMode)> {
     match pragma {
+        PragmaName::AnalysisLimit => query_pragma(
+            PragmaName::AnalysisLimit,
+            schema,
+            Some(value),
+            pager,
+            connection,
+            program,
+        ),
         PragmaName::ApplicationId => {
             let data = parse_signed_number(&value)?;
             let app_id_value = match data {
@@ -209,7 +225,10 @@ fn update_pragma(
             program.emit_insn(Insn::Noop {});
             Ok((program, TransactionMode::None))
         }
-        PragmaName::TableInfo => {
+        PragmaName::TableInfo
+        | PragmaName::TableXinfo
+        | PragmaName::ForeignKeyList
+        | PragmaName::ForeignKeyCheck => {
             // because we need control over the write parameter for the transaction,
             // this should be unreachable. We have to force-call query_pragma before
             // getting here
@@ -294,7 +313,7 @@ fn update_pragma(
                             name: ast::Name::new(table),
                             alias: None,
                         },
-                        false,
+                        0,
                         true, // if_not_exists
                         ast::CreateTableBody::ColumnsAndConstraints {
                             columns: turso_cdc_table_columns(),
@@ -320,6 +339,30 @@ fn update_pragma(
             connection,
             program,
         ),
+        PragmaName::RecursiveTriggers => query_pragma(
+            PragmaName::RecursiveTriggers,
+            schema,
+            Some(value),
+            pager,
+            connection,
+            program,
+        ),
+        PragmaName::ForeignKeys => query_pragma(
+            PragmaName::ForeignKeys,
+            schema,
+            Some(value),
+            pager,
+            connection,
+            program,
+        ),
+        PragmaName::IgnoreCheckConstraints => query_pragma(
+            PragmaName::IgnoreCheckConstraints,
+            schema,
+            Some(value),
+            pager,
+            connection,
+            program,
+        ),
         PragmaName::FreelistCount => query_pragma(
             PragmaName::FreelistCount,
             schema,
@@ -328,6 +371,7 @@ fn update_pragma(
             connection,
             program,
         ),
+        PragmaName::Optimize => translate_optimize(schema, syms, Some(value), &connection, program),
         PragmaName::EncryptionKey => {
             let value = parse_string(&value)?;
             let key = EncryptionKey::from_hex_string(&value)?;
@@ -361,6 +405,26 @@ fn update_pragma(
             connection.set_sync_mode(mode);
             Ok((program, TransactionMode::None))
         }
+        PragmaName::TempStore => {
+            use crate::TempStore;
+
+            let value = match value {
+                Expr::Name(name) => normalize_ident(name.as_str()),
+                Expr::Literal(Literal::Numeric(n)) => n,
+                _ => String::new(),
+            };
+            // Like SQLite, a leading digit selects the setting and unknown values reset it.
+            let temp_store = match value.as_bytes().first() {
+                Some(b'1') => TempStore::File,
+                Some(b'2') => TempStore::Memory,
+                Some(b'0') => TempStore::Default,
+                _ if value == "file" => TempStore::File,
+                _ if value == "memory" => TempStore::Memory,
+                _ => TempStore::Default,
+            };
+            connection.set_temp_store(temp_store)?;
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::DataSyncRetry => {
             let retry_enabled = match value {
                 Expr::Name(name) => {
@@ -380,6 +444,51 @@ fn update_pragma(
     }
 }
 
+/// `PRAGMA optimize` runs ANALYZE on the tables of the main database that have indexes but no
+/// statistics, or on all the tables if the 0x10000 bit of the mask is set. ANALYZE is only run if
+/// the 0x02 bit is set, which it is by default.
+fn translate_optimize(
+    schema: &Schema,
+    syms: &SymbolTable,
+    mask: Option<ast::Expr>,
+    connection: &Arc<crate::Connection>,
+    mut program: ProgramBuilder,
+) -> crate::Result<(ProgramBuilder, TransactionMode)> {
+    let mask = match mask {
+        Some(value) => match parse_signed_number(&value)? {
+            Value::Integer(mask) => mask,
+            Value::Float(mask) => mask as i64,
+            _ => bail_parse_error!("Invalid value for PRAGMA optimize"),
+        },
+        None => 0xfffe,
+    };
+    if mask & 0x02 == 0 {
+        return Ok((program, TransactionMode::None));
+    }
+    let all_tables = mask & 0x10000 != 0;
+    let targets: Vec<AnalyzeTarget> = database_targets(schema)
+        .into_iter()
+        .filter(|target| {
+            schema.table_stats(&target.table.name).is_none()
+                && (all_tables
+                    || !target.table.has_rowid
+                    || schema.get_indices(&target.table.name).next().is_some())
+        })
+        .collect();
+    if !targets.is_empty() {
+        emit_analyze(
+            &mut program,
+            schema,
+            syms,
+            0,
+            &targets,
+            false,
+            connection.get_analysis_limit(),
+        )?;
+    }
+    Ok((program, TransactionMode::None))
+}
+
 fn query_pragma(
     pragma: PragmaName,
     schema: &Schema,
@@ -390,6 +499,23 @@ fn query_pragma(
 ) -> crate::Result<(ProgramBuilder, TransactionMode)> {
     let register = program.alloc_register();
     match pragma {
+        PragmaName::AnalysisLimit => {
+            if let Some(value) = value {
+                let limit = match parse_signed_number(&value)? {
+                    Value::Integer(limit) => limit,
+                    Value::Float(limit) => limit as i64,
+                    _ => bail_parse_error!("Invalid value for PRAGMA analysis_limit"),
+                };
+                // Like SQLite, negative values leave the limit unchanged.
+                if limit >= 0 {
+                    connection.set_analysis_limit(limit);
+                }
+            }
+            program.emit_int(connection.get_analysis_limit(), register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::ApplicationId => {
             program.emit_insn(Insn::ReadCookie {
                 db: 0,
@@ -460,6 +586,7 @@ fn query_pragma(
             Ok((program, TransactionMode::None))
         }
         PragmaName::LegacyFileFormat => Ok((program, TransactionMode::None)),
+        PragmaName::Optimize => unreachable!("optimize is translated by translate_optimize"),
         PragmaName::WalCheckpoint => {
             // Checkpoint uses 3 registers: P1, P2, P3. Ref Insn::Checkpoint for more info.
             // Allocate two more here as one was allocated at the top.
@@ -513,28 +640,46 @@ fn query_pragma(
             program.add_pragma_result_column(pragma.to_string());
             Ok((program, TransactionMode::Read))
         }
-        PragmaName::TableInfo => {
+        PragmaName::TableInfo | PragmaName::TableXinfo => {
+            let extended = matches!(pragma, PragmaName::TableXinfo);
             let name = match value {
                 Some(ast::Expr::Name(name)) => Some(normalize_ident(name.as_str())),
                 _ => None,
             };
 
             let base_reg = register;
-            program.alloc_registers(5);
+            program.alloc_registers(5 + extended as usize);
             if let Some(name) = name {
+                // TEMP tables hide the tables of the main database.
+                let temp_schema;
+                let qualified_name = QualifiedName::single(ast::Name::new(&name));
+                let schema = if connection.resolve_database_id(&qualified_name)? == 1 {
+                    temp_schema = connection.get_database_schema(1)?;
+                    temp_schema.as_ref()
+                } else {
+                    schema
+                };
                 if let Some(table) = schema.get_table(&name) {
-                    emit_columns_for_table_info(&mut program, table.columns(), base_reg);
+                    emit_columns_for_table_info(&mut program, table.columns(), base_reg, extended);
                 } else if let Some(view_mutex) = schema.get_materialized_view(&name) {
                     let view = view_mutex.lock().unwrap();
                     let flat_columns = view.column_schema.flat_columns();
-                    emit_columns_for_table_info(&mut program, &flat_columns, base_reg);
+                    emit_columns_for_table_info(&mut program, &flat_columns, base_reg, extended);
                 } else if let Some(view) = schema.get_view(&name) {
-                    emit_columns_for_table_info(&mut program, &view.columns, base_reg);
+                    emit_columns_for_table_info(&mut program, &view.columns, base_reg, extended);
                 }
             }
-            let col_names = ["cid", "name", "type", "notnull", "dflt_value", "pk"];
-            for name in col_names {
-                program.add_pragma_result_column(name.into());
+            let col_names = [
+                "cid",
+                "name",
+                "type",
+                "notnull",
+                "dflt_value",
+                "pk",
+                "hidden",
+            ];
+            for name in &col_names[..6 + extended as usize] {
+                program.add_pragma_result_column(name.to_string());
             }
             Ok((program, TransactionMode::None))
         }
@@ -608,22 +753,7 @@ fn query_pragma(
         }
         PragmaName::QueryOnly => {
             if let Some(value_expr) = value {
-                let is_query_only = match value_expr {
-                    ast::Expr::Literal(Literal::Numeric(i)) => i.parse::<i64>().unwrap() != 0,
-                    ast::Expr::Literal(Literal::String(ref s))
-                    | ast::Expr::Name(Name::Ident(ref s)) => {
-                        let s = s.as_bytes();
-                        match_ignore_ascii_case!(match s {
-                            b"1" | b"on" | b"true" => true,
-                            _ => false,
-                        })
-                    }
-                    _ => {
-                        return Err(LimboError::ParseError(format!(
-                            "Invalid value for PRAGMA query_only: {value_expr:?}"
-                        )));
-                    }
-                };
+                let is_query_only = parse_pragma_bool(&value_expr, "query_only")?;
                 connection.set_query_only(is_query_only);
                 return Ok((program, TransactionMode::None));
             };
@@ -636,6 +766,96 @@ fn query_pragma(
 
             Ok((program, TransactionMode::None))
         }
+        PragmaName::RecursiveTriggers => {
+            if let Some(value_expr) = value {
+                let recursive_triggers = parse_pragma_bool(&value_expr, "recursive_triggers")?;
+                connection.set_recursive_triggers(recursive_triggers);
+                return Ok((program, TransactionMode::None));
+            };
+
+            let register = program.alloc_register();
+            let recursive_triggers = connection.get_recursive_triggers();
+            program.emit_int(recursive_triggers as i64, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::ForeignKeys => {
+            if let Some(value_expr) = value {
+                let foreign_keys = parse_pragma_bool(&value_expr, "foreign_keys")?;
+                // Like in SQLite, foreign key enforcement cannot be changed in a transaction.
+                if connection.get_auto_commit() {
+                    connection.set_foreign_keys(foreign_keys);
+                }
+                return Ok((program, TransactionMode::None));
+            };
+
+            let register = program.alloc_register();
+            let foreign_keys = connection.get_foreign_keys();
+            program.emit_int(foreign_keys as i64, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::IgnoreCheckConstraints => {
+            if let Some(value_expr) = value {
+                let ignore_check_constraints =
+                    parse_pragma_bool(&value_expr, "ignore_check_constraints")?;
+                connection.set_ignore_check_constraints(ignore_check_constraints);
+                return Ok((program, TransactionMode::None));
+            };
+
+            let register = program.alloc_register();
+            let ignore_check_constraints = connection.get_ignore_check_constraints();
+            program.emit_int(ignore_check_constraints as i64, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::ForeignKeyList => {
+            let name = match value {
+                Some(ast::Expr::Name(name)) => Some(normalize_ident(name.as_str())),
+                _ => None,
+            };
+
+            let base_reg = program.alloc_registers(8);
+            if let Some(table) = name.and_then(|name| schema.get_btree_table(&name)) {
+                // Foreign keys are numbered from the last one declared.
+                for (id, fk) in table.foreign_keys.iter().rev().enumerate() {
+                    for (seq, from) in fk.child_columns.iter().enumerate() {
+                        program.emit_int(id as i64, base_reg);
+                        program.emit_int(seq as i64, base_reg + 1);
+                        program.emit_string8(fk.parent_table_str.clone(), base_reg + 2);
+                        program.emit_string8(from.clone(), base_reg + 3);
+                        match fk.parent_columns.get(seq) {
+                            Some(to) => program.emit_string8(to.clone(), base_reg + 4),
+                            None => program.emit_null(base_reg + 4, None),
+                        }
+                        program.emit_string8(fk.on_update.to_string(), base_reg + 5);
+                        program.emit_string8(fk.on_delete.to_string(), base_reg + 6);
+                        program.emit_string8("NONE".to_string(), base_reg + 7);
+                        program.emit_result_row(base_reg, 8);
+                    }
+                }
+            }
+            let pragma = pragma_for(&pragma);
+            for col_name in pragma.columns.iter() {
+                program.add_pragma_result_column(col_name.to_string());
+            }
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::ForeignKeyCheck => {
+            let name = match value {
+                Some(ast::Expr::Name(name)) => Some(normalize_ident(name.as_str())),
+                _ => None,
+            };
+            translate_foreign_key_check(schema, name.as_deref(), &mut program)?;
+            let pragma = pragma_for(&pragma);
+            for col_name in pragma.columns.iter() {
+                program.add_pragma_result_column(col_name.to_string());
+            }
+            Ok((program, TransactionMode::Read))
+        }
         PragmaName::FreelistCount => {
             let value = pager.freepage_list();
             let register = program.alloc_register();
@@ -667,6 +887,14 @@ fn query_pragma(
             }
             Ok((program, TransactionMode::None))
         }
+        PragmaName::TempStore => {
+            let temp_store = connection.get_temp_store();
+            let register = program.alloc_register();
+            program.emit_int(temp_store as i64, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::Synchronous => {
             let mode = connection.get_sync_mode();
             let register = program.alloc_register();
@@ -686,17 +914,42 @@ fn query_pragma(
     }
 }
 
-/// Helper function to emit column information for PRAGMA table_info
-/// Used by both tables and views since they now have the same column emission logic
+/// Parses the value of a boolean pragma, e.g. `PRAGMA foreign_keys = ON`. Numbers are true
+/// unless they are zero.
+fn parse_pragma_bool(value: &Expr, pragma: &str) -> crate::Result<bool> {
+    match value {
+        Expr::Literal(Literal::Numeric(_)) | Expr::Unary(..) => match parse_signed_number(value)? {
+            Value::Integer(i) => Ok(i != 0),
+            Value::Float(f) => Ok(f != 0.0),
+            _ => bail_parse_error!("Invalid value for PRAGMA {pragma}: {value:?}"),
+        },
+        Expr::Literal(Literal::String(s))
+        | Expr::Literal(Literal::Keyword(s))
+        | Expr::Name(Name::Ident(s)) => Ok(match_ignore_ascii_case!(match s.as_bytes() {
+            b"1" | b"on" | b"true" => true,
+            _ => false,
+        })),
+        _ => bail_parse_error!("Invalid value for PRAGMA {pragma}: {value:?}"),
+    }
+}
+
+/// Helper function to emit column information for PRAGMA table_info, or for PRAGMA table_xinfo
+/// if `extended` is set. Used by both tables and views since they now have the same column
+/// emission logic. Only table_xinfo reports hidden and generated columns, along with a `hidden`
+/// column telling them apart.
 fn emit_columns_for_table_info(
     program: &mut ProgramBuilder,
     columns: &[crate::schema::Column],
     base_reg: usize,
+    extended: bool,
 ) {
     // According to the SQLite documentation: "The 'cid' column should not be taken to
     // mean more than 'rank within the current result set'."
     // Therefore, we enumerate only after filtering out hidden columns.
-    for (i, column) in columns.iter().filter(|col| !col.hidden).enumerate() {
+    let columns = columns
+        .iter()
+        .filter(|col| extended || (!col.hidden && col.generated.is_none()));
+    for (i, column) in columns.enumerate() {
         // cid
         program.emit_int(i as i64, base_reg);
         // name
@@ -721,7 +974,20 @@ fn emit_columns_for_table_info(
         // pk
         program.emit_bool(column.primary_key, base_reg + 5);
 
-        program.emit_result_row(base_reg, 6);
+        if !extended {
+            program.emit_result_row(base_reg, 6);
+            continue;
+        }
+
+        // hidden
+        let hidden = match &column.generated {
+            Some(generated) if generated.stored => 3,
+            Some(_) => 2,
+            None => column.hidden as i64,
+        };
+        program.emit_int(hidden, base_reg + 6);
+
+        program.emit_result_row(base_reg, 7);
     }
 }
 
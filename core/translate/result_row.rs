@@ -11,7 +11,7 @@ use crate::{
 
 use super::{
     emitter::{LimitCtx, Resolver},
-    expr::translate_expr,
+    expr::{translate_expr, translate_expr_no_constant_opt, NoConstantOptReason},
     plan::{Distinctness, QueryDestination, SelectPlan},
 };
 
@@ -47,13 +47,29 @@ pub fn emit_select_result(
             || reg_nonagg_emit_once_flag.is_none()
     }) {
         let reg = start_reg + i;
-        translate_expr(
-            program,
-            Some(&plan.table_references),
-            &rc.expr,
-            reg,
-            resolver,
-        )?;
+        // The SELECTs of a compound SELECT may all write their results to the same registers,
+        // so a constant result column must not be hoisted.
+        if matches!(
+            plan.query_destination,
+            QueryDestination::CoroutineYield { .. } | QueryDestination::EphemeralIndex { .. }
+        ) {
+            translate_expr_no_constant_opt(
+                program,
+                Some(&plan.table_references),
+                &rc.expr,
+                reg,
+                resolver,
+                NoConstantOptReason::RegisterReuse,
+            )?;
+        } else {
+            translate_expr(
+                program,
+                Some(&plan.table_references),
+                &rc.expr,
+                reg,
+                resolver,
+            )?;
+        }
     }
 
     // Handle SELECT DISTINCT deduplication
@@ -143,6 +159,19 @@ pub fn emit_result_row_and_limit(
                 end_offset: BranchOffset::Offset(0),
             });
         }
+        QueryDestination::ExistsSubqueryResult { result_reg } => {
+            program.emit_insn(Insn::Integer {
+                value: 1,
+                dest: *result_reg,
+            });
+        }
+        QueryDestination::ScalarSubqueryResult { result_reg } => {
+            program.emit_insn(Insn::Copy {
+                src_reg: result_columns_start_reg,
+                dst_reg: *result_reg,
+                extra_amount: 0,
+            });
+        }
     }
 
     if plan.limit.is_some() {
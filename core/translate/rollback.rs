@@ -2,7 +2,11 @@ use turso_parser::ast::Name;
 
 use crate::{
     schema::Schema,
-    vdbe::{builder::ProgramBuilder, insn::Insn},
+    translate::transaction::translate_savepoint,
+    vdbe::{
+        builder::ProgramBuilder,
+        insn::{Insn, SavepointOp},
+    },
     Result, SymbolTable,
 };
 
@@ -10,13 +14,14 @@ pub fn translate_rollback(
     _schema: &Schema,
     _syms: &SymbolTable,
     mut program: ProgramBuilder,
-    txn_name: Option<Name>,
+    // Like in SQLite, the name of the transaction is ignored.
+    _txn_name: Option<Name>,
     savepoint_name: Option<Name>,
 ) -> Result<ProgramBuilder> {
-    assert!(
-        txn_name.is_none() && savepoint_name.is_none(),
-        "txn_name and savepoint not supported yet"
-    );
+    if let Some(savepoint_name) = savepoint_name {
+        // ROLLBACK TO keeps the transaction open.
+        return translate_savepoint(SavepointOp::Rollback, savepoint_name, program);
+    }
     program.emit_insn(Insn::AutoCommit {
         auto_commit: true,
         rollback: true,
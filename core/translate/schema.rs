@@ -3,46 +3,48 @@ use std::sync::Arc;
 use crate::ast;
 use crate::ext::VTabImpl;
 use crate::schema::create_table;
+use crate::schema::Affinity;
 use crate::schema::BTreeTable;
 use crate::schema::Column;
 use crate::schema::Schema;
 use crate::schema::Table;
 use crate::schema::Type;
 use crate::storage::pager::CreateBTreeFlags;
+use crate::translate::analyze::emit_clear_stat_rows;
 use crate::translate::emitter::emit_cdc_full_record;
 use crate::translate::emitter::emit_cdc_insns;
 use crate::translate::emitter::prepare_cdc_if_necessary;
 use crate::translate::emitter::OperationMode;
 use crate::translate::emitter::Resolver;
+use crate::translate::expr::get_expr_affinity;
+use crate::translate::plan::{Plan, QueryDestination, SelectPlan};
+use crate::translate::select::{prepare_select_plan, translate_select};
 use crate::translate::ProgramBuilder;
 use crate::translate::ProgramBuilderOpts;
 use crate::util::normalize_ident;
 use crate::util::PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX;
 use crate::vdbe::builder::CursorType;
 use crate::vdbe::insn::Cookie;
-use crate::vdbe::insn::{CmpInsFlags, InsertFlags, Insn};
+use crate::vdbe::insn::{CmpInsFlags, InsertFlags, Insn, RegisterOrLiteral};
 use crate::Connection;
 use crate::SymbolTable;
 use crate::{bail_parse_error, Result};
 
 use turso_ext::VTabKind;
+use turso_parser::lexer::is_keyword;
 
 #[allow(clippy::too_many_arguments)]
 pub fn translate_create_table(
     tbl_name: ast::QualifiedName,
-    temporary: bool,
+    database_id: usize,
     if_not_exists: bool,
     body: ast::CreateTableBody,
     schema: &Schema,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
-    connection: &Connection,
+    connection: &Arc<Connection>,
 ) -> Result<ProgramBuilder> {
     let normalized_tbl_name = normalize_ident(tbl_name.name.as_str());
-    if temporary {
-        bail_parse_error!("TEMPORARY table not supported yet");
-    }
-
     // Check for STRICT mode without experimental flag
     if let ast::CreateTableBody::ColumnsAndConstraints { options, .. } = &body {
         if options.contains(ast::TableOptions::STRICT) && !connection.experimental_strict_enabled()
@@ -65,6 +67,35 @@ pub fn translate_create_table(
         bail_parse_error!("Table {} already exists", normalized_tbl_name);
     }
 
+    // The columns of CREATE TABLE ... AS SELECT are derived from the result columns of the
+    // SELECT, and the table is stored in sqlite_schema with explicit column definitions.
+    let (body, sql, as_select) = match body {
+        ast::CreateTableBody::AsSelect(select) => {
+            let columns = columns_from_select(&select, schema, syms, &mut program, connection)?;
+            let sql = create_table_as_select_to_str(&dequote_ident(&tbl_name.name), &columns);
+            let body = ast::CreateTableBody::ColumnsAndConstraints {
+                columns: columns
+                    .into_iter()
+                    .map(|(name, affinity)| ast::ColumnDefinition {
+                        col_name: ast::Name::new(quote_ident_if_needed(&name)),
+                        col_type: affinity_type_name(affinity).map(|name| ast::Type {
+                            name: name.to_string(),
+                            size: None,
+                        }),
+                        constraints: vec![],
+                    })
+                    .collect(),
+                constraints: vec![],
+                options: ast::TableOptions::NONE,
+            };
+            (body, sql, Some(select))
+        }
+        body => {
+            let sql = create_table_body_to_str(&tbl_name, &body);
+            (body, sql, None)
+        }
+    };
+
     let mut has_autoincrement = false;
     if let ast::CreateTableBody::ColumnsAndConstraints {
         columns,
@@ -107,7 +138,7 @@ pub fn translate_create_table(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
-        db: 0,
+        db: database_id,
     });
     let resolver = Resolver::new(schema, syms);
     let cdc_table = prepare_cdc_if_necessary(&mut program, schema, SQLITE_TABLEID)?;
@@ -116,7 +147,7 @@ pub fn translate_create_table(
         if has_autoincrement && schema.get_table("sqlite_sequence").is_none() {
             let seq_table_root_reg = program.alloc_register();
             program.emit_insn(Insn::CreateBtree {
-                db: 0,
+                db: database_id,
                 root: seq_table_root_reg,
                 flags: CreateBTreeFlags::new_table(),
             });
@@ -138,19 +169,27 @@ pub fn translate_create_table(
             false
         };
 
-    let sql = create_table_body_to_str(&tbl_name, &body);
-
     let parse_schema_label = program.allocate_label();
     // TODO: ReadCookie
     // TODO: If
     // TODO: SetCookie
     // TODO: SetCookie
 
+    // The rows of a WITHOUT ROWID table are stored in an index b-tree keyed on the PRIMARY KEY.
+    let without_rowid = matches!(
+        &body,
+        ast::CreateTableBody::ColumnsAndConstraints { options, .. }
+            if options.contains(ast::TableOptions::WITHOUT_ROWID)
+    );
     let table_root_reg = program.alloc_register();
     program.emit_insn(Insn::CreateBtree {
-        db: 0,
+        db: database_id,
         root: table_root_reg,
-        flags: CreateBTreeFlags::new_table(),
+        flags: if without_rowid {
+            CreateBTreeFlags::new_index()
+        } else {
+            CreateBTreeFlags::new_table()
+        },
     });
 
     // Create an automatic index B-tree if needed
@@ -184,19 +223,33 @@ pub fn translate_create_table(
         }
         for index_reg in index_regs.iter() {
             program.emit_insn(Insn::CreateBtree {
-                db: 0,
+                db: database_id,
                 root: *index_reg,
                 flags: CreateBTreeFlags::new_index(),
             });
         }
     }
 
+    if let Some(select) = as_select {
+        let table = Arc::new(BTreeTable::from_sql(&sql, 0)?);
+        program = emit_create_table_as_select_rows(
+            program,
+            select,
+            table,
+            table_root_reg,
+            database_id,
+            schema,
+            syms,
+            connection,
+        )?;
+    }
+
     let table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
     let sqlite_schema_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
-        db: 0,
+        db: database_id,
     });
 
     let cdc_table = prepare_cdc_if_necessary(&mut program, schema, SQLITE_TABLEID)?;
@@ -238,7 +291,7 @@ pub fn translate_create_table(
     program.resolve_label(parse_schema_label, program.offset());
     // TODO: SetCookie
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
         value: schema.schema_version as i32 + 1,
         p5: 0,
@@ -252,7 +305,7 @@ pub fn translate_create_table(
     }
 
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: database_id,
         where_clause: Some(parse_schema_where_clause),
     });
 
@@ -266,6 +319,7 @@ pub enum SchemaEntryType {
     Table,
     Index,
     View,
+    Trigger,
 }
 
 impl SchemaEntryType {
@@ -274,6 +328,7 @@ impl SchemaEntryType {
             SchemaEntryType::Table => "table",
             SchemaEntryType::Index => "index",
             SchemaEntryType::View => "view",
+            SchemaEntryType::Trigger => "trigger",
         }
     }
 }
@@ -380,7 +435,12 @@ fn collect_autoindexes(
     let mut regs: Vec<usize> = Vec::new();
 
     // include UNIQUE singles, include PK single only if not rowid alias
-    for us in table.unique_sets.iter().filter(|us| us.columns.len() == 1) {
+    // the PRIMARY KEY of a WITHOUT ROWID table is stored in the table b-tree itself
+    for us in table
+        .unique_sets
+        .iter()
+        .filter(|us| us.columns.len() == 1 && (table.has_rowid || !us.is_primary_key))
+    {
         let (col_name, _sort) = us.columns.first().unwrap();
         let Some((_pos, col)) = table.get_column(col_name) else {
             bail_parse_error!("Column {col_name} not found in table {}", table.name);
@@ -398,7 +458,11 @@ fn collect_autoindexes(
         }
     }
 
-    for _us in table.unique_sets.iter().filter(|us| us.columns.len() > 1) {
+    for _us in table
+        .unique_sets
+        .iter()
+        .filter(|us| us.columns.len() > 1 && (table.has_rowid || !us.is_primary_key))
+    {
         regs.push(program.alloc_register());
     }
     if regs.is_empty() {
@@ -409,19 +473,242 @@ fn collect_autoindexes(
 }
 
 fn create_table_body_to_str(tbl_name: &ast::QualifiedName, body: &ast::CreateTableBody) -> String {
-    let mut sql = String::new();
-    sql.push_str(format!("CREATE TABLE {} {}", tbl_name.name.as_str(), body).as_str());
-    match body {
-        ast::CreateTableBody::ColumnsAndConstraints {
-            columns: _,
-            constraints: _,
-            options: _,
-        } => {}
-        ast::CreateTableBody::AsSelect(_select) => todo!("as select not yet supported"),
+    format!("CREATE TABLE {} {}", tbl_name.name.as_str(), body)
+}
+
+/// Derive the column names and affinities of a CREATE TABLE ... AS SELECT from the result
+/// columns of its left-most SELECT, following SQLite: an alias or a referenced column keeps its
+/// name, any other expression is named after its text, and duplicate names get a `:N` suffix.
+fn columns_from_select(
+    select: &ast::Select,
+    schema: &Schema,
+    syms: &SymbolTable,
+    program: &mut ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<Vec<(String, Affinity)>> {
+    // Alias expression result columns with their text so that every result column of the
+    // prepared plan carries a name, and strip the quotes from explicit aliases.
+    let mut select = select.clone();
+    if let ast::OneSelect::Select { columns, .. } = &mut select.body.select {
+        for column in columns.iter_mut() {
+            let ast::ResultColumn::Expr(expr, alias) = column else {
+                continue;
+            };
+            match alias {
+                Some(ast::As::As(name) | ast::As::Elided(name)) => {
+                    *name = ast::Name::Ident(dequote_ident(name));
+                }
+                None if !matches!(
+                    expr.as_ref(),
+                    ast::Expr::Id(_) | ast::Expr::Qualified(..) | ast::Expr::DoublyQualified(..)
+                ) =>
+                {
+                    *alias = Some(ast::As::As(ast::Name::Ident(expr.to_string())));
+                }
+                None => {}
+            }
+        }
+    }
+    let plan = prepare_select_plan(
+        schema,
+        select,
+        syms,
+        &[],
+        &mut program.table_reference_counter,
+        QueryDestination::ResultRows,
+        connection,
+    )?;
+    let (first, rest) = match &plan {
+        Plan::Select(plan) => (plan, vec![]),
+        Plan::CompoundSelect {
+            left, right_most, ..
+        } => (
+            &left[0].0,
+            left[1..]
+                .iter()
+                .map(|(plan, _)| plan)
+                .chain(std::iter::once(right_most))
+                .collect(),
+        ),
+        Plan::Delete(_) | Plan::Update(_) => unreachable!("expected a SELECT plan"),
+    };
+    let affinity_of = |plan: &SelectPlan, i: usize| {
+        get_expr_affinity(&plan.result_columns[i].expr, Some(&plan.table_references))
+    };
+
+    let mut columns: Vec<(String, Affinity)> = Vec::with_capacity(first.result_columns.len());
+    for (i, result_column) in first.result_columns.iter().enumerate() {
+        let mut name = result_column
+            .name(&first.table_references)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("column{}", i + 1));
+        let mut cnt = 0;
+        while columns.iter().any(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+            let base = name
+                .trim_end_matches(|c: char| c.is_ascii_digit())
+                .strip_suffix(':')
+                .unwrap_or(&name);
+            cnt += 1;
+            name = format!("{base}:{cnt}");
+        }
+        // A column of a compound SELECT only keeps its affinity if every SELECT agrees on it.
+        let affinity = affinity_of(first, i);
+        let affinity = if rest.iter().all(|plan| affinity_of(plan, i) == affinity) {
+            affinity
+        } else {
+            Affinity::Blob
+        };
+        columns.push((name, affinity));
     }
+    Ok(columns)
+}
+
+pub(crate) fn dequote_ident(name: &ast::Name) -> String {
+    match name {
+        ast::Name::Quoted(name) => {
+            let quote = &name[..1];
+            let escaped = if quote == "[" { "]]" } else { &quote.repeat(2) };
+            name[1..name.len() - 1].replace(escaped, &escaped[..1])
+        }
+        ast::Name::Ident(name) => name.clone(),
+    }
+}
+
+/// The declared type SQLite gives a CREATE TABLE ... AS SELECT column of the given affinity.
+fn affinity_type_name(affinity: Affinity) -> Option<&'static str> {
+    match affinity {
+        Affinity::Text => Some("TEXT"),
+        Affinity::Numeric => Some("NUM"),
+        Affinity::Integer => Some("INT"),
+        Affinity::Real => Some("REAL"),
+        Affinity::Blob => None,
+    }
+}
+
+fn quote_ident_if_needed(name: &str) -> String {
+    let needs_quote = name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        || is_keyword(name.as_bytes());
+    if needs_quote {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    } else {
+        name.to_string()
+    }
+}
+
+/// Build the SQL stored in sqlite_schema for a CREATE TABLE ... AS SELECT, formatted the same
+/// way SQLite formats it.
+fn create_table_as_select_to_str(tbl_name: &str, columns: &[(String, Affinity)]) -> String {
+    let ident_len = |name: &str| name.len() + name.matches('"').count() + 2;
+    let len = ident_len(tbl_name)
+        + columns
+            .iter()
+            .map(|(name, _)| ident_len(name) + 5)
+            .sum::<usize>();
+    let (start, sep, end) = if len < 50 {
+        ("", ",", ")")
+    } else {
+        ("\n  ", ",\n  ", "\n)")
+    };
+    let mut sql = format!("CREATE TABLE {}({start}", quote_ident_if_needed(tbl_name));
+    for (i, (name, affinity)) in columns.iter().enumerate() {
+        if i > 0 {
+            sql.push_str(sep);
+        }
+        sql.push_str(&quote_ident_if_needed(name));
+        if let Some(ty) = affinity_type_name(*affinity) {
+            sql.push(' ');
+            sql.push_str(ty);
+        }
+    }
+    sql.push_str(end);
     sql
 }
 
+/// Run the SELECT of a CREATE TABLE ... AS SELECT as a coroutine and insert every row it
+/// yields into the newly created table.
+#[allow(clippy::too_many_arguments)]
+fn emit_create_table_as_select_rows(
+    mut program: ProgramBuilder,
+    select: ast::Select,
+    table: Arc<BTreeTable>,
+    root_page_reg: usize,
+    database_id: usize,
+    schema: &Schema,
+    syms: &SymbolTable,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    let yield_reg = program.alloc_register();
+    let jump_on_definition_label = program.allocate_label();
+    let start_offset_label = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: jump_on_definition_label,
+        start_offset: start_offset_label,
+    });
+    program.preassign_label_to_next_insn(start_offset_label);
+
+    let query_destination = QueryDestination::CoroutineYield {
+        yield_reg,
+        coroutine_implementation_start: start_offset_label,
+    };
+    program.incr_nesting();
+    let result = translate_select(schema, select, syms, program, query_destination, connection)?;
+    program = result.program;
+    program.decr_nesting();
+
+    program.emit_insn(Insn::EndCoroutine { yield_reg });
+    program.preassign_label_to_next_insn(jump_on_definition_label);
+
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id,
+        root_page: RegisterOrLiteral::Register(root_page_reg),
+        db: database_id,
+    });
+
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    program.preassign_label_to_next_insn(loop_start_label);
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: loop_end_label,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: yield_reg + 1,
+        count: result.num_result_cols,
+        dest_reg: record_reg,
+        index_name: None,
+        affinity_str: Some(
+            table
+                .columns
+                .iter()
+                .map(|col| col.affinity().aff_mask())
+                .collect(),
+        ),
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: InsertFlags::new(),
+        table_name: table.name.clone(),
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+    Ok(program)
+}
+
 fn create_vtable_body_to_str(vtab: &ast::CreateVirtualTable, module: Arc<VTabImpl>) -> String {
     let args = vtab
         .args
@@ -559,7 +846,7 @@ pub fn translate_create_virtual_table(
     });
     let parse_schema_where_clause = format!("tbl_name = '{table_name}' AND type != 'trigger'");
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: 0,
         where_clause: Some(parse_schema_where_clause),
     });
 
@@ -569,6 +856,7 @@ pub fn translate_create_virtual_table(
 pub fn translate_drop_table(
     tbl_name: ast::QualifiedName,
     if_exists: bool,
+    database_id: usize,
     schema: &Schema,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
@@ -617,9 +905,7 @@ pub fn translate_drop_table(
     let table_name_and_root_page_register = program.alloc_register(); //  r2, this register is special because it's first used to track table name and then moved root page
     let table_reg = program.emit_string8_new_reg(tbl_name.name.as_str().to_string()); //  r3
     program.mark_last_insn_constant();
-    let table_type = program.emit_string8_new_reg("trigger".to_string()); //  r4
-    program.mark_last_insn_constant();
-    let row_id_reg = program.alloc_register(); //  r5
+    let row_id_reg = program.alloc_register(); //  r4
 
     let schema_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
     let sqlite_schema_cursor_id_0 = program.alloc_cursor_id(
@@ -629,10 +915,10 @@ pub fn translate_drop_table(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id_0,
         root_page: 1usize.into(),
-        db: 0,
+        db: database_id,
     });
 
-    //  1. Remove all entries from the schema table related to the table we are dropping, except for triggers
+    //  1. Remove all entries from the schema table related to the table we are dropping, including its triggers
     //  loop to beginning of schema table
     let end_metadata_label = program.allocate_label();
     let metadata_loop = program.allocate_label();
@@ -656,24 +942,12 @@ pub fn translate_drop_table(
         flags: CmpInsFlags::default(),
         collation: program.curr_collation(),
     });
-    program.emit_column_or_rowid(
-        sqlite_schema_cursor_id_0,
-        0,
-        table_name_and_root_page_register,
-    );
-    program.emit_insn(Insn::Eq {
-        lhs: table_name_and_root_page_register,
-        rhs: table_type,
-        target_pc: next_label,
-        flags: CmpInsFlags::default(),
-        collation: program.curr_collation(),
-    });
     program.emit_insn(Insn::RowId {
         cursor_id: sqlite_schema_cursor_id_0,
         dest: row_id_reg,
     });
     if let Some((cdc_cursor_id, _)) = cdc_table {
-        let table_type = program.emit_string8_new_reg("table".to_string()); // r4
+        let table_type = program.emit_string8_new_reg("table".to_string());
         program.mark_last_insn_constant();
 
         let skip_cdc_label = program.allocate_label();
@@ -730,7 +1004,7 @@ pub fn translate_drop_table(
         program.emit_insn(Insn::Destroy {
             root: index.root_page,
             former_root_reg: 0, //  no autovacuum (https://www.sqlite.org/opcode.html#Destroy)
-            is_temp: 0,
+            db: database_id,
         });
 
         //  3. TODO: Open an ephemeral table, and read over triggers from schema table into ephemeral table
@@ -746,7 +1020,7 @@ pub fn translate_drop_table(
             program.emit_insn(Insn::Destroy {
                 root: table.root_page,
                 former_root_reg: table_name_and_root_page_register,
-                is_temp: 0,
+                db: database_id,
             });
         }
         Table::Virtual(vtab) => {
@@ -783,6 +1057,8 @@ pub fn translate_drop_table(
             name: "ephemeral_scratch".to_string(),
             has_rowid: true,
             has_autoincrement: false,
+            foreign_keys: vec![],
+            checks: vec![],
             primary_key_columns: vec![],
             columns: vec![Column {
                 name: Some("rowid".to_string()),
@@ -795,6 +1071,7 @@ pub fn translate_drop_table(
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             }],
             is_strict: false,
             unique_sets: vec![],
@@ -814,7 +1091,7 @@ pub fn translate_drop_table(
         program.emit_insn(Insn::OpenRead {
             cursor_id: sqlite_schema_cursor_id_1,
             root_page: 1usize,
-            db: 0,
+            db: database_id,
         });
 
         let schema_column_0_register = program.alloc_register();
@@ -871,7 +1148,7 @@ pub fn translate_drop_table(
         program.emit_insn(Insn::OpenWrite {
             cursor_id: sqlite_schema_cursor_id_1,
             root_page: 1usize.into(),
-            db: 0,
+            db: database_id,
         });
 
         // Loop to copy over row id's from the ephemeral table and then re-insert into the schema table with the correct root page
@@ -945,7 +1222,7 @@ pub fn translate_drop_table(
         program.emit_insn(Insn::OpenWrite {
             cursor_id: seq_cursor_id,
             root_page: seq_table.root_page.into(),
-            db: 0,
+            db: database_id,
         });
 
         let end_loop_label = program.allocate_label();
@@ -983,16 +1260,19 @@ pub fn translate_drop_table(
         program.preassign_label_to_next_insn(end_loop_label);
     }
 
+    // The statistics of the table and of its indexes go with it.
+    emit_clear_stat_rows(&mut program, schema, database_id, 0, table.get_name());
+
     // Drop the in-memory structures for the table
     program.emit_insn(Insn::DropTable {
-        db: 0,
+        db: database_id,
         _p2: 0,
         _p3: 0,
         table_name: tbl_name.name.as_str().to_string(),
     });
 
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
         value: schema.schema_version as i32 + 1,
         p5: 0,
@@ -4,17 +4,19 @@ use super::plan::{
     Search, TableReferences, WhereTerm, Window,
 };
 use crate::schema::Table;
+use crate::translate::collate::CollationSeq;
 use crate::translate::emitter::Resolver;
 use crate::translate::expr::{bind_and_rewrite_expr, BindingBehavior, ParamState};
 use crate::translate::group_by::compute_group_by_sort_order;
 use crate::translate::optimizer::optimize_plan;
 use crate::translate::plan::{GroupBy, Plan, ResultSetColumn, SelectPlan};
 use crate::translate::planner::{
-    break_predicate_at_and_boundaries, parse_from, parse_limit, parse_where,
+    break_predicate_at_and_boundaries, ordinal, parse_from, parse_limit, parse_where,
     resolve_window_and_aggregate_functions,
 };
+use crate::translate::subquery::plan_subqueries_from_plan;
 use crate::translate::window::plan_windows;
-use crate::util::normalize_ident;
+use crate::util::{exprs_are_equivalent, normalize_ident};
 use crate::vdbe::builder::{ProgramBuilderOpts, TableRefIdCounter};
 use crate::vdbe::insn::Insn;
 use crate::{schema::Schema, vdbe::builder::ProgramBuilder, Result};
@@ -45,6 +47,7 @@ pub fn translate_select(
         query_destination,
         connection,
     )?;
+    plan_subqueries_from_plan(&mut program, &mut select_plan, schema, syms, connection)?;
     optimize_plan(&mut select_plan, schema)?;
     let num_result_cols;
     let opts = match &select_plan {
@@ -117,12 +120,14 @@ pub fn prepare_select_plan(
             &mut param_ctx,
         )?)),
         false => {
+            // The WITH clause is visible to every SELECT of the compound.
+            let with = select.with;
             let mut last = prepare_one_select_plan(
                 schema,
                 select.body.select,
                 None,
                 vec![],
-                None,
+                with.clone(),
                 syms,
                 outer_query_refs,
                 table_ref_counter,
@@ -139,7 +144,7 @@ pub fn prepare_select_plan(
                     select,
                     None,
                     vec![],
-                    None,
+                    with.clone(),
                     syms,
                     outer_query_refs,
                     table_ref_counter,
@@ -160,20 +165,26 @@ pub fn prepare_select_plan(
                 parse_limit(&mut l, connection, &mut param_ctx)
             })?;
 
-            // FIXME: handle ORDER BY for compound selects
-            if !select.order_by.is_empty() {
-                crate::bail_parse_error!("ORDER BY is not supported for compound SELECTs yet");
-            }
-            // FIXME: handle WITH for compound selects
-            if select.with.is_some() {
-                crate::bail_parse_error!("WITH is not supported for compound SELECTs yet");
-            }
+            let order_by = if select.order_by.is_empty() {
+                None
+            } else {
+                let selects = left
+                    .iter()
+                    .map(|(plan, _)| plan)
+                    .chain(std::iter::once(&last))
+                    .collect::<Vec<_>>();
+                Some(resolve_compound_order_by(
+                    select.order_by,
+                    &selects,
+                    connection,
+                )?)
+            };
             Ok(Plan::CompoundSelect {
                 left,
                 right_most: last,
                 limit,
                 offset,
-                order_by: None,
+                order_by,
             })
         }
     }
@@ -288,6 +299,7 @@ fn prepare_one_select_plan(
                 distinctness: Distinctness::from_ast(distinctness.as_ref()),
                 values: vec![],
                 window: None,
+                non_from_clause_subqueries: vec![],
             };
 
             let mut windows = Vec::with_capacity(window_clause.len());
@@ -553,6 +565,7 @@ fn prepare_one_select_plan(
                     .map(|values| values.iter().map(|value| *value.clone()).collect())
                     .collect(),
                 window: None,
+                non_from_clause_subqueries: vec![],
             };
 
             Ok(plan)
@@ -586,6 +599,79 @@ fn add_vtab_predicates_to_where_clause(
     Ok(())
 }
 
+/// Resolves the ORDER BY terms of a compound SELECT to the result columns they sort by.
+/// A term is either a result column number, or an expression that matches a result column
+/// of one of the SELECTs, which are tried from left to right. Unless the term has a COLLATE
+/// clause, the column is sorted by the collation of the leftmost SELECT whose result column
+/// is a column reference or has a COLLATE clause.
+fn resolve_compound_order_by(
+    order_by: Vec<ast::SortedColumn>,
+    selects: &[&SelectPlan],
+    connection: &Arc<crate::Connection>,
+) -> Result<Vec<(usize, ast::SortOrder, Option<CollationSeq>)>> {
+    let num_columns = selects[0].result_columns.len();
+    order_by
+        .into_iter()
+        .enumerate()
+        .map(|(i, term)| {
+            let (expr, collation) = match *term.expr {
+                Expr::Collate(expr, collation) => {
+                    (*expr, Some(CollationSeq::new(collation.as_str())?))
+                }
+                expr => (expr, None),
+            };
+            let column = match &expr {
+                Expr::Literal(ast::Literal::Numeric(n)) => match n.parse::<usize>() {
+                    Ok(n) if (1..=num_columns).contains(&n) => Some(n - 1),
+                    _ => crate::bail_parse_error!(
+                        "{} ORDER BY term out of range - should be between 1 and {}",
+                        ordinal(i + 1),
+                        num_columns
+                    ),
+                },
+                _ => selects.iter().find_map(|plan| {
+                    let mut expr = expr.clone();
+                    let mut table_references = plan.table_references.clone();
+                    bind_and_rewrite_expr(
+                        &mut expr,
+                        Some(&mut table_references),
+                        Some(&plan.result_columns),
+                        connection,
+                        &mut ParamState::default(),
+                        BindingBehavior::TryResultColumnsFirst,
+                    )
+                    .ok()?;
+                    plan.result_columns
+                        .iter()
+                        .position(|column| exprs_are_equivalent(&column.expr, &expr))
+                }),
+            };
+            let Some(column) = column else {
+                crate::bail_parse_error!(
+                    "{} ORDER BY term does not match any column in the result set",
+                    ordinal(i + 1)
+                );
+            };
+            let collation = match collation {
+                Some(collation) => Some(collation),
+                None => selects
+                    .iter()
+                    .find_map(|plan| match &plan.result_columns[column].expr {
+                        Expr::Collate(_, collation) => Some(CollationSeq::new(collation.as_str())),
+                        Expr::Column { table, column, .. } => plan
+                            .table_references
+                            .find_table_by_internal_id(*table)
+                            .and_then(|table| table.get_column_at(*column))
+                            .map(|column| Ok(column.collation.unwrap_or_default())),
+                        _ => None,
+                    })
+                    .transpose()?,
+            };
+            Ok((column, term.order.unwrap_or(ast::SortOrder::Asc), collation))
+        })
+        .collect()
+}
+
 /// Replaces a column number in an ORDER BY or GROUP BY expression with a copy of the column expression.
 /// For example, in SELECT u.first_name, count(1) FROM users u GROUP BY 1 ORDER BY 2,
 /// the column number 1 is replaced with u.first_name and the column number 2 is replaced with count(1).
@@ -623,6 +709,18 @@ fn count_plan_required_cursors(plan: &SelectPlan) -> usize {
             }
         } + if let Table::FromClauseSubquery(from_clause_subquery) = &t.table {
             count_plan_required_cursors(&from_clause_subquery.plan)
+                + from_clause_subquery.recursive.as_ref().map_or(0, |recursive_cte| {
+                    2 + recursive_cte
+                        .plans()
+                        .map(count_plan_required_cursors)
+                        .sum::<usize>()
+                })
+                + from_clause_subquery.compound.as_ref().map_or(0, |compound| {
+                    2 + compound
+                        .compound_selects()
+                        .map(count_plan_required_cursors)
+                        .sum::<usize>()
+                })
         } else {
             0
         })
@@ -642,6 +740,18 @@ fn estimate_num_instructions(select: &SelectPlan) -> usize {
             Operation::Search(_) => 15,
         } + if let Table::FromClauseSubquery(from_clause_subquery) = &t.table {
             10 + estimate_num_instructions(&from_clause_subquery.plan)
+                + from_clause_subquery.recursive.as_ref().map_or(0, |recursive_cte| {
+                    30 + recursive_cte
+                        .plans()
+                        .map(|plan| 10 + estimate_num_instructions(plan))
+                        .sum::<usize>()
+                })
+                + from_clause_subquery.compound.as_ref().map_or(0, |compound| {
+                    20 + compound
+                        .compound_selects()
+                        .map(|plan| 10 + estimate_num_instructions(plan))
+                        .sum::<usize>()
+                })
         } else {
             0
         })
@@ -665,6 +775,18 @@ fn estimate_num_labels(select: &SelectPlan) -> usize {
             Operation::Search(_) => 3,
         } + if let Table::FromClauseSubquery(from_clause_subquery) = &t.table {
             3 + estimate_num_labels(&from_clause_subquery.plan)
+                + from_clause_subquery.recursive.as_ref().map_or(0, |recursive_cte| {
+                    4 + recursive_cte
+                        .plans()
+                        .map(|plan| 4 + estimate_num_labels(plan))
+                        .sum::<usize>()
+                })
+                + from_clause_subquery.compound.as_ref().map_or(0, |compound| {
+                    4 + compound
+                        .compound_selects()
+                        .map(|plan| 4 + estimate_num_labels(plan))
+                        .sum::<usize>()
+                })
         } else {
             0
         })
@@ -1,14 +1,29 @@
+use std::sync::Arc;
+
+use turso_parser::ast::{self, Expr, SortOrder, SubqueryType, TableInternalId};
+
 use crate::{
     emit_explain,
-    schema::Table,
-    vdbe::{builder::ProgramBuilder, insn::Insn},
-    QueryMode, Result,
+    schema::{FromClauseSubquery, Index, IndexColumn, Schema, Table},
+    vdbe::{
+        builder::{CursorType, ProgramBuilder},
+        insn::{IdxInsertFlags, Insn},
+        BranchOffset, CursorID,
+    },
+    Connection, QueryMode, Result, SymbolTable,
 };
 
 use super::{
+    compound_select::emit_compound_select_rows,
     emitter::{emit_query, Resolver, TranslateCtx},
+    expr::{translate_expr, walk_expr, walk_expr_mut, WalkControl},
     main_loop::LoopLabels,
-    plan::{Operation, QueryDestination, Search, SelectPlan, TableReferences},
+    plan::{
+        ColumnUsedMask, NonFromClauseSubquery, Operation, OuterQueryReference, Plan,
+        QueryDestination, Search, SelectPlan, TableReferences,
+    },
+    result_row::try_fold_expr_to_i64,
+    select::prepare_select_plan,
 };
 
 /// Emit the subqueries contained in the FROM clause.
@@ -45,6 +60,14 @@ pub fn emit_subqueries(
                             table_reference.identifier
                         )
                     }
+                    Search::Seek {
+                        index: Some(index), ..
+                    } if table_reference
+                        .btree()
+                        .is_some_and(|btree| index.is_without_rowid_primary_key_of(&btree)) =>
+                    {
+                        format!("SEARCH {} USING PRIMARY KEY", table_reference.identifier)
+                    }
                     Search::Seek {
                         index: Some(index), ..
                     } => {
@@ -59,8 +82,13 @@ pub fn emit_subqueries(
 
         if let Table::FromClauseSubquery(from_clause_subquery) = &mut table_reference.table {
             // Emit the subquery and get the start register of the result columns.
-            let result_columns_start =
-                emit_subquery(program, &mut from_clause_subquery.plan, t_ctx)?;
+            let result_columns_start = if from_clause_subquery.recursive.is_some() {
+                emit_recursive_cte(program, from_clause_subquery, t_ctx)?
+            } else if from_clause_subquery.compound.is_some() {
+                emit_compound_subquery(program, from_clause_subquery, t_ctx)?
+            } else {
+                emit_subquery(program, &mut from_clause_subquery.plan, t_ctx)?
+            };
             // Set the start register of the subquery's result columns.
             // This is done so that translate_expr() can read the result columns of the subquery,
             // as if it were reading from a regular table.
@@ -112,6 +140,7 @@ pub fn emit_subquery(
         label_main_loop_end: None,
         meta_group_by: None,
         meta_left_joins: (0..plan.joined_tables().len()).map(|_| None).collect(),
+        meta_right_joins: (0..plan.joined_tables().len()).map(|_| None).collect(),
         meta_sort: None,
         reg_agg_start: None,
         reg_nonagg_emit_once_flag: None,
@@ -137,3 +166,854 @@ pub fn emit_subquery(
     program.preassign_label_to_next_insn(subquery_body_end_label);
     Ok(result_column_start_reg)
 }
+
+/// Emit a compound SELECT in a FROM clause and return the start register of its columns.
+/// Like [emit_subquery], this emits a coroutine, in which every SELECT of the compound yields
+/// its rows in the same registers.
+fn emit_compound_subquery(
+    program: &mut ProgramBuilder,
+    subquery: &mut FromClauseSubquery,
+    t_ctx: &mut TranslateCtx,
+) -> Result<usize> {
+    let mut compound = subquery
+        .compound
+        .as_deref()
+        .cloned()
+        .expect("emit_compound_subquery called on non-compound subquery");
+    let yield_reg = program.alloc_register();
+    // The rows are yielded in the registers that directly follow the yield register.
+    let result_column_start_reg = program.alloc_registers(subquery.columns.len());
+    let coroutine_implementation_start_offset = program.allocate_label();
+    match &mut subquery.plan.query_destination {
+        QueryDestination::CoroutineYield {
+            yield_reg: y,
+            coroutine_implementation_start,
+        } => {
+            *y = yield_reg;
+            *coroutine_implementation_start = coroutine_implementation_start_offset;
+        }
+        _ => unreachable!("emit_compound_subquery called on non-subquery"),
+    }
+    for plan in compound.compound_selects_mut() {
+        plan.query_destination = subquery.plan.query_destination.clone();
+    }
+    let subquery_body_end_label = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: subquery_body_end_label,
+        start_offset: coroutine_implementation_start_offset,
+    });
+    program.preassign_label_to_next_insn(coroutine_implementation_start_offset);
+    emit_compound_select_rows(
+        program,
+        compound,
+        t_ctx.resolver.schema,
+        t_ctx.resolver.symbol_table,
+        Some(result_column_start_reg),
+    )?;
+    program.emit_insn(Insn::EndCoroutine { yield_reg });
+    program.preassign_label_to_next_insn(subquery_body_end_label);
+    Ok(result_column_start_reg)
+}
+
+/// The queue of a recursive CTE: an ephemeral index whose key is made of the ORDER BY columns,
+/// a sequence number and the row, so that rows are popped off in ORDER BY order, and otherwise
+/// in the order they were pushed. With UNION, a second ephemeral index holds every row ever
+/// pushed, so that no row is pushed twice.
+struct RecursiveCteQueue {
+    cursor_id: CursorID,
+    index_name: String,
+    /// The cursor and the name of the index of the rows pushed so far, with UNION.
+    distinct: Option<(CursorID, String)>,
+    /// The result columns that the rows are ordered by.
+    order_by: Vec<usize>,
+    num_columns: usize,
+    reg_sequence: usize,
+    reg_key: usize,
+    reg_record: usize,
+}
+
+impl RecursiveCteQueue {
+    fn key_len(&self) -> usize {
+        self.order_by.len() + 1 + self.num_columns
+    }
+
+    /// Emit the instructions to push the row in the registers starting at `reg_row`.
+    fn emit_push(&self, program: &mut ProgramBuilder, reg_row: usize) {
+        let label_skip = program.allocate_label();
+        if let Some((cursor_id, index_name)) = &self.distinct {
+            program.emit_insn(Insn::Found {
+                cursor_id: *cursor_id,
+                target_pc: label_skip,
+                record_reg: reg_row,
+                num_regs: self.num_columns,
+            });
+            program.emit_insn(Insn::MakeRecord {
+                start_reg: reg_row,
+                count: self.num_columns,
+                dest_reg: self.reg_record,
+                index_name: Some(index_name.clone()),
+                affinity_str: None,
+            });
+            program.emit_insn(Insn::IdxInsert {
+                cursor_id: *cursor_id,
+                record_reg: self.reg_record,
+                unpacked_start: None,
+                unpacked_count: None,
+                flags: IdxInsertFlags::new(),
+            });
+        }
+        for (i, column) in self.order_by.iter().enumerate() {
+            program.emit_insn(Insn::Copy {
+                src_reg: reg_row + column,
+                dst_reg: self.reg_key + i,
+                extra_amount: 0,
+            });
+        }
+        program.emit_insn(Insn::AddImm {
+            register: self.reg_sequence,
+            value: 1,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: self.reg_sequence,
+            dst_reg: self.reg_key + self.order_by.len(),
+            extra_amount: 0,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_row,
+            dst_reg: self.reg_key + self.order_by.len() + 1,
+            extra_amount: self.num_columns - 1,
+        });
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: self.reg_key,
+            count: self.key_len(),
+            dest_reg: self.reg_record,
+            index_name: Some(self.index_name.clone()),
+            affinity_str: None,
+        });
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id: self.cursor_id,
+            record_reg: self.reg_record,
+            unpacked_start: None,
+            unpacked_count: None,
+            flags: IdxInsertFlags::new(),
+        });
+        program.preassign_label_to_next_insn(label_skip);
+    }
+
+    /// Emit a SELECT of the recursive CTE and push every row it returns.
+    fn emit_push_select(
+        &self,
+        program: &mut ProgramBuilder,
+        plan: &mut SelectPlan,
+        t_ctx: &mut TranslateCtx,
+    ) -> Result<()> {
+        let reg_row = emit_subquery(program, plan, t_ctx)?;
+        let QueryDestination::CoroutineYield { yield_reg, .. } = plan.query_destination else {
+            unreachable!("recursive CTE SELECT with non-subquery query type");
+        };
+        let label_loop_start = program.allocate_label();
+        let label_loop_end = program.allocate_label();
+        program.preassign_label_to_next_insn(label_loop_start);
+        program.emit_insn(Insn::Yield {
+            yield_reg,
+            end_offset: label_loop_end,
+        });
+        self.emit_push(program, reg_row);
+        program.emit_insn(Insn::Goto {
+            target_pc: label_loop_start,
+        });
+        program.preassign_label_to_next_insn(label_loop_end);
+        Ok(())
+    }
+}
+
+/// Emit the LIMIT or OFFSET value of a recursive CTE into a new register.
+#[allow(clippy::borrowed_box)]
+fn emit_limit_value(
+    program: &mut ProgramBuilder,
+    expr: &Box<Expr>,
+    resolver: &Resolver,
+) -> Result<usize> {
+    let reg = program.alloc_register();
+    if let Some(value) = try_fold_expr_to_i64(expr) {
+        program.emit_insn(Insn::Integer { value, dest: reg });
+    } else {
+        translate_expr(program, None, expr, reg, resolver)?;
+        program.emit_insn(Insn::MustBeInt { reg });
+    }
+    Ok(reg)
+}
+
+/// Emit a recursive CTE and return the start register of its columns.
+///
+/// Like [emit_subquery], this emits a coroutine that yields the rows of the CTE. The rows of the
+/// initial SELECTs are pushed onto a [RecursiveCteQueue]. Then, until the queue is empty or the
+/// LIMIT is reached, a row is popped off the queue and yielded, and the recursive SELECTs are run
+/// with their reference to the CTE yielding that row, pushing their rows onto the queue.
+fn emit_recursive_cte(
+    program: &mut ProgramBuilder,
+    subquery: &mut FromClauseSubquery,
+    t_ctx: &mut TranslateCtx,
+) -> Result<usize> {
+    let recursive_cte = subquery
+        .recursive
+        .as_mut()
+        .expect("emit_recursive_cte called on non-recursive subquery");
+    let yield_reg = program.alloc_register();
+    let coroutine_implementation_start_offset = program.allocate_label();
+    match &mut subquery.plan.query_destination {
+        QueryDestination::CoroutineYield {
+            yield_reg: y,
+            coroutine_implementation_start,
+        } => {
+            *y = yield_reg;
+            *coroutine_implementation_start = coroutine_implementation_start_offset;
+        }
+        _ => unreachable!("emit_recursive_cte called on non-subquery"),
+    }
+    let subquery_body_end_label = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: subquery_body_end_label,
+        start_offset: coroutine_implementation_start_offset,
+    });
+    program.preassign_label_to_next_insn(coroutine_implementation_start_offset);
+
+    let num_columns = subquery.columns.len();
+    let column_names: Vec<String> = subquery
+        .columns
+        .iter()
+        .map(|column| column.name.clone().unwrap_or_default())
+        .collect();
+    let index_column = |name: &str, order: SortOrder| IndexColumn {
+        name: name.to_string(),
+        order,
+        pos_in_table: 0,
+        collation: None, // FIXME: this should be inferred
+        default: None,
+        expr: None,
+    };
+    let ephemeral_index = |name: String, columns: Vec<IndexColumn>| {
+        Arc::new(Index {
+            name,
+            table_name: String::new(),
+            root_page: 0,
+            columns,
+            unique: false,
+            ephemeral: true,
+            has_rowid: false,
+            primary_key_suffix: vec![],
+            where_clause: None,
+        })
+    };
+
+    let queue_index = ephemeral_index(
+        format!("{}_queue", subquery.name),
+        recursive_cte
+            .order_by
+            .iter()
+            .map(|(column, order)| index_column(&column_names[*column], *order))
+            .chain(std::iter::once(index_column("sequence", SortOrder::Asc)))
+            .chain(
+                column_names
+                    .iter()
+                    .map(|name| index_column(name, SortOrder::Asc)),
+            )
+            .collect(),
+    );
+    let queue_cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(queue_index.clone()));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id: queue_cursor_id,
+        is_table: false,
+    });
+    let distinct = if recursive_cte.distinct {
+        let index = ephemeral_index(
+            format!("{}_distinct", subquery.name),
+            column_names
+                .iter()
+                .map(|name| index_column(name, SortOrder::Asc))
+                .collect(),
+        );
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id,
+            is_table: false,
+        });
+        Some((cursor_id, index.name.clone()))
+    } else {
+        None
+    };
+
+    let queue = RecursiveCteQueue {
+        cursor_id: queue_cursor_id,
+        index_name: queue_index.name.clone(),
+        distinct,
+        order_by: recursive_cte
+            .order_by
+            .iter()
+            .map(|(column, _)| *column)
+            .collect(),
+        num_columns,
+        reg_sequence: program.alloc_register(),
+        reg_key: program.alloc_registers(queue_index.columns.len()),
+        reg_record: program.alloc_register(),
+    };
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: queue.reg_sequence,
+    });
+
+    let label_end = program.allocate_label();
+    let reg_limit = match &recursive_cte.limit {
+        Some(limit) => {
+            let reg = emit_limit_value(program, limit, &t_ctx.resolver)?;
+            program.emit_insn(Insn::IfNot {
+                reg,
+                target_pc: label_end,
+                jump_if_null: false,
+            });
+            Some(reg)
+        }
+        None => None,
+    };
+    let reg_offset = match &recursive_cte.offset {
+        Some(offset) => Some(emit_limit_value(program, offset, &t_ctx.resolver)?),
+        None => None,
+    };
+
+    for plan in recursive_cte.initial.iter_mut() {
+        queue.emit_push_select(program, plan, t_ctx)?;
+    }
+
+    // Pop the next row off the queue into the registers of the current row.
+    let reg_current = program.alloc_registers(num_columns);
+    let reg_popped_key = program.alloc_registers(queue.key_len());
+    let label_loop_start = program.allocate_label();
+    let label_recurse = program.allocate_label();
+    program.preassign_label_to_next_insn(label_loop_start);
+    program.emit_insn(Insn::Rewind {
+        cursor_id: queue.cursor_id,
+        pc_if_empty: label_end,
+    });
+    for i in 0..queue.key_len() {
+        program.emit_insn(Insn::Column {
+            cursor_id: queue.cursor_id,
+            column: i,
+            dest: reg_popped_key + i,
+            default: None,
+        });
+    }
+    program.emit_insn(Insn::IdxDelete {
+        start_reg: reg_popped_key,
+        num_regs: queue.key_len(),
+        cursor_id: queue.cursor_id,
+        raise_error_if_no_matching_entry: false,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_popped_key + queue.order_by.len() + 1,
+        dst_reg: reg_current,
+        extra_amount: num_columns - 1,
+    });
+
+    // Rows skipped by the OFFSET are not yielded, but still recursed into.
+    if let Some(reg) = reg_offset {
+        program.emit_insn(Insn::IfPos {
+            reg,
+            target_pc: label_recurse,
+            decrement_by: 1,
+        });
+    }
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: BranchOffset::Offset(0),
+    });
+    if let Some(reg) = reg_limit {
+        program.emit_insn(Insn::DecrJumpZero {
+            reg,
+            target_pc: label_end,
+        });
+    }
+
+    program.preassign_label_to_next_insn(label_recurse);
+    for (plan, cte_reference) in recursive_cte.recursive.iter_mut() {
+        let cte_table = plan
+            .table_references
+            .find_joined_table_by_internal_id_mut(*cte_reference)
+            .expect("recursive SELECT must refer to the CTE");
+        let Table::FromClauseSubquery(cte_subquery) = &mut cte_table.table else {
+            unreachable!("the reference to a recursive CTE must be a subquery");
+        };
+        for (i, result_column) in cte_subquery.plan.result_columns.iter_mut().enumerate() {
+            result_column.expr = Expr::Register(reg_current + i);
+        }
+        queue.emit_push_select(program, plan, t_ctx)?;
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: label_loop_start,
+    });
+
+    program.preassign_label_to_next_insn(label_end);
+    program.emit_insn(Insn::EndCoroutine { yield_reg });
+    program.preassign_label_to_next_insn(subquery_body_end_label);
+    Ok(reg_current)
+}
+
+/// Returns whether the expression contains a subquery.
+pub fn expr_has_subquery(expr: &Expr) -> bool {
+    let mut has_subquery = false;
+    let _ = walk_expr(expr, &mut |expr: &Expr| -> Result<WalkControl> {
+        has_subquery |= matches!(
+            expr,
+            Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. }
+        );
+        Ok(WalkControl::Continue)
+    });
+    has_subquery
+}
+
+/// Plan the subqueries outside the FROM clause of a statement, e.g. `WHERE x IN (SELECT ...)`
+/// or `SELECT (SELECT max(y) FROM u)`, including those of the subqueries in its FROM clause.
+///
+/// Each of them becomes a [NonFromClauseSubquery] of the [SelectPlan] it appears in, and the
+/// expression is replaced with an [Expr::SubqueryResult] that reads its result.
+pub fn plan_subqueries_from_plan(
+    program: &mut ProgramBuilder,
+    plan: &mut Plan,
+    schema: &Schema,
+    syms: &SymbolTable,
+    connection: &Arc<Connection>,
+) -> Result<()> {
+    match plan {
+        Plan::Select(plan) => {
+            plan_subqueries_from_select_plan(program, plan, schema, syms, connection)
+        }
+        Plan::CompoundSelect {
+            left, right_most, ..
+        } => {
+            for (plan, _) in left.iter_mut() {
+                plan_subqueries_from_select_plan(program, plan, schema, syms, connection)?;
+            }
+            plan_subqueries_from_select_plan(program, right_most, schema, syms, connection)
+        }
+        // The WHERE clause of a DELETE or UPDATE with subqueries is moved to the ephemeral plan
+        // that collects the rows to modify, whose subqueries are planned with it. See
+        // prepare_ephemeral_rowid_plan.
+        Plan::Delete(_) | Plan::Update(_) => Ok(()),
+    }
+}
+
+pub fn plan_subqueries_from_select_plan(
+    program: &mut ProgramBuilder,
+    plan: &mut SelectPlan,
+    schema: &Schema,
+    syms: &SymbolTable,
+    connection: &Arc<Connection>,
+) -> Result<()> {
+    // The subqueries in the FROM clause first, so that the columns of this query they
+    // reference are marked as used before this query is optimized.
+    let mut used_outer_columns = Vec::new();
+    for table in plan.table_references.joined_tables_mut() {
+        if let Table::FromClauseSubquery(from_clause_subquery) = &mut table.table {
+            let mut plans = vec![from_clause_subquery.plan.as_mut()];
+            if let Some(recursive_cte) = &mut from_clause_subquery.recursive {
+                plans.extend(recursive_cte.initial.iter_mut());
+                plans.extend(recursive_cte.recursive.iter_mut().map(|(plan, _)| plan));
+            }
+            if let Some(compound) = &mut from_clause_subquery.compound {
+                plans.extend(compound.compound_selects_mut());
+            }
+            for subplan in plans {
+                plan_subqueries_from_select_plan(program, subplan, schema, syms, connection)?;
+                used_outer_columns.extend(used_outer_query_refs(subplan));
+            }
+        }
+    }
+    mark_outer_columns_used(&mut plan.table_references, used_outer_columns);
+
+    // The tables of this query and of its own outer queries are in scope in its subqueries.
+    let outer_query_refs = plan
+        .table_references
+        .joined_tables()
+        .iter()
+        .map(|t| OuterQueryReference {
+            identifier: t.identifier.clone(),
+            internal_id: t.internal_id,
+            table: t.table.clone(),
+            col_used_mask: ColumnUsedMask::default(),
+        })
+        .chain(
+            plan.table_references
+                .outer_query_refs()
+                .iter()
+                .map(|t| OuterQueryReference {
+                    col_used_mask: ColumnUsedMask::default(),
+                    ..t.clone()
+                }),
+        )
+        .collect::<Vec<_>>();
+    let mut ctx = SubqueryPlanningCtx {
+        program,
+        schema,
+        syms,
+        connection,
+        parent_tables: plan
+            .table_references
+            .joined_tables()
+            .iter()
+            .map(|t| t.internal_id)
+            .collect(),
+        outer_query_refs,
+        subqueries: Vec::new(),
+        planned: Vec::new(),
+    };
+
+    for term in plan.where_clause.iter_mut() {
+        ctx.plan_subqueries_in_expr(&mut term.expr)?;
+    }
+    for result_column in plan.result_columns.iter_mut() {
+        let original_expr = result_column.expr.clone();
+        ctx.plan_subqueries_in_expr(&mut result_column.expr)?;
+        // Keep the name of the column, which would otherwise be derived from the rewritten expression.
+        if result_column.alias.is_none() && result_column.expr != original_expr {
+            result_column.alias = Some(original_expr.to_string());
+        }
+    }
+    if let Some(group_by) = &mut plan.group_by {
+        for expr in group_by.exprs.iter_mut() {
+            ctx.plan_subqueries_in_expr(expr)?;
+        }
+        for expr in group_by.having.iter_mut().flatten() {
+            ctx.plan_subqueries_in_expr(expr)?;
+        }
+    }
+    for (expr, _) in plan.order_by.iter_mut() {
+        ctx.plan_subqueries_in_expr(expr)?;
+    }
+    for aggregate in plan.aggregates.iter_mut() {
+        for input in aggregate.inputs_mut() {
+            ctx.plan_subqueries_in_expr(input)?;
+        }
+        ctx.plan_subqueries_in_expr(&mut aggregate.original_expr)?;
+    }
+
+    let SubqueryPlanningCtx { subqueries, .. } = ctx;
+    if subqueries.is_empty() {
+        return Ok(());
+    }
+    // The subqueries of a query with window functions are pushed down to the subquery the
+    // window reads from (see plan_windows), except IN compared with a window function.
+    if plan.window.is_some() {
+        crate::bail_parse_error!(
+            "IN with a subquery on the value of a window function is not supported"
+        );
+    }
+
+    for subquery in subqueries.iter() {
+        let used_outer_columns = used_outer_query_refs(&subquery.plan);
+        mark_outer_columns_used(&mut plan.table_references, used_outer_columns);
+    }
+    plan.non_from_clause_subqueries = subqueries;
+
+    Ok(())
+}
+
+/// The columns of the outer queries that the given plan references, per table.
+fn used_outer_query_refs(plan: &SelectPlan) -> Vec<(TableInternalId, ColumnUsedMask)> {
+    plan.table_references
+        .outer_query_refs()
+        .iter()
+        .filter(|outer_ref| outer_ref.is_used())
+        .map(|outer_ref| (outer_ref.internal_id, outer_ref.col_used_mask.clone()))
+        .collect()
+}
+
+/// Mark the columns that a subquery references as used in the query it belongs to, so that
+/// they are read from the table cursors and the queries further out know they are referenced.
+fn mark_outer_columns_used(
+    table_references: &mut TableReferences,
+    used_outer_columns: Vec<(TableInternalId, ColumnUsedMask)>,
+) {
+    for (internal_id, col_used_mask) in used_outer_columns {
+        let Some(columns) = table_references
+            .find_table_by_internal_id(internal_id)
+            .map(|table| table.columns().len())
+        else {
+            continue;
+        };
+        for column in (0..columns.min(128)).filter(|column| col_used_mask.get(*column)) {
+            table_references.mark_column_used(internal_id, column);
+        }
+    }
+}
+
+/// The kind of a subquery outside the FROM clause, before it is planned.
+enum SubqueryKind {
+    Exists,
+    Scalar,
+    In { lhs: Box<Expr>, not: bool },
+}
+
+struct SubqueryPlanningCtx<'a> {
+    program: &'a mut ProgramBuilder,
+    schema: &'a Schema,
+    syms: &'a SymbolTable,
+    connection: &'a Arc<Connection>,
+    /// The tables of the query the subqueries belong to.
+    parent_tables: Vec<TableInternalId>,
+    outer_query_refs: Vec<OuterQueryReference>,
+    subqueries: Vec<NonFromClauseSubquery>,
+    /// The subquery expressions planned so far and what they were replaced with, so that the
+    /// copies of an expression (e.g. in the result columns and in the aggregates) read the same
+    /// subquery.
+    planned: Vec<(Expr, Expr)>,
+}
+
+impl SubqueryPlanningCtx<'_> {
+    fn plan_subqueries_in_expr(&mut self, expr: &mut Expr) -> Result<()> {
+        walk_expr_mut(expr, &mut |expr: &mut Expr| -> Result<WalkControl> {
+            if !matches!(
+                expr,
+                Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. }
+            ) {
+                return Ok(WalkControl::Continue);
+            }
+            if let Some((_, planned)) = self.planned.iter().find(|(original, _)| original == expr) {
+                *expr = planned.clone();
+                return Ok(WalkControl::Continue);
+            }
+            let original = expr.clone();
+            let planned = match std::mem::replace(expr, Expr::Literal(ast::Literal::Null)) {
+                Expr::Exists(select) => self.plan_subquery(select, SubqueryKind::Exists)?,
+                Expr::Subquery(select) => self.plan_subquery(select, SubqueryKind::Scalar)?,
+                Expr::InSelect { lhs, not, rhs } => {
+                    self.plan_subquery(rhs, SubqueryKind::In { lhs, not })?
+                }
+                _ => unreachable!(),
+            };
+            self.planned.push((original, planned.clone()));
+            *expr = planned;
+            Ok(WalkControl::Continue)
+        })?;
+        Ok(())
+    }
+
+    /// Plan a single subquery and return the [Expr::SubqueryResult] that replaces it.
+    fn plan_subquery(&mut self, select: ast::Select, kind: SubqueryKind) -> Result<Expr> {
+        let (query_type, query_destination) = match &kind {
+            SubqueryKind::Exists => {
+                let result_reg = self.program.alloc_register();
+                (
+                    SubqueryType::Exists { result_reg },
+                    QueryDestination::ExistsSubqueryResult { result_reg },
+                )
+            }
+            SubqueryKind::Scalar => {
+                let result_reg = self.program.alloc_register();
+                (
+                    SubqueryType::Scalar { result_reg },
+                    QueryDestination::ScalarSubqueryResult { result_reg },
+                )
+            }
+            SubqueryKind::In { .. } => {
+                if !self.schema.indexes_enabled() {
+                    crate::bail_parse_error!(
+                        "IN with a subquery is not allowed without indexes enabled"
+                    );
+                }
+                let index = Arc::new(Index {
+                    name: "ephemeral_in_subquery".to_string(),
+                    table_name: String::new(),
+                    root_page: 0,
+                    columns: vec![IndexColumn {
+                        name: String::new(),
+                        order: SortOrder::Asc,
+                        pos_in_table: 0,
+                        collation: None,
+                        default: None,
+                        expr: None,
+                    }],
+                    unique: false,
+                    ephemeral: true,
+                    has_rowid: false,
+                    primary_key_suffix: vec![],
+                    where_clause: None,
+                });
+                let cursor_id = self
+                    .program
+                    .alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+                (
+                    SubqueryType::In { cursor_id },
+                    QueryDestination::EphemeralIndex {
+                        cursor_id,
+                        index,
+                        is_delete: false,
+                    },
+                )
+            }
+        };
+
+        // A compound SELECT is read as a FROM clause subquery: SELECT * FROM (<compound>).
+        let select = if select.body.compounds.is_empty() {
+            select
+        } else {
+            ast::Select {
+                with: None,
+                body: ast::SelectBody {
+                    select: ast::OneSelect::Select {
+                        distinctness: None,
+                        columns: vec![ast::ResultColumn::Star],
+                        from: Some(ast::FromClause {
+                            select: Box::new(ast::SelectTable::Select(select, None)),
+                            joins: vec![],
+                        }),
+                        where_clause: None,
+                        group_by: None,
+                        window_clause: vec![],
+                    },
+                    compounds: vec![],
+                },
+                order_by: vec![],
+                limit: None,
+            }
+        };
+        let Plan::Select(mut plan) = prepare_select_plan(
+            self.schema,
+            select,
+            self.syms,
+            &self.outer_query_refs,
+            &mut self.program.table_reference_counter,
+            query_destination,
+            self.connection,
+        )?
+        else {
+            unreachable!("a single SELECT is not planned as a compound SELECT");
+        };
+        match kind {
+            SubqueryKind::Exists => {}
+            SubqueryKind::Scalar | SubqueryKind::In { .. } => {
+                if plan.result_columns.len() != 1 {
+                    crate::bail_parse_error!(
+                        "sub-select returns {} columns - expected 1",
+                        plan.result_columns.len()
+                    );
+                }
+            }
+        }
+        // Only the first row of EXISTS and scalar subqueries is needed.
+        if !matches!(kind, SubqueryKind::In { .. }) {
+            plan.limit = match plan.limit.take() {
+                Some(limit) if try_fold_expr_to_i64(&limit) == Some(0) => {
+                    plan.contains_constant_false_condition = true;
+                    None
+                }
+                Some(limit) if try_fold_expr_to_i64(&limit).is_none() => {
+                    Some(Box::new(Expr::Binary(
+                        limit,
+                        ast::Operator::NotEquals,
+                        Box::new(Expr::Literal(ast::Literal::Numeric("0".to_string()))),
+                    )))
+                }
+                _ => Some(Box::new(Expr::Literal(ast::Literal::Numeric(
+                    "1".to_string(),
+                )))),
+            };
+        }
+
+        plan_subqueries_from_select_plan(
+            self.program,
+            &mut plan,
+            self.schema,
+            self.syms,
+            self.connection,
+        )?;
+        let correlated_tables = used_outer_query_refs(&plan)
+            .into_iter()
+            .map(|(internal_id, _)| internal_id)
+            .filter(|internal_id| self.parent_tables.contains(internal_id))
+            .collect();
+
+        let internal_id = self.program.table_reference_counter.next();
+        self.subqueries.push(NonFromClauseSubquery {
+            internal_id,
+            query_type,
+            plan: Box::new(plan),
+            correlated_tables,
+        });
+        let (lhs, not_in) = match kind {
+            SubqueryKind::In { lhs, not } => (Some(lhs), not),
+            SubqueryKind::Exists | SubqueryKind::Scalar => (None, false),
+        };
+        Ok(Expr::SubqueryResult {
+            subquery_id: internal_id,
+            lhs,
+            not_in,
+            query_type,
+        })
+    }
+}
+
+/// Emit a subquery outside the FROM clause, storing its result where its
+/// [Expr::SubqueryResult] reads it from. A subquery that does not reference any outer query
+/// is only evaluated once per statement.
+pub fn emit_non_from_clause_subquery(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    syms: &SymbolTable,
+    subquery: &mut NonFromClauseSubquery,
+) -> Result<()> {
+    let correlated = subquery.is_correlated();
+    let label_skip = program.allocate_label();
+    if !correlated {
+        program.emit_insn(Insn::Once {
+            target_pc_when_reentered: label_skip,
+        });
+    }
+    emit_explain!(
+        program,
+        true,
+        format!(
+            "{}{} SUBQUERY {}",
+            if correlated { "CORRELATED " } else { "" },
+            match subquery.query_type {
+                SubqueryType::In { .. } => "LIST",
+                SubqueryType::Exists { .. } | SubqueryType::Scalar { .. } => "SCALAR",
+            },
+            subquery.internal_id
+        )
+    );
+    match subquery.query_type {
+        SubqueryType::Exists { result_reg } => program.emit_int(0, result_reg),
+        SubqueryType::Scalar { result_reg } => program.emit_null(result_reg, None),
+        SubqueryType::In { cursor_id } => {
+            if correlated {
+                // The index is opened once and emptied before every evaluation.
+                let label_opened = program.allocate_label();
+                program.emit_insn(Insn::Once {
+                    target_pc_when_reentered: label_opened,
+                });
+                program.emit_insn(Insn::OpenEphemeral {
+                    cursor_id,
+                    is_table: false,
+                });
+                program.preassign_label_to_next_insn(label_opened);
+                program.emit_insn(Insn::ResetSorter { cursor_id });
+            } else {
+                program.emit_insn(Insn::OpenEphemeral {
+                    cursor_id,
+                    is_table: false,
+                });
+            }
+        }
+    }
+    let mut t_ctx = TranslateCtx::new(
+        program,
+        schema,
+        syms,
+        subquery.plan.table_references.joined_tables().len(),
+    );
+    emit_query(program, &mut subquery.plan, &mut t_ctx)?;
+    program.pop_current_parent_explain();
+    program.preassign_label_to_next_insn(label_skip);
+    Ok(())
+}
@@ -0,0 +1,1269 @@
+//! Triggers.
+//!
+//! A trigger is stored in sqlite_schema as its CREATE TRIGGER statement and kept in the
+//! [Schema] as AST. Whenever a statement that fires a trigger is translated, the trigger body
+//! is compiled into a separate [Program](crate::vdbe::Program) (a subprogram), which the
+//! firing statement runs once per affected row with the [Insn::Program] instruction.
+//!
+//! The subprogram receives the OLD and NEW images of the row as parameters in its first
+//! registers (see [TriggerRowRegisters]). References to `NEW.x` and `OLD.x` in the trigger
+//! body are rewritten to reads of those registers before the body is translated.
+
+use std::num::NonZeroUsize;
+use std::sync::{Arc, OnceLock};
+
+use turso_parser::ast::{self, Expr, TriggerEvent, TriggerTime};
+
+use crate::schema::{BTreeTable, Column, Schema, Trigger, Type, View};
+use crate::translate::emitter::{OperationMode, Resolver};
+use crate::translate::expr::{translate_expr, walk_expr_mut, WalkControl};
+use crate::translate::fkey::RowImage;
+use crate::translate::plan::QueryDestination;
+use crate::translate::planner::ROWID_STRS;
+use crate::translate::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
+use crate::translate::select::translate_select;
+use crate::translate::translate_inner;
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts};
+use crate::vdbe::insn::{CmpInsFlags, Cookie, InsertFlags, Insn, SubProgram, SubProgramRef};
+use crate::vdbe::{BranchOffset, CursorID};
+use crate::{bail_parse_error, Connection, Result, SymbolTable};
+
+/// Registers holding the OLD and NEW images of the row a trigger fires for, laid out as
+/// `[OLD rowid, OLD columns.., NEW rowid, NEW columns..]`.
+/// The statement firing the trigger fills these registers, and they are copied into the
+/// registers of the trigger program starting at register 1.
+#[derive(Debug, Clone, Copy)]
+pub struct TriggerRowRegisters {
+    start_reg: usize,
+    num_columns: usize,
+}
+
+impl TriggerRowRegisters {
+    pub fn alloc(program: &mut ProgramBuilder, num_columns: usize) -> Self {
+        let start_reg = program.alloc_registers(2 * (num_columns + 1));
+        Self {
+            start_reg,
+            num_columns,
+        }
+    }
+
+    pub fn start_reg(&self) -> usize {
+        self.start_reg
+    }
+
+    pub fn num_registers(&self) -> usize {
+        2 * (self.num_columns + 1)
+    }
+
+    pub fn old_rowid(&self) -> usize {
+        self.start_reg
+    }
+
+    /// First register of the OLD column values, in table column order.
+    pub fn old_columns(&self) -> usize {
+        self.start_reg + 1
+    }
+
+    pub fn new_rowid(&self) -> usize {
+        self.start_reg + self.num_columns + 1
+    }
+
+    /// First register of the NEW column values, in table column order.
+    pub fn new_columns(&self) -> usize {
+        self.new_rowid() + 1
+    }
+
+    pub fn old_image(&self) -> RowImage {
+        RowImage {
+            rowid_reg: self.old_rowid(),
+            columns_start_reg: self.old_columns(),
+        }
+    }
+
+    pub fn new_image(&self) -> RowImage {
+        RowImage {
+            rowid_reg: self.new_rowid(),
+            columns_start_reg: self.new_columns(),
+        }
+    }
+
+    /// Sets the OLD image to NULL, for triggers fired by an INSERT.
+    pub fn emit_null_old(&self, program: &mut ProgramBuilder) {
+        program.emit_insn(Insn::Null {
+            dest: self.old_rowid(),
+            dest_end: Some(self.old_columns() + self.num_columns - 1),
+        });
+    }
+
+    /// Sets the NEW image to NULL, for triggers fired by a DELETE.
+    pub fn emit_null_new(&self, program: &mut ProgramBuilder) {
+        program.emit_insn(Insn::Null {
+            dest: self.new_rowid(),
+            dest_end: Some(self.new_columns() + self.num_columns - 1),
+        });
+    }
+
+    /// Loads the OLD image from the row `cursor_id` is positioned at.
+    pub fn emit_old_from_cursor(&self, program: &mut ProgramBuilder, cursor_id: CursorID) {
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: self.old_rowid(),
+        });
+        for i in 0..self.num_columns {
+            program.emit_column_or_rowid(cursor_id, i, self.old_columns() + i);
+        }
+    }
+
+    /// Loads the NEW image from `rowid_reg` and the registers starting at `columns_start_reg`,
+    /// applying the affinities of `columns`.
+    pub fn emit_new_from_registers(
+        &self,
+        program: &mut ProgramBuilder,
+        rowid_reg: usize,
+        columns_start_reg: usize,
+        columns: &[Column],
+    ) {
+        program.emit_insn(Insn::Copy {
+            src_reg: rowid_reg,
+            dst_reg: self.new_rowid(),
+            extra_amount: 0,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: columns_start_reg,
+            dst_reg: self.new_columns(),
+            extra_amount: self.num_columns - 1,
+        });
+        program.emit_insn(Insn::Affinity {
+            start_reg: self.new_columns(),
+            count: NonZeroUsize::new(self.num_columns).expect("nonzero col count"),
+            affinities: columns
+                .iter()
+                .map(|col| col.affinity().aff_mask())
+                .collect::<String>(),
+        });
+    }
+}
+
+fn trigger_fires(
+    trigger: &Trigger,
+    mode: OperationMode,
+    time: Option<TriggerTime>,
+    updated_columns: Option<&[String]>,
+) -> bool {
+    if time.is_some_and(|time| time != trigger.time) {
+        return false;
+    }
+    match mode {
+        OperationMode::INSERT => matches!(trigger.event, TriggerEvent::Insert),
+        OperationMode::DELETE => matches!(trigger.event, TriggerEvent::Delete),
+        OperationMode::UPDATE => match updated_columns {
+            Some(columns) => trigger.fires_on_update_of(columns),
+            None => matches!(
+                trigger.event,
+                TriggerEvent::Update | TriggerEvent::UpdateOf(_)
+            ),
+        },
+        OperationMode::SELECT => false,
+    }
+}
+
+/// Returns true if `table_name` has triggers that fire for `mode` at `time` (or at any time
+/// if `time` is None). For UPDATE, `updated_columns` restricts the check to triggers that
+/// fire for an update of those columns.
+pub fn has_triggers(
+    schema: &Schema,
+    table_name: &str,
+    mode: OperationMode,
+    time: Option<TriggerTime>,
+    updated_columns: Option<&[String]>,
+) -> bool {
+    schema
+        .get_triggers_for_table(table_name)
+        .any(|trigger| trigger_fires(trigger, mode, time, updated_columns))
+}
+
+/// Emits an [Insn::Program] for every trigger on `table_name` that fires for `mode` at `time`.
+/// `regs` must hold the OLD and NEW images of the current row. If a trigger program executes
+/// RAISE(IGNORE), execution continues at `ignore_label`.
+#[allow(clippy::too_many_arguments)]
+pub fn emit_triggers(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    connection: &Arc<Connection>,
+    table_name: &str,
+    columns: &[Column],
+    mode: OperationMode,
+    time: TriggerTime,
+    updated_columns: Option<&[String]>,
+    regs: TriggerRowRegisters,
+    ignore_label: BranchOffset,
+) -> Result<()> {
+    let triggers = resolver
+        .schema
+        .get_triggers_for_table(table_name)
+        .filter(|trigger| trigger_fires(trigger, mode, Some(time), updated_columns))
+        .cloned()
+        .collect::<Vec<_>>();
+    for trigger in triggers {
+        let Some(sub_program) = compile_trigger(
+            program,
+            resolver,
+            connection,
+            &trigger,
+            columns,
+            mode,
+            connection.get_recursive_triggers(),
+        )?
+        else {
+            continue;
+        };
+        program.emit_insn(Insn::Program {
+            params_start_reg: regs.start_reg(),
+            sub_program,
+            ignore_jump_target: ignore_label,
+        });
+    }
+    Ok(())
+}
+
+/// Emits an [Insn::Program] running `trigger`, which performs a foreign key action on the
+/// child rows of the row in `regs`. Unlike user triggers, these always fire recursively, so
+/// that e.g. ON DELETE CASCADE works on a self-referencing table.
+pub fn emit_fk_action_trigger(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    connection: &Arc<Connection>,
+    trigger: &Trigger,
+    columns: &[Column],
+    mode: OperationMode,
+    regs: TriggerRowRegisters,
+) -> Result<()> {
+    let sub_program = compile_trigger(program, resolver, connection, trigger, columns, mode, true)?
+        .expect("recursive trigger programs are always compiled");
+    let done_label = program.allocate_label();
+    program.emit_insn(Insn::Program {
+        params_start_reg: regs.start_reg(),
+        sub_program,
+        ignore_jump_target: done_label,
+    });
+    program.preassign_label_to_next_insn(done_label);
+    Ok(())
+}
+
+/// Compiles the program of `trigger`. Returns None if the trigger must not fire because it
+/// is already running and `recursive` is false.
+fn compile_trigger(
+    program: &ProgramBuilder,
+    resolver: &Resolver,
+    connection: &Arc<Connection>,
+    trigger: &Trigger,
+    columns: &[Column],
+    mode: OperationMode,
+    recursive: bool,
+) -> Result<Option<SubProgramRef>> {
+    if let Some(slot) = program.trigger_being_compiled(&trigger.name) {
+        if !recursive {
+            return Ok(None);
+        }
+        return Ok(Some(SubProgramRef::Recursive(slot)));
+    }
+
+    let slot = Arc::new(OnceLock::new());
+    let mut sub_program = program.new_trigger_program(
+        trigger.name.clone(),
+        slot.clone(),
+        ProgramBuilderOpts {
+            num_cursors: 1,
+            approx_num_insns: 32,
+            approx_num_labels: 4,
+        },
+    );
+    sub_program.prologue();
+    let regs = TriggerRowRegisters::alloc(&mut sub_program, columns.len());
+    debug_assert_eq!(regs.start_reg(), 1);
+    let row = TriggerRow {
+        columns,
+        regs,
+        has_old: matches!(mode, OperationMode::UPDATE | OperationMode::DELETE),
+        has_new: matches!(mode, OperationMode::UPDATE | OperationMode::INSERT),
+        database: non_main_database(program, connection),
+    };
+
+    let end_label = sub_program.allocate_label();
+    if let Some(when_clause) = &trigger.when_clause {
+        let mut when_clause = when_clause.as_ref().clone();
+        rewrite_expr(&mut when_clause, &row)?;
+        let reg = sub_program.alloc_register();
+        translate_expr(&mut sub_program, None, &when_clause, reg, resolver)?;
+        sub_program.emit_insn(Insn::IfNot {
+            reg,
+            target_pc: end_label,
+            jump_if_null: true,
+        });
+    }
+    for command in trigger.commands.iter() {
+        let mut command = command.clone();
+        rewrite_trigger_cmd(&mut command, &row)?;
+        sub_program = translate_inner(
+            resolver.schema,
+            trigger_cmd_to_stmt(command),
+            resolver.symbol_table,
+            sub_program,
+            connection,
+            &trigger.sql,
+        )?;
+    }
+    sub_program.preassign_label_to_next_insn(end_label);
+    sub_program.epilogue(resolver.schema);
+
+    #[allow(clippy::arc_with_non_send_sync)]
+    let sub_program = Arc::new(SubProgram {
+        trigger_name: trigger.name.clone(),
+        num_params: regs.num_registers(),
+        program: sub_program.build(connection.clone(), false, &trigger.sql),
+    });
+    let _ = slot.set(Arc::downgrade(&sub_program));
+    Ok(Some(SubProgramRef::Owned(sub_program)))
+}
+
+fn trigger_cmd_to_stmt(command: ast::TriggerCmd) -> ast::Stmt {
+    match command {
+        ast::TriggerCmd::Update {
+            or_conflict,
+            tbl_name,
+            sets,
+            from,
+            where_clause,
+        } => ast::Stmt::Update(ast::Update {
+            with: None,
+            or_conflict,
+            tbl_name: ast::QualifiedName::single(tbl_name),
+            indexed: None,
+            sets,
+            from,
+            where_clause,
+            returning: vec![],
+            order_by: vec![],
+            limit: None,
+        }),
+        ast::TriggerCmd::Insert {
+            or_conflict,
+            tbl_name,
+            col_names,
+            select,
+            upsert,
+            returning,
+        } => ast::Stmt::Insert {
+            with: None,
+            or_conflict,
+            tbl_name: ast::QualifiedName::single(tbl_name),
+            columns: col_names,
+            body: ast::InsertBody::Select(select, upsert),
+            returning,
+        },
+        ast::TriggerCmd::Delete {
+            tbl_name,
+            where_clause,
+        } => ast::Stmt::Delete {
+            with: None,
+            tbl_name: ast::QualifiedName::single(tbl_name),
+            indexed: None,
+            where_clause,
+            returning: vec![],
+            order_by: vec![],
+            limit: None,
+        },
+        ast::TriggerCmd::Select(select) => ast::Stmt::Select(select),
+    }
+}
+
+/// The row a trigger program is compiled for, used to resolve `NEW.x` and `OLD.x`.
+struct TriggerRow<'a> {
+    columns: &'a [Column],
+    regs: TriggerRowRegisters,
+    has_old: bool,
+    has_new: bool,
+    /// The database of the trigger if it is not the main one. Unqualified table names are
+    /// looked up in it.
+    database: Option<ast::Name>,
+}
+
+impl TriggerRow<'_> {
+    /// Returns the register holding `qualifier.column_name` if `qualifier` is NEW or OLD.
+    fn resolve(&self, qualifier: &ast::Name, column_name: &ast::Name) -> Result<Option<usize>> {
+        let (rowid_reg, columns_start_reg) = match normalize_ident(qualifier.as_str()).as_str() {
+            "new" if self.has_new => (self.regs.new_rowid(), self.regs.new_columns()),
+            "old" if self.has_old => (self.regs.old_rowid(), self.regs.old_columns()),
+            "new" | "old" => {
+                bail_parse_error!(
+                    "no such column: {}.{}",
+                    qualifier.as_str(),
+                    column_name.as_str()
+                )
+            }
+            _ => return Ok(None),
+        };
+        let name = normalize_ident(column_name.as_str());
+        if let Some((idx, column)) = self.columns.iter().enumerate().find(|(_, column)| {
+            column
+                .name
+                .as_ref()
+                .is_some_and(|n| n.eq_ignore_ascii_case(&name))
+        }) {
+            // The record stores NULL for a rowid alias column, the value is the rowid.
+            if column.is_rowid_alias {
+                return Ok(Some(rowid_reg));
+            }
+            return Ok(Some(columns_start_reg + idx));
+        }
+        if ROWID_STRS.iter().any(|s| s.eq_ignore_ascii_case(&name)) {
+            return Ok(Some(rowid_reg));
+        }
+        bail_parse_error!(
+            "no such column: {}.{}",
+            qualifier.as_str(),
+            column_name.as_str()
+        )
+    }
+}
+
+fn rewrite_trigger_cmd(command: &mut ast::TriggerCmd, row: &TriggerRow) -> Result<()> {
+    match command {
+        ast::TriggerCmd::Update {
+            sets,
+            from,
+            where_clause,
+            ..
+        } => {
+            for set in sets.iter_mut() {
+                rewrite_expr(&mut set.expr, row)?;
+            }
+            if let Some(from) = from {
+                rewrite_from(from, row)?;
+            }
+            if let Some(where_clause) = where_clause {
+                rewrite_expr(where_clause, row)?;
+            }
+        }
+        ast::TriggerCmd::Insert {
+            select,
+            upsert,
+            returning,
+            ..
+        } => {
+            rewrite_select(select, row)?;
+            let mut upsert = upsert.as_deref_mut();
+            while let Some(clause) = upsert {
+                if let Some(index) = &mut clause.index {
+                    for target in index.targets.iter_mut() {
+                        rewrite_expr(&mut target.expr, row)?;
+                    }
+                    if let Some(where_clause) = &mut index.where_clause {
+                        rewrite_expr(where_clause, row)?;
+                    }
+                }
+                if let ast::UpsertDo::Set { sets, where_clause } = &mut clause.do_clause {
+                    for set in sets.iter_mut() {
+                        rewrite_expr(&mut set.expr, row)?;
+                    }
+                    if let Some(where_clause) = where_clause {
+                        rewrite_expr(where_clause, row)?;
+                    }
+                }
+                upsert = clause.next.as_deref_mut();
+            }
+            for column in returning.iter_mut() {
+                if let ast::ResultColumn::Expr(expr, _) = column {
+                    rewrite_expr(expr, row)?;
+                }
+            }
+        }
+        ast::TriggerCmd::Delete { where_clause, .. } => {
+            if let Some(where_clause) = where_clause {
+                rewrite_expr(where_clause, row)?;
+            }
+        }
+        ast::TriggerCmd::Select(select) => rewrite_select(select, row)?,
+    }
+    Ok(())
+}
+
+fn rewrite_expr(expr: &mut Expr, row: &TriggerRow) -> Result<()> {
+    walk_expr_mut(expr, &mut |expr: &mut Expr| -> Result<WalkControl> {
+        match expr {
+            Expr::Qualified(qualifier, column_name) => {
+                if let Some(reg) = row.resolve(qualifier, column_name)? {
+                    *expr = Expr::Register(reg);
+                }
+            }
+            Expr::Exists(select) | Expr::Subquery(select) => rewrite_select(select, row)?,
+            Expr::InSelect { rhs, .. } => rewrite_select(rhs, row)?,
+            _ => {}
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(())
+}
+
+fn rewrite_select(select: &mut ast::Select, row: &TriggerRow) -> Result<()> {
+    if let Some(with) = &mut select.with {
+        for cte in with.ctes.iter_mut() {
+            rewrite_select(&mut cte.select, row)?;
+        }
+    }
+    rewrite_one_select(&mut select.body.select, row)?;
+    for compound in select.body.compounds.iter_mut() {
+        rewrite_one_select(&mut compound.select, row)?;
+    }
+    for sorted_column in select.order_by.iter_mut() {
+        rewrite_expr(&mut sorted_column.expr, row)?;
+    }
+    if let Some(limit) = &mut select.limit {
+        rewrite_expr(&mut limit.expr, row)?;
+        if let Some(offset) = &mut limit.offset {
+            rewrite_expr(offset, row)?;
+        }
+    }
+    Ok(())
+}
+
+fn rewrite_one_select(select: &mut ast::OneSelect, row: &TriggerRow) -> Result<()> {
+    match select {
+        ast::OneSelect::Select {
+            columns,
+            from,
+            where_clause,
+            group_by,
+            ..
+        } => {
+            for column in columns.iter_mut() {
+                if let ast::ResultColumn::Expr(expr, _) = column {
+                    rewrite_expr(expr, row)?;
+                }
+            }
+            if let Some(from) = from {
+                rewrite_from(from, row)?;
+            }
+            if let Some(where_clause) = where_clause {
+                rewrite_expr(where_clause, row)?;
+            }
+            if let Some(group_by) = group_by {
+                for expr in group_by.exprs.iter_mut() {
+                    rewrite_expr(expr, row)?;
+                }
+                if let Some(having) = &mut group_by.having {
+                    rewrite_expr(having, row)?;
+                }
+            }
+        }
+        ast::OneSelect::Values(values) => {
+            for expr in values.iter_mut().flat_map(|v| v.iter_mut()) {
+                rewrite_expr(expr, row)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rewrite_from(from: &mut ast::FromClause, row: &TriggerRow) -> Result<()> {
+    rewrite_select_table(&mut from.select, row)?;
+    for join in from.joins.iter_mut() {
+        rewrite_select_table(&mut join.table, row)?;
+        if let Some(ast::JoinConstraint::On(expr)) = &mut join.constraint {
+            rewrite_expr(expr, row)?;
+        }
+    }
+    Ok(())
+}
+
+fn rewrite_select_table(table: &mut ast::SelectTable, row: &TriggerRow) -> Result<()> {
+    match table {
+        ast::SelectTable::Table(name, ..) => {
+            if name.db_name.is_none() {
+                name.db_name = row.database.clone();
+            }
+        }
+        ast::SelectTable::TableCall(_, args, _) => {
+            for arg in args.iter_mut() {
+                rewrite_expr(arg, row)?;
+            }
+        }
+        ast::SelectTable::Select(select, _) => rewrite_select(select, row)?,
+        ast::SelectTable::Sub(from, _) => rewrite_from(from, row)?,
+    }
+    Ok(())
+}
+
+/// Fires the INSTEAD OF triggers of `view` for every row produced by `select`.
+/// The rows are collected into an ephemeral table first, so that the trigger programs are
+/// free to modify the tables the rows are read from. `fill_row` loads the OLD and NEW images
+/// of the current row from the ephemeral table, given its cursor and number of columns.
+#[allow(clippy::too_many_arguments)]
+fn emit_instead_of_triggers(
+    mut program: ProgramBuilder,
+    resolver: &Resolver,
+    connection: &Arc<Connection>,
+    view: &View,
+    select: ast::Select,
+    mode: OperationMode,
+    updated_columns: Option<&[String]>,
+    fill_row: impl FnOnce(&mut ProgramBuilder, usize, usize, TriggerRowRegisters) -> Result<()>,
+) -> Result<ProgramBuilder> {
+    let halt_label = program.allocate_label();
+    let yield_reg = program.alloc_register();
+    let jump_on_definition_label = program.allocate_label();
+    let start_offset_label = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: jump_on_definition_label,
+        start_offset: start_offset_label,
+    });
+    program.preassign_label_to_next_insn(start_offset_label);
+
+    let query_destination = QueryDestination::CoroutineYield {
+        yield_reg,
+        coroutine_implementation_start: halt_label,
+    };
+    program.incr_nesting();
+    let result = translate_select(
+        resolver.schema,
+        select,
+        resolver.symbol_table,
+        program,
+        query_destination,
+        connection,
+    )?;
+    program = result.program;
+    program.decr_nesting();
+
+    program.emit_insn(Insn::EndCoroutine { yield_reg });
+    program.preassign_label_to_next_insn(jump_on_definition_label);
+
+    let num_columns = result.num_result_cols;
+    let table = Arc::new(BTreeTable {
+        root_page: 0, // Not relevant for ephemeral table definition
+        name: view.name.clone(),
+        has_rowid: true,
+        has_autoincrement: false,
+        foreign_keys: vec![],
+        checks: vec![],
+        primary_key_columns: vec![],
+        columns: (0..num_columns)
+            .map(|_| Column {
+                name: None,
+                ty: Type::Null,
+                ty_str: String::new(),
+                primary_key: false,
+                is_rowid_alias: false,
+                notnull: false,
+                default: None,
+                unique: false,
+                collation: None,
+                hidden: false,
+                generated: None,
+            })
+            .collect(),
+        is_strict: false,
+        unique_sets: vec![],
+    });
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id,
+        is_table: true,
+    });
+
+    let fill_loop_label = program.allocate_label();
+    let fill_done_label = program.allocate_label();
+    program.preassign_label_to_next_insn(fill_loop_label);
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: fill_done_label,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: yield_reg + 1,
+        count: num_columns,
+        dest_reg: record_reg,
+        index_name: None,
+        affinity_str: None,
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: InsertFlags::new().require_seek(),
+        table_name: "".to_string(),
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: fill_loop_label,
+    });
+    program.preassign_label_to_next_insn(fill_done_label);
+
+    let regs = TriggerRowRegisters::alloc(&mut program, view.columns.len());
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    let next_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: loop_end_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+    fill_row(&mut program, cursor_id, num_columns, regs)?;
+    emit_triggers(
+        &mut program,
+        resolver,
+        connection,
+        &view.name,
+        &view.columns,
+        mode,
+        TriggerTime::InsteadOf,
+        updated_columns,
+        regs,
+        next_label,
+    )?;
+    program.preassign_label_to_next_insn(next_label);
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+    program.emit_insn(Insn::Close { cursor_id });
+    program.resolve_label(halt_label, program.offset());
+    Ok(program)
+}
+
+#[allow(clippy::vec_box)]
+fn view_column_exprs(view: &View) -> Vec<Box<Expr>> {
+    view.columns
+        .iter()
+        .map(|column| {
+            Box::new(Expr::Qualified(
+                ast::Name::Ident(view.name.clone()),
+                ast::Name::Ident(column.name.clone().unwrap_or_default()),
+            ))
+        })
+        .collect()
+}
+
+/// The name of the database modified by `program`, unless it is the main database.
+fn non_main_database(program: &ProgramBuilder, connection: &Connection) -> Option<ast::Name> {
+    match program.database_id() {
+        0 => None,
+        database_id => connection
+            .get_database_name_by_index(database_id)
+            .map(ast::Name::new),
+    }
+}
+
+#[allow(clippy::vec_box)]
+fn view_select(
+    view: &View,
+    database: Option<ast::Name>,
+    columns: Vec<Box<Expr>>,
+    where_clause: Option<Box<Expr>>,
+) -> ast::Select {
+    ast::Select {
+        with: None,
+        body: ast::SelectBody {
+            select: ast::OneSelect::Select {
+                distinctness: None,
+                columns: columns
+                    .into_iter()
+                    .map(|expr| ast::ResultColumn::Expr(expr, None))
+                    .collect(),
+                from: Some(ast::FromClause {
+                    select: Box::new(ast::SelectTable::Table(
+                        ast::QualifiedName {
+                            db_name: database,
+                            name: ast::Name::Ident(view.name.clone()),
+                            alias: None,
+                        },
+                        None,
+                        None,
+                    )),
+                    joins: vec![],
+                }),
+                where_clause,
+                group_by: None,
+                window_clause: vec![],
+            },
+            compounds: vec![],
+        },
+        order_by: vec![],
+        limit: None,
+    }
+}
+
+/// Translates an INSERT into a view, which runs the INSTEAD OF INSERT triggers of the view
+/// for every inserted row.
+pub fn translate_insert_into_view(
+    program: ProgramBuilder,
+    resolver: &Resolver,
+    connection: &Arc<Connection>,
+    view: &View,
+    columns: &[ast::Name],
+    body: ast::InsertBody,
+) -> Result<ProgramBuilder> {
+    if !has_triggers(
+        resolver.schema,
+        &view.name,
+        OperationMode::INSERT,
+        Some(TriggerTime::InsteadOf),
+        None,
+    ) {
+        bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
+    let select = match body {
+        ast::InsertBody::Select(_, Some(_)) => {
+            bail_parse_error!("cannot UPSERT a view");
+        }
+        ast::InsertBody::Select(select, None) => select,
+        ast::InsertBody::DefaultValues => ast::Select {
+            with: None,
+            body: ast::SelectBody {
+                select: ast::OneSelect::Values(vec![view
+                    .columns
+                    .iter()
+                    .map(|_| Box::new(Expr::Literal(ast::Literal::Null)))
+                    .collect()]),
+                compounds: vec![],
+            },
+            order_by: vec![],
+            limit: None,
+        },
+    };
+
+    // For each column of the view, the position of its value in the inserted rows.
+    let value_indexes = if columns.is_empty() {
+        (0..view.columns.len()).map(Some).collect::<Vec<_>>()
+    } else {
+        let mut value_indexes = vec![None; view.columns.len()];
+        for (value_index, name) in columns.iter().enumerate() {
+            let name = normalize_ident(name.as_str());
+            let Some(idx) = view.columns.iter().position(|column| {
+                column
+                    .name
+                    .as_ref()
+                    .is_some_and(|n| n.eq_ignore_ascii_case(&name))
+            }) else {
+                bail_parse_error!("table {} has no column named {}", view.name, name);
+            };
+            value_indexes[idx] = Some(value_index);
+        }
+        value_indexes
+    };
+    let num_values = if columns.is_empty() {
+        view.columns.len()
+    } else {
+        columns.len()
+    };
+
+    emit_instead_of_triggers(
+        program,
+        resolver,
+        connection,
+        view,
+        select,
+        OperationMode::INSERT,
+        None,
+        |program, cursor_id, num_columns, regs| {
+            if num_columns != num_values {
+                if columns.is_empty() {
+                    bail_parse_error!(
+                        "table {} has {} columns but {} values were supplied",
+                        view.name,
+                        view.columns.len(),
+                        num_columns
+                    );
+                }
+                bail_parse_error!("{} values for {} columns", num_columns, num_values);
+            }
+            regs.emit_null_old(program);
+            program.emit_null(regs.new_rowid(), None);
+            for (i, value_index) in value_indexes.iter().enumerate() {
+                match value_index {
+                    Some(value_index) => {
+                        program.emit_column_or_rowid(
+                            cursor_id,
+                            *value_index,
+                            regs.new_columns() + i,
+                        );
+                    }
+                    None => program.emit_null(regs.new_columns() + i, None),
+                }
+            }
+            Ok(())
+        },
+    )
+}
+
+/// Translates an UPDATE of a view, which runs the INSTEAD OF UPDATE triggers of the view
+/// for every row of the view matching the WHERE clause.
+pub fn translate_update_of_view(
+    program: ProgramBuilder,
+    resolver: &Resolver,
+    connection: &Arc<Connection>,
+    view: &View,
+    body: ast::Update,
+) -> Result<ProgramBuilder> {
+    let mut new_exprs = view_column_exprs(view);
+    let mut updated_columns = vec![];
+    for set in body.sets {
+        let values = match *set.expr {
+            Expr::Parenthesized(values) => values,
+            expr => vec![Box::new(expr)],
+        };
+        if set.col_names.len() != values.len() {
+            bail_parse_error!(
+                "{} columns assigned {} values",
+                set.col_names.len(),
+                values.len()
+            );
+        }
+        for (name, value) in set.col_names.iter().zip(values) {
+            let name = normalize_ident(name.as_str());
+            let Some(idx) = view.columns.iter().position(|column| {
+                column
+                    .name
+                    .as_ref()
+                    .is_some_and(|n| n.eq_ignore_ascii_case(&name))
+            }) else {
+                bail_parse_error!("no such column: {}", name);
+            };
+            new_exprs[idx] = value;
+            updated_columns.push(name);
+        }
+    }
+    if !has_triggers(
+        resolver.schema,
+        &view.name,
+        OperationMode::UPDATE,
+        Some(TriggerTime::InsteadOf),
+        Some(&updated_columns),
+    ) {
+        bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
+
+    let num_view_columns = view.columns.len();
+    let mut result_columns = view_column_exprs(view);
+    result_columns.extend(new_exprs);
+    let mut select = view_select(
+        view,
+        non_main_database(&program, connection),
+        result_columns,
+        body.where_clause,
+    );
+    select.with = body.with;
+    select.order_by = body.order_by;
+    select.limit = body.limit;
+    // The tables of the FROM clause are joined with the view
+    if let (
+        Some(from),
+        ast::OneSelect::Select {
+            from: Some(view_from),
+            ..
+        },
+    ) = (body.from, &mut select.body.select)
+    {
+        view_from.joins.push(ast::JoinedSelectTable {
+            operator: ast::JoinOperator::Comma,
+            table: from.select,
+            constraint: None,
+        });
+        view_from.joins.extend(from.joins);
+    }
+    emit_instead_of_triggers(
+        program,
+        resolver,
+        connection,
+        view,
+        select,
+        OperationMode::UPDATE,
+        Some(&updated_columns),
+        |program, cursor_id, _, regs| {
+            program.emit_null(regs.old_rowid(), None);
+            program.emit_null(regs.new_rowid(), None);
+            for i in 0..num_view_columns {
+                program.emit_column_or_rowid(cursor_id, i, regs.old_columns() + i);
+                program.emit_column_or_rowid(
+                    cursor_id,
+                    num_view_columns + i,
+                    regs.new_columns() + i,
+                );
+            }
+            Ok(())
+        },
+    )
+}
+
+/// Translates a DELETE from a view, which runs the INSTEAD OF DELETE triggers of the view
+/// for every row of the view matching the WHERE clause.
+#[allow(clippy::too_many_arguments)]
+pub fn translate_delete_from_view(
+    program: ProgramBuilder,
+    resolver: &Resolver,
+    connection: &Arc<Connection>,
+    view: &View,
+    with: Option<ast::With>,
+    where_clause: Option<Box<Expr>>,
+    order_by: Vec<ast::SortedColumn>,
+    limit: Option<ast::Limit>,
+) -> Result<ProgramBuilder> {
+    if !has_triggers(
+        resolver.schema,
+        &view.name,
+        OperationMode::DELETE,
+        Some(TriggerTime::InsteadOf),
+        None,
+    ) {
+        bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
+    let num_view_columns = view.columns.len();
+    let mut select = view_select(
+        view,
+        non_main_database(&program, connection),
+        view_column_exprs(view),
+        where_clause,
+    );
+    select.with = with;
+    select.order_by = order_by;
+    select.limit = limit;
+    emit_instead_of_triggers(
+        program,
+        resolver,
+        connection,
+        view,
+        select,
+        OperationMode::DELETE,
+        None,
+        |program, cursor_id, _, regs| {
+            program.emit_null(regs.old_rowid(), None);
+            for i in 0..num_view_columns {
+                program.emit_column_or_rowid(cursor_id, i, regs.old_columns() + i);
+            }
+            regs.emit_null_new(program);
+            Ok(())
+        },
+    )
+}
+
+pub fn translate_create_trigger(
+    stmt: ast::Stmt,
+    database_id: usize,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    let ast::Stmt::CreateTrigger {
+        temporary,
+        if_not_exists,
+        trigger_name,
+        time,
+        tbl_name,
+        ..
+    } = &stmt
+    else {
+        unreachable!("translate_create_trigger called with {stmt:?}");
+    };
+    if *temporary {
+        bail_parse_error!("TEMP triggers are not supported yet");
+    }
+
+    let normalized_trigger_name = normalize_ident(trigger_name.name.as_str());
+    let normalized_tbl_name = normalize_ident(tbl_name.name.as_str());
+    let database_name = connection
+        .get_database_name_by_index(database_id)
+        .unwrap_or_else(|| "main".to_string());
+    // Like in SQLite, a trigger can only be created on a table of its own database.
+    if tbl_name.db_name.is_some() && connection.resolve_database_id(tbl_name)? != database_id {
+        bail_parse_error!(
+            "trigger {} cannot reference objects in database {}",
+            normalized_trigger_name,
+            normalize_ident(tbl_name.db_name.as_ref().unwrap().as_str())
+        );
+    }
+    if schema.get_trigger(&normalized_trigger_name).is_some() {
+        if *if_not_exists {
+            return Ok(program);
+        }
+        bail_parse_error!("trigger {} already exists", normalized_trigger_name);
+    }
+    if crate::schema::is_system_table(&normalized_tbl_name)
+        || normalized_tbl_name.starts_with("sqlite_")
+    {
+        bail_parse_error!("cannot create trigger on system table");
+    }
+
+    let time = time.unwrap_or(TriggerTime::Before);
+    if schema.get_view(&normalized_tbl_name).is_some() {
+        if time != TriggerTime::InsteadOf {
+            let time = match time {
+                TriggerTime::Before => "BEFORE",
+                _ => "AFTER",
+            };
+            bail_parse_error!(
+                "cannot create {} trigger on view: {}",
+                time,
+                normalized_tbl_name
+            );
+        }
+    } else if schema.is_materialized_view(&normalized_tbl_name) {
+        bail_parse_error!(
+            "cannot create trigger on materialized view: {}",
+            normalized_tbl_name
+        );
+    } else if let Some(table) = schema.get_table(&normalized_tbl_name) {
+        if table.virtual_table().is_some() {
+            bail_parse_error!("cannot create triggers on virtual tables");
+        }
+        if time == TriggerTime::InsteadOf {
+            bail_parse_error!(
+                "cannot create INSTEAD OF trigger on table: {}",
+                normalized_tbl_name
+            );
+        }
+    } else {
+        bail_parse_error!("no such table: {}.{}", database_name, normalized_tbl_name);
+    }
+
+    // Like in SQLite, the schema stores the trigger without database names.
+    let mut stored_stmt = stmt.clone();
+    if let ast::Stmt::CreateTrigger {
+        trigger_name,
+        tbl_name,
+        ..
+    } = &mut stored_stmt
+    {
+        trigger_name.db_name = None;
+        tbl_name.db_name = None;
+    }
+    let sql = stored_stmt.to_string();
+
+    let table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: 1usize.into(),
+        db: database_id,
+    });
+
+    let resolver = Resolver::new(schema, syms);
+    emit_schema_entry(
+        &mut program,
+        &resolver,
+        sqlite_schema_cursor_id,
+        None, // cdc_table_cursor_id, no cdc for triggers
+        SchemaEntryType::Trigger,
+        &normalized_trigger_name,
+        &normalized_tbl_name,
+        0, // triggers don't have a btree
+        Some(sql),
+    )?;
+
+    program.emit_insn(Insn::ParseSchema {
+        db: database_id,
+        where_clause: Some(format!(
+            "type = 'trigger' AND name = '{normalized_trigger_name}'"
+        )),
+    });
+
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: (schema.schema_version + 1) as i32,
+        p5: 0,
+    });
+
+    Ok(program)
+}
+
+pub fn translate_drop_trigger(
+    schema: &Schema,
+    database_id: usize,
+    trigger_name: &str,
+    if_exists: bool,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    let normalized_trigger_name = normalize_ident(trigger_name);
+    if schema.get_trigger(&normalized_trigger_name).is_none() {
+        if if_exists {
+            return Ok(program);
+        }
+        bail_parse_error!("no such trigger: {}", normalized_trigger_name);
+    }
+
+    let schema_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id =
+        program.alloc_cursor_id(CursorType::BTreeTable(schema_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: 1usize.into(),
+        db: database_id,
+    });
+
+    let trigger_name_reg = program.emit_string8_new_reg(normalized_trigger_name.clone());
+    let type_reg = program.emit_string8_new_reg("trigger".to_string());
+
+    let end_loop_label = program.allocate_label();
+    let loop_start_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_empty: end_loop_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+
+    // Column 0 is type, Column 1 is name
+    let col0_reg = program.alloc_register();
+    let col1_reg = program.alloc_register();
+    program.emit_column_or_rowid(sqlite_schema_cursor_id, 0, col0_reg);
+    program.emit_column_or_rowid(sqlite_schema_cursor_id, 1, col1_reg);
+
+    let skip_delete_label = program.allocate_label();
+    program.emit_insn(Insn::Ne {
+        lhs: col0_reg,
+        rhs: type_reg,
+        target_pc: skip_delete_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    program.emit_insn(Insn::Ne {
+        lhs: col1_reg,
+        rhs: trigger_name_reg,
+        target_pc: skip_delete_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    program.emit_insn(Insn::Delete {
+        cursor_id: sqlite_schema_cursor_id,
+        table_name: SQLITE_TABLEID.to_string(),
+    });
+    program.resolve_label(skip_delete_label, program.offset());
+
+    program.emit_insn(Insn::Next {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_next: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(end_loop_label);
+
+    program.emit_insn(Insn::DropTrigger {
+        db: database_id,
+        trigger_name: normalized_trigger_name,
+    });
+
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: (schema.schema_version + 1) as i32,
+        p5: 1, // update version
+    });
+
+    Ok(program)
+}
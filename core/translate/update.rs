@@ -1,13 +1,16 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-use crate::schema::{BTreeTable, Column, Type};
+use crate::schema::{BTreeTable, Column, Index, IndexColumn, Type};
+use crate::translate::emitter::{OperationMode, Resolver};
 use crate::translate::expr::{
     bind_and_rewrite_expr, walk_expr, BindingBehavior, ParamState, WalkControl,
 };
+use crate::translate::fkey::has_fks;
 use crate::translate::optimizer::optimize_select_plan;
 use crate::translate::plan::{Operation, QueryDestination, Scan, Search, SelectPlan};
 use crate::translate::planner::parse_limit;
+use crate::translate::trigger::{has_triggers, translate_update_of_view};
 use crate::vdbe::builder::CursorType;
 use crate::{
     bail_parse_error,
@@ -16,16 +19,17 @@ use crate::{
     vdbe::builder::{ProgramBuilder, ProgramBuilderOpts},
     SymbolTable,
 };
-use turso_parser::ast::{self, Expr, Indexed, SortOrder};
+use turso_parser::ast::{self, Expr, Indexed, ResolveType, SortOrder};
 
 use super::emitter::emit_program;
 use super::expr::process_returning_clause;
 use super::optimizer::optimize_plan;
 use super::plan::{
-    ColumnUsedMask, IterationDirection, JoinedTable, Plan, ResultSetColumn, TableReferences,
-    UpdatePlan,
+    ColumnUsedMask, IterationDirection, JoinedTable, OuterQueryReference, Plan, ResultSetColumn,
+    TableReferences, UpdatePlan, WhereTerm,
 };
-use super::planner::parse_where;
+use super::planner::{check_indexed_by, parse_from_clause, parse_where, parse_with};
+use super::subquery::{expr_has_subquery, plan_subqueries_from_select_plan};
 /*
 * Update is simple. By default we scan the table, and for each row, we check the WHERE
 * clause. If it evaluates to true, we build the new record with the updated value and insert.
@@ -58,11 +62,27 @@ addr  opcode         p1    p2    p3    p4             p5  comment
 pub fn translate_update(
     schema: &Schema,
     body: &mut ast::Update,
+    database_id: usize,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
     connection: &Arc<crate::Connection>,
 ) -> crate::Result<ProgramBuilder> {
-    let mut plan = prepare_update_plan(&mut program, schema, body, connection, false)?;
+    if let Some(view) = schema.get_view(body.tbl_name.name.as_str()) {
+        if !body.returning.is_empty() {
+            bail_parse_error!("RETURNING is not supported for views");
+        }
+        let resolver = Resolver::new(schema, syms);
+        return translate_update_of_view(program, &resolver, connection, view, body.clone());
+    }
+    let mut plan = prepare_update_plan(
+        &mut program,
+        schema,
+        body,
+        database_id,
+        syms,
+        connection,
+        false,
+    )?;
     optimize_plan(&mut plan, schema)?;
     let opts = ProgramBuilderOpts {
         num_cursors: 1,
@@ -74,16 +94,26 @@ pub fn translate_update(
     Ok(program)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn translate_update_for_schema_change(
     schema: &Schema,
     body: &mut ast::Update,
+    database_id: usize,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
     connection: &Arc<crate::Connection>,
     ddl_query: &str,
     after: impl FnOnce(&mut ProgramBuilder),
 ) -> crate::Result<ProgramBuilder> {
-    let mut plan = prepare_update_plan(&mut program, schema, body, connection, true)?;
+    let mut plan = prepare_update_plan(
+        &mut program,
+        schema,
+        body,
+        database_id,
+        syms,
+        connection,
+        true,
+    )?;
 
     if let Plan::Update(plan) = &mut plan {
         if program.capture_data_changes_mode().has_updates() {
@@ -106,27 +136,11 @@ pub fn prepare_update_plan(
     program: &mut ProgramBuilder,
     schema: &Schema,
     body: &mut ast::Update,
+    database_id: usize,
+    syms: &SymbolTable,
     connection: &Arc<crate::Connection>,
     is_internal_schema_change: bool,
 ) -> crate::Result<Plan> {
-    if body.with.is_some() {
-        bail_parse_error!("WITH clause is not supported in UPDATE");
-    }
-    if body.or_conflict.is_some() {
-        bail_parse_error!("ON CONFLICT clause is not supported in UPDATE");
-    }
-    if body
-        .indexed
-        .as_ref()
-        .is_some_and(|i| matches!(i, Indexed::IndexedBy(_)))
-    {
-        bail_parse_error!("INDEXED BY clause is not supported in UPDATE");
-    }
-
-    if !body.order_by.is_empty() {
-        bail_parse_error!("ORDER BY is not supported in UPDATE");
-    }
-
     let table_name = &body.tbl_name.name;
 
     // Check if this is a system table that should be protected from direct writes
@@ -153,16 +167,7 @@ pub fn prepare_update_plan(
     }
 
     let table_name = table.get_name();
-    let iter_dir = body
-        .order_by
-        .first()
-        .and_then(|ob| {
-            ob.order.map(|o| match o {
-                SortOrder::Asc => IterationDirection::Forwards,
-                SortOrder::Desc => IterationDirection::Backwards,
-            })
-        })
-        .unwrap_or(IterationDirection::Forwards);
+    check_indexed_by(schema, table_name, body.indexed.as_ref())?;
 
     let joined_tables = vec![JoinedTable {
         table: match table.as_ref() {
@@ -172,10 +177,11 @@ pub fn prepare_update_plan(
         },
         identifier: table_name.to_string(),
         internal_id: program.table_reference_counter.next(),
-        op: build_scan_op(&table, iter_dir),
+        op: build_scan_op(&table),
         join_info: None,
         col_used_mask: ColumnUsedMask::default(),
-        database_id: 0,
+        database_id,
+        indexed: body.indexed.clone(),
     }];
     let mut table_references = TableReferences::new(joined_tables, vec![]);
 
@@ -188,18 +194,44 @@ pub fn prepare_update_plan(
 
     let mut set_clauses = Vec::with_capacity(body.sets.len());
     let mut param_idx = ParamState::default();
+    let from = body.from.take();
+    let or_conflict = body.or_conflict;
+    // FAIL and ROLLBACK only change what happens to the transaction when a row violates a
+    // constraint, which is handled when the statement halts.
+    if let Some(resolve_type @ (ResolveType::Fail | ResolveType::Rollback)) = or_conflict {
+        program.set_resolve_type(resolve_type);
+    }
+
+    // The CTEs of the WITH clause can be referred to by the subqueries of the WHERE clause,
+    // which are evaluated by the ephemeral plan.
+    let ctes = match body.with.take() {
+        Some(with) => parse_with(
+            schema,
+            with,
+            syms,
+            &[],
+            &mut program.table_reference_counter,
+            connection,
+            &mut param_idx,
+        )?,
+        None => vec![],
+    };
 
     // Process each SET assignment and map column names to expressions
     // e.g the statement `SET x = 1, y = 2, z = 3` has 3 set assigments
     for set in &mut body.sets {
-        bind_and_rewrite_expr(
-            &mut set.expr,
-            Some(&mut table_references),
-            None,
-            connection,
-            &mut param_idx,
-            BindingBehavior::ResultColumnsNotAllowed,
-        )?;
+        // With a FROM clause, the SET expressions are bound and evaluated by the ephemeral plan,
+        // which joins the table with the tables of the FROM clause.
+        if from.is_none() {
+            bind_and_rewrite_expr(
+                &mut set.expr,
+                Some(&mut table_references),
+                None,
+                connection,
+                &mut param_idx,
+                BindingBehavior::ResultColumnsNotAllowed,
+            )?;
+        }
 
         let values = match set.expr.as_ref() {
             Expr::Parenthesized(vals) => vals.clone(),
@@ -221,6 +253,9 @@ pub fn prepare_update_plan(
                 Some(idx) => idx,
                 None => bail_parse_error!("no such column: {}", ident),
             };
+            if table.columns()[*col_index].generated.is_some() {
+                bail_parse_error!("cannot UPDATE generated column \"{}\"", ident);
+            }
 
             // Update existing entry or add new one
             match set_clauses.iter_mut().find(|(idx, _)| idx == col_index) {
@@ -239,21 +274,13 @@ pub fn prepare_update_plan(
         &mut param_idx,
     )?;
 
-    let order_by = body
-        .order_by
-        .iter_mut()
-        .map(|o| {
-            let _ = bind_and_rewrite_expr(
-                &mut o.expr,
-                Some(&mut table_references),
-                Some(&result_columns),
-                connection,
-                &mut param_idx,
-                BindingBehavior::ResultColumnsNotAllowed,
-            );
-            (o.expr.clone(), o.order.unwrap_or(SortOrder::Asc))
-        })
-        .collect();
+    // With an ORDER BY clause, the rows to update are chosen by the ephemeral plan, which sorts
+    // them and applies the LIMIT and OFFSET.
+    let (order_by, ephemeral_limit) = if body.order_by.is_empty() {
+        (vec![], None)
+    } else {
+        (std::mem::take(&mut body.order_by), body.limit.take())
+    };
 
     // Sqlite determines we should create an ephemeral table if we do not have a FROM clause
     // Difficult to say what items from the plan can be checked for this so currently just checking if a RowId Alias is referenced
@@ -261,142 +288,77 @@ pub fn prepare_update_plan(
     // https://github.com/sqlite/sqlite/blob/master/src/update.c#L670
     let columns = table.columns();
 
+    // Changing the PRIMARY KEY of a WITHOUT ROWID table moves the row within the table b-tree,
+    // just like changing the rowid does.
+    let without_rowid = table.btree().is_some_and(|btree| !btree.has_rowid);
     let rowid_alias_used = set_clauses.iter().fold(false, |accum, (idx, _)| {
-        accum || columns[*idx].is_rowid_alias
+        accum || columns[*idx].is_rowid_alias || (without_rowid && columns[*idx].primary_key)
     });
 
-    let (ephemeral_plan, mut where_clause) = if rowid_alias_used {
-        let mut where_clause = vec![];
-        let internal_id = program.table_reference_counter.next();
-
-        let joined_tables = vec![JoinedTable {
-            table: match table.as_ref() {
-                Table::Virtual(vtab) => Table::Virtual(vtab.clone()),
-                Table::BTree(btree_table) => Table::BTree(btree_table.clone()),
-                _ => unreachable!(),
-            },
-            identifier: table_name.to_string(),
-            internal_id,
-            op: build_scan_op(&table, iter_dir),
-            join_info: None,
-            col_used_mask: ColumnUsedMask::default(),
-            database_id: 0,
-        }];
-        let mut table_references = TableReferences::new(joined_tables, vec![]);
-
-        // Parse the WHERE clause
-        parse_where(
-            body.where_clause.as_deref(),
-            &mut table_references,
-            Some(&result_columns),
-            &mut where_clause,
-            connection,
-            &mut param_idx,
-        )?;
-
-        let table = Arc::new(BTreeTable {
-            root_page: 0, // Not relevant for ephemeral table definition
-            name: "ephemeral_scratch".to_string(),
-            has_rowid: true,
-            has_autoincrement: false,
-            primary_key_columns: vec![],
-            columns: vec![Column {
-                name: Some("rowid".to_string()),
-                ty: Type::Integer,
-                ty_str: "INTEGER".to_string(),
-                primary_key: true,
-                is_rowid_alias: false,
-                notnull: true,
-                default: None,
-                unique: false,
-                collation: None,
-                hidden: false,
-            }],
-            is_strict: false,
-            unique_sets: vec![],
-        });
-
-        let temp_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
-
-        let mut ephemeral_plan = SelectPlan {
-            table_references,
-            result_columns: vec![ResultSetColumn {
-                expr: Expr::RowId {
-                    database: None,
-                    table: internal_id,
-                },
-                alias: None,
-                contains_aggregates: false,
-            }],
-            where_clause,       // original WHERE terms from the UPDATE clause
-            group_by: None,     // N/A
-            order_by: vec![],   // N/A
-            aggregates: vec![], // N/A
-            limit: None,        // N/A
-            query_destination: QueryDestination::EphemeralTable {
-                cursor_id: temp_cursor_id,
-                table,
-            },
-            join_order: vec![],
-            offset: None,
-            contains_constant_false_condition: false,
-            distinctness: super::plan::Distinctness::NonDistinct,
-            values: vec![],
-            window: None,
-        };
-
-        optimize_select_plan(&mut ephemeral_plan, schema)?;
-        let table = ephemeral_plan
-            .table_references
-            .joined_tables()
-            .first()
-            .unwrap();
-        // We do not need to emit an ephemeral plan if we are not going to loop over the table values
-        if matches!(table.op, Operation::Search(Search::RowidEq { .. })) {
-            (None, vec![])
-        } else {
-            (Some(ephemeral_plan), vec![])
+    // Triggers and foreign key actions fired by the UPDATE may modify the table, so the rows to
+    // update are collected upfront instead of being updated while the table is scanned.
+    let updated_column_names = set_clauses
+        .iter()
+        .filter_map(|(idx, _)| columns[*idx].name.as_deref().map(normalize_ident))
+        .collect::<Vec<_>>();
+    let has_update_triggers = has_triggers(
+        schema,
+        table_name,
+        OperationMode::UPDATE,
+        None,
+        Some(&updated_column_names),
+    );
+
+    let has_update_fks = has_fks(schema, connection, table_name, Some(&updated_column_names));
+
+    let where_has_subquery = body.where_clause.as_deref().is_some_and(expr_has_subquery);
+
+    // The new values of the SET clause are evaluated by the join of the ephemeral plan, and the
+    // UPDATE loop reads them back from the ephemeral table.
+    let (from_values, from_values_start_reg) = if from.is_some() {
+        let start_reg = program.alloc_registers(set_clauses.len());
+        let mut values = Vec::with_capacity(set_clauses.len());
+        for (i, (_, expr)) in set_clauses.iter_mut().enumerate() {
+            let value = std::mem::replace(expr, Box::new(Expr::Register(start_reg + i)));
+            values.push(*value);
         }
+        (values, Some(start_reg))
     } else {
-        (None, vec![])
+        (vec![], None)
     };
 
-    if ephemeral_plan.is_none() {
-        // Parse the WHERE clause
-        parse_where(
-            body.where_clause.as_deref(),
-            &mut table_references,
-            Some(&result_columns),
-            &mut where_clause,
-            connection,
-            &mut param_idx,
-        )?;
-    };
-
-    // Parse the LIMIT/OFFSET clause
-    let (limit, offset) = body.limit.as_mut().map_or(Ok((None, None)), |l| {
-        parse_limit(l, connection, &mut param_idx)
-    })?;
-
     // Check what indexes will need to be updated by checking set_clauses and see
     // if a column is contained in an index.
     let indexes = schema.get_indices(table_name);
-    let updated_cols: HashSet<usize> = set_clauses.iter().map(|(i, _)| *i).collect();
-    let rowid_alias_used = set_clauses
-        .iter()
-        .any(|(idx, _)| columns[*idx].is_rowid_alias);
-    let indexes_to_update = if rowid_alias_used {
-        // If the rowid alias is used in the SET clause, we need to update all indexes
+    let mut updated_cols: HashSet<usize> = set_clauses.iter().map(|(i, _)| *i).collect();
+    // The generated columns computed from the updated columns change as well.
+    if let Some(btree) = table.btree() {
+        updated_cols.extend(btree.generated_columns_depending_on(
+            &set_clauses.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+        ));
+    }
+    let indexes_to_update: Vec<Arc<Index>> = if rowid_alias_used
+        || or_conflict == Some(ResolveType::Replace)
+    {
+        // If the rowid alias is used in the SET clause, we need to update all indexes. The same
+        // goes for the PRIMARY KEY of a WITHOUT ROWID table, which every index entry ends with.
+        // REPLACE also needs all of them open, to delete the entries of the conflicting rows.
         indexes.cloned().collect()
     } else {
         // otherwise we need to update the indexes whose columns are set in the SET clause,
         // or if the colunns used in the partial index WHERE clause are being updated
         indexes
             .filter_map(|idx| {
-                let mut needs = idx
-                    .columns
-                    .iter()
-                    .any(|c| updated_cols.contains(&c.pos_in_table));
+                let joined_table = &table_references.joined_tables()[0];
+                let mut needs = idx.columns.iter().any(|c| {
+                    match c.bind_expr(&joined_table.table, joined_table.internal_id) {
+                        // an expression is updated if any of the columns it uses is
+                        Some(expr) => collect_cols_used_in_expr(&expr)
+                            .iter()
+                            .any(|c| updated_cols.contains(c)),
+                        None => updated_cols.contains(&c.pos_in_table),
+                    }
+                });
 
                 if !needs {
                     if let Some(w) = &idx.where_clause {
@@ -428,6 +390,75 @@ pub fn prepare_update_plan(
             .collect()
     };
 
+    // The UPDATE loop can't iterate over the index named by INDEXED BY while it modifies the
+    // entries of that index, so the rows to update are collected upfront.
+    let indexed_by_updated = match &body.indexed {
+        Some(Indexed::IndexedBy(index_name)) => {
+            let index_name = normalize_ident(index_name.as_str());
+            indexes_to_update.iter().any(|idx| idx.name == index_name)
+        }
+        _ => false,
+    };
+
+    let ephemeral_plan = if rowid_alias_used
+        || has_update_triggers
+        || has_update_fks
+        || where_has_subquery
+        || from.is_some()
+        || !order_by.is_empty()
+        // REPLACE deletes the rows that conflict with the new values, which may not have been
+        // visited by the loop yet.
+        || or_conflict == Some(ResolveType::Replace)
+        || indexed_by_updated
+    {
+        prepare_ephemeral_rowid_plan(
+            program,
+            schema,
+            syms,
+            ctes,
+            match table.as_ref() {
+                Table::Virtual(vtab) => Table::Virtual(vtab.clone()),
+                Table::BTree(btree_table) => Table::BTree(btree_table.clone()),
+                _ => unreachable!(),
+            },
+            table_name,
+            database_id,
+            body.indexed.clone(),
+            build_scan_op(&table),
+            from,
+            body.where_clause.as_deref(),
+            from_values,
+            order_by,
+            ephemeral_limit,
+            Some(&result_columns),
+            connection,
+            &mut param_idx,
+        )?
+    } else {
+        None
+    };
+    let mut where_clause = vec![];
+
+    if ephemeral_plan.is_some() {
+        // The hint applies to the search for the rows to update, which the ephemeral plan does.
+        table_references.joined_tables_mut()[0].indexed = None;
+    } else {
+        // Parse the WHERE clause
+        parse_where(
+            body.where_clause.as_deref(),
+            &mut table_references,
+            Some(&result_columns),
+            &mut where_clause,
+            connection,
+            &mut param_idx,
+        )?;
+    };
+
+    // Parse the LIMIT/OFFSET clause
+    let (limit, offset) = body.limit.as_mut().map_or(Ok((None, None)), |l| {
+        parse_limit(l, connection, &mut param_idx)
+    })?;
+
     Ok(Plan::Update(UpdatePlan {
         table_references,
         set_clauses,
@@ -437,20 +468,325 @@ pub fn prepare_update_plan(
         } else {
             Some(result_columns)
         },
-        order_by,
+        order_by: vec![],
         limit,
         offset,
         contains_constant_false_condition: false,
         indexes_to_update,
         ephemeral_plan,
+        from_values_start_reg,
+        or_conflict,
         cdc_update_alter_statement: None,
     }))
 }
 
-fn build_scan_op(table: &Table, iter_dir: IterationDirection) -> Operation {
+/// Builds a plan that collects the rowids of the rows of `table` matching `where_clause` into
+/// an ephemeral table, or the PRIMARY KEYs of a WITHOUT ROWID table into an ephemeral index.
+/// Statements use it when the table may change while they iterate over
+/// the rows to modify, e.g. when the rowid itself is updated or when triggers fire,
+/// and when the WHERE clause has subqueries, which may refer to the CTEs in `ctes`.
+///
+/// For UPDATE ... FROM, the tables of the `from` clause are joined with `table`, and the
+/// `values` of the SET clause are evaluated by the join and stored along with the rowid.
+/// A row matched by several rows of the join is only stored once, with the values of the last.
+/// In a WITHOUT ROWID table, the values follow the PRIMARY KEY in the entries of the ephemeral
+/// index, so such a row has an entry for each distinct set of values, and the UPDATE only uses
+/// the first one.
+///
+/// With an `order_by` clause, only the first rows in that order within the `limit` and its
+/// offset are collected.
+/// Returns None if the WHERE clause selects a single rowid, as there is no loop to protect.
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_ephemeral_rowid_plan(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut ctes: Vec<JoinedTable>,
+    table: Table,
+    table_name: &str,
+    database_id: usize,
+    indexed: Option<ast::Indexed>,
+    op: Operation,
+    from: Option<ast::FromClause>,
+    where_clause: Option<&Expr>,
+    mut values: Vec<Expr>,
+    order_by: Vec<ast::SortedColumn>,
+    limit: Option<ast::Limit>,
+    result_columns: Option<&[ResultSetColumn]>,
+    connection: &Arc<crate::Connection>,
+    param_idx: &mut ParamState,
+) -> crate::Result<Option<SelectPlan>> {
+    let mut where_terms = vec![];
+    let internal_id = program.table_reference_counter.next();
+
+    let joined_tables = vec![JoinedTable {
+        table,
+        identifier: table_name.to_string(),
+        internal_id,
+        op,
+        join_info: None,
+        col_used_mask: ColumnUsedMask::default(),
+        database_id,
+        indexed,
+    }];
+    let mut table_references = TableReferences::new(joined_tables, vec![]);
+
+    if let Some(from) = from {
+        let mut vtab_predicates = vec![];
+        parse_from_clause(
+            schema,
+            from,
+            syms,
+            &mut ctes,
+            &mut where_terms,
+            &mut vtab_predicates,
+            &mut table_references,
+            &mut program.table_reference_counter,
+            connection,
+            param_idx,
+        )?;
+        for mut expr in vtab_predicates {
+            bind_and_rewrite_expr(
+                &mut expr,
+                Some(&mut table_references),
+                None,
+                connection,
+                param_idx,
+                BindingBehavior::ResultColumnsNotAllowed,
+            )?;
+            where_terms.push(WhereTerm {
+                expr,
+                from_outer_join: None,
+                consumed: false,
+            });
+        }
+    }
+
+    for value in values.iter_mut() {
+        bind_and_rewrite_expr(
+            value,
+            Some(&mut table_references),
+            None,
+            connection,
+            param_idx,
+            BindingBehavior::ResultColumnsNotAllowed,
+        )?;
+    }
+
+    // Parse the WHERE clause
+    parse_where(
+        where_clause,
+        &mut table_references,
+        result_columns,
+        &mut where_terms,
+        connection,
+        param_idx,
+    )?;
+
+    let mut order_by_terms = Vec::with_capacity(order_by.len());
+    for mut o in order_by {
+        bind_and_rewrite_expr(
+            &mut o.expr,
+            Some(&mut table_references),
+            None,
+            connection,
+            param_idx,
+            BindingBehavior::ResultColumnsNotAllowed,
+        )?;
+        order_by_terms.push((o.expr, o.order.unwrap_or(SortOrder::Asc)));
+    }
+    let (limit, offset) = limit.map_or(Ok((None, None)), |mut l| {
+        parse_limit(&mut l, connection, param_idx)
+    })?;
+
+    // The rows are identified by their rowid, or by their PRIMARY KEY in a WITHOUT ROWID table,
+    // which is collected into an ephemeral index instead.
+    let (result_columns, query_destination) = match table_references.joined_tables()[0]
+        .btree()
+        .filter(|btree| !btree.has_rowid)
+    {
+        Some(btree) => {
+            let primary_key = Index::without_rowid_primary_key(&btree);
+            let num_key_columns = primary_key.columns.len();
+            let value_columns = (0..values.len()).map(|i| IndexColumn {
+                name: format!("value{i}"),
+                order: SortOrder::Asc,
+                pos_in_table: num_key_columns + i,
+                collation: None,
+                default: None,
+                expr: None,
+            });
+            let index = Arc::new(Index {
+                name: "ephemeral_primary_key".to_string(),
+                table_name: String::new(),
+                root_page: 0,
+                columns: primary_key
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, column)| IndexColumn {
+                        pos_in_table: i,
+                        order: SortOrder::Asc,
+                        ..column.clone()
+                    })
+                    .chain(value_columns)
+                    .collect(),
+                unique: false,
+                ephemeral: true,
+                has_rowid: false,
+                primary_key_suffix: vec![],
+                where_clause: None,
+            });
+            let result_columns = primary_key
+                .columns
+                .iter()
+                .map(|column| ResultSetColumn {
+                    expr: Expr::Column {
+                        database: None,
+                        table: internal_id,
+                        column: column.pos_in_table,
+                        is_rowid_alias: false,
+                    },
+                    alias: None,
+                    contains_aggregates: false,
+                })
+                .chain(values.drain(..).map(|value| ResultSetColumn {
+                    expr: value,
+                    alias: None,
+                    contains_aggregates: false,
+                }))
+                .collect();
+            let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+            (
+                result_columns,
+                QueryDestination::EphemeralIndex {
+                    cursor_id,
+                    index,
+                    is_delete: false,
+                },
+            )
+        }
+        None => {
+            // The values are the columns of the records, and the rowid is their key.
+            let mut columns = (0..values.len())
+                .map(|i| Column {
+                    name: Some(format!("value{i}")),
+                    ty: Type::Null,
+                    ty_str: String::new(),
+                    primary_key: false,
+                    is_rowid_alias: false,
+                    notnull: false,
+                    default: None,
+                    unique: false,
+                    collation: None,
+                    hidden: false,
+                    generated: None,
+                })
+                .collect::<Vec<_>>();
+            columns.push(Column {
+                name: Some("rowid".to_string()),
+                ty: Type::Integer,
+                ty_str: "INTEGER".to_string(),
+                primary_key: true,
+                is_rowid_alias: false,
+                notnull: true,
+                default: None,
+                unique: false,
+                collation: None,
+                hidden: false,
+                generated: None,
+            });
+            let table = Arc::new(BTreeTable {
+                root_page: 0, // Not relevant for ephemeral table definition
+                name: "ephemeral_scratch".to_string(),
+                has_rowid: true,
+                has_autoincrement: false,
+                foreign_keys: vec![],
+                checks: vec![],
+                primary_key_columns: vec![],
+                columns,
+                is_strict: false,
+                unique_sets: vec![],
+            });
+            let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+            let mut result_columns = values
+                .drain(..)
+                .map(|value| ResultSetColumn {
+                    expr: value,
+                    alias: None,
+                    contains_aggregates: false,
+                })
+                .collect::<Vec<_>>();
+            result_columns.push(ResultSetColumn {
+                expr: Expr::RowId {
+                    database: None,
+                    table: internal_id,
+                },
+                alias: None,
+                contains_aggregates: false,
+            });
+            (
+                result_columns,
+                QueryDestination::EphemeralTable { cursor_id, table },
+            )
+        }
+    };
+
+    let mut ephemeral_plan = SelectPlan {
+        table_references,
+        result_columns,
+        where_clause: where_terms, // original WHERE terms from the statement
+        group_by: None,            // N/A
+        order_by: order_by_terms,
+        aggregates: vec![], // N/A
+        limit,
+        query_destination,
+        join_order: vec![],
+        offset,
+        contains_constant_false_condition: false,
+        distinctness: super::plan::Distinctness::NonDistinct,
+        values: vec![],
+        window: None,
+        non_from_clause_subqueries: vec![],
+    };
+
+    // The CTEs are only in scope in the subqueries of the WHERE clause, which is already bound.
+    for cte in ctes {
+        ephemeral_plan
+            .table_references
+            .add_outer_query_ref(OuterQueryReference {
+                identifier: cte.identifier,
+                internal_id: cte.internal_id,
+                table: cte.table,
+                col_used_mask: ColumnUsedMask::default(),
+            });
+    }
+    plan_subqueries_from_select_plan(program, &mut ephemeral_plan, schema, syms, connection)?;
+
+    optimize_select_plan(&mut ephemeral_plan, schema)?;
+    let table = ephemeral_plan
+        .table_references
+        .joined_tables()
+        .first()
+        .unwrap();
+    // We do not need to emit an ephemeral plan if we are not going to loop over the table values.
+    // The subqueries of the WHERE clause are only planned in the ephemeral plan though.
+    if matches!(table.op, Operation::Search(Search::RowidEq { .. }))
+        && ephemeral_plan.non_from_clause_subqueries.is_empty()
+        && ephemeral_plan.table_references.joined_tables().len() == 1
+        && ephemeral_plan.limit.is_none()
+        && ephemeral_plan.offset.is_none()
+    {
+        Ok(None)
+    } else {
+        Ok(Some(ephemeral_plan))
+    }
+}
+
+fn build_scan_op(table: &Table) -> Operation {
     match table {
         Table::BTree(_) => Operation::Scan(Scan::BTreeTable {
-            iter_dir,
+            iter_dir: IterationDirection::Forwards,
             index: None,
         }),
         Table::Virtual(_) => Operation::default_scan_for(table),
@@ -15,13 +15,14 @@ use crate::{
     schema::{Index, IndexColumn, Schema, Table},
     translate::{
         emitter::{
-            emit_cdc_full_record, emit_cdc_insns, emit_cdc_patch_record, OperationMode, Resolver,
+            emit_cdc_full_record, emit_cdc_insns, emit_cdc_patch_record, emit_check_constraints,
+            emit_generated_columns, emit_new_primary_key, OperationMode, Resolver,
         },
         expr::{
             emit_returning_results, translate_expr, translate_expr_no_constant_opt, walk_expr_mut,
             NoConstantOptReason, ReturningValueRegisters,
         },
-        insert::{Insertion, ROWID_COLUMN},
+        insert::{emit_table_record, Insertion, ROWID_COLUMN},
         plan::ResultSetColumn,
     },
     util::normalize_ident,
@@ -30,6 +31,7 @@ use crate::{
         insn::{IdxInsertFlags, InsertFlags, Insn},
         BranchOffset,
     },
+    Connection,
 };
 
 // The following comment is copied directly from SQLite source and should be used as a guiding light
@@ -161,6 +163,13 @@ fn collect_changed_cols(
             }
         }
     }
+    if let Some(bt) = table.btree() {
+        let set_columns = set_pairs
+            .iter()
+            .map(|(col_idx, _)| *col_idx)
+            .collect::<Vec<_>>();
+        cols_changed.extend(bt.generated_columns_depending_on(&set_columns));
+    }
     (cols_changed, rowid_changed)
 }
 
@@ -174,7 +183,7 @@ fn upsert_index_is_affected(
     if rowid_changed {
         return true;
     }
-    let km = index_keys(idx);
+    let km = index_keys(idx, table);
     let pm = partial_index_cols(idx, table);
     for c in km.iter().chain(pm.iter()) {
         if changed_cols.contains(c) {
@@ -184,18 +193,30 @@ fn upsert_index_is_affected(
     false
 }
 
-/// Columns used by index key
+/// Columns used by index key, including the columns used by the expressions of an index on
+/// expressions
 #[inline]
-fn index_keys(idx: &Index) -> Vec<usize> {
-    idx.columns.iter().map(|ic| ic.pos_in_table).collect()
+fn index_keys(idx: &Index, table: &Table) -> Vec<usize> {
+    idx.columns
+        .iter()
+        .flat_map(|ic| match &ic.expr {
+            Some(expr) => cols_used_in_expr(expr, table).into_iter().collect(),
+            None => vec![ic.pos_in_table],
+        })
+        .collect()
 }
 
 /// Columns referenced by the partial WHERE (empty if none).
 fn partial_index_cols(idx: &Index, table: &Table) -> HashSet<usize> {
-    use ast::{Expr, Name};
     let Some(expr) = &idx.where_clause else {
         return HashSet::new();
     };
+    cols_used_in_expr(expr, table)
+}
+
+/// Columns of `table` referenced by an expression of one of its indexes.
+fn cols_used_in_expr(expr: &ast::Expr, table: &Table) -> HashSet<usize> {
+    use ast::{Expr, Name};
     let mut out = HashSet::new();
     let _ = walk_expr(expr, &mut |e: &ast::Expr| -> crate::Result<WalkControl> {
         match e {
@@ -301,7 +322,14 @@ pub fn resolve_upsert_target(
     }
 
     // Targeted: must match PK or a non-partial UNIQUE index.
-    if upsert_matches_pk(upsert, table) {
+    // The PRIMARY KEY of a WITHOUT ROWID table is not one of the indexes of the table.
+    let matches_pk = match table.btree().filter(|btree| !btree.has_rowid) {
+        Some(btree) => {
+            upsert_matches_index(upsert, &Index::without_rowid_primary_key(&btree), table)
+        }
+        None => upsert_matches_pk(upsert, table),
+    };
+    if matches_pk {
         return Ok(ResolvedUpsertTarget::PrimaryKey);
     }
 
@@ -321,7 +349,9 @@ pub fn resolve_upsert_target(
 ///
 /// This routine is entered after the caller has determined that an INSERT
 /// would violate a UNIQUE/PRIMARY KEY constraint and that the user requested
-/// `ON CONFLICT ... DO UPDATE`.
+/// `ON CONFLICT ... DO UPDATE`. The conflicting row is identified by the rowid in
+/// `conflict_rowid_reg`, or for a WITHOUT ROWID table by the PRIMARY KEY in the registers
+/// starting at `conflict_rowid_reg`.
 ///
 /// High-level flow:
 /// 1. Seek to the conflicting row by rowid and load the current row snapshot
@@ -353,30 +383,37 @@ pub fn emit_upsert(
     returning: &mut [ResultSetColumn],
     cdc_cursor_id: Option<usize>,
     row_done_label: BranchOffset,
+    connection: &Connection,
 ) -> crate::Result<()> {
+    let btree = table.btree().expect("UPSERT targets a btree table");
+    // The rows of a WITHOUT ROWID table are identified by their PRIMARY KEY and have no rowid.
+    let primary_key = (!btree.has_rowid).then(|| Index::without_rowid_primary_key(&btree));
+
     // Seek & snapshot CURRENT
-    program.emit_insn(Insn::SeekRowid {
-        cursor_id: tbl_cursor_id,
-        src_reg: conflict_rowid_reg,
-        target_pc: row_done_label,
-    });
-    let num_cols = table.columns().len();
-    let current_start = program.alloc_registers(num_cols);
-    for (i, col) in table.columns().iter().enumerate() {
-        if col.is_rowid_alias {
-            program.emit_insn(Insn::RowId {
+    let rowid_reg = match &primary_key {
+        Some(primary_key) => {
+            program.emit_insn(Insn::NotFound {
                 cursor_id: tbl_cursor_id,
-                dest: current_start + i,
+                target_pc: row_done_label,
+                record_reg: conflict_rowid_reg,
+                num_regs: primary_key.columns.len(),
             });
-        } else {
-            program.emit_insn(Insn::Column {
+            let rowid_reg = program.alloc_register();
+            program.emit_null(rowid_reg, None);
+            rowid_reg
+        }
+        None => {
+            program.emit_insn(Insn::SeekRowid {
                 cursor_id: tbl_cursor_id,
-                column: i,
-                dest: current_start + i,
-                default: None,
+                src_reg: conflict_rowid_reg,
+                target_pc: row_done_label,
             });
+            conflict_rowid_reg
         }
-    }
+    };
+    let num_cols = table.columns().len();
+    let current_start = emit_row_image(program, table, tbl_cursor_id);
+    emit_generated_columns(program, &btree, current_start, rowid_reg, resolver)?;
 
     // BEFORE for index maintenance / CDC
     let before_start = if cdc_cursor_id.is_some() || !idx_cursors.is_empty() {
@@ -405,7 +442,7 @@ pub fn emit_upsert(
             pred,
             table,
             current_start,
-            conflict_rowid_reg,
+            rowid_reg,
             Some(table.get_name()),
             Some(insertion),
             true,
@@ -426,7 +463,7 @@ pub fn emit_upsert(
             expr,
             table,
             current_start,
-            conflict_rowid_reg,
+            rowid_reg,
             Some(table.get_name()),
             Some(insertion),
             true,
@@ -460,40 +497,81 @@ pub fn emit_upsert(
         }
     }
 
-    if let Some(bt) = table.btree() {
-        if bt.is_strict {
-            program.emit_insn(Insn::TypeCheck {
-                start_reg: new_start,
-                count: num_cols,
-                check_generated: true,
-                table_reference: Arc::clone(&bt),
-            });
-        }
+    emit_generated_columns(
+        program,
+        &btree,
+        new_start,
+        new_rowid_reg.unwrap_or(rowid_reg),
+        resolver,
+    )?;
+    if btree.is_strict {
+        program.emit_insn(Insn::TypeCheck {
+            start_reg: new_start,
+            count: num_cols,
+            check_generated: true,
+            table_reference: Arc::clone(&btree),
+        });
     }
+    let set_columns = set_pairs
+        .iter()
+        .map(|(col_idx, _)| *col_idx)
+        .collect::<Vec<_>>();
+    let updated_columns = set_columns
+        .iter()
+        .copied()
+        .chain(btree.generated_columns_depending_on(&set_columns))
+        .filter_map(|col_idx| table.columns()[col_idx].name.clone())
+        .collect::<Vec<_>>();
+    emit_check_constraints(
+        program,
+        connection,
+        &btree,
+        Some(&updated_columns),
+        new_start,
+        new_rowid_reg.unwrap_or(rowid_reg),
+        None,
+        resolver,
+    )?;
+
+    let (changed_cols, rowid_changed) = collect_changed_cols(table, set_pairs);
+    // The entries of the indexes of a WITHOUT ROWID table end with the PRIMARY KEY, like they
+    // end with the rowid for the other tables.
+    let primary_key_changed = primary_key.as_ref().is_some_and(|primary_key| {
+        primary_key
+            .columns
+            .iter()
+            .any(|column| changed_cols.contains(&column.pos_in_table))
+    });
+    let new_key_reg = primary_key
+        .as_ref()
+        .map(|primary_key| emit_new_primary_key(program, &btree, primary_key, new_start));
 
     // Index rebuild (DELETE old, INSERT new), honoring partial-index WHEREs
     if let Some(before) = before_start {
-        let (changed_cols, rowid_changed) = collect_changed_cols(table, set_pairs);
-
         for (idx_name, _root, idx_cid) in idx_cursors {
             let idx_meta = schema
                 .get_index(table.get_name(), idx_name)
                 .expect("index exists");
 
-            if !upsert_index_is_affected(table, idx_meta, &changed_cols, rowid_changed) {
+            if !upsert_index_is_affected(
+                table,
+                idx_meta,
+                &changed_cols,
+                rowid_changed || primary_key_changed,
+            ) {
                 continue; // skip untouched index completely
             }
             let k = idx_meta.columns.len();
+            let entry_len = if primary_key.is_some() {
+                k + idx_meta.primary_key_suffix.len()
+            } else {
+                k + 1
+            };
 
             let before_pred_reg = eval_partial_pred_for_row_image(
-                program,
-                table,
-                idx_meta,
-                before,
-                conflict_rowid_reg,
-                resolver,
+                program, table, idx_meta, before, rowid_reg, resolver,
             );
-            let new_rowid = new_rowid_reg.unwrap_or(conflict_rowid_reg);
+            let new_rowid = new_rowid_reg.unwrap_or(rowid_reg);
             let new_pred_reg = eval_partial_pred_for_row_image(
                 program, table, idx_meta, new_start, new_rowid, resolver,
             );
@@ -510,23 +588,33 @@ pub fn emit_upsert(
             });
 
             // DELETE old key
-            let del = program.alloc_registers(k + 1);
-            for (i, ic) in idx_meta.columns.iter().enumerate() {
-                let (ci, _) = table.get_column_by_name(&ic.name).unwrap();
+            let del = program.alloc_registers(entry_len);
+            for (i, ic) in idx_meta
+                .columns
+                .iter()
+                .chain(idx_meta.primary_key_suffix.iter())
+                .enumerate()
+            {
+                emit_index_column_for_row_image(
+                    program,
+                    table,
+                    ic,
+                    before,
+                    rowid_reg,
+                    del + i,
+                    resolver,
+                )?;
+            }
+            if primary_key.is_none() {
                 program.emit_insn(Insn::Copy {
-                    src_reg: before + ci,
-                    dst_reg: del + i,
+                    src_reg: rowid_reg,
+                    dst_reg: del + k,
                     extra_amount: 0,
                 });
             }
-            program.emit_insn(Insn::Copy {
-                src_reg: conflict_rowid_reg,
-                dst_reg: del + k,
-                extra_amount: 0,
-            });
             program.emit_insn(Insn::IdxDelete {
                 start_reg: del,
-                num_regs: k + 1,
+                num_regs: entry_len,
                 cursor_id: *idx_cid,
                 raise_error_if_no_matching_entry: false,
             });
@@ -546,67 +634,85 @@ pub fn emit_upsert(
             });
 
             // INSERT new key (use NEW rowid if present)
-            let ins = program.alloc_registers(k + 1);
-            for (i, ic) in idx_meta.columns.iter().enumerate() {
-                let (ci, _) = table.get_column_by_name(&ic.name).unwrap();
+            let ins = program.alloc_registers(entry_len);
+            for (i, ic) in idx_meta
+                .columns
+                .iter()
+                .chain(idx_meta.primary_key_suffix.iter())
+                .enumerate()
+            {
+                emit_index_column_for_row_image(
+                    program,
+                    table,
+                    ic,
+                    new_start,
+                    new_rowid,
+                    ins + i,
+                    resolver,
+                )?;
+            }
+            if primary_key.is_none() {
                 program.emit_insn(Insn::Copy {
-                    src_reg: new_start + ci,
-                    dst_reg: ins + i,
+                    src_reg: new_rowid,
+                    dst_reg: ins + k,
                     extra_amount: 0,
                 });
             }
-            program.emit_insn(Insn::Copy {
-                src_reg: new_rowid,
-                dst_reg: ins + k,
-                extra_amount: 0,
-            });
 
             let rec = program.alloc_register();
             program.emit_insn(Insn::MakeRecord {
                 start_reg: ins,
-                count: k + 1,
+                count: entry_len,
                 dest_reg: rec,
                 index_name: Some((*idx_name).clone()),
                 affinity_str: None,
             });
 
             if idx_meta.unique {
-                // Affinity on the key columns for the NoConflict probe
                 let ok = program.allocate_label();
-                let aff: String = idx_meta
-                    .columns
-                    .iter()
-                    .map(|c| {
-                        table
-                            .get_column_by_name(&c.name)
-                            .map(|(_, col)| col.affinity().aff_mask())
-                            .unwrap_or('B')
-                    })
-                    .collect();
-
-                program.emit_insn(Insn::Affinity {
-                    start_reg: ins,
-                    count: NonZeroUsize::new(k).unwrap(),
-                    affinities: aff,
-                });
-                program.emit_insn(Insn::NoConflict {
-                    cursor_id: *idx_cid,
-                    target_pc: ok,
-                    record_reg: ins,
-                    num_regs: k,
-                });
-                let hit = program.alloc_register();
-                program.emit_insn(Insn::IdxRowId {
-                    cursor_id: *idx_cid,
-                    dest: hit,
-                });
-                program.emit_insn(Insn::Eq {
-                    lhs: new_rowid,
-                    rhs: hit,
-                    target_pc: ok,
-                    flags: CmpInsFlags::default(),
-                    collation: program.curr_collation(),
-                });
+                if let Some(new_key_reg) = new_key_reg {
+                    emit_unique_probe_for_without_rowid_row_image(
+                        program,
+                        table,
+                        idx_meta,
+                        *idx_cid,
+                        new_start,
+                        new_key_reg,
+                        ok,
+                        resolver,
+                    )?;
+                } else {
+                    // Affinity on the key columns for the NoConflict probe
+                    let aff: String = idx_meta
+                        .columns
+                        .iter()
+                        .map(|c| c.affinity(table.columns()).aff_mask())
+                        .collect();
+
+                    program.emit_insn(Insn::Affinity {
+                        start_reg: ins,
+                        count: NonZeroUsize::new(k).unwrap(),
+                        affinities: aff,
+                    });
+                    program.emit_insn(Insn::NoConflict {
+                        cursor_id: *idx_cid,
+                        target_pc: ok,
+                        record_reg: ins,
+                        num_regs: k,
+                    });
+                    let hit = program.alloc_register();
+                    program.emit_insn(Insn::IdxRowId {
+                        cursor_id: *idx_cid,
+                        dest: hit,
+                    });
+                    program.emit_insn(Insn::Eq {
+                        lhs: new_rowid,
+                        rhs: hit,
+                        target_pc: ok,
+                        flags: CmpInsFlags::default(),
+                        collation: program.curr_collation(),
+                    });
+                }
                 let description = format_unique_violation_desc(table.get_name(), idx_meta);
                 program.emit_insn(Insn::Halt {
                     err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
@@ -619,7 +725,7 @@ pub fn emit_upsert(
                 cursor_id: *idx_cid,
                 record_reg: rec,
                 unpacked_start: Some(ins),
-                unpacked_count: Some((k + 1) as u16),
+                unpacked_count: Some(entry_len as u16),
                 flags: IdxInsertFlags::new().nchange(true),
             });
 
@@ -631,21 +737,59 @@ pub fn emit_upsert(
 
     // Build NEW table payload
     let rec = program.alloc_register();
-    let affinity_str = table
-        .columns()
-        .iter()
-        .map(|c| c.affinity().aff_mask())
-        .collect::<String>();
-    program.emit_insn(Insn::MakeRecord {
-        start_reg: new_start,
-        count: num_cols,
-        dest_reg: rec,
-        index_name: None,
-        affinity_str: Some(affinity_str),
-    });
+    emit_table_record(program, &btree, new_start, rec);
 
-    // If rowid changed, first ensure no other row owns it, then delete+insert
-    if let Some(rnew) = new_rowid_reg {
+    if let (Some(primary_key), Some(new_key_reg)) = (&primary_key, new_key_reg) {
+        // If the PRIMARY KEY changed, first ensure no other row owns it, then delete+insert.
+        // Otherwise the new record overwrites the old one.
+        if primary_key_changed {
+            let num_regs = primary_key.columns.len();
+            let ok = program.allocate_label();
+            let conflict = program.allocate_label();
+            program.emit_insn(Insn::NoConflict {
+                cursor_id: tbl_cursor_id,
+                target_pc: ok,
+                record_reg: new_key_reg,
+                num_regs,
+            });
+            for i in 0..num_regs {
+                program.emit_insn(Insn::Ne {
+                    lhs: new_key_reg + i,
+                    rhs: conflict_rowid_reg + i,
+                    target_pc: conflict,
+                    flags: CmpInsFlags::default(),
+                    collation: program.curr_collation(),
+                });
+            }
+            program.emit_insn(Insn::Goto { target_pc: ok });
+            program.preassign_label_to_next_insn(conflict);
+            program.emit_insn(Insn::Halt {
+                err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+                description: format_unique_violation_desc(table.get_name(), primary_key),
+            });
+            program.preassign_label_to_next_insn(ok);
+            // NoConflict moved the cursor away from the row being updated
+            program.emit_insn(Insn::NotFound {
+                cursor_id: tbl_cursor_id,
+                target_pc: row_done_label,
+                record_reg: conflict_rowid_reg,
+                num_regs,
+            });
+            program.emit_insn(Insn::Delete {
+                cursor_id: tbl_cursor_id,
+                table_name: table.get_name().to_string(),
+            });
+        }
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id: tbl_cursor_id,
+            record_reg: rec,
+            unpacked_start: None,
+            unpacked_count: None,
+            // Deleting the old row already counted the change
+            flags: IdxInsertFlags::new().nchange(!primary_key_changed),
+        });
+    } else if let Some(rnew) = new_rowid_reg {
+        // If rowid changed, first ensure no other row owns it, then delete+insert
         let ok = program.allocate_label();
 
         // If equal to old rowid, skip uniqueness probe
@@ -785,7 +929,7 @@ pub fn emit_upsert(
     // RETURNING from NEW image + final rowid
     if !returning.is_empty() {
         let regs = ReturningValueRegisters {
-            rowid_register: new_rowid_reg.unwrap_or(conflict_rowid_reg),
+            rowid_register: new_rowid_reg.unwrap_or(rowid_reg),
             columns_start_register: new_start,
             num_columns: num_cols,
         };
@@ -798,6 +942,306 @@ pub fn emit_upsert(
     Ok(())
 }
 
+/// Load the columns of the row the table cursor is positioned at into consecutive registers,
+/// returning the first one. The rowid alias column holds the rowid.
+fn emit_row_image(program: &mut ProgramBuilder, table: &Table, tbl_cursor_id: usize) -> usize {
+    let row_start = program.alloc_registers(table.columns().len());
+    for i in 0..table.columns().len() {
+        program.emit_column_or_rowid(tbl_cursor_id, i, row_start + i);
+    }
+    row_start
+}
+
+/// Delete the row whose rowid is in `rowid_reg`, along with its entries in the given indexes.
+/// This is how the REPLACE conflict resolution makes room for a row that conflicts with another
+/// one. Nothing happens if the row does not exist, and the table cursor is left wherever the
+/// delete positions it.
+pub fn emit_replace_delete_row(
+    program: &mut ProgramBuilder,
+    table: &Table,
+    tbl_cursor_id: usize,
+    rowid_reg: usize,
+    idx_cursors: &[(&Index, usize)],
+    resolver: &Resolver,
+) -> crate::Result<()> {
+    let done_label = program.allocate_label();
+    program.emit_insn(Insn::SeekRowid {
+        cursor_id: tbl_cursor_id,
+        src_reg: rowid_reg,
+        target_pc: done_label,
+    });
+    let row_start = emit_row_image(program, table, tbl_cursor_id);
+    for (idx, idx_cursor_id) in idx_cursors {
+        // Rows that do not satisfy the predicate of a partial index have no entry in it
+        let skip_label =
+            eval_partial_pred_for_row_image(program, table, idx, row_start, rowid_reg, resolver)
+                .map(|pred_reg| {
+                    let label = program.allocate_label();
+                    program.emit_insn(Insn::IfNot {
+                        reg: pred_reg,
+                        target_pc: label,
+                        jump_if_null: true,
+                    });
+                    label
+                });
+        let k = idx.columns.len();
+        let key_start = program.alloc_registers(k + 1);
+        for (i, ic) in idx.columns.iter().enumerate() {
+            emit_index_column_for_row_image(
+                program,
+                table,
+                ic,
+                row_start,
+                rowid_reg,
+                key_start + i,
+                resolver,
+            )?;
+        }
+        program.emit_insn(Insn::Copy {
+            src_reg: rowid_reg,
+            dst_reg: key_start + k,
+            extra_amount: 0,
+        });
+        program.emit_insn(Insn::IdxDelete {
+            start_reg: key_start,
+            num_regs: k + 1,
+            cursor_id: *idx_cursor_id,
+            raise_error_if_no_matching_entry: false,
+        });
+        if let Some(label) = skip_label {
+            program.preassign_label_to_next_insn(label);
+        }
+    }
+    program.emit_insn(Insn::Delete {
+        cursor_id: tbl_cursor_id,
+        table_name: table.get_name().to_string(),
+    });
+    program.preassign_label_to_next_insn(done_label);
+    Ok(())
+}
+
+/// Delete the row of a WITHOUT ROWID table whose PRIMARY KEY is in the `num_regs` registers
+/// starting at `key_start_reg`, along with its entries in the given indexes. Like
+/// [emit_replace_delete_row], nothing happens if the row does not exist.
+pub fn emit_replace_delete_without_rowid_row(
+    program: &mut ProgramBuilder,
+    table: &Table,
+    tbl_cursor_id: usize,
+    key_start_reg: usize,
+    num_regs: usize,
+    idx_cursors: &[(&Index, usize)],
+    resolver: &Resolver,
+) -> crate::Result<()> {
+    let done_label = program.allocate_label();
+    program.emit_insn(Insn::NotFound {
+        cursor_id: tbl_cursor_id,
+        target_pc: done_label,
+        record_reg: key_start_reg,
+        num_regs,
+    });
+    let row_start = emit_row_image(program, table, tbl_cursor_id);
+    for (idx, idx_cursor_id) in idx_cursors {
+        // There is no rowid to refer to, the entries end with the PRIMARY KEY instead
+        let skip_label = eval_partial_pred_for_row_image(
+            program,
+            table,
+            idx,
+            row_start,
+            key_start_reg,
+            resolver,
+        )
+        .map(|pred_reg| {
+            let label = program.allocate_label();
+            program.emit_insn(Insn::IfNot {
+                reg: pred_reg,
+                target_pc: label,
+                jump_if_null: true,
+            });
+            label
+        });
+        let entry_len = idx.columns.len() + idx.primary_key_suffix.len();
+        let entry_start = program.alloc_registers(entry_len);
+        for (i, ic) in idx
+            .columns
+            .iter()
+            .chain(idx.primary_key_suffix.iter())
+            .enumerate()
+        {
+            emit_index_column_for_row_image(
+                program,
+                table,
+                ic,
+                row_start,
+                key_start_reg,
+                entry_start + i,
+                resolver,
+            )?;
+        }
+        program.emit_insn(Insn::IdxDelete {
+            start_reg: entry_start,
+            num_regs: entry_len,
+            cursor_id: *idx_cursor_id,
+            raise_error_if_no_matching_entry: false,
+        });
+        if let Some(label) = skip_label {
+            program.preassign_label_to_next_insn(label);
+        }
+    }
+    program.emit_insn(Insn::Delete {
+        cursor_id: tbl_cursor_id,
+        table_name: table.get_name().to_string(),
+    });
+    program.preassign_label_to_next_insn(done_label);
+    Ok(())
+}
+
+/// Like [emit_unique_probe_for_row_image], for a WITHOUT ROWID table whose rows are identified by
+/// their PRIMARY KEY: jumps to `ok_label` if no row other than the one whose PRIMARY KEY is in the
+/// registers starting at `self_key_reg` has the key of the row image. Otherwise falls through with
+/// the PRIMARY KEY of the conflicting row in the returned registers.
+#[allow(clippy::too_many_arguments)]
+pub fn emit_unique_probe_for_without_rowid_row_image(
+    program: &mut ProgramBuilder,
+    table: &Table,
+    idx: &Index,
+    idx_cursor_id: usize,
+    row_start: usize,
+    self_key_reg: usize,
+    ok_label: BranchOffset,
+    resolver: &Resolver,
+) -> crate::Result<usize> {
+    let btree = table
+        .btree()
+        .expect("WITHOUT ROWID table must be a btree table");
+    // Rows that do not satisfy the predicate of a partial index are not constrained by it
+    if let Some(pred_reg) =
+        eval_partial_pred_for_row_image(program, table, idx, row_start, self_key_reg, resolver)
+    {
+        program.emit_insn(Insn::IfNot {
+            reg: pred_reg,
+            target_pc: ok_label,
+            jump_if_null: true,
+        });
+    }
+    let k = idx.columns.len();
+    let key_start = program.alloc_registers(k);
+    for (i, ic) in idx.columns.iter().enumerate() {
+        emit_index_column_for_row_image(
+            program,
+            table,
+            ic,
+            row_start,
+            self_key_reg,
+            key_start + i,
+            resolver,
+        )?;
+    }
+    program.emit_insn(Insn::Affinity {
+        start_reg: key_start,
+        count: NonZeroUsize::new(k).expect("nonzero col count"),
+        affinities: idx
+            .columns
+            .iter()
+            .map(|c| c.affinity(table.columns()).aff_mask())
+            .collect(),
+    });
+    program.emit_insn(Insn::NoConflict {
+        cursor_id: idx_cursor_id,
+        target_pc: ok_label,
+        record_reg: key_start,
+        num_regs: k,
+    });
+    let positions = idx.primary_key_positions(&btree);
+    let conflict_key_reg = program.alloc_registers(positions.len());
+    for (i, pos) in positions.into_iter().enumerate() {
+        program.emit_column_or_rowid(idx_cursor_id, pos, conflict_key_reg + i);
+    }
+    // The key is taken by the row itself if the entry has the same PRIMARY KEY
+    let conflict_label = program.allocate_label();
+    for i in 0..btree.primary_key_columns.len() {
+        program.emit_insn(Insn::Ne {
+            lhs: self_key_reg + i,
+            rhs: conflict_key_reg + i,
+            target_pc: conflict_label,
+            flags: CmpInsFlags::default(),
+            collation: program.curr_collation(),
+        });
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: ok_label,
+    });
+    program.preassign_label_to_next_insn(conflict_label);
+    Ok(conflict_key_reg)
+}
+
+/// Probe a UNIQUE index for the key of a row image, jumping to `ok_label` if no row other than
+/// the one in `self_rowid_reg` has that key. Otherwise falls through with the rowid of the
+/// conflicting row in the returned register.
+#[allow(clippy::too_many_arguments)]
+pub fn emit_unique_probe_for_row_image(
+    program: &mut ProgramBuilder,
+    table: &Table,
+    idx: &Index,
+    idx_cursor_id: usize,
+    row_start: usize,
+    rowid_reg: usize,
+    self_rowid_reg: usize,
+    ok_label: BranchOffset,
+    resolver: &Resolver,
+) -> crate::Result<usize> {
+    // Rows that do not satisfy the predicate of a partial index are not constrained by it
+    if let Some(pred_reg) =
+        eval_partial_pred_for_row_image(program, table, idx, row_start, rowid_reg, resolver)
+    {
+        program.emit_insn(Insn::IfNot {
+            reg: pred_reg,
+            target_pc: ok_label,
+            jump_if_null: true,
+        });
+    }
+    let k = idx.columns.len();
+    let key_start = program.alloc_registers(k);
+    for (i, ic) in idx.columns.iter().enumerate() {
+        emit_index_column_for_row_image(
+            program,
+            table,
+            ic,
+            row_start,
+            rowid_reg,
+            key_start + i,
+            resolver,
+        )?;
+    }
+    program.emit_insn(Insn::Affinity {
+        start_reg: key_start,
+        count: NonZeroUsize::new(k).expect("nonzero col count"),
+        affinities: idx
+            .columns
+            .iter()
+            .map(|c| c.affinity(table.columns()).aff_mask())
+            .collect(),
+    });
+    program.emit_insn(Insn::NoConflict {
+        cursor_id: idx_cursor_id,
+        target_pc: ok_label,
+        record_reg: key_start,
+        num_regs: k,
+    });
+    let conflict_rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::IdxRowId {
+        cursor_id: idx_cursor_id,
+        dest: conflict_rowid_reg,
+    });
+    program.emit_insn(Insn::Eq {
+        lhs: self_rowid_reg,
+        rhs: conflict_rowid_reg,
+        target_pc: ok_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    Ok(conflict_rowid_reg)
+}
+
 /// Normalize the `SET` clause into `(column_index, Expr)` pairs using table layout.
 ///
 /// Supports multi-target row-value SETs: `SET (a, b) = (expr1, expr2)`.
@@ -832,6 +1276,9 @@ pub fn collect_set_clauses_for_upsert(
             let Some(idx) = lookup.get(&normalize_ident(cn.as_str())) else {
                 bail_parse_error!("no such column: {}", cn);
             };
+            if table.columns()[*idx].generated.is_some() {
+                bail_parse_error!("cannot UPDATE generated column \"{}\"", cn);
+            }
             if let Some(existing) = out.iter_mut().find(|(i, _)| *i == *idx) {
                 existing.1 = e;
             } else {
@@ -873,6 +1320,39 @@ fn eval_partial_pred_for_row_image(
     Some(r)
 }
 
+/// Emit the value of a column of an index for a row image: the register of the table column, or
+/// for an index on an expression, the value of the expression.
+fn emit_index_column_for_row_image(
+    prg: &mut ProgramBuilder,
+    table: &Table,
+    ic: &IndexColumn,
+    row_start: usize,
+    rowid_reg: usize,
+    dest_reg: usize,
+    resolver: &Resolver,
+) -> crate::Result<()> {
+    if let Some(expr) = &ic.expr {
+        let mut e = expr.as_ref().clone();
+        rewrite_expr_to_registers(&mut e, table, row_start, rowid_reg, None, None, false)?;
+        translate_expr_no_constant_opt(
+            prg,
+            None,
+            &e,
+            dest_reg,
+            resolver,
+            NoConstantOptReason::RegisterReuse,
+        )?;
+        return Ok(());
+    }
+    let (ci, _) = table.get_column_by_name(&ic.name).unwrap();
+    prg.emit_insn(Insn::Copy {
+        src_reg: row_start + ci,
+        dst_reg: dest_reg,
+        extra_amount: 0,
+    });
+    Ok(())
+}
+
 /// Generic rewriter that maps column references to registers for a given row image.
 ///
 /// - Id/Qualified refs to the *target table* (when `table_name` is provided) resolve
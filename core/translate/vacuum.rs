@@ -0,0 +1,56 @@
+use crate::schema::Schema;
+use crate::translate::emitter::Resolver;
+use crate::translate::expr::translate_expr;
+use crate::translate::{ProgramBuilder, ProgramBuilderOpts};
+use crate::util::normalize_ident;
+use crate::vdbe::insn::Insn;
+use crate::{bail_parse_error, Connection, Result, SymbolTable};
+use std::sync::Arc;
+use turso_parser::ast::{Expr, Name};
+
+/// Translate VACUUM and VACUUM INTO. Only the main database can be vacuumed.
+pub fn translate_vacuum(
+    name: Option<Name>,
+    into: Option<Box<Expr>>,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    if let Some(name) = name {
+        let name = normalize_ident(name.as_str());
+        if name == "temp" || connection.get_attached_database(&name).is_some() {
+            bail_parse_error!(
+                "cannot VACUUM {}: only the main database can be vacuumed",
+                name
+            );
+        }
+        if name != "main" {
+            bail_parse_error!("unknown database {}", name);
+        }
+    }
+
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 0,
+        approx_num_insns: 4,
+        approx_num_labels: 0,
+    });
+
+    let dest = match into {
+        Some(expr) => {
+            let reg = program.alloc_register();
+            let resolver = Resolver::new(schema, syms);
+            translate_expr(&mut program, None, &expr, reg, &resolver)?;
+            // VACUUM INTO only reads the database.
+            program.begin_read_operation();
+            Some(reg)
+        }
+        None => {
+            program.begin_write_operation();
+            None
+        }
+    };
+    program.emit_insn(Insn::Vacuum { db: 0, dest });
+
+    Ok(program)
+}
@@ -22,7 +22,11 @@ pub fn emit_values(
         QueryDestination::CoroutineYield { yield_reg, .. } => {
             emit_values_in_subquery(program, plan, &t_ctx.resolver, yield_reg)?
         }
-        QueryDestination::EphemeralIndex { .. } => emit_toplevel_values(program, plan, t_ctx)?,
+        QueryDestination::EphemeralIndex { .. }
+        | QueryDestination::ExistsSubqueryResult { .. }
+        | QueryDestination::ScalarSubqueryResult { .. } => {
+            emit_toplevel_values(program, plan, t_ctx)?
+        }
         QueryDestination::EphemeralTable { .. } => unreachable!(),
     };
     Ok(reg_result_cols_start)
@@ -168,6 +172,25 @@ fn emit_values_to_destination(
             emit_values_to_index(program, plan, start_reg, row_len);
         }
         QueryDestination::EphemeralTable { .. } => unreachable!(),
+        QueryDestination::ExistsSubqueryResult { result_reg } => {
+            program.emit_insn(Insn::Integer {
+                value: 1,
+                dest: *result_reg,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: end_label,
+            });
+        }
+        QueryDestination::ScalarSubqueryResult { result_reg } => {
+            program.emit_insn(Insn::Copy {
+                src_reg: start_reg,
+                dst_reg: *result_reg,
+                extra_amount: 0,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: end_label,
+            });
+        }
     }
 }
 
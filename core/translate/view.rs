@@ -77,6 +77,8 @@ pub fn translate_create_materialized_view(
         has_rowid: true,
         is_strict: false,
         has_autoincrement: false,
+        foreign_keys: vec![],
+        checks: vec![],
 
         unique_sets: vec![],
     });
@@ -199,7 +201,7 @@ pub fn translate_create_materialized_view(
 
     // Parse schema to load the new view and DBSP state table
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: 0,
         where_clause: Some(format!(
             "name = '{normalized_view_name}' OR name = '{dbsp_table_name}' OR name = '{dbsp_index_name}'"
         )),
@@ -226,8 +228,10 @@ fn create_materialized_view_to_str(view_name: &str, select_stmt: &ast::Select) -
     format!("CREATE MATERIALIZED VIEW {view_name} AS {select_stmt}")
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn translate_create_view(
     schema: &Schema,
+    database_id: usize,
     view_name: &str,
     select_stmt: &ast::Select,
     _columns: &[ast::IndexedColumn],
@@ -257,7 +261,7 @@ pub fn translate_create_view(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
-        db: 0,
+        db: database_id,
     });
 
     // Add the view entry to sqlite_schema
@@ -276,12 +280,12 @@ pub fn translate_create_view(
 
     // Parse schema to load the new view
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: database_id,
         where_clause: Some(format!("name = '{normalized_view_name}'")),
     });
 
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
         value: (schema.schema_version + 1) as i32,
         p5: 0,
@@ -296,6 +300,7 @@ fn create_view_to_str(view_name: &str, select_stmt: &ast::Select) -> String {
 
 pub fn translate_drop_view(
     schema: &Schema,
+    database_id: usize,
     view_name: &str,
     if_exists: bool,
     mut program: ProgramBuilder,
@@ -326,7 +331,7 @@ pub fn translate_drop_view(
                 program.emit_insn(Insn::Destroy {
                     root: btree_table.root_page,
                     former_root_reg: 0, // No autovacuum
-                    is_temp: 0,
+                    db: database_id,
                 });
             }
         }
@@ -339,7 +344,7 @@ pub fn translate_drop_view(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
-        db: 0,
+        db: database_id,
     });
 
     // Allocate registers for searching
@@ -356,6 +361,11 @@ pub fn translate_drop_view(
         dest: type_reg,
         value: "view".to_string(),
     });
+    let trigger_type_reg = program.alloc_register();
+    program.emit_insn(Insn::String8 {
+        dest: trigger_type_reg,
+        value: "trigger".to_string(),
+    });
 
     // Start scanning from the beginning
     let end_loop_label = program.allocate_label();
@@ -377,16 +387,40 @@ pub fn translate_drop_view(
 
     // Check if type == 'view' and name == view_name
     let skip_delete_label = program.allocate_label();
+    let check_trigger_label = program.allocate_label();
+    let delete_label = program.allocate_label();
 
     // Both regular and materialized views are stored as type='view' in sqlite_schema
     program.emit_insn(Insn::Ne {
         lhs: col0_reg,
         rhs: type_reg,
+        target_pc: check_trigger_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+
+    program.emit_insn(Insn::Ne {
+        lhs: col1_reg,
+        rhs: view_name_reg,
         target_pc: skip_delete_label,
         flags: CmpInsFlags::default(),
         collation: program.curr_collation(),
     });
+    program.emit_insn(Insn::Goto {
+        target_pc: delete_label,
+    });
 
+    // Otherwise check if type == 'trigger' and tbl_name == view_name, as the INSTEAD OF
+    // triggers of the view are dropped with it
+    program.preassign_label_to_next_insn(check_trigger_label);
+    program.emit_insn(Insn::Ne {
+        lhs: col0_reg,
+        rhs: trigger_type_reg,
+        target_pc: skip_delete_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    program.emit_column_or_rowid(sqlite_schema_cursor_id, 2, col1_reg);
     program.emit_insn(Insn::Ne {
         lhs: col1_reg,
         rhs: view_name_reg,
@@ -396,6 +430,7 @@ pub fn translate_drop_view(
     });
 
     // Get the rowid and delete this row
+    program.preassign_label_to_next_insn(delete_label);
     program.emit_insn(Insn::RowId {
         cursor_id: sqlite_schema_cursor_id,
         dest: rowid_reg,
@@ -417,7 +452,7 @@ pub fn translate_drop_view(
 
     // Remove the view from the in-memory schema
     program.emit_insn(Insn::DropView {
-        db: 0,
+        db: database_id,
         view_name: normalized_view_name.clone(),
     });
 
@@ -428,7 +463,7 @@ pub fn translate_drop_view(
         value: (schema.schema_version + 1) as i64,
     });
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
         value: (schema.schema_version + 1) as i32,
         p5: 1, // update version
@@ -1,23 +1,29 @@
-use crate::schema::{BTreeTable, Schema, Table};
+use crate::error::SQLITE_ERROR;
+use crate::function::WindowFunc;
+use crate::schema::{Affinity, BTreeTable, Schema, Table};
 use crate::translate::aggregation::{translate_aggregation_step, AggArgumentSource};
 use crate::translate::emitter::{Resolver, TranslateCtx};
-use crate::translate::expr::{walk_expr, walk_expr_mut, WalkControl};
+use crate::translate::expr::{translate_expr, walk_expr, walk_expr_mut, WalkControl};
 use crate::translate::order_by::order_by_sorter_insert;
 use crate::translate::plan::{
     Aggregate, Distinctness, JoinOrderMember, JoinedTable, QueryDestination, ResultSetColumn,
-    SelectPlan, TableReferences, Window,
+    SelectPlan, TableReferences, Window, WindowFunctionKind,
 };
 use crate::translate::planner::resolve_window_and_aggregate_functions;
 use crate::translate::result_row::emit_select_result;
 use crate::util::exprs_are_equivalent;
 use crate::vdbe::builder::{CursorType, ProgramBuilder, TableRefIdCounter};
-use crate::vdbe::insn::{InsertFlags, Insn};
+use crate::vdbe::insn::{CmpInsFlags, InsertFlags, Insn};
 use crate::vdbe::{BranchOffset, CursorID};
 use crate::SymbolTable;
 use std::mem;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 use turso_parser::ast::Name::Ident;
-use turso_parser::ast::{Expr, FunctionTail, Literal, Over, SortOrder, TableInternalId};
+use turso_parser::ast::{
+    Expr, FrameBound, FrameExclude, FrameMode, FunctionTail, Literal, Over, SortOrder,
+    TableInternalId,
+};
 
 const SUBQUERY_DATABASE_ID: usize = 0;
 
@@ -161,6 +167,16 @@ fn prepare_window_subquery(
     // they reference the subquery instead. The original expressions are included in the
     // subquery’s result columns.
     for col in outer_plan.result_columns.iter_mut() {
+        // Keep the name of a subquery column, which would otherwise be derived from the
+        // column of the window subquery it is rewritten into.
+        if col.alias.is_none()
+            && matches!(
+                col.expr,
+                Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. }
+            )
+        {
+            col.alias = Some(col.expr.to_string());
+        }
         rewrite_terminal_expr(
             &mut outer_plan.aggregates,
             &mut col.expr,
@@ -210,6 +226,7 @@ fn prepare_window_subquery(
         distinctness: Distinctness::NonDistinct,
         values: vec![],
         window: None,
+        non_from_clause_subqueries: vec![],
     };
 
     prepare_window_subquery(
@@ -313,6 +330,16 @@ fn rewrite_terminal_expr(
                 Expr::RowId { .. } | Expr::Column { .. } => {
                     rewrite_expr_as_subquery_column(expr, ctx, false);
                 }
+                // A subquery may refer to the tables of the query, so it is pushed down to the
+                // subquery along with them, unless IN compares the value of a window function.
+                Expr::Exists(_) | Expr::Subquery(_) => {
+                    rewrite_expr_as_subquery_column(expr, ctx, false);
+                    return Ok(WalkControl::SkipChildren);
+                }
+                Expr::InSelect { lhs, .. } if !contains_window_function(lhs)? => {
+                    rewrite_expr_as_subquery_column(expr, ctx, false);
+                    return Ok(WalkControl::SkipChildren);
+                }
                 _ => {}
             }
 
@@ -321,26 +348,44 @@ fn rewrite_terminal_expr(
     )
 }
 
+/// Whether the expression calls a window function.
+fn contains_window_function(expr: &Expr) -> crate::Result<bool> {
+    let mut found = false;
+    walk_expr(expr, &mut |expr: &Expr| -> crate::Result<WalkControl> {
+        if let Expr::FunctionCall { filter_over, .. } | Expr::FunctionCallStar { filter_over, .. } =
+            expr
+        {
+            found |= filter_over.over_clause.is_some();
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(found)
+}
+
 fn rewrite_expr_referencing_current_window(
     aggregates: &mut Vec<Aggregate>,
     window_name: String,
     ctx: &mut WindowSubqueryContext,
     expr: &mut Expr,
 ) -> crate::Result<()> {
-    fn normalize_over_clause(filter_over: &mut FunctionTail, window_name: &str) {
-        // FILTER clause is not supported yet. Proper checks elsewhere return appropriate
-        // error messages, and this ensures that nothing slips through unnoticed.
-        assert!(
-            filter_over.filter_clause.is_none(),
-            "FILTER in window functions is not supported"
-        );
+    fn normalize_over_clause(
+        aggregates: &mut Vec<Aggregate>,
+        ctx: &mut WindowSubqueryContext,
+        filter_over: &mut FunctionTail,
+        window_name: &str,
+    ) -> crate::Result<()> {
+        // The FILTER expression is evaluated by the subquery, like the arguments.
+        if let Some(filter) = filter_over.filter_clause.as_deref_mut() {
+            let contains_aggregates = resolve_window_and_aggregate_functions(
+                ctx.schema, ctx.syms, filter, aggregates, None,
+            )?;
+            rewrite_expr_as_subquery_column(filter, ctx, contains_aggregates);
+        }
 
         // Replace inline OVER clause with a reference to the named window.
         // The window name may be user-provided or planner-generated.
-        *filter_over = FunctionTail {
-            filter_clause: None,
-            over_clause: Some(Over::Name(Ident(window_name.to_string()))),
-        };
+        filter_over.over_clause = Some(Over::Name(Ident(window_name.to_string())));
+        Ok(())
     }
 
     match expr {
@@ -361,13 +406,13 @@ fn rewrite_expr_referencing_current_window(
                 order_by.is_empty(),
                 "ORDER BY in window functions is not supported"
             );
-            normalize_over_clause(filter_over, &window_name);
+            normalize_over_clause(aggregates, ctx, filter_over, &window_name)?;
         }
         Expr::FunctionCallStar {
             filter_over,
             name: _,
         } => {
-            normalize_over_clause(filter_over, &window_name);
+            normalize_over_clause(aggregates, ctx, filter_over, &window_name)?;
         }
         _ => unreachable!("only functions can reference windows"),
     }
@@ -433,16 +478,17 @@ pub struct WindowLabels {
 
 #[derive(Debug)]
 pub struct WindowRegisters {
-    /// Stores the ROWID of the last row inserted into the buffer table.
-    /// If NULL, we are before inserting the first row of a new partition.
+    /// Stores the ROWID of the last row inserted into the buffer table. Rows of each partition
+    /// are numbered from 1, so when a partition is flushed this is also the partition size.
     pub rowid: usize,
     /// Start of the register array storing partition key values for the current partition.
     pub partition_start: Option<usize>,
-    /// Start of the register array storing accumulator states for each window function
-    /// (populated by `AggStep` during aggregation).
+    /// Start of the register array storing the state of each window function: accumulators of
+    /// aggregate functions (populated by `AggStep`), or per-partition arguments such as the
+    /// number of buckets of `ntile()`.
     pub acc_start: usize,
-    /// Start of the register array storing current accumulator results for each window function
-    /// (populated by `AggValue` when computing results without clearing accumulators).
+    /// Start of the register array storing the value of each window function for the row
+    /// currently being returned.
     pub acc_result_start: usize,
     /// Stores the address to which control returns after all buffered rows are flushed.
     pub flush_buffer_return_offset: usize,
@@ -452,13 +498,58 @@ pub struct WindowRegisters {
     /// Start of the register array storing column values that need to be propagated
     /// from the subquery to the parent query.
     pub result_columns_start: usize,
-    /// Start of the register array holding ORDER BY column values for the current row.
-    /// These registers are used to detect whether the current row is a "peer"
-    /// (i.e., has identical ORDER BY values to the previous row).
+    /// Start of the register array holding ORDER BY column values of the row under the peer
+    /// cursor. These registers are used to detect whether that row is a "peer"
+    /// (i.e., has identical ORDER BY values to the rows of the current peer group).
     pub new_order_by_columns_start: Option<usize>,
-    /// Start of the register array holding ORDER BY column values from the previous row.
-    /// These are used to compare against the current row to determine peer relationships.
+    /// Start of the register array holding ORDER BY column values of the current peer group.
     pub prev_order_by_columns_start: Option<usize>,
+    /// Position of the row being returned within its partition, starting from 1.
+    pub row_number: usize,
+    /// Position of the first row of the current peer group.
+    pub rank: usize,
+    /// Number of peer groups seen so far in the current partition.
+    pub dense_rank: usize,
+    /// Position of the last row of the current peer group, which is also the end of the
+    /// default window frame for every row of the group.
+    pub peer_group_end: usize,
+    /// Set once the peer cursor has moved past the last row of the partition.
+    pub peer_eof: usize,
+    /// Registers used to evaluate the window frame of the row being returned.
+    pub frame: WindowFrameRegisters,
+}
+
+#[derive(Debug)]
+pub struct WindowFrameRegisters {
+    /// Position of the first row of the frame.
+    pub start: usize,
+    /// Position of the last row of the frame. The frame is empty if it ends before it starts.
+    pub end: usize,
+    /// Value of the `<expr> PRECEDING` or `<expr> FOLLOWING` offset of the start bound.
+    pub start_offset: Option<usize>,
+    /// Value of the `<expr> PRECEDING` or `<expr> FOLLOWING` offset of the end bound.
+    pub end_offset: Option<usize>,
+    /// Scan state of the start bound, for RANGE and GROUPS frames with an offset.
+    pub start_scan: Option<FrameBoundScan>,
+    /// Scan state of the end bound, for RANGE and GROUPS frames with an offset.
+    pub end_scan: Option<FrameBoundScan>,
+    /// Positions of the first and last rows fed to aggregate functions, when the frame is
+    /// maintained incrementally from one row to the next.
+    pub agg_start: usize,
+    pub agg_end: usize,
+}
+
+/// Registers tracking how far a RANGE or GROUPS frame bound with an offset has advanced through
+/// the partition. Such bounds never move backwards as rows are returned, so the scan for the
+/// bound of a row resumes where the scan for the previous row stopped.
+#[derive(Debug)]
+pub struct FrameBoundScan {
+    /// Number of rows the bound has moved past.
+    pub position: usize,
+    /// Peer group number of the row at `position` (GROUPS only).
+    pub group: usize,
+    /// ORDER BY values of the row at `position` (GROUPS only).
+    pub order_by_columns_start: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -467,6 +558,12 @@ pub struct WindowCursors {
     pub buffer_read: CursorID,
     /// Cursor used to write to the ephemeral buffer table
     pub buffer_write: CursorID,
+    /// Cursor that runs ahead of `buffer_read` to find the end of the current peer group
+    /// and to feed the rows of the group to aggregate functions
+    pub buffer_peer: CursorID,
+    /// Cursor used to read other rows of the partition, e.g. for `lag()` or to evaluate
+    /// a custom window frame. Only opened when such a function or frame is present.
+    pub buffer_lookup: Option<CursorID>,
 }
 
 pub fn init_window<'a>(
@@ -500,7 +597,7 @@ pub fn init_window<'a>(
     let order_by_len = window.order_by.len();
     let window_function_count = window.functions.len();
 
-    // An ephemeral table used to buffer rows for the current frame
+    // An ephemeral table used to buffer rows of the current partition
     let buffer_table = Arc::new(BTreeTable {
         root_page: 0,
         // TODO: Generating the name this way may cause collisions with real tables in the
@@ -514,6 +611,8 @@ pub fn init_window<'a>(
         is_strict: false,
         unique_sets: vec![],
         has_autoincrement: false,
+        foreign_keys: vec![],
+        checks: vec![],
     });
     let cursor_buffer_read = program.alloc_cursor_id(CursorType::BTreeTable(buffer_table.clone()));
     let cursor_buffer_write = program.alloc_cursor_id(CursorType::BTreeTable(buffer_table.clone()));
@@ -525,6 +624,26 @@ pub fn init_window<'a>(
         original_cursor_id: cursor_buffer_read,
         new_cursor_id: cursor_buffer_write,
     });
+    let cursor_buffer_peer = program.alloc_cursor_id(CursorType::BTreeTable(buffer_table.clone()));
+    program.emit_insn(Insn::OpenDup {
+        original_cursor_id: cursor_buffer_read,
+        new_cursor_id: cursor_buffer_peer,
+    });
+    let cursor_buffer_lookup = if !window.frame.is_default()
+        || window
+            .functions
+            .iter()
+            .any(|f| matches!(&f.func, WindowFunctionKind::Window(func) if func.needs_row_lookup()))
+    {
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(buffer_table.clone()));
+        program.emit_insn(Insn::OpenDup {
+            original_cursor_id: cursor_buffer_read,
+            new_cursor_id: cursor_id,
+        });
+        Some(cursor_id)
+    } else {
+        None
+    };
 
     // Window function processing is similar to aggregation processing in how results are mapped
     // to registers. Each function expression is stored in `expr_to_reg_cache` along with its
@@ -553,6 +672,31 @@ pub fn init_window<'a>(
             .push((expr, reg_col_start + i));
     }
 
+    // Frame offsets are constant, so they are evaluated once and validated whenever
+    // a partition is returned.
+    let mut translate_offset = |bound: &FrameBound| -> crate::Result<Option<usize>> {
+        match bound {
+            FrameBound::Preceding(expr) | FrameBound::Following(expr) => {
+                let reg = program.alloc_register();
+                translate_expr(program, None, expr, reg, &t_ctx.resolver)?;
+                Ok(Some(reg))
+            }
+            _ => Ok(None),
+        }
+    };
+    let reg_frame_start_offset = translate_offset(&window.frame.start)?;
+    let reg_frame_end_offset = translate_offset(&window.frame.end)?;
+    let mut alloc_frame_bound_scan = |offset: Option<usize>| match window.frame.mode {
+        FrameMode::Range | FrameMode::Groups if offset.is_some() => Some(FrameBoundScan {
+            position: program.alloc_register(),
+            group: program.alloc_register(),
+            order_by_columns_start: alloc_optional_registers(program, order_by_len),
+        }),
+        _ => None,
+    };
+    let frame_start_scan = alloc_frame_bound_scan(reg_frame_start_offset);
+    let frame_end_scan = alloc_frame_bound_scan(reg_frame_end_offset);
+
     t_ctx.meta_window = Some(WindowMetadata {
         labels: WindowLabels {
             flush_buffer: program.allocate_label(),
@@ -572,10 +716,27 @@ pub fn init_window<'a>(
             result_columns_start: reg_col_start,
             prev_order_by_columns_start: alloc_optional_registers(program, order_by_len),
             new_order_by_columns_start: alloc_optional_registers(program, order_by_len),
+            row_number: program.alloc_register(),
+            rank: program.alloc_register(),
+            dense_rank: program.alloc_register(),
+            peer_group_end: program.alloc_register(),
+            peer_eof: program.alloc_register(),
+            frame: WindowFrameRegisters {
+                start: program.alloc_register(),
+                end: program.alloc_register(),
+                start_offset: reg_frame_start_offset,
+                end_offset: reg_frame_end_offset,
+                start_scan: frame_start_scan,
+                end_scan: frame_end_scan,
+                agg_start: program.alloc_register(),
+                agg_end: program.alloc_register(),
+            },
         },
         cursors: WindowCursors {
             buffer_read: cursor_buffer_read,
             buffer_write: cursor_buffer_write,
+            buffer_peer: cursor_buffer_peer,
+            buffer_lookup: cursor_buffer_lookup,
         },
         src_column_count,
         expressions_referencing_subquery,
@@ -641,18 +802,17 @@ fn collect_expressions_referencing_subquery<'a>(
 ///
 /// Note:
 /// The **buffer table** mentioned below is an ephemeral B-tree that temporarily
-/// stores rows for the current window frame.
+/// stores the rows of the current partition.
 ///
 /// High-level overview:
-/// - Each row from the subquery is read, and its ORDER BY columns are loaded into
-///   dedicated registers for comparison and partitioning purposes.
-/// - If the row starts a new partition (based on PARTITION BY columns), the buffer
-///   and accumulators are flushed or reset as needed.
-/// - Rows are compared against the previous row to determine if they are "peers"
-///   (i.e., have the same ORDER BY values). Non-peer rows may trigger flushing
-///   of intermediate results.
+/// - Each row from the subquery is read, and its PARTITION BY columns are compared with the
+///   ones of the previous row.
+/// - If the row starts a new partition, all buffered rows of the previous partition are
+///   returned and the buffer is cleared.
 /// - The row is then inserted into the window’s buffer table.
-/// - Aggregate steps for any window functions are executed.
+///
+/// Window functions are evaluated only once the whole partition is buffered, because some
+/// of them (e.g. `ntile()` or `lead()`) depend on rows that follow the current one.
 pub fn emit_window_loop_source(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
@@ -668,10 +828,7 @@ pub fn emit_window_loop_source(
     } = t_ctx.meta_window.as_ref().expect("missing window metadata");
     let window = plan.window.as_ref().expect("missing window");
 
-    emit_load_order_by_columns(program, window, registers);
     emit_flush_buffer_if_new_partition(program, labels, registers, window);
-    emit_reset_state_if_new_partition(program, registers, window);
-    emit_flush_buffer_if_not_peer(program, labels, registers, window);
     emit_insert_row_into_buffer(
         program,
         registers,
@@ -679,7 +836,6 @@ pub fn emit_window_loop_source(
         input_column_count,
         buffer_table_name,
     );
-    emit_aggregation_step(program, window, &t_ctx.resolver, plan, registers)?;
 
     Ok(())
 }
@@ -722,11 +878,6 @@ fn emit_flush_buffer_if_new_partition(
             target_pc: labels.flush_buffer,
             return_reg: registers.flush_buffer_return_offset,
         });
-        // Reset rowid to signal the start of processing a new partition.
-        program.emit_insn(Insn::Null {
-            dest: registers.rowid,
-            dest_end: None,
-        });
         program.emit_insn(Insn::Copy {
             src_reg: registers.src_columns_start,
             dst_reg: reg_partition_start,
@@ -737,111 +888,6 @@ fn emit_flush_buffer_if_new_partition(
     }
 }
 
-fn emit_reset_state_if_new_partition(
-    program: &mut ProgramBuilder,
-    registers: &WindowRegisters,
-    window: &Window,
-) {
-    let label_skip_reset_state = program.allocate_label();
-
-    // If rowid is null, it means we are starting a new partition. It was either set by the code
-    // initializing window processing or by code detecting the start of a new partition.
-    program.emit_insn(Insn::NotNull {
-        reg: registers.rowid,
-        target_pc: label_skip_reset_state,
-    });
-    if let Some(dst_reg_start) = registers.new_order_by_columns_start {
-        // Initialize previous ORDER BY values for the new partition. The first row of the
-        // partition is compared to itself, not to the row from the previous partition.
-        program.add_comment(
-            program.offset(),
-            "initialize previous peer register for new partition",
-        );
-        program.emit_insn(Insn::Copy {
-            src_reg: dst_reg_start,
-            dst_reg: registers
-                .prev_order_by_columns_start
-                .expect("prev_order_by_columns_start must exist"),
-            extra_amount: window.order_by.len() - 1,
-        });
-    }
-    // Since this is a new partition, we must reset accumulator registers.
-    program.add_comment(program.offset(), "reset accumulator registers");
-    program.emit_insn(Insn::Null {
-        dest: registers.acc_start,
-        dest_end: Some(registers.acc_start + window.functions.len() - 1),
-    });
-
-    program.preassign_label_to_next_insn(label_skip_reset_state);
-}
-
-fn emit_flush_buffer_if_not_peer(
-    program: &mut ProgramBuilder,
-    labels: &WindowLabels,
-    registers: &WindowRegisters,
-    window: &Window,
-) {
-    if let Some(reg_new_order_by_columns_start) = registers.new_order_by_columns_start {
-        let label_peer = program.allocate_label();
-        let label_not_peer = program.allocate_label();
-        let order_by_len = window.order_by.len();
-        let reg_prev_order_by_columns_start = registers
-            .prev_order_by_columns_start
-            .expect("prev_order_by_columns_start must exist");
-
-        program.add_comment(program.offset(), "compare ORDER BY columns to detect peer");
-        program.emit_insn(Insn::Compare {
-            start_reg_a: reg_prev_order_by_columns_start,
-            start_reg_b: reg_new_order_by_columns_start,
-            count: order_by_len,
-            collation: program.curr_collation(),
-        });
-        program.emit_insn(Insn::Jump {
-            target_pc_lt: label_not_peer,
-            target_pc_eq: label_peer,
-            target_pc_gt: label_not_peer,
-        });
-
-        program.resolve_label(label_not_peer, program.offset());
-        program.add_comment(program.offset(), "detected non-peer row");
-        program.emit_insn(Insn::Gosub {
-            target_pc: labels.flush_buffer,
-            return_reg: registers.flush_buffer_return_offset,
-        });
-        program.emit_insn(Insn::Copy {
-            src_reg: reg_new_order_by_columns_start,
-            dst_reg: reg_prev_order_by_columns_start,
-            extra_amount: order_by_len - 1,
-        });
-
-        program.resolve_label(label_peer, program.offset());
-    }
-}
-
-fn emit_load_order_by_columns(
-    program: &mut ProgramBuilder,
-    window: &Window,
-    registers: &WindowRegisters,
-) {
-    if let Some(reg_new_order_by_columns_start) = registers.new_order_by_columns_start {
-        // Source columns are deduplicated and may appear in a different order than
-        // the ORDER BY terms. Therefore, we must restore the original ORDER BY layout
-        // here by copying the values into an array of registers.
-        for (i, (expr, _)) in window.order_by.iter().enumerate() {
-            match expr {
-                Expr::Column { column, .. } => {
-                    program.emit_insn(Insn::Copy {
-                        src_reg: registers.src_columns_start + column,
-                        dst_reg: reg_new_order_by_columns_start + i,
-                        extra_amount: 0,
-                    });
-                }
-                _ => unreachable!("expected Column, got {:?}", expr),
-            }
-        }
-    }
-}
-
 fn emit_insert_row_into_buffer(
     program: &mut ProgramBuilder,
     registers: &WindowRegisters,
@@ -872,36 +918,6 @@ fn emit_insert_row_into_buffer(
     });
 }
 
-fn emit_aggregation_step(
-    program: &mut ProgramBuilder,
-    window: &Window,
-    resolver: &Resolver,
-    plan: &SelectPlan,
-    registers: &WindowRegisters,
-) -> crate::Result<()> {
-    for (i, func) in window.functions.iter().enumerate() {
-        // The aggregation step is performed incrementally as each row from the subquery is
-        // processed. Therefore, we don’t need to access the buffer table and can obtain argument
-        // values directly by evaluating the expressions that reference the subquery result columns.
-        let args = match &func.original_expr {
-            Expr::FunctionCall { args, .. } => args.iter().map(|a| (**a).clone()).collect(),
-            Expr::FunctionCallStar { .. } => vec![],
-            _ => unreachable!("All window functions should be either FunctionCall or FunctionCallStar expressions"),
-        };
-
-        let reg_acc_start = registers.acc_start + i;
-        translate_aggregation_step(
-            program,
-            &plan.table_references,
-            AggArgumentSource::new_from_expression(&func.func, &args, &Distinctness::NonDistinct),
-            reg_acc_start,
-            resolver,
-        )?;
-    }
-
-    Ok(())
-}
-
 /// Emits bytecode to output all buffered rows produced by window processing.
 ///
 /// The generated code has two possible entry points:
@@ -947,7 +963,7 @@ pub fn emit_window_results(
         pc_if_empty: label_empty,
     });
 
-    emit_return_buffered_rows(program, window, t_ctx, plan)?;
+    emit_return_buffered_rows(program, window, t_ctx, plan, label_empty)?;
 
     program.resolve_label(label_empty, program.offset());
 
@@ -964,11 +980,18 @@ pub fn emit_window_results(
     Ok(())
 }
 
+/// Emits bytecode returning all rows of the buffered partition, one peer group at a time.
+///
+/// For each peer group, the peer cursor walks over the rows of the group, feeding them to
+/// aggregate functions, until it reaches a row with different ORDER BY values (or the end
+/// of the partition). Then the read cursor walks over the same rows, evaluating the remaining
+/// window functions and returning each row.
 fn emit_return_buffered_rows(
     program: &mut ProgramBuilder,
     window: &Window,
     t_ctx: &mut TranslateCtx,
     plan: &SelectPlan,
+    label_empty: BranchOffset,
 ) -> crate::Result<()> {
     let WindowMetadata {
         labels,
@@ -978,17 +1001,25 @@ fn emit_return_buffered_rows(
         ..
     } = t_ctx.meta_window.as_ref().expect("missing window metadata");
 
-    for (i, func) in window.functions.iter().enumerate() {
-        program.emit_insn(Insn::AggValue {
-            acc_reg: registers.acc_start + i,
-            dest_reg: registers.acc_result_start + i,
-            func: func.func.clone(),
-        });
-    }
-
+    let label_peer_group_start = program.allocate_label();
+    let label_row_start = program.allocate_label();
     let label_skip_returning_row = program.allocate_label();
-    let label_loop_start = program.allocate_label();
-    program.preassign_label_to_next_insn(label_loop_start);
+    let label_peer_group_returned = program.allocate_label();
+    let label_partition_returned = program.allocate_label();
+
+    emit_reset_partition_state(program, window, registers, cursors, label_empty);
+
+    program.preassign_label_to_next_insn(label_peer_group_start);
+    emit_aggregate_peer_group(program, window, &t_ctx.resolver, plan, registers, cursors)?;
+
+    program.preassign_label_to_next_insn(label_row_start);
+    program.emit_insn(Insn::RowId {
+        cursor_id: cursors.buffer_read,
+        dest: registers.row_number,
+    });
+    emit_frame_bounds(program, window, registers, cursors);
+    emit_window_function_values(program, window, registers, cursors);
+    emit_frame_functions(program, window, &t_ctx.resolver, plan, registers, cursors)?;
 
     // Propagate subquery result column values to the outer query (if any) or directly to
     // the final output that will be returned to the user, by copying them from the buffer table
@@ -1025,10 +1056,1468 @@ fn emit_return_buffered_rows(
         program.preassign_label_to_next_insn(distinct_ctx.label_on_conflict);
     }
 
+    let WindowMetadata {
+        registers, cursors, ..
+    } = t_ctx.meta_window.as_ref().expect("missing window metadata");
+
+    // Move on to the next row of the peer group, or to the next peer group.
+    program.emit_insn(Insn::Eq {
+        lhs: registers.row_number,
+        rhs: registers.peer_group_end,
+        target_pc: label_peer_group_returned,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    program.emit_insn(Insn::Next {
+        cursor_id: cursors.buffer_read,
+        pc_if_next: label_row_start,
+    });
+    program.preassign_label_to_next_insn(label_peer_group_returned);
+    program.emit_insn(Insn::If {
+        reg: registers.peer_eof,
+        target_pc: label_partition_returned,
+        jump_if_null: false,
+    });
     program.emit_insn(Insn::Next {
         cursor_id: cursors.buffer_read,
-        pc_if_next: label_loop_start,
+        pc_if_next: label_peer_group_start,
     });
+    program.preassign_label_to_next_insn(label_partition_returned);
 
     Ok(())
 }
+
+/// Emits bytecode resetting the window function state at the start of a partition.
+/// The read cursor must be positioned at the first row of the partition.
+fn emit_reset_partition_state(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    registers: &WindowRegisters,
+    cursors: &WindowCursors,
+    label_empty: BranchOffset,
+) {
+    program.add_comment(program.offset(), "reset window function state");
+    program.emit_insn(Insn::Null {
+        dest: registers.acc_start,
+        dest_end: Some(registers.acc_start + window.functions.len() - 1),
+    });
+    for reg in [
+        registers.dense_rank,
+        registers.peer_group_end,
+        registers.peer_eof,
+    ] {
+        program.emit_insn(Insn::Integer {
+            value: 0,
+            dest: reg,
+        });
+    }
+    program.emit_insn(Insn::Rewind {
+        cursor_id: cursors.buffer_peer,
+        pc_if_empty: label_empty,
+    });
+
+    // The number of buckets of ntile() is taken from the first row of the partition.
+    for (i, func) in window.functions.iter().enumerate() {
+        if !matches!(func.func, WindowFunctionKind::Window(WindowFunc::Ntile)) {
+            continue;
+        }
+        let reg_buckets = registers.acc_start + i;
+        let label_valid = program.allocate_label();
+        program.emit_column_or_rowid(
+            cursors.buffer_read,
+            window_function_arg_column(&func.original_expr, 0),
+            reg_buckets,
+        );
+        program.emit_insn(Insn::Cast {
+            reg: reg_buckets,
+            affinity: Affinity::Integer,
+        });
+        program.emit_insn(Insn::IfPos {
+            reg: reg_buckets,
+            target_pc: label_valid,
+            decrement_by: 0,
+        });
+        program.emit_insn(Insn::Halt {
+            err_code: SQLITE_ERROR,
+            description: "argument of ntile must be a positive integer".to_string(),
+        });
+        program.preassign_label_to_next_insn(label_valid);
+    }
+
+    let frame = &registers.frame;
+    if let Some(reg_offset) = frame.start_offset {
+        emit_validate_frame_offset(program, window.frame.mode, reg_offset, "starting");
+    }
+    if let Some(reg_offset) = frame.end_offset {
+        emit_validate_frame_offset(program, window.frame.mode, reg_offset, "ending");
+    }
+    for scan in [&frame.start_scan, &frame.end_scan].into_iter().flatten() {
+        program.emit_insn(Insn::Integer {
+            value: 0,
+            dest: scan.position,
+        });
+        program.emit_insn(Insn::Integer {
+            value: 0,
+            dest: scan.group,
+        });
+    }
+    program.emit_insn(Insn::Integer {
+        value: 1,
+        dest: frame.agg_start,
+    });
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: frame.agg_end,
+    });
+}
+
+/// Emits bytecode checking that a frame offset is a non-negative integer, or a non-negative
+/// number for RANGE frames.
+fn emit_validate_frame_offset(
+    program: &mut ProgramBuilder,
+    mode: FrameMode,
+    reg_offset: usize,
+    bound: &str,
+) {
+    let label_invalid = program.allocate_label();
+    let label_valid = program.allocate_label();
+    program.emit_insn(Insn::IsNull {
+        reg: reg_offset,
+        target_pc: label_invalid,
+    });
+    program.emit_insn(Insn::Affinity {
+        start_reg: reg_offset,
+        count: NonZeroUsize::new(1).unwrap(),
+        affinities: Affinity::Numeric.aff_mask().to_string(),
+    });
+    let reg_tmp = program.alloc_register();
+    let description = if mode == FrameMode::Range {
+        // Text and blobs compare greater than any number.
+        program.emit_insn(Insn::String8 {
+            value: String::new(),
+            dest: reg_tmp,
+        });
+        program.emit_insn(Insn::Ge {
+            lhs: reg_offset,
+            rhs: reg_tmp,
+            target_pc: label_invalid,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::IfNeg {
+            reg: reg_offset,
+            target_pc: label_invalid,
+        });
+        format!("frame {bound} offset must be a non-negative number")
+    } else {
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_offset,
+            dst_reg: reg_tmp,
+            extra_amount: 0,
+        });
+        program.emit_insn(Insn::Cast {
+            reg: reg_tmp,
+            affinity: Affinity::Integer,
+        });
+        program.emit_insn(Insn::Ne {
+            lhs: reg_offset,
+            rhs: reg_tmp,
+            target_pc: label_invalid,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::IfNeg {
+            reg: reg_tmp,
+            target_pc: label_invalid,
+        });
+        // Store the offset as an integer, so that row positions computed from it are integers.
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_tmp,
+            dst_reg: reg_offset,
+            extra_amount: 0,
+        });
+        format!("frame {bound} offset must be a non-negative integer")
+    };
+    program.emit_insn(Insn::Goto {
+        target_pc: label_valid,
+    });
+    program.preassign_label_to_next_insn(label_invalid);
+    program.emit_insn(Insn::Halt {
+        err_code: SQLITE_ERROR,
+        description,
+    });
+    program.preassign_label_to_next_insn(label_valid);
+}
+
+/// Emits bytecode that advances the peer cursor past the current peer group and, for the
+/// default frame, feeds its rows to aggregate functions and computes the values of those
+/// functions for the group. Aggregates over other frames are computed for each row instead,
+/// see [emit_frame_functions].
+fn emit_aggregate_peer_group(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    registers: &WindowRegisters,
+    cursors: &WindowCursors,
+) -> crate::Result<()> {
+    let label_step = program.allocate_label();
+    let label_peer_next = program.allocate_label();
+    let label_peer_group_end = program.allocate_label();
+
+    program.emit_insn(Insn::Copy {
+        src_reg: registers.peer_group_end,
+        dst_reg: registers.rank,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::AddImm {
+        register: registers.rank,
+        value: 1,
+    });
+    program.emit_insn(Insn::AddImm {
+        register: registers.dense_rank,
+        value: 1,
+    });
+    if let Some(reg_prev_order_by_columns_start) = registers.prev_order_by_columns_start {
+        emit_load_order_by_columns(
+            program,
+            window,
+            cursors.buffer_peer,
+            reg_prev_order_by_columns_start,
+        );
+    }
+
+    program.preassign_label_to_next_insn(label_step);
+    if window.frame.is_default() {
+        emit_aggregate_step(
+            program,
+            window,
+            resolver,
+            plan,
+            registers,
+            cursors.buffer_peer,
+        )?;
+    }
+    program.emit_insn(Insn::AddImm {
+        register: registers.peer_group_end,
+        value: 1,
+    });
+    program.emit_insn(Insn::Next {
+        cursor_id: cursors.buffer_peer,
+        pc_if_next: label_peer_next,
+    });
+    program.emit_insn(Insn::Integer {
+        value: 1,
+        dest: registers.peer_eof,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_peer_group_end,
+    });
+
+    program.preassign_label_to_next_insn(label_peer_next);
+    match (
+        registers.prev_order_by_columns_start,
+        registers.new_order_by_columns_start,
+    ) {
+        (Some(reg_prev_order_by_columns_start), Some(reg_new_order_by_columns_start)) => {
+            emit_load_order_by_columns(
+                program,
+                window,
+                cursors.buffer_peer,
+                reg_new_order_by_columns_start,
+            );
+            program.add_comment(program.offset(), "compare ORDER BY columns to detect peer");
+            program.emit_insn(Insn::Compare {
+                start_reg_a: reg_prev_order_by_columns_start,
+                start_reg_b: reg_new_order_by_columns_start,
+                count: window.order_by.len(),
+                collation: program.curr_collation(),
+            });
+            program.emit_insn(Insn::Jump {
+                target_pc_lt: label_peer_group_end,
+                target_pc_eq: label_step,
+                target_pc_gt: label_peer_group_end,
+            });
+        }
+        _ => {
+            // Without ORDER BY, all rows of the partition are peers.
+            program.emit_insn(Insn::Goto {
+                target_pc: label_step,
+            });
+        }
+    }
+
+    program.preassign_label_to_next_insn(label_peer_group_end);
+    if window.frame.is_default() {
+        emit_aggregate_values(program, window, registers);
+    }
+
+    Ok(())
+}
+
+/// Emits bytecode feeding the row under `cursor_id` to all aggregate functions.
+fn emit_aggregate_step(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    registers: &WindowRegisters,
+    cursor_id: CursorID,
+) -> crate::Result<()> {
+    for (i, func) in window.functions.iter().enumerate() {
+        let WindowFunctionKind::Agg(agg_func) = &func.func else {
+            continue;
+        };
+        let label_skip = program.allocate_label();
+        emit_aggregate_filter(program, &func.original_expr, cursor_id, label_skip);
+        let args = emit_aggregate_args(program, &func.original_expr, cursor_id);
+        translate_aggregation_step(
+            program,
+            &plan.table_references,
+            AggArgumentSource::new_from_expression(agg_func, &args, &Distinctness::NonDistinct),
+            registers.acc_start + i,
+            resolver,
+        )?;
+        program.preassign_label_to_next_insn(label_skip);
+    }
+    Ok(())
+}
+
+/// Emits bytecode removing the row under `cursor_id` from all aggregate functions.
+fn emit_aggregate_inverse(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    registers: &WindowRegisters,
+    cursor_id: CursorID,
+) {
+    for (i, func) in window.functions.iter().enumerate() {
+        let WindowFunctionKind::Agg(agg_func) = &func.func else {
+            continue;
+        };
+        let label_skip = program.allocate_label();
+        emit_aggregate_filter(program, &func.original_expr, cursor_id, label_skip);
+        let args = emit_aggregate_args(program, &func.original_expr, cursor_id);
+        let col = match args.first() {
+            Some(Expr::Register(reg)) => *reg,
+            // count(*) has no argument to read.
+            _ => program.alloc_register(),
+        };
+        program.emit_insn(Insn::AggInverse {
+            acc_reg: registers.acc_start + i,
+            col,
+            func: agg_func.clone(),
+        });
+        program.preassign_label_to_next_insn(label_skip);
+    }
+}
+
+fn emit_aggregate_values(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    registers: &WindowRegisters,
+) {
+    for (i, func) in window.functions.iter().enumerate() {
+        if let WindowFunctionKind::Agg(agg_func) = &func.func {
+            program.emit_insn(Insn::AggValue {
+                acc_reg: registers.acc_start + i,
+                dest_reg: registers.acc_result_start + i,
+                func: agg_func.clone(),
+            });
+        }
+    }
+}
+
+/// Emits bytecode jumping to `label_skip` if the row under `cursor_id` is rejected by the FILTER
+/// clause of an aggregate function.
+fn emit_aggregate_filter(
+    program: &mut ProgramBuilder,
+    expr: &Expr,
+    cursor_id: CursorID,
+    label_skip: BranchOffset,
+) {
+    let (Expr::FunctionCall { filter_over, .. } | Expr::FunctionCallStar { filter_over, .. }) =
+        expr
+    else {
+        return;
+    };
+    match filter_over.filter_clause.as_deref() {
+        Some(Expr::Column { column, .. }) => {
+            let reg = program.alloc_register();
+            program.emit_column_or_rowid(cursor_id, *column, reg);
+            program.emit_insn(Insn::IfNot {
+                reg,
+                target_pc: label_skip,
+                jump_if_null: true,
+            });
+        }
+        Some(filter) => unreachable!("expected Column, got {:?}", filter),
+        None => {}
+    }
+}
+
+/// Emits bytecode reading the arguments of an aggregate function from the row under
+/// `cursor_id`. Arguments reference subquery columns, which are read from the buffered row
+/// instead of the subquery's result registers.
+fn emit_aggregate_args(
+    program: &mut ProgramBuilder,
+    expr: &Expr,
+    cursor_id: CursorID,
+) -> Vec<Expr> {
+    match expr {
+        Expr::FunctionCall { args, .. } => args
+            .iter()
+            .map(|arg| match arg.as_ref() {
+                Expr::Column { column, .. } => {
+                    let reg = program.alloc_register();
+                    program.emit_column_or_rowid(cursor_id, *column, reg);
+                    Expr::Register(reg)
+                }
+                _ => unreachable!("expected Column, got {:?}", arg),
+            })
+            .collect(),
+        Expr::FunctionCallStar { .. } => vec![],
+        _ => unreachable!(
+            "All window functions should be either FunctionCall or FunctionCallStar expressions"
+        ),
+    }
+}
+
+/// Emits bytecode computing the positions of the first and last rows of the window frame of
+/// the row under the read cursor into `registers.frame`.
+fn emit_frame_bounds(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    registers: &WindowRegisters,
+    cursors: &WindowCursors,
+) {
+    let frame = &registers.frame;
+    emit_frame_bound(
+        program,
+        window,
+        registers,
+        cursors,
+        &window.frame.start,
+        frame.start_offset,
+        frame.start_scan.as_ref(),
+        frame.start,
+    );
+    emit_frame_bound(
+        program,
+        window,
+        registers,
+        cursors,
+        &window.frame.end,
+        frame.end_offset,
+        frame.end_scan.as_ref(),
+        frame.end,
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+fn emit_frame_bound(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    registers: &WindowRegisters,
+    cursors: &WindowCursors,
+    bound: &FrameBound,
+    reg_offset: Option<usize>,
+    scan: Option<&FrameBoundScan>,
+    dest: usize,
+) {
+    let is_start = dest == registers.frame.start;
+    let reg_partition_size = registers.rowid;
+    match (bound, window.frame.mode) {
+        (FrameBound::UnboundedPreceding, _) => {
+            program.emit_insn(Insn::Integer { value: 1, dest });
+        }
+        (FrameBound::UnboundedFollowing, _) => {
+            emit_copy_add_imm(program, reg_partition_size, dest, 0);
+        }
+        (FrameBound::CurrentRow, FrameMode::Rows) => {
+            emit_copy_add_imm(program, registers.row_number, dest, 0);
+        }
+        (FrameBound::CurrentRow, _) => {
+            // In RANGE and GROUPS frames, the current row includes all of its peers.
+            let src_reg = if is_start {
+                registers.rank
+            } else {
+                registers.peer_group_end
+            };
+            emit_copy_add_imm(program, src_reg, dest, 0);
+        }
+        (FrameBound::Preceding(_) | FrameBound::Following(_), FrameMode::Rows) => {
+            let reg_offset = reg_offset.expect("frame offset must be evaluated");
+            let label_done = program.allocate_label();
+            program.emit_insn(if matches!(bound, FrameBound::Preceding(_)) {
+                Insn::Subtract {
+                    lhs: registers.row_number,
+                    rhs: reg_offset,
+                    dest,
+                }
+            } else {
+                Insn::Add {
+                    lhs: registers.row_number,
+                    rhs: reg_offset,
+                    dest,
+                }
+            });
+            // Keep the bound within the partition.
+            if is_start {
+                program.emit_insn(Insn::IfPos {
+                    reg: dest,
+                    target_pc: label_done,
+                    decrement_by: 0,
+                });
+                program.emit_insn(Insn::Integer { value: 1, dest });
+            } else {
+                program.emit_insn(Insn::Le {
+                    lhs: dest,
+                    rhs: reg_partition_size,
+                    target_pc: label_done,
+                    flags: CmpInsFlags::default(),
+                    collation: None,
+                });
+                emit_copy_add_imm(program, reg_partition_size, dest, 0);
+            }
+            program.preassign_label_to_next_insn(label_done);
+        }
+        (FrameBound::Preceding(_) | FrameBound::Following(_), FrameMode::Range) => {
+            emit_range_frame_bound(
+                program,
+                window,
+                registers,
+                cursors,
+                bound,
+                reg_offset.expect("frame offset must be evaluated"),
+                scan.expect("frame bound scan registers must be allocated"),
+                dest,
+            );
+        }
+        (FrameBound::Preceding(_) | FrameBound::Following(_), FrameMode::Groups) => {
+            emit_groups_frame_bound(
+                program,
+                window,
+                registers,
+                cursors,
+                bound,
+                reg_offset.expect("frame offset must be evaluated"),
+                scan.expect("frame bound scan registers must be allocated"),
+                dest,
+            );
+        }
+    }
+}
+
+/// Emits bytecode finding a `<expr> PRECEDING` or `<expr> FOLLOWING` bound of a RANGE frame,
+/// i.e. the first (start bound) or last (end bound) row whose ORDER BY value lies within the
+/// offset of the current row's value.
+///
+/// NULL values sort before all other values, and text and blobs after all numbers. As in
+/// SQLite, the frame of a row with a NULL value spans its peers, and the offset is not applied
+/// to text and blob values.
+#[allow(clippy::too_many_arguments)]
+fn emit_range_frame_bound(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    registers: &WindowRegisters,
+    cursors: &WindowCursors,
+    bound: &FrameBound,
+    reg_offset: usize,
+    scan: &FrameBoundScan,
+    dest: usize,
+) {
+    let is_start = dest == registers.frame.start;
+    let cursor_lookup = cursors
+        .buffer_lookup
+        .expect("lookup cursor must be opened for custom frames");
+    let (order_by_expr, sort_order) = &window.order_by[0];
+    let key_column = match order_by_expr {
+        Expr::Column { column, .. } => *column,
+        _ => unreachable!("expected Column, got {:?}", order_by_expr),
+    };
+    let label_null_key = program.allocate_label();
+    let label_bound_value = program.allocate_label();
+    let label_scan = program.allocate_label();
+    let label_advance = program.allocate_label();
+    let label_scan_done = program.allocate_label();
+    let label_done = program.allocate_label();
+    let reg_key = program.alloc_register();
+    let reg_bound_value = program.alloc_register();
+    let reg_next = program.alloc_register();
+    let reg_next_key = program.alloc_register();
+
+    program.emit_column_or_rowid(cursors.buffer_read, key_column, reg_key);
+    program.emit_insn(Insn::IsNull {
+        reg: reg_key,
+        target_pc: label_null_key,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_key,
+        dst_reg: reg_bound_value,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::String8 {
+        value: String::new(),
+        dest: reg_next_key,
+    });
+    program.emit_insn(Insn::Ge {
+        lhs: reg_key,
+        rhs: reg_next_key,
+        target_pc: label_bound_value,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    // Rows preceding the current one have smaller values in ascending order.
+    let subtract = matches!(bound, FrameBound::Preceding(_)) == (*sort_order == SortOrder::Asc);
+    program.emit_insn(if subtract {
+        Insn::Subtract {
+            lhs: reg_key,
+            rhs: reg_offset,
+            dest: reg_bound_value,
+        }
+    } else {
+        Insn::Add {
+            lhs: reg_key,
+            rhs: reg_offset,
+            dest: reg_bound_value,
+        }
+    });
+    program.preassign_label_to_next_insn(label_bound_value);
+
+    // Move the bound past all rows that come before the frame (start bound), or that are
+    // still within the frame (end bound).
+    program.preassign_label_to_next_insn(label_scan);
+    emit_copy_add_imm(program, scan.position, reg_next, 1);
+    program.emit_insn(Insn::Gt {
+        lhs: reg_next,
+        rhs: registers.rowid,
+        target_pc: label_scan_done,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    program.emit_insn(Insn::SeekRowid {
+        cursor_id: cursor_lookup,
+        src_reg: reg_next,
+        target_pc: label_scan_done,
+    });
+    program.emit_column_or_rowid(cursor_lookup, key_column, reg_next_key);
+    let cmp = |lhs, rhs, target_pc| match (sort_order, is_start) {
+        (SortOrder::Asc, true) => Insn::Lt {
+            lhs,
+            rhs,
+            target_pc,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        },
+        (SortOrder::Asc, false) => Insn::Le {
+            lhs,
+            rhs,
+            target_pc,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        },
+        (SortOrder::Desc, true) => Insn::Gt {
+            lhs,
+            rhs,
+            target_pc,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        },
+        (SortOrder::Desc, false) => Insn::Ge {
+            lhs,
+            rhs,
+            target_pc,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        },
+    };
+    program.emit_insn(Insn::IsNull {
+        reg: reg_next_key,
+        target_pc: if *sort_order == SortOrder::Asc {
+            label_advance
+        } else {
+            label_scan_done
+        },
+    });
+    program.emit_insn(cmp(reg_next_key, reg_bound_value, label_advance));
+    program.emit_insn(Insn::Goto {
+        target_pc: label_scan_done,
+    });
+    program.preassign_label_to_next_insn(label_advance);
+    program.emit_insn(Insn::AddImm {
+        register: scan.position,
+        value: 1,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_scan,
+    });
+
+    program.preassign_label_to_next_insn(label_scan_done);
+    emit_copy_add_imm(program, scan.position, dest, if is_start { 1 } else { 0 });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_done,
+    });
+
+    program.preassign_label_to_next_insn(label_null_key);
+    let src_reg = if is_start {
+        registers.rank
+    } else {
+        registers.peer_group_end
+    };
+    emit_copy_add_imm(program, src_reg, dest, 0);
+    program.preassign_label_to_next_insn(label_done);
+}
+
+/// Emits bytecode finding a `<expr> PRECEDING` or `<expr> FOLLOWING` bound of a GROUPS frame,
+/// i.e. the first row of the first peer group (start bound) or the last row of the last peer
+/// group (end bound) within the offset of the current row's peer group.
+#[allow(clippy::too_many_arguments)]
+fn emit_groups_frame_bound(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    registers: &WindowRegisters,
+    cursors: &WindowCursors,
+    bound: &FrameBound,
+    reg_offset: usize,
+    scan: &FrameBoundScan,
+    dest: usize,
+) {
+    let is_start = dest == registers.frame.start;
+    let cursor_lookup = cursors
+        .buffer_lookup
+        .expect("lookup cursor must be opened for custom frames");
+    let label_scan = program.allocate_label();
+    let label_scan_done = program.allocate_label();
+    let reg_last_group = program.alloc_register();
+    let reg_next = program.alloc_register();
+    let reg_next_group = program.alloc_register();
+
+    // The bound moves past all rows whose group number is at most `reg_last_group`.
+    program.emit_insn(if matches!(bound, FrameBound::Preceding(_)) {
+        Insn::Subtract {
+            lhs: registers.dense_rank,
+            rhs: reg_offset,
+            dest: reg_last_group,
+        }
+    } else {
+        Insn::Add {
+            lhs: registers.dense_rank,
+            rhs: reg_offset,
+            dest: reg_last_group,
+        }
+    });
+    if is_start {
+        program.emit_insn(Insn::AddImm {
+            register: reg_last_group,
+            value: -1,
+        });
+    }
+
+    program.preassign_label_to_next_insn(label_scan);
+    emit_copy_add_imm(program, scan.position, reg_next, 1);
+    program.emit_insn(Insn::Gt {
+        lhs: reg_next,
+        rhs: registers.rowid,
+        target_pc: label_scan_done,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    match scan.order_by_columns_start {
+        Some(reg_order_by_columns_start) => {
+            let reg_new_order_by_columns_start = program.alloc_registers(window.order_by.len());
+            let label_new_group = program.allocate_label();
+            let label_same_group = program.allocate_label();
+            program.emit_insn(Insn::SeekRowid {
+                cursor_id: cursor_lookup,
+                src_reg: reg_next,
+                target_pc: label_scan_done,
+            });
+            emit_load_order_by_columns(
+                program,
+                window,
+                cursor_lookup,
+                reg_new_order_by_columns_start,
+            );
+            emit_copy_add_imm(program, scan.group, reg_next_group, 0);
+            program.emit_insn(Insn::IfNot {
+                reg: scan.position,
+                target_pc: label_new_group,
+                jump_if_null: false,
+            });
+            program.emit_insn(Insn::Compare {
+                start_reg_a: reg_order_by_columns_start,
+                start_reg_b: reg_new_order_by_columns_start,
+                count: window.order_by.len(),
+                collation: program.curr_collation(),
+            });
+            program.emit_insn(Insn::Jump {
+                target_pc_lt: label_new_group,
+                target_pc_eq: label_same_group,
+                target_pc_gt: label_new_group,
+            });
+            program.preassign_label_to_next_insn(label_new_group);
+            program.emit_insn(Insn::AddImm {
+                register: reg_next_group,
+                value: 1,
+            });
+            program.preassign_label_to_next_insn(label_same_group);
+            program.emit_insn(Insn::Gt {
+                lhs: reg_next_group,
+                rhs: reg_last_group,
+                target_pc: label_scan_done,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+            program.emit_insn(Insn::Copy {
+                src_reg: reg_new_order_by_columns_start,
+                dst_reg: reg_order_by_columns_start,
+                extra_amount: window.order_by.len() - 1,
+            });
+        }
+        None => {
+            // Without ORDER BY, all rows of the partition belong to the same group.
+            program.emit_insn(Insn::Integer {
+                value: 1,
+                dest: reg_next_group,
+            });
+            program.emit_insn(Insn::Gt {
+                lhs: reg_next_group,
+                rhs: reg_last_group,
+                target_pc: label_scan_done,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+        }
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_next,
+        dst_reg: scan.position,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_next_group,
+        dst_reg: scan.group,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_scan,
+    });
+
+    program.preassign_label_to_next_insn(label_scan_done);
+    emit_copy_add_imm(program, scan.position, dest, if is_start { 1 } else { 0 });
+}
+
+/// Emits bytecode computing the values of aggregate functions over a frame other than the
+/// default one, as well as the values of `first_value()`, `last_value()` and `nth_value()`
+/// when the frame excludes some of its rows.
+///
+/// When no rows are excluded and either the frame always starts at the beginning of the
+/// partition or all aggregates can remove rows (see `AggFunc::supports_inverse`), the
+/// aggregates are maintained incrementally: as frame bounds only move forward, rows leaving
+/// the frame are removed and rows entering it are added. Otherwise, the aggregates are
+/// recomputed from all rows of the frame for every row.
+fn emit_frame_functions(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    registers: &WindowRegisters,
+    cursors: &WindowCursors,
+) -> crate::Result<()> {
+    let has_aggregates = window
+        .functions
+        .iter()
+        .any(|f| matches!(f.func, WindowFunctionKind::Agg(_)));
+    let has_exclusion = window.frame.exclude != FrameExclude::NoOthers;
+    if window.frame.is_default() || !(has_aggregates || has_exclusion) {
+        return Ok(());
+    }
+    let cursor_lookup = cursors
+        .buffer_lookup
+        .expect("lookup cursor must be opened for custom frames");
+    let frame = &registers.frame;
+
+    let incremental = !has_exclusion
+        && (window.frame.start == FrameBound::UnboundedPreceding
+            || window.functions.iter().all(|f| match &f.func {
+                WindowFunctionKind::Agg(agg_func) => agg_func.supports_inverse(),
+                WindowFunctionKind::Window(_) => true,
+            }));
+    if incremental {
+        let label_remove = program.allocate_label();
+        let label_remove_next = program.allocate_label();
+        let label_add = program.allocate_label();
+        let label_done = program.allocate_label();
+
+        // Add rows up to the end of the frame, except rows that precede its start.
+        program.preassign_label_to_next_insn(label_add);
+        program.emit_insn(Insn::Ge {
+            lhs: frame.agg_end,
+            rhs: frame.end,
+            target_pc: label_remove,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::AddImm {
+            register: frame.agg_end,
+            value: 1,
+        });
+        program.emit_insn(Insn::Lt {
+            lhs: frame.agg_end,
+            rhs: frame.agg_start,
+            target_pc: label_add,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::SeekRowid {
+            cursor_id: cursor_lookup,
+            src_reg: frame.agg_end,
+            target_pc: label_add,
+        });
+        emit_aggregate_step(program, window, resolver, plan, registers, cursor_lookup)?;
+        program.emit_insn(Insn::Goto {
+            target_pc: label_add,
+        });
+
+        // Remove rows that precede the start of the frame, unless they were never added.
+        program.preassign_label_to_next_insn(label_remove);
+        program.emit_insn(Insn::Ge {
+            lhs: frame.agg_start,
+            rhs: frame.start,
+            target_pc: label_done,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Gt {
+            lhs: frame.agg_start,
+            rhs: frame.agg_end,
+            target_pc: label_remove_next,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::SeekRowid {
+            cursor_id: cursor_lookup,
+            src_reg: frame.agg_start,
+            target_pc: label_remove_next,
+        });
+        emit_aggregate_inverse(program, window, registers, cursor_lookup);
+        program.preassign_label_to_next_insn(label_remove_next);
+        program.emit_insn(Insn::AddImm {
+            register: frame.agg_start,
+            value: 1,
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_remove,
+        });
+        program.preassign_label_to_next_insn(label_done);
+    } else {
+        let label_loop = program.allocate_label();
+        let label_include = program.allocate_label();
+        let label_next = program.allocate_label();
+        let label_done = program.allocate_label();
+        let reg_position = program.alloc_register();
+        let reg_included_rows = program.alloc_register();
+
+        for (i, func) in window.functions.iter().enumerate() {
+            if matches!(func.func, WindowFunctionKind::Agg(_)) {
+                program.emit_insn(Insn::Null {
+                    dest: registers.acc_start + i,
+                    dest_end: None,
+                });
+            }
+        }
+        emit_copy_add_imm(program, frame.start, reg_position, 0);
+        program.emit_insn(Insn::Integer {
+            value: 0,
+            dest: reg_included_rows,
+        });
+
+        program.preassign_label_to_next_insn(label_loop);
+        program.emit_insn(Insn::Gt {
+            lhs: reg_position,
+            rhs: frame.end,
+            target_pc: label_done,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        let exclude_current_row = |program: &mut ProgramBuilder, target_pc| {
+            program.emit_insn(Insn::Eq {
+                lhs: reg_position,
+                rhs: registers.row_number,
+                target_pc,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+        };
+        let exclude_peers = |program: &mut ProgramBuilder| {
+            program.emit_insn(Insn::Lt {
+                lhs: reg_position,
+                rhs: registers.rank,
+                target_pc: label_include,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+            program.emit_insn(Insn::Le {
+                lhs: reg_position,
+                rhs: registers.peer_group_end,
+                target_pc: label_next,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+        };
+        match window.frame.exclude {
+            FrameExclude::NoOthers => {}
+            FrameExclude::CurrentRow => exclude_current_row(program, label_next),
+            FrameExclude::Group => exclude_peers(program),
+            FrameExclude::Ties => {
+                exclude_current_row(program, label_include);
+                exclude_peers(program);
+            }
+        }
+        program.preassign_label_to_next_insn(label_include);
+        program.emit_insn(Insn::SeekRowid {
+            cursor_id: cursor_lookup,
+            src_reg: reg_position,
+            target_pc: label_next,
+        });
+        emit_aggregate_step(program, window, resolver, plan, registers, cursor_lookup)?;
+        if has_exclusion {
+            program.emit_insn(Insn::AddImm {
+                register: reg_included_rows,
+                value: 1,
+            });
+            emit_excluded_frame_values(
+                program,
+                window,
+                registers,
+                cursor_lookup,
+                reg_included_rows,
+            );
+        }
+        program.preassign_label_to_next_insn(label_next);
+        program.emit_insn(Insn::AddImm {
+            register: reg_position,
+            value: 1,
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_loop,
+        });
+        program.preassign_label_to_next_insn(label_done);
+    }
+
+    emit_aggregate_values(program, window, registers);
+    Ok(())
+}
+
+/// Emits bytecode updating the values of `first_value()`, `last_value()` and `nth_value()`
+/// with the row under `cursor_id`, which is the `reg_included_rows`-th row of a frame with
+/// excluded rows. The position that `first_value()` and `nth_value()` look for is stored in
+/// the function's state register by [emit_window_function_values].
+fn emit_excluded_frame_values(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    registers: &WindowRegisters,
+    cursor_id: CursorID,
+    reg_included_rows: usize,
+) {
+    for (i, func) in window.functions.iter().enumerate() {
+        let dest = registers.acc_result_start + i;
+        let column = || window_function_arg_column(&func.original_expr, 0);
+        match func.func {
+            WindowFunctionKind::Window(WindowFunc::FirstValue | WindowFunc::NthValue) => {
+                let label_skip = program.allocate_label();
+                program.emit_insn(Insn::Ne {
+                    lhs: reg_included_rows,
+                    rhs: registers.acc_start + i,
+                    target_pc: label_skip,
+                    flags: CmpInsFlags::default(),
+                    collation: None,
+                });
+                program.emit_column_or_rowid(cursor_id, column(), dest);
+                program.preassign_label_to_next_insn(label_skip);
+            }
+            WindowFunctionKind::Window(WindowFunc::LastValue) => {
+                program.emit_column_or_rowid(cursor_id, column(), dest);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn emit_load_order_by_columns(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    cursor_id: CursorID,
+    reg_dst_start: usize,
+) {
+    // Source columns are deduplicated and may appear in a different order than
+    // the ORDER BY terms. Therefore, we must restore the original ORDER BY layout
+    // here by copying the values into an array of registers.
+    for (i, (expr, _)) in window.order_by.iter().enumerate() {
+        match expr {
+            Expr::Column { column, .. } => {
+                program.emit_column_or_rowid(cursor_id, *column, reg_dst_start + i);
+            }
+            _ => unreachable!("expected Column, got {:?}", expr),
+        }
+    }
+}
+
+/// Emits bytecode computing the values of non-aggregate window functions for the row under
+/// the read cursor.
+///
+/// Rows of a partition are numbered from 1 in the buffer table, so their positions double as
+/// rowids, which lets functions such as `lag()` seek directly to the row they need.
+fn emit_window_function_values(
+    program: &mut ProgramBuilder,
+    window: &Window,
+    registers: &WindowRegisters,
+    cursors: &WindowCursors,
+) {
+    let reg_partition_size = registers.rowid;
+    for (i, func) in window.functions.iter().enumerate() {
+        let WindowFunctionKind::Window(window_func) = &func.func else {
+            continue;
+        };
+        let dest = registers.acc_result_start + i;
+        let arg_column = |idx| window_function_arg_column(&func.original_expr, idx);
+        match window_func {
+            WindowFunc::RowNumber | WindowFunc::Rank | WindowFunc::DenseRank => {
+                program.emit_insn(Insn::Copy {
+                    src_reg: match window_func {
+                        WindowFunc::RowNumber => registers.row_number,
+                        WindowFunc::Rank => registers.rank,
+                        _ => registers.dense_rank,
+                    },
+                    dst_reg: dest,
+                    extra_amount: 0,
+                });
+            }
+            WindowFunc::PercentRank => {
+                // (rank - 1) / (partition size - 1), or 0 for a single-row partition.
+                let label_done = program.allocate_label();
+                let reg_numerator = program.alloc_register();
+                let reg_denominator = program.alloc_register();
+                program.emit_insn(Insn::Real { value: 0.0, dest });
+                emit_copy_add_imm(program, reg_partition_size, reg_denominator, -1);
+                program.emit_insn(Insn::IfNot {
+                    reg: reg_denominator,
+                    target_pc: label_done,
+                    jump_if_null: true,
+                });
+                emit_copy_add_imm(program, registers.rank, reg_numerator, -1);
+                program.emit_insn(Insn::RealAffinity {
+                    register: reg_numerator,
+                });
+                program.emit_insn(Insn::Divide {
+                    lhs: reg_numerator,
+                    rhs: reg_denominator,
+                    dest,
+                });
+                program.preassign_label_to_next_insn(label_done);
+            }
+            WindowFunc::CumeDist => {
+                // (rows up to the end of the peer group) / (partition size)
+                let reg_numerator = program.alloc_register();
+                emit_copy_add_imm(program, registers.peer_group_end, reg_numerator, 0);
+                program.emit_insn(Insn::RealAffinity {
+                    register: reg_numerator,
+                });
+                program.emit_insn(Insn::Divide {
+                    lhs: reg_numerator,
+                    rhs: reg_partition_size,
+                    dest,
+                });
+            }
+            WindowFunc::Ntile => {
+                emit_ntile_value(
+                    program,
+                    registers.row_number,
+                    reg_partition_size,
+                    registers.acc_start + i,
+                    dest,
+                );
+            }
+            WindowFunc::Lag | WindowFunc::Lead => {
+                let args_count = match &func.original_expr {
+                    Expr::FunctionCall { args, .. } => args.len(),
+                    _ => 0,
+                };
+                if args_count > 2 {
+                    program.emit_column_or_rowid(cursors.buffer_read, arg_column(2), dest);
+                } else {
+                    program.emit_insn(Insn::Null {
+                        dest,
+                        dest_end: None,
+                    });
+                }
+                let reg_target = program.alloc_register();
+                if args_count > 1 {
+                    let reg_offset = program.alloc_register();
+                    program.emit_column_or_rowid(cursors.buffer_read, arg_column(1), reg_offset);
+                    program.emit_insn(if *window_func == WindowFunc::Lag {
+                        Insn::Subtract {
+                            lhs: registers.row_number,
+                            rhs: reg_offset,
+                            dest: reg_target,
+                        }
+                    } else {
+                        Insn::Add {
+                            lhs: registers.row_number,
+                            rhs: reg_offset,
+                            dest: reg_target,
+                        }
+                    });
+                } else {
+                    let offset = if *window_func == WindowFunc::Lag {
+                        -1
+                    } else {
+                        1
+                    };
+                    emit_copy_add_imm(program, registers.row_number, reg_target, offset);
+                }
+                emit_lookup_column(program, cursors, reg_target, arg_column(0), dest);
+            }
+            WindowFunc::FirstValue | WindowFunc::LastValue | WindowFunc::NthValue => {
+                program.emit_insn(Insn::Null {
+                    dest,
+                    dest_end: None,
+                });
+                // Position of the row within the frame, or 0 for the last row.
+                let reg_n = registers.acc_start + i;
+                match window_func {
+                    WindowFunc::FirstValue => {
+                        program.emit_insn(Insn::Integer {
+                            value: 1,
+                            dest: reg_n,
+                        });
+                    }
+                    WindowFunc::LastValue => {
+                        program.emit_insn(Insn::Integer {
+                            value: 0,
+                            dest: reg_n,
+                        });
+                    }
+                    _ => {
+                        program.emit_column_or_rowid(cursors.buffer_read, arg_column(1), reg_n);
+                        emit_validate_nth_value_position(program, reg_n);
+                    }
+                }
+                // With excluded rows, the value is found while scanning the frame.
+                if window.frame.exclude != FrameExclude::NoOthers {
+                    continue;
+                }
+                let label_outside_frame = program.allocate_label();
+                let reg_target = program.alloc_register();
+                if *window_func == WindowFunc::LastValue {
+                    emit_copy_add_imm(program, registers.frame.end, reg_target, 0);
+                } else {
+                    program.emit_insn(Insn::Add {
+                        lhs: registers.frame.start,
+                        rhs: reg_n,
+                        dest: reg_target,
+                    });
+                    program.emit_insn(Insn::AddImm {
+                        register: reg_target,
+                        value: -1,
+                    });
+                }
+                program.emit_insn(Insn::Lt {
+                    lhs: reg_target,
+                    rhs: registers.frame.start,
+                    target_pc: label_outside_frame,
+                    flags: CmpInsFlags::default(),
+                    collation: None,
+                });
+                program.emit_insn(Insn::Gt {
+                    lhs: reg_target,
+                    rhs: registers.frame.end,
+                    target_pc: label_outside_frame,
+                    flags: CmpInsFlags::default(),
+                    collation: None,
+                });
+                emit_lookup_column(program, cursors, reg_target, arg_column(0), dest);
+                program.preassign_label_to_next_insn(label_outside_frame);
+            }
+        }
+    }
+}
+
+/// Emits bytecode checking that the second argument of `nth_value()` is a positive integer,
+/// converting it to an integer.
+fn emit_validate_nth_value_position(program: &mut ProgramBuilder, reg_n: usize) {
+    let label_valid = program.allocate_label();
+    let label_invalid = program.allocate_label();
+    let reg_n_real = program.alloc_register();
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_n,
+        dst_reg: reg_n_real,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::Cast {
+        reg: reg_n,
+        affinity: Affinity::Integer,
+    });
+    program.emit_insn(Insn::Cast {
+        reg: reg_n_real,
+        affinity: Affinity::Real,
+    });
+    // Reject values that are not integral, as well as zero and negative values.
+    program.emit_insn(Insn::Ne {
+        lhs: reg_n,
+        rhs: reg_n_real,
+        target_pc: label_invalid,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    program.emit_insn(Insn::IfPos {
+        reg: reg_n,
+        target_pc: label_valid,
+        decrement_by: 0,
+    });
+    program.preassign_label_to_next_insn(label_invalid);
+    program.emit_insn(Insn::Halt {
+        err_code: SQLITE_ERROR,
+        description: "second argument to nth_value must be a positive integer".to_string(),
+    });
+    program.preassign_label_to_next_insn(label_valid);
+}
+
+/// Emits bytecode computing the bucket number of `ntile()` for the given row. When the rows
+/// cannot be divided evenly, the first `size % buckets` buckets get one extra row.
+fn emit_ntile_value(
+    program: &mut ProgramBuilder,
+    reg_row_number: usize,
+    reg_partition_size: usize,
+    reg_buckets: usize,
+    dest: usize,
+) {
+    let label_at_least_one_row_per_bucket = program.allocate_label();
+    let label_small_bucket = program.allocate_label();
+    let label_done = program.allocate_label();
+    let reg_row_idx = program.alloc_register();
+    let reg_small_bucket_size = program.alloc_register();
+    let reg_large_bucket_size = program.alloc_register();
+    let reg_large_bucket_count = program.alloc_register();
+    let reg_large_bucket_rows = program.alloc_register();
+
+    emit_copy_add_imm(program, reg_row_number, reg_row_idx, -1);
+    program.emit_insn(Insn::Divide {
+        lhs: reg_partition_size,
+        rhs: reg_buckets,
+        dest: reg_small_bucket_size,
+    });
+    program.emit_insn(Insn::IfPos {
+        reg: reg_small_bucket_size,
+        target_pc: label_at_least_one_row_per_bucket,
+        decrement_by: 0,
+    });
+    // There are more buckets than rows, so each row gets its own bucket.
+    program.emit_insn(Insn::Copy {
+        src_reg: reg_row_number,
+        dst_reg: dest,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_done,
+    });
+
+    program.preassign_label_to_next_insn(label_at_least_one_row_per_bucket);
+    program.emit_insn(Insn::Remainder {
+        lhs: reg_partition_size,
+        rhs: reg_buckets,
+        dest: reg_large_bucket_count,
+    });
+    emit_copy_add_imm(program, reg_small_bucket_size, reg_large_bucket_size, 1);
+    program.emit_insn(Insn::Multiply {
+        lhs: reg_large_bucket_count,
+        rhs: reg_large_bucket_size,
+        dest: reg_large_bucket_rows,
+    });
+    program.emit_insn(Insn::Ge {
+        lhs: reg_row_idx,
+        rhs: reg_large_bucket_rows,
+        target_pc: label_small_bucket,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    program.emit_insn(Insn::Divide {
+        lhs: reg_row_idx,
+        rhs: reg_large_bucket_size,
+        dest,
+    });
+    program.emit_insn(Insn::AddImm {
+        register: dest,
+        value: 1,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_done,
+    });
+
+    program.preassign_label_to_next_insn(label_small_bucket);
+    program.emit_insn(Insn::Subtract {
+        lhs: reg_row_idx,
+        rhs: reg_large_bucket_rows,
+        dest: reg_row_idx,
+    });
+    program.emit_insn(Insn::Divide {
+        lhs: reg_row_idx,
+        rhs: reg_small_bucket_size,
+        dest,
+    });
+    program.emit_insn(Insn::Add {
+        lhs: dest,
+        rhs: reg_large_bucket_count,
+        dest,
+    });
+    program.emit_insn(Insn::AddImm {
+        register: dest,
+        value: 1,
+    });
+    program.preassign_label_to_next_insn(label_done);
+}
+
+fn emit_copy_add_imm(program: &mut ProgramBuilder, src_reg: usize, dst_reg: usize, value: i64) {
+    program.emit_insn(Insn::Copy {
+        src_reg,
+        dst_reg,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::AddImm {
+        register: dst_reg,
+        value,
+    });
+}
+
+/// Emits bytecode reading `column` of the buffered row at position `reg_position` into `dest`.
+/// If there is no such row, `dest` is left unchanged.
+fn emit_lookup_column(
+    program: &mut ProgramBuilder,
+    cursors: &WindowCursors,
+    reg_position: usize,
+    column: usize,
+    dest: usize,
+) {
+    let cursor_id = cursors
+        .buffer_lookup
+        .expect("lookup cursor must be opened for this window function");
+    let label_not_found = program.allocate_label();
+    program.emit_insn(Insn::SeekRowid {
+        cursor_id,
+        src_reg: reg_position,
+        target_pc: label_not_found,
+    });
+    program.emit_column_or_rowid(cursor_id, column, dest);
+    program.preassign_label_to_next_insn(label_not_found);
+}
+
+/// Returns the subquery column referenced by the argument at `idx` of a window function.
+fn window_function_arg_column(expr: &Expr, idx: usize) -> usize {
+    match expr {
+        Expr::FunctionCall { args, .. } => match args[idx].as_ref() {
+            Expr::Column { column, .. } => *column,
+            arg => unreachable!("expected Column, got {:?}", arg),
+        },
+        _ => unreachable!("expected FunctionCall, got {:?}", expr),
+    }
+}
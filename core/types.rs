@@ -603,6 +603,7 @@ pub struct SumAggState {
     pub r_err: f64,   // Error term for Kahan-Babushka-Neumaier summation
     pub approx: bool, // True if any non-integer value was input to the sum
     pub ovrfl: bool,  // Integer overflow seen
+    pub count: i64,   // Number of non-NULL values in the sum, needed to undo steps
 }
 impl Default for SumAggState {
     fn default() -> Self {
@@ -610,6 +611,7 @@ impl Default for SumAggState {
             r_err: 0.0,
             approx: false,
             ovrfl: false,
+            count: 0,
         }
     }
 }
@@ -1614,6 +1616,7 @@ impl IndexInfo {
                 let mut key_info: Vec<KeyInfo> = index
                     .columns
                     .iter()
+                    .chain(index.primary_key_suffix.iter())
                     .map(|c| KeyInfo {
                         sort_order: c.order,
                         collation: c.collation.unwrap_or_default(),
@@ -1628,7 +1631,9 @@ impl IndexInfo {
                 key_info
             },
             has_rowid: index.has_rowid,
-            num_cols: index.columns.len() + (index.has_rowid as usize),
+            num_cols: index.columns.len()
+                + index.primary_key_suffix.len()
+                + (index.has_rowid as usize),
         }
     }
 }
@@ -1227,6 +1227,7 @@ pub fn extract_view_columns(
                             unique: false,
                             collation: None,
                             hidden: false,
+                            generated: None,
                         },
                     });
                 }
@@ -1261,6 +1262,7 @@ pub fn extract_view_columns(
                                         unique: false,
                                         collation: table_column.collation,
                                         hidden: false,
+                                        generated: None,
                                     },
                                 });
                             }
@@ -1282,6 +1284,7 @@ pub fn extract_view_columns(
                                 unique: false,
                                 collation: None,
                                 hidden: false,
+                                generated: None,
                             },
                         });
                     }
@@ -1318,6 +1321,7 @@ pub fn extract_view_columns(
                                         unique: false,
                                         collation: table_column.collation,
                                         hidden: false,
+                                        generated: None,
                                     },
                                 });
                             }
@@ -1336,6 +1340,7 @@ pub fn extract_view_columns(
                                     unique: false,
                                     collation: None,
                                     hidden: false,
+                                    generated: None,
                                 },
                             });
                         }
@@ -0,0 +1,285 @@
+//! VACUUM and VACUUM INTO.
+//!
+//! Both rebuild every b-tree of the main database by replaying its schema and rows into a
+//! fresh database. VACUUM INTO leaves that copy at the requested path. The in-place VACUUM
+//! builds the copy in a temporary file and then writes its pages over the main database inside
+//! the current write transaction, so the swap reaches the database file through the WAL.
+use crate::{Connection, LimboError, Result};
+use std::sync::Arc;
+
+#[cfg(feature = "fs")]
+use crate::util::IOExt;
+#[cfg(feature = "fs")]
+use crate::{
+    schema::DBSP_TABLE_PREFIX, storage::checksum::CHECKSUM_REQUIRED_RESERVED_BYTES, Database,
+    DatabaseOpts, OpenFlags, Statement, StepResult, TransactionState, IO,
+};
+#[cfg(feature = "fs")]
+use std::num::NonZero;
+
+/// Number of rows copied per transaction, so that the dirty pages of the copy fit in its page
+/// cache.
+#[cfg(feature = "fs")]
+const COPY_BATCH_SIZE: usize = 1000;
+
+/// A row of `sqlite_schema` that has to be replayed on the copy.
+#[cfg(feature = "fs")]
+struct SchemaEntry {
+    ty: String,
+    name: String,
+    sql: String,
+}
+
+#[cfg(not(feature = "fs"))]
+pub(crate) fn vacuum(_conn: &Arc<Connection>) -> Result<()> {
+    Err(LimboError::InvalidArgument(
+        "vacuum not available in this build (no-fs)".to_string(),
+    ))
+}
+
+#[cfg(not(feature = "fs"))]
+pub(crate) fn vacuum_into(_conn: &Arc<Connection>, _path: &str) -> Result<()> {
+    Err(LimboError::InvalidArgument(
+        "vacuum not available in this build (no-fs)".to_string(),
+    ))
+}
+
+/// Rebuilds the main database in place. Must run inside the write transaction of the VACUUM
+/// statement, with `conn` set up for nested statements.
+#[cfg(feature = "fs")]
+pub(crate) fn vacuum(conn: &Arc<Connection>) -> Result<()> {
+    // The directory and the copy in it are deleted when it is dropped.
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("vacuum.db");
+    let Some(path) = path.to_str() else {
+        return Err(LimboError::InternalError(
+            "Failed to convert path to string".to_string(),
+        ));
+    };
+    // The copy goes through the I/O of the database, so that it stays in memory only when the
+    // database itself does.
+    let io = conn.db.io.clone();
+    let result = overwrite_with_copy(conn, io.clone(), path);
+    // An in-memory I/O keeps the files until they are removed.
+    let _ = io.remove_file(path);
+    let _ = io.remove_file(&format!("{path}-wal"));
+    result?;
+
+    // Every b-tree got a new root page, so the schema has to be parsed again.
+    conn.set_tx_state(TransactionState::Write {
+        schema_did_change: true,
+    });
+    conn.reparse_schema()
+}
+
+/// Builds the compacted copy of the main database at `path` and writes its pages over the
+/// main database.
+#[cfg(feature = "fs")]
+fn overwrite_with_copy(conn: &Arc<Connection>, io: Arc<dyn IO>, path: &str) -> Result<()> {
+    let copy =
+        Database::open_file_with_flags(io, path, OpenFlags::default(), copy_opts(conn)?, None)?;
+    let copy_conn = copy.connect()?;
+    copy_database(conn, &copy_conn)?;
+
+    let copy_pager = copy_conn.get_pager();
+    // Nothing is written to a copy without any table, so its first page may not exist yet.
+    copy_pager.io.block(|| copy_pager.maybe_allocate_page1())?;
+    copy_pager.begin_read_tx()?;
+    let result = conn.get_pager().overwrite_with(&copy_pager);
+    copy_pager.end_read_tx()?;
+    result?;
+    copy_conn.close()
+}
+
+/// Writes a compacted copy of the main database to `path`, which must not exist or be empty.
+/// Must run with `conn` set up for nested statements.
+#[cfg(feature = "fs")]
+pub(crate) fn vacuum_into(conn: &Arc<Connection>, path: &str) -> Result<()> {
+    if std::fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0) {
+        return Err(LimboError::InvalidArgument(
+            "output file already exists".to_string(),
+        ));
+    }
+    let io = Database::io_for_path(path)?;
+    let db =
+        Database::open_file_with_flags(io, path, OpenFlags::default(), copy_opts(conn)?, None)?;
+    let dest = db.connect()?;
+    let result = copy_database(conn, &dest);
+    // Closing the last connection checkpoints the copy into its database file.
+    dest.close()?;
+    result
+}
+
+#[cfg(feature = "fs")]
+fn copy_opts(conn: &Arc<Connection>) -> Result<DatabaseOpts> {
+    let use_indexes = conn
+        .db
+        .schema
+        .lock()
+        .map_err(|_| LimboError::SchemaLocked)?
+        .indexes_enabled();
+    Ok(DatabaseOpts::new()
+        .with_indexes(use_indexes)
+        .with_views(conn.db.experimental_views_enabled())
+        .with_strict(conn.db.experimental_strict_enabled()))
+}
+
+/// Replays the schema and the rows of the main database of `src` on the empty database of
+/// `dest`. Tables are filled before their indexes and triggers are created, so that the
+/// copy neither fires triggers nor maintains indexes row by row.
+#[cfg(feature = "fs")]
+fn copy_database(src: &Arc<Connection>, dest: &Arc<Connection>) -> Result<()> {
+    let src_pager = src.get_pager();
+    let (page_size, reserved_space, user_version, application_id) = src_pager.io.block(|| {
+        src_pager.with_header(|header| {
+            (
+                header.page_size.get(),
+                header.reserved_space,
+                header.user_version.get(),
+                header.application_id.get(),
+            )
+        })
+    })?;
+
+    // The pages of the copy must have the same layout as the ones they replace.
+    dest.reset_page_size(page_size)?;
+    let key = src.encryption_key.read().clone();
+    match (src.get_encryption_cipher_mode(), key) {
+        (Some(cipher_mode), Some(key)) => {
+            dest.set_encryption_cipher(cipher_mode)?;
+            dest.set_encryption_key(key)?;
+        }
+        _ if reserved_space != CHECKSUM_REQUIRED_RESERVED_BYTES => {
+            dest.get_pager().reset_checksum_context();
+        }
+        _ => {}
+    }
+    dest.syms.write().extend(&src.syms.read());
+
+    let mut entries = Vec::new();
+    let mut stmt =
+        src.prepare("SELECT type, name, tbl_name, sql FROM sqlite_schema WHERE sql IS NOT NULL")?;
+    loop {
+        match stmt.step()? {
+            StepResult::Row => {
+                let row = stmt.row().unwrap();
+                // The state tables of materialized views are recreated with the views.
+                if row.get::<&str>(2)?.starts_with(DBSP_TABLE_PREFIX) {
+                    continue;
+                }
+                entries.push(SchemaEntry {
+                    ty: row.get::<&str>(0)?.to_string(),
+                    name: row.get::<&str>(1)?.to_string(),
+                    sql: row.get::<&str>(3)?.to_string(),
+                });
+            }
+            StepResult::IO => stmt.run_once()?,
+            StepResult::Done => break,
+            StepResult::Interrupt | StepResult::Busy => return Err(LimboError::Busy),
+        }
+    }
+    drop(stmt);
+
+    // sqlite_sequence is created along with the first AUTOINCREMENT table and is filled as
+    // rows are copied, so its rows are replaced after every other table is copied.
+    let is_sequence = |entry: &SchemaEntry| entry.name.eq_ignore_ascii_case("sqlite_sequence");
+    let is_virtual = |entry: &SchemaEntry| {
+        entry
+            .sql
+            .get(..14)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("CREATE VIRTUAL"))
+    };
+    let tables = || entries.iter().filter(|entry| entry.ty == "table");
+
+    for entry in tables().filter(|entry| !is_sequence(entry)) {
+        dest.execute(&entry.sql)?;
+    }
+    for entry in tables().filter(|entry| !is_sequence(entry) && !is_virtual(entry)) {
+        copy_table(src, dest, &entry.name)?;
+    }
+    if tables().any(is_sequence) {
+        dest.execute("DELETE FROM sqlite_sequence")?;
+        copy_table(src, dest, "sqlite_sequence")?;
+    }
+    for entry in entries.iter().filter(|entry| entry.ty != "table") {
+        dest.execute(&entry.sql)?;
+    }
+
+    if user_version != 0 {
+        dest.execute(format!("PRAGMA user_version = {user_version}"))?;
+    }
+    if application_id != 0 {
+        dest.execute(format!("PRAGMA application_id = {application_id}"))?;
+    }
+    Ok(())
+}
+
+/// Copies every row of `table_name`, keeping the rowids, in batches of [COPY_BATCH_SIZE].
+#[cfg(feature = "fs")]
+fn copy_table(src: &Arc<Connection>, dest: &Arc<Connection>, table_name: &str) -> Result<()> {
+    let Some(table) = src.schema.read().get_btree_table(table_name) else {
+        return Err(LimboError::InternalError(format!(
+            "no such table: {table_name}"
+        )));
+    };
+    // Generated columns are computed again by the copy.
+    let mut columns: Vec<String> = table
+        .columns
+        .iter()
+        .filter(|col| col.generated.is_none())
+        .map(|col| quote_ident(col.name.as_deref().unwrap_or_default()))
+        .collect();
+    // Tables with a rowid alias keep their rowids through the alias column.
+    if table.has_rowid && table.get_rowid_alias_column().is_none() {
+        columns.insert(0, "rowid".to_string());
+    }
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let columns = columns.join(", ");
+    let table_name = quote_ident(table_name);
+
+    let mut select = src.prepare(format!("SELECT {columns} FROM {table_name}"))?;
+    let mut insert = dest.prepare(format!(
+        "INSERT INTO {table_name}({columns}) VALUES ({placeholders})"
+    ))?;
+    dest.execute("BEGIN")?;
+    let mut batch_size = 0;
+    loop {
+        match select.step()? {
+            StepResult::Row => {
+                let row = select.row().unwrap();
+                for (i, value) in row.get_values().enumerate() {
+                    insert.bind_at(NonZero::new(i + 1).unwrap(), value.clone());
+                }
+                run_to_completion(&mut insert)?;
+                insert.reset();
+                batch_size += 1;
+                if batch_size == COPY_BATCH_SIZE {
+                    dest.execute("COMMIT")?;
+                    dest.execute("BEGIN")?;
+                    batch_size = 0;
+                }
+            }
+            StepResult::IO => select.run_once()?,
+            StepResult::Done => break,
+            StepResult::Interrupt | StepResult::Busy => return Err(LimboError::Busy),
+        }
+    }
+    dest.execute("COMMIT")
+}
+
+#[cfg(feature = "fs")]
+fn run_to_completion(stmt: &mut Statement) -> Result<()> {
+    loop {
+        match stmt.step()? {
+            StepResult::Row => {}
+            StepResult::IO => stmt.run_once()?,
+            StepResult::Done => return Ok(()),
+            StepResult::Interrupt | StepResult::Busy => return Err(LimboError::Busy),
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
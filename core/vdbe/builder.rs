@@ -1,4 +1,8 @@
-use std::{cell::Cell, cmp::Ordering, sync::Arc};
+use std::{
+    cell::Cell,
+    cmp::Ordering,
+    sync::{Arc, OnceLock, Weak},
+};
 
 use tracing::{instrument, Level};
 use turso_parser::ast::{self, TableInternalId};
@@ -34,7 +38,7 @@ impl TableRefIdCounter {
     }
 }
 
-use super::{BranchOffset, CursorID, Insn, InsnReference, JumpTarget, Program};
+use super::{insn::SubProgram, BranchOffset, CursorID, Insn, InsnReference, JumpTarget, Program};
 
 /// A key that uniquely identifies a cursor.
 /// The key is a pair of table reference id and index.
@@ -111,13 +115,23 @@ pub struct ProgramBuilder {
     init_label: BranchOffset,
     start_offset: BranchOffset,
     capture_data_changes_mode: CaptureDataChangesMode,
-    // TODO: when we support multiple dbs, this should be a write mask to track which DBs need to be written
+    /// Transaction mode of the main database.
     txn_mode: TransactionMode,
+    /// Transaction modes of the temp and attached databases accessed by the program.
+    aux_txn_modes: Vec<(usize, TransactionMode, u32)>,
     rollback: bool,
+    /// How a constraint violated by the statement itself is resolved, see [Program::resolve_type].
+    resolve_type: ast::ResolveType,
     /// The mode in which the query is being executed.
     query_mode: QueryMode,
     /// Current parent explain address, if any.
     current_parent_explain_idx: Option<usize>,
+    /// Triggers whose programs are being compiled, outermost first. Non-empty only when
+    /// this builder compiles a trigger program; used to detect recursive triggers.
+    trigger_stack: Vec<(String, Arc<OnceLock<Weak<SubProgram>>>)>,
+    /// The database modified by the statement. Trigger programs inherit it from the program
+    /// that fires them, since a trigger only modifies tables of its own database.
+    database_id: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -200,16 +214,70 @@ impl ProgramBuilder {
             start_offset: BranchOffset::Placeholder,
             capture_data_changes_mode,
             txn_mode: TransactionMode::None,
+            aux_txn_modes: Vec::new(),
             rollback: false,
+            resolve_type: ast::ResolveType::Abort,
             query_mode,
             current_parent_explain_idx: None,
+            trigger_stack: Vec::new(),
+            database_id: 0,
         }
     }
 
+    /// Create a builder for the program of trigger `trigger_name`, fired from the program
+    /// being built by `self`. `slot` is filled with the compiled program once it is built,
+    /// so that recursive invocations of the same trigger can refer to it.
+    pub fn new_trigger_program(
+        &self,
+        trigger_name: String,
+        slot: Arc<OnceLock<Weak<SubProgram>>>,
+        opts: ProgramBuilderOpts,
+    ) -> Self {
+        let mut builder = ProgramBuilder::new(
+            self.query_mode,
+            self.capture_data_changes_mode.clone(),
+            opts,
+        );
+        builder.trigger_stack = self.trigger_stack.clone();
+        builder.trigger_stack.push((trigger_name, slot));
+        builder.database_id = self.database_id;
+        builder
+    }
+
+    /// Whether this builder compiles a trigger program.
+    pub fn is_trigger_program(&self) -> bool {
+        !self.trigger_stack.is_empty()
+    }
+
+    /// If the trigger `trigger_name` is currently being compiled (i.e. it fires itself,
+    /// directly or through other triggers), returns the slot its program will be stored in.
+    pub fn trigger_being_compiled(
+        &self,
+        trigger_name: &str,
+    ) -> Option<Arc<OnceLock<Weak<SubProgram>>>> {
+        self.trigger_stack
+            .iter()
+            .find(|(name, _)| name == trigger_name)
+            .map(|(_, slot)| slot.clone())
+    }
+
+    pub fn database_id(&self) -> usize {
+        self.database_id
+    }
+
+    pub fn set_database_id(&mut self, database_id: usize) {
+        self.database_id = database_id;
+    }
+
     pub fn capture_data_changes_mode(&self) -> &CaptureDataChangesMode {
         &self.capture_data_changes_mode
     }
 
+    /// Disables change data capture for the statement being compiled.
+    pub fn disable_capture_data_changes(&mut self) {
+        self.capture_data_changes_mode = CaptureDataChangesMode::Off;
+    }
+
     pub fn extend(&mut self, opts: &ProgramBuilderOpts) {
         self.insns.reserve(opts.approx_num_insns);
         self.cursor_ref.reserve(opts.num_cursors);
@@ -379,10 +447,6 @@ impl ProgramBuilder {
         });
     }
 
-    // no users yet, but I want to avoid someone else in the future
-    // just adding parameters to emit_halt! If you use this, remove the
-    // clippy warning please.
-    #[allow(dead_code)]
     pub fn emit_halt_err(&mut self, err_code: usize, description: String) {
         self.emit_insn(Insn::Halt {
             err_code,
@@ -670,6 +734,9 @@ impl ProgramBuilder {
                 } => {
                     resolve(target_pc, "DecrJumpZero");
                 }
+                Insn::StatSample { pc_if_done, .. } => {
+                    resolve(pc_if_done, "StatSample");
+                }
                 Insn::SorterNext {
                     cursor_id: _cursor_id,
                     pc_if_next,
@@ -688,6 +755,9 @@ impl ProgramBuilder {
                 Insn::IfPos { target_pc, .. } => {
                     resolve(target_pc, "IfPos");
                 }
+                Insn::IfNeg { target_pc, .. } => {
+                    resolve(target_pc, "IfNeg");
+                }
                 Insn::Next { pc_if_next, .. } => {
                     resolve(pc_if_next, "Next");
                 }
@@ -727,6 +797,14 @@ impl ProgramBuilder {
                 Insn::Gosub { target_pc, .. } => {
                     resolve(target_pc, "Gosub");
                 }
+                Insn::Program {
+                    ignore_jump_target, ..
+                } => {
+                    resolve(ignore_jump_target, "Program");
+                }
+                Insn::FkIfZero { target_pc, .. } => {
+                    resolve(target_pc, "FkIfZero");
+                }
                 Insn::Jump {
                     target_pc_eq,
                     target_pc_lt,
@@ -789,6 +867,16 @@ impl ProgramBuilder {
         self.cursor_ref
             .iter()
             .position(|(k, _)| k.as_ref().is_some_and(|k| k.equals(key)))
+            .or_else(|| {
+                // The PRIMARY KEY of a WITHOUT ROWID table is the table b-tree itself,
+                // so it is read through the table cursor.
+                let index = key.index.as_ref()?;
+                self.cursor_ref.iter().position(|(k, cursor_type)| {
+                    k.as_ref().is_some_and(|k| {
+                        k.equals(&CursorKey::table(key.table_reference_id))
+                    }) && matches!(cursor_type, CursorType::BTreeTable(btree) if index.is_without_rowid_primary_key_of(btree))
+                })
+            })
     }
 
     pub fn resolve_cursor_id(&self, key: &CursorKey) -> CursorID {
@@ -796,6 +884,15 @@ impl ProgramBuilder {
             .unwrap_or_else(|| panic!("Cursor not found: {key:?}"))
     }
 
+    /// Returns the index of the index cursor associated with the given table reference, if any.
+    pub fn resolve_cursor_index(&self, table_reference_id: TableInternalId) -> Option<Arc<Index>> {
+        self.cursor_ref.iter().find_map(|(k, _)| {
+            k.as_ref()
+                .filter(|k| k.table_reference_id == table_reference_id)
+                .and_then(|k| k.index.clone())
+        })
+    }
+
     pub fn set_collation(&mut self, c: Option<(CollationSeq, bool)>) {
         self.collation = c
     }
@@ -849,10 +946,58 @@ impl ProgramBuilder {
         }
     }
 
+    /// Like [ProgramBuilder::begin_write_operation], for the database at `database_id`. The
+    /// transaction of the main database drives the other ones, so it is started too.
+    pub fn begin_write_operation_on(&mut self, database_id: usize) {
+        if database_id == 0 {
+            return self.begin_write_operation();
+        }
+        self.begin_read_operation();
+        match self
+            .aux_txn_modes
+            .iter_mut()
+            .find(|(db, _, _)| *db == database_id)
+        {
+            Some((_, mode, _)) => *mode = TransactionMode::Write,
+            None => self
+                .aux_txn_modes
+                .push((database_id, TransactionMode::Write, 0)),
+        }
+    }
+
+    /// Like [ProgramBuilder::begin_read_operation], for the database at `database_id`.
+    pub fn begin_read_operation_on(&mut self, database_id: usize) {
+        if database_id == 0 {
+            return self.begin_read_operation();
+        }
+        self.begin_read_operation();
+        if !self
+            .aux_txn_modes
+            .iter()
+            .any(|(db, _, _)| *db == database_id)
+        {
+            self.aux_txn_modes
+                .push((database_id, TransactionMode::Read, 0));
+        }
+    }
+
+    /// Sets the schema cookies checked by the transactions of the temp and attached databases,
+    /// whose schemas are kept by the connection rather than passed to the epilogue.
+    pub fn set_aux_schema_cookies(&mut self, schema_cookie: impl Fn(usize) -> u32) {
+        for (db, _, cookie) in self.aux_txn_modes.iter_mut() {
+            *cookie = schema_cookie(*db);
+        }
+    }
+
     pub fn begin_concurrent_operation(&mut self) {
         self.txn_mode = TransactionMode::Concurrent;
     }
 
+    /// Sets how a constraint violated by the statement is resolved, e.g. for UPDATE OR FAIL.
+    pub fn set_resolve_type(&mut self, resolve_type: ast::ResolveType) {
+        self.resolve_type = resolve_type;
+    }
+
     /// Indicates the rollback behvaiour for the halt instruction in epilogue
     pub fn rollback(&mut self) {
         self.rollback = true;
@@ -867,12 +1012,20 @@ impl ProgramBuilder {
             self.emit_halt(self.rollback);
             self.preassign_label_to_next_insn(self.init_label);
 
-            if !matches!(self.txn_mode, TransactionMode::None) {
+            // A trigger program runs inside the transaction of the statement that fired it.
+            if !matches!(self.txn_mode, TransactionMode::None) && !self.is_trigger_program() {
                 self.emit_insn(Insn::Transaction {
                     db: 0,
                     tx_mode: self.txn_mode,
                     schema_cookie: schema.schema_version,
                 });
+                for (db, tx_mode, schema_cookie) in std::mem::take(&mut self.aux_txn_modes) {
+                    self.emit_insn(Insn::Transaction {
+                        db,
+                        tx_mode,
+                        schema_cookie,
+                    });
+                }
             }
 
             self.emit_constant_insns();
@@ -905,10 +1058,14 @@ impl ProgramBuilder {
             dest: rowid,
         });
 
-        self.emit_insn(Insn::IsNull {
-            reg: rowid,
-            target_pc: loop_end,
-        });
+        // The rows of a WITHOUT ROWID table have no rowid
+        let (_, cursor_type) = self.cursor_ref.get(cursor_id).unwrap();
+        if !matches!(cursor_type, CursorType::BTreeTable(btree) if !btree.has_rowid) {
+            self.emit_insn(Insn::IsNull {
+                reg: rowid,
+                target_pc: loop_end,
+            });
+        }
 
         f(self, rowid);
 
@@ -931,6 +1088,13 @@ impl ProgramBuilder {
                     cursor_id,
                     dest: out,
                 });
+            } else if column_def.is_virtual_generated() {
+                // A VIRTUAL generated column has no value in the record. It is computed from the
+                // other columns with `emit_table_column`.
+                self.emit_insn(Insn::Null {
+                    dest: out,
+                    dest_end: None,
+                });
             } else {
                 self.emit_column(cursor_id, column, out);
             }
@@ -947,7 +1111,10 @@ impl ProgramBuilder {
         let default = 'value: {
             let default = match cursor_type {
                 CursorType::BTreeTable(btree) => &btree.columns[column].default,
-                CursorType::BTreeIndex(index) => &index.columns[column].default,
+                CursorType::BTreeIndex(index) => match index.columns.get(column) {
+                    Some(index_column) => &index_column.default,
+                    None => break 'value None,
+                },
                 CursorType::MaterializedView(btree, _) => &btree.columns[column].default,
                 _ => break 'value None,
             };
@@ -980,6 +1147,11 @@ impl ProgramBuilder {
             })
         };
 
+        // The columns of a WITHOUT ROWID table are stored in PRIMARY KEY order.
+        let column = match cursor_type {
+            CursorType::BTreeTable(btree) => btree.column_storage_position(column),
+            _ => column,
+        };
         self.emit_insn(Insn::Column {
             cursor_id,
             column,
@@ -992,6 +1164,7 @@ impl ProgramBuilder {
         self.resolve_labels();
 
         self.parameters.list.dedup();
+        let is_subprogram = self.is_trigger_program();
         Program {
             max_registers: self.next_free_register,
             insns: self.insns,
@@ -1005,6 +1178,8 @@ impl ProgramBuilder {
             table_references: self.table_references,
             sql: sql.to_string(),
             accesses_db: !matches!(self.txn_mode, TransactionMode::None),
+            is_subprogram,
+            resolve_type: self.resolve_type,
         }
     }
 }
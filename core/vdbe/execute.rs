@@ -5,6 +5,7 @@ use crate::mvcc::database::CheckpointStateMachine;
 use crate::numeric::{NullableInteger, Numeric};
 use crate::schema::Table;
 use crate::state_machine::StateMachine;
+use crate::stats::StatAccumulator;
 use crate::storage::btree::{
     integrity_check, IntegrityCheckError, IntegrityCheckState, PageCategory,
 };
@@ -13,6 +14,7 @@ use crate::storage::page_cache::PageCache;
 use crate::storage::pager::{AtomicDbState, CreateBTreeFlags, DbState};
 use crate::storage::sqlite3_ondisk::{read_varint, DatabaseHeader, PageSize};
 use crate::translate::collate::CollationSeq;
+use crate::translate::expr::{walk_expr_mut, WalkControl};
 use crate::types::{
     compare_immutable, compare_records_generic, Extendable, IOCompletions, ImmutableRecord,
     SeekResult, Text,
@@ -23,7 +25,9 @@ use crate::vdbe::registers_to_ref_values;
 use crate::vector::{vector_concat, vector_slice};
 use crate::{
     error::{
-        LimboError, SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY,
+        LimboError, SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_CHECK, SQLITE_CONSTRAINT_FOREIGNKEY,
+        SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY, SQLITE_CONSTRAINT_TRIGGER,
+        SQLITE_ERROR, SQLITE_IGNORE,
     },
     ext::ExtValue,
     function::{AggFunc, ExtFunc, MathFunc, MathFuncArity, ScalarFunc, VectorFunc},
@@ -63,12 +67,12 @@ use crate::{
     },
     vdbe::{
         builder::CursorType,
-        insn::{IdxInsertFlags, Insn},
+        insn::{IdxInsertFlags, Insn, SavepointOp},
     },
     vector::{vector32, vector64, vector_distance_cos, vector_distance_l2, vector_extract},
 };
 
-use crate::{info, turso_assert, OpenFlags, RefValue, Row, TransactionState};
+use crate::{info, turso_assert, OpenFlags, RefValue, Row, Savepoint, TransactionState};
 
 use super::{
     insn::{Cookie, RegisterOrLiteral},
@@ -261,10 +265,10 @@ pub fn op_drop_index(
     pager: &Arc<Pager>,
     mv_store: Option<&Arc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    load_insn!(DropIndex { index, db: _ }, insn);
+    load_insn!(DropIndex { index, db }, insn);
     program
         .connection
-        .with_schema_mut(|schema| schema.remove_index(index));
+        .with_database_schema_mut(*db, |schema| schema.remove_index(index));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -1088,9 +1092,9 @@ pub fn op_open_read(
                 .unwrap()
                 .replace(Cursor::new_materialized_view(mv_cursor));
         }
-        CursorType::BTreeTable(_) => {
+        CursorType::BTreeTable(table) => {
             // Regular table
-            let cursor = BTreeCursor::new_table(mv_cursor, pager.clone(), *root_page, num_columns);
+            let cursor = BTreeCursor::new_for_table(mv_cursor, pager.clone(), *root_page, table);
             cursors
                 .get_mut(*cursor_id)
                 .unwrap()
@@ -1714,8 +1718,11 @@ pub fn op_column(
                             drop(record_cursor);
 
                             match serial_type {
-                                // NULL
-                                0 => break 'ifnull,
+                                // NULL. Unlike a column missing from the record, this does not
+                                // fall back to the column's DEFAULT.
+                                0 => {
+                                    state.registers[*dest] = Register::Value(Value::Null);
+                                }
                                 // I8
                                 1 => {
                                     state.registers[*dest] =
@@ -2128,8 +2135,35 @@ pub fn halt(
     err_code: usize,
     description: &str,
 ) -> Result<InsnFunctionStepResult> {
-    if err_code > 0 {
-        // invalidate page cache in case of error
+    if program.is_subprogram {
+        // A trigger program runs inside the statement that fired it, so it never ends the
+        // transaction; control simply returns to the calling program.
+        match err_code {
+            0 => return Ok(InsnFunctionStepResult::Done),
+            SQLITE_IGNORE => {
+                state.raise_ignore = true;
+                return Ok(InsnFunctionStepResult::Done);
+            }
+            _ => {}
+        }
+    }
+    if !program.is_subprogram
+        && matches!(
+            err_code,
+            SQLITE_CONSTRAINT_PRIMARYKEY
+                | SQLITE_CONSTRAINT_NOTNULL
+                | SQLITE_CONSTRAINT_CHECK
+                | SQLITE_CONSTRAINT_UNIQUE
+        )
+    {
+        state.halt_resolve_type = program.resolve_type;
+    }
+    if err_code > 0
+        && !program.connection.has_statement_journal()
+        && state.halt_resolve_type != ast::ResolveType::Fail
+    {
+        // invalidate page cache in case of error, unless only the statement will be rolled back
+        // or its changes are kept
         pager.clear_page_cache();
     }
     match err_code {
@@ -2144,11 +2178,27 @@ pub fn halt(
                 "NOT NULL constraint failed: {description} (19)"
             )));
         }
+        SQLITE_CONSTRAINT_CHECK => {
+            return Err(LimboError::Constraint(format!(
+                "CHECK constraint failed: {description} (19)"
+            )));
+        }
         SQLITE_CONSTRAINT_UNIQUE => {
             return Err(LimboError::Constraint(format!(
                 "UNIQUE constraint failed: {description} (19)"
             )));
         }
+        SQLITE_CONSTRAINT_TRIGGER => {
+            return Err(LimboError::Constraint(format!("{description} (19)")));
+        }
+        SQLITE_CONSTRAINT_FOREIGNKEY => {
+            return Err(LimboError::Constraint(
+                "FOREIGN KEY constraint failed (19)".to_string(),
+            ));
+        }
+        SQLITE_ERROR => {
+            return Err(LimboError::Constraint(description.to_string()));
+        }
         _ => {
             return Err(LimboError::Constraint(format!(
                 "undocumented halt error code {description}"
@@ -2158,6 +2208,16 @@ pub fn halt(
 
     let auto_commit = program.connection.auto_commit.load(Ordering::SeqCst);
     tracing::trace!("halt(auto_commit={})", auto_commit);
+    if state.fk_immediate_violations > 0
+        || (auto_commit && program.connection.get_deferred_fk_violations() > 0)
+    {
+        return Err(LimboError::Constraint(
+            "FOREIGN KEY constraint failed (19)".to_string(),
+        ));
+    }
+    if !program.connection.is_nested_stmt.load(Ordering::SeqCst) {
+        program.connection.end_statement(pager);
+    }
     if auto_commit {
         program
             .commit_txn(pager.clone(), state, mv_store, false)
@@ -2207,6 +2267,118 @@ pub fn op_halt_if_null(
     }
 }
 
+/// Maximum nesting depth of trigger programs, see SQLITE_MAX_TRIGGER_DEPTH.
+const MAX_TRIGGER_DEPTH: usize = 1000;
+
+pub fn op_program(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(
+        Program {
+            params_start_reg,
+            sub_program,
+            ignore_jump_target,
+        },
+        insn
+    );
+    let sub_program = sub_program.get();
+    if state.sub_state.is_none() {
+        if state.frame_depth >= MAX_TRIGGER_DEPTH {
+            return Err(LimboError::Constraint(
+                "too many levels of trigger recursion".to_string(),
+            ));
+        }
+        let mut sub_state = ProgramState::new(
+            sub_program.program.max_registers,
+            sub_program.program.cursor_ref.len(),
+        );
+        sub_state.frame_depth = state.frame_depth + 1;
+        // Foreign key violations are counted per statement, across all of its subprograms.
+        sub_state.fk_immediate_violations = state.fk_immediate_violations;
+        for i in 0..sub_program.num_params {
+            sub_state.registers[i + 1] = state.registers[*params_start_reg + i].clone();
+        }
+        state.sub_state = Some(Box::new(sub_state));
+    }
+
+    // The subprogram shares the pager and transaction of the calling program. If it needs to
+    // wait for I/O, we return to the caller with the subprogram state intact and resume it
+    // when this instruction is re-executed.
+    let sub = &sub_program.program;
+    let sub_state = state.sub_state.as_deref_mut().unwrap();
+    let result = loop {
+        let (insn, _) = &sub.insns[sub_state.pc as usize];
+        match insn.to_function()(sub, sub_state, insn, pager, mv_store) {
+            // Rows produced by SELECT statements in a trigger body are discarded.
+            Ok(InsnFunctionStepResult::Step) | Ok(InsnFunctionStepResult::Row) => {}
+            Ok(InsnFunctionStepResult::Done) => break Ok(()),
+            Ok(InsnFunctionStepResult::IO(io)) => return Ok(InsnFunctionStepResult::IO(io)),
+            Ok(InsnFunctionStepResult::Interrupt) => return Ok(InsnFunctionStepResult::Interrupt),
+            Err(LimboError::Busy) => return Err(LimboError::Busy),
+            Err(err) => break Err(err),
+        }
+    };
+    let ignored = sub_state.raise_ignore;
+    state.fk_immediate_violations = sub_state.fk_immediate_violations;
+    state.sub_state = None;
+    result?;
+
+    if ignored {
+        state.pc = ignore_jump_target.as_offset_int();
+    } else {
+        state.pc += 1;
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_fk_counter(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(FkCounter { deferred, amount }, insn);
+    if *deferred {
+        program.connection.add_deferred_fk_violations(*amount);
+    } else {
+        state.fk_immediate_violations += *amount;
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_fk_if_zero(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(
+        FkIfZero {
+            deferred,
+            target_pc,
+        },
+        insn
+    );
+    let violations = if *deferred {
+        program.connection.get_deferred_fk_violations()
+    } else {
+        state.fk_immediate_violations
+    };
+    if violations == 0 {
+        state.pc = target_pc.as_offset_int();
+    } else {
+        state.pc += 1;
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum OpTransactionState {
     Start,
@@ -2244,6 +2416,36 @@ pub fn op_transaction_inner(
         },
         insn
     );
+    if *db != 0 {
+        // The temp and attached databases have transactions of their own, which end together
+        // with the transaction of the main database.
+        if !program.connection.is_nested_stmt.load(Ordering::SeqCst) {
+            let write = matches!(tx_mode, TransactionMode::Write);
+            return_if_io!(program.connection.begin_aux_tx(*db, write));
+            // Like for the main database, a statement prepared against an older schema of the
+            // database is prepared again.
+            let pager = program.get_pager_from_database_index(db);
+            match with_header(&pager, None, program, |header| header.schema_cookie.get()) {
+                Ok(IOResult::Done(header_schema_cookie)) => {
+                    if header_schema_cookie != *schema_cookie {
+                        tracing::debug!(
+                            "schema of database {} changed, force reprepare: {} != {}",
+                            db,
+                            header_schema_cookie,
+                            *schema_cookie
+                        );
+                        program.connection.reload_database_schema(*db);
+                        return Err(LimboError::SchemaUpdated);
+                    }
+                }
+                Ok(IOResult::IO(io)) => return Ok(InsnFunctionStepResult::IO(io)),
+                Err(LimboError::Page1NotAlloc) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        state.pc += 1;
+        return Ok(InsnFunctionStepResult::Step);
+    }
     let pager = program.get_pager_from_database_index(db);
     loop {
         match state.op_transaction_state {
@@ -2414,6 +2616,16 @@ pub fn op_transaction_inner(
                     }
                 }
 
+                let conn = &program.connection;
+                if *db == 0
+                    && matches!(tx_mode, TransactionMode::Write)
+                    && mv_store.is_none()
+                    && !conn.auto_commit.load(Ordering::SeqCst)
+                    && !conn.is_nested_stmt.load(Ordering::SeqCst)
+                {
+                    conn.begin_statement(&pager);
+                }
+
                 state.pc += 1;
                 return Ok(InsnFunctionStepResult::Step);
             }
@@ -2443,6 +2655,15 @@ pub fn op_auto_commit(
     }
 
     if *auto_commit != conn.auto_commit.load(Ordering::SeqCst) {
+        if *auto_commit && !*rollback && conn.get_deferred_fk_violations() > 0 {
+            // The transaction stays open so the violations can still be fixed.
+            return Err(LimboError::TxError(
+                "FOREIGN KEY constraint failed".to_string(),
+            ));
+        }
+        conn.clear_savepoints();
+        pager.clear_savepoints();
+        conn.set_deferred_fk_violations(0);
         if *rollback {
             // TODO(pere): add rollback I/O logic once we implement rollback journal
             if let Some(mv_store) = mv_store {
@@ -2453,6 +2674,7 @@ pub fn op_auto_commit(
                 return_if_io!(pager.end_tx(true, &conn));
             }
             conn.set_tx_state(TransactionState::None);
+            conn.end_aux_transactions(true)?;
             conn.auto_commit.store(true, Ordering::SeqCst);
         } else {
             conn.auto_commit.store(*auto_commit, Ordering::SeqCst);
@@ -2486,6 +2708,82 @@ pub fn op_auto_commit(
         .map(Into::into)
 }
 
+pub fn op_savepoint(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(Savepoint { op, name }, insn);
+    let conn = program.connection.clone();
+    if matches!(state.commit_state, CommitState::Committing) {
+        return program
+            .commit_txn(pager.clone(), state, mv_store, false)
+            .map(Into::into);
+    }
+    if mv_store.is_some() {
+        return Err(LimboError::TxError(
+            "savepoints are not supported in MVCC mode".to_string(),
+        ));
+    }
+    // Drop the journal of a statement that was abandoned before it halted.
+    conn.end_statement(pager);
+
+    match op {
+        SavepointOp::Begin => {
+            // A savepoint outside of a transaction behaves like BEGIN DEFERRED.
+            let starts_transaction = conn.auto_commit.load(Ordering::SeqCst);
+            if starts_transaction {
+                conn.auto_commit.store(false, Ordering::SeqCst);
+            }
+            conn.push_savepoint(Savepoint {
+                name: Some(name.clone()),
+                starts_transaction,
+                schema: conn.schema.read().clone(),
+                deferred_fk_violations: conn.get_deferred_fk_violations(),
+            });
+            pager.open_savepoint();
+            conn.open_aux_savepoints();
+        }
+        SavepointOp::Release => {
+            let Some(idx) = conn.find_savepoint(name) else {
+                return Err(LimboError::TxError(format!("no such savepoint: {name}")));
+            };
+            if idx == 0 && conn.get_savepoint(idx).starts_transaction {
+                // Releasing the outermost savepoint of a transaction it started commits it.
+                if conn.get_deferred_fk_violations() > 0 {
+                    return Err(LimboError::TxError(
+                        "FOREIGN KEY constraint failed".to_string(),
+                    ));
+                }
+                conn.clear_savepoints();
+                pager.clear_savepoints();
+                conn.auto_commit.store(true, Ordering::SeqCst);
+                return program
+                    .commit_txn(pager.clone(), state, mv_store, false)
+                    .map(Into::into);
+            }
+            conn.truncate_savepoints(idx);
+            pager.release_savepoint(idx);
+            conn.release_aux_savepoints(idx);
+        }
+        SavepointOp::Rollback => {
+            let Some(idx) = conn.find_savepoint(name) else {
+                return Err(LimboError::TxError(format!("no such savepoint: {name}")));
+            };
+            pager.rollback_to_savepoint(idx)?;
+            conn.rollback_aux_savepoints(idx)?;
+            let savepoint = conn.get_savepoint(idx);
+            *conn.schema.write() = savepoint.schema;
+            conn.set_deferred_fk_violations(savepoint.deferred_fk_violations);
+            conn.truncate_savepoints(idx + 1);
+        }
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_goto(
     program: &Program,
     state: &mut ProgramState,
@@ -3649,8 +3947,11 @@ pub fn op_agg_step(
             let AggContext::Avg(acc, count) = agg.borrow_mut() else {
                 unreachable!();
             };
-            *acc = acc.exec_add(col.get_value());
-            *count += 1;
+            // NULLs are ignored by avg()
+            if !matches!(col.get_value(), Value::Null) {
+                *acc = acc.exec_add(col.get_value());
+                *count += 1;
+            }
         }
         AggFunc::Sum | AggFunc::Total => {
             let col = state.registers[*col].clone();
@@ -3665,6 +3966,9 @@ pub fn op_agg_step(
             };
             match col {
                 Register::Value(value) => {
+                    if !matches!(value, Value::Null) {
+                        sum_state.count += 1;
+                    }
                     match value {
                         Value::Null => {
                             // Ignore NULLs
@@ -3896,6 +4200,79 @@ pub fn op_agg_step(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_agg_inverse(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(AggInverse { acc_reg, col, func }, insn);
+    let col = state.registers[*col].get_value().clone();
+    let Register::Aggregate(agg) = state.registers[*acc_reg].borrow_mut() else {
+        panic!(
+            "Unexpected value {:?} in AggInverse at register {}",
+            state.registers[*acc_reg], *acc_reg
+        );
+    };
+    match (func, agg) {
+        (AggFunc::Avg, AggContext::Avg(acc, count)) => {
+            if !matches!(col, Value::Null) {
+                *acc = acc.exec_subtract(&col);
+                *count += -1;
+            }
+        }
+        (AggFunc::Count0, AggContext::Count(count)) => {
+            *count += -1;
+        }
+        (AggFunc::Count, AggContext::Count(count)) => {
+            if !matches!(col, Value::Null) {
+                *count += -1;
+            }
+        }
+        (AggFunc::Sum | AggFunc::Total, AggContext::Sum(acc, sum_state)) => {
+            match col {
+                Value::Integer(i) => match acc {
+                    Value::Integer(acc_i) => match acc_i.checked_sub(i) {
+                        Some(diff) => *acc = Value::Integer(diff),
+                        None => {
+                            *acc = Value::Float(*acc_i as f64);
+                            sum_state.approx = true;
+                            sum_state.ovrfl = true;
+                            apply_kbn_step(acc, -(i as f64), sum_state);
+                        }
+                    },
+                    Value::Float(_) => apply_kbn_step(acc, -(i as f64), sum_state),
+                    _ => unreachable!(),
+                },
+                Value::Float(f) => {
+                    if let Value::Integer(acc_i) = acc {
+                        *acc = Value::Float(*acc_i as f64);
+                    }
+                    sum_state.approx = true;
+                    apply_kbn_step(acc, -f, sum_state);
+                }
+                _ => {}
+            }
+            if !matches!(col, Value::Null) {
+                sum_state.count -= 1;
+                if sum_state.count == 0 {
+                    // All values were removed. Like SQLite, keep returning a float if one was
+                    // ever added.
+                    *acc = match func {
+                        AggFunc::Total => Value::Float(0.0),
+                        _ => Value::Null,
+                    };
+                    sum_state.r_err = 0.0;
+                }
+            }
+        }
+        (func, agg) => unreachable!("AggInverse is not supported for {func:?} with {agg:?}"),
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_agg_final(
     program: &Program,
     state: &mut ProgramState,
@@ -3926,7 +4303,7 @@ pub fn op_agg_final(
                     unreachable!();
                 };
                 let value = match acc {
-                    Value::Null => match sum_state.approx {
+                    Value::Null => match sum_state.approx && sum_state.count > 0 {
                         true => Value::Float(0.0),
                         false => Value::Null,
                     },
@@ -4028,7 +4405,9 @@ pub fn op_agg_final(
                 AggFunc::Count | AggFunc::Count0 => {
                     state.registers[dest_reg] = Register::Value(Value::Integer(0));
                 }
-                _ => {}
+                _ => {
+                    state.registers[dest_reg] = Register::Value(Value::Null);
+                }
             }
         }
         other => {
@@ -5321,16 +5700,24 @@ pub fn op_function(
                                     break 'sql None;
                                 }
 
+                                // The column is renamed in the expressions of an index on
+                                // expressions as well.
                                 for column in &mut columns {
-                                    match column.expr.as_mut() {
-                                        ast::Expr::Id(ast::Name::Ident(id))
-                                        | ast::Expr::Id(ast::Name::Quoted(id))
-                                            if normalize_ident(id) == rename_from =>
-                                        {
-                                            *id = column_def.col_name.as_str().to_owned();
-                                        }
-                                        _ => {}
-                                    }
+                                    walk_expr_mut(
+                                        &mut column.expr,
+                                        &mut |expr: &mut ast::Expr| {
+                                            match expr {
+                                                ast::Expr::Id(ast::Name::Ident(id))
+                                                | ast::Expr::Id(ast::Name::Quoted(id))
+                                                    if normalize_ident(id) == rename_from =>
+                                                {
+                                                    *id = column_def.col_name.as_str().to_owned();
+                                                }
+                                                _ => {}
+                                            }
+                                            Ok(WalkControl::Continue)
+                                        },
+                                    )?;
                                 }
 
                                 Some(
@@ -5357,13 +5744,48 @@ pub fn op_function(
 
                                 let ast::CreateTableBody::ColumnsAndConstraints {
                                     mut columns,
-                                    constraints,
+                                    mut constraints,
                                     options,
                                 } = body
                                 else {
                                     todo!()
                                 };
 
+                                if matches!(alter_func, AlterTableFunc::RenameColumn) {
+                                    // The column is renamed in the CHECK constraints and in the
+                                    // expressions of generated columns as well.
+                                    let checks = columns
+                                        .iter_mut()
+                                        .flat_map(|column| column.constraints.iter_mut())
+                                        .filter_map(|c| match &mut c.constraint {
+                                            ast::ColumnConstraint::Check(expr)
+                                            | ast::ColumnConstraint::Generated { expr, .. } => {
+                                                Some(expr)
+                                            }
+                                            _ => None,
+                                        })
+                                        .chain(constraints.iter_mut().filter_map(|c| {
+                                            match &mut c.constraint {
+                                                ast::TableConstraint::Check(expr) => Some(expr),
+                                                _ => None,
+                                            }
+                                        }));
+                                    for expr in checks {
+                                        walk_expr_mut(expr, &mut |expr: &mut ast::Expr| {
+                                            match expr {
+                                                ast::Expr::Id(ast::Name::Ident(id))
+                                                | ast::Expr::Id(ast::Name::Quoted(id))
+                                                    if normalize_ident(id) == rename_from =>
+                                                {
+                                                    *id = column_def.col_name.as_str().to_owned();
+                                                }
+                                                _ => {}
+                                            }
+                                            Ok(WalkControl::Continue)
+                                        })?;
+                                    }
+                                }
+
                                 let column = columns
                                     .iter_mut()
                                     .find(|column| {
@@ -5415,6 +5837,9 @@ pub fn op_function(
         crate::function::Func::Agg(_) => {
             unreachable!("Aggregate functions should not be handled here")
         }
+        crate::function::Func::Window(_) => {
+            unreachable!("Window functions should not be handled here")
+        }
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -5939,8 +6364,12 @@ pub fn op_delete(
     }
 
     state.op_delete_state.sub_state = OpDeleteSubState::MaybeCaptureRecord;
-    let prev_changes = program.n_change.get();
-    program.n_change.set(prev_changes + 1);
+    // Entries deleted from indexes are not rows of their own
+    let (_, cursor_type) = program.cursor_ref.get(*cursor_id).unwrap();
+    if matches!(cursor_type, CursorType::BTreeTable(_)) {
+        let prev_changes = program.n_change.get();
+        program.n_change.set(prev_changes + 1);
+    }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -6017,13 +6446,14 @@ pub fn op_idx_delete(
                 state.op_idx_delete_state = Some(OpIdxDeleteState::Verifying);
             }
             Some(OpIdxDeleteState::Verifying) => {
-                let rowid = {
+                // Entries of indexes on WITHOUT ROWID tables end with the PRIMARY KEY instead
+                let (rowid, has_rowid) = {
                     let cursor = state.get_cursor(*cursor_id);
                     let cursor = cursor.as_btree_mut();
-                    return_if_io!(cursor.rowid())
+                    (return_if_io!(cursor.rowid()), cursor.has_rowid())
                 };
 
-                if rowid.is_none() && *raise_error_if_no_matching_entry {
+                if rowid.is_none() && has_rowid && *raise_error_if_no_matching_entry {
                     let reg_values = (*start_reg..*start_reg + *num_regs)
                         .map(|i| &state.registers[i])
                         .collect::<Vec<_>>();
@@ -6091,13 +6521,18 @@ pub fn op_idx_insert(
     match state.op_idx_insert_state {
         OpIdxInsertState::MaybeSeek => {
             let (_, cursor_type) = program.cursor_ref.get(cursor_id).unwrap();
-            let CursorType::BTreeIndex(index_meta) = cursor_type else {
-                panic!("IdxInsert: not a BTreeIndex cursor");
+            let unique = match cursor_type {
+                CursorType::BTreeIndex(index_meta) => index_meta.unique,
+                // The rows of a WITHOUT ROWID table are inserted into the table b-tree itself.
+                // Its PRIMARY KEY is checked before the insertion, and an existing row with the
+                // same key is overwritten.
+                CursorType::BTreeTable(table) if !table.has_rowid => false,
+                _ => panic!("IdxInsert: not a BTreeIndex cursor"),
             };
 
             // TODO: currently we never pass USE_SEEK, so this other check is a bit redundant and we always seek,
             // but I guess it's FutureProofed™®
-            if !index_meta.unique && flags.has(IdxInsertFlags::USE_SEEK) {
+            if !unique && flags.has(IdxInsertFlags::USE_SEEK) {
                 state.op_idx_insert_state = OpIdxInsertState::Insert;
                 return Ok(InsnFunctionStepResult::Step);
             }
@@ -6113,7 +6548,7 @@ pub fn op_idx_insert(
                 SeekOp::GE { eq_only: true },
             )? {
                 SeekInternalResult::Found => {
-                    state.op_idx_insert_state = if index_meta.unique {
+                    state.op_idx_insert_state = if unique {
                         OpIdxInsertState::UniqueConstraintCheck
                     } else {
                         OpIdxInsertState::Insert
@@ -6182,6 +6617,12 @@ pub fn op_idx_insert(
             // Increment metrics for index write
             if flags.has(IdxInsertFlags::NCHANGE) {
                 state.metrics.rows_written = state.metrics.rows_written.saturating_add(1);
+                let (_, cursor_type) = program.cursor_ref.get(cursor_id).unwrap();
+                if matches!(cursor_type, CursorType::BTreeTable(_)) {
+                    // A row of a WITHOUT ROWID table was written.
+                    let prev_changes = program.n_change.get();
+                    program.n_change.set(prev_changes + 1);
+                }
             }
             state.op_idx_insert_state = OpIdxInsertState::MaybeSeek;
             state.pc += 1;
@@ -6652,16 +7093,16 @@ pub fn op_open_write(
             .unwrap()
             .replace(Cursor::new_btree(cursor));
     } else {
-        let num_columns = match cursor_type {
-            CursorType::BTreeTable(table_rc) => table_rc.columns.len(),
-            CursorType::MaterializedView(table_rc, _) => table_rc.columns.len(),
+        let table = match cursor_type {
+            CursorType::BTreeTable(table_rc) => table_rc,
+            CursorType::MaterializedView(table_rc, _) => table_rc,
             _ => unreachable!(
                 "Expected BTreeTable or MaterializedView. This should not have happened."
             ),
         };
 
         let cursor =
-            BTreeCursor::new_table(mv_cursor, pager.clone(), root_page as usize, num_columns);
+            BTreeCursor::new_for_table(mv_cursor, pager.clone(), root_page as usize, table);
         cursors
             .get_mut(*cursor_id)
             .unwrap()
@@ -6702,15 +7143,10 @@ pub fn op_create_btree(
 ) -> Result<InsnFunctionStepResult> {
     load_insn!(CreateBtree { db, root, flags }, insn);
 
-    assert_eq!(*db, 0);
-
     if program.connection.is_readonly(*db) {
         return Err(LimboError::ReadOnly);
     }
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
+    let (pager, mv_store) = database_pager(program, *db, pager, mv_store);
 
     if let Some(mv_store) = mv_store {
         let root_page = mv_store.get_next_table_id();
@@ -6736,13 +7172,11 @@ pub fn op_destroy(
         Destroy {
             root,
             former_root_reg,
-            is_temp,
+            db,
         },
         insn
     );
-    if *is_temp == 1 {
-        todo!("temp databases not implemented yet.");
-    }
+    let (pager, _) = database_pager(program, *db, pager, mv_store);
     // TODO not sure if should be BTreeCursor::new_table or BTreeCursor::new_index here or neither and just pass an emtpy vec
     let mut cursor = BTreeCursor::new(None, pager.clone(), *root, 0);
     let former_root_page_result = cursor.btree_destroy()?;
@@ -6767,7 +7201,7 @@ pub fn op_reset_sorter(
     let cursor = state.get_cursor(*cursor_id);
 
     match cursor_type {
-        CursorType::BTreeTable(table) => {
+        CursorType::BTreeTable(_) | CursorType::BTreeIndex(_) => {
             let cursor = cursor.as_btree_mut();
             return_if_io!(cursor.clear_btree());
         }
@@ -6789,12 +7223,9 @@ pub fn op_drop_table(
     mv_store: Option<&Arc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
     load_insn!(DropTable { db, table_name, .. }, insn);
-    if *db > 0 {
-        todo!("temp databases not implemented yet");
-    }
     let conn = program.connection.clone();
     {
-        conn.with_schema_mut(|schema| {
+        conn.with_database_schema_mut(*db, |schema| {
             schema.remove_indices_for_table(table_name);
             schema.remove_table(table_name);
         });
@@ -6811,11 +7242,8 @@ pub fn op_drop_view(
     _mv_store: Option<&Arc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
     load_insn!(DropView { db, view_name }, insn);
-    if *db > 0 {
-        todo!("temp databases not implemented yet");
-    }
     let conn = program.connection.clone();
-    conn.with_schema_mut(|schema| {
+    conn.with_database_schema_mut(*db, |schema| {
         schema.remove_view(view_name)?;
         Ok::<(), crate::LimboError>(())
     })?;
@@ -6823,6 +7251,23 @@ pub fn op_drop_view(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_drop_trigger(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    _pager: &Arc<Pager>,
+    _mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(DropTrigger { db, trigger_name }, insn);
+    let conn = program.connection.clone();
+    conn.with_database_schema_mut(*db, |schema| {
+        schema.remove_trigger(trigger_name)?;
+        Ok::<(), crate::LimboError>(())
+    })?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_close(
     program: &Program,
     state: &mut ProgramState,
@@ -6884,11 +7329,8 @@ pub fn op_page_count(
     mv_store: Option<&Arc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
     load_insn!(PageCount { db, dest }, insn);
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
-    let count = match with_header(pager, mv_store, program, |header| {
+    let (pager, mv_store) = database_pager(program, *db, pager, mv_store);
+    let count = match with_header(&pager, mv_store, program, |header| {
         header.database_size.get()
     }) {
         Err(_) => 0.into(),
@@ -6907,24 +7349,23 @@ pub fn op_parse_schema(
     pager: &Arc<Pager>,
     mv_store: Option<&Arc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    load_insn!(
-        ParseSchema {
-            db: _,
-            where_clause,
-        },
-        insn
-    );
+    load_insn!(ParseSchema { db, where_clause }, insn);
 
     let conn = program.connection.clone();
+    let schema_table = match conn.get_database_name_by_index(*db) {
+        Some(name) if *db != 0 => format!("{name}.sqlite_schema"),
+        _ => "sqlite_schema".to_string(),
+    };
+    let mv_store = if *db == 0 { mv_store } else { None };
     // set auto commit to false in order for parse schema to not commit changes as transaction state is stored in connection,
     // and we use the same connection for nested query.
     let previous_auto_commit = conn.auto_commit.load(Ordering::SeqCst);
     conn.auto_commit.store(false, Ordering::SeqCst);
 
     let maybe_nested_stmt_err = if let Some(where_clause) = where_clause {
-        let stmt = conn.prepare(format!("SELECT * FROM sqlite_schema WHERE {where_clause}"))?;
+        let stmt = conn.prepare(format!("SELECT * FROM {schema_table} WHERE {where_clause}"))?;
 
-        conn.with_schema_mut(|schema| {
+        conn.with_database_schema_mut(*db, |schema| {
             // TODO: This function below is synchronous, make it async
             let existing_views = schema.incremental_views.clone();
             conn.is_nested_stmt.store(true, Ordering::SeqCst);
@@ -6938,9 +7379,9 @@ pub fn op_parse_schema(
             )
         })
     } else {
-        let stmt = conn.prepare("SELECT * FROM sqlite_schema")?;
+        let stmt = conn.prepare(format!("SELECT * FROM {schema_table}"))?;
 
-        conn.with_schema_mut(|schema| {
+        conn.with_database_schema_mut(*db, |schema| {
             // TODO: This function below is synchronous, make it async
             let existing_views = schema.incremental_views.clone();
             conn.is_nested_stmt.store(true, Ordering::SeqCst);
@@ -6963,6 +7404,52 @@ pub fn op_parse_schema(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_vacuum(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(Vacuum { db: _, dest }, insn);
+
+    let conn = program.connection.clone();
+    if !conn.auto_commit.load(Ordering::SeqCst) {
+        return Err(LimboError::TxError(
+            "cannot VACUUM from within a transaction".to_string(),
+        ));
+    }
+    if mv_store.is_some() {
+        return Err(LimboError::TxError(
+            "VACUUM is not supported in MVCC mode".to_string(),
+        ));
+    }
+    let path = match dest {
+        Some(dest) => match state.registers[*dest].get_value() {
+            Value::Text(path) => Some(path.as_str().to_string()),
+            _ => return Err(LimboError::InvalidArgument("non-text filename".to_string())),
+        },
+        None => None,
+    };
+
+    // The copy reads the database through nested statements on this connection, which must
+    // neither commit nor open transactions of their own.
+    let previous_auto_commit = conn.auto_commit.load(Ordering::SeqCst);
+    conn.auto_commit.store(false, Ordering::SeqCst);
+    conn.is_nested_stmt.store(true, Ordering::SeqCst);
+    let result = match path {
+        Some(path) => crate::vacuum::vacuum_into(&conn, &path),
+        None => crate::vacuum::vacuum(&conn),
+    };
+    conn.is_nested_stmt.store(false, Ordering::SeqCst);
+    conn.auto_commit
+        .store(previous_auto_commit, Ordering::SeqCst);
+    result?;
+
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_populate_materialized_views(
     program: &Program,
     state: &mut ProgramState,
@@ -7048,12 +7535,9 @@ pub fn op_read_cookie(
     mv_store: Option<&Arc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
     load_insn!(ReadCookie { db, dest, cookie }, insn);
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
+    let (pager, mv_store) = database_pager(program, *db, pager, mv_store);
 
-    let cookie_value = match with_header(pager, mv_store, program, |header| match cookie {
+    let cookie_value = match with_header(&pager, mv_store, program, |header| match cookie {
         Cookie::ApplicationId => header.application_id.get().into(),
         Cookie::UserVersion => header.user_version.get().into(),
         Cookie::SchemaVersion => header.schema_cookie.get().into(),
@@ -7086,11 +7570,9 @@ pub fn op_set_cookie(
         },
         insn
     );
-    if *db > 0 {
-        todo!("temp databases not implemented yet");
-    }
+    let (pager, mv_store) = database_pager(program, *db, pager, mv_store);
 
-    return_if_io!(with_header_mut(pager, mv_store, program, |header| {
+    return_if_io!(with_header_mut(&pager, mv_store, program, |header| {
         match cookie {
             Cookie::ApplicationId => header.application_id = (*value).into(),
             Cookie::UserVersion => header.user_version = (*value).into(),
@@ -7098,6 +7580,14 @@ pub fn op_set_cookie(
                 header.vacuum_mode_largest_root_page = (*value as u32).into();
             }
             Cookie::IncrementalVacuum => header.incremental_vacuum_enabled = (*value as u32).into(),
+            Cookie::SchemaVersion if *db != 0 => {
+                // The schema of the temp or an attached database is kept by the connection, and
+                // restored by it on rollback.
+                program
+                    .connection
+                    .with_database_schema_mut(*db, |schema| schema.schema_version = *value as u32);
+                header.schema_cookie = (*value as u32).into();
+            }
             Cookie::SchemaVersion => {
                 // we update transaction state to indicate that the schema has changed
                 match program.connection.get_tx_state() {
@@ -7325,8 +7815,13 @@ pub fn op_open_ephemeral(
     match &mut state.op_open_ephemeral_state {
         OpOpenEphemeralState::Start => {
             tracing::trace!("Start");
-            let page_size =
-                return_if_io!(with_header(pager, mv_store, program, |header| header.page_size));
+            // The main database has no header until its first table is created, e.g. when only
+            // the temp or attached databases are used.
+            let page_size = if mv_store.is_none() && !pager.db_state.is_initialized() {
+                program.connection.get_page_size()
+            } else {
+                return_if_io!(with_header(pager, mv_store, program, |header| header.page_size))
+            };
             let conn = program.connection.clone();
             let io = conn.pager.read().io.clone();
             let rand_num = io.generate_random_number();
@@ -7519,8 +8014,7 @@ pub fn op_open_dup(
     let (_, cursor_type) = program.cursor_ref.get(*original_cursor_id).unwrap();
     match cursor_type {
         CursorType::BTreeTable(table) => {
-            let cursor =
-                BTreeCursor::new_table(mv_cursor, pager.clone(), root_page, table.columns.len());
+            let cursor = BTreeCursor::new_for_table(mv_cursor, pager.clone(), root_page, table);
             let cursors = &mut state.cursors;
             cursors
                 .get_mut(*new_cursor_id)
@@ -7698,6 +8192,143 @@ pub fn op_count(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_stat_init(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(
+        StatInit {
+            num_key_columns,
+            num_columns,
+            row_count_reg,
+            sample,
+        },
+        insn
+    );
+    let row_count = match state.registers[*row_count_reg].get_value() {
+        Value::Integer(count) => (*count).max(0) as u64,
+        _ => 0,
+    };
+    state.stat_accumulator = Some(StatAccumulator::new(
+        *num_key_columns,
+        *num_columns,
+        row_count,
+        *sample,
+    ));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_stat_push(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(
+        StatPush {
+            cursor_id,
+            changed_column_reg,
+        },
+        insn
+    );
+    let changed = match state.registers[*changed_column_reg].get_value() {
+        Value::Integer(changed) => (*changed).max(0) as usize,
+        _ => 0,
+    };
+    let wants_samples = state
+        .stat_accumulator
+        .as_ref()
+        .expect("StatPush without StatInit")
+        .wants_samples();
+    let record = if wants_samples {
+        let cursor = get_cursor!(state, *cursor_id);
+        let cursor = cursor.as_btree_mut();
+        return_if_io!(cursor.record()).map(|record| record.as_blob().clone())
+    } else {
+        None
+    };
+    state
+        .stat_accumulator
+        .as_mut()
+        .unwrap()
+        .push(changed, record);
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_stat_get(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(StatGet { dest }, insn);
+    let stat = state
+        .stat_accumulator
+        .as_mut()
+        .expect("StatGet without StatInit")
+        .stat1();
+    state.registers[*dest] = Register::Value(Value::build_text(stat));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_stat_sample(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(StatSample { dest, pc_if_done }, insn);
+    let sample = state
+        .stat_accumulator
+        .as_mut()
+        .expect("StatSample without StatInit")
+        .next_sample();
+    let Some((neq, nlt, ndlt, record)) = sample else {
+        state.pc = pc_if_done.as_offset_int();
+        return Ok(InsnFunctionStepResult::Step);
+    };
+    state.registers[*dest] = Register::Value(Value::build_text(neq));
+    state.registers[*dest + 1] = Register::Value(Value::build_text(nlt));
+    state.registers[*dest + 2] = Register::Value(Value::build_text(ndlt));
+    state.registers[*dest + 3] = Register::Value(Value::Blob(record));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_load_analysis(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Arc<Pager>,
+    mv_store: Option<&Arc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    load_insn!(LoadAnalysis { db }, insn);
+
+    // Like ParseSchema, read the tables with nested statements that must neither commit nor
+    // open transactions of their own.
+    let conn = program.connection.clone();
+    let previous_auto_commit = conn.auto_commit.load(Ordering::SeqCst);
+    conn.auto_commit.store(false, Ordering::SeqCst);
+    conn.is_nested_stmt.store(true, Ordering::SeqCst);
+    let result = conn.load_analysis(*db);
+    conn.is_nested_stmt.store(false, Ordering::SeqCst);
+    conn.auto_commit
+        .store(previous_auto_commit, Ordering::SeqCst);
+    result?;
+
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 #[derive(Debug)]
 pub enum OpIntegrityCheckState {
     Start,
@@ -7821,14 +8452,14 @@ pub fn op_rename_table(
     pager: &Arc<Pager>,
     mv_store: Option<&Arc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    load_insn!(RenameTable { from, to }, insn);
+    load_insn!(RenameTable { db, from, to }, insn);
 
     let normalized_from = normalize_ident(from.as_str());
     let normalized_to = normalize_ident(to.as_str());
 
     let conn = program.connection.clone();
 
-    conn.with_schema_mut(|schema| {
+    conn.with_database_schema_mut(*db, |schema| {
         if let Some(mut indexes) = schema.indexes.remove(&normalized_from) {
             indexes.iter_mut().for_each(|index| {
                 let index = Arc::make_mut(index);
@@ -7870,6 +8501,7 @@ pub fn op_drop_column(
 ) -> Result<InsnFunctionStepResult> {
     load_insn!(
         DropColumn {
+            db,
             table,
             column_index
         },
@@ -7878,7 +8510,7 @@ pub fn op_drop_column(
 
     let conn = program.connection.clone();
 
-    conn.with_schema_mut(|schema| {
+    conn.with_database_schema_mut(*db, |schema| {
         let table = schema
             .tables
             .get_mut(table)
@@ -7891,7 +8523,9 @@ pub fn op_drop_column(
         };
 
         let btree = Arc::make_mut(btree);
-        btree.columns.remove(*column_index)
+        let column = btree.columns.remove(*column_index);
+        // The CHECK constraints of the column are dropped with it.
+        btree.checks.retain(|check| check.column != column.name);
     });
 
     state.pc += 1;
@@ -7905,11 +8539,11 @@ pub fn op_add_column(
     pager: &Arc<Pager>,
     mv_store: Option<&Arc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    load_insn!(AddColumn { table, column }, insn);
+    load_insn!(AddColumn { db, table, column }, insn);
 
     let conn = program.connection.clone();
 
-    conn.with_schema_mut(|schema| {
+    conn.with_database_schema_mut(*db, |schema| {
         let table = schema
             .tables
             .get_mut(table)
@@ -7938,6 +8572,7 @@ pub fn op_alter_column(
 ) -> Result<InsnFunctionStepResult> {
     load_insn!(
         AlterColumn {
+            db,
             table: table_name,
             column_index,
             definition,
@@ -7951,7 +8586,7 @@ pub fn op_alter_column(
     let normalized_table_name = normalize_ident(table_name.as_str());
     let new_column = crate::schema::Column::from(definition);
 
-    conn.with_schema_mut(|schema| {
+    conn.with_database_schema_mut(*db, |schema| {
         let table = schema
             .tables
             .get_mut(&normalized_table_name)
@@ -7965,6 +8600,35 @@ pub fn op_alter_column(
 
         let btree = Arc::make_mut(btree);
 
+        if *rename {
+            let old_name = btree.columns[*column_index]
+                .name
+                .as_deref()
+                .map(normalize_ident)
+                .expect("btree column should be named");
+            for check in &mut btree.checks {
+                if check.column.as_ref() == Some(&old_name) {
+                    check.column = Some(normalize_ident(definition.col_name.as_str()));
+                }
+            }
+            let exprs = btree.checks.iter_mut().map(|check| &mut check.expr).chain(
+                btree
+                    .columns
+                    .iter_mut()
+                    .filter_map(|column| column.generated.as_mut().map(|g| &mut g.expr)),
+            );
+            for expr in exprs {
+                let _ = walk_expr_mut(expr, &mut |expr: &mut ast::Expr| {
+                    if let ast::Expr::Id(ast::Name::Ident(id) | ast::Name::Quoted(id)) = expr {
+                        if normalize_ident(id) == old_name {
+                            *id = definition.col_name.as_str().to_owned();
+                        }
+                    }
+                    Ok(WalkControl::Continue)
+                });
+            }
+        }
+
         let column = btree
             .columns
             .get_mut(*column_index)
@@ -7974,9 +8638,20 @@ pub fn op_alter_column(
             for index in indexes {
                 let index = Arc::make_mut(index);
                 for index_column in &mut index.columns {
-                    if index_column.name
-                        == *column.name.as_ref().expect("btree column should be named")
-                    {
+                    let old_name = column.name.as_ref().expect("btree column should be named");
+                    if let Some(expr) = index_column.expr.as_mut() {
+                        let _ = walk_expr_mut(expr, &mut |expr: &mut ast::Expr| {
+                            if let ast::Expr::Id(ast::Name::Ident(id) | ast::Name::Quoted(id)) =
+                                expr
+                            {
+                                if normalize_ident(id) == normalize_ident(old_name) {
+                                    *id = definition.col_name.as_str().to_owned();
+                                }
+                            }
+                            Ok(WalkControl::Continue)
+                        });
+                        index_column.name = expr.to_string();
+                    } else if index_column.name == *old_name {
                         index_column.name = definition.col_name.as_str().to_owned();
                     }
                 }
@@ -9469,6 +10144,20 @@ pub fn op_journal_mode(
     Ok(InsnFunctionStepResult::Step)
 }
 
+/// Returns the pager and MVCC store of the database at `db`. Only the main database uses MVCC.
+fn database_pager<'a>(
+    program: &Program,
+    db: usize,
+    pager: &Arc<Pager>,
+    mv_store: Option<&'a Arc<MvStore>>,
+) -> (Arc<Pager>, Option<&'a Arc<MvStore>>) {
+    if db == 0 {
+        (pager.clone(), mv_store)
+    } else {
+        (program.get_pager_from_database_index(&db), None)
+    }
+}
+
 fn with_header<T, F>(
     pager: &Arc<Pager>,
     mv_store: Option<&Arc<MvStore>>,
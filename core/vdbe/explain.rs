@@ -1,6 +1,9 @@
 use turso_parser::ast::SortOrder;
 
-use crate::vdbe::{builder::CursorType, insn::RegisterOrLiteral};
+use crate::vdbe::{
+    builder::CursorType,
+    insn::{RegisterOrLiteral, SavepointOp},
+};
 
 use super::{Insn, InsnReference, Program, Value};
 use crate::function::{Func, ScalarFunc};
@@ -639,6 +642,19 @@ pub fn insn_to_row(
                 0,
                 "".to_string(),
             ),
+            Insn::Program {
+                params_start_reg,
+                sub_program,
+                ignore_jump_target,
+            } => (
+                "Program",
+                *params_start_reg as i32,
+                ignore_jump_target.as_debug_int(),
+                0,
+                Value::build_text("program"),
+                0,
+                format!("TRIGGER {}", sub_program.trigger_name()),
+            ),
             Insn::HaltIfNull {
                 err_code,
                 target_reg,
@@ -652,6 +668,34 @@ pub fn insn_to_row(
                 0,
                 "".to_string(),
             ),
+            Insn::FkCounter { deferred, amount } => (
+                "FkCounter",
+                *deferred as i32,
+                *amount as i32,
+                0,
+                Value::build_text(""),
+                0,
+                format!(
+                    "{} fk violations += {amount}",
+                    if *deferred { "deferred" } else { "immediate" }
+                ),
+            ),
+            Insn::FkIfZero {
+                deferred,
+                target_pc,
+            } => (
+                "FkIfZero",
+                *deferred as i32,
+                target_pc.as_debug_int(),
+                0,
+                Value::build_text(""),
+                0,
+                format!(
+                    "if {} fk violations == 0 goto {}",
+                    if *deferred { "deferred" } else { "immediate" },
+                    target_pc.as_debug_int()
+                ),
+            ),
             Insn::Transaction { db, tx_mode, schema_cookie} => (
                 "Transaction",
                 *db as i32,
@@ -943,6 +987,15 @@ pub fn insn_to_row(
                 0,
                 format!("accum=r[{}] step(r[{}])", *acc_reg, *col),
             ),
+            Insn::AggInverse { acc_reg, col, func } => (
+                "AggInverse",
+                0,
+                *col as i32,
+                *acc_reg as i32,
+                Value::build_text(func.to_string()),
+                0,
+                format!("accum=r[{}] inverse(r[{}])", *acc_reg, *col),
+            ),
             Insn::AggFinal { register, func } => (
                 "AggFinal",
                 0,
@@ -1268,16 +1321,16 @@ pub fn insn_to_row(
             Insn::Destroy {
                 root,
                 former_root_reg,
-                is_temp,
+                db,
             } => (
                 "Destroy",
                 *root as i32,
                 *former_root_reg as i32,
-                *is_temp as i32,
+                *db as i32,
                 Value::build_text(""),
                 0,
                 format!(
-                    "root iDb={root} former_root={former_root_reg} is_temp={is_temp}"
+                    "iDb={db} root={root} former_root={former_root_reg}"
                 ),
             ),
             Insn::ResetSorter { cursor_id } => (
@@ -1312,6 +1365,15 @@ pub fn insn_to_row(
                 0,
                 format!("DROP VIEW {view_name}"),
             ),
+            Insn::DropTrigger { db, trigger_name } => (
+                "DropTrigger",
+                *db as i32,
+                0,
+                0,
+                Value::build_text(trigger_name),
+                0,
+                format!("DROP TRIGGER {trigger_name}"),
+            ),
             Insn::DropIndex { db: _, index } => (
                 "DropIndex",
                 0,
@@ -1360,6 +1422,18 @@ pub fn insn_to_row(
                 0,
                 where_clause.clone().unwrap_or("NULL".to_string()),
             ),
+            Insn::Vacuum { db, dest } => (
+                "Vacuum",
+                *db as i32,
+                dest.map_or(0, |dest| dest as i32),
+                0,
+                Value::build_text(""),
+                0,
+                match dest {
+                    Some(dest) => format!("vacuum db={db} into r[{dest}]"),
+                    None => format!("vacuum db={db}"),
+                },
+            ),
             Insn::PopulateMaterializedViews { cursors } => (
                 "PopulateMaterializedViews",
                 0,
@@ -1509,6 +1583,19 @@ pub fn insn_to_row(
                 0,
                 format!("auto_commit={auto_commit}, rollback={rollback}"),
             ),
+            Insn::Savepoint { op, name } => (
+                "Savepoint",
+                match op {
+                    SavepointOp::Begin => 0,
+                    SavepointOp::Release => 1,
+                    SavepointOp::Rollback => 2,
+                },
+                0,
+                0,
+                Value::build_text(name.clone()),
+                0,
+                format!("{op} {name}"),
+            ),
             Insn::OpenEphemeral {
                 cursor_id,
                 is_table,
@@ -1632,6 +1719,59 @@ pub fn insn_to_row(
                 0,
                 "".to_string(),
             ),
+            Insn::StatInit {
+                num_key_columns,
+                num_columns,
+                row_count_reg,
+                sample,
+            } => (
+                "StatInit",
+                *num_key_columns as i32,
+                *num_columns as i32,
+                *row_count_reg as i32,
+                Value::build_text(""),
+                *sample as u16,
+                format!("stat_init(key_columns={num_key_columns}, columns={num_columns}, rows=r[{row_count_reg}])"),
+            ),
+            Insn::StatPush {
+                cursor_id,
+                changed_column_reg,
+            } => (
+                "StatPush",
+                *cursor_id as i32,
+                *changed_column_reg as i32,
+                0,
+                Value::build_text(""),
+                0,
+                format!("stat_push(cursor {cursor_id}, changed=r[{changed_column_reg}])"),
+            ),
+            Insn::StatGet { dest } => (
+                "StatGet",
+                *dest as i32,
+                0,
+                0,
+                Value::build_text(""),
+                0,
+                format!("r[{dest}]=stat_get()"),
+            ),
+            Insn::StatSample { dest, pc_if_done } => (
+                "StatSample",
+                *dest as i32,
+                pc_if_done.as_debug_int(),
+                0,
+                Value::build_text(""),
+                0,
+                format!("r[{dest}..{}]=stat_sample()", dest + 3),
+            ),
+            Insn::LoadAnalysis { db } => (
+                "LoadAnalysis",
+                *db as i32,
+                0,
+                0,
+                Value::build_text(""),
+                0,
+                "".to_string(),
+            ),
             Insn::Int64 {
                 _p1,
                 out_reg,
@@ -1677,36 +1817,36 @@ pub fn insn_to_row(
                 0,
                 format!("affinity(r[{}]={:?})", *reg, affinity),
             ),
-            Insn::RenameTable { from, to } => (
+            Insn::RenameTable { db, from, to } => (
                 "RenameTable",
-                0,
+                *db as i32,
                 0,
                 0,
                 Value::build_text(""),
                 0,
                 format!("rename_table({from}, {to})"),
             ),
-            Insn::DropColumn { table, column_index } => (
+            Insn::DropColumn { db, table, column_index } => (
                 "DropColumn",
-                0,
+                *db as i32,
                 0,
                 0,
                 Value::build_text(""),
                 0,
                 format!("drop_column({table}, {column_index})"),
             ),
-            Insn::AddColumn { table, column } => (
+            Insn::AddColumn { db, table, column } => (
                 "AddColumn",
-                0,
+                *db as i32,
                 0,
                 0,
                 Value::build_text(""),
                 0,
                 format!("add_column({table}, {column:?})"),
             ),
-            Insn::AlterColumn { table, column_index, definition: column, rename } => (
+            Insn::AlterColumn { db, table, column_index, definition: column, rename } => (
                 "AlterColumn",
-                0,
+                *db as i32,
                 0,
                 0,
                 Value::build_text(""),
@@ -1,9 +1,9 @@
 use std::{
     num::{NonZero, NonZeroUsize},
-    sync::Arc,
+    sync::{Arc, OnceLock, Weak},
 };
 
-use super::{execute, AggFunc, BranchOffset, CursorID, FuncCtx, InsnFunction, PageIdx};
+use super::{execute, AggFunc, BranchOffset, CursorID, FuncCtx, InsnFunction, PageIdx, Program};
 use crate::{
     schema::{Affinity, BTreeTable, Column, Index},
     storage::{pager::CreateBTreeFlags, wal::CheckpointMode},
@@ -152,6 +152,23 @@ impl<T: Copy + std::fmt::Display> std::fmt::Display for RegisterOrLiteral<T> {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SavepointOp {
+    Begin,
+    Release,
+    Rollback,
+}
+
+impl std::fmt::Display for SavepointOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SavepointOp::Begin => write!(f, "BEGIN"),
+            SavepointOp::Release => write!(f, "RELEASE"),
+            SavepointOp::Rollback => write!(f, "ROLLBACK"),
+        }
+    }
+}
+
 // There are currently 190 opcodes in sqlite
 #[repr(u8)]
 #[derive(Description, Debug, EnumDiscriminants)]
@@ -473,6 +490,15 @@ pub enum Insn {
         description: String,
     },
 
+    /// Execute a trigger program. The registers params_start_reg..params_start_reg+num_params
+    /// of the current frame are copied into registers 1.. of the subprogram before it runs.
+    /// If the subprogram executes RAISE(IGNORE), jump to ignore_jump_target.
+    Program {
+        params_start_reg: usize,
+        sub_program: SubProgramRef,
+        ignore_jump_target: BranchOffset,
+    },
+
     /// Halt the program if P3 is null.
     HaltIfNull {
         target_reg: usize,   // P3
@@ -480,6 +506,19 @@ pub enum Insn {
         err_code: usize,     // p1
     },
 
+    /// Add `amount` to the number of foreign key violations: the deferred violations of the
+    /// transaction if `deferred` is true, otherwise the immediate violations of the statement.
+    FkCounter {
+        deferred: bool,
+        amount: i64,
+    },
+
+    /// Jump to target_pc if the deferred (or immediate) foreign key violation counter is zero.
+    FkIfZero {
+        deferred: bool,
+        target_pc: BranchOffset,
+    },
+
     /// Start a transaction.
     Transaction {
         db: usize,                // p1
@@ -493,6 +532,13 @@ pub enum Insn {
         rollback: bool,
     },
 
+    /// Open a new savepoint named `name`, release it or roll back to it. Releasing the savepoint
+    /// that started the transaction commits the transaction.
+    Savepoint {
+        op: SavepointOp,
+        name: String,
+    },
+
     /// Branch to the given PC.
     Goto {
         target_pc: BranchOffset,
@@ -684,6 +730,14 @@ pub enum Insn {
         func: AggFunc,
     },
 
+    /// Remove a row from the aggregate accumulator in register `acc_reg`, undoing an earlier
+    /// `AggStep` with the same arguments. Used to slide window frames without recomputing them.
+    AggInverse {
+        acc_reg: usize,
+        col: usize,
+        func: AggFunc,
+    },
+
     AggFinal {
         register: usize,
         func: AggFunc,
@@ -862,16 +916,17 @@ pub enum Insn {
         root: usize,
         /// Register to store the former value of any moved root page (for AUTOVACUUM)
         former_root_reg: usize,
-        /// Whether this is a temporary table (1) or main database table (0)
-        is_temp: usize,
+        /// The database the table/index belongs to (P3)
+        db: usize,
     },
 
-    /// Deletes all contents from the ephemeral table that the cursor points to.
+    /// Deletes all contents from the ephemeral table or index that the cursor points to.
     ///
     /// In Turso, we do not currently distinguish strictly between ephemeral
     /// and standard tables at the type level. Therefore, it is the caller’s
     /// responsibility to ensure that `ResetSorter` is applied only to ephemeral
-    /// tables.
+    /// tables, or to a standard b-tree that is meant to be emptied, like an index
+    /// rebuilt by REINDEX. The root page of the b-tree is kept.
     ///
     /// SQLite also supports sorter cursors, but this is not yet implemented in Turso.
     ResetSorter {
@@ -901,6 +956,12 @@ pub enum Insn {
         //  The name of the index being dropped
         index: Arc<Index>,
     },
+    DropTrigger {
+        /// The database within which this trigger needs to be dropped
+        db: usize,
+        /// The name of the trigger being dropped
+        trigger_name: String,
+    },
 
     /// Close a cursor.
     Close {
@@ -936,6 +997,13 @@ pub enum Insn {
         where_clause: Option<String>,
     },
 
+    /// Rebuild database `db` into a compacted copy. If `dest` is set, it is the register holding
+    /// the path the copy is written to (VACUUM INTO); otherwise the copy replaces the database.
+    Vacuum {
+        db: usize,
+        dest: Option<usize>,
+    },
+
     /// Populate all materialized views after schema parsing
     /// The cursors parameter contains a mapping of view names to cursor IDs that have been
     /// opened to the view's btree for writing the materialized data
@@ -1085,6 +1153,45 @@ pub enum Insn {
         exact: bool,
     },
 
+    /// Start gathering the statistics of an index for ANALYZE. The entries of the index have
+    /// `num_columns` columns, of which the first `num_key_columns` are reported in sqlite_stat1,
+    /// and register `row_count_reg` holds the number of entries. If `sample` is set, entries are
+    /// also sampled for sqlite_stat4.
+    StatInit {
+        num_key_columns: usize,
+        num_columns: usize,
+        row_count_reg: usize,
+        sample: bool,
+    },
+
+    /// Add the entry at the current position of index cursor P1 to the statistics started by
+    /// [Insn::StatInit]. Register P2 holds the index of the leftmost column of the entry that
+    /// differs from the previous entry.
+    StatPush {
+        cursor_id: CursorID,
+        changed_column_reg: usize,
+    },
+
+    /// Store the sqlite_stat1 text of the statistics gathered since [Insn::StatInit] in
+    /// register P1.
+    StatGet {
+        dest: usize,
+    },
+
+    /// Store the neq, nlt, ndlt and sample columns of the next sqlite_stat4 sample of the
+    /// statistics gathered since [Insn::StatInit] in registers P1..P1+3, or jump to P2 if all
+    /// the samples were returned.
+    StatSample {
+        dest: usize,
+        pc_if_done: BranchOffset,
+    },
+
+    /// Read the sqlite_stat1 and sqlite_stat4 tables of database P1 into its schema, for the
+    /// query planner.
+    LoadAnalysis {
+        db: usize,
+    },
+
     /// Do an analysis of the currently open database. Store in register (P1+1) the text of an error message describing any problems.
     /// If no problems are found, store a NULL in register (P1+1).
     /// The register (P1) contains one less than the maximum number of allowed errors.
@@ -1097,18 +1204,22 @@ pub enum Insn {
         message_register: usize,
     },
     RenameTable {
+        db: usize,
         from: String,
         to: String,
     },
     DropColumn {
+        db: usize,
         table: String,
         column_index: usize,
     },
     AddColumn {
+        db: usize,
         table: String,
         column: Column,
     },
     AlterColumn {
+        db: usize,
         table: String,
         column_index: usize,
         definition: turso_parser::ast::ColumnDefinition,
@@ -1232,6 +1343,7 @@ impl InsnVariants {
             InsnVariants::HaltIfNull => execute::op_halt_if_null,
             InsnVariants::Transaction => execute::op_transaction,
             InsnVariants::AutoCommit => execute::op_auto_commit,
+            InsnVariants::Savepoint => execute::op_savepoint,
             InsnVariants::Goto => execute::op_goto,
             InsnVariants::Gosub => execute::op_gosub,
             InsnVariants::Return => execute::op_return,
@@ -1256,6 +1368,7 @@ impl InsnVariants {
             InsnVariants::IdxLT => execute::op_idx_lt,
             InsnVariants::DecrJumpZero => execute::op_decr_jump_zero,
             InsnVariants::AggStep => execute::op_agg_step,
+            InsnVariants::AggInverse => execute::op_agg_inverse,
             InsnVariants::AggFinal | InsnVariants::AggValue => execute::op_agg_final,
             InsnVariants::SorterOpen => execute::op_sorter_open,
             InsnVariants::SorterInsert => execute::op_sorter_insert,
@@ -1284,10 +1397,15 @@ impl InsnVariants {
             InsnVariants::ResetSorter => execute::op_reset_sorter,
             InsnVariants::DropTable => execute::op_drop_table,
             InsnVariants::DropView => execute::op_drop_view,
+            InsnVariants::DropTrigger => execute::op_drop_trigger,
+            InsnVariants::Program => execute::op_program,
+            InsnVariants::FkCounter => execute::op_fk_counter,
+            InsnVariants::FkIfZero => execute::op_fk_if_zero,
             InsnVariants::Close => execute::op_close,
             InsnVariants::IsNull => execute::op_is_null,
             InsnVariants::CollSeq => execute::op_coll_seq,
             InsnVariants::ParseSchema => execute::op_parse_schema,
+            InsnVariants::Vacuum => execute::op_vacuum,
             InsnVariants::PopulateMaterializedViews => execute::op_populate_materialized_views,
             InsnVariants::ShiftRight => execute::op_shift_right,
             InsnVariants::ShiftLeft => execute::op_shift_left,
@@ -1308,6 +1426,11 @@ impl InsnVariants {
             InsnVariants::Affinity => execute::op_affinity,
             InsnVariants::IdxDelete => execute::op_idx_delete,
             InsnVariants::Count => execute::op_count,
+            InsnVariants::StatInit => execute::op_stat_init,
+            InsnVariants::StatPush => execute::op_stat_push,
+            InsnVariants::StatGet => execute::op_stat_get,
+            InsnVariants::StatSample => execute::op_stat_sample,
+            InsnVariants::LoadAnalysis => execute::op_load_analysis,
             InsnVariants::IntegrityCk => execute::op_integrity_check,
             InsnVariants::RenameTable => execute::op_rename_table,
             InsnVariants::DropColumn => execute::op_drop_column,
@@ -1341,6 +1464,48 @@ impl Insn {
     }
 }
 
+/// A compiled trigger body, executed by [Insn::Program].
+pub struct SubProgram {
+    pub trigger_name: String,
+    /// Number of registers copied from the calling frame, starting at register 1.
+    pub num_params: usize,
+    pub program: Program,
+}
+
+impl std::fmt::Debug for SubProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubProgram")
+            .field("trigger_name", &self.trigger_name)
+            .field("num_params", &self.num_params)
+            .finish()
+    }
+}
+
+/// Reference to a trigger subprogram. A recursive trigger refers to the program that is still
+/// being compiled when its own [Insn::Program] is emitted, so that reference is filled in once
+/// compilation finishes and held weakly to avoid a reference cycle.
+#[derive(Debug, Clone)]
+pub enum SubProgramRef {
+    Owned(Arc<SubProgram>),
+    Recursive(Arc<OnceLock<Weak<SubProgram>>>),
+}
+
+impl SubProgramRef {
+    pub fn get(&self) -> Arc<SubProgram> {
+        match self {
+            SubProgramRef::Owned(sub_program) => sub_program.clone(),
+            SubProgramRef::Recursive(slot) => slot
+                .get()
+                .and_then(Weak::upgrade)
+                .expect("recursive trigger program must outlive its callers"),
+        }
+    }
+
+    pub fn trigger_name(&self) -> String {
+        self.get().trigger_name.clone()
+    }
+}
+
 // TODO: Add remaining cookies.
 #[derive(Description, Debug, Clone, Copy)]
 pub enum Cookie {
@@ -30,6 +30,7 @@ use crate::{
     function::{AggFunc, FuncCtx},
     mvcc::{database::CommitStateMachine, LocalClock},
     state_machine::StateMachine,
+    stats::StatAccumulator,
     storage::sqlite3_ondisk::SmallVec,
     translate::{collate::CollationSeq, plan::TableReferences},
     types::{IOCompletions, IOResult, RawSlice, TextRef},
@@ -69,6 +70,7 @@ use std::{
     sync::{atomic::Ordering, Arc},
 };
 use tracing::{instrument, Level};
+use turso_parser::ast;
 
 /// State machine for committing view deltas with I/O handling
 #[derive(Debug, Clone)]
@@ -300,6 +302,19 @@ pub struct ProgramState {
     op_checkpoint_state: OpCheckpointState,
     /// State machine for committing view deltas with I/O handling
     view_delta_state: ViewDeltaCommitState,
+    /// State of the trigger subprogram currently being run by [Insn::Program], if any.
+    sub_state: Option<Box<ProgramState>>,
+    /// Number of trigger subprograms this state is nested in; 0 for the top-level statement.
+    frame_depth: usize,
+    /// Set when a trigger subprogram executes RAISE(IGNORE).
+    raise_ignore: bool,
+    /// How the error the statement halted with is resolved, see [Program::resolve_type].
+    halt_resolve_type: ast::ResolveType,
+    /// Number of outstanding immediate foreign key violations of the running statement,
+    /// maintained by [Insn::FkCounter] and checked when the statement halts.
+    fk_immediate_violations: i64,
+    /// Statistics of the index being analyzed, started by [Insn::StatInit].
+    stat_accumulator: Option<StatAccumulator>,
 }
 
 impl ProgramState {
@@ -344,6 +359,12 @@ impl ProgramState {
             op_transaction_state: OpTransactionState::Start,
             op_checkpoint_state: OpCheckpointState::StartCheckpoint,
             view_delta_state: ViewDeltaCommitState::NotStarted,
+            sub_state: None,
+            frame_depth: 0,
+            raise_ignore: false,
+            halt_resolve_type: ast::ResolveType::Abort,
+            fk_immediate_violations: 0,
+            stat_accumulator: None,
         }
     }
 
@@ -428,6 +449,11 @@ impl ProgramState {
         self.op_column_state = OpColumnState::Start;
         self.op_row_id_state = OpRowIdState::Start;
         self.view_delta_state = ViewDeltaCommitState::NotStarted;
+        self.sub_state = None;
+        self.raise_ignore = false;
+        self.halt_resolve_type = ast::ResolveType::Abort;
+        self.fk_immediate_violations = 0;
+        self.stat_accumulator = None;
     }
 
     pub fn get_cursor(&mut self, cursor_id: CursorID) -> &mut Cursor {
@@ -501,6 +527,12 @@ pub struct Program {
     /// Used to determine whether we need to check for schema changes when
     /// starting a transaction.
     pub accesses_db: bool,
+    /// Whether this is a trigger program run by [Insn::Program] rather than a top-level statement.
+    pub is_subprogram: bool,
+    /// How a NOT NULL, CHECK, UNIQUE or PRIMARY KEY constraint violated by the statement is
+    /// resolved: ABORT undoes the statement, FAIL keeps the changes it made before the violation
+    /// and ROLLBACK rolls back the whole transaction.
+    pub resolve_type: ast::ResolveType,
 }
 
 impl Program {
@@ -649,7 +681,13 @@ impl Program {
                 }
                 if let Some(err) = io.get_error() {
                     let err = err.into();
-                    handle_program_error(&pager, &self.connection, &err, mv_store.as_ref())?;
+                    handle_program_error(
+                        &pager,
+                        &self.connection,
+                        &err,
+                        mv_store.as_ref(),
+                        ast::ResolveType::Abort,
+                    )?;
                     return Err(err);
                 }
                 state.io_completions = None;
@@ -693,7 +731,13 @@ impl Program {
                     return Ok(StepResult::Busy);
                 }
                 Err(err) => {
-                    handle_program_error(&pager, &self.connection, &err, mv_store.as_ref())?;
+                    handle_program_error(
+                        &pager,
+                        &self.connection,
+                        &err,
+                        mv_store.as_ref(),
+                        state.halt_resolve_type,
+                    )?;
                     return Err(err);
                 }
             }
@@ -832,6 +876,7 @@ impl Program {
             let conn = self.connection.clone();
             let auto_commit = conn.auto_commit.load(Ordering::SeqCst);
             if auto_commit {
+                conn.end_aux_transactions(rollback)?;
                 // FIXME: we don't want to commit stuff from other programs.
                 if matches!(program_state.commit_state, CommitState::Ready) {
                     let Some(tx_id) = conn.get_mv_tx_id() else {
@@ -877,6 +922,7 @@ impl Program {
                     rollback,
                 )
             } else if auto_commit {
+                connection.end_aux_transactions(rollback)?;
                 let current_state = connection.get_tx_state();
                 tracing::trace!("Auto-commit state: {:?}", current_state);
                 match current_state {
@@ -1062,12 +1108,14 @@ impl Row {
     }
 }
 
-/// Handle a program error by rolling back the transaction
+/// Handle a program error by rolling back the transaction, or only the statement, as
+/// `resolve_type` says for constraint violations.
 pub fn handle_program_error(
     pager: &Arc<Pager>,
     connection: &Connection,
     err: &LimboError,
     mv_store: Option<&Arc<MvStore>>,
+    resolve_type: ast::ResolveType,
 ) -> Result<()> {
     if connection.is_nested_stmt.load(Ordering::SeqCst) {
         // Errors from nested statements are handled by the parent statement.
@@ -1080,7 +1128,45 @@ pub fn handle_program_error(
         LimboError::TableLocked => {}
         // Busy errors do not cause a rollback.
         LimboError::Busy => {}
+        // FAIL keeps the changes the statement made before the violation, which are committed
+        // right away outside of an explicit transaction. MVCC transactions are rolled back.
+        LimboError::Constraint(_)
+            if resolve_type == ast::ResolveType::Fail && mv_store.is_none() =>
+        {
+            if connection.auto_commit.load(Ordering::SeqCst) {
+                if let Err(err) = connection.end_aux_transactions(false) {
+                    pager.io.block(|| pager.end_tx(true, connection))?;
+                    connection.set_tx_state(TransactionState::None);
+                    return Err(err);
+                }
+                pager.io.block(|| pager.end_tx(false, connection))?;
+                connection.set_tx_state(TransactionState::None);
+            } else {
+                connection.end_statement(pager);
+            }
+        }
+        // Errors raised by the statement itself inside an explicit transaction only undo the
+        // statement, using its statement journal.
+        LimboError::Constraint(_)
+        | LimboError::IntegerOverflow
+        | LimboError::ParseError(_)
+        | LimboError::ConversionError(_)
+        | LimboError::InvalidArgument(_)
+        | LimboError::InvalidColumnType
+        | LimboError::InvalidBlobSize(_)
+        | LimboError::ExtensionError(_)
+            if connection.has_statement_journal() && resolve_type != ast::ResolveType::Rollback =>
+        {
+            connection.rollback_statement(pager)?;
+        }
         _ => {
+            if resolve_type == ast::ResolveType::Rollback {
+                // Like ROLLBACK, this ends an explicit transaction too.
+                connection.clear_savepoints();
+                pager.clear_savepoints();
+                connection.set_deferred_fk_violations(0);
+                connection.auto_commit.store(true, Ordering::SeqCst);
+            }
             if let Some(mv_store) = mv_store {
                 if let Some(tx_id) = connection.get_mv_tx_id() {
                     connection.set_tx_state(TransactionState::None);
@@ -1096,6 +1182,7 @@ pub fn handle_program_error(
                     })?;
             }
             connection.set_tx_state(TransactionState::None);
+            connection.end_aux_transactions(true)?;
         }
     }
     Ok(())
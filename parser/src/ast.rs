@@ -468,6 +468,18 @@ pub enum Expr {
     Raise(ResolveType, Option<Box<Expr>>),
     /// Subquery expression
     Subquery(Select),
+    /// Subquery outside the FROM clause that has been planned separately
+    /// This is not part of SQL syntax but used internally by the query planner
+    SubqueryResult {
+        /// internal id of the planned subquery
+        subquery_id: TableInternalId,
+        /// left-hand side of `IN`
+        lhs: Option<Box<Expr>>,
+        /// `NOT IN`
+        not_in: bool,
+        /// where the result of the subquery is stored
+        query_type: SubqueryType,
+    },
     /// Unary expression
     Unary(UnaryOperator, Box<Expr>),
     /// Parameters
@@ -664,6 +676,27 @@ impl Operator {
     }
 }
 
+/// Where the result of an [Expr::SubqueryResult] is stored
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SubqueryType {
+    /// `EXISTS`: the register holds 1 if the subquery returns a row, 0 otherwise
+    Exists {
+        /// result register
+        result_reg: usize,
+    },
+    /// scalar subquery: the register holds the first row of the subquery, or NULL
+    Scalar {
+        /// result register
+        result_reg: usize,
+    },
+    /// `IN`: the rows of the subquery are stored in an ephemeral index
+    In {
+        /// cursor of the ephemeral index
+        cursor_id: usize,
+    },
+}
+
 /// Unary operators
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -1308,6 +1341,8 @@ pub type PragmaValue = Box<Expr>; // TODO
 #[strum(serialize_all = "snake_case")]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PragmaName {
+    /// set the maximum number of index entries that ANALYZE visits
+    AnalysisLimit,
     /// Returns the application ID of the database file.
     ApplicationId,
     /// set the autovacuum mode
@@ -1326,8 +1361,16 @@ pub enum PragmaName {
     DatabaseList,
     /// Encoding - only support utf8
     Encoding,
+    /// check the foreign key constraints of the database
+    ForeignKeyCheck,
+    /// returns the foreign keys of a table
+    ForeignKeyList,
+    /// enable or disable foreign key constraints
+    ForeignKeys,
     /// Current free page count.
     FreelistCount,
+    /// enable or disable the enforcement of CHECK constraints
+    IgnoreCheckConstraints,
     /// Run integrity check on the database file
     IntegrityCheck,
     /// `journal_mode` pragma
@@ -1343,18 +1386,26 @@ pub enum PragmaName {
     /// `module_list` pragma
     /// `module_list` lists modules used by virtual tables.
     ModuleList,
+    /// run ANALYZE on the tables that need it
+    Optimize,
     /// Return the total number of pages in the database file.
     PageCount,
     /// Return the page size of the database in bytes.
     PageSize,
     /// make connection query only
     QueryOnly,
+    /// enable or disable recursive triggers
+    RecursiveTriggers,
     /// Returns schema version of the database file.
     SchemaVersion,
     /// Control database synchronization mode (OFF | FULL | NORMAL | EXTRA)
     Synchronous,
     /// returns information about the columns of a table
     TableInfo,
+    /// returns information about the columns of a table, including hidden and generated columns
+    TableXinfo,
+    /// where temporary tables and indices are stored (DEFAULT | FILE | MEMORY)
+    TempStore,
     /// enable capture-changes logic for the connection
     UnstableCaptureDataChangesConn,
     /// Returns the user version of the database file.
@@ -895,6 +895,28 @@ impl ToTokens for Expr {
                 query.to_tokens(s, context)?;
                 s.append(TK_RP, None)
             }
+            Self::SubqueryResult {
+                subquery_id,
+                lhs,
+                not_in,
+                query_type,
+            } => {
+                // This is for internal use only, not part of SQL syntax
+                if let Some(lhs) = lhs {
+                    lhs.to_tokens(s, context)?;
+                    if *not_in {
+                        s.append(TK_NOT, None)?;
+                    }
+                    s.append(TK_IN, None)?;
+                }
+                if let SubqueryType::Exists { .. } = query_type {
+                    s.append(TK_EXISTS, None)?;
+                }
+                s.append(
+                    TK_VARIABLE,
+                    Some(&format!("$subquery{}", usize::from(*subquery_id))),
+                )
+            }
             Self::Unary(op, sub_expr) => {
                 op.to_tokens(s, context)?;
                 sub_expr.to_tokens(s, context)
@@ -155,6 +155,11 @@ fn keyword_or_id_token(input: &[u8]) -> TokenType {
     })
 }
 
+/// Returns true if `input` is an SQL keyword (case-insensitive).
+pub fn is_keyword(input: &[u8]) -> bool {
+    keyword_or_id_token(input) != TokenType::TK_ID
+}
+
 #[inline(always)]
 pub fn is_identifier_start(b: u8) -> bool {
     b.is_ascii_uppercase() || b == b'_' || b.is_ascii_lowercase() || b > b'\x7F'
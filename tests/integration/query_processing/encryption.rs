@@ -196,3 +196,58 @@ fn test_non_4k_page_size_encryption() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_vacuum_encrypted_database() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let db_name = format!("test-{}.db", rng().next_u32());
+    let tmp_db = TempDatabase::new(&db_name, false);
+    let db_path = tmp_db.path.clone();
+    let copy_path = db_path.with_file_name("copy.db");
+    let uri_for = |path: &std::path::Path| {
+        format!(
+            "file:{}?cipher=aegis256&hexkey=b1bbfda4f589dc9daaf004fe21111e00dc00c98237102f5c7002a5669fc76327",
+            path.to_str().unwrap()
+        )
+    };
+
+    {
+        let (_io, conn) =
+            turso_core::Connection::from_uri(&uri_for(&db_path), true, false, false, false)?;
+        run_query(
+            &tmp_db,
+            &conn,
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);",
+        )?;
+        for i in 0..100 {
+            run_query(
+                &tmp_db,
+                &conn,
+                &format!("INSERT INTO test (value) VALUES ('value-{i}')"),
+            )?;
+        }
+        run_query(&tmp_db, &conn, "DELETE FROM test WHERE id > 1")?;
+        run_query(&tmp_db, &conn, "VACUUM")?;
+        run_query(
+            &tmp_db,
+            &conn,
+            &format!("VACUUM INTO '{}'", copy_path.to_str().unwrap()),
+        )?;
+        do_flush(&conn, &tmp_db)?;
+    }
+
+    // Both the vacuumed database and the copy are encrypted with the original key.
+    for path in [&db_path, &copy_path] {
+        let (_io, conn) =
+            turso_core::Connection::from_uri(&uri_for(path), true, false, false, false)?;
+        let mut row_count = 0;
+        run_query_on_row(&tmp_db, &conn, "SELECT * FROM test", |row: &Row| {
+            assert_eq!(row.get::<i64>(0).unwrap(), 1);
+            assert_eq!(row.get::<String>(1).unwrap(), "value-0");
+            row_count += 1;
+        })?;
+        assert_eq!(row_count, 1);
+    }
+
+    Ok(())
+}
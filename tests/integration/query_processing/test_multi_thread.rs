@@ -332,3 +332,39 @@ fn test_interleaved_transactions() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_schema_reprepare_attached() {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new_empty(false);
+    let aux_path = tmp_db.path.with_file_name("aux.db");
+    let attach = format!("ATTACH DATABASE '{}' AS aux", aux_path.to_str().unwrap());
+    let conn1 = tmp_db.connect_limbo();
+    conn1.execute(&attach).unwrap();
+    conn1.execute("CREATE TABLE aux.t(x)").unwrap();
+    conn1.execute("INSERT INTO aux.t VALUES (1), (2)").unwrap();
+    let conn2 = tmp_db.connect_limbo();
+    conn2.execute(&attach).unwrap();
+    let mut stmt = conn2.prepare("SELECT * FROM aux.t").unwrap();
+    conn1.execute("DROP TABLE aux.t").unwrap();
+    conn1.execute("CREATE TABLE aux.t(a, b)").unwrap();
+    conn1.execute("INSERT INTO aux.t VALUES (3, 4)").unwrap();
+
+    let mut rows = Vec::new();
+    loop {
+        match stmt.step().unwrap() {
+            turso_core::StepResult::Done => {
+                break;
+            }
+            turso_core::StepResult::Row => {
+                let row = stmt.row().unwrap();
+                rows.push((row.get::<i64>(0).unwrap(), row.get::<i64>(1).unwrap()));
+            }
+            turso_core::StepResult::IO => {
+                stmt.run_once().unwrap();
+            }
+            step => panic!("unexpected step result {step:?}"),
+        }
+    }
+    assert_eq!(rows, vec![(3, 4)]);
+}
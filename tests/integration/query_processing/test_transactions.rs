@@ -124,6 +124,60 @@ fn test_txn_error_doesnt_rollback_txn() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_constraint_error_rolls_back_statement_only() -> Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x unique);", false);
+    let conn = tmp_db.connect_limbo();
+
+    conn.execute("begin")?;
+    conn.execute("insert into t values (1)")?;
+    conn.execute("savepoint sp")?;
+    // should fail, undoing the insert of 2 but keeping the transaction and the savepoint
+    assert!(conn
+        .execute("insert into t values (2), (1)")
+        .inspect_err(|e| assert!(matches!(e, LimboError::Constraint(_))))
+        .is_err());
+    conn.execute("insert into t values (3)")?;
+    conn.execute("rollback to sp")?;
+    conn.execute("insert into t values (4)")?;
+    conn.execute("commit")?;
+    let mut stmt = conn.query("select group_concat(x) from t")?.unwrap();
+    if let StepResult::Row = stmt.step()? {
+        let row = stmt.row().unwrap();
+        assert_eq!(*row.get::<&Value>(0).unwrap(), Value::build_text("1,4"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_deferred_foreign_key_violation_keeps_txn_open() -> Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table p (id integer primary key); create table c (pid references p deferrable initially deferred);",
+        false,
+    );
+    let conn = tmp_db.connect_limbo();
+
+    conn.execute("pragma foreign_keys = on")?;
+    conn.execute("begin")?;
+    conn.execute("insert into c values (1)")?;
+    // should fail, leaving the transaction open so that the violation can be fixed
+    assert!(conn
+        .execute("commit")
+        .inspect_err(|e| assert!(matches!(e, LimboError::TxError(_))))
+        .is_err());
+    assert!(!conn.get_auto_commit());
+    conn.execute("insert into p values (1)")?;
+    conn.execute("commit")?;
+    let mut stmt = conn.query("select count(*) from p, c")?.unwrap();
+    if let StepResult::Row = stmt.step()? {
+        let row = stmt.row().unwrap();
+        assert_eq!(*row.get::<&Value>(0).unwrap(), Value::Integer(1));
+    }
+
+    Ok(())
+}
+
 #[test]
 /// Connection 2 should see the initial data (table 'test' in schema + 2 rows). Regression test for #2997
 /// It should then see another created table 'test2' in schema, as well.
@@ -790,3 +790,87 @@ fn test_insert_with_column_names() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_vacuum_shrinks_database() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE t(a INTEGER PRIMARY KEY, b BLOB)")?;
+    conn.execute("CREATE INDEX tb ON t(b)")?;
+    for i in 0..200 {
+        conn.execute(format!("INSERT INTO t VALUES ({i}, randomblob(1000))"))?;
+    }
+    conn.execute("DELETE FROM t WHERE a >= 10")?;
+    let page_count = |conn: &Arc<Connection>| {
+        common::limbo_exec_rows(&tmp_db, conn, "PRAGMA page_count")[0][0].clone()
+    };
+    let rusqlite::types::Value::Integer(before) = page_count(&conn) else {
+        panic!("page_count must be an integer");
+    };
+
+    conn.execute("VACUUM")?;
+    let rusqlite::types::Value::Integer(after) = page_count(&conn) else {
+        panic!("page_count must be an integer");
+    };
+    assert!(
+        after < before,
+        "{after} pages after VACUUM, {before} before"
+    );
+    assert_eq!(
+        common::limbo_exec_rows(&tmp_db, &conn, "PRAGMA freelist_count"),
+        vec![vec![rusqlite::types::Value::Integer(0)]]
+    );
+    conn.close()?;
+
+    // The compacted database must survive a reopen.
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, true);
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(
+        common::limbo_exec_rows(&tmp_db, &conn, "SELECT count(*), sum(a) FROM t"),
+        vec![vec![
+            rusqlite::types::Value::Integer(10),
+            rusqlite::types::Value::Integer(45)
+        ]]
+    );
+    assert_eq!(
+        common::limbo_exec_rows(&tmp_db, &conn, "PRAGMA integrity_check"),
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_vacuum_into() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE t(x TEXT)")?;
+    conn.execute("CREATE INDEX tx ON t(x)")?;
+    conn.execute("INSERT INTO t VALUES ('a'), ('b'), ('c')")?;
+    conn.execute("PRAGMA user_version = 3")?;
+
+    let copy_path = tmp_db.path.with_file_name("copy.db");
+    let vacuum_into = format!("VACUUM INTO '{}'", copy_path.to_str().unwrap());
+    conn.execute(&vacuum_into)?;
+    let Err(error) = conn.execute(&vacuum_into) else {
+        panic!("VACUUM INTO must not overwrite an existing database");
+    };
+    assert!(error.to_string().contains("output file already exists"));
+
+    let copy_db = TempDatabase::new_with_existent(&copy_path, true);
+    let copy_conn = copy_db.connect_limbo();
+    assert_eq!(
+        common::limbo_exec_rows(&copy_db, &copy_conn, "SELECT x FROM t ORDER BY x"),
+        vec![
+            vec![rusqlite::types::Value::Text("a".to_string())],
+            vec![rusqlite::types::Value::Text("b".to_string())],
+            vec![rusqlite::types::Value::Text("c".to_string())]
+        ]
+    );
+    assert_eq!(
+        common::limbo_exec_rows(&copy_db, &copy_conn, "PRAGMA user_version"),
+        vec![vec![rusqlite::types::Value::Integer(3)]]
+    );
+    Ok(())
+}